@@ -10,6 +10,10 @@ const frame2 = document.getElementById('pdf-2');
 const fileList = document.getElementById('file-list');
 const activeFileName = document.getElementById('active-file-name');
 const assetUpload = document.getElementById('asset-upload');
+const pdfWrapper = document.querySelector('.pdf-wrapper');
+const pdfPages = document.getElementById('pdf-pages');
+const viewerToolbar = document.getElementById('viewer-toolbar');
+const errorList = document.getElementById('error-list');
 
 // Panels for Mobile
 const editorPanel = document.getElementById('editor-panel');
@@ -118,7 +122,8 @@ function connect() {
         const data = JSON.parse(event.data);
         if (data.type === 'compile_success') {
             emptyState.style.display = 'none';
-            renderPDF(data.pdf);
+            renderPDF(data.pdf, data.changed_pages);
+            renderErrors([]);
             compileTimeEl.textContent = data.compile_time_ms + 'ms';
             statusDot.className = 'status-dot connected';
             statusText.textContent = 'Synced';
@@ -127,6 +132,9 @@ function connect() {
             statusDot.className = 'status-dot';
             statusText.textContent = 'Error';
             showError('LaTeX Error: ' + data.error.substring(0, 300));
+            renderErrors(data.details || []);
+        } else if (data.type === 'healing_report') {
+            console.info('Self-healing applied:', data.fixes);
         }
     };
 
@@ -137,13 +145,27 @@ function connect() {
     };
 }
 
-function renderPDF(base64) {
-    currentPdfBase64 = base64;  // Store for export
+function decodeBase64(base64) {
     const binary = atob(base64);
     const bytes = new Uint8Array(binary.length);
     for (let i = 0; i < binary.length; i++) {
         bytes[i] = binary.charCodeAt(i);
     }
+    return bytes;
+}
+
+// PDF.js viewer (viewer.js) when it loaded, else the browser's viewer in two iframes
+function renderPDF(base64, changedPages) {
+    currentPdfBase64 = base64;  // Store for export
+    if (window.tachyonViewer === undefined) return;  // Drawn once viewer.js is ready
+    const bytes = decodeBase64(base64);
+    if (window.tachyonViewer) {
+        pdfWrapper.classList.add('canvas-mode');
+        pdfPages.classList.remove('hidden');
+        viewerToolbar.classList.remove('hidden');
+        window.tachyonViewer.render(bytes, changedPages);
+        return;
+    }
     const blob = new Blob([bytes], { type: 'application/pdf' });
     const url = URL.createObjectURL(blob);
 
@@ -172,21 +194,50 @@ function compile() {
     }
 }
 
+// Structured errors from the server, each jumping to its file and line
+function renderErrors(details) {
+    errorList.innerHTML = '';
+    errorList.classList.toggle('hidden', details.length === 0);
+    details.forEach(detail => {
+        const item = document.createElement('div');
+        item.className = 'error-item';
+        const location = document.createElement('span');
+        location.className = 'error-location';
+        location.textContent = detail.line ? `${detail.file || project.main}:${detail.line}` : (detail.file || '');
+        item.appendChild(location);
+        item.appendChild(document.createTextNode(detail.message || ''));
+        item.onclick = () => jumpToLine(detail.file || project.main, detail.line);
+        errorList.appendChild(item);
+    });
+}
+
+function jumpToLine(file, line) {
+    if (project.files[file] !== undefined && file !== activeFile) switchFile(file);
+    if (!line || editor.disabled) return;
+    const lines = editor.value.split('\n');
+    const start = lines.slice(0, line - 1).reduce((offset, text) => offset + text.length + 1, 0);
+    editor.focus();
+    editor.setSelectionRange(start, start + (lines[line - 1] || '').length);
+    editor.scrollTop = Math.max(0, (line - 5) * parseFloat(getComputedStyle(editor).lineHeight));
+}
+
 function showError(message) {
     errorToast.textContent = message;
+    errorToast.classList.remove('notice');
     errorToast.classList.add('visible');
     clearTimeout(errorTimeout);
     errorTimeout = setTimeout(() => { errorToast.classList.remove('visible'); }, 6000);
 }
 
+function showNotice(message) {
+    showError(message);
+    errorToast.classList.add('notice');
+}
+
 function exportPDF() {
     if (currentPdfBase64) {
         // Create fresh blob from stored base64
-        const binary = atob(currentPdfBase64);
-        const bytes = new Uint8Array(binary.length);
-        for (let i = 0; i < binary.length; i++) {
-            bytes[i] = binary.charCodeAt(i);
-        }
+        const bytes = decodeBase64(currentPdfBase64);
 
         // Use File object instead of Blob for better Chrome compatibility
         const file = new File([bytes], 'document.pdf', { type: 'application/pdf' });
@@ -239,7 +290,54 @@ document.addEventListener('keydown', (e) => {
     }
 });
 
-window.addEventListener('load', () => {
+window.addEventListener('tachyon-viewer-ready', () => {
+    if (currentPdfBase64) renderPDF(currentPdfBase64, null);
+});
+
+// Shared snippets (v1.5): /p/<id> opens the snippet, Share stores the current text files
+async function loadSnippet() {
+    const match = location.pathname.match(/^\/p\/([\w-]+)$/);
+    if (!match) return;
+    try {
+        const response = await fetch(`/playground/${match[1]}`);
+        if (!response.ok) throw new Error(await response.text());
+        const snippet = await response.json();
+        project = { main: snippet.main, files: snippet.files };
+        activeFile = snippet.main;
+        activeFileName.textContent = snippet.main;
+    } catch (e) {
+        showError('Snippet unavailable: ' + e.message);
+    }
+}
+
+async function shareSnippet() {
+    if (activeFile.endsWith('.tex') || activeFile.endsWith('.sty') || activeFile.endsWith('.cls')) {
+        project.files[activeFile] = editor.value;
+    }
+    const textFiles = Object.fromEntries(Object.entries(project.files).filter(([name]) => /\.(tex|sty|cls|bib)$/.test(name)));
+    try {
+        const response = await fetch('/playground', {
+            method: 'POST',
+            headers: { 'Content-Type': 'application/json' },
+            body: JSON.stringify({ main: project.main, files: textFiles }),
+        });
+        if (!response.ok) throw new Error(await response.text());
+        const snippet = await response.json();
+        history.replaceState(null, '', `/p/${snippet.id}`);
+        // Relative unless the server has a PUBLIC_URL
+        const url = new URL(snippet.url, location.origin).href;
+        await navigator.clipboard?.writeText(url).catch(() => {});
+        statusText.textContent = 'Link copied';
+        const expires = new Date(snippet.expires_at * 1000).toLocaleDateString();
+        const skipped = Object.keys(project.files).length - Object.keys(textFiles).length;
+        showNotice(`Shared ${url} (until ${expires})${skipped ? `, without ${skipped} binary asset(s)` : ''}`);
+    } catch (e) {
+        showError('Share failed: ' + e.message);
+    }
+}
+
+window.addEventListener('load', async () => {
+    await loadSnippet();
     initUI();
     connect();
 
@@ -0,0 +1,108 @@
+// PDF.js preview (v1.4): each page is drawn to its own canvas, and after a recompile only
+// the pages the server reports in `changed_pages` are redrawn, so the scroll position and
+// the untouched pages stay put while typing. PDF.js is served from our own origin
+// (public/vendor/pdfjs, vendored at build time); without it, app.js falls back to iframes.
+const PDFJS_BUILD = '/vendor/pdfjs';
+const ZOOM_STEPS = [0.5, 0.75, 1, 1.25, 1.5, 2];
+
+const pagesEl = document.getElementById('pdf-pages');
+const pageCountEl = document.getElementById('page-count');
+const zoomLevelEl = document.getElementById('zoom-level');
+
+let pdfjsLib = null;
+let doc = null;
+let zoom = 2;  // index into ZOOM_STEPS, 100% of the panel width
+let queue = Promise.resolve();
+
+try {
+    pdfjsLib = await import(`${PDFJS_BUILD}/pdf.min.mjs`);
+    pdfjsLib.GlobalWorkerOptions.workerSrc = `${PDFJS_BUILD}/pdf.worker.min.mjs`;
+} catch (e) {
+    console.warn('PDF.js unavailable, using the browser PDF viewer:', e);
+}
+
+// Renders run one at a time, in the order the compiles arrived
+function enqueue(task) {
+    queue = queue.then(task).catch(e => console.error('Preview render failed:', e));
+    return queue;
+}
+
+function pageCanvas(number) {
+    let canvas = pagesEl.querySelector(`canvas[data-page="${number}"]`);
+    if (!canvas) {
+        canvas = document.createElement('canvas');
+        canvas.className = 'pdf-page';
+        canvas.dataset.page = number;
+        pagesEl.appendChild(canvas);
+    }
+    return canvas;
+}
+
+async function drawPage(number) {
+    const page = await doc.getPage(number);
+    const fitWidth = (pagesEl.clientWidth - 32) / page.getViewport({ scale: 1 }).width;
+    const viewport = page.getViewport({ scale: fitWidth * ZOOM_STEPS[zoom] });
+    const ratio = window.devicePixelRatio || 1;
+
+    // Draw off-screen and swap, so the page never flashes blank
+    const canvas = document.createElement('canvas');
+    canvas.width = Math.floor(viewport.width * ratio);
+    canvas.height = Math.floor(viewport.height * ratio);
+    await page.render({
+        canvasContext: canvas.getContext('2d'),
+        viewport,
+        transform: ratio !== 1 ? [ratio, 0, 0, ratio, 0, 0] : null,
+    }).promise;
+
+    const target = pageCanvas(number);
+    target.width = canvas.width;
+    target.height = canvas.height;
+    target.style.width = `${Math.floor(viewport.width)}px`;
+    target.style.height = `${Math.floor(viewport.height)}px`;
+    target.getContext('2d').drawImage(canvas, 0, 0);
+}
+
+async function drawAll(changedPages) {
+    const count = doc.numPages;
+    const redraw = changedPages ? new Set(changedPages) : null;
+    for (let number = 1; number <= count; number++) {
+        const missing = !pagesEl.querySelector(`canvas[data-page="${number}"]`);
+        if (!redraw || redraw.has(number) || missing) {
+            await drawPage(number);
+        }
+    }
+    // Pages past the end of a shorter document
+    pagesEl.querySelectorAll('canvas').forEach(canvas => {
+        if (Number(canvas.dataset.page) > count) canvas.remove();
+    });
+    pageCountEl.textContent = `${count} ${count === 1 ? 'page' : 'pages'}`;
+    zoomLevelEl.textContent = `${Math.round(ZOOM_STEPS[zoom] * 100)}%`;
+}
+
+// `changedPages` is the server's list of 1-based pages that differ from the last compile;
+// null redraws every page
+function render(bytes, changedPages) {
+    return enqueue(async () => {
+        const next = await pdfjsLib.getDocument({ data: bytes }).promise;
+        const previous = doc;
+        doc = next;
+        await drawAll(changedPages);
+        if (previous) previous.destroy();
+    });
+}
+
+function setZoom(step) {
+    const next = Math.min(ZOOM_STEPS.length - 1, Math.max(0, zoom + step));
+    if (next === zoom || !doc) return;
+    zoom = next;
+    enqueue(() => drawAll(null));
+}
+
+let resizeTimer;
+window.addEventListener('resize', () => {
+    clearTimeout(resizeTimer);
+    resizeTimer = setTimeout(() => { if (doc) enqueue(() => drawAll(null)); }, 200);
+});
+
+window.tachyonViewer = pdfjsLib ? { render, zoomIn: () => setZoom(1), zoomOut: () => setZoom(-1) } : null;
+window.dispatchEvent(new Event('tachyon-viewer-ready'));
@@ -0,0 +1,192 @@
+use regex::Regex;
+use std::collections::{HashMap, HashSet};
+
+use crate::bib::strip_comment;
+use crate::models::{AssetInfo, AssetReport};
+
+/// Extensions treated as graphics when looking for unused project files.
+const GRAPHIC_EXTENSIONS: &[&str] = &["png", "jpg", "jpeg", "pdf", "eps", "svg", "gif", "bmp", "tif", "tiff"];
+
+/// An open figure/table environment and the assets nested inside it.
+struct OpenFloat {
+    env: String,
+    index: usize,
+    children: Vec<usize>,
+}
+
+pub struct AssetScanner;
+
+impl AssetScanner {
+    /// Lists every `\includegraphics`, figure, table and tabular in the project,
+    /// with labels, captions and whether anything `\ref`s them.
+    /// `files` are the remaining project file names, used to spot unused graphics.
+    pub fn scan(sources: &HashMap<String, String>, files: &[String]) -> AssetReport {
+        let token_re = Regex::new(
+            r"\\(begin|end)\s*\{([a-zA-Z]+\*?)\}|\\includegraphics\*?\s*(?:\[[^\]]*\])?\s*\{([^}]*)\}|\\caption\s*(?:\[[^\]]*\])?\s*\{|\\label\s*\{([^}]*)\}"
+        ).unwrap();
+        let ref_re = Regex::new(r"\\(?:[cCvV]ref|autoref|ref|pageref|eqref|nameref|labelcref)\*?\s*\{([^}]*)\}").unwrap();
+
+        let mut names: Vec<&String> = sources.keys().filter(|n| n.ends_with(".tex")).collect();
+        names.sort();
+
+        let mut assets: Vec<AssetInfo> = Vec::new();
+        let mut refs: HashSet<String> = HashSet::new();
+
+        for name in names {
+            let cleaned: String = sources[name].lines().map(strip_comment).collect::<Vec<_>>().join("\n");
+
+            for caps in ref_re.captures_iter(&cleaned) {
+                refs.extend(caps[1].split(',').map(|k| k.trim().to_string()));
+            }
+
+            let mut stack: Vec<OpenFloat> = Vec::new();
+            for caps in token_re.captures_iter(&cleaned) {
+                let m = caps.get(0).unwrap();
+                let line = (cleaned[..m.start()].matches('\n').count() + 1) as u32;
+                let token = m.as_str();
+
+                if let (Some(kind), Some(env)) = (caps.get(1), caps.get(2)) {
+                    let env = env.as_str();
+                    let base = env.trim_end_matches('*');
+                    if !matches!(base, "figure" | "table" | "tabular" | "tabularx" | "longtable" | "subfigure" | "wrapfigure") {
+                        continue;
+                    }
+                    if kind.as_str() == "begin" {
+                        let index = assets.len();
+                        assets.push(AssetInfo {
+                            kind: Self::kind_of(base).to_string(),
+                            file: name.clone(),
+                            line,
+                            path: None,
+                            label: None,
+                            caption: None,
+                            referenced: false,
+                        });
+                        if let Some(parent) = stack.last_mut() {
+                            parent.children.push(index);
+                        }
+                        stack.push(OpenFloat { env: env.to_string(), index, children: Vec::new() });
+                    } else if let Some(pos) = stack.iter().rposition(|f| f.env == env) {
+                        // Nested assets inherit the enclosing float's label and caption
+                        for closed in stack.drain(pos..).rev().collect::<Vec<_>>() {
+                            let (label, caption) = (assets[closed.index].label.clone(), assets[closed.index].caption.clone());
+                            for child in closed.children {
+                                if assets[child].label.is_none() { assets[child].label = label.clone(); }
+                                if assets[child].caption.is_none() { assets[child].caption = caption.clone(); }
+                            }
+                        }
+                    }
+                } else if let Some(path) = caps.get(3) {
+                    let index = assets.len();
+                    assets.push(AssetInfo {
+                        kind: "graphic".to_string(),
+                        file: name.clone(),
+                        line,
+                        path: Some(path.as_str().trim().to_string()),
+                        label: None,
+                        caption: None,
+                        referenced: false,
+                    });
+                    if let Some(parent) = stack.last_mut() {
+                        parent.children.push(index);
+                    }
+                } else if let Some(label) = caps.get(4) {
+                    if let Some(idx) = Self::innermost_float(&stack, &assets) {
+                        assets[idx].label.get_or_insert_with(|| label.as_str().trim().to_string());
+                    }
+                } else if token.starts_with("\\caption") {
+                    if let Some(idx) = Self::innermost_float(&stack, &assets) {
+                        let caption = Self::read_group(&cleaned[m.end()..]);
+                        assets[idx].caption.get_or_insert(caption);
+                    }
+                }
+            }
+        }
+
+        for asset in &mut assets {
+            asset.referenced = asset.label.as_ref().map(|l| refs.contains(l)).unwrap_or(false);
+        }
+
+        let used: Vec<&str> = assets.iter().filter_map(|a| a.path.as_deref()).collect();
+        let unused_files = files.iter()
+            .filter(|f| {
+                let ext = f.rsplit('.').next().unwrap_or("").to_ascii_lowercase();
+                GRAPHIC_EXTENSIONS.contains(&ext.as_str())
+            })
+            .filter(|f| {
+                let stem = f.rsplit_once('.').map(|(s, _)| s).unwrap_or(f);
+                !used.iter().any(|u| *u == f.as_str() || *u == stem || f.ends_with(&format!("/{}", u)) || stem.ends_with(&format!("/{}", u)))
+            })
+            .cloned()
+            .collect();
+
+        AssetReport { assets, unused_files }
+    }
+
+    fn kind_of(env: &str) -> &'static str {
+        match env {
+            "figure" | "subfigure" | "wrapfigure" => "figure",
+            "table" => "table",
+            _ => "tabular",
+        }
+    }
+
+    /// Index of the innermost open figure/table (captions and labels belong to floats, not tabulars).
+    fn innermost_float(stack: &[OpenFloat], assets: &[AssetInfo]) -> Option<usize> {
+        stack.iter().rev()
+            .map(|f| f.index)
+            .find(|&idx| assets[idx].kind != "tabular")
+    }
+
+    /// Reads a brace group whose opening `{` has already been consumed.
+    fn read_group(text: &str) -> String {
+        let mut depth = 1u32;
+        for (i, c) in text.char_indices() {
+            match c {
+                '{' => depth += 1,
+                '}' => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return text[..i].split_whitespace().collect::<Vec<_>>().join(" ");
+                    }
+                }
+                _ => {}
+            }
+        }
+        text.split_whitespace().collect::<Vec<_>>().join(" ")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scan_figures_and_unused() {
+        let mut sources = HashMap::new();
+        sources.insert("main.tex".to_string(), r#"\begin{figure}
+  \includegraphics[width=\linewidth]{img/plot}
+  \caption{A {nice}
+    plot}
+  \label{fig:plot}
+\end{figure}
+\begin{table}
+  \begin{tabular}{cc} a & b \end{tabular}
+  \caption{Numbers}\label{tab:nums}
+\end{table}
+See Figure~\ref{fig:plot}.
+"#.to_string());
+        let files = vec!["img/plot.png".to_string(), "img/old.jpg".to_string()];
+
+        let report = AssetScanner::scan(&sources, &files);
+        assert_eq!(report.assets.len(), 4);
+        let graphic = report.assets.iter().find(|a| a.kind == "graphic").unwrap();
+        assert_eq!(graphic.caption.as_deref(), Some("A {nice} plot"));
+        assert_eq!(graphic.label.as_deref(), Some("fig:plot"));
+        assert!(graphic.referenced);
+        let table = report.assets.iter().find(|a| a.kind == "table").unwrap();
+        assert_eq!(table.line, 7);
+        assert!(!table.referenced);
+        assert_eq!(report.unused_files, vec!["img/old.jpg".to_string()]);
+    }
+}
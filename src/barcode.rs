@@ -0,0 +1,214 @@
+use qrcode::{Color, QrCode};
+
+/// Code 128 bar/space width patterns, indexed by symbol value (0-105), plus STOP (106).
+const CODE128_PATTERNS: &[&str] = &[
+    "212222", "222122", "222221", "121223", "121322", "131222", "122213", "122312", "132212", "221213",
+    "221312", "231212", "112232", "122132", "122231", "113222", "123122", "123221", "223211", "221132",
+    "221231", "213212", "223112", "312131", "311222", "321122", "321221", "312212", "322112", "322211",
+    "212123", "212321", "232121", "111323", "131123", "131321", "112313", "132113", "132311", "211313",
+    "231113", "231311", "112133", "112331", "132131", "113123", "113321", "133121", "313121", "211331",
+    "231131", "213113", "213311", "213131", "311123", "311321", "331121", "312113", "312311", "332111",
+    "314111", "221411", "431111", "111224", "111422", "121124", "121421", "141122", "141221", "112214",
+    "112412", "122114", "122411", "142112", "142211", "241211", "221114", "413111", "241112", "134111",
+    "111242", "121142", "121241", "114212", "124112", "124211", "411212", "421112", "421211", "212141",
+    "214121", "412121", "111143", "111341", "131141", "114113", "114311", "411113", "411311", "113141",
+    "114131", "311141", "411131", "211412", "211214", "211232", "2331112",
+];
+
+const CODE128_START_B: usize = 104;
+const CODE128_STOP: usize = 106;
+
+/// Bar height of 1D barcodes, in modules.
+const BARCODE_HEIGHT_MODULES: usize = 50;
+
+/// Most bytes a QR code holds (version 40, low error correction).
+const MAX_QR_BYTES: usize = 2953;
+
+/// Most characters encoded as Code 128; each adds 11 modules to the width.
+const MAX_CODE128_CHARS: usize = 256;
+
+/// Largest PNG rendered, in pixels.
+pub const MAX_PNG_PIXELS: usize = 16_000_000;
+
+/// A monochrome module grid (row-major, `true` = dark).
+pub struct Grid {
+    pub width: usize,
+    pub height: usize,
+    pub cells: Vec<bool>,
+}
+
+impl Grid {
+    /// Adds a light border of `quiet` modules on every side.
+    fn with_quiet_zone(self, quiet: usize) -> Self {
+        let width = self.width + 2 * quiet;
+        let height = self.height + 2 * quiet;
+        let mut cells = vec![false; width * height];
+        for y in 0..self.height {
+            for x in 0..self.width {
+                cells[(y + quiet) * width + x + quiet] = self.cells[y * self.width + x];
+            }
+        }
+        Self { width, height, cells }
+    }
+}
+
+pub struct BarcodeGenerator;
+
+impl BarcodeGenerator {
+    pub fn qr(data: &str) -> Result<Grid, String> {
+        if data.len() > MAX_QR_BYTES {
+            return Err(format!("QR data is limited to {} bytes", MAX_QR_BYTES));
+        }
+        let code = QrCode::new(data.as_bytes()).map_err(|e| format!("QR encoding failed: {}", e))?;
+        let width = code.width();
+        let cells = code.to_colors().into_iter().map(|c| c == Color::Dark).collect();
+        Ok(Grid { width, height: width, cells }.with_quiet_zone(4))
+    }
+
+    /// Code 128 (subset B): printable ASCII only.
+    pub fn code128(data: &str) -> Result<Grid, String> {
+        if data.is_empty() || !data.bytes().all(|b| (32..127).contains(&b)) {
+            return Err("Code 128 data must be non-empty printable ASCII".to_string());
+        }
+        if data.len() > MAX_CODE128_CHARS {
+            return Err(format!("Code 128 data is limited to {} characters", MAX_CODE128_CHARS));
+        }
+
+        let mut symbols = vec![CODE128_START_B];
+        symbols.extend(data.bytes().map(|b| (b - 32) as usize));
+        let checksum = symbols.iter().enumerate()
+            .map(|(i, &v)| if i == 0 { v } else { v * i })
+            .sum::<usize>() % 103;
+        symbols.push(checksum);
+        symbols.push(CODE128_STOP);
+
+        let mut row = Vec::new();
+        for symbol in symbols {
+            for (i, w) in CODE128_PATTERNS[symbol].bytes().enumerate() {
+                // Patterns alternate bar, space, bar, ...
+                row.extend(std::iter::repeat(i % 2 == 0).take((w - b'0') as usize));
+            }
+        }
+
+        let width = row.len();
+        let cells = row.iter().copied().cycle().take(width * BARCODE_HEIGHT_MODULES).collect();
+        Ok(Grid { width, height: BARCODE_HEIGHT_MODULES, cells }.with_quiet_zone(10))
+    }
+
+    /// Refuses PNGs of the grid at `scale` larger than [`MAX_PNG_PIXELS`].
+    pub fn check_png_size(grid: &Grid, scale: usize) -> Result<(), String> {
+        let pixels = (grid.width * scale).checked_mul(grid.height * scale);
+        if pixels.is_none_or(|pixels| pixels > MAX_PNG_PIXELS) {
+            return Err(format!("A {}x{} module barcode at scale {} exceeds {} pixels; lower the scale", grid.width, grid.height, scale, MAX_PNG_PIXELS));
+        }
+        Ok(())
+    }
+
+    /// Renders the grid as an 8-bit grayscale PNG, `scale` pixels per module.
+    pub fn to_png(grid: &Grid, scale: usize) -> Result<Vec<u8>, String> {
+        Self::check_png_size(grid, scale)?;
+        let (w, h) = (grid.width * scale, grid.height * scale);
+        let mut pixels = Vec::with_capacity(w * h);
+        for y in 0..h {
+            for x in 0..w {
+                pixels.push(if grid.cells[(y / scale) * grid.width + x / scale] { 0u8 } else { 255u8 });
+            }
+        }
+
+        let mut out = Vec::new();
+        {
+            let mut encoder = png::Encoder::new(&mut out, w as u32, h as u32);
+            encoder.set_color(png::ColorType::Grayscale);
+            encoder.set_depth(png::BitDepth::Eight);
+            let mut writer = encoder.write_header().map_err(|e| e.to_string())?;
+            writer.write_image_data(&pixels).map_err(|e| e.to_string())?;
+        }
+        Ok(out)
+    }
+
+    /// Renders the grid as a single-page vector PDF, `scale` points per module.
+    pub fn to_pdf(grid: &Grid, scale: usize) -> Vec<u8> {
+        let mut content = String::from("0 0 0 rg\n");
+        for y in 0..grid.height {
+            for x in 0..grid.width {
+                if grid.cells[y * grid.width + x] {
+                    // PDF origin is bottom-left
+                    let py = (grid.height - 1 - y) * scale;
+                    content.push_str(&format!("{} {} {} {} re\n", x * scale, py, scale, scale));
+                }
+            }
+        }
+        content.push_str("f\n");
+
+        let objects = [
+            "<< /Type /Catalog /Pages 2 0 R >>".to_string(),
+            "<< /Type /Pages /Kids [3 0 R] /Count 1 >>".to_string(),
+            format!(
+                "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 {} {}] /Contents 4 0 R /Resources << >> >>",
+                grid.width * scale, grid.height * scale
+            ),
+            format!("<< /Length {} >>\nstream\n{}endstream", content.len(), content),
+        ];
+
+        let mut pdf = b"%PDF-1.4\n".to_vec();
+        let mut offsets = Vec::new();
+        for (i, body) in objects.iter().enumerate() {
+            offsets.push(pdf.len());
+            pdf.extend_from_slice(format!("{} 0 obj\n{}\nendobj\n", i + 1, body).as_bytes());
+        }
+        let xref_offset = pdf.len();
+        let mut xref = format!("xref\n0 {}\n0000000000 65535 f \n", objects.len() + 1);
+        for offset in offsets {
+            xref.push_str(&format!("{:010} 00000 n \n", offset));
+        }
+        pdf.extend_from_slice(xref.as_bytes());
+        pdf.extend_from_slice(format!(
+            "trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{}\n%%EOF\n",
+            objects.len() + 1, xref_offset
+        ).as_bytes());
+        pdf
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_code128_patterns_are_well_formed() {
+        assert_eq!(CODE128_PATTERNS.len(), 107);
+        for (i, p) in CODE128_PATTERNS.iter().enumerate() {
+            let modules: u32 = p.bytes().map(|b| (b - b'0') as u32).sum();
+            assert_eq!(modules, if i == CODE128_STOP { 13 } else { 11 }, "pattern {}", i);
+        }
+    }
+
+    #[test]
+    fn test_code128_width() {
+        // start + 3 chars + checksum = 5 symbols of 11 modules, stop = 13, quiet zones = 20
+        let grid = BarcodeGenerator::code128("ABC").unwrap();
+        assert_eq!(grid.width, 5 * 11 + 13 + 20);
+        assert!(BarcodeGenerator::code128("héllo").is_err());
+    }
+
+    #[test]
+    fn test_qr_renders() {
+        let grid = BarcodeGenerator::qr("https://example.com").unwrap();
+        let png = BarcodeGenerator::to_png(&grid, 2).unwrap();
+        assert!(png.starts_with(b"\x89PNG"));
+        let pdf = BarcodeGenerator::to_pdf(&grid, 2);
+        assert!(pdf.starts_with(b"%PDF-1.4") && pdf.ends_with(b"%%EOF\n"));
+    }
+
+    #[test]
+    fn test_size_limits() {
+        assert!(BarcodeGenerator::code128(&"A".repeat(MAX_CODE128_CHARS + 1)).is_err());
+        assert!(BarcodeGenerator::qr(&"A".repeat(MAX_QR_BYTES + 1)).is_err());
+
+        // 256 characters are ~2,900 modules wide: fine at scale 4, too many pixels at 64
+        let grid = BarcodeGenerator::code128(&"A".repeat(MAX_CODE128_CHARS)).unwrap();
+        assert!(BarcodeGenerator::check_png_size(&grid, 4).is_ok());
+        assert!(BarcodeGenerator::check_png_size(&grid, 64).is_err());
+        assert!(BarcodeGenerator::to_png(&grid, 64).is_err());
+    }
+}
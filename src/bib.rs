@@ -0,0 +1,454 @@
+use regex::Regex;
+use std::collections::{HashMap, HashSet};
+
+use crate::models::{BibDuplicate, BibFormatReport, ValidationMessage};
+
+/// Canonical field order used when normalizing entries.
+/// Fields not listed here keep their relative order after these.
+const FIELD_ORDER: &[&str] = &[
+    "author", "editor", "title", "booktitle", "journal", "series", "edition",
+    "volume", "number", "pages", "chapter", "publisher", "school", "institution",
+    "organization", "address", "month", "year", "doi", "isbn", "issn", "url", "note",
+];
+
+/// Minimum bigram similarity for two titles to be considered the same work.
+const TITLE_SIMILARITY_THRESHOLD: f64 = 0.9;
+
+/// A single parsed BibTeX entry.
+/// Field values are kept verbatim (including their `{}` or `""` delimiters)
+/// so they can be written back without losing information.
+#[derive(Debug, Clone)]
+pub struct BibEntry {
+    pub entry_type: String,
+    pub key: String,
+    pub fields: Vec<(String, String)>,
+    pub line: u32,
+}
+
+impl BibEntry {
+    /// Returns the raw value of a field (case-insensitive lookup).
+    pub fn field(&self, name: &str) -> Option<&str> {
+        self.fields.iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+/// Strips the outer `{}` or `""` delimiters from a raw field value.
+pub fn unwrap_value(raw: &str) -> &str {
+    let raw = raw.trim();
+    if raw.len() >= 2 && ((raw.starts_with('{') && raw.ends_with('}')) || (raw.starts_with('"') && raw.ends_with('"'))) {
+        &raw[1..raw.len() - 1]
+    } else {
+        raw
+    }
+}
+
+/// Location of a top-level `@type{...}` block inside a .bib file.
+struct RawBlock {
+    entry_type: String,
+    start: usize,
+    open: usize,
+    end: usize,
+}
+
+pub struct BibParser;
+
+impl BibParser {
+    /// Parses every regular entry of a .bib file.
+    /// `@comment`, `@preamble` and `@string` blocks are skipped.
+    pub fn parse(content: &str) -> Vec<BibEntry> {
+        let mut entries = Vec::new();
+
+        for block in Self::scan(content) {
+            if matches!(block.entry_type.as_str(), "comment" | "preamble" | "string") {
+                continue;
+            }
+
+            let body = &content[block.open + 1..block.end];
+            let (key, rest) = match body.find(',') {
+                Some(comma) => (body[..comma].trim(), &body[comma + 1..]),
+                None => (body.trim(), ""),
+            };
+            if key.is_empty() {
+                continue;
+            }
+
+            entries.push(BibEntry {
+                entry_type: block.entry_type,
+                key: key.to_string(),
+                fields: Self::parse_fields(rest),
+                line: (content[..block.start].matches('\n').count() + 1) as u32,
+            });
+        }
+
+        entries
+    }
+
+    /// Returns the verbatim text of `@string` and `@preamble` blocks,
+    /// which must survive reformatting for macros to keep resolving.
+    pub fn directives(content: &str) -> Vec<String> {
+        Self::scan(content)
+            .into_iter()
+            .filter(|b| matches!(b.entry_type.as_str(), "preamble" | "string"))
+            .map(|b| content[b.start..(b.end + 1).min(content.len())].to_string())
+            .collect()
+    }
+
+    fn scan(content: &str) -> Vec<RawBlock> {
+        let bytes = content.as_bytes();
+        let mut blocks = Vec::new();
+        let mut pos = 0;
+
+        while let Some(offset) = content[pos..].find('@') {
+            let start = pos + offset;
+            pos = start + 1;
+
+            let mut type_end = pos;
+            while type_end < bytes.len() && (bytes[type_end].is_ascii_alphanumeric() || bytes[type_end] == b'_') {
+                type_end += 1;
+            }
+            let entry_type = content[pos..type_end].to_ascii_lowercase();
+
+            let mut open = type_end;
+            while open < bytes.len() && bytes[open].is_ascii_whitespace() {
+                open += 1;
+            }
+            if entry_type.is_empty() || open >= bytes.len() || (bytes[open] != b'{' && bytes[open] != b'(') {
+                continue;
+            }
+
+            let end = Self::body_end(bytes, open);
+            pos = (end + 1).min(bytes.len());
+            blocks.push(RawBlock { entry_type, start, open, end });
+        }
+
+        blocks
+    }
+
+    /// Returns the index of the delimiter closing the entry opened at `open`
+    /// (or the end of input if the entry is unterminated).
+    fn body_end(bytes: &[u8], open: usize) -> usize {
+        let close = if bytes[open] == b'(' { b')' } else { b'}' };
+        let mut depth = 0u32;
+        for (i, &b) in bytes.iter().enumerate().skip(open + 1) {
+            match b {
+                b'{' => depth += 1,
+                b'}' if depth > 0 => depth -= 1,
+                c if c == close && depth == 0 => return i,
+                _ => {}
+            }
+        }
+        bytes.len()
+    }
+
+    fn parse_fields(body: &str) -> Vec<(String, String)> {
+        let bytes = body.as_bytes();
+        let mut fields = Vec::new();
+        let mut i = 0;
+
+        while i < bytes.len() {
+            let eq = match body[i..].find('=') {
+                Some(o) => i + o,
+                None => break,
+            };
+            let name = body[i..eq].trim().trim_start_matches(',').trim().to_ascii_lowercase();
+
+            // Value ends at the first comma outside braces and quotes
+            let mut j = eq + 1;
+            let mut depth = 0u32;
+            let mut in_quotes = false;
+            while j < bytes.len() {
+                match bytes[j] {
+                    b'{' => depth += 1,
+                    b'}' => depth = depth.saturating_sub(1),
+                    b'"' if depth == 0 => in_quotes = !in_quotes,
+                    b',' if depth == 0 && !in_quotes => break,
+                    _ => {}
+                }
+                j += 1;
+            }
+
+            let value = body[eq + 1..j].trim().to_string();
+            if !name.is_empty() {
+                fields.push((name, value));
+            }
+            i = j + 1;
+        }
+
+        fields
+    }
+}
+
+pub struct BibFormatter;
+
+impl BibFormatter {
+    /// Normalizes a .bib file: lowercase types and field names, canonical field
+    /// order, brace-delimited values, and duplicate entries merged by DOI or title.
+    pub fn format(content: &str) -> (String, BibFormatReport) {
+        let parsed = BibParser::parse(content);
+        let entries_in = parsed.len();
+        let mut empty_fields_removed = 0;
+        let mut kept: Vec<BibEntry> = Vec::new();
+        let mut duplicates = Vec::new();
+
+        for mut entry in parsed {
+            let before = entry.fields.len();
+            entry.fields.retain(|(_, v)| !unwrap_value(v).trim().is_empty());
+            empty_fields_removed += before - entry.fields.len();
+
+            match kept.iter_mut().find_map(|k| Self::duplicate_reason(k, &entry).map(|r| (k, r))) {
+                Some((original, reason)) => {
+                    // Keep the first entry, but adopt any fields only the duplicate has
+                    for (name, value) in entry.fields {
+                        if original.field(&name).is_none() {
+                            original.fields.push((name, value));
+                        }
+                    }
+                    duplicates.push(BibDuplicate {
+                        kept: original.key.clone(),
+                        removed: entry.key,
+                        reason: reason.to_string(),
+                    });
+                }
+                None => kept.push(entry),
+            }
+        }
+
+        let mut out = String::new();
+        for directive in BibParser::directives(content) {
+            out.push_str(&directive);
+            out.push_str("\n\n");
+        }
+        for entry in &kept {
+            out.push_str(&Self::render(entry));
+            out.push('\n');
+        }
+
+        let report = BibFormatReport {
+            entries_in,
+            entries_out: kept.len(),
+            empty_fields_removed,
+            duplicates,
+        };
+        (out, report)
+    }
+
+    fn duplicate_reason(a: &BibEntry, b: &BibEntry) -> Option<&'static str> {
+        if a.key == b.key {
+            return Some("key");
+        }
+        if let (Some(da), Some(db)) = (a.field("doi"), b.field("doi")) {
+            if Self::normalize_doi(da) == Self::normalize_doi(db) {
+                return Some("doi");
+            }
+        }
+        if let (Some(ta), Some(tb)) = (a.field("title"), b.field("title")) {
+            if title_similarity(unwrap_value(ta), unwrap_value(tb)) >= TITLE_SIMILARITY_THRESHOLD {
+                return Some("title");
+            }
+        }
+        None
+    }
+
+    fn normalize_doi(raw: &str) -> String {
+        let doi = unwrap_value(raw).trim().to_ascii_lowercase();
+        ["https://doi.org/", "http://doi.org/", "https://dx.doi.org/", "http://dx.doi.org/", "doi:"]
+            .iter()
+            .find_map(|p| doi.strip_prefix(p))
+            .unwrap_or(&doi)
+            .trim()
+            .to_string()
+    }
+
+    fn render(entry: &BibEntry) -> String {
+        let mut fields: Vec<&(String, String)> = entry.fields.iter().collect();
+        // Stable sort: unknown fields keep their original relative order at the end
+        fields.sort_by_key(|(name, _)| FIELD_ORDER.iter().position(|f| f == name).unwrap_or(FIELD_ORDER.len()));
+
+        let mut out = format!("@{}{{{},\n", entry.entry_type, entry.key);
+        for (name, value) in fields {
+            out.push_str(&format!("  {} = {},\n", name, Self::normalize_value(value)));
+        }
+        out.push_str("}\n");
+        out
+    }
+
+    /// Collapses whitespace and converts `"..."` values to `{...}`.
+    /// Bare numbers, macros and `#` concatenations are left as-is.
+    fn normalize_value(raw: &str) -> String {
+        let collapsed = raw.split_whitespace().collect::<Vec<_>>().join(" ");
+        if collapsed.len() >= 2 && collapsed.starts_with('"') && collapsed.ends_with('"') && !collapsed.contains('#') {
+            format!("{{{}}}", &collapsed[1..collapsed.len() - 1])
+        } else {
+            collapsed
+        }
+    }
+}
+
+/// Dice coefficient over character bigrams of the normalized titles (0.0 to 1.0).
+pub fn title_similarity(a: &str, b: &str) -> f64 {
+    fn bigrams(s: &str) -> Vec<(char, char)> {
+        let normalized: Vec<char> = s.chars()
+            .filter(|c| c.is_alphanumeric())
+            .flat_map(char::to_lowercase)
+            .collect();
+        normalized.windows(2).map(|w| (w[0], w[1])).collect()
+    }
+
+    let (ba, mut bb) = (bigrams(a), bigrams(b));
+    if ba.is_empty() || bb.is_empty() {
+        return if a.trim().eq_ignore_ascii_case(b.trim()) { 1.0 } else { 0.0 };
+    }
+    let total = ba.len() + bb.len();
+    let mut matches = 0;
+    for pair in &ba {
+        if let Some(idx) = bb.iter().position(|p| p == pair) {
+            bb.swap_remove(idx);
+            matches += 1;
+        }
+    }
+    (2 * matches) as f64 / total as f64
+}
+
+pub struct CitationChecker;
+
+impl CitationChecker {
+    /// Cross-checks `\cite` keys in .tex sources against entries of .bib sources.
+    /// Reports citations with no matching entry and entries that are never cited.
+    pub fn check(sources: &HashMap<String, String>) -> Vec<ValidationMessage> {
+        let cite_re = Regex::new(r"\\[a-zA-Z]*cite[a-zA-Z]*\*?(?:\s*\[[^\]]*\]){0,2}\s*\{([^}]*)\}").unwrap();
+        let bibitem_re = Regex::new(r"\\bibitem\s*(?:\[[^\]]*\])?\s*\{([^}]+)\}").unwrap();
+
+        let mut names: Vec<&String> = sources.keys().collect();
+        names.sort();
+
+        let mut bib_entries: Vec<(String, BibEntry)> = Vec::new();
+        let mut defined: HashSet<String> = HashSet::new();
+        let mut citations: Vec<(String, u32, String)> = Vec::new();
+        let mut cite_all = false;
+
+        for name in names {
+            let content = &sources[name];
+            if name.ends_with(".bib") {
+                for entry in BibParser::parse(content) {
+                    defined.insert(entry.key.clone());
+                    bib_entries.push((name.clone(), entry));
+                }
+            } else if name.ends_with(".tex") {
+                for (idx, line) in content.lines().enumerate() {
+                    let line = strip_comment(line);
+                    for caps in bibitem_re.captures_iter(line) {
+                        defined.insert(caps[1].trim().to_string());
+                    }
+                    for caps in cite_re.captures_iter(line) {
+                        for key in caps[1].split(',').map(str::trim).filter(|k| !k.is_empty()) {
+                            if key == "*" {
+                                cite_all = true;
+                            } else {
+                                citations.push((name.clone(), idx as u32 + 1, key.to_string()));
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        let mut messages = Vec::new();
+        let cited: HashSet<&str> = citations.iter().map(|(_, _, k)| k.as_str()).collect();
+
+        for (file, line, key) in &citations {
+            if !defined.contains(key) {
+                messages.push(ValidationMessage {
+                    file: file.clone(),
+                    line: *line,
+                    message: format!("Citation '{}' is not defined in any .bib file", key),
+                });
+            }
+        }
+
+        if !cite_all {
+            for (file, entry) in &bib_entries {
+                if !cited.contains(entry.key.as_str()) {
+                    messages.push(ValidationMessage {
+                        file: file.clone(),
+                        line: entry.line,
+                        message: format!("Bibliography entry '{}' is never cited", entry.key),
+                    });
+                }
+            }
+        }
+
+        messages
+    }
+}
+
+/// Removes a trailing `%` comment from a line. A `%` after an odd number of backslashes
+/// is escaped (`\%`, `\\\%`); after an even number (`\\%`, a line break) it starts one.
+pub fn strip_comment(line: &str) -> &str {
+    let mut escaped = false;
+    for (i, &b) in line.as_bytes().iter().enumerate() {
+        if b == b'%' && !escaped {
+            return &line[..i];
+        }
+        escaped = b == b'\\' && !escaped;
+    }
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_strip_comment() {
+        assert_eq!(strip_comment("a % note"), "a ");
+        assert_eq!(strip_comment("50\\% done"), "50\\% done");
+        assert_eq!(strip_comment("a\\\\% note"), "a\\\\");
+        assert_eq!(strip_comment("50\\\\\\% done % note"), "50\\\\\\% done ");
+    }
+
+    #[test]
+    fn test_parse_entries() {
+        let bib = r#"@comment{ignored}
+@article{knuth84,
+  author = {Donald E. Knuth},
+  title = "Literate {Programming}",
+  year = 1984,
+}
+"#;
+        let entries = BibParser::parse(bib);
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].key, "knuth84");
+        assert_eq!(entries[0].line, 2);
+        assert_eq!(entries[0].field("year"), Some("1984"));
+        assert_eq!(unwrap_value(entries[0].field("title").unwrap()), "Literate {Programming}");
+    }
+
+    #[test]
+    fn test_format_normalizes_and_dedups() {
+        let bib = r#"@string{acm = "ACM"}
+@ARTICLE{a1, Year = 2001, TITLE = "Fast   Compilers", doi = {10.1/XYZ}, note = {}}
+@article{a2, title = {Fast compilers.}, publisher = acm}
+@article{a3, title = {Other}, doi = {https://doi.org/10.1/xyz}}
+"#;
+        let (out, report) = BibFormatter::format(bib);
+        assert_eq!(report.entries_in, 3);
+        assert_eq!(report.entries_out, 1);
+        assert_eq!(report.empty_fields_removed, 1);
+        assert!(out.starts_with("@string{acm = \"ACM\"}"));
+        assert!(out.contains("@article{a1,\n  title = {Fast Compilers},\n  publisher = acm,\n  year = 2001,\n  doi = {10.1/XYZ},\n}"));
+    }
+
+    #[test]
+    fn test_undefined_and_uncited() {
+        let mut sources = HashMap::new();
+        sources.insert("main.tex".to_string(), "See \\cite{knuth84, missing}.\n% \\cite{commented}\n".to_string());
+        sources.insert("refs.bib".to_string(), "@book{knuth84, title={TAOCP}}\n@book{unused, title={X}}\n".to_string());
+
+        let messages = CitationChecker::check(&sources);
+        assert_eq!(messages.len(), 2);
+        assert!(messages.iter().any(|m| m.file == "main.tex" && m.line == 1 && m.message.contains("'missing'")));
+        assert!(messages.iter().any(|m| m.file == "refs.bib" && m.line == 2 && m.message.contains("'unused'")));
+    }
+}
@@ -0,0 +1,247 @@
+//! TeX bundle upgrades (`/admin/bundles`): find newer Tectonic bundles, stage one next to the
+//! active bundle, rebuild the regression tests (`/tests`) with it, then switch to it or roll
+//! back in one step. The choice is kept in storage, so a pinned bundle survives restarts.
+
+use axum::http::StatusCode;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tracing::{info, warn};
+use xxhash_rust::xxh64::xxh64;
+
+use crate::compiler::CapturingStatusBackend;
+use crate::models::{BundleRelease, BundleStatus, BundleTrial, BundleUpdate};
+use crate::services::AppState;
+use crate::settings::Settings;
+use crate::storage::Storage;
+use crate::util::unix_now;
+
+const STATE_KEY: &str = "bundles/state";
+
+/// Release number of the bundle Tectonic 0.15 uses by default, which BUNDLE_VERSION
+/// `default` stands for when looking for updates.
+const TECTONIC_DEFAULT_RELEASE: u32 = 33;
+
+/// Newer releases probed past the active one.
+const MAX_PROBES: u32 = 10;
+
+const PROBE_TIMEOUT: Duration = Duration::from_secs(15);
+
+#[derive(Clone)]
+pub struct BundleManager {
+    status: Arc<RwLock<BundleStatus>>,
+    storage: Arc<dyn Storage>,
+}
+
+impl BundleManager {
+    /// The bundles recorded in storage; on first start, BUNDLE_VERSION at BUNDLE_URL.
+    pub async fn load(storage: Arc<dyn Storage>, settings: &Settings) -> Self {
+        let stored = match storage.get(STATE_KEY).await {
+            Ok(data) => data.and_then(|data| serde_json::from_slice::<BundleStatus>(&data).ok()),
+            Err(e) => {
+                warn!("Failed to read the bundle state: {}", e);
+                None
+            }
+        };
+        let status = stored.unwrap_or_else(|| BundleStatus {
+            active: BundleRelease { version: settings.bundle_version.clone(), url: settings.bundle_url.clone(), since: unix_now(), trial: None },
+            staged: None,
+            previous: None,
+        });
+        Self { status: Arc::new(RwLock::new(status)), storage }
+    }
+
+    pub fn status(&self) -> BundleStatus {
+        self.status.read().unwrap().clone()
+    }
+
+    pub fn active(&self) -> BundleRelease {
+        self.status.read().unwrap().active.clone()
+    }
+
+    pub fn staged(&self) -> Option<BundleRelease> {
+        self.status.read().unwrap().staged.clone()
+    }
+
+    /// Stages a bundle, replacing any staged before.
+    pub async fn stage(&self, version: String, url: String) -> Result<BundleStatus, (StatusCode, String)> {
+        self.update(|status| {
+            if status.active.version == version {
+                return Err((StatusCode::CONFLICT, format!("Bundle {} is already active", version)));
+            }
+            status.staged = Some(BundleRelease { version, url: Some(url), since: unix_now(), trial: None });
+            Ok(())
+        }).await
+    }
+
+    pub async fn unstage(&self) -> Result<BundleStatus, (StatusCode, String)> {
+        self.update(|status| match status.staged.take() {
+            Some(_) => Ok(()),
+            None => Err((StatusCode::NOT_FOUND, "No bundle is staged".to_string())),
+        }).await
+    }
+
+    /// Records a trial on the staged bundle, unless another was staged in the meantime.
+    pub async fn record_trial(&self, version: &str, trial: BundleTrial) -> Result<BundleStatus, (StatusCode, String)> {
+        self.update(|status| match status.staged.as_mut().filter(|staged| staged.version == version) {
+            Some(staged) => {
+                staged.trial = Some(trial);
+                Ok(())
+            }
+            None => Err((StatusCode::CONFLICT, format!("Bundle {} is no longer staged", version))),
+        }).await
+    }
+
+    /// Makes the staged bundle the active one, keeping the active one for rollback. Unless
+    /// `force`d, the staged bundle must have passed a trial.
+    pub async fn activate(&self, force: bool) -> Result<BundleStatus, (StatusCode, String)> {
+        self.update(|status| {
+            let Some(mut staged) = status.staged.take() else {
+                return Err((StatusCode::NOT_FOUND, "No bundle is staged".to_string()));
+            };
+            let verdict = match &staged.trial {
+                None => Some("has not been tried against the regression tests".to_string()),
+                Some(trial) if trial.failed > 0 => Some(format!("failed {} of {} regression test(s)", trial.failed, trial.tests)),
+                Some(_) => None,
+            };
+            if let Some(verdict) = verdict.filter(|_| !force) {
+                let message = format!("Bundle {} {}; pass force=true to activate it anyway", staged.version, verdict);
+                status.staged = Some(staged);
+                return Err((StatusCode::CONFLICT, message));
+            }
+            staged.since = unix_now();
+            status.previous = Some(std::mem::replace(&mut status.active, staged));
+            Ok(())
+        }).await
+    }
+
+    /// Swaps the active bundle with the one it replaced.
+    pub async fn rollback(&self) -> Result<BundleStatus, (StatusCode, String)> {
+        self.update(|status| {
+            let Some(mut previous) = status.previous.take() else {
+                return Err((StatusCode::NOT_FOUND, "There is no previous bundle to roll back to".to_string()));
+            };
+            previous.since = unix_now();
+            status.previous = Some(std::mem::replace(&mut status.active, previous));
+            Ok(())
+        }).await
+    }
+
+    /// Applies `change` under the lock, then persists the result. Compiles pick up a new
+    /// active bundle from their next start.
+    async fn update(&self, change: impl FnOnce(&mut BundleStatus) -> Result<(), (StatusCode, String)>) -> Result<BundleStatus, (StatusCode, String)> {
+        let status = {
+            let mut status = self.status.write().unwrap();
+            change(&mut status)?;
+            status.clone()
+        };
+        let data = serde_json::to_vec(&status).map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+        if let Err(e) = self.storage.put(STATE_KEY, data).await {
+            // Still applied: the state is only lost on restart
+            warn!("Failed to persist the bundle state: {}", e);
+        }
+        Ok(status)
+    }
+}
+
+/// Cache key seed for a bundle: 0 for BUNDLE_VERSION, so keys written before any switch
+/// stay valid.
+pub fn cache_seed(settings: &Settings, version: &str) -> u64 {
+    if version == settings.bundle_version { 0 } else { xxh64(version.as_bytes(), 0) }
+}
+
+/// Points the caches at the active bundle and rebuilds the regression tests last run on
+/// another one. Called at startup and after every switch.
+pub fn activated(state: &AppState) {
+    let active = state.bundles.active();
+    state.compilation_cache.set_bundle_seed(cache_seed(&state.settings, &active.version));
+    info!("📚 TeX bundle {} ({})", active.version, active.url.as_deref().unwrap_or("Tectonic default"));
+    tokio::spawn(crate::regression::run_stale(state.clone()));
+}
+
+pub fn release_url(settings: &Settings, version: &str) -> String {
+    settings.bundle_url_template.replace("{version}", version)
+}
+
+/// Numbered releases past the active one that exist at BUNDLE_URL_TEMPLATE, probed in
+/// order until one is missing.
+pub async fn check_updates(settings: &Settings, active: &str) -> Result<Vec<BundleUpdate>, (StatusCode, String)> {
+    let current = match active {
+        "default" => TECTONIC_DEFAULT_RELEASE,
+        version => version.trim_start_matches('v').parse::<u32>().map_err(|_| {
+            (StatusCode::CONFLICT, format!("Bundle version '{}' is not a release number; stage updates by URL", version))
+        })?,
+    };
+    let client = reqwest::Client::builder().timeout(PROBE_TIMEOUT).build().map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    let mut available = Vec::new();
+    for release in current + 1..=current + MAX_PROBES {
+        let version = release.to_string();
+        let url = release_url(settings, &version);
+        let response = client.head(&url).send().await.map_err(|e| (StatusCode::BAD_GATEWAY, format!("Cannot reach {}: {}", url, e)))?;
+        if !response.status().is_success() {
+            break;
+        }
+        available.push(BundleUpdate { version, url });
+    }
+    Ok(available)
+}
+
+/// Opens a bundle through Tectonic's cache, downloading its index, so a bundle that cannot
+/// be used is refused when staged rather than when compiles start failing.
+pub async fn open(state: &AppState, url: &str) -> Result<(), String> {
+    let (config, url) = (state.config.clone(), url.to_string());
+    tokio::task::spawn_blocking(move || {
+        let mut status = CapturingStatusBackend::new();
+        config.make_cached_url_provider(&url, false, None, &mut status)
+            .map(|_| ())
+            .map_err(|e| format!("Cannot open bundle {}: {}\n{}", url, e, status.get_logs()))
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+
+    fn trial(failed: usize) -> BundleTrial {
+        BundleTrial { ran_at: 0, tests: 2, passed: 2 - failed, failed, results: Vec::new() }
+    }
+
+    #[tokio::test]
+    async fn test_stage_activate_rollback() {
+        let storage: Arc<dyn Storage> = Arc::new(MemoryStorage::new());
+        let settings = Settings::from_env();
+        let bundles = BundleManager::load(storage.clone(), &settings).await;
+        assert_eq!(bundles.active().version, settings.bundle_version);
+        assert_eq!(bundles.stage(settings.bundle_version.clone(), "https://b/x.tar".into()).await.unwrap_err().0, StatusCode::CONFLICT);
+
+        bundles.stage("34".into(), release_url(&settings, "34")).await.unwrap();
+        assert_eq!(bundles.activate(false).await.unwrap_err().0, StatusCode::CONFLICT, "untried");
+        bundles.record_trial("34", trial(1)).await.unwrap();
+        assert_eq!(bundles.activate(false).await.unwrap_err().0, StatusCode::CONFLICT, "failed trial");
+        assert_eq!(bundles.record_trial("35", trial(0)).await.unwrap_err().0, StatusCode::CONFLICT);
+        bundles.record_trial("34", trial(0)).await.unwrap();
+
+        let status = bundles.activate(false).await.unwrap();
+        assert_eq!(status.active.version, "34");
+        assert_eq!(status.active.url.as_deref(), Some("https://relay.fullyjustified.net/default_bundle_v34.tar"));
+        assert_eq!(status.previous.unwrap().version, settings.bundle_version);
+        assert!(status.staged.is_none());
+
+        // The switch is pinned across restarts
+        let reloaded = BundleManager::load(storage, &settings).await;
+        assert_eq!(reloaded.active().version, "34");
+        let status = reloaded.rollback().await.unwrap();
+        assert_eq!(status.active.version, settings.bundle_version);
+        assert_eq!(status.previous.unwrap().version, "34");
+        assert_eq!(reloaded.unstage().await.unwrap_err().0, StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn test_cache_seed() {
+        let settings = Settings::from_env();
+        assert_eq!(cache_seed(&settings, &settings.bundle_version), 0);
+        assert_ne!(cache_seed(&settings, "34"), cache_seed(&settings, "35"));
+    }
+}
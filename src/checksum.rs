@@ -0,0 +1,142 @@
+//! Per-file checksums sent with an upload, verified once the bytes are on disk so a
+//! corrupted transfer is reported file by file instead of surfacing as a compile error.
+//!
+//! A checksum is written `<algorithm>=<hex>` (or with `:`), with `xxh64` or `sha256`, either in
+//! the `X-Checksum` header of a multipart part or in a `checksums` field holding a JSON object
+//! of file name to checksum.
+
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::Path;
+use xxhash_rust::xxh64::Xxh64;
+
+use crate::models::ChecksumMismatch;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Algorithm {
+    Xxh64,
+    Sha256,
+}
+
+impl Algorithm {
+    pub fn name(self) -> &'static str {
+        match self {
+            Algorithm::Xxh64 => "xxh64",
+            Algorithm::Sha256 => "sha256",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Checksum {
+    pub algorithm: Algorithm,
+    /// Lowercase hex digest
+    pub digest: String,
+}
+
+impl Checksum {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let (algorithm, digest) = value
+            .trim()
+            .split_once(['=', ':'])
+            .ok_or_else(|| format!("Checksum '{}' must look like sha256=<hex> or xxh64=<hex>", value))?;
+        let digest = digest.trim().to_ascii_lowercase();
+        let (algorithm, length) = match algorithm.trim().to_ascii_lowercase().as_str() {
+            "xxh64" => (Algorithm::Xxh64, 16),
+            "sha256" => (Algorithm::Sha256, 64),
+            other => return Err(format!("Unsupported checksum algorithm '{}' (use xxh64 or sha256)", other)),
+        };
+        if digest.is_empty() || digest.len() > length || !digest.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(format!("'{}' is not a valid {} digest", digest, algorithm.name()));
+        }
+        // xxh64 digests are often printed without leading zeros
+        Ok(Self { algorithm, digest: format!("{:0>width$}", digest, width = length) })
+    }
+
+    /// Reads the `checksums` field: a JSON object of file name to checksum.
+    pub fn parse_manifest(json: &str) -> Result<HashMap<String, Checksum>, String> {
+        let entries: HashMap<String, String> = serde_json::from_str(json)
+            .map_err(|e| format!("checksums must be a JSON object of file name to checksum: {}", e))?;
+        entries
+            .into_iter()
+            .map(|(file, value)| Ok((file.trim_start_matches("./").to_string(), Checksum::parse(&value)?)))
+            .collect()
+    }
+}
+
+/// Hashes a file in chunks with `algorithm`, returning the lowercase hex digest.
+pub fn hash_file(path: &Path, algorithm: Algorithm) -> std::io::Result<String> {
+    let mut file = std::fs::File::open(path)?;
+    let mut buffer = vec![0u8; 64 * 1024];
+    let (mut xxh, mut sha) = (Xxh64::new(0), Sha256::new());
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        match algorithm {
+            Algorithm::Xxh64 => xxh.update(&buffer[..read]),
+            Algorithm::Sha256 => sha.update(&buffer[..read]),
+        }
+    }
+    Ok(match algorithm {
+        Algorithm::Xxh64 => format!("{:016x}", xxh.digest()),
+        Algorithm::Sha256 => hex::encode(sha.finalize()),
+    })
+}
+
+/// Checks every expected checksum against the uploaded files in `dir`. Files named in
+/// `expected` but absent from `uploaded` are reported with no `actual` digest.
+pub fn verify(dir: &Path, expected: &HashMap<String, Checksum>, uploaded: &[String]) -> Vec<ChecksumMismatch> {
+    let mut files: Vec<(&String, &Checksum)> = expected.iter().collect();
+    files.sort_by_key(|(file, _)| file.as_str());
+    files
+        .into_iter()
+        .filter_map(|(file, checksum)| {
+            let actual = if uploaded.contains(file) { hash_file(&dir.join(file), checksum.algorithm).ok() } else { None };
+            (actual.as_ref() != Some(&checksum.digest)).then(|| ChecksumMismatch {
+                file: file.clone(),
+                algorithm: checksum.algorithm.name().to_string(),
+                expected: checksum.digest.clone(),
+                actual,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_checksums() {
+        assert_eq!(Checksum::parse("XXH64:ABC").unwrap(), Checksum { algorithm: Algorithm::Xxh64, digest: "0000000000000abc".into() });
+        assert!(Checksum::parse("md5=abc").is_err());
+        assert!(Checksum::parse("sha256=xyz").is_err());
+        assert!(Checksum::parse("abc").is_err());
+        let manifest = Checksum::parse_manifest(r#"{"./fig.png": "sha256=00ff"}"#).unwrap();
+        assert_eq!(manifest["fig.png"].algorithm, Algorithm::Sha256);
+        assert!(Checksum::parse_manifest("[1]").is_err());
+    }
+
+    #[test]
+    fn test_verify_files() {
+        let dir = tempfile::TempDir::new().unwrap();
+        std::fs::write(dir.path().join("main.tex"), b"hello").unwrap();
+        std::fs::write(dir.path().join("fig.png"), b"png").unwrap();
+        let sha = crate::receipt::sha256_hex(b"hello");
+        let xxh = format!("{:016x}", xxhash_rust::xxh64::xxh64(b"corrupted", 0));
+        let expected = HashMap::from([
+            ("main.tex".to_string(), Checksum::parse(&format!("sha256={}", sha)).unwrap()),
+            ("fig.png".to_string(), Checksum::parse(&format!("xxh64={}", xxh)).unwrap()),
+            ("missing.bib".to_string(), Checksum::parse("xxh64=1").unwrap()),
+        ]);
+        let uploaded = vec!["main.tex".to_string(), "fig.png".to_string()];
+
+        let mismatches = verify(dir.path(), &expected, &uploaded);
+        assert_eq!(mismatches.iter().map(|m| m.file.as_str()).collect::<Vec<_>>(), ["fig.png", "missing.bib"]);
+        assert_eq!(mismatches[0].actual.as_deref(), Some(format!("{:016x}", xxhash_rust::xxh64::xxh64(b"png", 0)).as_str()));
+        assert_eq!(mismatches[1].actual, None);
+    }
+}
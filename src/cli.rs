@@ -0,0 +1,224 @@
+//! Client-mode subcommands: compile, watch, validate and render-math against a running
+//! server (`--server` / `TACHYON_SERVER`) or the embedded engine, so local builds and CI
+//! run the same pipeline.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use tachyon_tex_client::{Client, CompileOptions, Project};
+use tempfile::TempDir;
+use tracing::{error, info, warn};
+
+use crate::validator::Validator;
+use crate::compiler::Compiler;
+use crate::sandbox::Sandbox;
+
+/// How often `--watch` polls the project for changes.
+const WATCH_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Where compiles run: on a remote server or in-process.
+pub enum Engine {
+    Remote(Client),
+    Local { config: tectonic::config::PersistentConfig, format_cache_path: PathBuf },
+}
+
+impl Engine {
+    pub fn new(server: Option<String>, config: tectonic::config::PersistentConfig, format_cache_path: PathBuf) -> Self {
+        match server {
+            Some(url) => Engine::Remote(Client::new(url)),
+            None => Engine::Local { config, format_cache_path },
+        }
+    }
+
+    /// Compiles `main` with every file in its directory except `output`, returning the PDF.
+    pub async fn compile(&self, main: &Path, output: &Path, force: bool) -> Result<Vec<u8>, String> {
+        let root = project_root(main);
+        let main_name = main.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+        let files = project_files(&root, output);
+        match self {
+            Engine::Remote(client) => {
+                let mut project = Project::new();
+                for (name, path) in &files {
+                    project = project.file(name.clone(), std::fs::read(path).map_err(|e| format!("Failed to read {}: {}", name, e))?);
+                }
+                let project = project.main(main_name);
+                let options = CompileOptions { force, ..Default::default() };
+                let output = client.compile_project(&project, &options).await.map_err(|e| match e {
+                    tachyon_tex_client::Error::Compile { error, logs } => format!("{}\n\n{}", error, logs),
+                    other => other.to_string(),
+                })?;
+                info!("✅ Compiled on server in {} ms (cache: {})",
+                    output.meta.compile_time_ms.unwrap_or(0), output.meta.cache.as_deref().unwrap_or("-"));
+                for warning in &output.meta.warnings {
+                    warn!("{}:{} {}", warning.file.as_deref().unwrap_or("?"), warning.line.unwrap_or(0), warning.message);
+                }
+                Ok(output.pdf.to_vec())
+            }
+            Engine::Local { config, format_cache_path } => {
+                // Compile a copy: self-healing may rewrite the main file
+                let workspace = TempDir::new().map_err(|e| e.to_string())?;
+                for (name, path) in &files {
+                    let target = workspace.path().join(name);
+                    if let Some(parent) = target.parent() {
+                        std::fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+                    }
+                    std::fs::copy(path, target).map_err(|e| format!("Failed to copy {}: {}", name, e))?;
+                }
+                let (result, logs) = Compiler::compile_file(&workspace.path().join(&main_name), workspace.path(), format_cache_path, config, None);
+                result.map_err(|e| format!("{}\n\n{}", e, logs))
+            }
+        }
+    }
+
+    /// Runs the static checks over the `.tex` and `.bib` files next to `main`, returning
+    /// errors first, then warnings.
+    pub async fn validate(&self, main: &Path) -> Result<Vec<String>, String> {
+        let root = project_root(main);
+        let main_name = main.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+        let mut project = Project::new().main(main_name.clone());
+        for (name, path) in project_files(&root, Path::new("")) {
+            if name.ends_with(".tex") || name.ends_with(".bib") {
+                project = project.file(name, std::fs::read(&path).map_err(|e| e.to_string())?);
+            }
+        }
+        let messages = match self {
+            Engine::Remote(client) => {
+                let result = client.validate(&project).await.map_err(|e| e.to_string())?;
+                result.errors.into_iter().chain(result.warnings)
+                    .map(|m| format!("{}:{} {}", m.file, m.line, m.message))
+                    .collect()
+            }
+            Engine::Local { .. } => {
+                let result = Validator::validate(&project.sources(), Some(&main_name));
+                result.errors.into_iter().chain(result.warnings)
+                    .map(|m| format!("{}:{} {}", m.file, m.line, m.message))
+                    .collect()
+            }
+        };
+        Ok(messages)
+    }
+}
+
+/// Compiles `main` to `output`, then with `watch` keeps recompiling whenever a file in
+/// the project changes.
+pub async fn compile(engine: &Engine, main: &Path, output: Option<PathBuf>, force: bool, watch: bool) -> Result<(), String> {
+    let output = output.unwrap_or_else(|| main.with_extension("pdf"));
+    let root = project_root(main);
+    let mut last_seen = snapshot(&root, &output);
+    loop {
+        match engine.compile(main, &output, force).await {
+            Ok(pdf) => {
+                std::fs::write(&output, &pdf).map_err(|e| format!("Failed to write {}: {}", output.display(), e))?;
+                info!("📄 Wrote {} ({} bytes)", output.display(), pdf.len());
+            }
+            Err(e) if watch => error!("❌ Compilation failed: {}", e),
+            Err(e) => return Err(e),
+        }
+        if !watch {
+            return Ok(());
+        }
+
+        info!("👀 Watching {} for changes...", root.display());
+        loop {
+            tokio::time::sleep(WATCH_INTERVAL).await;
+            let current = snapshot(&root, &output);
+            if current != last_seen {
+                last_seen = current;
+                break;
+            }
+        }
+    }
+}
+
+/// Wraps a math expression in a standalone document.
+pub fn math_document(expression: &str, inline: bool) -> String {
+    let body = if inline { format!("${}$", expression) } else { format!("$\\displaystyle {}$", expression) };
+    format!(
+        "\\documentclass[border=2pt]{{standalone}}\n\\usepackage{{amsmath,amssymb}}\n\\begin{{document}}\n{}\n\\end{{document}}\n",
+        body
+    )
+}
+
+/// Renders a math expression to PDF, SVG or PNG.
+pub async fn render_math(engine: &Engine, expression: &str, inline: bool, format: &str, output: &Path) -> Result<(), String> {
+    if !matches!(format, "pdf" | "svg" | "png") {
+        return Err(format!("Unknown format '{}' (expected pdf, svg or png)", format));
+    }
+    let workspace = TempDir::new().map_err(|e| e.to_string())?;
+    let main = workspace.path().join("main.tex");
+    std::fs::write(&main, math_document(expression, inline)).map_err(|e| e.to_string())?;
+    let pdf = engine.compile(&main, output, false).await?;
+    let bytes = if format == "pdf" {
+        pdf
+    } else {
+        let sandbox = Sandbox::from_settings(&crate::settings::Settings::from_env());
+        Compiler::convert_pdf(&pdf, format, workspace.path(), &sandbox)?
+    };
+    std::fs::write(output, &bytes).map_err(|e| format!("Failed to write {}: {}", output.display(), e))?;
+    info!("🧮 Wrote {} ({} bytes)", output.display(), bytes.len());
+    Ok(())
+}
+
+fn project_root(main: &Path) -> PathBuf {
+    match main.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+        _ => PathBuf::from("."),
+    }
+}
+
+/// Project files as (relative name, path), skipping hidden entries and `skip`.
+fn project_files(root: &Path, skip: &Path) -> Vec<(String, PathBuf)> {
+    let skip = skip.canonicalize().ok();
+    let mut files = Vec::new();
+    let mut pending = vec![root.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        let Ok(entries) = std::fs::read_dir(&dir) else { continue };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if entry.file_name().to_string_lossy().starts_with('.') {
+                continue;
+            }
+            if path.is_dir() {
+                pending.push(path);
+            } else if skip.is_none() || path.canonicalize().ok() != skip {
+                let name = path.strip_prefix(root).unwrap_or(&path).to_string_lossy().replace('\\', "/");
+                files.push((name, path));
+            }
+        }
+    }
+    files
+}
+
+/// Modification times of the project files, used by `--watch` to detect changes.
+fn snapshot(root: &Path, output: &Path) -> HashMap<PathBuf, SystemTime> {
+    project_files(root, output).into_iter()
+        .filter_map(|(_, path)| std::fs::metadata(&path).and_then(|m| m.modified()).ok().map(|modified| (path, modified)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_math_document() {
+        let doc = math_document("e^{i\\pi} + 1 = 0", false);
+        assert!(doc.contains("{standalone}"));
+        assert!(doc.contains("$\\displaystyle e^{i\\pi} + 1 = 0$"));
+        assert!(math_document("x", true).contains("\n$x$\n"));
+    }
+
+    #[test]
+    fn test_snapshot_detects_changes() {
+        let dir = TempDir::new().unwrap();
+        let output = dir.path().join("main.pdf");
+        std::fs::write(dir.path().join("main.tex"), "a").unwrap();
+        std::fs::write(&output, "pdf").unwrap();
+        let before = snapshot(dir.path(), &output);
+        assert_eq!(before.len(), 1);
+
+        std::fs::write(dir.path().join("chapter.tex"), "b").unwrap();
+        assert_ne!(snapshot(dir.path(), &output), before);
+        assert_eq!(project_root(Path::new("main.tex")), PathBuf::from("."));
+    }
+}
@@ -1,19 +1,30 @@
-use std::path::Path;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
 use std::fs;
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
 use tectonic::driver::{ProcessingSessionBuilder, OutputFormat, PassSetting};
+use tectonic::io::{IoProvider, OpenResult};
 use tectonic::status::{StatusBackend, MessageKind};
+use tectonic::unstable_opts::UnstableOptions;
 
 pub struct CapturingStatusBackend {
     logs: Vec<String>,
+    /// Width TeX logs dumped on errors are rewrapped at; see [`rewrap_log`]
+    max_print_line: Option<usize>,
+    /// TeX log of a compile that typeset past errors, which tectonic does not dump when
+    /// a PDF comes out; appended after everything else
+    error_log: Option<String>,
 }
 
 impl CapturingStatusBackend {
     pub fn new() -> Self {
-        Self { logs: Vec::new() }
+        Self { logs: Vec::new(), max_print_line: None, error_log: None }
     }
     
     pub fn get_logs(&self) -> String {
-        self.logs.join("\n")
+        self.logs.iter().chain(&self.error_log).map(String::as_str).collect::<Vec<_>>().join("\n")
     }
 }
 
@@ -32,11 +43,205 @@ impl StatusBackend for CapturingStatusBackend {
 
     fn dump_error_logs(&mut self, output: &[u8]) {
         if let Ok(s) = std::str::from_utf8(output) {
-            self.logs.push(s.to_string());
+            self.logs.push(match self.max_print_line {
+                Some(width) => rewrap_log(s, width),
+                None => s.to_string(),
+            });
         }
     }
 }
 
+/// Interaction modes accepted by [`TexOptions::parse`]. Tectonic has no terminal to
+/// interact on, so they only differ in whether typesetting stops at the first error.
+pub const INTERACTION_MODES: &[&str] = &["batchmode", "nonstopmode", "scrollmode", "errorstopmode"];
+
+/// Column Tectonic wraps TeX's log at; it reads no texmf.cnf, so this cannot be changed in
+/// the engine itself.
+const ENGINE_MAX_PRINT_LINE: usize = 79;
+
+/// Engine settings a request may change, as `latex` takes them on its command line.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct TexOptions {
+    /// Keep typesetting past errors, so the log reports all of them (`batchmode`,
+    /// `nonstopmode`, `scrollmode`), instead of stopping at the first (`errorstopmode`)
+    #[serde(default)]
+    pub continue_on_errors: bool,
+    /// Width log lines are wrapped at instead of 79 columns (0: never wrapped)
+    pub max_print_line: Option<usize>,
+    /// `\jobname`, and so the base name of the PDF and log
+    pub jobname: Option<String>,
+    /// Self-healing rules a failed compile may be retried with (`None`: all of them)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub healer_rules: Option<Vec<String>>,
+}
+
+impl TexOptions {
+    pub fn parse(interaction: Option<&str>, max_print_line: Option<usize>, jobname: Option<&str>) -> Result<Self, String> {
+        let continue_on_errors = match interaction {
+            None | Some("errorstopmode") => false,
+            Some(mode) if INTERACTION_MODES.contains(&mode) => true,
+            Some(mode) => return Err(format!("Unknown interaction mode '{}'; use one of {}", mode, INTERACTION_MODES.join(", "))),
+        };
+        if let Some(width) = max_print_line.filter(|width| *width != 0 && *width < ENGINE_MAX_PRINT_LINE) {
+            return Err(format!("max_print_line must be 0 (no wrapping) or at least {}, not {}", ENGINE_MAX_PRINT_LINE, width));
+        }
+        if let Some(name) = jobname {
+            if name.is_empty() || name.len() > 64 || name.starts_with(['.', '-']) || !name.chars().all(|c| c.is_ascii_alphanumeric() || "._-".contains(c)) {
+                return Err(format!("Invalid jobname '{}': use at most 64 letters, digits, '.', '_' and '-'", name));
+            }
+        }
+        Ok(Self {
+            continue_on_errors,
+            max_print_line: max_print_line.filter(|width| *width != ENGINE_MAX_PRINT_LINE),
+            jobname: jobname.map(str::to_string),
+            healer_rules: None,
+        })
+    }
+
+    /// Limits self-healing to the comma-separated rules in `heal` (`all`, `none` or rule
+    /// names; unset: all).
+    pub fn with_healer_rules(mut self, heal: Option<&str>) -> Result<Self, String> {
+        self.healer_rules = match heal {
+            Some(list) => crate::healer::parse_rules(list)?,
+            None => None,
+        };
+        Ok(self)
+    }
+
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Base name of the PDF and log compiling `main_tex_path` produces.
+    pub fn output_stem(&self, main_tex_path: &Path) -> Option<String> {
+        match &self.jobname {
+            Some(jobname) => Some(jobname.clone()),
+            None => main_tex_path.file_stem()?.to_str().map(str::to_string),
+        }
+    }
+
+    /// Name TeX is given for `main_tex_path`, which its log and error reports use.
+    /// TeX takes \jobname from it.
+    pub fn input_name(&self, main_tex_path: &Path) -> String {
+        match &self.jobname {
+            Some(jobname) => format!("{}.tex", jobname),
+            None => main_tex_path.file_name().unwrap_or_default().to_string_lossy().to_string(),
+        }
+    }
+}
+
+/// Undoes the engine's wrapping of long log lines, which splits error messages, and wraps
+/// them at `width` columns instead (0: not at all). A line of exactly 79 characters is
+/// taken as continued on the next.
+pub fn rewrap_log(log: &str, width: usize) -> String {
+    let mut lines = Vec::new();
+    let mut current = String::new();
+    for line in log.lines() {
+        current.push_str(line);
+        if line.chars().count() == ENGINE_MAX_PRINT_LINE {
+            continue;
+        }
+        lines.push(std::mem::take(&mut current));
+    }
+    if !current.is_empty() {
+        lines.push(current);
+    }
+    if width == 0 {
+        return lines.join("\n");
+    }
+    lines.iter()
+        .flat_map(|line| {
+            let chars: Vec<char> = line.chars().collect();
+            if chars.is_empty() {
+                return vec![String::new()];
+            }
+            chars.chunks(width).map(|chunk| chunk.iter().collect()).collect::<Vec<String>>()
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Measures the resources consumed by one compile.
+/// Tectonic runs in-process on the calling thread, so CPU time is read per thread
+/// (`/proc/thread-self/schedstat`). Memory is not reported: the only peak RSS
+/// available in-process (`VmHWM`) covers the whole server, not this compile.
+pub struct ResourceMeter {
+    start: Instant,
+    cpu_start_ns: Option<u64>,
+}
+
+impl ResourceMeter {
+    pub fn start() -> Self {
+        Self { start: Instant::now(), cpu_start_ns: Self::thread_cpu_ns() }
+    }
+
+    /// Must be called on the same thread as `start()`.
+    pub fn finish(self, output_bytes: usize) -> crate::models::ResourceUsage {
+        let cpu_time_ms = match (self.cpu_start_ns, Self::thread_cpu_ns()) {
+            (Some(before), Some(after)) => Some(after.saturating_sub(before) / 1_000_000),
+            _ => None,
+        };
+        crate::models::ResourceUsage {
+            wall_time_ms: self.start.elapsed().as_millis() as u64,
+            cpu_time_ms,
+            output_bytes,
+        }
+    }
+
+    fn thread_cpu_ns() -> Option<u64> {
+        fs::read_to_string("/proc/thread-self/schedstat").ok()?
+            .split_whitespace().next()?
+            .parse().ok()
+    }
+}
+
+/// Error returned for compiles abandoned because nobody is waiting for them anymore.
+pub const CANCELLED: &str = "Compilation cancelled";
+
+/// Extensions of files TeX looks up by name (`\documentclass`, `\usepackage`,
+/// `\bibliographystyle`, ...) rather than by path.
+pub(crate) const SUPPORT_FILE_EXTENSIONS: &[&str] = &["cls", "sty", "bst", "clo", "cfg", "def", "fd", "bbx", "cbx", "lbx", "ldf"];
+
+/// How deep [`input_search_paths`] descends below the workspace root.
+const MAX_SEARCH_DEPTH: usize = 8;
+
+/// Workspace subdirectories holding class, style or bibliography style files, like a
+/// `TEXINPUTS=./<root>//` search, so projects that keep them in folders compile unmodified.
+/// The root itself is always searched and is not included.
+pub fn input_search_paths(root: &Path) -> Vec<PathBuf> {
+    fn walk(dir: &Path, depth: usize, paths: &mut Vec<PathBuf>, is_root: bool) {
+        let Ok(entries) = fs::read_dir(dir) else { return };
+        let mut has_support_files = false;
+        let mut subdirs = Vec::new();
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if entry.file_name().to_string_lossy().starts_with('.') {
+                continue;
+            }
+            match entry.file_type() {
+                Ok(t) if t.is_dir() => subdirs.push(path),
+                Ok(t) if t.is_file() => {
+                    has_support_files |= path.extension().and_then(|e| e.to_str()).is_some_and(|e| SUPPORT_FILE_EXTENSIONS.contains(&e));
+                }
+                _ => {}
+            }
+        }
+        if has_support_files && !is_root {
+            paths.push(dir.to_path_buf());
+        }
+        if depth < MAX_SEARCH_DEPTH {
+            subdirs.sort();
+            for subdir in subdirs {
+                walk(&subdir, depth + 1, paths, false);
+            }
+        }
+    }
+
+    let mut paths = Vec::new();
+    walk(root, 0, &mut paths, true);
+    paths
+}
+
 pub struct Compiler;
 
 impl Compiler {
@@ -47,26 +252,59 @@ impl Compiler {
     /// * `output_dir` - Directory where output files will be written
     /// * `format_cache_path` - Path to the tectonic format cache
     /// * `config_ptr` - Tectonic persistent config
+    /// * `bundle_url` - Bundle to compile with; `None` for the config's default bundle
     pub fn compile_file(
         main_tex_path: &Path,
         output_dir: &Path,
         format_cache_path: &Path,
         config: &tectonic::config::PersistentConfig,
+        bundle_url: Option<&str>,
     ) -> (Result<Vec<u8>, String>, String) {
-        let (mut res, mut logs) = Self::internal_compile(main_tex_path, output_dir, format_cache_path, config);
+        Self::compile_file_cancellable(main_tex_path, output_dir, format_cache_path, config, bundle_url, &TexOptions::default(), &AtomicBool::new(false))
+    }
 
-        if res.is_err() {
+    /// Like [`Compiler::compile_file`], with the request's `options`, but gives up between
+    /// tectonic runs once `cancelled` is set. A run already in progress cannot be interrupted.
+    pub fn compile_file_cancellable(
+        main_tex_path: &Path,
+        output_dir: &Path,
+        format_cache_path: &Path,
+        config: &tectonic::config::PersistentConfig,
+        bundle_url: Option<&str>,
+        options: &TexOptions,
+        cancelled: &AtomicBool,
+    ) -> (Result<Vec<u8>, String>, String) {
+        if cancelled.load(Ordering::Relaxed) {
+            return (Err(CANCELLED.to_string()), String::new());
+        }
+        let expansion = match crate::render::TableRenderer::preprocess(main_tex_path) {
+            Ok(expansion) => expansion,
+            Err(e) => return (Err(e), String::new()),
+        };
+
+        let (mut res, mut logs) = Self::internal_compile(main_tex_path, output_dir, format_cache_path, config, bundle_url, options);
+        if let Some((_, source_map)) = &expansion {
+            // Everything logged numbers the main file as expanded
+            logs.insert_str(0, &source_map.log_line(&options.input_name(main_tex_path)));
+        }
+
+        if res.is_err() && !cancelled.load(Ordering::Relaxed) {
             if let Ok(content) = fs::read_to_string(main_tex_path) {
                 // Moonshot #1: Self-Healing Logic
-                if let Some(fixed_content) = crate::healer::SelfHealer::attempt_heal(&content, &logs) {
+                if let Some(healing) = crate::healer::SelfHealer::heal_with(&content, &logs, options.healer_rules.as_deref()) {
                     tracing::info!("🚑 Self-Healing triggered for {:?}", main_tex_path);
-                    let _ = fs::write(main_tex_path, fixed_content);
+                    let _ = fs::write(main_tex_path, &healing.content);
                     
                     logs.push_str("\n\n--- [Tachyon Self-Healing 🚑] ---\nErrors detected. Applying automated fixes and retrying...\n");
+                    logs.push_str(&crate::healer::SelfHealer::fixes_log_line(&healing.fixes));
+                    // Everything logged after this line numbers the main file as healed
+                    logs.push_str(&healing.source_map.log_line(&options.input_name(main_tex_path)));
                     
-                    let (retry_res, retry_logs) = Self::internal_compile(main_tex_path, output_dir, format_cache_path, config);
+                    let (retry_res, retry_logs) = Self::internal_compile(main_tex_path, output_dir, format_cache_path, config, bundle_url, options);
                     logs.push_str(&retry_logs);
                     res = retry_res;
+                    // Source snippets of the errors are read from the workspace
+                    let _ = fs::write(main_tex_path, &content);
                     
                     if res.is_ok() {
                         logs.push_str("\n[Self-Healing] ✅ FIXED! Compilation succeeded after auto-patching.\n");
@@ -74,25 +312,84 @@ impl Compiler {
                 }
             }
         }
+        if let Some((original, _)) = &expansion {
+            // The user's source is put back for error snippets and later builds
+            let _ = fs::write(main_tex_path, original);
+        }
         (res, logs)
     }
 
+    /// Converts the first page of a PDF to "svg" or "png" using poppler's `pdftocairo`,
+    /// run inside `sandbox` with `work_dir` as the only writable directory.
+    pub fn convert_pdf(pdf_data: &[u8], format: &str, work_dir: &Path, sandbox: &crate::sandbox::Sandbox) -> Result<Vec<u8>, String> {
+        let input = work_dir.join("convert-input.pdf");
+        fs::write(&input, pdf_data).map_err(|e| e.to_string())?;
+
+        let mut cmd = Command::new("pdftocairo");
+        sandbox.apply(&mut cmd, work_dir);
+        cmd.args(["-f", "1", "-l", "1"]);
+        let output = match format {
+            "svg" => {
+                let output = work_dir.join("convert-output.svg");
+                cmd.arg("-svg").arg(&input).arg(&output);
+                output
+            }
+            "png" => {
+                // -singlefile makes pdftocairo append ".png" to the output root itself
+                cmd.args(["-png", "-singlefile", "-r", "150"]).arg(&input).arg(work_dir.join("convert-output"));
+                work_dir.join("convert-output.png")
+            }
+            other => return Err(format!("Unsupported output format '{}'", other)),
+        };
+
+        let result = cmd.output().map_err(|e| format!("pdftocairo is not available: {}", e))?;
+        if !result.status.success() {
+            return Err(format!("pdftocairo failed: {}", String::from_utf8_lossy(&result.stderr)));
+        }
+        fs::read(&output).map_err(|e| e.to_string())
+    }
+
+    /// Which of `names` the bundle at `bundle_url` (`None`: the config's default) has.
+    pub fn bundle_files(config: &tectonic::config::PersistentConfig, bundle_url: Option<&str>, names: &[String]) -> Result<Vec<String>, String> {
+        let mut status = CapturingStatusBackend::new();
+        let mut bundle = match bundle_url {
+            Some(url) => config.make_cached_url_provider(url, false, None, &mut status),
+            None => config.default_bundle(false, &mut status),
+        }.map_err(|e| format!("Bundle error: {}", e))?;
+        Ok(names.iter()
+            .filter(|name| matches!(bundle.input_open_name(name, &mut status), OpenResult::Ok(_)))
+            .cloned()
+            .collect())
+    }
+
     fn internal_compile(
         main_tex_path: &Path,
         output_dir: &Path,
         format_cache_path: &Path,
         config: &tectonic::config::PersistentConfig,
+        bundle_url: Option<&str>,
+        options: &TexOptions,
     ) -> (Result<Vec<u8>, String>, String) {
-        let mut status = CapturingStatusBackend::new();
-        let bundle_res = config.default_bundle(false, &mut status);
+        let mut status = CapturingStatusBackend { max_print_line: options.max_print_line, ..CapturingStatusBackend::new() };
+        let bundle_res = match bundle_url {
+            Some(url) => config.make_cached_url_provider(url, false, None, &mut status),
+            None => config.default_bundle(false, &mut status),
+        };
         
         match bundle_res {
             Ok(bundle) => {
                 let mut sb = ProcessingSessionBuilder::default();
-                let tex_input_name = main_tex_path.file_name()
-                    .unwrap_or_default()
-                    .to_string_lossy();
-                    
+                let mut search_paths = input_search_paths(output_dir);
+                // The tenant's registry comes last, so a project's own copy of a package wins
+                let registry = output_dir.join(crate::registry::REGISTRY_DIR);
+                if registry.is_dir() {
+                    search_paths.push(registry);
+                }
+                if !search_paths.is_empty() {
+                    tracing::info!("📚 Searching {} extra input directories", search_paths.len());
+                }
+                let tex_input_name = options.input_name(main_tex_path);
+
                 sb.bundle(bundle)
                     .primary_input_path(main_tex_path)
                     .tex_input_name(&tex_input_name)
@@ -100,21 +397,35 @@ impl Compiler {
                     .format_cache_path(format_cache_path)
                     .output_dir(output_dir)
                     .print_stdout(false)
+                    .keep_logs(true)
                     .output_format(OutputFormat::Pdf)
-                    .pass(PassSetting::Default);
+                    .pass(PassSetting::Default)
+                    .unstables(UnstableOptions { extra_search_paths: search_paths, continue_on_errors: options.continue_on_errors, ..Default::default() });
 
+                let (mut packages, mut error_log) = (Vec::new(), None);
                 let res = (|| -> Result<Vec<u8>, String> {
                     let mut sess = sb.create(&mut status).map_err(|e| e.to_string())?;
                     sess.run(&mut status).map_err(|e| e.to_string())?;
                     
-                    let pdf_name = main_tex_path.file_stem()
-                        .ok_or("Invalid filename")?
-                        .to_str()
-                        .ok_or("Invalid UTF-8 filename")?;
-                        
+                    let pdf_name = options.output_stem(main_tex_path).ok_or("Invalid filename")?;
                     let pdf_path = output_dir.join(format!("{}.pdf", pdf_name));
-                    fs::read(&pdf_path).map_err(|e| e.to_string())
+                    let pdf = fs::read(&pdf_path).map_err(|e| e.to_string())?;
+
+                    // The TeX log names the release of every class and package loaded
+                    if let Ok(tex_log) = fs::read_to_string(output_dir.join(format!("{}.log", pdf_name))) {
+                        packages = tex_log.lines()
+                            .filter(|line| line.starts_with("Package: ") || line.starts_with("Document Class: "))
+                            .map(str::to_string)
+                            .collect();
+                        // Errors typeset past are only in the log, which is not dumped on success
+                        if options.continue_on_errors && tex_log.lines().any(|line| line.starts_with("! ")) {
+                            error_log = Some(rewrap_log(&tex_log, options.max_print_line.unwrap_or(ENGINE_MAX_PRINT_LINE)));
+                        }
+                    }
+                    Ok(pdf)
                 })();
+                status.logs.extend(packages);
+                status.error_log = error_log;
                 
                 (res, status.get_logs())
             },
@@ -122,3 +433,58 @@ impl Compiler {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resource_meter() {
+        let usage = ResourceMeter::start().finish(1234);
+        assert_eq!(usage.output_bytes, 1234);
+        assert!(usage.wall_time_ms < 1000);
+        if cfg!(target_os = "linux") {
+            assert!(usage.cpu_time_ms.is_some());
+        }
+    }
+
+    #[test]
+    fn test_input_search_paths() {
+        let root = tempfile::tempdir().unwrap();
+        for file in ["custom.cls", "styles/local.sty", "styles/bib/ieee.bst", "figures/plot.pdf", ".git/hooks/x.sty"] {
+            let path = root.path().join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "").unwrap();
+        }
+        assert_eq!(input_search_paths(root.path()), vec![root.path().join("styles"), root.path().join("styles/bib")]);
+    }
+
+    #[test]
+    fn test_tex_options() {
+        assert!(TexOptions::parse(None, None, None).unwrap().is_default());
+        assert!(TexOptions::parse(Some("errorstopmode"), Some(79), None).unwrap().is_default());
+        let options = TexOptions::parse(Some("nonstopmode"), Some(0), Some("report-2026")).unwrap();
+        assert!(options.continue_on_errors);
+        assert_eq!(options.output_stem(Path::new("/w/main.tex")).as_deref(), Some("report-2026"));
+        assert_eq!(TexOptions::default().output_stem(Path::new("/w/main.tex")).as_deref(), Some("main"));
+        let options = TexOptions::default().with_healer_rules(Some("none")).unwrap();
+        assert_eq!(options.healer_rules, Some(vec![]));
+        assert!(!options.is_default());
+        assert!(TexOptions::default().with_healer_rules(Some("all")).unwrap().is_default());
+        assert!(TexOptions::parse(Some("quiet"), None, None).is_err());
+        assert!(TexOptions::parse(None, Some(40), None).is_err());
+        for jobname in ["", "../x", "-x", "a b", "x/y"] {
+            assert!(TexOptions::parse(None, None, Some(jobname)).is_err(), "{}", jobname);
+        }
+    }
+
+    #[test]
+    fn test_rewrap_log() {
+        let message = format!("! Undefined control sequence {}", "x".repeat(100));
+        let chars: Vec<char> = message.chars().collect();
+        let wrapped = format!("{}\n{}\nl.9 \\foo", chars[..79].iter().collect::<String>(), chars[79..].iter().collect::<String>());
+        assert_eq!(rewrap_log(&wrapped, 0), format!("{}\nl.9 \\foo", message));
+        assert_eq!(rewrap_log(&wrapped, 200), rewrap_log(&wrapped, 0));
+        assert_eq!(rewrap_log(&wrapped, 79), wrapped);
+    }
+}
@@ -0,0 +1,115 @@
+//! Payload compression for the live-preview WebSocket protocol. Clients opt in by offering
+//! the `tachyon.zstd` or `tachyon.deflate` subprotocol; large fields (returned PDFs) are
+//! then compressed before base64 encoding. Clients may send compressed file contents as
+//! `{"base64": ..., "encoding": "zstd" | "deflate"}` regardless of negotiation.
+
+use std::io::{Read, Write};
+
+/// Subprotocols offered to WebSocket clients, in server preference order.
+pub const WS_PROTOCOLS: [&str; 2] = ["tachyon.zstd", "tachyon.deflate"];
+
+/// Payloads smaller than this are sent as-is; compressing them saves nothing.
+pub const MIN_COMPRESS_BYTES: usize = 1024;
+
+/// Upper bound for a decompressed upload, guarding against compression bombs.
+const MAX_DECOMPRESSED_BYTES: u64 = 128 * 1024 * 1024;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Encoding {
+    Zstd,
+    Deflate,
+}
+
+impl Encoding {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "zstd" => Some(Encoding::Zstd),
+            "deflate" => Some(Encoding::Deflate),
+            _ => None,
+        }
+    }
+
+    /// Maps a negotiated subprotocol (`tachyon.zstd`, ...) to its encoding.
+    pub fn from_protocol(protocol: &str) -> Option<Self> {
+        protocol.strip_prefix("tachyon.").and_then(Self::parse)
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Encoding::Zstd => "zstd",
+            Encoding::Deflate => "deflate",
+        }
+    }
+
+    pub fn compress(&self, data: &[u8]) -> Result<Vec<u8>, String> {
+        match self {
+            Encoding::Zstd => zstd::bulk::compress(data, 3).map_err(|e| e.to_string()),
+            Encoding::Deflate => {
+                let mut encoder = flate2::write::DeflateEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(data).map_err(|e| e.to_string())?;
+                encoder.finish().map_err(|e| e.to_string())
+            }
+        }
+    }
+
+    pub fn decompress(&self, data: &[u8]) -> Result<Vec<u8>, String> {
+        let mut out = Vec::new();
+        let read = match self {
+            Encoding::Zstd => zstd::stream::read::Decoder::new(data)
+                .map_err(|e| e.to_string())?
+                .take(MAX_DECOMPRESSED_BYTES + 1)
+                .read_to_end(&mut out),
+            Encoding::Deflate => flate2::read::DeflateDecoder::new(data)
+                .take(MAX_DECOMPRESSED_BYTES + 1)
+                .read_to_end(&mut out),
+        };
+        read.map_err(|e| format!("Invalid {} data: {}", self.as_str(), e))?;
+        if out.len() as u64 > MAX_DECOMPRESSED_BYTES {
+            return Err(format!("Decompressed payload exceeds {} bytes", MAX_DECOMPRESSED_BYTES));
+        }
+        Ok(out)
+    }
+
+    /// Compresses `data` when it is large enough to be worth it and actually shrinks;
+    /// returns the bytes to send and the encoding applied, if any.
+    pub fn maybe_compress(encoding: Option<Self>, data: &[u8]) -> (Vec<u8>, Option<Self>) {
+        if let Some(encoding) = encoding.filter(|_| data.len() >= MIN_COMPRESS_BYTES) {
+            if let Ok(compressed) = encoding.compress(data) {
+                if compressed.len() < data.len() {
+                    return (compressed, Some(encoding));
+                }
+            }
+        }
+        (data.to_vec(), None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let data = b"%PDF-1.5 stream stream stream stream ".repeat(100);
+        for encoding in [Encoding::Zstd, Encoding::Deflate] {
+            let compressed = encoding.compress(&data).unwrap();
+            assert!(compressed.len() < data.len());
+            assert_eq!(encoding.decompress(&compressed).unwrap(), data);
+        }
+        assert!(Encoding::Zstd.decompress(b"not zstd").is_err());
+    }
+
+    #[test]
+    fn test_negotiation_and_threshold() {
+        assert_eq!(Encoding::from_protocol("tachyon.zstd"), Some(Encoding::Zstd));
+        assert_eq!(Encoding::from_protocol("tachyon.deflate"), Some(Encoding::Deflate));
+        assert_eq!(Encoding::from_protocol("zstd"), None);
+
+        let (small, applied) = Encoding::maybe_compress(Some(Encoding::Zstd), b"tiny");
+        assert_eq!((small.as_slice(), applied), (&b"tiny"[..], None));
+        let large = vec![0u8; 4096];
+        let (_, applied) = Encoding::maybe_compress(Some(Encoding::Deflate), &large);
+        assert_eq!(applied, Some(Encoding::Deflate));
+        assert_eq!(Encoding::maybe_compress(None, &large).1, None);
+    }
+}
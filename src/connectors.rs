@@ -0,0 +1,570 @@
+//! Data connectors: rows fetched each time a project compiles, from a JSON API, a PostgreSQL
+//! query or a Google Sheet, and written into its sources as `<name>.csv` for csvsimple,
+//! pgfplotstable or datatool. They run on `POST /projects/{id}/compile` and on `/schedules`
+//! runs. Credentials are written through `Storage` with the project's tenant, so storage
+//! encryption seals them under the tenant's key, and the API never returns them.
+
+use base64::{engine::general_purpose, Engine as _};
+use openssl::hash::MessageDigest;
+use openssl::pkey::PKey;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, AUTHORIZATION};
+use serde::{Deserialize, Serialize};
+use futures_util::TryStreamExt;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio_postgres::config::Host;
+use tracing::{info, warn};
+
+use crate::models::{ConnectorConfig, ConnectorInfo, ConnectorPreview};
+use crate::render::AuxFile;
+use crate::services::AppState;
+use crate::settings::Settings;
+use crate::storage::Storage;
+use crate::webhooks::Webhooks;
+use crate::util::unix_now;
+
+const PREFIX: &str = "connectors/";
+
+pub const KINDS: &[&str] = &["http", "postgres", "google_sheets"];
+
+/// Rows shown by a preview.
+const PREVIEW_ROWS: usize = 20;
+
+const SHEETS_API: &str = "https://sheets.googleapis.com/v4/spreadsheets/";
+const GOOGLE_TOKEN_URL: &str = "https://oauth2.googleapis.com/token";
+const SHEETS_SCOPE: &str = "https://www.googleapis.com/auth/spreadsheets.readonly";
+
+/// Where a connector reads from, with its credentials.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Source {
+    Http { url: String, headers: BTreeMap<String, String>, pointer: Option<String> },
+    Postgres { url: String, query: String },
+    GoogleSheets { spreadsheet_id: String, range: String, auth: SheetsAuth },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "snake_case")]
+enum SheetsAuth {
+    ApiKey(String),
+    ServiceAccount(ServiceAccount),
+}
+
+/// The fields of a Google service account key that are used.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct ServiceAccount {
+    client_email: String,
+    private_key: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredConnector {
+    name: String,
+    project_id: String,
+    tenant: Option<String>,
+    source: Source,
+    escape: bool,
+    updated_at: u64,
+}
+
+impl StoredConnector {
+    fn info(&self) -> ConnectorInfo {
+        let (kind, source) = match &self.source {
+            Source::Http { url, .. } => ("http", reqwest::Url::parse(url).map_or_else(|_| url.clone(), |mut url| {
+                url.set_query(None);
+                url.to_string()
+            })),
+            Source::Postgres { url, .. } => ("postgres", url.parse::<tokio_postgres::Config>().map(|config| database(&config)).unwrap_or_default()),
+            Source::GoogleSheets { spreadsheet_id, range, .. } => ("google_sheets", format!("{} {}", spreadsheet_id, range)),
+        };
+        ConnectorInfo {
+            name: self.name.clone(),
+            kind: kind.to_string(),
+            source,
+            file: file_name(&self.name),
+            escape: self.escape,
+            updated_at: self.updated_at,
+        }
+    }
+}
+
+/// `user@host:port/dbname` of a connection string, without the password.
+fn database(config: &tokio_postgres::Config) -> String {
+    let hosts: Vec<String> = config.get_hosts().iter().map(|host| match host {
+        Host::Tcp(host) => host.clone(),
+        #[cfg(unix)]
+        Host::Unix(_) => "socket".to_string(),
+    }).collect();
+    format!(
+        "{}@{}:{}/{}",
+        config.get_user().unwrap_or_default(),
+        hosts.join(","),
+        config.get_ports().first().copied().unwrap_or(5432),
+        config.get_dbname().unwrap_or_default(),
+    )
+}
+
+/// The rows a connector fetched.
+#[derive(Debug, Default, PartialEq)]
+pub struct Table {
+    pub columns: Vec<String>,
+    pub rows: Vec<Vec<String>>,
+}
+
+impl Table {
+    /// The table as CSV, with a header row. Values are escaped for LaTeX when `escape` is set.
+    pub fn to_csv(&self, escape: bool) -> String {
+        let mut out = String::new();
+        let mut line = |cells: &mut dyn Iterator<Item = String>| {
+            let cells: Vec<String> = cells.map(|cell| csv_field(&cell)).collect();
+            out.push_str(&cells.join(","));
+            out.push('\n');
+        };
+        line(&mut self.columns.iter().cloned());
+        for row in &self.rows {
+            line(&mut row.iter().map(|cell| if escape { crate::latex::escape(cell) } else { cell.clone() }));
+        }
+        out
+    }
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) || value.trim() != value {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn file_name(name: &str) -> String {
+    format!("{}.csv", name)
+}
+
+#[derive(Clone)]
+pub struct ConnectorStore {
+    storage: Arc<dyn Storage>,
+}
+
+impl ConnectorStore {
+    pub fn new(storage: Arc<dyn Storage>) -> Self {
+        Self { storage }
+    }
+
+    /// Adds or replaces a project's connector after checking its configuration.
+    pub async fn put(&self, project_id: &str, tenant: Option<&str>, name: &str, config: ConnectorConfig, settings: &Settings) -> Result<ConnectorInfo, String> {
+        if !is_valid_id(name) || name.len() > 64 {
+            return Err("Connector names may only hold letters, digits, '-' and '_' (at most 64)".to_string());
+        }
+        let stored = StoredConnector {
+            name: name.to_string(),
+            project_id: project_id.to_string(),
+            tenant: tenant.map(str::to_string),
+            source: parse(config.kind.as_str(), &config, settings)?,
+            escape: config.escape,
+            updated_at: unix_now(),
+        };
+        let data = serde_json::to_vec(&stored).map_err(|e| e.to_string())?;
+        self.storage.put(&key(project_id, name), data).await?;
+        Ok(stored.info())
+    }
+
+    /// The project's connectors, by name.
+    pub async fn list(&self, project_id: &str, tenant: Option<&str>) -> Vec<ConnectorInfo> {
+        self.all(project_id, tenant).await.iter().map(StoredConnector::info).collect()
+    }
+
+    /// Whether the project had a connector with this name.
+    pub async fn delete(&self, project_id: &str, name: &str, tenant: Option<&str>) -> bool {
+        self.load(project_id, name, tenant).await.is_some() && self.storage.delete(&key(project_id, name)).await.is_ok()
+    }
+
+    async fn load(&self, project_id: &str, name: &str, tenant: Option<&str>) -> Option<StoredConnector> {
+        if !is_valid_id(project_id) || !is_valid_id(name) {
+            return None;
+        }
+        let stored: StoredConnector = serde_json::from_slice(&self.storage.get(&key(project_id, name)).await.ok()??).ok()?;
+        (stored.tenant.as_deref() == tenant && stored.project_id == project_id).then_some(stored)
+    }
+
+    async fn all(&self, project_id: &str, tenant: Option<&str>) -> Vec<StoredConnector> {
+        if !is_valid_id(project_id) {
+            return Vec::new();
+        }
+        let keys = match self.storage.list(&format!("{}{}/", PREFIX, project_id)).await {
+            Ok(keys) => keys,
+            Err(e) => {
+                warn!("Failed to list the connectors of project {}: {}", project_id, e);
+                return Vec::new();
+            }
+        };
+        let mut connectors: Vec<StoredConnector> = Vec::with_capacity(keys.len());
+        for key in keys {
+            if let Some(stored) = self.storage.get(&key).await.ok().flatten().and_then(|data| serde_json::from_slice::<StoredConnector>(&data).ok()) {
+                if stored.tenant.as_deref() == tenant && stored.project_id == project_id {
+                    connectors.push(stored);
+                }
+            }
+        }
+        connectors.sort_by(|a, b| a.name.cmp(&b.name));
+        connectors
+    }
+}
+
+fn key(project_id: &str, name: &str) -> String {
+    format!("{}{}/{}", PREFIX, project_id, name)
+}
+
+fn is_valid_id(id: &str) -> bool {
+    !id.is_empty() && id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// Checks a connector's configuration against its kind and the outbound policies.
+fn parse(kind: &str, config: &ConnectorConfig, settings: &Settings) -> Result<Source, String> {
+    let required = |value: &Option<String>, field: &str| {
+        value.clone().filter(|v| !v.trim().is_empty()).ok_or_else(|| format!("{} connectors need {}", kind, field))
+    };
+    let source = match kind {
+        "http" => {
+            let url = required(&config.url, "url")?;
+            Webhooks::validate_url(&url, settings)?;
+            header_map(&config.headers)?;
+            if let Some(pointer) = config.pointer.as_deref().filter(|p| !p.is_empty() && !p.starts_with('/')) {
+                return Err(format!("pointer must be a JSON pointer starting with '/', not '{}'", pointer));
+            }
+            Source::Http { url, headers: config.headers.clone(), pointer: config.pointer.clone().filter(|p| !p.is_empty()) }
+        }
+        "postgres" => {
+            let url = required(&config.url, "url")?;
+            let query = required(&config.query, "query")?;
+            postgres_config(&url, settings)?;
+            Source::Postgres { url, query }
+        }
+        "google_sheets" => {
+            let spreadsheet_id = required(&config.spreadsheet_id, "spreadsheet_id")?;
+            let range = required(&config.range, "range")?;
+            let auth = match (&config.api_key, &config.service_account) {
+                (Some(key), None) => SheetsAuth::ApiKey(key.clone()),
+                (None, Some(key)) => {
+                    let account: ServiceAccount = serde_json::from_str(key).map_err(|e| format!("service_account is not a service account key: {}", e))?;
+                    PKey::private_key_from_pem(account.private_key.as_bytes()).map_err(|e| format!("The service account's private_key is unreadable: {}", e))?;
+                    SheetsAuth::ServiceAccount(account)
+                }
+                _ => return Err("google_sheets connectors need exactly one of api_key and service_account".to_string()),
+            };
+            Source::GoogleSheets { spreadsheet_id, range, auth }
+        }
+        _ => return Err(format!("Unknown connector type '{}'; expected one of: {}", kind, KINDS.join(", "))),
+    };
+    Ok(source)
+}
+
+fn header_map(headers: &BTreeMap<String, String>) -> Result<HeaderMap, String> {
+    let mut map = HeaderMap::new();
+    for (name, value) in headers {
+        let name = HeaderName::from_bytes(name.as_bytes()).map_err(|_| format!("Invalid header name '{}'", name))?;
+        let value = HeaderValue::from_str(value).map_err(|_| format!("Invalid value for header {}", name))?;
+        map.insert(name, value);
+    }
+    Ok(map)
+}
+
+/// Parses a connection string, accepting only TCP hosts on CONNECTOR_DATABASE_HOSTS.
+fn postgres_config(url: &str, settings: &Settings) -> Result<tokio_postgres::Config, String> {
+    if settings.connector_database_hosts.is_empty() {
+        return Err("PostgreSQL connectors are disabled; the operator can allow database hosts with CONNECTOR_DATABASE_HOSTS".to_string());
+    }
+    let mut config: tokio_postgres::Config = url.parse().map_err(|e| format!("Invalid connection string: {}", e))?;
+    if config.get_hosts().is_empty() || !config.get_hostaddrs().is_empty() {
+        return Err("The connection string must name its host, without hostaddr".to_string());
+    }
+    for host in config.get_hosts() {
+        match host {
+            Host::Tcp(host) if Webhooks::host_allowed(host, &settings.connector_database_hosts) => {}
+            Host::Tcp(host) => return Err(format!("Database host '{}' is not in CONNECTOR_DATABASE_HOSTS", host)),
+            #[cfg(unix)]
+            Host::Unix(_) => return Err("Unix socket hosts are not allowed".to_string()),
+        }
+    }
+    config.connect_timeout(Duration::from_secs(settings.connector_timeout_secs));
+    Ok(config)
+}
+
+/// Fetches the rows of every connector of a project and adds them to `files` as CSV,
+/// replacing files of the same name. Returns how many connectors ran.
+pub async fn add_files(state: &AppState, project_id: &str, tenant: Option<&str>, files: &mut Vec<AuxFile>) -> Result<usize, String> {
+    let connectors = state.connectors.all(project_id, tenant).await;
+    for connector in &connectors {
+        let table = fetch(&connector.source, &state.settings).await.map_err(|e| format!("Data connector {} failed: {}", connector.name, e))?;
+        info!("🔌 Connector {} of project {} fetched {} row(s)", connector.name, project_id, table.rows.len());
+        let name = file_name(&connector.name);
+        files.retain(|(file, _)| *file != name);
+        files.push((name, table.to_csv(connector.escape).into_bytes()));
+    }
+    Ok(connectors.len())
+}
+
+/// Runs one connector without compiling. `None` if the project has no such connector.
+pub async fn preview(state: &AppState, project_id: &str, name: &str, tenant: Option<&str>) -> Option<Result<ConnectorPreview, String>> {
+    let connector = state.connectors.load(project_id, name, tenant).await?;
+    Some(fetch(&connector.source, &state.settings).await.map(|table| ConnectorPreview {
+        total_rows: table.rows.len(),
+        rows: table.rows.into_iter().take(PREVIEW_ROWS)
+            .map(|row| row.into_iter().map(|cell| if connector.escape { crate::latex::escape(&cell) } else { cell }).collect())
+            .collect(),
+        columns: table.columns,
+    }))
+}
+
+async fn fetch(source: &Source, settings: &Settings) -> Result<Table, String> {
+    let timeout = Duration::from_secs(settings.connector_timeout_secs);
+    let fetch = async {
+        match source {
+            Source::Http { url, headers, pointer } => {
+                let data = crate::jobs::download(url, header_map(headers)?, settings).await?;
+                let value: Value = serde_json::from_slice(&data).map_err(|e| format!("The response is not JSON: {}", e))?;
+                json_table(&value, pointer.as_deref())
+            }
+            Source::Postgres { url, query } => query_postgres(url, query, settings).await,
+            Source::GoogleSheets { spreadsheet_id, range, auth } => read_sheet(spreadsheet_id, range, auth, timeout).await,
+        }
+    };
+    let table = tokio::time::timeout(timeout, fetch).await.map_err(|_| format!("Timed out after {}s", timeout.as_secs()))??;
+    if table.rows.len() > settings.connector_max_rows {
+        return Err(format!("{} rows is more than CONNECTOR_MAX_ROWS ({})", table.rows.len(), settings.connector_max_rows));
+    }
+    Ok(table)
+}
+
+/// Rows from a JSON array: objects give one column per key, arrays are rows under a header
+/// row, and other values form a single `value` column.
+fn json_table(value: &Value, pointer: Option<&str>) -> Result<Table, String> {
+    let found = match pointer {
+        Some(pointer) => value.pointer(pointer).ok_or_else(|| format!("The response has nothing at {}", pointer))?,
+        None => value,
+    };
+    let Value::Array(items) = found else {
+        return Err(format!("Expected an array of rows at {}", pointer.unwrap_or("the top level")));
+    };
+    let mut table = Table::default();
+    if items.iter().all(Value::is_object) {
+        for item in items.iter().filter_map(Value::as_object) {
+            for key in item.keys() {
+                if !table.columns.contains(key) {
+                    table.columns.push(key.clone());
+                }
+            }
+        }
+        table.rows = items.iter().filter_map(Value::as_object)
+            .map(|item| table.columns.iter().map(|column| item.get(column).map(cell).unwrap_or_default()).collect())
+            .collect();
+    } else if items.iter().all(Value::is_array) {
+        let mut rows = items.iter().filter_map(Value::as_array).map(|row| row.iter().map(cell).collect::<Vec<_>>());
+        table.columns = rows.next().unwrap_or_default();
+        table.rows = rows.collect();
+    } else {
+        table.columns = vec!["value".to_string()];
+        table.rows = items.iter().map(|item| vec![cell(item)]).collect();
+    }
+    Ok(table)
+}
+
+fn cell(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(text) => text.clone(),
+        other => other.to_string(),
+    }
+}
+
+/// Runs `query`, a single statement, in a read-only transaction and returns every value as
+/// text, stopping as soon as it yields more than CONNECTOR_MAX_ROWS rows.
+async fn query_postgres(url: &str, query: &str, settings: &Settings) -> Result<Table, String> {
+    let config = postgres_config(url, settings)?;
+    let tls = native_tls::TlsConnector::new().map_err(|e| e.to_string())?;
+    let (mut client, connection) = config.connect(postgres_native_tls::MakeTlsConnector::new(tls)).await
+        .map_err(|e| format!("Cannot connect to {}: {}", database(&config), e))?;
+    tokio::spawn(async move {
+        if let Err(e) = connection.await {
+            warn!("PostgreSQL connection closed with an error: {}", e);
+        }
+    });
+    // Dropped without a commit: the transaction is rolled back whatever the query did
+    let transaction = client.build_transaction().read_only(true).start().await.map_err(|e| e.to_string())?;
+    transaction.batch_execute(&format!("SET LOCAL statement_timeout = {}", settings.connector_timeout_secs * 1000)).await.map_err(|e| e.to_string())?;
+    // Prepared statements hold exactly one statement, so nothing can be chained after it
+    let statement = transaction.prepare(query).await.map_err(|e| format!("Query failed: {}", e))?;
+    let columns: Vec<String> = statement.columns().iter().map(|column| column.name().to_string()).collect();
+    let as_json = transaction.prepare(&wrap_as_json(query)).await.map_err(|e| format!("Query failed: {}", e))?;
+    let rows = transaction.query_raw(&as_json, std::iter::empty::<String>()).await.map_err(|e| format!("Query failed: {}", e))?;
+    futures_util::pin_mut!(rows);
+
+    let mut table = Table { columns, rows: Vec::new() };
+    while let Some(row) = rows.try_next().await.map_err(|e| format!("Query failed: {}", e))? {
+        if table.rows.len() == settings.connector_max_rows {
+            return Err(format!("The query returned more than CONNECTOR_MAX_ROWS ({}) rows", settings.connector_max_rows));
+        }
+        let object: serde_json::Map<String, Value> = serde_json::from_str(row.get::<_, &str>(0)).map_err(|e| e.to_string())?;
+        table.rows.push(table.columns.iter().map(|column| object.get(column).map(cell).unwrap_or_default()).collect());
+    }
+    Ok(table)
+}
+
+/// `query` as a query yielding each of its rows as one JSON object, so values of any type
+/// come back as text.
+fn wrap_as_json(query: &str) -> String {
+    format!("SELECT row_to_json(q)::text FROM ({}\n) AS q", query.trim().trim_end_matches(';'))
+}
+
+/// Reads a range of a Google Sheet; its first row names the columns.
+async fn read_sheet(spreadsheet_id: &str, range: &str, auth: &SheetsAuth, timeout: Duration) -> Result<Table, String> {
+    let client = reqwest::Client::builder().timeout(timeout).build().map_err(|e| e.to_string())?;
+    let mut url = reqwest::Url::parse(SHEETS_API).map_err(|e| e.to_string())?;
+    url.path_segments_mut().map_err(|_| "Invalid Sheets API URL".to_string())?.pop_if_empty().extend([spreadsheet_id, "values", range]);
+    let request = match auth {
+        SheetsAuth::ApiKey(key) => {
+            url.query_pairs_mut().append_pair("key", key);
+            client.get(url)
+        }
+        SheetsAuth::ServiceAccount(account) => {
+            let token = access_token(&client, account).await?;
+            client.get(url).header(AUTHORIZATION, format!("Bearer {}", token))
+        }
+    };
+    let response = request.send().await.map_err(|e| format!("Google Sheets request failed: {}", e))?;
+    if !response.status().is_success() {
+        let status = response.status();
+        return Err(format!("Google Sheets answered HTTP {}: {}", status, response.text().await.unwrap_or_default()));
+    }
+    let body: Value = response.json().await.map_err(|e| format!("Unreadable Google Sheets response: {}", e))?;
+    let values = body.get("values").cloned().unwrap_or_else(|| Value::Array(Vec::new()));
+    let mut table = json_table(&values, None)?;
+    // Sheets leaves out trailing empty cells
+    for row in &mut table.rows {
+        row.resize(table.columns.len().max(row.len()), String::new());
+    }
+    Ok(table)
+}
+
+/// Exchanges a signed JWT for an access token, as Google service accounts authenticate.
+async fn access_token(client: &reqwest::Client, account: &ServiceAccount) -> Result<String, String> {
+    let assertion = service_account_jwt(account, unix_now())?;
+    let body = format!("grant_type=urn%3Aietf%3Aparams%3Aoauth%3Agrant-type%3Ajwt-bearer&assertion={}", assertion);
+    let response = client.post(GOOGLE_TOKEN_URL)
+        .header(reqwest::header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+        .body(body)
+        .send()
+        .await
+        .map_err(|e| format!("Google token request failed: {}", e))?;
+    if !response.status().is_success() {
+        return Err(format!("Google refused the service account: HTTP {}", response.status()));
+    }
+    let token: Value = response.json().await.map_err(|e| format!("Unreadable Google token response: {}", e))?;
+    token.get("access_token").and_then(Value::as_str).map(str::to_string).ok_or_else(|| "Google sent no access_token".to_string())
+}
+
+fn service_account_jwt(account: &ServiceAccount, now: u64) -> Result<String, String> {
+    let encode = |data: &[u8]| general_purpose::URL_SAFE_NO_PAD.encode(data);
+    let claims = serde_json::json!({
+        "iss": account.client_email,
+        "scope": SHEETS_SCOPE,
+        "aud": GOOGLE_TOKEN_URL,
+        "iat": now,
+        "exp": now + 3600,
+    });
+    let input = format!("{}.{}", encode(br#"{"alg":"RS256","typ":"JWT"}"#), encode(claims.to_string().as_bytes()));
+    let key = PKey::private_key_from_pem(account.private_key.as_bytes()).map_err(|e| e.to_string())?;
+    let mut signer = openssl::sign::Signer::new(MessageDigest::sha256(), &key).map_err(|e| e.to_string())?;
+    signer.update(input.as_bytes()).map_err(|e| e.to_string())?;
+    let signature = signer.sign_to_vec().map_err(|e| e.to_string())?;
+    Ok(format!("{}.{}", input, encode(&signature)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+
+    fn config(json: Value) -> ConnectorConfig {
+        serde_json::from_value(json).unwrap()
+    }
+
+    #[test]
+    fn test_json_table() {
+        let response = serde_json::json!({"data": {"items": [
+            {"region": "North", "total": 1200.5, "note": null},
+            {"region": "South", "total": 980, "rep": "A&B"},
+        ]}});
+        let table = json_table(&response, Some("/data/items")).unwrap();
+        assert_eq!(table.columns, ["note", "region", "total", "rep"]);
+        assert_eq!(table.rows[0], ["", "North", "1200.5", ""]);
+        assert_eq!(table.to_csv(true), "note,region,total,rep\n,North,1200.5,\n,South,980,A\\&B\n");
+
+        let rows = json_table(&serde_json::json!([["name", "qty"], ["Bolt, M4", 3]]), None).unwrap();
+        assert_eq!(rows.to_csv(false), "name,qty\n\"Bolt, M4\",3\n");
+        assert_eq!(json_table(&serde_json::json!([1, "two"]), None).unwrap().rows, [["1"], ["two"]]);
+        assert!(json_table(&response, None).is_err());
+        assert!(json_table(&response, Some("/missing")).is_err());
+    }
+
+    #[test]
+    fn test_parse() {
+        let settings = Settings { connector_database_hosts: vec!["*.db.example.com".into()], ..Settings::from_env() };
+        let http = config(serde_json::json!({"type": "http", "url": "https://api.example.com/sales?token=s3cret", "headers": {"Authorization": "Bearer x"}, "pointer": "/items"}));
+        assert!(matches!(parse("http", &http, &settings), Ok(Source::Http { .. })));
+        assert!(parse("http", &config(serde_json::json!({"type": "http", "url": "https://api.example.com", "pointer": "items"})), &settings).is_err());
+        assert!(parse("http", &config(serde_json::json!({"type": "http", "url": "https://api.example.com", "headers": {"bad header": "x"}})), &settings).is_err());
+
+        let postgres = |url: &str| config(serde_json::json!({"type": "postgres", "url": url, "query": "SELECT 1"}));
+        assert!(parse("postgres", &postgres("postgres://report:pw@eu.db.example.com/sales"), &settings).is_ok());
+        assert!(parse("postgres", &postgres("postgres://report:pw@localhost/sales"), &settings).is_err());
+        assert!(parse("postgres", &postgres("host=eu.db.example.com hostaddr=127.0.0.1 user=report"), &settings).is_err());
+        assert!(parse("postgres", &postgres("postgres://report:pw@eu.db.example.com/sales"), &Settings::from_env()).is_err(), "disabled without an allow-list");
+
+        let sheets = config(serde_json::json!({"type": "google_sheets", "spreadsheet_id": "1AbC", "range": "Sales!A1:C"}));
+        assert!(parse("google_sheets", &sheets, &settings).is_err(), "no credentials");
+        assert!(parse("mysql", &sheets, &settings).is_err());
+    }
+
+    #[test]
+    fn test_wrap_as_json() {
+        assert_eq!(wrap_as_json(" SELECT region, total FROM sales; "), "SELECT row_to_json(q)::text FROM (SELECT region, total FROM sales\n) AS q");
+        // A trailing comment cannot swallow the end of the wrapper
+        assert!(wrap_as_json("SELECT 1 -- totals").ends_with("-- totals\n) AS q"));
+    }
+
+    #[test]
+    fn test_service_account_jwt() {
+        let key = openssl::rsa::Rsa::generate(2048).unwrap();
+        let pem = String::from_utf8(key.private_key_to_pem().unwrap()).unwrap();
+        let account = ServiceAccount { client_email: "reports@acme.iam.gserviceaccount.com".into(), private_key: pem };
+        let jwt = service_account_jwt(&account, 1_000).unwrap();
+        let parts: Vec<&str> = jwt.split('.').collect();
+        let claims: Value = serde_json::from_slice(&general_purpose::URL_SAFE_NO_PAD.decode(parts[1]).unwrap()).unwrap();
+        assert_eq!(claims["iss"], "reports@acme.iam.gserviceaccount.com");
+        assert_eq!(claims["exp"], 4_600);
+
+        let public = PKey::public_key_from_pem(&key.public_key_to_pem().unwrap()).unwrap();
+        let mut verifier = openssl::sign::Verifier::new(MessageDigest::sha256(), &public).unwrap();
+        verifier.update(format!("{}.{}", parts[0], parts[1]).as_bytes()).unwrap();
+        assert!(verifier.verify(&general_purpose::URL_SAFE_NO_PAD.decode(parts[2]).unwrap()).unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_store_hides_credentials() {
+        let store = ConnectorStore::new(Arc::new(MemoryStorage::new()));
+        let settings = Settings::from_env();
+        let http = || config(serde_json::json!({"type": "http", "url": "https://api.example.com/sales?token=s3cret"}));
+        let info = store.put("p1", Some("acme"), "sales", http(), &settings).await.unwrap();
+        assert_eq!(info.source, "https://api.example.com/sales");
+        assert_eq!(info.file, "sales.csv");
+        assert!(store.put("p1", Some("acme"), "../x", http(), &settings).await.is_err());
+
+        assert_eq!(store.list("p1", Some("acme")).await.len(), 1);
+        assert!(store.list("p1", None).await.is_empty());
+        assert!(!store.delete("p1", "sales", Some("other")).await);
+        assert!(store.delete("p1", "sales", Some("acme")).await);
+        assert!(store.list("p1", Some("acme")).await.is_empty());
+    }
+}
@@ -0,0 +1,131 @@
+//! CORS policy: which web frontends may call the API from a browser. Origins, methods,
+//! headers and credentials come from the CORS_* settings, and TENANT_CORS_ORIGINS limits
+//! a tenant's requests (`X-Tenant-Id`) to its own frontends.
+
+use axum::http::{header, request::Parts, HeaderName, HeaderValue, Method};
+use std::sync::Arc;
+use tower_http::cors::{AllowHeaders, AllowMethods, AllowOrigin, Any, CorsLayer, ExposeHeaders};
+
+use crate::settings::Settings;
+
+/// Response headers exposed to browsers when CORS_EXPOSE_HEADERS is `*` but credentials
+/// rule out the wildcard.
+const API_HEADERS: [&str; 17] = [
+    "x-output-hash", "x-compile-time-ms", "x-cache", "x-warnings", "x-resource-usage", "x-project-id",
+    "x-main-file", "x-fingerprint", "x-files-received", "x-compile-receipt", "x-targets-succeeded",
+    "x-targets-failed", "x-hmr", "x-exam-seed", "x-conversion-warnings", "x-checksum", "x-certificate-ids",
+];
+
+/// The CORS layer for the API. With the defaults (any origin, no credentials) every origin
+/// may call it, as before CORS was configurable.
+pub fn layer(settings: &Settings) -> Result<CorsLayer, String> {
+    let credentials = settings.cors_allow_credentials;
+    let any_origin = settings.cors_allowed_origins.iter().any(|origin| origin == "*");
+    if credentials && any_origin {
+        return Err("CORS_ALLOW_CREDENTIALS needs explicit CORS_ALLOWED_ORIGINS, not *".to_string());
+    }
+
+    let mut layer = CorsLayer::new().allow_credentials(credentials);
+    layer = if any_origin && settings.tenant_cors_origins.is_empty() {
+        layer.allow_origin(Any)
+    } else {
+        let policy = Arc::new((settings.cors_allowed_origins.clone(), settings.tenant_cors_origins.clone()));
+        // The answer depends on the tenant, so caches must key on it too
+        layer
+            .allow_origin(AllowOrigin::predicate(move |origin: &HeaderValue, request: &Parts| {
+                let tenant = request.headers.get("X-Tenant-Id").and_then(|v| v.to_str().ok());
+                let preflight = request.method == Method::OPTIONS;
+                origin.to_str().is_ok_and(|origin| origin_allowed(&policy.0, &policy.1, origin, tenant, preflight))
+            }))
+            .vary([header::ORIGIN, header::ACCESS_CONTROL_REQUEST_METHOD, header::ACCESS_CONTROL_REQUEST_HEADERS, HeaderName::from_static("x-tenant-id")])
+    };
+
+    layer = match settings.cors_allowed_methods.as_slice() {
+        [any] if any == "*" && credentials => layer.allow_methods(AllowMethods::mirror_request()),
+        [any] if any == "*" => layer.allow_methods(Any),
+        methods => layer.allow_methods(
+            methods
+                .iter()
+                .map(|m| m.to_ascii_uppercase().parse::<Method>().map_err(|_| format!("Invalid method '{}' in CORS_ALLOWED_METHODS", m)))
+                .collect::<Result<Vec<_>, _>>()?,
+        ),
+    };
+    layer = match settings.cors_allowed_headers.as_slice() {
+        [any] if any == "*" && credentials => layer.allow_headers(AllowHeaders::mirror_request()),
+        [any] if any == "*" => layer.allow_headers(Any),
+        headers => layer.allow_headers(header_names(headers, "CORS_ALLOWED_HEADERS")?),
+    };
+    layer = match settings.cors_expose_headers.as_slice() {
+        [any] if any == "*" && credentials => layer.expose_headers(API_HEADERS.map(HeaderName::from_static)),
+        [any] if any == "*" => layer.expose_headers(Any),
+        headers => layer.expose_headers(ExposeHeaders::list(header_names(headers, "CORS_EXPOSE_HEADERS")?)),
+    };
+    Ok(layer)
+}
+
+fn header_names(names: &[String], setting: &str) -> Result<Vec<HeaderName>, String> {
+    names.iter().map(|name| name.parse().map_err(|_| format!("Invalid header '{}' in {}", name, setting))).collect()
+}
+
+/// Whether `origin` may call the API. A tenant with TENANT_CORS_ORIGINS entries is held to
+/// those; other requests to CORS_ALLOWED_ORIGINS. Preflights never carry `X-Tenant-Id`, so
+/// they pass for any configured origin and the actual request is checked.
+fn origin_allowed(allowed: &[String], tenants: &[(String, String)], origin: &str, tenant: Option<&str>, preflight: bool) -> bool {
+    let mut tenant_origins = tenants.iter().filter(|(name, _)| Some(name.as_str()) == tenant).peekable();
+    if tenant_origins.peek().is_some() {
+        return tenant_origins.any(|(_, pattern)| origin_matches(pattern, origin));
+    }
+    allowed.iter().any(|pattern| origin_matches(pattern, origin)) || (preflight && tenants.iter().any(|(_, pattern)| origin_matches(pattern, origin)))
+}
+
+/// Matches an origin against `*`, an exact origin, or a `scheme://*.domain` wildcard
+/// covering the domain's subdomains.
+fn origin_matches(pattern: &str, origin: &str) -> bool {
+    let pattern = pattern.trim_end_matches('/');
+    if pattern == "*" || pattern.eq_ignore_ascii_case(origin) {
+        return true;
+    }
+    let Some((scheme, domain)) = pattern.split_once("://*.") else { return false };
+    origin.split_once("://").is_some_and(|(origin_scheme, host)| {
+        let host = host.to_ascii_lowercase();
+        origin_scheme.eq_ignore_ascii_case(scheme) && host.strip_suffix(&domain.to_ascii_lowercase()).is_some_and(|sub| sub.len() > 1 && sub.ends_with('.'))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_origin_allowed() {
+        let allowed = vec!["https://app.example.com".to_string(), "https://*.example.org".to_string()];
+        let tenants = vec![("acme".to_string(), "https://acme.io".to_string())];
+        assert!(origin_allowed(&allowed, &tenants, "https://app.example.com", None, false));
+        assert!(origin_allowed(&allowed, &tenants, "https://docs.example.org", None, false));
+        assert!(!origin_allowed(&allowed, &tenants, "https://example.org", None, false));
+        assert!(!origin_allowed(&allowed, &tenants, "http://docs.example.org", None, false));
+        assert!(!origin_allowed(&allowed, &tenants, "https://evil-example.org", None, false));
+
+        // A tenant's requests only come from its own frontends
+        assert!(origin_allowed(&allowed, &tenants, "https://acme.io", Some("acme"), false));
+        assert!(!origin_allowed(&allowed, &tenants, "https://app.example.com", Some("acme"), false));
+        assert!(!origin_allowed(&allowed, &tenants, "https://acme.io", None, false));
+        assert!(origin_allowed(&allowed, &tenants, "https://acme.io", None, true));
+        assert!(origin_allowed(&allowed, &tenants, "https://app.example.com", Some("other"), false));
+    }
+
+    #[test]
+    fn test_layer_settings() {
+        let settings = Settings::from_env();
+        assert!(layer(&settings).is_ok());
+        assert!(layer(&Settings { cors_allow_credentials: true, ..Settings::from_env() }).is_err());
+        let settings = Settings {
+            cors_allow_credentials: true,
+            cors_allowed_origins: vec!["https://app.example.com".into()],
+            cors_allowed_methods: vec!["get".into(), "POST".into()],
+            ..Settings::from_env()
+        };
+        assert!(layer(&settings).is_ok());
+        assert!(layer(&Settings { cors_allowed_headers: vec!["bad header".into()], ..settings }).is_err());
+    }
+}
@@ -0,0 +1,174 @@
+//! Cron expressions for `/schedules`: five fields (minute, hour, day of month, month, day of
+//! week) evaluated in UTC, each `*`, a number, a range, a list or a `/step` of those, plus
+//! the `@hourly`, `@daily`, `@weekly`, `@monthly` and `@yearly` shorthands.
+
+/// How far ahead a next run is searched for; expressions such as `0 0 31 2 *` never match.
+const HORIZON_SECS: u64 = 5 * 366 * 86_400;
+
+const MONTHS: [&str; 12] = ["jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec"];
+const WEEKDAYS: [&str; 7] = ["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+
+/// A parsed expression: one bit per allowed value of each field.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cron {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    /// Whether day of month and day of week were both restricted, in which case a day
+    /// matching either runs, as in Vixie cron
+    either_day: bool,
+}
+
+impl Cron {
+    pub fn parse(expression: &str) -> Result<Self, String> {
+        let expanded = match expression.trim() {
+            "@hourly" => "0 * * * *",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@weekly" => "0 0 * * 0",
+            "@monthly" => "0 0 1 * *",
+            "@yearly" | "@annually" => "0 0 1 1 *",
+            other => other,
+        };
+        let fields: Vec<&str> = expanded.split_whitespace().collect();
+        let [minute, hour, day, month, weekday] = fields[..] else {
+            return Err(format!("Cron expression '{}' must have 5 fields: minute hour day-of-month month day-of-week", expression));
+        };
+        let weekdays = field(weekday, "day of week", 0, 7, &WEEKDAYS)?;
+        Ok(Self {
+            minutes: field(minute, "minute", 0, 59, &[])?,
+            hours: field(hour, "hour", 0, 23, &[])?,
+            days: field(day, "day of month", 1, 31, &[])?,
+            months: field(month, "month", 1, 12, &MONTHS)?,
+            // 7 is Sunday too
+            weekdays: (weekdays | weekdays >> 7) & 0x7F,
+            either_day: !day.starts_with('*') && !weekday.starts_with('*'),
+        })
+    }
+
+    /// The first matching minute strictly after `unix`, as a Unix time.
+    pub fn next_after(&self, unix: u64) -> Option<u64> {
+        let mut t = (unix / 60 + 1) * 60;
+        while t <= unix + HORIZON_SECS {
+            let (_, month, day, hour, minute, _) = civil(t);
+            if !self.day_matches(month, day, (t / 86_400 + 4) % 7) {
+                t = (t / 86_400 + 1) * 86_400;
+            } else if self.hours & 1 << hour == 0 {
+                t = (t / 3600 + 1) * 3600;
+            } else if self.minutes & 1 << minute == 0 {
+                t += 60;
+            } else {
+                return Some(t);
+            }
+        }
+        None
+    }
+
+    fn day_matches(&self, month: u32, day: u32, weekday: u64) -> bool {
+        if self.months & 1 << month == 0 {
+            return false;
+        }
+        let (by_day, by_weekday) = (self.days & 1 << day != 0, self.weekdays & 1 << weekday != 0);
+        if self.either_day { by_day || by_weekday } else { by_day && by_weekday }
+    }
+}
+
+/// Parses one field into a bit set of the values from `min` to `max` it allows. `names`
+/// are accepted for the values from `min` upwards.
+fn field(text: &str, what: &str, min: u32, max: u32, names: &[&str]) -> Result<u64, String> {
+    let value = |s: &str| -> Result<u32, String> {
+        let lower = s.to_ascii_lowercase();
+        let parsed = match names.iter().position(|name| *name == lower) {
+            Some(index) => index as u32 + min,
+            None => s.parse().map_err(|_| format!("Invalid {} '{}'", what, s))?,
+        };
+        if !(min..=max).contains(&parsed) {
+            return Err(format!("{} {} is outside {} to {}", what, parsed, min, max));
+        }
+        Ok(parsed)
+    };
+    let mut bits = 0u64;
+    for part in text.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => match step.parse::<u32>() {
+                Ok(step) if step > 0 => (range, step),
+                _ => return Err(format!("Invalid {} step '{}'", what, step)),
+            },
+            None => (part, 1),
+        };
+        let (first, last) = match range {
+            "*" => (min, max),
+            _ => match range.split_once('-') {
+                Some((first, last)) => (value(first)?, value(last)?),
+                // "5/15" runs from 5 to the end of the range
+                None if step > 1 => (value(range)?, max),
+                None => (value(range)?, value(range)?),
+            },
+        };
+        if first > last {
+            return Err(format!("Invalid {} range '{}'", what, range));
+        }
+        for v in (first..=last).step_by(step as usize) {
+            bits |= 1 << v;
+        }
+    }
+    Ok(bits)
+}
+
+/// Year, month, day, hour, minute and second (UTC) of a Unix time.
+pub(crate) fn civil(unix: u64) -> (i64, u32, u32, u64, u64, u64) {
+    let (days, seconds) = ((unix / 86_400) as i64, unix % 86_400);
+    // Howard Hinnant's days-to-civil algorithm
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day, seconds / 3600, seconds / 60 % 60, seconds % 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 2026-10-17 (a Saturday) 10:30:00 UTC
+    const NOW: u64 = 1_792_233_000;
+
+    fn next(expression: &str) -> (i64, u32, u32, u64, u64, u64) {
+        civil(Cron::parse(expression).unwrap().next_after(NOW).unwrap())
+    }
+
+    #[test]
+    fn test_civil() {
+        assert_eq!(civil(NOW), (2026, 10, 17, 10, 30, 0));
+        assert_eq!(civil(951_782_400), (2000, 2, 29, 0, 0, 0));
+    }
+
+    #[test]
+    fn test_next_after() {
+        assert_eq!(next("* * * * *"), (2026, 10, 17, 10, 31, 0));
+        assert_eq!(next("@hourly"), (2026, 10, 17, 11, 0, 0));
+        assert_eq!(next("@daily"), (2026, 10, 18, 0, 0, 0));
+        assert_eq!(next("30 2 * * *"), (2026, 10, 18, 2, 30, 0));
+        assert_eq!(next("*/20 10 * * *"), (2026, 10, 17, 10, 40, 0));
+        assert_eq!(next("0 9 * * mon-fri"), (2026, 10, 19, 9, 0, 0));
+        assert_eq!(next("0 0 * * 7"), (2026, 10, 18, 0, 0, 0));
+        assert_eq!(next("@monthly"), (2026, 11, 1, 0, 0, 0));
+        assert_eq!(next("0 6 29 feb *"), (2028, 2, 29, 6, 0, 0));
+        // Both day fields restricted: the 1st of the month or any Monday
+        assert_eq!(next("0 0 1 * 1"), (2026, 10, 19, 0, 0, 0));
+        assert_eq!(Cron::parse("0 0 31 2 *").unwrap().next_after(NOW), None);
+    }
+
+    #[test]
+    fn test_invalid() {
+        for expression in ["", "* * * *", "60 * * * *", "* 24 * * *", "* * 0 * *", "* * * 13 *", "*/0 * * * *", "5-1 * * * *", "* * * foo *", "@often"] {
+            assert!(Cron::parse(expression).is_err(), "{}", expression);
+        }
+    }
+}
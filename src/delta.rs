@@ -0,0 +1,211 @@
+//! rsync-style binary deltas between successive PDFs of a live-preview session. The old
+//! file is split into fixed blocks indexed by a rolling checksum; the new file is scanned
+//! byte by byte and encoded as copies of matching old blocks plus literal runs.
+//!
+//! Encoding: `TDL1`, varint new length, then ops — `0, varint offset, varint len` copies
+//! from the old file, `1, varint len, bytes` inserts literally.
+
+use std::collections::HashMap;
+use xxhash_rust::xxh64::xxh64;
+
+const MAGIC: &[u8; 4] = b"TDL1";
+const OP_COPY: u8 = 0;
+const OP_INSERT: u8 = 1;
+
+const MIN_BLOCK: usize = 256;
+const MAX_BLOCK: usize = 8192;
+
+/// Block size grows with the square root of the file, as in rsync.
+fn block_size(len: usize) -> usize {
+    ((len as f64).sqrt() as usize).clamp(MIN_BLOCK, MAX_BLOCK)
+}
+
+/// rsync's weak checksum: two 16-bit sums that can slide one byte at a time.
+struct Rolling {
+    a: u32,
+    b: u32,
+    len: u32,
+}
+
+impl Rolling {
+    fn new(block: &[u8]) -> Self {
+        let (mut a, mut b) = (0u32, 0u32);
+        for (i, &byte) in block.iter().enumerate() {
+            a = a.wrapping_add(byte as u32);
+            b = b.wrapping_add((block.len() - i) as u32 * byte as u32);
+        }
+        Self { a: a & 0xffff, b: b & 0xffff, len: block.len() as u32 }
+    }
+
+    fn roll(&mut self, out: u8, inp: u8) {
+        self.a = self.a.wrapping_sub(out as u32).wrapping_add(inp as u32) & 0xffff;
+        self.b = self.b.wrapping_sub(self.len.wrapping_mul(out as u32)).wrapping_add(self.a) & 0xffff;
+    }
+
+    fn digest(&self) -> u32 {
+        (self.b << 16) | self.a
+    }
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn read_varint(data: &[u8], pos: &mut usize) -> Result<u64, String> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let byte = *data.get(*pos).ok_or("Truncated delta")?;
+        *pos += 1;
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err("Invalid varint in delta".to_string())
+}
+
+struct Encoder {
+    out: Vec<u8>,
+    /// Pending copy (offset, len), merged while contiguous
+    copy: Option<(usize, usize)>,
+}
+
+impl Encoder {
+    fn copy(&mut self, offset: usize, len: usize) {
+        match &mut self.copy {
+            Some((start, pending)) if *start + *pending == offset => *pending += len,
+            _ => {
+                self.flush_copy();
+                self.copy = Some((offset, len));
+            }
+        }
+    }
+
+    fn insert(&mut self, bytes: &[u8]) {
+        if bytes.is_empty() {
+            return;
+        }
+        self.flush_copy();
+        self.out.push(OP_INSERT);
+        write_varint(&mut self.out, bytes.len() as u64);
+        self.out.extend_from_slice(bytes);
+    }
+
+    fn flush_copy(&mut self) {
+        if let Some((offset, len)) = self.copy.take() {
+            self.out.push(OP_COPY);
+            write_varint(&mut self.out, offset as u64);
+            write_varint(&mut self.out, len as u64);
+        }
+    }
+}
+
+/// Encodes `new` as a delta against `old`.
+pub fn diff(old: &[u8], new: &[u8]) -> Vec<u8> {
+    let bs = block_size(old.len());
+    let mut blocks: HashMap<u32, Vec<(usize, u64)>> = HashMap::new();
+    for (i, block) in old.chunks_exact(bs).enumerate() {
+        blocks.entry(Rolling::new(block).digest()).or_default().push((i * bs, xxh64(block, 0)));
+    }
+
+    let mut encoder = Encoder { out: MAGIC.to_vec(), copy: None };
+    write_varint(&mut encoder.out, new.len() as u64);
+
+    let mut literal_start = 0;
+    let mut pos = 0;
+    let mut rolling = (new.len() >= bs).then(|| Rolling::new(&new[..bs]));
+    while let Some(weak) = rolling.as_mut() {
+        let matched = blocks.get(&weak.digest()).and_then(|candidates| {
+            let strong = xxh64(&new[pos..pos + bs], 0);
+            candidates.iter().find(|(_, h)| *h == strong).map(|(offset, _)| *offset)
+        });
+        if let Some(offset) = matched {
+            encoder.insert(&new[literal_start..pos]);
+            encoder.copy(offset, bs);
+            pos += bs;
+            literal_start = pos;
+            rolling = (pos + bs <= new.len()).then(|| Rolling::new(&new[pos..pos + bs]));
+            continue;
+        }
+        if pos + bs >= new.len() {
+            break;
+        }
+        weak.roll(new[pos], new[pos + bs]);
+        pos += 1;
+    }
+    encoder.insert(&new[literal_start..]);
+    encoder.flush_copy();
+    encoder.out
+}
+
+/// Rebuilds the new file from `old` and a delta produced by [`diff`].
+pub fn apply(old: &[u8], delta: &[u8]) -> Result<Vec<u8>, String> {
+    if !delta.starts_with(MAGIC) {
+        return Err("Not a delta".to_string());
+    }
+    let mut pos = MAGIC.len();
+    let expected = read_varint(delta, &mut pos)? as usize;
+    let mut out = Vec::with_capacity(expected.min(old.len() + delta.len()));
+    while pos < delta.len() {
+        let op = delta[pos];
+        pos += 1;
+        match op {
+            OP_COPY => {
+                let offset = read_varint(delta, &mut pos)? as usize;
+                let len = read_varint(delta, &mut pos)? as usize;
+                let end = offset.checked_add(len).filter(|end| *end <= old.len()).ok_or("Copy out of range")?;
+                out.extend_from_slice(&old[offset..end]);
+            }
+            OP_INSERT => {
+                let len = read_varint(delta, &mut pos)? as usize;
+                let end = pos.checked_add(len).filter(|end| *end <= delta.len()).ok_or("Truncated insert")?;
+                out.extend_from_slice(&delta[pos..end]);
+                pos = end;
+            }
+            other => return Err(format!("Unknown delta op {}", other)),
+        }
+    }
+    if out.len() != expected {
+        return Err(format!("Delta produced {} bytes, expected {}", out.len(), expected));
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(len: usize, seed: u64) -> Vec<u8> {
+        (0..len).map(|i| (xxh64(&(i as u64).to_le_bytes(), seed) & 0xff) as u8).collect()
+    }
+
+    #[test]
+    fn test_small_edit_gives_small_delta() {
+        let old = sample(200_000, 1);
+        let mut new = old.clone();
+        new.splice(100_000..100_010, b"edited page content".iter().copied());
+        new.extend_from_slice(b"%%EOF trailer");
+
+        let delta = diff(&old, &new);
+        assert!(delta.len() < 2_000, "delta too large: {}", delta.len());
+        assert_eq!(apply(&old, &delta).unwrap(), new);
+    }
+
+    #[test]
+    fn test_unrelated_and_edge_inputs() {
+        for (old, new) in [
+            (sample(5_000, 1), sample(7_000, 2)),
+            (Vec::new(), sample(300, 3)),
+            (sample(300, 4), Vec::new()),
+            (b"short".to_vec(), b"shorter".to_vec()),
+        ] {
+            assert_eq!(apply(&old, &diff(&old, &new)).unwrap(), new);
+        }
+        assert!(apply(b"old", b"TDL1\x05\x00\x00\x09").is_err());
+        assert!(apply(b"old", b"nope").is_err());
+    }
+}
@@ -0,0 +1,560 @@
+//! Reads Word (.docx) files into the HTML subset understood by [`HtmlConverter`], so Word
+//! documents share its LaTeX mapping and compile with a bundled template.
+
+use regex::Regex;
+use std::collections::HashMap;
+use std::io::Read;
+
+use crate::html::{decode_entities, HtmlConverter};
+use crate::latex::{escape, fill_template};
+use crate::render::AuxFile;
+
+const DOCX_TEMPLATE: &str = include_str!("../templates/docx.tex");
+
+/// Upper bound on the decompressed size of any single part of the archive.
+const MAX_PART_BYTES: u64 = 64 * 1024 * 1024;
+
+/// Image types the engine can include; others (EMF, WMF, ...) are skipped.
+const IMAGE_EXTENSIONS: &[&str] = &["png", "jpg", "jpeg", "pdf"];
+
+/// A Word document converted to LaTeX.
+pub struct DocxConversion {
+    pub source: String,
+    /// Images referenced by the source
+    pub files: Vec<AuxFile>,
+    pub warnings: Vec<String>,
+}
+
+pub struct DocxConverter;
+
+impl DocxConverter {
+    pub fn convert(data: &[u8]) -> Result<DocxConversion, String> {
+        let mut zip = zip::ZipArchive::new(std::io::Cursor::new(data)).map_err(|e| format!("Not a .docx file: {}", e))?;
+        let document = read_part(&mut zip, "word/document.xml")?.ok_or("Not a .docx file: word/document.xml is missing")?;
+        let relationships = read_part(&mut zip, "word/_rels/document.xml.rels")?.map(|x| relationships(&x)).unwrap_or_default();
+        let numbering = read_part(&mut zip, "word/numbering.xml")?.map(|x| ordered_lists(&x)).unwrap_or_default();
+        let styles = read_part(&mut zip, "word/styles.xml")?.map(|x| style_names(&x)).unwrap_or_default();
+        let core = read_part(&mut zip, "docProps/core.xml")?.unwrap_or_default();
+
+        let body = Body::new(&relationships, &numbering, &styles).render(&document);
+        let conversion = HtmlConverter::convert(&body.html, false)?;
+        let mut warnings = conversion.warnings;
+
+        let mut files = Vec::new();
+        for (target, name) in &body.images {
+            match read_binary_part(&mut zip, &format!("word/{}", target.trim_start_matches('/').trim_start_matches("word/")))? {
+                Some(bytes) => files.push((name.clone(), bytes)),
+                None => warnings.push(format!("Image {} is missing from the archive", target)),
+            }
+        }
+        warnings.extend(body.warnings);
+
+        let core_field = |tag: &str| {
+            let re = Regex::new(&format!(r"<{tag}[^>]*>([^<]*)</{tag}>")).unwrap();
+            re.captures(&core).map(|c| decode_entities(&c[1]).trim().to_string()).filter(|v| !v.is_empty())
+        };
+        let (title, maketitle) = match core_field("dc:title") {
+            Some(title) => (
+                format!("\\title{{{}}}\n\\author{{{}}}\n\\date{{}}", escape(&title), core_field("dc:creator").map(|a| escape(&a)).unwrap_or_default()),
+                "\\maketitle",
+            ),
+            None => (String::new(), ""),
+        };
+
+        let source = fill_template(DOCX_TEMPLATE, &[
+            ("GEOMETRY", &body.geometry),
+            ("PREAMBLE", &conversion.preamble.join("\n")),
+            ("TITLE", &title),
+            ("MAKETITLE", maketitle),
+            ("BODY", &conversion.latex),
+        ]);
+        Ok(DocxConversion { source, files, warnings })
+    }
+}
+
+fn read_part<R: Read + std::io::Seek>(zip: &mut zip::ZipArchive<R>, name: &str) -> Result<Option<String>, String> {
+    match read_binary_part(zip, name)? {
+        Some(bytes) => String::from_utf8(bytes).map(Some).map_err(|_| format!("{} is not UTF-8", name)),
+        None => Ok(None),
+    }
+}
+
+fn read_binary_part<R: Read + std::io::Seek>(zip: &mut zip::ZipArchive<R>, name: &str) -> Result<Option<Vec<u8>>, String> {
+    let file = match zip.by_name(name) {
+        Ok(f) => f,
+        Err(zip::result::ZipError::FileNotFound) => return Ok(None),
+        Err(e) => return Err(format!("Cannot read {}: {}", name, e)),
+    };
+    let mut bytes = Vec::new();
+    file.take(MAX_PART_BYTES + 1).read_to_end(&mut bytes).map_err(|e| format!("Cannot read {}: {}", name, e))?;
+    if bytes.len() as u64 > MAX_PART_BYTES {
+        return Err(format!("{} exceeds {} bytes", name, MAX_PART_BYTES));
+    }
+    Ok(Some(bytes))
+}
+
+/// `rId` → (target, external) from a relationships part.
+fn relationships(xml: &str) -> HashMap<String, (String, bool)> {
+    tokens(xml).into_iter()
+        .filter_map(|t| match t {
+            Token::Start { name, attrs, .. } if name == "Relationship" => Some((
+                attr(&attrs, "Id")?.to_string(),
+                (attr(&attrs, "Target")?.to_string(), attr(&attrs, "TargetMode") == Some("External")),
+            )),
+            _ => None,
+        })
+        .collect()
+}
+
+/// (`numId`, level) pairs whose list is numbered rather than bulleted.
+fn ordered_lists(xml: &str) -> HashMap<(String, String), bool> {
+    let mut abstract_formats: HashMap<(String, String), bool> = HashMap::new();
+    let mut num_to_abstract: Vec<(String, String)> = Vec::new();
+    let (mut abstract_id, mut level, mut num_id) = (String::new(), String::new(), String::new());
+    for token in tokens(xml) {
+        let Token::Start { name, attrs, .. } = token else { continue };
+        match name.as_str() {
+            "w:abstractNum" => abstract_id = attr(&attrs, "w:abstractNumId").unwrap_or_default().to_string(),
+            "w:lvl" => level = attr(&attrs, "w:ilvl").unwrap_or_default().to_string(),
+            "w:numFmt" if num_id.is_empty() => {
+                let ordered = !matches!(attr(&attrs, "w:val"), Some("bullet") | Some("none"));
+                abstract_formats.insert((abstract_id.clone(), level.clone()), ordered);
+            }
+            "w:num" => num_id = attr(&attrs, "w:numId").unwrap_or_default().to_string(),
+            "w:abstractNumId" if !num_id.is_empty() => {
+                num_to_abstract.push((num_id.clone(), attr(&attrs, "w:val").unwrap_or_default().to_string()));
+            }
+            _ => {}
+        }
+    }
+    let mut ordered = HashMap::new();
+    for (num, abstract_num) in num_to_abstract {
+        for ((a, level), is_ordered) in &abstract_formats {
+            if *a == abstract_num {
+                ordered.insert((num.clone(), level.clone()), *is_ordered);
+            }
+        }
+    }
+    ordered
+}
+
+/// Style id → lowercase style name ("Heading1" or a localized id → "heading 1").
+fn style_names(xml: &str) -> HashMap<String, String> {
+    let mut names = HashMap::new();
+    let mut id = None;
+    for token in tokens(xml) {
+        let Token::Start { name, attrs, .. } = token else { continue };
+        match name.as_str() {
+            "w:style" => id = attr(&attrs, "w:styleId").map(str::to_string),
+            "w:name" => {
+                if let (Some(id), Some(name)) = (id.take(), attr(&attrs, "w:val")) {
+                    names.insert(id, name.to_ascii_lowercase());
+                }
+            }
+            _ => {}
+        }
+    }
+    names
+}
+
+#[derive(Debug)]
+enum Token {
+    Start { name: String, attrs: Vec<(String, String)>, empty: bool },
+    End(String),
+    Text(String),
+}
+
+/// A flat token stream; Office XML is machine-written, so this is all the parsing it needs.
+fn tokens(xml: &str) -> Vec<Token> {
+    let re = Regex::new(r#"<(/?)([\w:.-]+)((?:[^>"]|"[^"]*")*?)(/?)>|([^<]+)"#).unwrap();
+    let attr_re = Regex::new(r#"([\w:.-]+)\s*=\s*"([^"]*)""#).unwrap();
+    re.captures_iter(xml)
+        .map(|caps| match caps.get(2) {
+            Some(name) if caps[1].is_empty() => Token::Start {
+                name: name.as_str().to_string(),
+                attrs: attr_re.captures_iter(&caps[3]).map(|a| (a[1].to_string(), decode_entities(&a[2]))).collect(),
+                empty: !caps[4].is_empty(),
+            },
+            Some(name) => Token::End(name.as_str().to_string()),
+            None => Token::Text(decode_entities(&caps[5])),
+        })
+        .collect()
+}
+
+fn attr<'a>(attrs: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    attrs.iter().find(|(k, _)| k == name).map(|(_, v)| v.as_str())
+}
+
+/// Whether a toggle property (`<w:b/>`, `<w:i w:val="0"/>`) is on.
+fn toggled(attrs: &[(String, String)]) -> bool {
+    !matches!(attr(attrs, "w:val"), Some("0") | Some("false") | Some("none"))
+}
+
+fn html_escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+#[derive(Default)]
+struct RunStyle {
+    bold: bool,
+    italic: bool,
+    underline: bool,
+    strike: bool,
+    vertical: Option<&'static str>,
+    color: Option<String>,
+}
+
+impl RunStyle {
+    fn wrap(&self, mut text: String) -> String {
+        for (on, tag) in [(self.bold, "strong"), (self.italic, "em"), (self.underline, "u"), (self.strike, "s")] {
+            if on {
+                text = format!("<{tag}>{text}</{tag}>");
+            }
+        }
+        if let Some(tag) = self.vertical {
+            text = format!("<{tag}>{text}</{tag}>");
+        }
+        if let Some(color) = &self.color {
+            text = format!("<span style=\"color:#{}\">{}</span>", color, text);
+        }
+        text
+    }
+}
+
+#[derive(Default)]
+struct Paragraph {
+    style: Option<String>,
+    align: Option<&'static str>,
+    /// (numId, level)
+    list: Option<(String, usize)>,
+    content: String,
+}
+
+/// Walks `word/document.xml`, writing HTML.
+struct Body<'a> {
+    relationships: &'a HashMap<String, (String, bool)>,
+    numbering: &'a HashMap<(String, String), bool>,
+    styles: &'a HashMap<String, String>,
+    html: String,
+    /// Open lists, innermost last
+    lists: Vec<&'static str>,
+    /// (archive target, file name)
+    images: Vec<(String, String)>,
+    warnings: Vec<String>,
+    geometry: String,
+}
+
+impl<'a> Body<'a> {
+    fn new(relationships: &'a HashMap<String, (String, bool)>, numbering: &'a HashMap<(String, String), bool>, styles: &'a HashMap<String, String>) -> Self {
+        Self {
+            relationships, numbering, styles,
+            html: String::new(),
+            lists: Vec::new(),
+            images: Vec::new(),
+            warnings: Vec::new(),
+            geometry: "a4paper,margin=2.5cm".to_string(),
+        }
+    }
+
+    fn render(mut self, document: &str) -> Self {
+        let mut paragraph: Option<Paragraph> = None;
+        let mut run = RunStyle::default();
+        let (mut in_paragraph_props, mut in_run_props, mut in_text) = (false, false, false);
+        let mut link_open = false;
+        // Per open table: whether the current row repeats as a header, and the pending
+        // cell's column span until its `<td>` is written
+        let mut tables: Vec<(bool, Option<usize>)> = Vec::new();
+        let mut image_width: Option<u64> = None;
+
+        for token in tokens(document) {
+            match token {
+                Token::Start { name, attrs, empty } => match name.as_str() {
+                    "w:p" => {
+                        self.open_pending_cell(&mut tables);
+                        paragraph = Some(Paragraph::default());
+                        if empty {
+                            paragraph = None;
+                        }
+                    }
+                    "w:pPr" if !empty => in_paragraph_props = true,
+                    "w:pStyle" if in_paragraph_props => {
+                        if let Some(p) = paragraph.as_mut() {
+                            p.style = attr(&attrs, "w:val").map(|id| self.styles.get(id).cloned().unwrap_or_else(|| id.to_ascii_lowercase()));
+                        }
+                    }
+                    "w:jc" if in_paragraph_props => {
+                        if let Some(p) = paragraph.as_mut() {
+                            p.align = match attr(&attrs, "w:val") {
+                                Some("center") => Some("center"),
+                                Some("right") | Some("end") => Some("right"),
+                                _ => None,
+                            };
+                        }
+                    }
+                    "w:ilvl" if in_paragraph_props => {
+                        if let Some(p) = paragraph.as_mut() {
+                            let level = attr(&attrs, "w:val").and_then(|v| v.parse().ok()).unwrap_or(0);
+                            p.list = Some((p.list.take().map(|l| l.0).unwrap_or_default(), level));
+                        }
+                    }
+                    "w:numId" if in_paragraph_props => {
+                        if let Some(p) = paragraph.as_mut() {
+                            let id = attr(&attrs, "w:val").unwrap_or_default().to_string();
+                            // numId 0 switches numbering off
+                            p.list = if id == "0" { None } else { Some((id, p.list.as_ref().map_or(0, |l| l.1))) };
+                        }
+                    }
+                    "w:r" => run = RunStyle::default(),
+                    "w:rPr" if !in_paragraph_props && !empty => in_run_props = true,
+                    "w:b" if in_run_props => run.bold = toggled(&attrs),
+                    "w:i" if in_run_props => run.italic = toggled(&attrs),
+                    "w:u" if in_run_props => run.underline = toggled(&attrs),
+                    "w:strike" | "w:dstrike" if in_run_props => run.strike = toggled(&attrs),
+                    "w:vertAlign" if in_run_props => run.vertical = match attr(&attrs, "w:val") {
+                        Some("superscript") => Some("sup"),
+                        Some("subscript") => Some("sub"),
+                        _ => None,
+                    },
+                    "w:color" if in_run_props => {
+                        run.color = attr(&attrs, "w:val").filter(|c| c.len() == 6 && c.chars().all(|c| c.is_ascii_hexdigit()) && *c != "000000").map(str::to_string);
+                    }
+                    "w:t" if !empty => in_text = true,
+                    "w:tab" if !in_paragraph_props => self.push_text(&mut paragraph, " "),
+                    "w:br" if attr(&attrs, "w:type").is_none_or(|t| t == "textWrapping") => self.push_text(&mut paragraph, "<br>"),
+                    "w:hyperlink" => {
+                        let target = attr(&attrs, "r:id").and_then(|id| self.relationships.get(id)).filter(|(_, external)| *external);
+                        if let (Some((url, _)), Some(p)) = (target, paragraph.as_mut()) {
+                            p.content.push_str(&format!("<a href=\"{}\">", html_escape(url)));
+                            link_open = !empty;
+                        }
+                    }
+                    "wp:extent" => image_width = attr(&attrs, "cx").and_then(|cx| cx.parse::<u64>().ok()),
+                    "a:blip" => {
+                        if let Some(img) = attr(&attrs, "r:embed").and_then(|id| self.image(id, image_width.take())) {
+                            self.push_text(&mut paragraph, &img);
+                        }
+                    }
+                    "w:tbl" => {
+                        self.close_lists();
+                        self.html.push_str("<table>");
+                        tables.push((false, None));
+                    }
+                    "w:tr" => {
+                        self.html.push_str("<tr>");
+                        if let Some(table) = tables.last_mut() {
+                            table.0 = false;
+                        }
+                    }
+                    "w:tblHeader" => {
+                        if let Some(table) = tables.last_mut() {
+                            table.0 = toggled(&attrs);
+                        }
+                    }
+                    "w:tc" => {
+                        self.close_lists();
+                        if let Some(table) = tables.last_mut() {
+                            table.1 = Some(1);
+                        }
+                    }
+                    "w:gridSpan" => {
+                        if let Some((_, Some(span))) = tables.last_mut() {
+                            *span = attr(&attrs, "w:val").and_then(|v| v.parse().ok()).unwrap_or(1);
+                        }
+                    }
+                    "w:pgSz" => {
+                        // Widths in twentieths of a point; Letter is 12240, A4 11906
+                        let width = attr(&attrs, "w:w").and_then(|w| w.parse::<i64>().ok()).unwrap_or(11906);
+                        let paper = if (width - 12240).abs() < 100 { "letterpaper" } else { "a4paper" };
+                        self.geometry = self.geometry.replacen("a4paper", paper, 1);
+                    }
+                    "w:pgMar" => {
+                        let cm = |side: &str| attr(&attrs, side).and_then(|v| v.parse::<f64>().ok()).map(|twips| format!("{:.2}cm", twips / 567.0));
+                        if let (Some(top), Some(right), Some(bottom), Some(left)) = (cm("w:top"), cm("w:right"), cm("w:bottom"), cm("w:left")) {
+                            let paper = self.geometry.split(',').next().unwrap_or("a4paper").to_string();
+                            self.geometry = format!("{},top={},right={},bottom={},left={}", paper, top, right, bottom, left);
+                        }
+                    }
+                    _ => {}
+                },
+                Token::End(name) => match name.as_str() {
+                    "w:pPr" => in_paragraph_props = false,
+                    "w:rPr" => in_run_props = false,
+                    "w:t" => in_text = false,
+                    "w:hyperlink" if link_open => {
+                        self.push_text(&mut paragraph, "</a>");
+                        link_open = false;
+                    }
+                    "w:p" => {
+                        if let Some(p) = paragraph.take() {
+                            self.paragraph(p);
+                        }
+                    }
+                    "w:tc" => {
+                        self.open_pending_cell(&mut tables);
+                        self.close_lists();
+                        let header = tables.last().is_some_and(|t| t.0);
+                        self.html.push_str(if header { "</th>" } else { "</td>" });
+                    }
+                    "w:tr" => self.html.push_str("</tr>"),
+                    "w:tbl" => {
+                        self.html.push_str("</table>");
+                        tables.pop();
+                    }
+                    _ => {}
+                },
+                Token::Text(text) if in_text => {
+                    let styled = run.wrap(html_escape(&text));
+                    self.push_text(&mut paragraph, &styled);
+                }
+                Token::Text(_) => {}
+            }
+        }
+        self.close_lists();
+        self
+    }
+
+    fn push_text(&mut self, paragraph: &mut Option<Paragraph>, html: &str) {
+        if let Some(p) = paragraph.as_mut() {
+            p.content.push_str(html);
+        }
+    }
+
+    fn open_pending_cell(&mut self, tables: &mut [(bool, Option<usize>)]) {
+        if let Some((header, pending)) = tables.last_mut() {
+            if let Some(span) = pending.take() {
+                let tag = if *header { "th" } else { "td" };
+                if span > 1 {
+                    self.html.push_str(&format!("<{} colspan=\"{}\">", tag, span));
+                } else {
+                    self.html.push_str(&format!("<{}>", tag));
+                }
+            }
+        }
+    }
+
+    fn paragraph(&mut self, p: Paragraph) {
+        if let Some((num_id, level)) = &p.list {
+            let ordered = self.numbering.get(&(num_id.clone(), level.to_string())).copied().unwrap_or(false);
+            let tag = if ordered { "ol" } else { "ul" };
+            let depth = level + 1;
+            while self.lists.len() > depth {
+                let list = self.lists.pop().unwrap_or("ul");
+                self.html.push_str(&format!("</li></{}>", list));
+            }
+            if self.lists.len() == depth {
+                self.html.push_str("</li>");
+            }
+            while self.lists.len() < depth {
+                self.html.push_str(&format!("<{}>", tag));
+                self.lists.push(tag);
+            }
+            self.html.push_str(&format!("<li>{}", p.content));
+            return;
+        }
+
+        self.close_lists();
+        if p.content.trim().is_empty() {
+            return;
+        }
+        let style = p.style.as_deref().unwrap_or_default();
+        if style == "title" {
+            self.html.push_str(&format!("<h1>{}</h1>", p.content));
+        } else if let Some(level) = style.strip_prefix("heading ").and_then(|l| l.trim().parse::<usize>().ok()) {
+            self.html.push_str(&format!("<h{0}>{1}</h{0}>", level.clamp(1, 6), p.content));
+        } else if style.contains("quote") {
+            self.html.push_str(&format!("<blockquote>{}</blockquote>", p.content));
+        } else {
+            let align = p.align.map(|a| format!(" style=\"text-align:{}\"", a)).unwrap_or_default();
+            self.html.push_str(&format!("<p{}>{}</p>", align, p.content));
+        }
+    }
+
+    fn close_lists(&mut self) {
+        while let Some(list) = self.lists.pop() {
+            self.html.push_str(&format!("</li></{}>", list));
+        }
+    }
+
+    /// An `<img>` for an embedded picture, registering the file it needs.
+    fn image(&mut self, id: &str, width_emu: Option<u64>) -> Option<String> {
+        let (target, _) = self.relationships.get(id)?;
+        let file_name = target.rsplit('/').next().unwrap_or(target);
+        let extension = file_name.rsplit('.').next().unwrap_or_default().to_ascii_lowercase();
+        if !IMAGE_EXTENSIONS.contains(&extension.as_str()) || !file_name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_')) {
+            self.warnings.push(format!("Skipped image {} (only PNG, JPEG and PDF images are supported)", file_name));
+            return None;
+        }
+        let name = format!("docx-{}", file_name);
+        if !self.images.iter().any(|(_, n)| *n == name) {
+            self.images.push((target.clone(), name.clone()));
+        }
+        // 9525 EMU per CSS pixel
+        let width = width_emu.map(|emu| format!(" width=\"{}px\"", emu / 9525)).unwrap_or_default();
+        Some(format!("<img src=\"{}\"{}>", name, width))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn docx(parts: &[(&str, &str)]) -> Vec<u8> {
+        let mut zip = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+        for (name, content) in parts {
+            zip.start_file(*name, zip::write::FileOptions::default()).unwrap();
+            zip.write_all(content.as_bytes()).unwrap();
+        }
+        zip.finish().unwrap().into_inner()
+    }
+
+    const DOCUMENT: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<w:document xmlns:w="http://schemas.openxmlformats.org/wordprocessingml/2006/main"><w:body>
+<w:p><w:pPr><w:pStyle w:val="Ttulo1"/></w:pPr><w:r><w:t>Results &amp; Notes</w:t></w:r></w:p>
+<w:p><w:pPr><w:jc w:val="center"/><w:rPr><w:b/></w:rPr></w:pPr><w:r><w:t xml:space="preserve">Plain </w:t></w:r><w:r><w:rPr><w:b/><w:i w:val="0"/></w:rPr><w:t>bold</w:t></w:r><w:hyperlink r:id="rId2"><w:r><w:t>site</w:t></w:r></w:hyperlink></w:p>
+<w:p><w:pPr><w:numPr><w:ilvl w:val="0"/><w:numId w:val="1"/></w:numPr></w:pPr><w:r><w:t>first</w:t></w:r></w:p>
+<w:p><w:pPr><w:numPr><w:ilvl w:val="1"/><w:numId w:val="1"/></w:numPr></w:pPr><w:r><w:t>nested</w:t></w:r></w:p>
+<w:p><w:pPr><w:numPr><w:ilvl w:val="0"/><w:numId w:val="1"/></w:numPr></w:pPr><w:r><w:t>second</w:t></w:r></w:p>
+<w:tbl><w:tr><w:trPr><w:tblHeader/></w:trPr><w:tc><w:tcPr><w:gridSpan w:val="2"/></w:tcPr><w:p><w:r><w:t>Head</w:t></w:r></w:p></w:tc></w:tr>
+<w:tr><w:tc><w:p><w:r><w:t>a</w:t></w:r></w:p></w:tc><w:tc><w:p><w:r><w:drawing><wp:inline><wp:extent cx="952500" cy="952500"/><a:graphic><a:blip r:embed="rId3"/></a:graphic></wp:inline></w:drawing></w:r></w:p></w:tc></w:tr></w:tbl>
+<w:sectPr><w:pgSz w:w="12240" w:h="15840"/><w:pgMar w:top="1134" w:right="1134" w:bottom="1134" w:left="1134"/></w:sectPr>
+</w:body></w:document>"#;
+
+    const RELS: &str = r#"<Relationships><Relationship Id="rId2" Type="hyperlink" Target="https://example.com/?a=1&amp;b=2" TargetMode="External"/><Relationship Id="rId3" Type="image" Target="media/image1.png"/></Relationships>"#;
+
+    const NUMBERING: &str = r#"<w:numbering><w:abstractNum w:abstractNumId="7"><w:lvl w:ilvl="0"><w:numFmt w:val="decimal"/></w:lvl><w:lvl w:ilvl="1"><w:numFmt w:val="bullet"/></w:lvl></w:abstractNum><w:num w:numId="1"><w:abstractNumId w:val="7"/></w:num></w:numbering>"#;
+
+    #[test]
+    fn test_body_html() {
+        let relationships = relationships(RELS);
+        let numbering = ordered_lists(NUMBERING);
+        let styles = style_names(r#"<w:styles><w:style w:styleId="Ttulo1"><w:name w:val="heading 1"/></w:style></w:styles>"#);
+        let body = Body::new(&relationships, &numbering, &styles).render(DOCUMENT);
+        assert_eq!(body.html, concat!(
+            "<h1>Results &amp; Notes</h1>",
+            "<p style=\"text-align:center\">Plain <strong>bold</strong><a href=\"https://example.com/?a=1&amp;b=2\">site</a></p>",
+            "<ol><li>first<ul><li>nested</li></ul></li><li>second</li></ol>",
+            "<table><tr><th colspan=\"2\"><p>Head</p></th></tr><tr><td><p>a</p></td><td><p><img src=\"docx-image1.png\" width=\"100px\"></p></td></tr></table>",
+        ));
+        assert_eq!(body.images, [("media/image1.png".to_string(), "docx-image1.png".to_string())]);
+        assert_eq!(body.geometry, "letterpaper,top=2.00cm,right=2.00cm,bottom=2.00cm,left=2.00cm");
+    }
+
+    #[test]
+    fn test_convert() {
+        let data = docx(&[
+            ("word/document.xml", DOCUMENT),
+            ("word/_rels/document.xml.rels", RELS),
+            ("word/numbering.xml", NUMBERING),
+            ("word/media/image1.png", "\u{89}PNG"),
+            ("docProps/core.xml", "<cp:coreProperties><dc:title>Q3 Report</dc:title><dc:creator>Ana</dc:creator></cp:coreProperties>"),
+        ]);
+        let out = DocxConverter::convert(&data).unwrap();
+        assert!(out.source.starts_with("\\documentclass[11pt]{article}\n\\usepackage[letterpaper,top=2.00cm"));
+        assert!(out.source.contains("\\title{Q3 Report}\n\\author{Ana}"));
+        assert!(out.source.contains("\\begin{enumerate}\n\\item first\n\n\\begin{itemize}\n\\item nested\n\\end{itemize}\n\\item second\n\\end{enumerate}"), "{}", out.source);
+        assert!(out.source.contains("\\multicolumn{2}{l}{Head} \\\\\n\\midrule\na & \\includegraphics[width=75bp]{docx-image1.png}"));
+        assert_eq!(out.files[0].0, "docx-image1.png");
+        assert!(DocxConverter::convert(b"not a zip").is_err());
+    }
+}
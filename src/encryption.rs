@@ -0,0 +1,267 @@
+//! Encryption at rest for everything written through `Storage`: cached PDFs, blobs (and so
+//! project files and uploads), outputs and shared formats. Objects are sealed with
+//! AES-256-GCM under the key of the tenant whose request wrote them (`X-Tenant-Id`), or the
+//! server key otherwise. Content-addressed objects, which several tenants may write with
+//! the same bytes, always take the server key. The storage key is authenticated too, so
+//! objects cannot be swapped.
+//!
+//! Sealed objects start with a header naming their key, so keys can be added or rotated
+//! without re-encrypting; objects written before encryption was enabled are read as-is.
+//! Dropping a tenant's key makes everything sealed under it unreadable.
+
+use bytes::Bytes;
+use futures_util::future::BoxFuture;
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN};
+use ring::rand::{SecureRandom, SystemRandom};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tracing::info;
+
+use crate::receipt::sha256_hex;
+use crate::settings::Settings;
+use crate::storage::Storage;
+use crate::tenancy::current_tenant;
+
+const MAGIC: &[u8; 4] = b"TXE1";
+
+/// Content-addressed objects: identical content from any tenant lands on the same key, so
+/// sealing it under a tenant key would hand it to whichever tenant wrote last, and dropping
+/// that key would break it for the others.
+const SHARED_PREFIXES: &[&str] = &["blobs/", "outputs/", "formats/", "pdf/"];
+
+struct Key {
+    /// First 16 hex digits of the key's SHA-256, stored in each object's header
+    id: String,
+    key: LessSafeKey,
+}
+
+impl Key {
+    fn from_hex(name: &str, hex_key: &str) -> Result<Self, String> {
+        let bytes = hex::decode(hex_key.trim()).map_err(|e| format!("Encryption key for {} is not valid hex: {}", name, e))?;
+        let unbound = UnboundKey::new(&AES_256_GCM, &bytes).map_err(|_| format!("Encryption key for {} must be 32 bytes (64 hex characters)", name))?;
+        Ok(Self { id: sha256_hex(&bytes)[..16].to_string(), key: LessSafeKey::new(unbound) })
+    }
+}
+
+/// The server key and per-tenant keys.
+#[derive(Default)]
+pub struct Keyring {
+    default: Option<Arc<Key>>,
+    tenants: HashMap<String, Arc<Key>>,
+    by_id: HashMap<String, Arc<Key>>,
+}
+
+impl Keyring {
+    /// Reads keys from STORAGE_ENCRYPTION_KEY, TENANT_ENCRYPTION_KEYS and the output of
+    /// ENCRYPTION_KEYS_COMMAND (a KMS hook printing `tenant=hex` lines, `default=hex` for
+    /// the server key). Later sources override earlier ones.
+    pub fn from_settings(settings: &Settings) -> Result<Self, String> {
+        let mut entries: Vec<(String, String)> = Vec::new();
+        if let Some(key) = &settings.storage_encryption_key {
+            entries.push(("default".to_string(), key.clone()));
+        }
+        entries.extend(settings.tenant_encryption_keys.iter().cloned());
+        if let Some(command) = &settings.encryption_keys_command {
+            entries.extend(run_key_command(command)?);
+        }
+
+        let mut keyring = Self::default();
+        for (name, hex_key) in entries {
+            keyring.add(&name, &hex_key)?;
+        }
+        Ok(keyring)
+    }
+
+    /// A keyring whose only key is `hex_key`, named after the setting it came from; it
+    /// seals secrets kept apart from the storage encryption.
+    pub fn single(setting: &str, hex_key: &str) -> Result<Self, String> {
+        let mut keyring = Self::default();
+        let key = Arc::new(Key::from_hex(setting, hex_key)?);
+        keyring.by_id.insert(key.id.clone(), key.clone());
+        keyring.default = Some(key);
+        Ok(keyring)
+    }
+
+    pub fn add(&mut self, name: &str, hex_key: &str) -> Result<(), String> {
+        let key = Arc::new(Key::from_hex(name, hex_key)?);
+        self.by_id.insert(key.id.clone(), key.clone());
+        if name == "default" {
+            self.default = Some(key);
+        } else {
+            self.tenants.insert(name.to_string(), key);
+        }
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.by_id.is_empty()
+    }
+
+    /// Key for new objects written by `tenant`; `None` stores them unencrypted.
+    fn for_tenant(&self, tenant: Option<&str>) -> Option<&Arc<Key>> {
+        tenant.and_then(|t| self.tenants.get(t)).or(self.default.as_ref())
+    }
+
+    pub(crate) fn seal(&self, storage_key: &str, tenant: Option<&str>, mut data: Vec<u8>) -> Result<Vec<u8>, String> {
+        let Some(key) = self.for_tenant(tenant) else { return Ok(data) };
+        let mut nonce = [0u8; NONCE_LEN];
+        SystemRandom::new().fill(&mut nonce).map_err(|_| "No randomness for encryption".to_string())?;
+        key.key
+            .seal_in_place_append_tag(Nonce::assume_unique_for_key(nonce), Aad::from(storage_key.as_bytes()), &mut data)
+            .map_err(|_| format!("Failed to encrypt {}", storage_key))?;
+
+        let mut sealed = Vec::with_capacity(MAGIC.len() + 1 + key.id.len() + NONCE_LEN + data.len());
+        sealed.extend_from_slice(MAGIC);
+        sealed.push(key.id.len() as u8);
+        sealed.extend_from_slice(key.id.as_bytes());
+        sealed.extend_from_slice(&nonce);
+        sealed.extend_from_slice(&data);
+        Ok(sealed)
+    }
+
+    pub(crate) fn open(&self, storage_key: &str, stored: Bytes) -> Result<Bytes, String> {
+        let Some(rest) = stored.strip_prefix(MAGIC.as_slice()) else { return Ok(stored) };
+        let corrupt = || format!("Encrypted object {} is corrupt", storage_key);
+        let (&id_len, rest) = rest.split_first().ok_or_else(corrupt)?;
+        let id = rest.get(..id_len as usize).ok_or_else(corrupt)?;
+        let nonce = rest.get(id_len as usize..id_len as usize + NONCE_LEN).ok_or_else(corrupt)?;
+        let key = self
+            .by_id
+            .get(std::str::from_utf8(id).map_err(|_| corrupt())?)
+            .ok_or_else(|| format!("No key to decrypt {} (key {})", storage_key, String::from_utf8_lossy(id)))?;
+
+        let mut data = rest[id_len as usize + NONCE_LEN..].to_vec();
+        let nonce = Nonce::try_assume_unique_for_key(nonce).map_err(|_| corrupt())?;
+        let plain_len = key
+            .key
+            .open_in_place(nonce, Aad::from(storage_key.as_bytes()), &mut data)
+            .map_err(|_| format!("Failed to decrypt {}: wrong key or tampered data", storage_key))?
+            .len();
+        data.truncate(plain_len);
+        Ok(Bytes::from(data))
+    }
+}
+
+fn run_key_command(command: &str) -> Result<Vec<(String, String)>, String> {
+    let output = std::process::Command::new("sh")
+        .args(["-c", command])
+        .output()
+        .map_err(|e| format!("ENCRYPTION_KEYS_COMMAND failed to start: {}", e))?;
+    if !output.status.success() {
+        return Err(format!("ENCRYPTION_KEYS_COMMAND failed: {}", String::from_utf8_lossy(&output.stderr).trim()));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| line.trim().split_once('='))
+        .map(|(name, key)| (name.trim().to_string(), key.trim().to_string()))
+        .collect())
+}
+
+/// Wraps `storage` so objects are encrypted, when any key is configured.
+pub fn wrap(storage: Arc<dyn Storage>, settings: &Settings) -> Result<Arc<dyn Storage>, String> {
+    let keyring = Keyring::from_settings(settings)?;
+    if keyring.is_empty() {
+        return Ok(storage);
+    }
+    info!(
+        "🔐 Encrypting stored objects ({} tenant key(s){})",
+        keyring.tenants.len(),
+        if keyring.default.is_some() { ", server key" } else { "; shared objects and other tenants unencrypted" }
+    );
+    Ok(Arc::new(EncryptedStorage { inner: storage, keyring: Arc::new(keyring) }))
+}
+
+pub struct EncryptedStorage {
+    inner: Arc<dyn Storage>,
+    keyring: Arc<Keyring>,
+}
+
+impl Storage for EncryptedStorage {
+    fn name(&self) -> &'static str {
+        self.inner.name()
+    }
+
+    fn get<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<Option<Bytes>, String>> {
+        Box::pin(async move {
+            match self.inner.get(key).await? {
+                Some(stored) => self.keyring.open(key, stored).map(Some),
+                None => Ok(None),
+            }
+        })
+    }
+
+    fn put<'a>(&'a self, key: &'a str, data: Vec<u8>) -> BoxFuture<'a, Result<(), String>> {
+        Box::pin(async move {
+            let tenant = current_tenant().filter(|_| !SHARED_PREFIXES.iter().any(|prefix| key.starts_with(prefix)));
+            let sealed = self.keyring.seal(key, tenant.as_deref(), data)?;
+            self.inner.put(key, sealed).await
+        })
+    }
+
+    fn head<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<Option<u64>, String>> {
+        self.inner.head(key)
+    }
+
+    fn delete<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<(), String>> {
+        self.inner.delete(key)
+    }
+
+    fn list<'a>(&'a self, prefix: &'a str) -> BoxFuture<'a, Result<Vec<String>, String>> {
+        self.inner.list(prefix)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+    use crate::tenancy::TENANT;
+
+    const SERVER_KEY: &str = "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f";
+    const ACME_KEY: &str = "1f1e1d1c1b1a191817161514131211100f0e0d0c0b0a09080706050403020100";
+
+    fn storage(keys: &[(&str, &str)]) -> (Arc<MemoryStorage>, EncryptedStorage) {
+        let mut keyring = Keyring::default();
+        for (name, key) in keys {
+            keyring.add(name, key).unwrap();
+        }
+        let inner = Arc::new(MemoryStorage::new());
+        (inner.clone(), EncryptedStorage { inner, keyring: Arc::new(keyring) })
+    }
+
+    #[tokio::test]
+    async fn test_tenant_keys() {
+        let (inner, encrypted) = storage(&[("default", SERVER_KEY), ("acme", ACME_KEY)]);
+        encrypted.put("schedules/a", b"server secret".to_vec()).await.unwrap();
+        TENANT.scope(Some("acme".to_string()), encrypted.put("schedules/b", b"acme secret".to_vec())).await.unwrap();
+
+        let raw_a = inner.get("schedules/a").await.unwrap().unwrap();
+        let raw_b = inner.get("schedules/b").await.unwrap().unwrap();
+        assert!(raw_a.starts_with(MAGIC) && !raw_a.windows(6).any(|w| w == b"secret"));
+        assert_ne!(raw_a[5..21], raw_b[5..21]);
+        assert_eq!(encrypted.get("schedules/a").await.unwrap().unwrap(), "server secret");
+        assert_eq!(encrypted.get("schedules/b").await.unwrap().unwrap(), "acme secret");
+
+        // Content-addressed objects are shared between tenants and keep the server key
+        TENANT.scope(Some("acme".to_string()), encrypted.put("blobs/shared", b"shared".to_vec())).await.unwrap();
+        assert_eq!(inner.get("blobs/shared").await.unwrap().unwrap()[5..21], raw_a[5..21]);
+
+        // Swapping objects between keys, or losing the tenant key, fails to decrypt
+        inner.put("schedules/c", raw_a.to_vec()).await.unwrap();
+        assert!(encrypted.get("schedules/c").await.is_err());
+        let (_, without_acme) = storage(&[("default", SERVER_KEY)]);
+        without_acme.inner.put("schedules/b", raw_b.to_vec()).await.unwrap();
+        assert!(without_acme.get("schedules/b").await.unwrap_err().contains("No key"));
+    }
+
+    #[tokio::test]
+    async fn test_plaintext_objects_still_readable() {
+        let (inner, encrypted) = storage(&[("acme", ACME_KEY)]);
+        inner.put("outputs/old", b"%PDF-1.5".to_vec()).await.unwrap();
+        assert_eq!(encrypted.get("outputs/old").await.unwrap().unwrap(), "%PDF-1.5");
+        // Without a server key, tenants without a key are stored unencrypted
+        encrypted.put("outputs/new", b"%PDF-1.7".to_vec()).await.unwrap();
+        assert_eq!(inner.get("outputs/new").await.unwrap().unwrap(), "%PDF-1.7");
+        assert!(Keyring::default().add("x", "abcd").is_err());
+    }
+}
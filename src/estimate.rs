@@ -0,0 +1,215 @@
+//! Preflight estimates of compile time and PDF size, made from the sources and asset sizes
+//! alone. Heuristics give a first guess; once a preamble has been compiled, the ratio of
+//! actual to predicted figures for it calibrates later estimates.
+
+use regex::Regex;
+use std::collections::HashMap;
+
+use crate::bib::strip_comment;
+use crate::models::EstimateResponse;
+
+/// Format-build cost of packages known to be slow to load, in milliseconds.
+/// Anything else counts as `DEFAULT_PACKAGE_MS`.
+const PACKAGE_MS: &[(&str, u64)] = &[
+    ("tikz", 450), ("pgfplots", 900), ("circuitikz", 600), ("tcolorbox", 500), ("forest", 300),
+    ("fontspec", 900), ("unicode-math", 1200), ("polyglossia", 300), ("babel", 120),
+    ("biblatex", 350), ("hyperref", 150), ("siunitx", 250), ("microtype", 200), ("chemfig", 250),
+    ("listings", 90),
+];
+const DEFAULT_PACKAGE_MS: u64 = 30;
+
+/// Engine start-up with a warm format.
+const BASE_MS: u64 = 250;
+/// Dumping a new format, on top of its packages.
+const FORMAT_MS: u64 = 600;
+/// A warm format has its packages preloaded; this share of their cost remains.
+const WARM_PACKAGE_SHARE: f64 = 0.2;
+const PAGE_MS: f64 = 35.0;
+const TIKZ_MS: f64 = 120.0;
+const PLOT_MS: f64 = 300.0;
+const IMAGE_MS: f64 = 20.0;
+const IMAGE_MS_PER_MB: f64 = 35.0;
+
+const BASE_BYTES: f64 = 12_000.0;
+/// Subsets of the default Type 1 fonts; OpenType fonts loaded by fontspec are larger.
+const FONT_BYTES: f64 = 40_000.0;
+const OPENTYPE_FONT_BYTES: f64 = 100_000.0;
+const PAGE_BYTES: f64 = 3_500.0;
+const TIKZ_BYTES: f64 = 6_000.0;
+
+/// Extensions of assets embedded in the PDF.
+const IMAGE_EXTENSIONS: &[&str] = &["png", "jpg", "jpeg", "pdf", "eps", "gif"];
+
+/// Words of running text per page, by layout.
+const WORDS_PER_PAGE: f64 = 450.0;
+const WORDS_PER_TWOCOLUMN_PAGE: f64 = 700.0;
+
+/// Weight of a new compile in the calibration ratios.
+const CALIBRATION_WEIGHT: f64 = 0.3;
+
+/// Calibration learned from earlier compiles of one preamble.
+#[derive(Clone, Debug, PartialEq)]
+pub struct History {
+    pub compiles: u64,
+    /// Actual over predicted compile time, averaged with recent compiles weighing most
+    pub time_ratio: f64,
+    pub size_ratio: f64,
+}
+
+impl History {
+    /// Folds in a compile that took `compile_time_ms` and produced `output_bytes`,
+    /// against the heuristic `predicted` for it.
+    pub fn record(history: Option<&History>, predicted: &EstimateResponse, compile_time_ms: u64, output_bytes: usize) -> History {
+        let time_ratio = compile_time_ms as f64 / predicted.compile_time_ms.max(1) as f64;
+        let size_ratio = output_bytes as f64 / predicted.output_bytes.max(1) as f64;
+        match history {
+            None => History { compiles: 1, time_ratio, size_ratio },
+            Some(h) => History {
+                compiles: h.compiles + 1,
+                time_ratio: h.time_ratio + (time_ratio - h.time_ratio) * CALIBRATION_WEIGHT,
+                size_ratio: h.size_ratio + (size_ratio - h.size_ratio) * CALIBRATION_WEIGHT,
+            },
+        }
+    }
+}
+
+pub struct Estimator;
+
+impl Estimator {
+    /// Estimates a compile of `main` from the project's `.tex` sources and the sizes of its
+    /// other files. `warm_format` tells whether the preamble's format is already built.
+    pub fn estimate(sources: &HashMap<String, String>, main: &str, assets: &[(String, u64)], warm_format: bool, history: Option<&History>) -> EstimateResponse {
+        let package_re = Regex::new(r"\\(?:usepackage|RequirePackage)\s*(?:\[[^\]]*\])?\s*\{([^}]*)\}").unwrap();
+        let class_re = Regex::new(r"\\documentclass\s*(?:\[([^\]]*)\])?\s*\{([^}]*)\}").unwrap();
+
+        let tex: Vec<String> = sources
+            .iter()
+            .filter(|(name, _)| name.ends_with(".tex"))
+            .map(|(_, content)| content.lines().map(strip_comment).collect::<Vec<_>>().join("\n"))
+            .collect();
+        let main_source = sources.get(main).map(|s| s.lines().map(strip_comment).collect::<Vec<_>>().join("\n")).unwrap_or_default();
+        let count = |pattern: &str| tex.iter().map(|t| t.matches(pattern).count()).sum::<usize>();
+
+        let (class, class_options) = class_re
+            .captures(&main_source)
+            .map(|c| (c[2].trim().to_string(), c.get(1).map_or("", |o| o.as_str()).to_string()))
+            .unwrap_or_default();
+        let mut packages: Vec<String> = tex
+            .iter()
+            .flat_map(|t| package_re.captures_iter(t).flat_map(|c| c[1].split(',').map(|p| p.trim().to_string()).collect::<Vec<_>>()))
+            .filter(|p| !p.is_empty())
+            .collect();
+        packages.sort();
+        packages.dedup();
+
+        // Pages: beamer counts frames, other classes running text plus floats and breaks
+        let words = tex.iter().map(|t| prose_words(t)).sum::<usize>();
+        let figures = count("\\includegraphics");
+        let tikz = count("\\begin{tikzpicture}");
+        let plots = count("\\begin{axis}");
+        let pages = if class == "beamer" {
+            count("\\begin{frame}") + count("\\frame{")
+        } else {
+            let words_per_page = if class_options.contains("twocolumn") { WORDS_PER_TWOCOLUMN_PAGE } else { WORDS_PER_PAGE };
+            let breaks = count("\\newpage") + count("\\clearpage") + count("\\chapter") + count("\\part{");
+            let floats = (figures + tikz) as f64 * 0.3 + count("\\begin{table") as f64 * 0.2;
+            let front = if count("\\tableofcontents") > 0 { 1.0 } else { 0.0 };
+            (words as f64 / words_per_page + floats + breaks as f64 * 0.5 + front).ceil() as usize
+        }
+        .max(1);
+
+        let images: Vec<u64> = assets
+            .iter()
+            .filter(|(name, _)| name.rsplit_once('.').is_some_and(|(_, ext)| IMAGE_EXTENSIONS.contains(&ext.to_ascii_lowercase().as_str())))
+            .map(|(_, size)| *size)
+            .collect();
+        let image_bytes: u64 = images.iter().sum();
+
+        let package_ms: u64 = packages
+            .iter()
+            .map(|p| PACKAGE_MS.iter().find(|(name, _)| name == p).map_or(DEFAULT_PACKAGE_MS, |(_, ms)| *ms))
+            .sum::<u64>()
+            + if class == "beamer" { 700 } else { 0 };
+        let preamble_ms = if warm_format { (package_ms as f64 * WARM_PACKAGE_SHARE) as u64 } else { FORMAT_MS + package_ms };
+        // Cross-references, citations and tables of contents need a second pass
+        let passes = if ["\\ref{", "\\cite", "\\tableofcontents", "\\pageref{", "\\autoref{"].iter().any(|p| count(p) > 0) { 2 } else { 1 };
+        let body_ms = (pages as f64 * PAGE_MS + tikz as f64 * TIKZ_MS + plots as f64 * PLOT_MS) * passes as f64;
+        let images_ms = images.iter().map(|size| IMAGE_MS + *size as f64 / 1_048_576.0 * IMAGE_MS_PER_MB).sum::<f64>();
+        let heuristic_ms = BASE_MS as f64 + preamble_ms as f64 + body_ms + images_ms;
+
+        let opentype = packages.iter().any(|p| p == "fontspec" || p == "unicode-math");
+        let heuristic_bytes = BASE_BYTES
+            + if opentype { OPENTYPE_FONT_BYTES } else { FONT_BYTES }
+            + pages as f64 * PAGE_BYTES
+            + (tikz + plots) as f64 * TIKZ_BYTES
+            + image_bytes as f64;
+
+        let (time_ratio, size_ratio) = history.map_or((1.0, 1.0), |h| (h.time_ratio, h.size_ratio));
+        EstimateResponse {
+            compile_time_ms: (heuristic_ms * time_ratio).round() as u64,
+            output_bytes: (heuristic_bytes * size_ratio).round() as u64,
+            pages,
+            basis: if history.is_some() { "history" } else { "heuristic" }.to_string(),
+            history_compiles: history.map_or(0, |h| h.compiles),
+            warm_format,
+            passes,
+            words,
+            images: images.len(),
+            image_bytes,
+            packages,
+        }
+    }
+}
+
+/// Words of running text, skipping math and command names.
+fn prose_words(source: &str) -> usize {
+    let body = source.find("\\begin{document}").map_or(source, |pos| &source[pos..]);
+    let math = Regex::new(r"\$\$[^$]*\$\$|\$[^$]*\$").unwrap();
+    let command = Regex::new(r"\\[a-zA-Z@]+\*?|\\.").unwrap();
+    let prose = math.replace_all(body, " ");
+    let prose = command.replace_all(&prose, " ");
+    prose.split_whitespace().filter(|w| w.chars().any(char::is_alphanumeric)).count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn project(main: &str) -> HashMap<String, String> {
+        HashMap::from([("main.tex".to_string(), main.to_string())])
+    }
+
+    #[test]
+    fn test_heuristic_estimate() {
+        let prose = "word ".repeat(2000);
+        let sources = project(&format!(
+            "\\documentclass{{article}}\n\\usepackage{{tikz}}\n\\usepackage{{amsmath, hyperref}} % \\usepackage{{pgfplots}}\n\\begin{{document}}\n{}\n\\includegraphics{{fig.png}}\nSee \\ref{{fig}}.\n\\end{{document}}\n",
+            prose
+        ));
+        let assets = vec![("fig.png".to_string(), 2_097_152), ("data.csv".to_string(), 500)];
+        let cold = Estimator::estimate(&sources, "main.tex", &assets, false, None);
+        assert_eq!(cold.packages, ["amsmath", "hyperref", "tikz"]);
+        assert_eq!((cold.pages, cold.passes, cold.images, cold.image_bytes), (5, 2, 1, 2_097_152));
+        assert_eq!(cold.basis, "heuristic");
+        assert!(cold.output_bytes > 2_097_152);
+
+        let warm = Estimator::estimate(&sources, "main.tex", &assets, true, None);
+        assert!(warm.compile_time_ms + 1000 < cold.compile_time_ms);
+    }
+
+    #[test]
+    fn test_history_calibrates() {
+        let sources = project("\\documentclass{beamer}\n\\begin{document}\n\\begin{frame}A\\end{frame}\n\\begin{frame}B\\end{frame}\n\\end{document}\n");
+        let predicted = Estimator::estimate(&sources, "main.tex", &[], true, None);
+        assert_eq!(predicted.pages, 2);
+
+        let history = History::record(None, &predicted, predicted.compile_time_ms * 2, predicted.output_bytes as usize);
+        let calibrated = Estimator::estimate(&sources, "main.tex", &[], true, Some(&history));
+        assert_eq!(calibrated.compile_time_ms, predicted.compile_time_ms * 2);
+        assert_eq!(calibrated.output_bytes, predicted.output_bytes);
+        assert_eq!((calibrated.basis.as_str(), calibrated.history_compiles), ("history", 1));
+
+        let history = History::record(Some(&history), &predicted, predicted.compile_time_ms, predicted.output_bytes as usize);
+        assert!((history.time_ratio - 1.7).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,512 @@
+//! Randomized exam variants from a question bank. Everything random is drawn from a
+//! generator seeded by the request, so the same seed reproduces the same variants.
+
+use regex::Regex;
+use std::collections::BTreeMap;
+
+use crate::generate::Branding;
+use crate::latex::{escape, fill_template};
+use crate::models::{BrandingProfile, ExamParam, ExamQuestion, ExamRequest};
+
+const EXAM_TEMPLATE: &str = include_str!("../templates/exam.tex");
+
+/// Variants are labelled with a single letter.
+pub const MAX_EXAM_VARIANTS: usize = 26;
+
+/// Upper bound on the distinct values of a `min`/`max`/`step` parameter.
+const MAX_PARAM_STEPS: f64 = 1_000_000.0;
+
+/// One variant: the exam and its answer key, as LaTeX.
+#[derive(Debug)]
+pub struct ExamVariant {
+    pub label: char,
+    pub exam: String,
+    pub key: String,
+}
+
+impl ExamVariant {
+    pub fn exam_file(&self, extension: &str) -> String {
+        format!("exam-{}.{}", self.label, extension)
+    }
+
+    pub fn key_file(&self, extension: &str) -> String {
+        format!("exam-{}-key.{}", self.label, extension)
+    }
+}
+
+pub struct ExamGenerator;
+
+impl ExamGenerator {
+    pub fn variants(req: &ExamRequest, seed: u64, branding: Option<&BrandingProfile>) -> Result<Vec<ExamVariant>, String> {
+        if req.questions.is_empty() {
+            return Err("The question bank is empty".to_string());
+        }
+        let variants = req.variants.unwrap_or(1);
+        if variants == 0 || variants > MAX_EXAM_VARIANTS {
+            return Err(format!("Provide between 1 and {} variants", MAX_EXAM_VARIANTS));
+        }
+        let count = req.count.unwrap_or(req.questions.len());
+        if count == 0 || count > req.questions.len() {
+            return Err(format!("count must be between 1 and the {} questions in the bank", req.questions.len()));
+        }
+        for (i, question) in req.questions.iter().enumerate() {
+            validate(question).map_err(|e| format!("Question {}: {}", i + 1, e))?;
+        }
+
+        (0..variants)
+            .map(|v| {
+                let label = (b'A' + v as u8) as char;
+                // Each variant has its own stream, so adding variants leaves earlier ones unchanged
+                let mut rng = Rng::new(seed, v as u64);
+                let mut picked: Vec<usize> = (0..req.questions.len()).collect();
+                rng.shuffle(&mut picked);
+                picked.truncate(count);
+                if !req.shuffle.unwrap_or(true) {
+                    picked.sort_unstable();
+                }
+
+                let mut exam = Vec::with_capacity(count);
+                let mut key = Vec::with_capacity(count);
+                for index in picked {
+                    let question = &req.questions[index];
+                    let drawn = draw(question, &mut rng, req.shuffle_choices.unwrap_or(true))
+                        .map_err(|e| format!("Question {}: {}", index + 1, e))?;
+                    exam.push(drawn.item(false));
+                    key.push(drawn.item(true));
+                }
+                Ok(ExamVariant {
+                    label,
+                    exam: Self::document(req, label, &exam, false, branding),
+                    key: Self::document(req, label, &key, true, branding),
+                })
+            })
+            .collect()
+    }
+
+    fn document(req: &ExamRequest, label: char, questions: &[String], key: bool, branding: Option<&BrandingProfile>) -> String {
+        let points: f64 = req.questions.iter().filter_map(|q| q.points).sum();
+        let mut header = Vec::new();
+        if key {
+            header.push("\\textbf{Answer key}".to_string());
+        } else {
+            header.push("Name: \\rule{7cm}{0.4pt}".to_string());
+        }
+        if let Some(instructions) = req.instructions.as_deref().filter(|i| !i.trim().is_empty()) {
+            header.push(escape(instructions.trim()));
+        }
+        if points > 0.0 && req.count.is_none_or(|c| c == req.questions.len()) {
+            header.push(format!("Total: {} points", number(points, 2)));
+        }
+        let subtitle = req.subtitle.as_deref().map(|s| format!("{}\\par", escape(s))).unwrap_or_default();
+        fill_template(EXAM_TEMPLATE, &[
+            ("BRANDING", &Branding::preamble(branding)),
+            ("LOGO", &Branding::logo(branding, "1.2cm")),
+            ("TITLE", &escape(&req.title)),
+            ("VARIANT", &format!("Variant {}", label)),
+            ("SUBTITLE", &subtitle),
+            ("HEADER", &header.join("\n\n")),
+            ("QUESTIONS", &questions.join("\n")),
+        ])
+    }
+}
+
+/// A question with its parameters substituted and its choices in variant order.
+struct DrawnQuestion {
+    text: String,
+    /// (choice, is correct)
+    choices: Vec<(String, bool)>,
+    answer: Option<String>,
+    points: Option<f64>,
+}
+
+impl DrawnQuestion {
+    fn item(&self, key: bool) -> String {
+        let points = self.points.map(|p| format!("\\hfill({} pt{})", number(p, 2), if p == 1.0 { "" } else { "s" })).unwrap_or_default();
+        let mut out = format!("\\item {}{}\n", self.text, points);
+        if !self.choices.is_empty() {
+            out.push_str("\\begin{enumerate}\n");
+            for (choice, correct) in &self.choices {
+                if key && *correct {
+                    out.push_str(&format!("\\item \\textbf{{{}}}~$\\checkmark$\n", choice));
+                } else {
+                    out.push_str(&format!("\\item {}\n", choice));
+                }
+            }
+            out.push_str("\\end{enumerate}\n");
+        }
+        if key {
+            if let Some(answer) = &self.answer {
+                out.push_str(&format!("\n\\textbf{{Answer:}} {}\n", answer));
+            }
+        } else if self.choices.is_empty() {
+            out.push_str("\\vspace{3cm}\n");
+        }
+        out
+    }
+}
+
+fn validate(question: &ExamQuestion) -> Result<(), String> {
+    if question.text.trim().is_empty() {
+        return Err("text is empty".to_string());
+    }
+    match question.correct {
+        Some(c) if c >= question.choices.len() => return Err(format!("correct choice {} is out of range", c)),
+        None if !question.choices.is_empty() => return Err("multiple choice questions need \"correct\"".to_string()),
+        _ => {}
+    }
+    for (name, param) in &question.params {
+        if !param.values.is_empty() {
+            continue;
+        }
+        let (Some(min), Some(max)) = (param.min, param.max) else {
+            return Err(format!("parameter '{}' needs values or min and max", name));
+        };
+        let step = param.step.unwrap_or(1.0);
+        if !(min.is_finite() && max.is_finite() && step.is_finite()) || min > max || step <= 0.0 || (max - min) / step > MAX_PARAM_STEPS {
+            return Err(format!("parameter '{}' has an invalid range", name));
+        }
+    }
+    Ok(())
+}
+
+/// Draws parameter values and substitutes them into the question's text, choices and answer.
+fn draw(question: &ExamQuestion, rng: &mut Rng, shuffle_choices: bool) -> Result<DrawnQuestion, String> {
+    let mut values: BTreeMap<&str, (String, Option<f64>)> = BTreeMap::new();
+    for (name, param) in &question.params {
+        values.insert(name, draw_param(param, rng));
+    }
+    let fill = |text: &str| substitute(text, &values);
+
+    let mut choices = question.choices.iter().enumerate()
+        .map(|(i, c)| Ok((fill(c)?, Some(i) == question.correct)))
+        .collect::<Result<Vec<_>, String>>()?;
+    if shuffle_choices {
+        rng.shuffle(&mut choices);
+    }
+    Ok(DrawnQuestion {
+        text: fill(&question.text)?,
+        choices,
+        answer: question.answer.as_deref().map(fill).transpose()?,
+        points: question.points,
+    })
+}
+
+/// The printed value and, for numbers, the value expressions compute with.
+fn draw_param(param: &ExamParam, rng: &mut Rng) -> (String, Option<f64>) {
+    if !param.values.is_empty() {
+        return match &param.values[rng.below(param.values.len() as u64) as usize] {
+            serde_json::Value::String(s) => (s.clone(), s.trim().parse().ok()),
+            serde_json::Value::Number(n) => (n.to_string(), n.as_f64()),
+            other => (other.to_string(), None),
+        };
+    }
+    // validate() guarantees min and max
+    let (min, max, step) = (param.min.unwrap_or(0.0), param.max.unwrap_or(0.0), param.step.unwrap_or(1.0));
+    let steps = ((max - min) / step + 1e-9).floor() as u64;
+    let digits = decimals(step);
+    let value = round(min + rng.below(steps + 1) as f64 * step, digits);
+    (number(value, digits), Some(value))
+}
+
+/// Replaces `<<name>>` and `<<= expression : digits>>` placeholders.
+fn substitute(text: &str, values: &BTreeMap<&str, (String, Option<f64>)>) -> Result<String, String> {
+    let placeholder = Regex::new(r"<<\s*(=)?\s*([^<>]+?)\s*>>").unwrap();
+    let mut out = String::with_capacity(text.len());
+    let mut last = 0;
+    for caps in placeholder.captures_iter(text) {
+        let whole = caps.get(0).unwrap();
+        out.push_str(&text[last..whole.start()]);
+        last = whole.end();
+        let body = &caps[2];
+        if caps.get(1).is_none() {
+            let (printed, _) = values.get(body).ok_or_else(|| format!("unknown parameter '{}'", body))?;
+            out.push_str(printed);
+            continue;
+        }
+        let (expression, digits) = match body.rsplit_once(':') {
+            Some((expression, digits)) => (expression, Some(digits.trim().parse::<usize>().map_err(|_| format!("invalid digits in '{}'", body))?.min(10))),
+            None => (body, None),
+        };
+        let value = Expr { tokens: tokenize(expression)?, pos: 0, values }.evaluate()
+            .map_err(|e| format!("cannot evaluate '{}': {}", expression.trim(), e))?;
+        out.push_str(&number(value, digits.unwrap_or(4)));
+    }
+    out.push_str(&text[last..]);
+    Ok(out)
+}
+
+/// Prints up to `digits` decimals without trailing zeros.
+fn number(value: f64, digits: usize) -> String {
+    let fixed = format!("{:.*}", digits, value);
+    let trimmed = if fixed.contains('.') { fixed.trim_end_matches('0').trim_end_matches('.') } else { &fixed };
+    if trimmed == "-0" { "0".to_string() } else { trimmed.to_string() }
+}
+
+fn round(value: f64, digits: usize) -> f64 {
+    let factor = 10f64.powi(digits as i32);
+    (value * factor).round() / factor
+}
+
+/// Decimal places of a step such as 0.25.
+fn decimals(step: f64) -> usize {
+    (0..10).find(|d| (step * 10f64.powi(*d as i32)).fract().abs() < 1e-9).unwrap_or(10)
+}
+
+// ============================================================================
+// Expressions
+// ============================================================================
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(f64),
+    Name(String),
+    Symbol(char),
+}
+
+fn tokenize(expression: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = expression.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c.is_ascii_digit() || c == '.' {
+            let mut literal = String::new();
+            while let Some(&d) = chars.peek().filter(|d| d.is_ascii_digit() || **d == '.') {
+                literal.push(d);
+                chars.next();
+            }
+            tokens.push(Token::Number(literal.parse().map_err(|_| format!("invalid number '{}'", literal))?));
+        } else if c.is_alphabetic() || c == '_' {
+            let mut name = String::new();
+            while let Some(&d) = chars.peek().filter(|d| d.is_alphanumeric() || **d == '_') {
+                name.push(d);
+                chars.next();
+            }
+            tokens.push(Token::Name(name));
+        } else if "+-*/^(),".contains(c) {
+            tokens.push(Token::Symbol(c));
+            chars.next();
+        } else {
+            return Err(format!("unexpected '{}'", c));
+        }
+    }
+    Ok(tokens)
+}
+
+/// Recursive-descent evaluator: sums of products of powers of (signed) atoms.
+struct Expr<'a> {
+    tokens: Vec<Token>,
+    pos: usize,
+    values: &'a BTreeMap<&'a str, (String, Option<f64>)>,
+}
+
+impl Expr<'_> {
+    fn evaluate(mut self) -> Result<f64, String> {
+        let value = self.sum()?;
+        if self.pos < self.tokens.len() {
+            return Err("unexpected trailing input".to_string());
+        }
+        if value.is_finite() { Ok(value) } else { Err("the result is not a finite number".to_string()) }
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn eat(&mut self, symbol: char) -> bool {
+        if self.peek() == Some(&Token::Symbol(symbol)) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn sum(&mut self) -> Result<f64, String> {
+        let mut value = self.product()?;
+        loop {
+            if self.eat('+') {
+                value += self.product()?;
+            } else if self.eat('-') {
+                value -= self.product()?;
+            } else {
+                return Ok(value);
+            }
+        }
+    }
+
+    fn product(&mut self) -> Result<f64, String> {
+        let mut value = self.unary()?;
+        loop {
+            if self.eat('*') {
+                value *= self.unary()?;
+            } else if self.eat('/') {
+                let divisor = self.unary()?;
+                if divisor == 0.0 {
+                    return Err("division by zero".to_string());
+                }
+                value /= divisor;
+            } else {
+                return Ok(value);
+            }
+        }
+    }
+
+    fn unary(&mut self) -> Result<f64, String> {
+        if self.eat('-') {
+            return Ok(-self.unary()?);
+        }
+        self.eat('+');
+        let base = self.atom()?;
+        // Right-associative, and binds tighter than unary minus: -2^2 = -4
+        if self.eat('^') {
+            return Ok(base.powf(self.unary()?));
+        }
+        Ok(base)
+    }
+
+    fn atom(&mut self) -> Result<f64, String> {
+        match self.tokens.get(self.pos).cloned() {
+            Some(Token::Number(n)) => {
+                self.pos += 1;
+                Ok(n)
+            }
+            Some(Token::Symbol('(')) => {
+                self.pos += 1;
+                let value = self.sum()?;
+                if !self.eat(')') {
+                    return Err("missing ')'".to_string());
+                }
+                Ok(value)
+            }
+            Some(Token::Name(name)) => {
+                self.pos += 1;
+                if !self.eat('(') {
+                    return match self.values.get(name.as_str()) {
+                        Some((_, Some(value))) => Ok(*value),
+                        Some(_) => Err(format!("parameter '{}' is not numeric", name)),
+                        None => Err(format!("unknown parameter '{}'", name)),
+                    };
+                }
+                let mut args = vec![self.sum()?];
+                while self.eat(',') {
+                    args.push(self.sum()?);
+                }
+                if !self.eat(')') {
+                    return Err("missing ')'".to_string());
+                }
+                match (name.as_str(), args.as_slice()) {
+                    ("sqrt", [x]) if *x >= 0.0 => Ok(x.sqrt()),
+                    ("sqrt", [_]) => Err("square root of a negative number".to_string()),
+                    ("abs", [x]) => Ok(x.abs()),
+                    ("round", [x]) => Ok(x.round()),
+                    ("round", [x, digits]) => Ok(round(*x, digits.clamp(0.0, 10.0) as usize)),
+                    ("min", [first, rest @ ..]) => Ok(rest.iter().fold(*first, |a, b| a.min(*b))),
+                    ("max", [first, rest @ ..]) => Ok(rest.iter().fold(*first, |a, b| a.max(*b))),
+                    _ => Err(format!("unknown function {}() with {} argument(s)", name, args.len())),
+                }
+            }
+            _ => Err("unexpected end of expression".to_string()),
+        }
+    }
+}
+
+// ============================================================================
+// Random numbers
+// ============================================================================
+
+/// SplitMix64: tiny, and stable across releases so seeds stay reproducible.
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64, stream: u64) -> Self {
+        let mut rng = Self(seed ^ stream.wrapping_mul(0xA076_1D64_78BD_642F));
+        rng.next();
+        rng
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform in `0..n`.
+    fn below(&mut self, n: u64) -> u64 {
+        self.next() % n.max(1)
+    }
+
+    /// Fisher-Yates.
+    fn shuffle<T>(&mut self, items: &mut [T]) {
+        for i in (1..items.len()).rev() {
+            let j = self.below(i as u64 + 1) as usize;
+            items.swap(i, j);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request() -> ExamRequest {
+        serde_json::from_value(serde_json::json!({
+            "title": "Algebra & Co",
+            "variants": 3,
+            "questions": [
+                { "text": "Compute $<<a>> \\times <<b>>$.", "params": { "a": { "min": 2, "max": 9 }, "b": { "values": [10, 20] } },
+                  "answer": "$<<= a * b>>$", "points": 2 },
+                { "text": "Capital of <<country>>?", "params": { "country": { "values": ["France"] } },
+                  "choices": ["Paris", "Rome", "Madrid"], "correct": 0, "points": 1 },
+                { "text": "Half of <<x>>", "params": { "x": { "min": 0.5, "max": 2, "step": 0.25 } }, "answer": "<<= x / 2 : 3>>" }
+            ]
+        })).unwrap()
+    }
+
+    #[test]
+    fn test_variants_are_reproducible() {
+        let req = request();
+        let first = ExamGenerator::variants(&req, 42, None).unwrap();
+        let again = ExamGenerator::variants(&req, 42, None).unwrap();
+        assert_eq!(first.len(), 3);
+        assert_eq!(first.iter().map(|v| v.label).collect::<String>(), "ABC");
+        assert!(first.iter().zip(&again).all(|(a, b)| a.exam == b.exam && a.key == b.key));
+        let other = ExamGenerator::variants(&req, 43, None).unwrap();
+        assert!(first.iter().zip(&other).any(|(a, b)| a.exam != b.exam));
+
+        let exam = &first[0].exam;
+        assert!(exam.contains("Algebra \\& Co") && exam.contains("Variant A") && exam.contains("Total: 3 points"));
+        assert!(exam.contains("\\item Capital of France?\\hfill(1 pt)"));
+        assert!(!exam.contains("checkmark") && !exam.contains("Answer:"));
+        let key = &first[0].key;
+        assert!(key.contains("\\item \\textbf{Paris}~$\\checkmark$"));
+
+        let product = Regex::new(r"Compute \$(\d) \\times (\d+)\$.\\hfill\(2 pts\)\n\n\\textbf\{Answer:\} \$(\d+)\$").unwrap();
+        let caps = product.captures(key).unwrap();
+        assert_eq!(caps[1].parse::<u32>().unwrap() * caps[2].parse::<u32>().unwrap(), caps[3].parse::<u32>().unwrap());
+    }
+
+    #[test]
+    fn test_substitute() {
+        let values: BTreeMap<&str, (String, Option<f64>)> = BTreeMap::from([("a", ("3".to_string(), Some(3.0))), ("name", ("Ana".to_string(), None))]);
+        assert_eq!(substitute("<<name>>: <<= (a + 1)^2 / 3 : 2>> <<= -2^2 + sqrt(16) * max(1, a)>>", &values).unwrap(), "Ana: 5.33 8");
+        assert!(substitute("<<= a / (a - 3)>>", &values).unwrap_err().contains("division by zero"));
+        assert!(substitute("<<= name + 1>>", &values).unwrap_err().contains("not numeric"));
+        assert!(substitute("<<missing>>", &values).is_err());
+    }
+
+    #[test]
+    fn test_invalid_banks() {
+        let mut req = request();
+        req.count = Some(4);
+        assert!(ExamGenerator::variants(&req, 1, None).is_err());
+        let mut req = request();
+        req.questions[1].correct = Some(3);
+        assert_eq!(ExamGenerator::variants(&req, 1, None).unwrap_err(), "Question 2: correct choice 3 is out of range");
+        let mut req = request();
+        req.variants = Some(27);
+        assert!(ExamGenerator::variants(&req, 1, None).is_err());
+    }
+}
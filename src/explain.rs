@@ -0,0 +1,248 @@
+use crate::models::ErrorExplanation;
+
+/// Language used when the client asks for nothing we support.
+const DEFAULT_LANGUAGE: &str = "en";
+
+/// Languages with a complete message catalog.
+const SUPPORTED_LANGUAGES: &[&str] = &["en", "es"];
+
+/// One known TeX error and its localized explanations.
+struct CatalogEntry {
+    id: &'static str,
+    /// Substrings that must all appear in the raw TeX error message
+    patterns: &'static [&'static str],
+    /// (language, explanation, suggested fix)
+    messages: &'static [(&'static str, &'static str, &'static str)],
+}
+
+/// Message catalog, checked in order (first match wins).
+const CATALOG: &[CatalogEntry] = &[
+    CatalogEntry {
+        id: "undefined_control_sequence",
+        patterns: &["Undefined control sequence"],
+        messages: &[
+            ("en", "A command on this line is not defined. It is either misspelled or comes from a package that is not loaded.",
+                   "Check the spelling of the command, or add the \\usepackage{...} that provides it."),
+            ("es", "Un comando en esta línea no está definido. Puede estar mal escrito o pertenecer a un paquete que no se ha cargado.",
+                   "Revisa la ortografía del comando o añade el \\usepackage{...} que lo define."),
+        ],
+    },
+    CatalogEntry {
+        id: "undefined_environment",
+        patterns: &["Environment", "undefined"],
+        messages: &[
+            ("en", "This \\begin{...} uses an environment that does not exist.",
+                   "Check the environment name, or load the package that defines it (e.g. amsmath for align)."),
+            ("es", "Este \\begin{...} usa un entorno que no existe.",
+                   "Revisa el nombre del entorno o carga el paquete que lo define (p. ej. amsmath para align)."),
+        ],
+    },
+    CatalogEntry {
+        id: "missing_dollar",
+        patterns: &["Missing $ inserted"],
+        messages: &[
+            ("en", "A math-only symbol (like _, ^ or \\alpha) was used outside math mode, or a math formula was not closed.",
+                   "Wrap the formula in $...$, or escape the character in text (\\_ instead of _)."),
+            ("es", "Se usó un símbolo matemático (como _, ^ o \\alpha) fuera del modo matemático, o una fórmula no se cerró.",
+                   "Encierra la fórmula entre $...$ o escapa el carácter en el texto (\\_ en lugar de _)."),
+        ],
+    },
+    CatalogEntry {
+        id: "file_not_found",
+        patterns: &["not found"],
+        messages: &[
+            ("en", "A file requested by \\input, \\include, \\includegraphics or \\usepackage could not be found.",
+                   "Make sure the file was uploaded with the project and that the path and letter case match."),
+            ("es", "No se encontró un archivo solicitado por \\input, \\include, \\includegraphics o \\usepackage.",
+                   "Asegúrate de haber subido el archivo con el proyecto y de que la ruta y las mayúsculas coincidan."),
+        ],
+    },
+    CatalogEntry {
+        id: "mismatched_environment",
+        patterns: &["ended by \\end"],
+        messages: &[
+            ("en", "An environment was closed with the wrong \\end{...}; environments must be closed in reverse order of opening.",
+                   "Find the matching \\begin and make the \\end name identical."),
+            ("es", "Un entorno se cerró con el \\end{...} equivocado; los entornos deben cerrarse en orden inverso al de apertura.",
+                   "Busca el \\begin correspondiente y usa exactamente el mismo nombre en el \\end."),
+        ],
+    },
+    CatalogEntry {
+        id: "missing_brace",
+        patterns: &["Missing } inserted"],
+        messages: &[
+            ("en", "A group opened with { was never closed.",
+                   "Count the braces on this line and add the missing }."),
+            ("es", "Un grupo abierto con { nunca se cerró.",
+                   "Cuenta las llaves de esta línea y añade la } que falta."),
+        ],
+    },
+    CatalogEntry {
+        id: "extra_brace",
+        patterns: &["Too many }'s"],
+        messages: &[
+            ("en", "There is a closing } without a matching {.",
+                   "Remove the extra } or add the missing {."),
+            ("es", "Hay una } de cierre sin su { correspondiente.",
+                   "Elimina la } sobrante o añade la { que falta."),
+        ],
+    },
+    CatalogEntry {
+        id: "runaway_argument",
+        patterns: &["Runaway argument"],
+        messages: &[
+            ("en", "A command argument was never closed, so TeX kept reading until the end of the paragraph or file.",
+                   "Look for a missing } in the arguments of the command before this point."),
+            ("es", "El argumento de un comando nunca se cerró, así que TeX siguió leyendo hasta el final del párrafo o del archivo.",
+                   "Busca una } faltante en los argumentos del comando anterior a este punto."),
+        ],
+    },
+    CatalogEntry {
+        id: "paragraph_ended",
+        patterns: &["Paragraph ended before"],
+        messages: &[
+            ("en", "A blank line appeared inside a command argument that cannot span paragraphs.",
+                   "Close the argument before the blank line, or remove the blank line."),
+            ("es", "Apareció una línea en blanco dentro del argumento de un comando que no admite varios párrafos.",
+                   "Cierra el argumento antes de la línea en blanco o elimina la línea en blanco."),
+        ],
+    },
+    CatalogEntry {
+        id: "misplaced_alignment",
+        patterns: &["Misplaced alignment tab character"],
+        messages: &[
+            ("en", "An & was used outside a table or alignment environment.",
+                   "Write \\& for a literal ampersand, or move it inside tabular/align."),
+            ("es", "Se usó & fuera de una tabla o entorno de alineación.",
+                   "Escribe \\& para un ampersand literal o muévelo dentro de tabular/align."),
+        ],
+    },
+    CatalogEntry {
+        id: "extra_alignment",
+        patterns: &["Extra alignment tab"],
+        messages: &[
+            ("en", "A table row has more columns (&) than the column specification allows.",
+                   "Remove the extra & or add columns to the \\begin{tabular}{...} specification."),
+            ("es", "Una fila de la tabla tiene más columnas (&) de las que permite la especificación.",
+                   "Elimina el & sobrante o añade columnas en \\begin{tabular}{...}."),
+        ],
+    },
+    CatalogEntry {
+        id: "no_line_to_end",
+        patterns: &["There's no line here to end"],
+        messages: &[
+            ("en", "A line break (\\\\ or \\newline) was used where no line has been started, e.g. after a blank line.",
+                   "Remove the \\\\, or use \\vspace{...} to add vertical space."),
+            ("es", "Se usó un salto de línea (\\\\ o \\newline) donde no hay ninguna línea iniciada, p. ej. tras una línea en blanco.",
+                   "Elimina el \\\\ o usa \\vspace{...} para añadir espacio vertical."),
+        ],
+    },
+    CatalogEntry {
+        id: "missing_number",
+        patterns: &["Missing number"],
+        messages: &[
+            ("en", "A command expected a number or length but got something else.",
+                   "Check arguments such as widths and counters (e.g. \\hspace{1cm}, not \\hspace{}) on this line."),
+            ("es", "Un comando esperaba un número o una longitud y recibió otra cosa.",
+                   "Revisa argumentos como anchos y contadores (p. ej. \\hspace{1cm}, no \\hspace{}) en esta línea."),
+        ],
+    },
+    CatalogEntry {
+        id: "double_script",
+        patterns: &["Double s"],
+        messages: &[
+            ("en", "Two subscripts or superscripts were applied to the same symbol (e.g. x_1_2).",
+                   "Group them with braces: x_{1_2} or {x_1}_2."),
+            ("es", "Se aplicaron dos subíndices o superíndices al mismo símbolo (p. ej. x_1_2).",
+                   "Agrúpalos con llaves: x_{1_2} o {x_1}_2."),
+        ],
+    },
+    CatalogEntry {
+        id: "missing_begin_document",
+        patterns: &["Missing \\begin{document}"],
+        messages: &[
+            ("en", "Text or commands that produce output appear before \\begin{document}.",
+                   "Move the content below \\begin{document}, or check the preamble for a stray character."),
+            ("es", "Hay texto o comandos que producen salida antes de \\begin{document}.",
+                   "Mueve el contenido debajo de \\begin{document} o revisa si hay un carácter suelto en el preámbulo."),
+        ],
+    },
+    CatalogEntry {
+        id: "emergency_stop",
+        patterns: &["Emergency stop"],
+        messages: &[
+            ("en", "TeX could not continue, usually because of an earlier error or the file ending unexpectedly.",
+                   "Fix the first error reported; check that the document ends with \\end{document}."),
+            ("es", "TeX no pudo continuar, normalmente por un error anterior o porque el archivo terminó inesperadamente.",
+                   "Corrige el primer error reportado y verifica que el documento termine con \\end{document}."),
+        ],
+    },
+];
+
+pub struct ErrorExplainer;
+
+impl ErrorExplainer {
+    /// Picks the best supported language from an `Accept-Language` header value.
+    pub fn negotiate(accept_language: Option<&str>) -> &'static str {
+        let mut ranges: Vec<(String, f32)> = accept_language.unwrap_or("")
+            .split(',')
+            .filter_map(|part| {
+                let mut pieces = part.split(';');
+                let tag = pieces.next()?.trim();
+                if tag.is_empty() {
+                    return None;
+                }
+                let q = pieces
+                    .find_map(|p| p.trim().strip_prefix("q="))
+                    .and_then(|q| q.parse::<f32>().ok())
+                    .unwrap_or(1.0);
+                let primary = tag.split('-').next().unwrap_or(tag).to_ascii_lowercase();
+                Some((primary, q))
+            })
+            .collect();
+        // Stable sort keeps header order for equal weights
+        ranges.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+
+        ranges.iter()
+            .filter(|(_, q)| *q > 0.0)
+            .find_map(|(lang, _)| SUPPORTED_LANGUAGES.iter().find(|l| *l == lang).copied())
+            .unwrap_or(DEFAULT_LANGUAGE)
+    }
+
+    /// Returns a human-readable explanation for a raw TeX error message, if known.
+    pub fn explain(message: &str, language: &str) -> Option<ErrorExplanation> {
+        let entry = CATALOG.iter().find(|e| e.patterns.iter().all(|p| message.contains(p)))?;
+        let (lang, explanation, suggestion) = entry.messages.iter()
+            .find(|(l, _, _)| *l == language)
+            .or_else(|| entry.messages.iter().find(|(l, _, _)| *l == DEFAULT_LANGUAGE))?;
+
+        Some(ErrorExplanation {
+            id: entry.id.to_string(),
+            language: lang.to_string(),
+            explanation: explanation.to_string(),
+            suggestion: suggestion.to_string(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_negotiate_language() {
+        assert_eq!(ErrorExplainer::negotiate(Some("es-ES,es;q=0.9,en;q=0.8")), "es");
+        assert_eq!(ErrorExplainer::negotiate(Some("fr-FR, en;q=0.5")), "en");
+        assert_eq!(ErrorExplainer::negotiate(Some("en;q=0.2, es;q=0.7")), "es");
+        assert_eq!(ErrorExplainer::negotiate(None), "en");
+    }
+
+    #[test]
+    fn test_explain_known_error() {
+        let e = ErrorExplainer::explain("Undefined control sequence.", "es").unwrap();
+        assert_eq!(e.id, "undefined_control_sequence");
+        assert_eq!(e.language, "es");
+        assert!(ErrorExplainer::explain("Environment foo undefined.", "en").is_some());
+        assert!(ErrorExplainer::explain("Something nobody has seen", "en").is_none());
+    }
+}
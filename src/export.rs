@@ -0,0 +1,108 @@
+//! Reproducible project exports: a ZIP of the sources, the compiled PDF and a manifest
+//! recording the engine, bundle and exact class/package releases the build used, so an
+//! archived build can be recompiled identically later.
+
+use regex::Regex;
+
+use crate::models::{ExportFile, ExportManifest, PackageVersion, Project, ProjectBuild};
+use crate::receipt::sha256_hex;
+use crate::render::{zip_files, AuxFile};
+
+/// Name of the manifest inside an export; the Overleaf importer reads it back.
+pub const MANIFEST_NAME: &str = "tachyon-export.json";
+pub const OUTPUT_NAME: &str = "output.pdf";
+const MANIFEST_FORMAT: u32 = 1;
+
+/// Classes and packages loaded by a build, from the `Document Class:` and `Package:`
+/// lines of the TeX log, in load order without repeats.
+pub fn packages_from_log(logs: &str) -> Vec<PackageVersion> {
+    let re = Regex::new(r"^(?:\[Note\] )?(Package|Document Class): (\S+) (\d{4}/\d{2}/\d{2})(?: (v\S+))?").unwrap();
+    let mut packages: Vec<PackageVersion> = Vec::new();
+    for caps in logs.lines().filter_map(|line| re.captures(line)) {
+        if packages.iter().any(|p| p.name == caps[2]) {
+            continue;
+        }
+        packages.push(PackageVersion {
+            name: caps[2].to_string(),
+            kind: if &caps[1] == "Package" { "package" } else { "class" }.to_string(),
+            date: caps[3].to_string(),
+            version: caps.get(4).map(|v| v.as_str().to_string()),
+        });
+    }
+    packages
+}
+
+pub fn manifest(project: &Project, build: &ProjectBuild, files: &[AuxFile], pdf: &[u8]) -> ExportManifest {
+    ExportManifest {
+        format: MANIFEST_FORMAT,
+        project_id: project.id.clone(),
+        main: project.main.clone(),
+        engine: project.engine.clone(),
+        build: build.clone(),
+        files: files
+            .iter()
+            .map(|(name, data)| ExportFile { name: name.clone(), sha256: sha256_hex(data), size: data.len() as u64 })
+            .collect(),
+        output_sha256: sha256_hex(pdf),
+    }
+}
+
+/// Packs the project files at the archive root, next to `output.pdf` and the manifest.
+pub fn bundle(project: &Project, build: &ProjectBuild, files: &[AuxFile], pdf: &[u8]) -> Result<Vec<u8>, String> {
+    let manifest = serde_json::to_vec_pretty(&manifest(project, build, files, pdf)).map_err(|e| e.to_string())?;
+    let mut entries: Vec<(String, &[u8])> = files.iter().map(|(name, data)| (name.clone(), data.as_slice())).collect();
+    entries.push((OUTPUT_NAME.to_string(), pdf));
+    entries.push((MANIFEST_NAME.to_string(), &manifest));
+    zip_files(&entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_packages_from_log() {
+        let log = "[Note] Running TeX ...\n\
+                   Document Class: article 2023/05/17 v1.4n Standard LaTeX document class\n\
+                   Package: amsmath 2022/04/08 v2.17n AMS math features\n\
+                   Package: hyperref 2023-02-07 v7.00v Hypertext links\n\
+                   [Note] Package: amsmath 2022/04/08 v2.17n AMS math features\n\
+                   [Note] Package: expl3 2023/06/16 L3 programming layer\n";
+        let packages = packages_from_log(log);
+        assert_eq!(packages.iter().map(|p| p.name.as_str()).collect::<Vec<_>>(), ["article", "amsmath", "expl3"]);
+        assert_eq!((packages[0].kind.as_str(), packages[0].version.as_deref()), ("class", Some("v1.4n")));
+        assert_eq!((packages[2].date.as_str(), packages[2].version.as_deref()), ("2023/06/16", None));
+    }
+
+    #[test]
+    fn test_bundle_round_trips_through_import() {
+        let project = Project {
+            id: "p1".into(),
+            main: "b.tex".into(),
+            engine: crate::models::ProjectEngine { program: "xelatex".into(), shell_escape: false, bibliography: "bibtex".into() },
+            files: Vec::new(),
+            warnings: Vec::new(),
+            created_at: 0,
+            last_build: None,
+        };
+        let build = ProjectBuild {
+            output_hash: "abc".into(),
+            compile_time_ms: 10,
+            compiled_at: 0,
+            engine_version: crate::receipt::ENGINE_VERSION.into(),
+            bundle_version: "default".into(),
+            packages: Vec::new(),
+        };
+        let files = vec![
+            ("a.tex".to_string(), b"\\documentclass{article}\\begin{document}a\\end{document}".to_vec()),
+            ("b.tex".to_string(), b"\\documentclass{article}\\begin{document}b\\end{document}".to_vec()),
+        ];
+        let zip = bundle(&project, &build, &files, b"%PDF-1.5").unwrap();
+
+        let imported = crate::overleaf::OverleafImporter::import(&zip).unwrap();
+        assert_eq!(imported.main, "b.tex");
+        let mut names: Vec<&str> = imported.files.iter().map(|(name, _)| name.as_str()).collect();
+        names.sort();
+        assert_eq!(names, ["a.tex", "b.tex"]);
+    }
+}
@@ -0,0 +1,235 @@
+//! Shared compile failures (`/compile?share_failure=true`): the sources, the end of the log
+//! and the parsed errors of a failed compile, kept under a short id for a while so users
+//! can ask for help with a link instead of re-uploading their project. `GET /failures/{id}`
+//! answers JSON, or a page listing the errors to browsers.
+
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::info;
+
+use crate::models::SharedFailure;
+use crate::playground::{is_short_id, short_id, sweep_expired, ShareQuota};
+use crate::settings::Settings;
+use crate::storage::Storage;
+use crate::tenancy::current_client;
+use crate::util::unix_now;
+
+const PREFIX: &str = "failures/";
+
+/// Bytes of the log kept, from its end where TeX reports the fatal error.
+const MAX_LOG_BYTES: usize = 256 * 1024;
+
+const SWEEP_INTERVAL_SECS: u64 = 3600;
+
+#[derive(Clone)]
+pub struct FailureStore {
+    storage: Arc<dyn Storage>,
+    quota: ShareQuota,
+}
+
+impl FailureStore {
+    pub fn new(storage: Arc<dyn Storage>) -> Self {
+        Self { storage, quota: ShareQuota::default() }
+    }
+
+    /// Stores a failure for FAILURE_SHARE_TTL_SECS, up to SHARES_PER_HOUR per client.
+    /// Sources beyond FAILURE_SHARE_MAX_KB are left out, the main file first in.
+    pub async fn share(
+        &self,
+        settings: &Settings,
+        main: &str,
+        error: &str,
+        logs: &str,
+        errors: Vec<serde_json::Value>,
+        sources: &HashMap<String, String>,
+    ) -> Result<SharedFailure, String> {
+        self.quota.take(&current_client(), settings.shares_per_hour)?;
+        let mut names: Vec<&String> = sources.keys().collect();
+        names.sort_by_key(|name| (name.as_str() != main, name.as_str()));
+        let mut budget = settings.failure_share_max_kb as usize * 1024;
+        let (mut files, mut omitted_files) = (HashMap::new(), Vec::new());
+        for name in names {
+            let content = &sources[name];
+            if content.len() <= budget {
+                budget -= content.len();
+                files.insert(name.clone(), content.clone());
+            } else {
+                omitted_files.push(name.clone());
+            }
+        }
+
+        let created_at = unix_now();
+        let failure = SharedFailure {
+            id: short_id(),
+            url: String::new(),
+            main: main.to_string(),
+            error: error.to_string(),
+            errors,
+            logs: log_tail(logs).to_string(),
+            files,
+            omitted_files,
+            created_at,
+            expires_at: created_at + settings.failure_share_ttl_secs,
+        };
+        let data = serde_json::to_vec(&failure).map_err(|e| e.to_string())?;
+        self.storage.put(&format!("{}{}", PREFIX, failure.id), data).await?;
+        Ok(failure)
+    }
+
+    /// An unexpired failure; an expired one is deleted on the way.
+    pub async fn get(&self, id: &str) -> Option<SharedFailure> {
+        if !is_short_id(id) {
+            return None;
+        }
+        let key = format!("{}{}", PREFIX, id);
+        let failure: SharedFailure = serde_json::from_slice(&self.storage.get(&key).await.ok()??).ok()?;
+        if failure.expires_at <= unix_now() {
+            self.storage.delete(&key).await.ok();
+            return None;
+        }
+        Some(failure)
+    }
+}
+
+/// Removes expired failures hourly; reads already skip them.
+pub async fn sweep_task(store: FailureStore) {
+    loop {
+        tokio::time::sleep(Duration::from_secs(SWEEP_INTERVAL_SECS)).await;
+        let removed = sweep_expired(store.storage.as_ref(), PREFIX).await;
+        if removed > 0 {
+            info!("🧹 Removed {} expired shared failure(s)", removed);
+        }
+    }
+}
+
+fn log_tail(logs: &str) -> &str {
+    let mut start = logs.len().saturating_sub(MAX_LOG_BYTES);
+    while !logs.is_char_boundary(start) {
+        start += 1;
+    }
+    &logs[start..]
+}
+
+/// The failure as a standalone page: each parsed error with its explanation and source
+/// lines, then the log and the sources.
+pub fn render_html(failure: &SharedFailure) -> String {
+    let mut html = String::new();
+    let _ = write!(
+        html,
+        "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"UTF-8\">\n<meta name=\"viewport\" content=\"width=device-width, initial-scale=1.0\">\n\
+         <meta name=\"robots\" content=\"noindex\">\n<title>Compile failure: {main} | Tachyon-Tex</title>\n<style>{STYLE}</style>\n</head>\n<body>\n\
+         <h1>Compile failure in <code>{main}</code></h1>\n<p class=\"muted\">Shared {created}, expires {expires} (UTC)</p>\n<pre class=\"summary\">{error}</pre>\n",
+        main = escape(&failure.main),
+        created = format_time(failure.created_at),
+        expires = format_time(failure.expires_at),
+        error = escape(&failure.error),
+    );
+
+    let _ = writeln!(html, "<h2>{} error(s)</h2>", failure.errors.len());
+    for error in &failure.errors {
+        let location = match (error["file"].as_str(), error["line"].as_u64()) {
+            (Some(file), Some(line)) => format!("{}:{}", file, line),
+            (Some(file), None) => file.to_string(),
+            _ => String::new(),
+        };
+        let _ = write!(html, "<div class=\"error\">\n<div><span class=\"location\">{}</span> {}</div>\n", escape(&location), escape(error["message"].as_str().unwrap_or_default()));
+        if let Some(explanation) = error.get("explanation") {
+            let _ = write!(
+                html,
+                "<p>{}</p>\n<p class=\"suggestion\">{}</p>\n",
+                escape(explanation["explanation"].as_str().unwrap_or_default()),
+                escape(explanation["suggestion"].as_str().unwrap_or_default())
+            );
+        }
+        if let Some(lines) = error["snippet"]["lines"].as_array() {
+            html.push_str("<pre class=\"snippet\">");
+            for line in lines {
+                let class = if line["error"].as_bool() == Some(true) { " class=\"hit\"" } else { "" };
+                let _ = writeln!(html, "<span{}>{:>5}  {}</span>", class, line["line"].as_u64().unwrap_or_default(), escape(line["text"].as_str().unwrap_or_default()));
+            }
+            html.push_str("</pre>\n");
+        }
+        html.push_str("</div>\n");
+    }
+
+    let _ = write!(html, "<details><summary>Log</summary>\n<pre>{}</pre>\n</details>\n", escape(&failure.logs));
+    let mut names: Vec<&String> = failure.files.keys().collect();
+    names.sort();
+    for name in names {
+        let _ = write!(html, "<details><summary>{}</summary>\n<pre>{}</pre>\n</details>\n", escape(name), escape(&failure.files[name]));
+    }
+    if !failure.omitted_files.is_empty() {
+        let _ = writeln!(html, "<p class=\"muted\">Not included (too large): {}</p>", escape(&failure.omitted_files.join(", ")));
+    }
+    html.push_str("</body>\n</html>\n");
+    html
+}
+
+const STYLE: &str = "body{background:#0a0a0f;color:#e4e4e7;font-family:Inter,system-ui,sans-serif;max-width:960px;margin:0 auto;padding:24px}\
+code,pre{font-family:'JetBrains Mono',monospace;font-size:13px}pre{background:#0d0d14;border:1px solid rgba(139,92,246,.15);border-radius:8px;padding:12px;overflow-x:auto}\
+.muted{color:#71717a;font-size:13px}.summary{border-color:rgba(239,68,68,.4)}.error{border-left:3px solid #ef4444;padding:4px 16px;margin:16px 0}\
+.location{color:#f87171;font-family:'JetBrains Mono',monospace}.suggestion{color:#22c55e}.hit{background:rgba(239,68,68,.2);display:inline-block;width:100%}\
+summary{cursor:pointer;color:#8b5cf6;margin:12px 0}";
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;").replace('\'', "&#39;")
+}
+
+/// `YYYY-MM-DD HH:MM` of a Unix time.
+fn format_time(secs: u64) -> String {
+    let days = (secs / 86_400) as i64;
+    // Civil date from days since 1970-01-01 (Howard Hinnant's algorithm)
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!("{:04}-{:02}-{:02} {:02}:{:02}", year, month, day, secs % 86_400 / 3600, secs % 3600 / 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+
+    #[tokio::test]
+    async fn test_share_and_render() {
+        let store = FailureStore::new(Arc::new(MemoryStorage::new()));
+        let settings = Settings { failure_share_max_kb: 1, ..Settings::from_env() };
+        let sources = HashMap::from([
+            ("main.tex".to_string(), "\\documentclass{article}\n\\begin{document}\n\\foo<b>\n\\end{document}".to_string()),
+            ("big.bib".to_string(), "x".repeat(2048)),
+        ]);
+        let errors = vec![serde_json::json!({
+            "file": "main.tex", "line": 3, "message": "Undefined control sequence",
+            "snippet": {"lines": [{"line": 3, "text": "\\foo<b>", "error": true}]},
+        })];
+        let failure = store.share(&settings, "main.tex", "Compilation failed", "! Undefined control sequence.", errors, &sources).await.unwrap();
+        assert_eq!(failure.omitted_files, vec!["big.bib"]);
+        assert_eq!(failure.expires_at - failure.created_at, settings.failure_share_ttl_secs);
+
+        let stored = store.get(&failure.id).await.unwrap();
+        assert_eq!(stored.files.keys().collect::<Vec<_>>(), vec!["main.tex"]);
+        assert!(store.get("failures/../x").await.is_none());
+
+        let html = render_html(&stored);
+        assert!(html.contains("<span class=\"location\">main.tex:3</span> Undefined control sequence"));
+        assert!(html.contains("\\foo&lt;b&gt;"), "sources are escaped");
+        assert!(html.contains("Not included (too large): big.bib"));
+    }
+
+    #[test]
+    fn test_helpers() {
+        assert_eq!(format_time(0), "1970-01-01 00:00");
+        assert_eq!(format_time(1_760_000_000), "2025-10-09 08:53");
+        let logs = format!("{}é! Fatal", "a".repeat(MAX_LOG_BYTES));
+        assert!(log_tail(&logs).ends_with("! Fatal"));
+        assert!(log_tail(&logs).len() <= MAX_LOG_BYTES);
+    }
+}
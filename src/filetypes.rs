@@ -0,0 +1,153 @@
+//! Upload allow-list: only file types a LaTeX project needs are written to a workspace.
+//! The extension must be allowed, the first bytes must match it (a `.png` holding a PDF is
+//! refused), and executables or scripts are refused whatever they are named.
+
+use std::io::Read;
+use std::path::Path;
+
+use crate::models::FileRejection;
+
+/// Extensions accepted when ALLOWED_FILE_TYPES is unset.
+pub const DEFAULT_ALLOWED: &str = "tex,ltx,sty,cls,clo,cfg,def,fd,ldf,dtx,ins,bib,bst,bbx,cbx,lbx,dbx,ist,\
+    txt,md,csv,tsv,dat,json,yaml,yml,xml,tikz,pgf,table,\
+    png,jpg,jpeg,gif,pdf,eps,ps,svg,ttf,otf,ttc,woff,woff2,pfb,afm,tfm,vf,enc,map";
+
+/// Bytes read from each file for sniffing.
+const HEAD_BYTES: usize = 4096;
+
+/// Extensions of plain-text files, which must not contain NUL bytes.
+const TEXT_EXTENSIONS: &[&str] = &[
+    "tex", "ltx", "sty", "cls", "clo", "cfg", "def", "fd", "ldf", "dtx", "ins", "bib", "bst", "bbx", "cbx", "lbx", "dbx",
+    "ist", "txt", "md", "csv", "tsv", "dat", "json", "yaml", "yml", "xml", "tikz", "pgf", "table", "svg",
+];
+
+pub struct FilePolicy<'a> {
+    /// Lowercase extensions without the dot; `*` accepts any
+    allowed: &'a [String],
+}
+
+impl<'a> FilePolicy<'a> {
+    pub fn new(allowed: &'a [String]) -> Self {
+        Self { allowed }
+    }
+
+    /// Checks a file from its name and first bytes; `None` when it is accepted.
+    pub fn check(&self, name: &str, head: &[u8]) -> Option<FileRejection> {
+        let detected = sniff(head);
+        let reject = |reason: String| Some(FileRejection { file: name.to_string(), reason, detected: detected.map(str::to_string) });
+        if let Some(kind @ ("elf" | "pe" | "mach-o" | "script")) = detected {
+            return reject(format!("{} files are never accepted", if kind == "script" { "script" } else { "executable" }));
+        }
+
+        let file_name = name.rsplit('/').next().unwrap_or(name);
+        let Some(extension) = file_name.rsplit_once('.').map(|(_, ext)| ext.to_ascii_lowercase()).filter(|ext| !ext.is_empty()) else {
+            return if self.allows("*") { None } else { reject("files without an extension are not accepted".to_string()) };
+        };
+        if !self.allows("*") && !self.allows(&extension) {
+            return reject(format!(".{} files are not accepted", extension));
+        }
+
+        let matches = match extension.as_str() {
+            "png" => detected == Some("png"),
+            "jpg" | "jpeg" => detected == Some("jpeg"),
+            "gif" => detected == Some("gif"),
+            "pdf" => detected == Some("pdf"),
+            "eps" | "ps" => detected == Some("postscript"),
+            "ttf" | "otf" | "ttc" => detected == Some("font"),
+            "woff" | "woff2" => detected == Some("woff"),
+            "svg" => !head.contains(&0) && String::from_utf8_lossy(head).contains("<svg"),
+            ext if TEXT_EXTENSIONS.contains(&ext) => !head.contains(&0),
+            _ => true,
+        };
+        if matches {
+            None
+        } else if TEXT_EXTENSIONS.contains(&extension.as_str()) && head.contains(&0) {
+            reject(format!("binary data in a .{} file", extension))
+        } else {
+            reject(format!("content does not look like a .{} file", extension))
+        }
+    }
+
+    /// Checks uploaded files already written to `dir`.
+    pub fn check_dir(&self, dir: &Path, files: &[String]) -> Vec<FileRejection> {
+        files
+            .iter()
+            .filter_map(|name| {
+                let mut head = Vec::with_capacity(HEAD_BYTES);
+                if let Ok(file) = std::fs::File::open(dir.join(name)) {
+                    file.take(HEAD_BYTES as u64).read_to_end(&mut head).ok();
+                }
+                self.check(name, &head)
+            })
+            .collect()
+    }
+
+    fn allows(&self, extension: &str) -> bool {
+        self.allowed.iter().any(|allowed| allowed == extension)
+    }
+}
+
+/// Identifies a file from its first bytes.
+pub fn sniff(head: &[u8]) -> Option<&'static str> {
+    let kind = match head {
+        [0x7f, b'E', b'L', b'F', ..] => "elf",
+        [b'M', b'Z', ..] if is_pe(head) => "pe",
+        [0xfe, 0xed, 0xfa, 0xce | 0xcf, ..] | [0xce | 0xcf, 0xfa, 0xed, 0xfe, ..] | [0xca, 0xfe, 0xba, 0xbe, ..] => "mach-o",
+        [b'#', b'!', ..] => "script",
+        [0x89, b'P', b'N', b'G', ..] => "png",
+        [0xff, 0xd8, 0xff, ..] => "jpeg",
+        [b'G', b'I', b'F', b'8', ..] => "gif",
+        [b'%', b'!', b'P', b'S', ..] | [0xc5, 0xd0, 0xd3, 0xc6, ..] => "postscript",
+        [0x00, 0x01, 0x00, 0x00, ..] | [b'O', b'T', b'T', b'O', ..] | [b't', b'r', b'u', b'e', ..] | [b't', b't', b'c', b'f', ..] => "font",
+        [b'w', b'O', b'F', b'F' | b'2', ..] => "woff",
+        [b'P', b'K', 0x03, 0x04, ..] => "zip",
+        // PDFs may carry a few bytes of junk before the header
+        _ if head.windows(5).take(1024).any(|w| w == b"%PDF-") => "pdf",
+        _ => return None,
+    };
+    Some(kind)
+}
+
+/// A DOS header whose `e_lfanew` points at a "PE\0\0" signature.
+fn is_pe(head: &[u8]) -> bool {
+    let Some(offset) = head.get(0x3c..0x40).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as usize) else { return false };
+    head.get(offset..offset + 4) == Some(b"PE\0\0")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn allowed() -> Vec<String> {
+        DEFAULT_ALLOWED.split(',').map(|ext| ext.trim().to_string()).collect()
+    }
+
+    #[test]
+    fn test_policy() {
+        let allowed = allowed();
+        let policy = FilePolicy::new(&allowed);
+        assert!(policy.check("main.tex", b"\\documentclass{article}").is_none());
+        assert!(policy.check("figs/Logo.PNG", b"\x89PNG\r\n\x1a\n....").is_none());
+        assert!(policy.check("paper.pdf", b"\n%PDF-1.7").is_none());
+        assert!(policy.check("fig.svg", b"<?xml version=\"1.0\"?>\n<svg xmlns=\"http://www.w3.org/2000/svg\"/>").is_none());
+
+        let rejected = policy.check("fig.png", b"%PDF-1.5").unwrap();
+        assert_eq!((rejected.reason.as_str(), rejected.detected.as_deref()), ("content does not look like a .png file", Some("pdf")));
+        assert_eq!(policy.check("main.tex", b"\x7fELF\x02\x01").unwrap().reason, "executable files are never accepted");
+        assert_eq!(policy.check("build.sty", b"#!/bin/sh\nrm -rf /").unwrap().reason, "script files are never accepted");
+        assert_eq!(policy.check("run.sh", b"echo").unwrap().reason, ".sh files are not accepted");
+        assert_eq!(policy.check("Makefile", b"all:").unwrap().reason, "files without an extension are not accepted");
+        assert_eq!(policy.check("data.csv", b"a,b\0c").unwrap().reason, "binary data in a .csv file");
+
+        let mut pe = vec![0u8; 0x84];
+        pe[..2].copy_from_slice(b"MZ");
+        pe[0x3c] = 0x80;
+        pe[0x80..].copy_from_slice(b"PE\0\0");
+        assert_eq!(sniff(&pe), Some("pe"));
+        assert_eq!(sniff(b"MZ is a text file"), None);
+
+        let any = vec!["*".to_string()];
+        assert!(FilePolicy::new(&any).check("Makefile", b"all:").is_none());
+        assert!(FilePolicy::new(&any).check("tool.exe", &pe).is_some());
+    }
+}
@@ -0,0 +1,137 @@
+//! Content fingerprints of compiled PDFs, for finding documents whose text overlaps
+//! (leaked exams, invoices issued twice). MinHash over word shingles estimates how much
+//! text two documents share; SimHash flags near-identical ones.
+
+use xxhash_rust::xxh64::xxh64;
+
+/// MinHash signature length; the similarity estimate's error is about 1/sqrt(64).
+pub const MINHASH_SIZE: usize = 64;
+
+/// Words per shingle.
+const SHINGLE_WORDS: usize = 3;
+
+/// Documents with fewer words than this are not fingerprinted: any overlap is noise.
+pub const MIN_WORDS: usize = 20;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Fingerprint {
+    pub simhash: u64,
+    pub minhash: Vec<u64>,
+    pub words: usize,
+}
+
+impl Fingerprint {
+    /// Fingerprints text; `None` when it is too short to compare meaningfully.
+    pub fn of_text(text: &str) -> Option<Self> {
+        let words: Vec<String> = text
+            .split(|c: char| !c.is_alphanumeric())
+            .filter(|w| !w.is_empty())
+            .map(|w| w.to_lowercase())
+            .collect();
+        if words.len() < MIN_WORDS {
+            return None;
+        }
+
+        let mut minhash = vec![u64::MAX; MINHASH_SIZE];
+        for shingle in words.windows(SHINGLE_WORDS) {
+            let hash = xxh64(shingle.join(" ").as_bytes(), 0);
+            for (i, slot) in minhash.iter_mut().enumerate() {
+                *slot = (*slot).min(mix(hash ^ (i as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15)));
+            }
+        }
+
+        // Each word votes on every bit of the SimHash
+        let mut votes = [0i64; 64];
+        for word in &words {
+            let hash = xxh64(word.as_bytes(), 0);
+            for (bit, vote) in votes.iter_mut().enumerate() {
+                *vote += if hash >> bit & 1 == 1 { 1 } else { -1 };
+            }
+        }
+        let simhash = votes.iter().enumerate().filter(|(_, v)| **v > 0).fold(0u64, |acc, (bit, _)| acc | 1 << bit);
+
+        Some(Self { simhash, minhash, words: words.len() })
+    }
+
+    /// Fingerprints the text of a PDF; pages whose text cannot be decoded are skipped.
+    pub fn of_pdf(pdf: &[u8]) -> Result<Option<Self>, String> {
+        Ok(Self::of_text(&extract_text(pdf)?))
+    }
+
+    /// Estimated Jaccard similarity of the two documents' shingle sets (0 to 1).
+    pub fn similarity(&self, other: &Fingerprint) -> f64 {
+        let same = self.minhash.iter().zip(&other.minhash).filter(|(a, b)| a == b).count();
+        same as f64 / MINHASH_SIZE as f64
+    }
+
+    /// Bits in which the SimHashes differ; 3 or fewer means near-identical text.
+    pub fn simhash_distance(&self, other: &Fingerprint) -> u32 {
+        (self.simhash ^ other.simhash).count_ones()
+    }
+}
+
+/// SplitMix64 finalizer, turning one hash into a family of independent ones.
+fn mix(mut z: u64) -> u64 {
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+pub fn extract_text(pdf: &[u8]) -> Result<String, String> {
+    let document = lopdf::Document::load_mem(pdf).map_err(|e| format!("Cannot read PDF: {}", e))?;
+    let mut text = String::new();
+    for page in document.get_pages().into_keys() {
+        if let Ok(page_text) = document.extract_text(&[page]) {
+            text.push_str(&page_text);
+            text.push('\n');
+        }
+    }
+    Ok(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXAM: &str = "Question one asks the student to derive the quadratic formula from first principles, \
+        then apply it to solve three equations with integer coefficients and explain each step in words. \
+        Question two covers the fundamental theorem of calculus and its use in computing areas under curves.";
+
+    #[test]
+    fn test_similar_documents() {
+        let original = Fingerprint::of_text(EXAM).unwrap();
+        let edited = Fingerprint::of_text(&EXAM.replace("three equations", "four equations")).unwrap();
+        let unrelated = Fingerprint::of_text("Invoice for consulting services rendered in March, including travel expenses, \
+            software licences and two days of on-site training for the operations team at the Madrid office.").unwrap();
+
+        assert_eq!(original.similarity(&original), 1.0);
+        assert!(original.similarity(&edited) > 0.6, "{}", original.similarity(&edited));
+        assert!(original.similarity(&unrelated) < 0.2);
+        assert!(original.simhash_distance(&edited) < original.simhash_distance(&unrelated));
+        assert!(Fingerprint::of_text("Too short to compare").is_none());
+    }
+
+    #[test]
+    fn test_pdf_text() {
+        use lopdf::{dictionary, Document, Object, Stream};
+
+        let mut doc = Document::with_version("1.5");
+        let pages_id = doc.new_object_id();
+        let font = doc.add_object(dictionary! { "Type" => "Font", "Subtype" => "Type1", "BaseFont" => "Helvetica", "Encoding" => "WinAnsiEncoding" });
+        let content = doc.add_object(Stream::new(dictionary! {}, format!("BT /F1 12 Tf ({}) Tj ET", EXAM).into_bytes()));
+        let page = doc.add_object(dictionary! {
+            "Type" => "Page",
+            "Parent" => pages_id,
+            "Contents" => content,
+            "Resources" => dictionary! { "Font" => dictionary! { "F1" => font } },
+        });
+        doc.objects.insert(pages_id, Object::Dictionary(dictionary! { "Type" => "Pages", "Kids" => vec![page.into()], "Count" => 1 }));
+        let catalog = doc.add_object(dictionary! { "Type" => "Catalog", "Pages" => pages_id });
+        doc.trailer.set("Root", catalog);
+        let mut pdf = Vec::new();
+        doc.save_to(&mut pdf).unwrap();
+
+        assert_eq!(Fingerprint::of_pdf(&pdf).unwrap(), Fingerprint::of_text(EXAM));
+        assert!(extract_text(b"not a pdf").is_err());
+    }
+}
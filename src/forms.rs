@@ -0,0 +1,367 @@
+//! `forms=true` post-processing: turns marked regions of a compiled PDF into fillable
+//! AcroForm fields (text, checkbox, signature), so generated contracts can be completed
+//! electronically. Regions are marked in the source with the bundled `tachyonform`
+//! package, whose commands leave bare widget annotations in the PDF, or given by
+//! coordinates in the `form_fields` part of the upload.
+//!
+//! Widgets sharing a name become one field with several widgets, so its value is
+//! mirrored. Text fields get their appearance from the viewer (`NeedAppearances`);
+//! checkboxes get a check mark drawn in ZapfDingbats.
+
+use lopdf::{dictionary, Dictionary, Document, Object, ObjectId, Stream};
+use std::collections::{BTreeSet, HashMap};
+
+use crate::models::FormFieldSpec;
+
+pub const PACKAGE_FILE: &str = "tachyonform.sty";
+pub const PACKAGE: &str = include_str!("../templates/tachyonform.sty");
+
+pub const FIELD_TYPES: &[&str] = &["text", "checkbox", "signature"];
+
+/// Field flag (`Ff`) of multi-line text fields
+const MULTILINE: i64 = 1 << 12;
+/// Annotation flag (`F`): print the widget with the page
+const PRINT: i64 = 4;
+/// Helvetica, sized to fit the field
+const TEXT_APPEARANCE: &str = "/Helv 0 Tf 0 g";
+const CHECK_APPEARANCE: &str = "/ZaDb 0 Tf 0 g";
+/// On state of checkboxes
+const CHECKED: &str = "Yes";
+
+/// Whether any of `sources` loads the `tachyonform` package.
+pub fn uses_package<'a>(mut sources: impl Iterator<Item = &'a String>) -> bool {
+    sources.any(|text| {
+        text.lines()
+            .map(|line| line.split('%').next().unwrap_or_default())
+            .any(|line| line.contains("\\usepackage") && line.contains("tachyonform"))
+    })
+}
+
+/// Checks the `form_fields` part before compiling.
+pub fn validate(fields: &[FormFieldSpec]) -> Result<(), String> {
+    for field in fields {
+        if field.name.is_empty() || field.name.contains('.') {
+            return Err(format!("Invalid form field name '{}': it must be non-empty and have no dots", field.name));
+        }
+        if !FIELD_TYPES.contains(&field.kind.as_str()) {
+            return Err(format!("Unknown type '{}' of form field '{}'; expected one of: {}", field.kind, field.name, FIELD_TYPES.join(", ")));
+        }
+        let [x, y, width, height] = field.rect;
+        if !(x >= 0.0 && y >= 0.0 && width > 0.0 && height > 0.0) || field.page == 0 {
+            return Err(format!("Invalid placement of form field '{}': rect is [x, y, width, height] in points, page is 1-based", field.name));
+        }
+    }
+    Ok(())
+}
+
+/// Adds the fields marked in `pdf` and those in `fields` to its form. Returns the PDF and
+/// the number of fields, which is zero (and the PDF unchanged) when nothing was marked.
+pub fn add_fields(pdf: &[u8], fields: &[FormFieldSpec]) -> Result<(Vec<u8>, usize), String> {
+    let mut doc = Document::load_mem(pdf).map_err(|e| format!("Failed to parse PDF: {}", e))?;
+    let pages = doc.get_pages();
+    let existing = existing_fields(&doc);
+
+    // Marked widgets: those with a field type that no form lists yet
+    let mut widgets: Vec<(ObjectId, ObjectId)> = Vec::new();
+    for &page in pages.values() {
+        for annotation in annotations(&doc, page) {
+            let Ok(dict) = doc.get_dictionary(annotation) else { continue };
+            let widget = dict.get(b"Subtype").and_then(Object::as_name).ok() == Some(b"Widget".as_slice());
+            if widget && dict.has(b"FT") && !dict.has(b"Parent") && !existing.contains(&annotation) {
+                widgets.push((annotation, page));
+            }
+        }
+    }
+    for field in fields {
+        let count = pages.len() as i64;
+        let number = if field.page < 0 { count + 1 + field.page } else { field.page };
+        let page = *u32::try_from(number).ok().and_then(|n| pages.get(&n))
+            .ok_or_else(|| format!("Form field '{}' is on page {}, but the document has {} page(s)", field.name, field.page, count))?;
+        let [x, y, width, height] = field.rect;
+        let mut widget = dictionary! {
+            "Type" => "Annot",
+            "Subtype" => "Widget",
+            "FT" => match field.kind.as_str() {
+                "checkbox" => "Btn",
+                "signature" => "Sig",
+                _ => "Tx",
+            },
+            "T" => Object::string_literal(field.name.as_str()),
+            "Rect" => vec![x.into(), y.into(), (x + width).into(), (y + height).into()],
+        };
+        if field.multiline && field.kind == "text" {
+            widget.set("Ff", MULTILINE);
+        }
+        let widget = doc.add_object(widget);
+        push_to_array(&mut doc, page, b"Annots", widget.into())?;
+        widgets.push((widget, page));
+    }
+    if widgets.is_empty() {
+        return Ok((pdf.to_vec(), 0));
+    }
+
+    // Widgets by field name, in order of appearance
+    let mut names: HashMap<Vec<u8>, usize> = HashMap::new();
+    let mut groups: Vec<Vec<ObjectId>> = Vec::new();
+    for &(widget, page) in &widgets {
+        complete_widget(&mut doc, widget, page)?;
+        let name = doc.get_dictionary(widget).and_then(|w| w.get(b"T")).and_then(Object::as_str).map(<[u8]>::to_vec).unwrap_or_default();
+        match names.get(&name) {
+            Some(&index) => groups[index].push(widget),
+            None => {
+                names.insert(name, groups.len());
+                groups.push(vec![widget]);
+            }
+        }
+    }
+    let mut new_fields = Vec::new();
+    for group in &groups {
+        if let [widget] = group[..] {
+            new_fields.push(widget.into());
+            continue;
+        }
+        new_fields.push(shared_field(&mut doc, group)?.into());
+    }
+
+    let acroform = acroform(&mut doc)?;
+    for field in new_fields {
+        push_to_array(&mut doc, acroform, b"Fields", field)?;
+    }
+    let helvetica = doc.add_object(dictionary! { "Type" => "Font", "Subtype" => "Type1", "BaseFont" => "Helvetica", "Encoding" => "WinAnsiEncoding" });
+    let dingbats = doc.add_object(dictionary! { "Type" => "Font", "Subtype" => "Type1", "BaseFont" => "ZapfDingbats" });
+    let form = doc.get_dictionary_mut(acroform).map_err(|e| e.to_string())?;
+    form.set("NeedAppearances", true);
+    if !form.has(b"DA") {
+        form.set("DA", Object::string_literal(TEXT_APPEARANCE));
+    }
+    if !form.has(b"DR") {
+        form.set("DR", dictionary! { "Font" => dictionary! { "Helv" => helvetica, "ZaDb" => dingbats } });
+    }
+    doc.prune_objects();
+
+    let mut out = Vec::new();
+    doc.save_to(&mut out).map_err(|e| format!("Failed to write PDF: {}", e))?;
+    Ok((out, groups.len()))
+}
+
+/// Gives a widget what a viewer needs to fill it: its page, print flag, default appearance
+/// and, for checkboxes, the checked and unchecked appearances.
+fn complete_widget(doc: &mut Document, widget: ObjectId, page: ObjectId) -> Result<(), String> {
+    let dict = doc.get_dictionary(widget).map_err(|e| e.to_string())?;
+    let kind = dict.get(b"FT").and_then(Object::as_name).map(<[u8]>::to_vec).unwrap_or_default();
+    let rect: Vec<f32> = dict.get(b"Rect").and_then(Object::as_array).map(|r| r.iter().filter_map(|v| v.as_float().ok()).collect()).unwrap_or_default();
+    let [x0, y0, x1, y1] = rect[..] else { return Err("Form field widget without a Rect".to_string()) };
+    let checkbox = match kind.as_slice() {
+        b"Btn" => Some((checkbox_appearance(doc, (x1 - x0).abs(), (y1 - y0).abs(), true), checkbox_appearance(doc, (x1 - x0).abs(), (y1 - y0).abs(), false))),
+        _ => None,
+    };
+    let dict = doc.get_dictionary_mut(widget).map_err(|e| e.to_string())?;
+    dict.set("Type", "Annot");
+    dict.set("P", page);
+    dict.set("F", PRINT);
+    match (kind.as_slice(), checkbox) {
+        (b"Btn", Some((on, off))) => {
+            dict.set("DA", Object::string_literal(CHECK_APPEARANCE));
+            // ZapfDingbats "4" is a check mark
+            dict.set("MK", dictionary! { "CA" => Object::string_literal("4") });
+            dict.set("AP", dictionary! { "N" => dictionary! { CHECKED => on, "Off" => off } });
+            dict.set("AS", "Off");
+            dict.set("V", "Off");
+        }
+        (b"Tx", _) => dict.set("DA", Object::string_literal(TEXT_APPEARANCE)),
+        _ => {}
+    }
+    Ok(())
+}
+
+/// A form XObject drawing a checkbox's state.
+fn checkbox_appearance(doc: &mut Document, width: f32, height: f32, checked: bool) -> ObjectId {
+    let content = if checked {
+        let size = 0.8 * width.min(height);
+        // The check mark is 0.846 em wide and about 0.7 em tall
+        format!("q 0 g BT /ZaDb {:.2} Tf {:.2} {:.2} Td (4) Tj ET Q", size, (width - 0.846 * size) / 2.0, (height - 0.7 * size) / 2.0)
+    } else {
+        String::new()
+    };
+    let font = doc.add_object(dictionary! { "Type" => "Font", "Subtype" => "Type1", "BaseFont" => "ZapfDingbats" });
+    doc.add_object(Stream::new(dictionary! {
+        "Type" => "XObject",
+        "Subtype" => "Form",
+        "BBox" => vec![0.into(), 0.into(), width.into(), height.into()],
+        "Resources" => dictionary! { "Font" => dictionary! { "ZaDb" => font } },
+    }, content.into_bytes()))
+}
+
+/// One field for widgets sharing a name: the name, type and flags move to a parent whose
+/// kids they become.
+fn shared_field(doc: &mut Document, widgets: &[ObjectId]) -> Result<ObjectId, String> {
+    let first = doc.get_dictionary(widgets[0]).map_err(|e| e.to_string())?.clone();
+    let mut parent = Dictionary::new();
+    for key in [b"FT".as_slice(), b"T", b"Ff", b"V"] {
+        if let Ok(value) = first.get(key) {
+            parent.set(key, value.clone());
+        }
+    }
+    parent.set("Kids", widgets.iter().map(|&w| Object::from(w)).collect::<Vec<_>>());
+    let parent_id = doc.new_object_id();
+    for &widget in widgets {
+        let dict = doc.get_dictionary_mut(widget).map_err(|e| e.to_string())?;
+        if dict.get(b"FT").ok() != first.get(b"FT").ok() {
+            let name = String::from_utf8_lossy(first.get(b"T").and_then(Object::as_str).unwrap_or_default()).into_owned();
+            return Err(format!("Form field '{}' is used for fields of different types", name));
+        }
+        for key in [b"FT".as_slice(), b"T", b"Ff", b"V"] {
+            dict.remove(key);
+        }
+        dict.set("Parent", parent_id);
+    }
+    doc.objects.insert(parent_id, Object::Dictionary(parent));
+    Ok(parent_id)
+}
+
+/// Fields (and their kids) already in the document's form.
+fn existing_fields(doc: &Document) -> BTreeSet<ObjectId> {
+    let mut seen = BTreeSet::new();
+    let form = doc.catalog().ok().and_then(|c| c.get(b"AcroForm").ok()).and_then(|f| doc.dereference(f).ok()).and_then(|(_, f)| f.as_dict().ok());
+    let mut pending: Vec<ObjectId> = form.and_then(|f| f.get(b"Fields").ok()).and_then(|f| doc.dereference(f).ok())
+        .and_then(|(_, f)| f.as_array().ok())
+        .map(|fields| fields.iter().filter_map(|f| f.as_reference().ok()).collect())
+        .unwrap_or_default();
+    while let Some(id) = pending.pop() {
+        if !seen.insert(id) {
+            continue;
+        }
+        if let Ok(kids) = doc.get_dictionary(id).and_then(|d| d.get(b"Kids")).and_then(Object::as_array) {
+            pending.extend(kids.iter().filter_map(|k| k.as_reference().ok()));
+        }
+    }
+    seen
+}
+
+fn annotations(doc: &Document, page: ObjectId) -> Vec<ObjectId> {
+    doc.get_dictionary(page).and_then(|p| p.get(b"Annots")).ok()
+        .and_then(|a| doc.dereference(a).ok())
+        .and_then(|(_, a)| a.as_array().ok())
+        .map(|annots| annots.iter().filter_map(|a| a.as_reference().ok()).collect())
+        .unwrap_or_default()
+}
+
+/// The document's form dictionary, created (or moved out of the catalog) as an object.
+fn acroform(doc: &mut Document) -> Result<ObjectId, String> {
+    let id = match doc.catalog().map_err(|e| e.to_string())?.get(b"AcroForm") {
+        Ok(Object::Reference(id)) => *id,
+        Ok(Object::Dictionary(form)) => {
+            let form = form.clone();
+            doc.add_object(form)
+        }
+        _ => doc.add_object(dictionary! {}),
+    };
+    doc.catalog_mut().map_err(|e| e.to_string())?.set("AcroForm", id);
+    Ok(id)
+}
+
+/// Appends `item` to the array at `key` of the dictionary `id`, creating it if missing.
+fn push_to_array(doc: &mut Document, id: ObjectId, key: &[u8], item: Object) -> Result<(), String> {
+    let existing = doc.get_dictionary(id).map_err(|e| e.to_string())?.get(key).ok().cloned();
+    match existing {
+        Some(Object::Reference(array)) => doc.get_object_mut(array).and_then(Object::as_array_mut).map_err(|e| e.to_string())?.push(item),
+        Some(Object::Array(mut items)) => {
+            items.push(item);
+            doc.get_dictionary_mut(id).map_err(|e| e.to_string())?.set(key, items);
+        }
+        _ => doc.get_dictionary_mut(id).map_err(|e| e.to_string())?.set(key, vec![item]),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lopdf::StringFormat;
+
+    /// Two pages; the first carries a widget as `\formtext{client}` leaves it.
+    fn contract() -> Vec<u8> {
+        let mut doc = Document::with_version("1.5");
+        let pages_id = doc.new_object_id();
+        let marked = doc.add_object(dictionary! {
+            "Type" => "Annot",
+            "Subtype" => "Widget",
+            "FT" => "Tx",
+            "T" => Object::String(b"client".to_vec(), StringFormat::Literal),
+            "Rect" => vec![72.into(), 700.into(), 214.into(), 712.into()],
+        });
+        let kids: Vec<Object> = (0..2).map(|n| {
+            let content = doc.add_object(Stream::new(dictionary! {}, b"BT (Contract) Tj ET".to_vec()));
+            let mut page = dictionary! { "Type" => "Page", "Parent" => pages_id, "Contents" => content, "MediaBox" => vec![0.into(), 0.into(), 595.into(), 842.into()] };
+            if n == 0 {
+                page.set("Annots", vec![marked.into()]);
+            }
+            doc.add_object(page).into()
+        }).collect();
+        doc.objects.insert(pages_id, Object::Dictionary(dictionary! { "Type" => "Pages", "Kids" => kids, "Count" => 2 }));
+        let catalog = doc.add_object(dictionary! { "Type" => "Catalog", "Pages" => pages_id });
+        doc.trailer.set("Root", catalog);
+        let mut out = Vec::new();
+        doc.save_to(&mut out).unwrap();
+        out
+    }
+
+    fn spec(name: &str, kind: &str, page: i64) -> FormFieldSpec {
+        FormFieldSpec { name: name.to_string(), kind: kind.to_string(), page, rect: [72.0, 100.0, 12.0, 12.0], multiline: false }
+    }
+
+    #[test]
+    fn test_add_fields() {
+        let fields = [spec("agree", "checkbox", 1), spec("client", "text", -1), spec("signature", "signature", 2)];
+        let (pdf, count) = add_fields(&contract(), &fields).unwrap();
+        assert_eq!(count, 3, "the two 'client' widgets share a field");
+
+        let doc = Document::load_mem(&pdf).unwrap();
+        let form = doc.catalog().unwrap().get(b"AcroForm").and_then(|f| doc.dereference(f)).unwrap().1.as_dict().unwrap().clone();
+        assert!(form.get(b"NeedAppearances").unwrap().as_bool().unwrap());
+        let fields: Vec<&Dictionary> = form.get(b"Fields").unwrap().as_array().unwrap().iter()
+            .map(|f| doc.get_dictionary(f.as_reference().unwrap()).unwrap())
+            .collect();
+        let names: Vec<&[u8]> = fields.iter().map(|f| f.get(b"T").unwrap().as_str().unwrap()).collect();
+        assert_eq!(names, [b"client".as_slice(), b"agree", b"signature"]);
+
+        let client = fields[0];
+        assert_eq!(client.get(b"FT").unwrap().as_name().unwrap(), b"Tx");
+        let kids = client.get(b"Kids").unwrap().as_array().unwrap();
+        assert_eq!(kids.len(), 2);
+        let pages = doc.get_pages();
+        let kid_pages: Vec<ObjectId> = kids.iter().map(|k| doc.get_dictionary(k.as_reference().unwrap()).unwrap().get(b"P").unwrap().as_reference().unwrap()).collect();
+        assert_eq!(kid_pages, [pages[&1], pages[&2]]);
+
+        let agree = fields[1];
+        assert_eq!(agree.get(b"AS").unwrap().as_name().unwrap(), b"Off");
+        let states = agree.get(b"AP").unwrap().as_dict().unwrap().get(b"N").unwrap().as_dict().unwrap();
+        assert!(states.has(b"Yes") && states.has(b"Off"));
+        assert_eq!(fields[2].get(b"FT").unwrap().as_name().unwrap(), b"Sig");
+    }
+
+    #[test]
+    fn test_nothing_marked() {
+        let mut doc = Document::load_mem(&contract()).unwrap();
+        for object in doc.objects.values_mut() {
+            if let Ok(page) = object.as_dict_mut() {
+                page.remove(b"Annots");
+            }
+        }
+        let mut pdf = Vec::new();
+        doc.save_to(&mut pdf).unwrap();
+        assert_eq!(add_fields(&pdf, &[]).unwrap(), (pdf, 0));
+        assert!(add_fields(&contract(), &[spec("x", "text", 3)]).is_err());
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(validate(&[spec("agree", "checkbox", 1)]).is_ok());
+        assert!(validate(&[spec("a.b", "text", 1)]).is_err());
+        assert!(validate(&[spec("agree", "radio", 1)]).is_err());
+        assert!(validate(&[spec("agree", "text", 0)]).is_err());
+        let sources = ["\\documentclass{article}\n\\usepackage{amsmath,tachyonform}".to_string()];
+        assert!(uses_package(sources.iter()));
+        assert!(!uses_package(["% \\usepackage{tachyonform}".to_string()].iter()));
+    }
+}
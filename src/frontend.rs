@@ -0,0 +1,70 @@
+//! The browser editor and live preview (`public/`), embedded in the binary so the server
+//! does not depend on its working directory. Debug builds read the files from disk, so
+//! edits show up on reload. Disabled with FRONTEND_ENABLED=false.
+
+use axum::extract::{Path, State};
+use axum::http::{header, HeaderMap, HeaderValue, StatusCode, Uri};
+use axum::response::{IntoResponse, Response};
+use rust_embed::RustEmbed;
+
+use crate::services::AppState;
+
+#[derive(RustEmbed)]
+#[folder = "public/"]
+struct Assets;
+
+/// Serves an embedded asset, `index.html` for `/`. Assets are revalidated with an ETag of
+/// their content hash, so a new build is picked up without a stale cache.
+pub async fn asset_handler(uri: Uri, headers: HeaderMap) -> Response {
+    let path = match uri.path().trim_start_matches('/') {
+        "" => "index.html",
+        path => path,
+    };
+    let Some(file) = Assets::get(path) else {
+        return (StatusCode::NOT_FOUND, "Not found".to_string()).into_response();
+    };
+    let etag = format!("\"{}\"", hex::encode(&file.metadata.sha256_hash()[..16]));
+    if headers.get(header::IF_NONE_MATCH).is_some_and(|tag| tag.as_bytes() == etag.as_bytes()) {
+        return StatusCode::NOT_MODIFIED.into_response();
+    }
+    (
+        [
+            (header::CONTENT_TYPE, HeaderValue::from_str(file.metadata.mimetype()).unwrap_or(HeaderValue::from_static("application/octet-stream"))),
+            (header::ETAG, HeaderValue::from_str(&etag).unwrap()),
+            (header::CACHE_CONTROL, HeaderValue::from_static("no-cache")),
+        ],
+        file.data,
+    )
+        .into_response()
+}
+
+/// The editor page for a shared snippet; app.js loads it from `GET /playground/{id}`.
+pub async fn playground_page_handler(State(state): State<AppState>, Path(id): Path<String>, headers: HeaderMap) -> Response {
+    if state.playground.get(&id).await.is_none() {
+        return (StatusCode::NOT_FOUND, format!("Snippet {} not found or expired", id)).into_response();
+    }
+    asset_handler(Uri::from_static("/index.html"), headers).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http_body_util::BodyExt;
+
+    #[tokio::test]
+    async fn test_serves_embedded_assets() {
+        let index = asset_handler("/".parse().unwrap(), HeaderMap::new()).await;
+        assert_eq!(index.status(), StatusCode::OK);
+        assert_eq!(index.headers()[header::CONTENT_TYPE], "text/html");
+        let etag = index.headers()[header::ETAG].clone();
+        assert!(String::from_utf8(index.into_body().collect().await.unwrap().to_bytes().to_vec()).unwrap().contains("viewer.js"));
+
+        let script = asset_handler("/viewer.js".parse().unwrap(), HeaderMap::new()).await;
+        assert_eq!(script.headers()[header::CONTENT_TYPE], "text/javascript");
+
+        let mut revalidate = HeaderMap::new();
+        revalidate.insert(header::IF_NONE_MATCH, etag);
+        assert_eq!(asset_handler("/index.html".parse().unwrap(), revalidate).await.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(asset_handler("/../Cargo.toml".parse().unwrap(), HeaderMap::new()).await.status(), StatusCode::NOT_FOUND);
+    }
+}
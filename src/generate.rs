@@ -0,0 +1,952 @@
+use std::collections::BTreeMap;
+use xxhash_rust::xxh64::xxh64;
+
+use crate::barcode::BarcodeGenerator;
+use crate::latex::{escape, fill_template, href};
+use crate::locale::{currency, Locale};
+use crate::models::{BrandingProfile, CertificateRequest, ChartRequest, ChartSeries, InvoiceParty, InvoiceRequest, ReportRequest, ReportSection, Resume, ResumeWork};
+use crate::render::{AuxFile, ChartRenderer, Table, TableRenderer, DEFAULT_LONG_TABLE_ROWS};
+
+const INVOICE_TEMPLATE: &str = include_str!("../templates/invoice.tex");
+const CERTIFICATE_TEMPLATE: &str = include_str!("../templates/certificate.tex");
+const CERTIFICATE_PAGE_TEMPLATE: &str = include_str!("../templates/certificate-page.tex");
+const REPORT_TEMPLATE: &str = include_str!("../templates/report.tex");
+
+/// Bundled CV styles selectable via `?template=` (or `meta.theme`).
+const RESUME_TEMPLATES: &[(&str, &str)] = &[
+    ("classic", include_str!("../templates/resume-classic.tex")),
+    ("modern", include_str!("../templates/resume-modern.tex")),
+];
+
+/// Upper bound on recipients per certificate request.
+pub const MAX_CERTIFICATES: usize = 500;
+
+/// Invoice labels for one language; numbers and dates are formatted by [`Locale`].
+struct InvoiceLabels {
+    code: &'static str,
+    invoice: &'static str,
+    number: &'static str,
+    issued: &'static str,
+    due: &'static str,
+    bill_to: &'static str,
+    tax_id: &'static str,
+    description: &'static str,
+    quantity: &'static str,
+    unit_price: &'static str,
+    tax: &'static str,
+    amount: &'static str,
+    subtotal: &'static str,
+    total: &'static str,
+    notes: &'static str,
+}
+
+const INVOICE_LABELS: &[InvoiceLabels] = &[
+    InvoiceLabels {
+        code: "en",
+        invoice: "Invoice", number: "Invoice no.", issued: "Date", due: "Due date", bill_to: "Bill to",
+        tax_id: "Tax ID", description: "Description", quantity: "Qty", unit_price: "Unit price",
+        tax: "Tax", amount: "Amount", subtotal: "Subtotal", total: "Total", notes: "Notes",
+    },
+    InvoiceLabels {
+        code: "es",
+        invoice: "Factura", number: "Factura n.º", issued: "Fecha", due: "Vencimiento", bill_to: "Facturar a",
+        tax_id: "NIF", description: "Descripción", quantity: "Cant.", unit_price: "Precio unitario",
+        tax: "IVA", amount: "Importe", subtotal: "Base imponible", total: "Total", notes: "Notas",
+    },
+    InvoiceLabels {
+        code: "de",
+        invoice: "Rechnung", number: "Rechnungsnr.", issued: "Datum", due: "Fällig am", bill_to: "Rechnung an",
+        tax_id: "USt-IdNr.", description: "Beschreibung", quantity: "Menge", unit_price: "Einzelpreis",
+        tax: "USt.", amount: "Betrag", subtotal: "Zwischensumme", total: "Gesamt", notes: "Hinweise",
+    },
+    InvoiceLabels {
+        code: "fr",
+        invoice: "Facture", number: "Facture n°", issued: "Date", due: "Échéance", bill_to: "Facturé à",
+        tax_id: "N° TVA", description: "Désignation", quantity: "Qté", unit_price: "Prix unitaire",
+        tax: "TVA", amount: "Montant", subtotal: "Total HT", total: "Total TTC", notes: "Remarques",
+    },
+];
+
+/// Computed invoice amounts, rounded to the currency's minor unit.
+#[derive(Debug, PartialEq)]
+pub struct InvoiceTotals {
+    pub lines: Vec<f64>,
+    /// (rate in percent, taxable base, tax amount), by ascending rate
+    pub taxes: Vec<(f64, f64, f64)>,
+    pub subtotal: f64,
+    pub total: f64,
+}
+
+pub struct InvoiceGenerator;
+
+impl InvoiceGenerator {
+    pub fn totals(req: &InvoiceRequest) -> Result<InvoiceTotals, String> {
+        if req.items.is_empty() {
+            return Err("Invoice needs at least one line item".to_string());
+        }
+        let digits = currency(&req.currency)?.1;
+
+        let mut lines = Vec::with_capacity(req.items.len());
+        // Keyed by rate in thousandths of a percent so f64 rates can be grouped
+        let mut by_rate: BTreeMap<i64, (f64, f64)> = BTreeMap::new();
+        for (i, item) in req.items.iter().enumerate() {
+            let rate = item.tax_rate.or(req.tax_rate).unwrap_or(0.0);
+            if !item.quantity.is_finite() || !item.unit_price.is_finite() {
+                return Err(format!("Item {} has a non-numeric quantity or price", i + 1));
+            }
+            if !(0.0..=100.0).contains(&rate) {
+                return Err(format!("Item {} has tax rate {} outside 0-100%", i + 1, rate));
+            }
+            let net = round_to(item.quantity * item.unit_price, digits);
+            lines.push(net);
+            by_rate.entry((rate * 1000.0).round() as i64).or_insert((rate, 0.0)).1 += net;
+        }
+
+        let taxes: Vec<(f64, f64, f64)> = by_rate.into_values()
+            .filter(|(rate, _)| *rate > 0.0)
+            .map(|(rate, base)| (rate, round_to(base, digits), round_to(base * rate / 100.0, digits)))
+            .collect();
+        let subtotal = round_to(lines.iter().sum(), digits);
+        let total = round_to(subtotal + taxes.iter().map(|t| t.2).sum::<f64>(), digits);
+        Ok(InvoiceTotals { lines, taxes, subtotal, total })
+    }
+
+    /// Fills the bundled invoice template. Returns the source and the files it
+    /// references (the branding logo).
+    pub fn to_latex(req: &InvoiceRequest, branding: Option<&BrandingProfile>) -> Result<(String, Vec<AuxFile>), String> {
+        let locale = Locale::resolve(req.locale.as_deref())?;
+        let labels = INVOICE_LABELS.iter().find(|l| l.code == locale.code).unwrap_or(&INVOICE_LABELS[0]);
+        let totals = Self::totals(req)?;
+        // Currency was validated by totals() before any amount is formatted
+        let money = |amount: f64| locale.money(amount, &req.currency);
+
+        let mut meta = vec![format!("{} {}", labels.number, escape(&req.number))];
+        meta.push(format!("{}: {}", labels.issued, locale.date(&req.issue_date)));
+        if let Some(due) = &req.due_date {
+            meta.push(format!("{}: {}", labels.due, locale.date(due)));
+        }
+
+        let mut items = String::new();
+        for (item, net) in req.items.iter().zip(&totals.lines) {
+            let rate = item.tax_rate.or(req.tax_rate).unwrap_or(0.0);
+            items.push_str(&format!(
+                "{} & {} & {} & {}\\% & {} \\\\\n",
+                escape(&item.description),
+                locale.number(item.quantity, if item.quantity.fract() == 0.0 { 0 } else { 2 }),
+                money(item.unit_price),
+                locale.number(rate, if rate.fract() == 0.0 { 0 } else { 2 }),
+                money(*net)
+            ));
+        }
+
+        let mut summary = format!("\\multicolumn{{4}}{{r}}{{{}}} & {} \\\\\n", labels.subtotal, money(totals.subtotal));
+        for (rate, base, tax) in &totals.taxes {
+            summary.push_str(&format!(
+                "\\multicolumn{{4}}{{r}}{{{} {}\\% ({})}} & {} \\\\\n",
+                labels.tax,
+                locale.number(*rate, if rate.fract() == 0.0 { 0 } else { 2 }),
+                money(*base),
+                money(*tax)
+            ));
+        }
+        summary.push_str(&format!("\\multicolumn{{4}}{{r}}{{\\bfseries {}}} & \\bfseries {} \\\\\n", labels.total, money(totals.total)));
+
+        let notes = match &req.notes {
+            Some(n) if !n.trim().is_empty() => format!("\\textbf{{{}}}\\\\\n{}\n", labels.notes, multiline(n)),
+            _ => String::new(),
+        };
+
+        let header = [labels.description, labels.quantity, labels.unit_price, labels.tax, labels.amount].join(" & ");
+        let source = fill_template(INVOICE_TEMPLATE, &[
+            ("BRANDING", &Branding::preamble(branding)),
+            ("LOGO", &Branding::logo(branding, "1.5cm")),
+            ("SELLER", &Self::party_block(&req.seller, labels)),
+            ("TITLE", labels.invoice),
+            ("META", &meta.join("\\\\\n")),
+            ("BILL_TO", labels.bill_to),
+            ("BUYER", &Self::party_block(&req.buyer, labels)),
+            ("ITEM_HEADER", &header),
+            ("ITEMS", &items),
+            ("TOTALS", &summary),
+            ("NOTES", &notes),
+        ]);
+        Ok((source, Branding::files(branding)))
+    }
+
+    fn party_block(party: &InvoiceParty, labels: &InvoiceLabels) -> String {
+        let mut lines = vec![format!("{{\\large\\bfseries {}}}", escape(&party.name))];
+        lines.extend(party.address.iter().filter(|l| !l.trim().is_empty()).map(|l| escape(l)));
+        if let Some(tax_id) = &party.tax_id {
+            lines.push(format!("{}: {}", labels.tax_id, escape(tax_id)));
+        }
+        if let Some(email) = &party.email {
+            lines.push(escape(email));
+        }
+        lines.join("\\\\\n")
+    }
+}
+
+// ============================================================================
+// Certificates
+// ============================================================================
+
+pub struct CertificateGenerator;
+
+impl CertificateGenerator {
+    /// Stable verification id: the same recipient, course and date always get the same id.
+    pub fn certificate_id(recipient: &str, course: &str, date: &str) -> String {
+        format!("{:016x}", xxh64(format!("{}\n{}\n{}", recipient, course, date).as_bytes(), 0))
+    }
+
+    /// Builds one page per recipient. Returns the source and the files it references
+    /// (background and QR codes), which must be placed next to it.
+    pub fn to_latex(
+        req: &CertificateRequest,
+        recipients: &[String],
+        background: Option<&AuxFile>,
+        branding: Option<&BrandingProfile>,
+    ) -> Result<(String, Vec<AuxFile>), String> {
+        let mut files = Branding::files(branding);
+        let logo = Branding::logo(branding, "2cm");
+        let background_cmd = match background {
+            Some((name, data)) => {
+                files.push((name.clone(), data.clone()));
+                format!("\\AddToShipoutPictureBG{{\\includegraphics[width=\\paperwidth,height=\\paperheight]{{{}}}}}", name)
+            }
+            None => String::new(),
+        };
+
+        let title = escape(req.title.as_deref().unwrap_or("Certificate of Completion"));
+        let course = escape(&req.course);
+        let date = Locale::resolve(req.locale.as_deref())?.date(&req.date);
+        let issuer = req.issuer.as_deref().map(escape).unwrap_or_default();
+
+        let mut pages = String::new();
+        for recipient in recipients {
+            let id = Self::certificate_id(recipient, &req.course, &req.date);
+            let verification = match &req.verify_url {
+                Some(url) => {
+                    let url = if url.contains("{id}") { url.replace("{id}", &id) } else { format!("{}{}", url, id) };
+                    let grid = BarcodeGenerator::qr(&url)?;
+                    let qr_name = format!("qr-{}.png", id);
+                    files.push((qr_name.clone(), BarcodeGenerator::to_png(&grid, 8)?));
+                    format!(
+                        "\\begin{{minipage}}[b]{{0.75\\textwidth}}\\small Certificate ID: \\texttt{{{}}}\\\\\nVerify at \\texttt{{{}}}\\end{{minipage}}\\hfill\n\\includegraphics[width=2.5cm]{{{}}}",
+                        id, escape(&url), qr_name
+                    )
+                }
+                None => format!("{{\\small Certificate ID: \\texttt{{{}}}}}", id),
+            };
+
+            pages.push_str(&fill_template(CERTIFICATE_PAGE_TEMPLATE, &[
+                ("LOGO", &logo),
+                ("TITLE", &title),
+                ("RECIPIENT", &escape(recipient)),
+                ("COURSE", &course),
+                ("DATE", &date),
+                ("ISSUER", &issuer),
+                ("VERIFICATION", &verification),
+            ]));
+        }
+
+        let source = fill_template(CERTIFICATE_TEMPLATE, &[
+            ("BACKGROUND", &background_cmd),
+            ("BRANDING", &Branding::preamble(branding)),
+            ("PAGES", &pages),
+        ]);
+        Ok((source, files))
+    }
+
+    /// File-system friendly name for an individual certificate.
+    pub fn file_name(recipient: &str, id: &str) -> String {
+        let slug: String = recipient.chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_lowercase() } else { '-' })
+            .collect();
+        let slug = slug.split('-').filter(|p| !p.is_empty()).collect::<Vec<_>>().join("-");
+        format!("{}-{}.pdf", if slug.is_empty() { "certificate" } else { &slug }, id)
+    }
+}
+
+// ============================================================================
+// Resumes (JSON Resume)
+// ============================================================================
+
+pub struct ResumeGenerator;
+
+impl ResumeGenerator {
+    /// Renders a JSON Resume document with one of the bundled templates. Each template
+    /// defines `\cvsection`, `\cventry{dates}{heading}{body}` and `\cvitem{label}{text}`.
+    /// Dates are written in `locale` (English by default).
+    pub fn to_latex(resume: &Resume, template: Option<&str>, locale: Option<&str>) -> Result<String, String> {
+        if resume.basics.name.trim().is_empty() {
+            return Err("basics.name is required".to_string());
+        }
+        let name = template.or(resume.meta.theme.as_deref()).unwrap_or("classic");
+        let template = RESUME_TEMPLATES.iter().find(|(n, _)| *n == name).map(|(_, t)| *t)
+            .ok_or_else(|| format!("Unknown resume template '{}' (expected classic or modern)", name))?;
+        let locale = Locale::resolve(locale)?;
+
+        let basics = &resume.basics;
+        let mut contact = Vec::new();
+        if let Some(location) = &basics.location {
+            let parts: Vec<&str> = [&location.city, &location.region, &location.country_code]
+                .into_iter().flatten().map(|s| s.as_str()).filter(|s| !s.trim().is_empty()).collect();
+            if !parts.is_empty() {
+                contact.push(escape(&parts.join(", ")));
+            }
+        }
+        if let Some(email) = &basics.email { contact.push(href(&format!("mailto:{}", email), email)); }
+        if let Some(phone) = &basics.phone { contact.push(escape(phone)); }
+        if let Some(url) = &basics.url { contact.push(href(url, url.trim_start_matches("https://").trim_start_matches("http://"))); }
+        for profile in &basics.profiles {
+            let text = format!("{}: {}", profile.network, profile.username.as_deref().unwrap_or_default());
+            contact.push(match &profile.url {
+                Some(url) => href(url, &text),
+                None => escape(&text),
+            });
+        }
+
+        let mut sections = String::new();
+        Self::work_section(&mut sections, locale, "Experience", &resume.work);
+        Self::section(&mut sections, "Education", resume.education.iter().map(|e| {
+            let degree = [e.study_type.as_deref(), e.area.as_deref()].into_iter().flatten().collect::<Vec<_>>().join(" in ");
+            let mut body = Vec::new();
+            if let Some(score) = &e.score { body.push(format!("Score: {}", escape(score))); }
+            if !e.courses.is_empty() { body.push(format!("Courses: {}", escape(&e.courses.join(", ")))); }
+            Self::entry(&date_range(locale, &e.start_date, &e.end_date), &heading(Some(&degree), Some(&e.institution)), &body.join("\\\\ "))
+        }));
+        Self::section(&mut sections, "Projects", resume.projects.iter().map(|p| {
+            Self::entry(&date_range(locale, &p.start_date, &p.end_date), &heading(Some(&p.name), None), &body(p.description.as_deref(), &p.highlights))
+        }));
+        Self::work_section(&mut sections, locale, "Volunteering", &resume.volunteer);
+        Self::section(&mut sections, "Awards", resume.awards.iter().map(|a| {
+            Self::entry(&a.date.as_deref().map(|d| locale.month_year(d)).unwrap_or_default(), &heading(Some(&a.title), a.awarder.as_deref()), &body(a.summary.as_deref(), &[]))
+        }));
+        Self::section(&mut sections, "Certificates", resume.certificates.iter().map(|c| {
+            Self::entry(&c.date.as_deref().map(|d| locale.month_year(d)).unwrap_or_default(), &heading(Some(&c.name), c.issuer.as_deref()), "")
+        }));
+        Self::section(&mut sections, "Publications", resume.publications.iter().map(|p| {
+            Self::entry(&p.release_date.as_deref().map(|d| locale.month_year(d)).unwrap_or_default(), &heading(Some(&p.name), p.publisher.as_deref()), &body(p.summary.as_deref(), &[]))
+        }));
+        Self::section(&mut sections, "Skills", resume.skills.iter().map(|s| {
+            let mut text = escape(&s.keywords.join(", "));
+            if let Some(level) = &s.level { text = format!("{} ({})", text, escape(level)); }
+            format!("\\cvitem{{{}}}{{{}}}\n", escape(&s.name), text)
+        }));
+        Self::section(&mut sections, "Languages", resume.languages.iter().map(|l| {
+            format!("\\cvitem{{{}}}{{{}}}\n", escape(&l.language), escape(l.fluency.as_deref().unwrap_or_default()))
+        }));
+        Self::section(&mut sections, "Interests", resume.interests.iter().map(|i| {
+            format!("\\cvitem{{{}}}{{{}}}\n", escape(&i.name), escape(&i.keywords.join(", ")))
+        }));
+
+        let summary = basics.summary.as_deref().filter(|s| !s.trim().is_empty())
+            .map(|s| format!("\\vspace{{8pt}}\n{}\n", multiline(s)))
+            .unwrap_or_default();
+
+        Ok(fill_template(template, &[
+            ("NAME", &escape(&basics.name)),
+            ("LABEL", &basics.label.as_deref().map(escape).unwrap_or_default()),
+            ("CONTACT", &contact.join(" \\textbullet{} ")),
+            ("SUMMARY", &summary),
+            ("SECTIONS", &sections),
+        ]))
+    }
+
+    fn work_section(out: &mut String, locale: &Locale, title: &str, entries: &[ResumeWork]) {
+        Self::section(out, title, entries.iter().map(|w| {
+            let org = match (&w.name, &w.location) {
+                (Some(name), Some(location)) => Some(format!("{}, {}", name, location)),
+                (name, _) => name.clone(),
+            };
+            Self::entry(&date_range(locale, &w.start_date, &w.end_date), &heading(w.position.as_deref(), org.as_deref()), &body(w.summary.as_deref(), &w.highlights))
+        }));
+    }
+
+    /// Appends a section, skipping it entirely when there are no entries.
+    fn section(out: &mut String, title: &str, entries: impl Iterator<Item = String>) {
+        let entries: String = entries.collect();
+        if !entries.is_empty() {
+            out.push_str(&format!("\\cvsection{{{}}}\n{}", title, entries));
+        }
+    }
+
+    fn entry(dates: &str, heading: &str, body: &str) -> String {
+        format!("\\cventry{{{}}}{{{}}}{{{}}}\n", dates, heading, body)
+    }
+}
+
+/// "\textbf{Title}, \textit{Organization}" with either part optional (inputs unescaped).
+fn heading(title: Option<&str>, organization: Option<&str>) -> String {
+    let title = title.filter(|t| !t.trim().is_empty()).map(|t| format!("\\textbf{{{}}}", escape(t)));
+    let organization = organization.filter(|o| !o.trim().is_empty()).map(|o| format!("\\textit{{{}}}", escape(o)));
+    [title, organization].into_iter().flatten().collect::<Vec<_>>().join(", ")
+}
+
+/// A summary paragraph followed by a bullet list of highlights.
+fn body(summary: Option<&str>, highlights: &[String]) -> String {
+    let mut out = summary.filter(|s| !s.trim().is_empty()).map(multiline).unwrap_or_default();
+    if !highlights.is_empty() {
+        out.push_str("\\begin{itemize}");
+        for highlight in highlights {
+            out.push_str(&format!("\\item {}", escape(highlight)));
+        }
+        out.push_str("\\end{itemize}");
+    }
+    out
+}
+
+/// "2019-03-01" -> "Mar 2019"; a missing end date means the position is current.
+fn date_range(locale: &Locale, start: &Option<String>, end: &Option<String>) -> String {
+    match (start, end) {
+        (Some(start), Some(end)) => format!("{} -- {}", locale.month_year(start), locale.month_year(end)),
+        (Some(start), None) => format!("{} -- {}", locale.month_year(start), locale.present),
+        (None, Some(end)) => locale.month_year(end),
+        (None, None) => String::new(),
+    }
+}
+
+// ============================================================================
+// Reports (tabular data)
+// ============================================================================
+
+/// Ways grouped values combine; `count` also works on text columns.
+const AGGREGATES: &[&str] = &["sum", "mean", "count", "min", "max"];
+
+pub struct ReportGenerator;
+
+impl ReportGenerator {
+    /// Fills the bundled report template with one block per section. Returns the source
+    /// and the files it references (the branding logo).
+    pub fn to_latex(req: &ReportRequest, branding: Option<&BrandingProfile>) -> Result<(String, Vec<AuxFile>), String> {
+        if req.sections.is_empty() {
+            return Err("Report needs at least one section".to_string());
+        }
+        let table = Table::from_request(req.csv.as_deref(), req.data.as_ref())?;
+        let report = Report { table: &table, locale: Locale::resolve(req.locale.as_deref())?, decimals: req.decimals.unwrap_or(2).min(6) };
+
+        let mut sections = String::new();
+        for (i, section) in req.sections.iter().enumerate() {
+            let body = report.section(section).map_err(|e| format!("Section {}: {}", i + 1, e))?;
+            if let Some(title) = section.title.as_deref().filter(|t| !t.trim().is_empty()) {
+                sections.push_str(&format!("\\section*{{{}}}\n", escape(title)));
+            }
+            if let Some(text) = section.text.as_deref().filter(|t| !t.trim().is_empty()) {
+                let paragraphs: Vec<String> = text.split("\n\n").map(|p| escape(p.trim())).filter(|p| !p.is_empty()).collect();
+                sections.push_str(&format!("{}\n\n", paragraphs.join("\n\n")));
+            }
+            sections.push_str(&body);
+            sections.push('\n');
+        }
+
+        let subtitle = req.subtitle.as_deref().map(|s| format!("{{\\large\\color{{brandsecondary}} {}}}\\par", escape(s))).unwrap_or_default();
+        let source = fill_template(REPORT_TEMPLATE, &[
+            ("BRANDING", &Branding::preamble(branding)),
+            ("LOGO", &Branding::logo(branding, "1.2cm")),
+            ("TITLE", &escape(&req.title)),
+            ("SUBTITLE", &subtitle),
+            ("SECTIONS", &sections),
+        ]);
+        Ok((source, Branding::files(branding)))
+    }
+}
+
+/// What a grouped table or chart shows per group: a column's aggregate, or the row count.
+type Measure = (String, Option<usize>);
+
+struct Report<'a> {
+    table: &'a Table,
+    locale: &'static Locale,
+    decimals: usize,
+}
+
+impl Report<'_> {
+    fn section(&self, section: &ReportSection) -> Result<String, String> {
+        match section.kind.as_str() {
+            "summary" => self.summary(section),
+            "table" => self.table_section(section),
+            "chart" => self.chart(section),
+            "text" if section.text.is_some() => Ok(String::new()),
+            "text" => Err("Text sections need \"text\"".to_string()),
+            other => Err(format!("Unknown section type '{}' (expected summary, table, chart or text)", other)),
+        }
+    }
+
+    /// Count, total, mean, minimum and maximum of each numeric column.
+    fn summary(&self, section: &ReportSection) -> Result<String, String> {
+        let columns = if section.columns.is_empty() {
+            (0..self.table.header.len()).filter(|c| self.numbers(*c).is_some()).collect()
+        } else {
+            self.columns(&section.columns)?
+        };
+        if columns.is_empty() {
+            return Err("The data has no numeric columns to summarize".to_string());
+        }
+
+        let mut rows = Vec::new();
+        for column in columns {
+            let values: Vec<f64> = self.numbers(column)
+                .ok_or_else(|| format!("Column '{}' is not numeric", self.table.header[column]))?
+                .into_iter().flatten().collect();
+            let mut row = vec![escape(&self.table.header[column]), self.locale.number(values.len() as f64, 0)];
+            for op in ["sum", "mean", "min", "max"] {
+                row.push(self.format(aggregate(op, &values), self.digits(op, column)));
+            }
+            rows.push(row);
+        }
+        let header: Vec<String> = ["Column", "Count", "Total", "Mean", "Min", "Max"].iter().map(|h| h.to_string()).collect();
+        Ok(center(&TableRenderer::typeset("lrrrrr", &header, &rows, None, None, DEFAULT_LONG_TABLE_ROWS)))
+    }
+
+    fn table_section(&self, section: &ReportSection) -> Result<String, String> {
+        let (align, header, rows, footer) = match &section.group_by {
+            Some(group_by) => {
+                let key = self.column(group_by)?;
+                let op = operation(section)?;
+                let measures = self.measures(section, op, Some(key))?;
+                let mut rows = Vec::new();
+                for (value, members) in self.groups(key) {
+                    let mut row = vec![escape(&value)];
+                    row.extend(measures.iter().map(|m| self.measure(m, op, &members)));
+                    rows.push(row);
+                }
+                let everything: Vec<usize> = (0..self.table.rows.len()).collect();
+                let label = if matches!(op, "sum" | "count") { "Total" } else { "All" };
+                let footer = section.totals.then(|| {
+                    let mut row = vec![format!("\\textbf{{{}}}", label)];
+                    row.extend(measures.iter().map(|m| format!("\\textbf{{{}}}", self.measure(m, op, &everything))));
+                    row
+                });
+                let mut header = vec![escape(group_by)];
+                header.extend(measures.iter().map(|(label, _)| escape(label)));
+                (format!("l{}", "r".repeat(measures.len())), header, rows, footer)
+            }
+            None => {
+                let columns = if section.columns.is_empty() { (0..self.table.header.len()).collect() } else { self.columns(&section.columns)? };
+                let numeric: Vec<Option<Vec<Option<f64>>>> = columns.iter().map(|c| self.numbers(*c)).collect();
+                let shown = section.limit.unwrap_or(self.table.rows.len()).min(self.table.rows.len());
+                let rows = (0..shown)
+                    .map(|r| columns.iter().zip(&numeric).map(|(c, values)| match values {
+                        Some(values) => self.format(values[r], self.digits("sum", *c)),
+                        None => escape(self.table.rows[r][*c].trim()),
+                    }).collect())
+                    .collect();
+                let footer = section.totals.then(|| {
+                    columns.iter().zip(&numeric).enumerate().map(|(i, (c, values))| match values {
+                        Some(values) => {
+                            let values: Vec<f64> = values.iter().flatten().copied().collect();
+                            format!("\\textbf{{{}}}", self.format(aggregate("sum", &values), self.digits("sum", *c)))
+                        }
+                        None if i == 0 => "\\textbf{Total}".to_string(),
+                        None => String::new(),
+                    }).collect()
+                });
+                let align = numeric.iter().map(|n| if n.is_some() { 'r' } else { 'l' }).collect();
+                let header = columns.iter().map(|c| escape(&self.table.header[*c])).collect();
+                (align, header, rows, footer)
+            }
+        };
+        Ok(center(&TableRenderer::typeset(&align, &header, &rows, footer.as_deref(), None, DEFAULT_LONG_TABLE_ROWS)))
+    }
+
+    /// A pgfplots chart with one series per column, over the groups or the x column.
+    fn chart(&self, section: &ReportSection) -> Result<String, String> {
+        let (x, series, default_type, x_label) = match &section.group_by {
+            Some(group_by) => {
+                let key = self.column(group_by)?;
+                let op = operation(section)?;
+                let measures = self.measures(section, op, Some(key))?;
+                let groups = self.groups(key);
+                let x = groups.iter().map(|(value, _)| serde_json::Value::String(value.clone())).collect::<Vec<_>>();
+                let series = measures.iter()
+                    .map(|m| (m.0.clone(), groups.iter().map(|(_, members)| self.measure_value(m, op, members).unwrap_or(0.0)).collect()))
+                    .collect::<Vec<(String, Vec<f64>)>>();
+                (x, series, "bar", Some(group_by.clone()))
+            }
+            None => {
+                if section.columns.is_empty() {
+                    return Err("Charts need at least one column to plot".to_string());
+                }
+                let x = match &section.x {
+                    Some(name) => {
+                        let column = self.column(name)?;
+                        match self.numbers(column) {
+                            Some(values) => values.iter().map(|v| v.and_then(serde_json::Number::from_f64).map(serde_json::Value::Number).unwrap_or(serde_json::Value::Null)).collect(),
+                            None => self.table.rows.iter().map(|r| serde_json::Value::String(r[column].trim().to_string())).collect(),
+                        }
+                    }
+                    None => Vec::new(),
+                };
+                if x.iter().any(|v| v.is_null()) {
+                    return Err(format!("Column '{}' has empty x values", section.x.as_deref().unwrap_or_default()));
+                }
+                let mut series = Vec::new();
+                for column in self.columns(&section.columns)? {
+                    let values = self.numbers(column).ok_or_else(|| format!("Column '{}' is not numeric", self.table.header[column]))?;
+                    series.push((self.table.header[column].clone(), values.into_iter().map(|v| v.unwrap_or(0.0)).collect()));
+                }
+                (x, series, "line", section.x.clone())
+            }
+        };
+
+        let single = series.len() == 1;
+        let spec = ChartRequest {
+            chart_type: Some(section.chart.clone().unwrap_or_else(|| default_type.to_string())),
+            title: None,
+            x_label,
+            y_label: if single { series.first().map(|s| s.0.clone()) } else { None },
+            series: series.into_iter()
+                .map(|(name, y)| ChartSeries { name: if single { None } else { Some(name) }, x: x.clone(), y })
+                .collect(),
+            width: Some("15cm".to_string()),
+            height: Some("8cm".to_string()),
+            legend_position: None,
+            format: None,
+        };
+        Ok(center(&ChartRenderer::to_picture(&spec)?))
+    }
+
+    fn column(&self, name: &str) -> Result<usize, String> {
+        self.table.header.iter().position(|h| h.trim() == name.trim())
+            .ok_or_else(|| format!("Unknown column '{}' (the data has {})", name, self.table.header.join(", ")))
+    }
+
+    fn columns(&self, names: &[String]) -> Result<Vec<usize>, String> {
+        names.iter().map(|n| self.column(n)).collect()
+    }
+
+    /// The column's values when every non-empty cell is a number; empty cells are `None`.
+    fn numbers(&self, column: usize) -> Option<Vec<Option<f64>>> {
+        let values: Vec<Option<f64>> = self.table.rows.iter()
+            .map(|r| {
+                let cell = r[column].trim();
+                if cell.is_empty() { Ok(None) } else { parse_number(cell).map(Some).ok_or(()) }
+            })
+            .collect::<Result<_, _>>()
+            .ok()?;
+        values.iter().any(Option::is_some).then_some(values)
+    }
+
+    /// Distinct values of a column in order of first appearance, with their rows.
+    fn groups(&self, column: usize) -> Vec<(String, Vec<usize>)> {
+        let mut groups: Vec<(String, Vec<usize>)> = Vec::new();
+        for (i, row) in self.table.rows.iter().enumerate() {
+            let value = row[column].trim();
+            match groups.iter_mut().find(|(v, _)| v == value) {
+                Some((_, members)) => members.push(i),
+                None => groups.push((value.to_string(), vec![i])),
+            }
+        }
+        groups
+    }
+
+    /// The numeric columns a grouped section aggregates: the requested ones, all numeric
+    /// columns by default, or the row count alone when counting.
+    fn measures(&self, section: &ReportSection, op: &str, key: Option<usize>) -> Result<Vec<Measure>, String> {
+        if section.columns.is_empty() {
+            if op == "count" {
+                return Ok(vec![("Count".to_string(), None)]);
+            }
+            let numeric: Vec<Measure> = (0..self.table.header.len())
+                .filter(|c| Some(*c) != key && self.numbers(*c).is_some())
+                .map(|c| (self.table.header[c].clone(), Some(c)))
+                .collect();
+            return if numeric.is_empty() { Err("The data has no numeric columns to aggregate".to_string()) } else { Ok(numeric) };
+        }
+        self.columns(&section.columns)?.into_iter()
+            .map(|c| match self.numbers(c) {
+                Some(_) => Ok((self.table.header[c].clone(), Some(c))),
+                None if op == "count" => Ok((self.table.header[c].clone(), Some(c))),
+                None => Err(format!("Column '{}' is not numeric", self.table.header[c])),
+            })
+            .collect()
+    }
+
+    fn measure_value(&self, (_, column): &Measure, op: &str, rows: &[usize]) -> Option<f64> {
+        let Some(column) = column else { return Some(rows.len() as f64) };
+        if op == "count" {
+            return Some(rows.iter().filter(|r| !self.table.rows[**r][*column].trim().is_empty()).count() as f64);
+        }
+        let values: Vec<f64> = rows.iter().filter_map(|r| parse_number(self.table.rows[*r][*column].trim())).collect();
+        aggregate(op, &values)
+    }
+
+    fn measure(&self, measure: &Measure, op: &str, rows: &[usize]) -> String {
+        let digits = measure.1.map_or(0, |c| self.digits(op, c));
+        self.format(self.measure_value(measure, op, rows), digits)
+    }
+
+    /// Decimal places for an aggregate: none for counts, `decimals` for means, and as many
+    /// as the column's values have otherwise.
+    fn digits(&self, op: &str, column: usize) -> usize {
+        match op {
+            "count" => 0,
+            "mean" => self.decimals,
+            _ => self.table.rows.iter()
+                .filter_map(|r| r[column].trim().split_once('.').map(|(_, fraction)| fraction.len()))
+                .max()
+                .unwrap_or(0)
+                .min(6),
+        }
+    }
+
+    fn format(&self, value: Option<f64>, digits: usize) -> String {
+        value.map(|v| self.locale.number(v, digits)).unwrap_or_else(|| "--".to_string())
+    }
+}
+
+fn operation(section: &ReportSection) -> Result<&str, String> {
+    let op = section.aggregate.as_deref().unwrap_or("sum");
+    if AGGREGATES.contains(&op) {
+        Ok(op)
+    } else {
+        Err(format!("Unknown aggregate '{}' (expected {})", op, AGGREGATES.join(", ")))
+    }
+}
+
+fn aggregate(op: &str, values: &[f64]) -> Option<f64> {
+    match op {
+        "count" => Some(values.len() as f64),
+        "sum" => Some(values.iter().sum()),
+        _ if values.is_empty() => None,
+        "mean" => Some(values.iter().sum::<f64>() / values.len() as f64),
+        "min" => values.iter().copied().reduce(f64::min),
+        _ => values.iter().copied().reduce(f64::max),
+    }
+}
+
+/// Reads "1234.5", "1,234.5" or "-3"; thousands separators must be commas.
+fn parse_number(cell: &str) -> Option<f64> {
+    cell.replace(',', "").parse::<f64>().ok().filter(|v| v.is_finite())
+}
+
+fn center(content: &str) -> String {
+    format!("\\begin{{center}}\n{}\\end{{center}}\n", content)
+}
+
+// ============================================================================
+// Branding
+// ============================================================================
+
+/// Colors used when a document has no branding profile.
+pub const DEFAULT_PRIMARY_COLOR: &str = "000000";
+pub const DEFAULT_SECONDARY_COLOR: &str = "555555";
+
+/// Injects a branding profile into templates that support it. Templates get
+/// `brandprimary`/`brandsecondary` colors, a `<<LOGO>>` slot and a footer.
+pub struct Branding;
+
+impl Branding {
+    /// Preamble defining the brand colors and, with footer text, a fancyhdr footer.
+    pub fn preamble(profile: Option<&BrandingProfile>) -> String {
+        let (primary, secondary) = profile
+            .map(|p| (p.primary_color.as_str(), p.secondary_color.as_str()))
+            .unwrap_or((DEFAULT_PRIMARY_COLOR, DEFAULT_SECONDARY_COLOR));
+        let mut out = format!(
+            "\\usepackage{{xcolor}}\n\\definecolor{{brandprimary}}{{HTML}}{{{}}}\n\\definecolor{{brandsecondary}}{{HTML}}{{{}}}\n",
+            primary, secondary
+        );
+        if let Some(footer) = profile.and_then(|p| p.footer_text.as_deref()).filter(|f| !f.trim().is_empty()) {
+            out.push_str(&format!(
+                "\\usepackage{{fancyhdr}}\n\\fancypagestyle{{plain}}{{\\fancyhf{{}}\\renewcommand{{\\headrulewidth}}{{0pt}}\\fancyfoot[C]{{\\small\\color{{brandsecondary}} {}}}}}\n\\pagestyle{{plain}}\n",
+                escape(footer)
+            ));
+        }
+        out
+    }
+
+    /// The logo at the given height, or nothing without a logo.
+    pub fn logo(profile: Option<&BrandingProfile>, height: &str) -> String {
+        match profile.and_then(|p| p.logo.as_ref()) {
+            Some((name, _)) => format!("\\includegraphics[height={}]{{{}}}\\par\\vspace{{6pt}}", height, name),
+            None => String::new(),
+        }
+    }
+
+    pub fn files(profile: Option<&BrandingProfile>) -> Vec<AuxFile> {
+        profile.and_then(|p| p.logo.clone()).into_iter().collect()
+    }
+
+    /// Accepts "#1a73e8" or "1A73E8"; returns six uppercase hex digits.
+    pub fn parse_color(color: &str) -> Result<String, String> {
+        let hex = color.trim().trim_start_matches('#');
+        if hex.len() == 6 && hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            Ok(hex.to_ascii_uppercase())
+        } else {
+            Err(format!("Invalid color '{}' (expected hex such as #1A73E8)", color))
+        }
+    }
+}
+
+/// Names an uploaded image `<stem>.<ext>` after its detected type.
+pub fn image_file(stem: &str, data: Vec<u8>) -> Result<AuxFile, String> {
+    let ext = if data.starts_with(b"\x89PNG") {
+        "png"
+    } else if data.starts_with(&[0xFF, 0xD8]) {
+        "jpg"
+    } else if data.starts_with(b"%PDF") {
+        "pdf"
+    } else {
+        return Err(format!("The {} must be a PNG, JPEG or PDF image", stem.replace('-', " ")));
+    };
+    Ok((format!("{}.{}", stem, ext), data))
+}
+
+fn round_to(value: f64, digits: usize) -> f64 {
+    let factor = 10f64.powi(digits as i32);
+    (value * factor).round() / factor
+}
+
+/// Escapes free text, keeping its line breaks.
+fn multiline(text: &str) -> String {
+    text.lines().filter(|l| !l.trim().is_empty()).map(escape).collect::<Vec<_>>().join("\\\\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::InvoiceItem;
+
+    fn party(name: &str) -> InvoiceParty {
+        InvoiceParty { name: name.to_string(), address: vec!["Calle Mayor 1".to_string()], tax_id: None, email: None }
+    }
+
+    fn request() -> InvoiceRequest {
+        InvoiceRequest {
+            number: "2024-001".to_string(),
+            issue_date: "2024-05-01".to_string(),
+            due_date: None,
+            seller: party("Acme & Sons"),
+            buyer: party("Globex"),
+            items: vec![
+                InvoiceItem { description: "Consulting".to_string(), quantity: 10.0, unit_price: 120.0, tax_rate: None },
+                InvoiceItem { description: "Books".to_string(), quantity: 3.0, unit_price: 19.99, tax_rate: Some(4.0) },
+            ],
+            currency: "EUR".to_string(),
+            tax_rate: Some(21.0),
+            locale: Some("es-ES".to_string()),
+            notes: None,
+            branding: None,
+            format: None,
+        }
+    }
+
+    #[test]
+    fn test_totals_group_taxes_by_rate() {
+        let totals = InvoiceGenerator::totals(&request()).unwrap();
+        assert_eq!(totals.lines, vec![1200.0, 59.97]);
+        assert_eq!(totals.taxes, vec![(4.0, 59.97, 2.40), (21.0, 1200.0, 252.0)]);
+        assert_eq!(totals.subtotal, 1259.97);
+        assert_eq!(totals.total, 1514.37);
+    }
+
+    #[test]
+    fn test_locale_formatting() {
+        let tex = InvoiceGenerator::to_latex(&request(), None).unwrap().0;
+        assert!(tex.contains("{\\Huge\\bfseries\\color{brandprimary} Factura}"));
+        assert!(tex.contains("\\bfseries 1.514,37~€"));
+        assert!(tex.contains("Fecha: 1 de mayo de 2024"));
+        assert!(tex.contains("Acme \\& Sons"));
+        assert!(!tex.contains("<<"));
+
+        let mut req = request();
+        req.buyer.name = "<<ITEMS>>".to_string();
+        assert!(InvoiceGenerator::to_latex(&req, None).unwrap().0.contains("{\\large\\bfseries <<ITEMS>>}"));
+
+        let profile = BrandingProfile {
+            id: "b1".to_string(),
+            name: None,
+            primary_color: Branding::parse_color("#1a73e8").unwrap(),
+            secondary_color: DEFAULT_SECONDARY_COLOR.to_string(),
+            footer_text: Some("ACME Inc. · 100% organic".to_string()),
+            has_logo: true,
+            logo: Some(image_file("brand-logo", b"\x89PNG....".to_vec()).unwrap()),
+            created_at: 0,
+        };
+        let (tex, files) = InvoiceGenerator::to_latex(&req, Some(&profile)).unwrap();
+        assert!(tex.contains("\\definecolor{brandprimary}{HTML}{1A73E8}"));
+        assert!(tex.contains("\\fancyfoot[C]{\\small\\color{brandsecondary} ACME Inc. · 100\\% organic}"));
+        assert!(tex.contains("\\includegraphics[height=1.5cm]{brand-logo.png}"));
+        assert_eq!(files[0].0, "brand-logo.png");
+
+        req.locale = None;
+        req.currency = "USD".to_string();
+        assert!(InvoiceGenerator::to_latex(&req, None).unwrap().0.contains("\\bfseries \\$1,514.37"));
+        req.currency = "usd".to_string();
+        assert!(InvoiceGenerator::to_latex(&req, None).is_err());
+    }
+
+    #[test]
+    fn test_certificates_embed_qr_codes() {
+        let req = CertificateRequest {
+            recipients: vec!["Ana Pérez".to_string(), "Bo_b".to_string()],
+            recipient: None,
+            course: "Rust 101".to_string(),
+            date: "2024-06-01".to_string(),
+            issuer: None,
+            title: None,
+            background_hash: None,
+            background_base64: None,
+            verify_url: Some("https://example.com/verify/{id}".to_string()),
+            locale: Some("fr".to_string()),
+            branding: None,
+            output: None,
+            format: None,
+        };
+        let (tex, files) = CertificateGenerator::to_latex(&req, &req.recipients, None, None).unwrap();
+        let id = CertificateGenerator::certificate_id("Ana Pérez", "Rust 101", "2024-06-01");
+        assert_eq!(tex.matches("\\newpage").count(), 2);
+        assert!(tex.contains("Bo\\_b"));
+        assert!(tex.contains("1 juin 2024"));
+        assert!(tex.contains(&format!("example.com/verify/{}", id)));
+        assert_eq!(files.len(), 2);
+        assert_eq!(files[0].0, format!("qr-{}.png", id));
+        assert_eq!(CertificateGenerator::file_name("Ana Pérez", &id), format!("ana-p-rez-{}.pdf", id));
+    }
+
+    #[test]
+    fn test_resume_sections() {
+        let resume: Resume = serde_json::from_value(serde_json::json!({
+            "basics": { "name": "Jane Doe", "email": "jane@example.com", "profiles": [{ "network": "GitHub", "username": "jd", "url": "https://github.com/jd" }] },
+            "work": [{ "name": "R&D Corp", "position": "Engineer", "startDate": "2019-03-01", "highlights": ["Cut build times 50%"] }],
+            "volunteer": [{ "organization": "Code Club", "position": "Mentor", "startDate": "2018", "endDate": "2019-06" }]
+        })).unwrap();
+
+        let tex = ResumeGenerator::to_latex(&resume, Some("modern"), None).unwrap();
+        assert!(tex.contains("\\cvsection{Experience}\n\\cventry{Mar 2019 -- Present}{\\textbf{Engineer}, \\textit{R\\&D Corp}}{\\begin{itemize}\\item Cut build times 50\\%\\end{itemize}}"));
+        assert!(tex.contains("\\cventry{2018 -- Jun 2019}{\\textbf{Mentor}, \\textit{Code Club}}{}"));
+        assert!(tex.contains("\\href{mailto:jane@example.com}{jane@example.com}"));
+        assert!(!tex.contains("\\cvsection{Education}"));
+        assert!(ResumeGenerator::to_latex(&resume, Some("fancy"), None).is_err());
+        let tex = ResumeGenerator::to_latex(&resume, None, Some("es")).unwrap();
+        assert!(tex.contains("\\cventry{mar. 2019 -- Actualidad}"));
+    }
+
+    #[test]
+    fn test_report_sections() {
+        let req: ReportRequest = serde_json::from_value(serde_json::json!({
+            "title": "Q3 Sales",
+            "csv": "region,units,price\nNorth,10,2.5\nSouth,4,1.25\nNorth,6,3\n",
+            "locale": "de",
+            "sections": [
+                { "type": "summary", "title": "Overview" },
+                { "type": "table", "group_by": "region", "columns": ["units"], "totals": true },
+                { "type": "table", "limit": 1 },
+                { "type": "chart", "group_by": "region", "aggregate": "count" }
+            ]
+        })).unwrap();
+        let (tex, _) = ReportGenerator::to_latex(&req, None).unwrap();
+        assert!(tex.contains("\\section*{Overview}\n\\begin{center}\n\\begin{tabular}{lrrrrr}"));
+        assert!(tex.contains("units & 3 & 20 & 6,67 & 4 & 10 \\\\\nprice & 3 & 6,75 & 2,25 & 1,25 & 3,00 \\\\\n"));
+        assert!(tex.contains("North & 16 \\\\\nSouth & 4 \\\\\n\\midrule\n\\textbf{Total} & \\textbf{20} \\\\\n"));
+        assert!(tex.contains("region & units & price \\\\\n\\midrule\nNorth & 10 & 2,50 \\\\\n\\bottomrule"));
+        assert!(tex.contains("symbolic x coords={North,South}"));
+        assert!(tex.contains("\\addplot+[] coordinates {(North,2) (South,1)};"));
+
+        let mut bad = req;
+        bad.sections[1].columns = vec!["region".to_string()];
+        assert_eq!(ReportGenerator::to_latex(&bad, None).unwrap_err(), "Section 2: Column 'region' is not numeric");
+        bad.sections[1].columns = vec!["profit".to_string()];
+        assert!(ReportGenerator::to_latex(&bad, None).unwrap_err().contains("Unknown column 'profit'"));
+    }
+}
@@ -0,0 +1,223 @@
+//! Batch grading (`POST /grade/batch`): compiles many student submissions, each a ZIP (or a
+//! single `.tex`), with a per-submission time and size limit, and reports for each whether
+//! it compiled, its page count, the files it refers to but did not include and how many
+//! errors and warnings the log shows.
+
+use futures_util::StreamExt;
+use regex::Regex;
+use std::collections::HashSet;
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+use tracing::info;
+
+use crate::bib::strip_comment;
+use crate::filetypes::FilePolicy;
+use crate::handlers::parse_log_errors;
+use crate::models::{GradeReport, SubmissionReport};
+use crate::overleaf::OverleafImporter;
+use crate::render::AuxFile;
+use crate::services::{AppState, Priority};
+use crate::warnings::parse_log_warnings;
+
+/// Extensions tried, in order, for an `\includegraphics` path given without one.
+const GRAPHIC_EXTENSIONS: &[&str] = &["pdf", "png", "jpg", "jpeg", "eps"];
+
+/// Columns of the CSV report, in `SubmissionReport` field order.
+const CSV_HEADER: &str = "submission,compiled,main,pages,missing_files,errors,warnings,compile_time_ms,error";
+
+/// Grades every submission, at most GRADE_CONCURRENCY at a time, in upload order.
+pub async fn grade_batch(state: &AppState, submissions: Vec<(String, bytes::Bytes)>, timeout: Duration) -> GradeReport {
+    let start = Instant::now();
+    let concurrency = state.settings.grade_concurrency.max(1);
+    let results: Vec<SubmissionReport> = futures_util::stream::iter(submissions)
+        .map(|(name, data)| grade(state, name, data, timeout))
+        .buffered(concurrency)
+        .collect()
+        .await;
+    let compiled = results.iter().filter(|r| r.compiled).count();
+    info!("🎓 Graded {} submission(s), {} compiled, in {}ms", results.len(), compiled, start.elapsed().as_millis());
+    GradeReport { submissions: results.len(), compiled, results }
+}
+
+async fn grade(state: &AppState, name: String, data: bytes::Bytes, timeout: Duration) -> SubmissionReport {
+    let mut report = SubmissionReport {
+        submission: name.clone(),
+        compiled: false,
+        main: None,
+        pages: None,
+        missing_files: Vec::new(),
+        errors: 0,
+        warnings: 0,
+        compile_time_ms: 0,
+        error: None,
+    };
+    let limit = state.settings.grade_submission_max_mb * 1024 * 1024;
+
+    let (main, mut files) = if name.ends_with(".tex") {
+        let file_name = name.rsplit('/').next().unwrap_or(&name).to_string();
+        (file_name.clone(), vec![(file_name, data.to_vec())])
+    } else {
+        match tokio::task::spawn_blocking(move || OverleafImporter::import(&data)).await {
+            Ok(Ok(project)) => (project.main, project.files),
+            Ok(Err(e)) => return SubmissionReport { error: Some(e), ..report },
+            Err(e) => return SubmissionReport { error: Some(format!("Import failed: {}", e)), ..report },
+        }
+    };
+    report.main = Some(main.clone());
+    let policy = FilePolicy::new(&state.settings.allowed_file_types);
+    files.retain(|(file_name, bytes)| policy.check(file_name, &bytes[..bytes.len().min(4096)]).is_none());
+    let size: u64 = files.iter().map(|(_, bytes)| bytes.len() as u64).sum();
+    if size > limit {
+        report.error = Some(format!("Submission expands to {} bytes, over the limit of {} MB", size, state.settings.grade_submission_max_mb));
+        return report;
+    }
+    report.missing_files = missing_files(&files);
+
+    // The PDF cache is bypassed so every submission's log is read
+    let (result, logs, compile_time_ms) = match crate::render::compile_uncached(state, &main, &files, Priority::Batch, timeout, None).await {
+        Ok(outcome) => outcome,
+        Err(e) => {
+            report.error = Some(e);
+            return report;
+        }
+    };
+    report.compile_time_ms = compile_time_ms;
+    report.errors = parse_log_errors(&logs).len();
+    report.warnings = parse_log_warnings(&logs).len();
+    // Files found through kpathsea paths the scan cannot follow show up in the log only
+    static NOT_FOUND: OnceLock<Regex> = OnceLock::new();
+    let not_found_re = NOT_FOUND.get_or_init(|| Regex::new(r"File [`']([^']+)' not found").unwrap());
+    for caps in not_found_re.captures_iter(&logs) {
+        if !report.missing_files.iter().any(|f| f == &caps[1]) {
+            report.missing_files.push(caps[1].to_string());
+        }
+    }
+    match result {
+        Ok(pdf) => {
+            report.compiled = true;
+            report.pages = crate::pages::page_count(&pdf).ok();
+        }
+        Err(e) => report.error = Some(e),
+    }
+    report
+}
+
+/// Files the sources `\input`, `\include`, `\includegraphics` or use as a bibliography
+/// that are not part of the submission.
+fn missing_files(files: &[AuxFile]) -> Vec<String> {
+    let names: HashSet<&str> = files.iter().map(|(name, _)| name.as_str()).collect();
+    static COMMAND: OnceLock<Regex> = OnceLock::new();
+    let command_re = COMMAND.get_or_init(|| Regex::new(r"\\(input|include|includegraphics|bibliography|addbibresource)\*?\s*(?:\[[^\]]*\])?\s*\{([^}]*)\}").unwrap());
+    let mut missing = Vec::new();
+    for (name, data) in files.iter().filter(|(name, _)| name.ends_with(".tex")) {
+        let text = String::from_utf8_lossy(data);
+        for line in text.lines().map(strip_comment) {
+            for caps in command_re.captures_iter(line) {
+                for target in caps[2].split(',').map(|t| t.trim().trim_start_matches("./")).filter(|t| !t.is_empty() && !t.contains('#')) {
+                    // (names that satisfy the reference, name reported when none exists)
+                    let (candidates, reported): (Vec<String>, String) = match &caps[1] {
+                        "includegraphics" => (
+                            std::iter::once(target.to_string()).chain(GRAPHIC_EXTENSIONS.iter().map(|ext| format!("{}.{}", target, ext))).collect(),
+                            target.to_string(),
+                        ),
+                        "bibliography" => {
+                            let bib = format!("{}.bib", target.trim_end_matches(".bib"));
+                            (vec![bib.clone()], bib)
+                        }
+                        "addbibresource" => (vec![target.to_string()], target.to_string()),
+                        _ if target.ends_with(".tex") => (vec![target.to_string()], target.to_string()),
+                        _ => (vec![target.to_string(), format!("{}.tex", target)], format!("{}.tex", target)),
+                    };
+                    // Paths are relative to the main file's folder; try the source's folder too
+                    let folder = name.rsplit_once('/').map(|(folder, _)| folder);
+                    let found = candidates.iter().any(|c| names.contains(c.as_str()) || folder.is_some_and(|f| names.contains(format!("{}/{}", f, c).as_str())));
+                    if !found && !missing.contains(&reported) {
+                        missing.push(reported);
+                    }
+                }
+            }
+        }
+    }
+    missing
+}
+
+/// The report as CSV, one row per submission.
+pub fn to_csv(report: &GradeReport) -> String {
+    let mut csv = format!("{}\n", CSV_HEADER);
+    for r in &report.results {
+        let fields = [
+            r.submission.clone(),
+            r.compiled.to_string(),
+            r.main.clone().unwrap_or_default(),
+            r.pages.map(|p| p.to_string()).unwrap_or_default(),
+            r.missing_files.join(";"),
+            r.errors.to_string(),
+            r.warnings.to_string(),
+            r.compile_time_ms.to_string(),
+            r.error.as_deref().and_then(|e| e.lines().next()).unwrap_or_default().to_string(),
+        ];
+        csv.push_str(&fields.iter().map(|f| csv_field(f)).collect::<Vec<_>>().join(","));
+        csv.push('\n');
+    }
+    csv
+}
+
+/// Quotes a field holding a separator, quote or line break. Fields starting with a formula
+/// character get a leading `'` so spreadsheets do not evaluate them.
+fn csv_field(value: &str) -> String {
+    let value = if value.starts_with(['=', '+', '-', '@']) { format!("'{}", value) } else { value.to_string() };
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value
+    }
+}
+
+/// Submission name from an uploaded file name: the path without its `.zip` extension.
+pub fn submission_name(file_name: &str, index: usize) -> String {
+    let name = file_name.trim_end_matches(".zip");
+    if name.is_empty() { format!("submission-{}", index + 1) } else { name.to_string() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn files(list: &[(&str, &str)]) -> Vec<AuxFile> {
+        list.iter().map(|(name, content)| (name.to_string(), content.as_bytes().to_vec())).collect()
+    }
+
+    #[test]
+    fn test_missing_files() {
+        let submission = files(&[
+            ("main.tex", "\\input{intro}\n\\include{chapters/one}\n\\includegraphics[width=3cm]{fig/cat}\n\\includegraphics{dog.png}\n% \\input{commented}\n\\bibliography{refs,extra}\n"),
+            ("intro.tex", ""),
+            ("fig/cat.png", ""),
+            ("refs.bib", ""),
+        ]);
+        assert_eq!(missing_files(&submission), vec!["chapters/one.tex", "dog.png", "extra.bib"]);
+    }
+
+    #[test]
+    fn test_csv() {
+        let report = GradeReport {
+            submissions: 1,
+            compiled: 0,
+            results: vec![SubmissionReport {
+                submission: "alice".into(),
+                compiled: false,
+                main: Some("main.tex".into()),
+                pages: None,
+                missing_files: vec!["a.png".into(), "b.bib".into()],
+                errors: 2,
+                warnings: 1,
+                compile_time_ms: 120,
+                error: Some("Missing $ inserted, \"here\"\nmore".into()),
+            }],
+        };
+        assert_eq!(to_csv(&report), format!("{}\nalice,false,main.tex,,a.png;b.bib,2,1,120,\"Missing $ inserted, \"\"here\"\"\"\n", CSV_HEADER));
+        assert_eq!(csv_field("=SUM(A1)"), "'=SUM(A1)");
+        assert_eq!(submission_name("bob.zip", 0), "bob");
+        assert_eq!(submission_name(".zip", 2), "submission-3");
+    }
+}
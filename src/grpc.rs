@@ -0,0 +1,263 @@
+//! gRPC API (`proto/tachyon.proto`) served alongside REST for internal services that
+//! prefer typed contracts. Compile and Render stream progress, then output chunks,
+//! then a final result.
+
+use std::path::{Component, Path};
+use std::pin::Pin;
+use std::time::Instant;
+use futures_util::Stream;
+use tokio::sync::mpsc;
+use tonic::{Request, Response, Status};
+use tracing::info;
+
+use crate::bib::CitationChecker;
+use crate::filetypes::FilePolicy;
+use crate::models::{ChartRequest, CompileWarning, TableRequest};
+use crate::render::{self, ChartRenderer, TableRenderer};
+use crate::services::{AppState, CompileNotes, Priority};
+use crate::validator::Validator;
+
+pub mod pb {
+    tonic::include_proto!("tachyon.v1");
+}
+
+use pb::compile_event::Event;
+use pb::tachyon_server::{Tachyon, TachyonServer};
+use pb::{Chunk, CompileEvent, CompileRequest, CompileResult, Diagnostic, Progress, RenderRequest, ValidateRequest, ValidateResponse};
+
+/// Size of each streamed output chunk.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+type EventStream = Pin<Box<dyn Stream<Item = Result<CompileEvent, Status>> + Send>>;
+
+pub struct TachyonGrpc {
+    state: AppState,
+}
+
+impl TachyonGrpc {
+    pub fn server(state: AppState) -> TachyonServer<Self> {
+        let limit = (state.settings.body_limit_mb * 1024 * 1024) as usize;
+        TachyonServer::new(Self { state }).max_decoding_message_size(limit)
+    }
+}
+
+/// Sender half of a response stream; send errors mean the client went away and are ignored.
+struct Events(mpsc::Sender<Result<CompileEvent, Status>>);
+
+impl Events {
+    async fn send(&self, event: Event) {
+        let _ = self.0.send(Ok(CompileEvent { event: Some(event) })).await;
+    }
+
+    async fn progress(&self, stage: &str) {
+        self.send(Event::Progress(Progress { stage: stage.to_string() })).await;
+    }
+
+    /// Streams `data` in chunks, then the final result.
+    async fn finish(&self, data: &[u8], result: CompileResult) {
+        for (i, chunk) in data.chunks(CHUNK_SIZE).enumerate() {
+            self.send(Event::Chunk(Chunk { offset: (i * CHUNK_SIZE) as u64, data: chunk.to_vec() })).await;
+        }
+        self.send(Event::Result(result)).await;
+    }
+}
+
+fn stream(events: impl FnOnce(Events) -> Pin<Box<dyn std::future::Future<Output = ()> + Send>>) -> EventStream {
+    let (tx, rx) = mpsc::channel(16);
+    tokio::spawn(events(Events(tx)));
+    Box::pin(futures_util::stream::unfold(rx, |mut rx| async move { rx.recv().await.map(|event| (event, rx)) }))
+}
+
+fn diagnostic(w: CompileWarning) -> Diagnostic {
+    Diagnostic { file: w.file.unwrap_or_default(), line: w.line.unwrap_or(0), message: w.message }
+}
+
+fn safe_relative(name: &str) -> bool {
+    !name.is_empty() && Path::new(name).components().all(|c| matches!(c, Component::Normal(_)))
+}
+
+#[tonic::async_trait]
+impl Tachyon for TachyonGrpc {
+    type CompileStream = EventStream;
+    type RenderStream = EventStream;
+
+    async fn compile(&self, request: Request<CompileRequest>) -> Result<Response<Self::CompileStream>, Status> {
+        let req = request.into_inner();
+        if req.files.is_empty() {
+            return Err(Status::invalid_argument("No files provided"));
+        }
+        if let Some(bad) = req.files.iter().find(|f| !safe_relative(&f.name)) {
+            return Err(Status::invalid_argument(format!("Invalid file name '{}'", bad.name)));
+        }
+        let main = if req.main.is_empty() {
+            req.files.iter().rev().find(|f| f.name.ends_with(".tex")).map(|f| f.name.clone()).unwrap_or_else(|| "main.tex".to_string())
+        } else {
+            req.main.clone()
+        };
+        if !safe_relative(&main) {
+            return Err(Status::invalid_argument(format!("Invalid main file '{}'", main)));
+        }
+
+        let state = self.state.clone();
+        Ok(Response::new(stream(move |events| Box::pin(async move {
+            let temp_dir = match state.janitor.workspace() {
+                Ok(d) => d,
+                Err(e) => return events.finish(&[], CompileResult { error: format!("Failed to create temp dir: {}", e), ..Default::default() }).await,
+            };
+            let policy = FilePolicy::new(&state.settings.allowed_file_types);
+            let rejected: Vec<String> = req.files.iter()
+                .filter_map(|file| policy.check(&file.name, &file.content[..file.content.len().min(4096)]))
+                .map(|rejection| format!("{}: {}", rejection.file, rejection.reason))
+                .collect();
+            if !rejected.is_empty() {
+                return events.finish(&[], CompileResult { error: format!("Files not accepted: {}", rejected.join("; ")), ..Default::default() }).await;
+            }
+            let mut input_hasher = state.compilation_cache.input_hasher();
+            let mut sources = std::collections::HashMap::new();
+            for file in &req.files {
+                let path = temp_dir.path().join(&file.name);
+                let written = path.parent().map_or(Ok(()), std::fs::create_dir_all).and_then(|_| std::fs::write(&path, &file.content));
+                if let Err(e) = written {
+                    return events.finish(&[], CompileResult { error: format!("Failed to write {}: {}", file.name, e), ..Default::default() }).await;
+                }
+                input_hasher.add_file(&file.name, &file.content);
+                if file.name.ends_with(".tex") || file.name.ends_with(".bib") {
+                    if let Ok(text) = std::str::from_utf8(&file.content) {
+                        sources.insert(file.name.clone(), text.to_string());
+                    }
+                }
+            }
+            let input_hash = input_hasher.finish(&main);
+            let mut warnings: Vec<CompileWarning> = CitationChecker::check(&sources).into_iter().map(Into::into).collect();
+
+            if !req.force {
+                if let Some(failure) = state.compilation_cache.get_failure(input_hash).await {
+                    return events.finish(&[], CompileResult {
+                        cache: "NEGATIVE".to_string(),
+                        error: failure.error,
+                        logs: failure.logs,
+                        ..Default::default()
+                    }).await;
+                }
+                if let Some((pdf, compile_time_ms, stale, notes)) = state.compilation_cache.get_pdf(input_hash).await {
+                    events.progress("cached").await;
+                    warnings.extend(notes.warnings);
+                    let output_hash = state.output_store.put(&pdf).await;
+                    let result = CompileResult {
+                        success: true,
+                        compile_time_ms,
+                        cache: if stale { "STALE" } else { "HIT" }.to_string(),
+                        output_hash,
+                        size_bytes: pdf.len() as u64,
+                        content_type: "application/pdf".to_string(),
+                        warnings: warnings.into_iter().map(diagnostic).collect(),
+                        ..Default::default()
+                    };
+                    return events.finish(&pdf, result).await;
+                }
+            }
+
+            let priority = Priority::parse(Some(&req.priority));
+            events.progress("queued").await;
+            let permit = state.scheduler.acquire(priority).await;
+            events.progress("compiling").await;
+            info!("📡 gRPC compiling {} ({} files, priority: {})", main, req.files.len(), priority.as_str());
+            let start = Instant::now();
+            let (result, logs) = crate::workers::compile(&state, temp_dir.path(), &temp_dir.path().join(&main), priority, permit).await;
+            let compile_time_ms = start.elapsed().as_millis() as u64;
+
+            match result {
+                Ok(pdf) => {
+                    let notes = CompileNotes::from_log(&logs);
+                    state.compilation_cache.put_pdf(input_hash, &pdf, compile_time_ms, &notes).await;
+                    warnings.extend(notes.warnings);
+                    let output_hash = state.output_store.put(&pdf).await;
+                    let result = CompileResult {
+                        success: true,
+                        compile_time_ms,
+                        cache: "MISS".to_string(),
+                        output_hash,
+                        size_bytes: pdf.len() as u64,
+                        content_type: "application/pdf".to_string(),
+                        warnings: warnings.into_iter().map(diagnostic).collect(),
+                        ..Default::default()
+                    };
+                    events.finish(&pdf, result).await;
+                }
+                Err(e) => {
+                    state.compilation_cache.put_failure(input_hash, &e, &logs).await;
+                    events.finish(&[], CompileResult { compile_time_ms, cache: "MISS".to_string(), error: e, logs, ..Default::default() }).await;
+                }
+            }
+        }))))
+    }
+
+    async fn validate(&self, request: Request<ValidateRequest>) -> Result<Response<ValidateResponse>, Status> {
+        let req = request.into_inner();
+        let sources = req.files.into_iter()
+            .filter_map(|f| String::from_utf8(f.content).ok().map(|text| (f.name, text)))
+            .collect();
+        let result = Validator::validate(&sources, Some(req.main.as_str()).filter(|m| !m.is_empty()));
+        Ok(Response::new(ValidateResponse {
+            valid: result.valid,
+            errors: result.errors.into_iter().map(|m| diagnostic(m.into())).collect(),
+            warnings: result.warnings.into_iter().map(|m| diagnostic(m.into())).collect(),
+        }))
+    }
+
+    async fn render(&self, request: Request<RenderRequest>) -> Result<Response<Self::RenderStream>, Status> {
+        let req = request.into_inner();
+        let source = match req.kind.as_str() {
+            "chart" => serde_json::from_str::<ChartRequest>(&req.spec_json)
+                .map_err(|e| e.to_string())
+                .and_then(|spec| ChartRenderer::to_latex(&spec)),
+            "table" => serde_json::from_str::<TableRequest>(&req.spec_json)
+                .map_err(|e| e.to_string())
+                .and_then(|spec| TableRenderer::from_request(&spec))
+                .map(|fragment| TableRenderer::to_latex(&fragment)),
+            other => Err(format!("Unknown render kind '{}' (expected chart or table)", other)),
+        }.map_err(Status::invalid_argument)?;
+        let format = if req.format.is_empty() { "pdf".to_string() } else { req.format };
+
+        let state = self.state.clone();
+        Ok(Response::new(stream(move |events| Box::pin(async move {
+            events.progress("compiling").await;
+            let start = Instant::now();
+            let (result, logs) = render::compile_source(&state, &source, &[], Priority::Interactive).await;
+            let pdf = match result {
+                Ok(pdf) => pdf,
+                Err(e) => return events.finish(&[], CompileResult { error: e, logs, ..Default::default() }).await,
+            };
+            if format != "pdf" {
+                events.progress("converting").await;
+            }
+            match render::convert_output(&state, pdf, &format) {
+                Ok((bytes, content_type)) => {
+                    let result = CompileResult {
+                        success: true,
+                        compile_time_ms: start.elapsed().as_millis() as u64,
+                        size_bytes: bytes.len() as u64,
+                        content_type: content_type.to_string(),
+                        ..Default::default()
+                    };
+                    events.finish(&bytes, result).await;
+                }
+                Err((_, e)) => events.finish(&[], CompileResult { error: e, ..Default::default() }).await,
+            }
+        }))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_safe_relative() {
+        assert!(safe_relative("main.tex"));
+        assert!(safe_relative("chapters/one.tex"));
+        assert!(!safe_relative("../etc/passwd"));
+        assert!(!safe_relative("/abs.tex"));
+        assert!(!safe_relative(""));
+    }
+}
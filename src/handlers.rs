@@ -1,179 +1,3739 @@
 use axum::{
-    extract::{State, Multipart, ws::{WebSocket, Message}},
+    extract::{FromRequest, State, Multipart, Path as UrlPath, Query, ws::{WebSocket, Message}},
     response::{IntoResponse, Response},
     Json,
-    http::{StatusCode, header},
+    http::{StatusCode, header, HeaderMap, HeaderName, HeaderValue},
 };
+use std::collections::HashMap;
 use std::fs;
-use std::path::PathBuf;
-use std::time::Instant;
-use tracing::{info, error};
-use tempfile::TempDir;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::{info, warn, error};
 use base64::{Engine as _, engine::general_purpose};
 use xxhash_rust::xxh64::xxh64;
-use regex::Regex;
+use sha2::{Digest, Sha256};
+use tokio::io::AsyncWriteExt;
 
 use crate::models::*;
 use crate::services::*;
-use crate::compiler::{Compiler, CapturingStatusBackend};
+use crate::checksum::Checksum;
+use crate::filetypes::FilePolicy;
+use crate::compiler::{CapturingStatusBackend, ResourceMeter};
+use crate::bib::{BibFormatter, CitationChecker};
+use crate::healer::SelfHealer;
+use crate::docx::DocxConverter;
+use crate::estimate::Estimator;
+use crate::exam::ExamGenerator;
+use crate::fingerprint::Fingerprint;
+use crate::html::HtmlConverter;
+use crate::overleaf::OverleafImporter;
+use crate::notebook::{NotebookConverter, NotebookOptions};
+use crate::validator::Validator;
+use crate::assets::AssetScanner;
+use crate::explain::ErrorExplainer;
+use crate::tutor::Tutor;
+use crate::print::PrintProfile;
+use crate::signing::{SignOptions, Signer, SignerConfig};
+use crate::barcode::BarcodeGenerator;
+use crate::generate::{image_file, Branding, CertificateGenerator, InvoiceGenerator, ReportGenerator, ResumeGenerator, DEFAULT_PRIMARY_COLOR, DEFAULT_SECONDARY_COLOR, MAX_CERTIFICATES};
+use crate::render::{self, ChartRenderer, TableRenderer};
+use crate::rewrite;
+use crate::texlog;
+use crate::receipt::sha256_hex;
+use crate::compression::{Encoding, WS_PROTOCOLS};
+use crate::settings::Settings;
+use crate::webhooks::Webhooks;
+use crate::util::unix_now;
+
+/// Maximum number of warnings serialized into the `X-Warnings` header.
+const MAX_HEADER_WARNINGS: usize = 50;
+
+/// Number of source lines shown above and below an error line in snippets.
+const SNIPPET_CONTEXT_LINES: u32 = 2;
 
 // ============================================================================
 // Handlers
 // ============================================================================
 
-pub async fn health_handler() -> &'static str {
-    "🚀 Tachyon-Tex Engine is Operational"
+#[utoipa::path(
+    get, path = "/health", tag = "system",
+    responses((status = 200, description = "Server is up", body = String))
+)]
+pub async fn health_handler() -> &'static str {
+    "🚀 Tachyon-Tex Engine is Operational"
+}
+
+/// Answers the ACME CA's HTTP-01 challenge while a certificate for ACME_DOMAINS is issued.
+pub async fn acme_challenge_handler(State(state): State<AppState>, UrlPath(token): UrlPath<String>) -> Response {
+    match state.acme_challenges.get(&token) {
+        Some(key_authorization) => key_authorization.into_response(),
+        None => (StatusCode::NOT_FOUND, "Unknown ACME challenge".to_string()).into_response(),
+    }
+}
+
+#[utoipa::path(
+    post, path = "/validate", tag = "compile",
+    request_body(content = ValidationRequest, description = "JSON sources, or the same multipart upload as /compile; a `main` text part names the main file, else it is the last .tex file"),
+    responses(
+        (status = 200, description = "Static checks of the project sources", body = ValidationResult),
+        (status = 400, description = "Malformed request body", body = String),
+    )
+)]
+pub async fn validate_handler(request: axum::extract::Request) -> Response {
+    let is_multipart = request.headers().get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with("multipart/form-data"));
+    let (sources, main) = if is_multipart {
+        match Multipart::from_request(request, &()).await {
+            Ok(multipart) => match multipart_sources(multipart).await {
+                Ok(parsed) => parsed,
+                Err(response) => return response,
+            },
+            Err(rejection) => return rejection.into_response(),
+        }
+    } else {
+        match Json::<ValidationRequest>::from_request(request, &()).await {
+            Ok(Json(payload)) => (payload.sources, payload.main),
+            Err(rejection) => return rejection.into_response(),
+        }
+    };
+    info!("🔍 Validating {} files...", sources.len());
+    Json(Validator::validate(&sources, main.as_deref())).into_response()
+}
+
+#[utoipa::path(
+    post, path = "/estimate", tag = "compile",
+    request_body(content_type = "multipart/form-data", description = "The same upload as /compile; only the sizes of non-source files are used"),
+    responses(
+        (status = 200, description = "Estimated compile time, PDF size and page count", body = EstimateResponse),
+        (status = 400, description = "Malformed multipart body or no .tex file", body = String),
+    )
+)]
+pub async fn estimate_handler(
+    State(state): State<AppState>,
+    mut multipart: Multipart,
+) -> Response {
+    let mut sources = HashMap::new();
+    let mut assets = Vec::new();
+    let mut main = None;
+    loop {
+        let mut field = match multipart.next_field().await {
+            Ok(Some(field)) => field,
+            Ok(None) => break,
+            Err(e) => return (StatusCode::BAD_REQUEST, format!("Multipart error: {}", e)).into_response(),
+        };
+        let file_name = field.file_name().unwrap_or("file.tex").to_string();
+        if !(file_name.ends_with(".tex") || file_name.ends_with(".bib")) {
+            // Only the size of assets matters: count it without buffering them
+            let mut size = 0u64;
+            loop {
+                match field.chunk().await {
+                    Ok(Some(chunk)) => size += chunk.len() as u64,
+                    Ok(None) => break,
+                    Err(e) => return (StatusCode::BAD_REQUEST, format!("Failed to read file {}: {}", file_name, e)).into_response(),
+                }
+            }
+            assets.push((file_name, size));
+            continue;
+        }
+        let data = match field.bytes().await {
+            Ok(data) => data,
+            Err(e) => return (StatusCode::BAD_REQUEST, format!("Failed to read file {}: {}", file_name, e)).into_response(),
+        };
+        if let Ok(text) = String::from_utf8(data.to_vec()) {
+            if file_name.ends_with(".tex") {
+                main = Some(file_name.clone());
+            }
+            sources.insert(file_name, text);
+        }
+    }
+    let Some(main) = main else {
+        return (StatusCode::BAD_REQUEST, "No .tex file uploaded".to_string()).into_response();
+    };
+
+    let (warm_format, history) = match FormatCache::extract_preamble(&sources[&main]).map(FormatCache::hash_preamble) {
+        Some(preamble_hash) => (state.format_cache.is_warm(preamble_hash).await, state.compile_history.get(preamble_hash).await),
+        None => (false, None),
+    };
+    let estimate = Estimator::estimate(&sources, &main, &assets, warm_format, history.as_ref());
+    info!("🔮 Estimated {}: ~{}ms, ~{:.1} KB, {} page(s) ({})", main, estimate.compile_time_ms, estimate.output_bytes as f64 / 1024.0, estimate.pages, estimate.basis);
+    Json(estimate).into_response()
+}
+
+/// Reads the text sources of a multipart upload, returning them with the main file.
+/// Binary fields are skipped: only sources take part in validation.
+async fn multipart_sources(mut multipart: Multipart) -> Result<(HashMap<String, String>, Option<String>), Response> {
+    let mut sources = HashMap::new();
+    let (mut main, mut named_main) = (None, None);
+    loop {
+        let field = match multipart.next_field().await {
+            Ok(Some(field)) => field,
+            Ok(None) => break,
+            Err(e) => return Err((StatusCode::BAD_REQUEST, format!("Multipart error: {}", e)).into_response()),
+        };
+        if field.file_name().is_none() && field.name() == Some("main") {
+            let name = field.text().await
+                .map_err(|e| (StatusCode::BAD_REQUEST, format!("Failed to read main: {}", e)).into_response())?;
+            named_main = Some(name.trim().to_string()).filter(|name| !name.is_empty());
+            continue;
+        }
+        let file_name = field.file_name().unwrap_or("file.tex").to_string();
+        if !(file_name.ends_with(".tex") || file_name.ends_with(".bib")) {
+            continue;
+        }
+        let data = field.bytes().await
+            .map_err(|e| (StatusCode::BAD_REQUEST, format!("Failed to read file {}: {}", file_name, e)).into_response())?;
+        if let Ok(text) = String::from_utf8(data.to_vec()) {
+            if file_name.ends_with(".tex") {
+                main = Some(file_name.clone());
+            }
+            sources.insert(file_name, text);
+        }
+    }
+    Ok((sources, named_main.or(main)))
+}
+
+#[utoipa::path(
+    get, path = "/outputs/{hash}", tag = "compile",
+    params(("hash" = String, Path, description = "Value of the `X-Output-Hash` header of a compile")),
+    responses(
+        (status = 200, description = "Stored PDF", content_type = "application/pdf"),
+        (status = 404, description = "No output stored for this hash", body = String),
+    )
+)]
+pub async fn output_handler(
+    State(state): State<AppState>,
+    UrlPath(hash): UrlPath<String>,
+) -> Response {
+    match state.output_store.get(&hash).await {
+        Some(pdf_data) => Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, "application/pdf")
+            .header(header::CACHE_CONTROL, "public, max-age=31536000, immutable")
+            .header("X-Output-Hash", hash)
+            .body(axum::body::Body::from(pdf_data))
+            .unwrap(),
+        None => (StatusCode::NOT_FOUND, format!("No output stored for hash {}", hash)).into_response(),
+    }
+}
+
+#[utoipa::path(
+    post, path = "/uploads", tag = "uploads",
+    params(("Upload-Length" = u64, Header, description = "Total size of the file in bytes")),
+    responses(
+        (status = 201, description = "Upload opened; send the bytes with PATCH to the `Location`", body = UploadStatus),
+        (status = 400, description = "Missing or invalid Upload-Length", body = String),
+        (status = 413, description = "Upload-Length exceeds UPLOAD_MAX_MB", body = String),
+    )
+)]
+pub async fn create_upload_handler(State(state): State<AppState>, headers: HeaderMap) -> Response {
+    let Some(length) = headers.get("Upload-Length").and_then(|v| v.to_str().ok()).and_then(|v| v.trim().parse::<u64>().ok()) else {
+        return (StatusCode::BAD_REQUEST, "Upload-Length header with the total size in bytes is required".to_string()).into_response();
+    };
+    match state.uploads.create(length) {
+        Ok(status) => {
+            info!("📤 Opened upload {} ({:.2} MB)", status.id, length as f64 / 1024.0 / 1024.0);
+            (
+                StatusCode::CREATED,
+                [(header::LOCATION, format!("/uploads/{}", status.id)), (HeaderName::from_static("upload-offset"), "0".to_string())],
+                Json(status),
+            ).into_response()
+        }
+        Err(e) => e.into_response(),
+    }
+}
+
+#[utoipa::path(
+    get, path = "/uploads/{id}", tag = "uploads",
+    params(("id" = String, Path, description = "Upload id from POST /uploads")),
+    responses(
+        (status = 200, description = "Bytes received so far (also in `Upload-Offset`, so HEAD works for resuming)", body = UploadStatus),
+        (status = 404, description = "Unknown or expired upload", body = String),
+    )
+)]
+pub async fn upload_status_handler(State(state): State<AppState>, UrlPath(id): UrlPath<String>) -> Response {
+    match state.uploads.status(&id) {
+        Some(status) => upload_response(status),
+        None => (StatusCode::NOT_FOUND, format!("Upload {} not found or expired", id)).into_response(),
+    }
+}
+
+#[utoipa::path(
+    patch, path = "/uploads/{id}", tag = "uploads",
+    params(
+        ("id" = String, Path, description = "Upload id from POST /uploads"),
+        ("Upload-Offset" = u64, Header, description = "Bytes already received, as reported by the server"),
+    ),
+    request_body(content_type = "application/offset+octet-stream", description = "The next bytes of the file"),
+    responses(
+        (status = 200, description = "Bytes appended; once `complete`, `hash` references the file in the blob store", body = UploadStatus),
+        (status = 400, description = "Missing Upload-Offset, interrupted body or data past Upload-Length", body = String),
+        (status = 404, description = "Unknown or expired upload", body = String),
+        (status = 409, description = "Upload-Offset does not match the bytes received, or another PATCH is running", body = String),
+    )
+)]
+pub async fn patch_upload_handler(
+    State(state): State<AppState>,
+    UrlPath(id): UrlPath<String>,
+    headers: HeaderMap,
+    body: axum::body::Body,
+) -> Response {
+    let Some(offset) = headers.get("Upload-Offset").and_then(|v| v.to_str().ok()).and_then(|v| v.trim().parse::<u64>().ok()) else {
+        return (StatusCode::BAD_REQUEST, "Upload-Offset header is required".to_string()).into_response();
+    };
+    match state.uploads.append(&id, offset, body.into_data_stream()).await {
+        Ok(status) => {
+            if let Some(hash) = &status.hash {
+                info!("📥 Upload {} complete -> blob {}", id, hash);
+            }
+            upload_response(status)
+        }
+        Err(e) => e.into_response(),
+    }
+}
+
+#[utoipa::path(
+    delete, path = "/uploads/{id}", tag = "uploads",
+    params(("id" = String, Path, description = "Upload id from POST /uploads")),
+    responses(
+        (status = 204, description = "Upload aborted"),
+        (status = 404, description = "Unknown or expired upload", body = String),
+    )
+)]
+pub async fn delete_upload_handler(State(state): State<AppState>, UrlPath(id): UrlPath<String>) -> Response {
+    if state.uploads.remove(&id) {
+        StatusCode::NO_CONTENT.into_response()
+    } else {
+        (StatusCode::NOT_FOUND, format!("Upload {} not found or expired", id)).into_response()
+    }
+}
+
+fn upload_response(status: UploadStatus) -> Response {
+    (
+        [
+            (HeaderName::from_static("upload-offset"), status.offset.to_string()),
+            (HeaderName::from_static("upload-length"), status.length.to_string()),
+        ],
+        Json(status),
+    ).into_response()
+}
+
+#[utoipa::path(
+    get, path = "/similar/{hash}", tag = "compile",
+    params(
+        ("hash" = String, Path, description = "Output hash of a compile made with `fingerprint=true`"),
+        ("X-Tenant-Id" = Option<String>, Header, description = "Only documents compiled for this tenant are compared"),
+        SimilarQuery,
+    ),
+    responses(
+        (status = 200, description = "Previously compiled documents with overlapping text", body = SimilarResponse),
+        (status = 404, description = "No fingerprint indexed for this hash and tenant", body = String),
+    )
+)]
+pub async fn similar_handler(
+    State(state): State<AppState>,
+    UrlPath(hash): UrlPath<String>,
+    Query(query): Query<SimilarQuery>,
+    headers: HeaderMap,
+) -> Response {
+    let tenant = headers.get("X-Tenant-Id").and_then(|v| v.to_str().ok());
+    let threshold = query.threshold.unwrap_or(0.5).clamp(0.0, 1.0);
+    let limit = query.limit.unwrap_or(10).min(100);
+    match state.fingerprints.similar(&hash, tenant, threshold, limit).await {
+        Some(matches) => Json(SimilarResponse { hash, matches }).into_response(),
+        None => (StatusCode::NOT_FOUND, format!("No fingerprint indexed for hash {}", hash)).into_response(),
+    }
+}
+
+#[utoipa::path(
+    post, path = "/stamp", tag = "tools",
+    params(StampQuery),
+    request_body(content_type = "multipart/form-data", description = "PDFs to stamp, one part per file, numbered in the order sent"),
+    responses(
+        (status = 200, description = "ZIP of the stamped PDFs, under their own names, and `stamp.json` (a StampManifest)", content_type = "application/zip"),
+        (status = 400, description = "Invalid stamp options, no PDF, a part that is not a readable PDF, or numbering past the largest number", body = String),
+        (status = 413, description = "A PDF over 100 MB", body = String),
+    )
+)]
+pub async fn stamp_handler(Query(query): Query<StampQuery>, mut multipart: Multipart) -> Response {
+    let options = match crate::stamp::StampOptions::new(&query.stamp, query.stamp_start, query.stamp_digits, query.stamp_position.as_deref()) {
+        Ok(options) => options,
+        Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+    };
+    let mut pdfs = Vec::new();
+    loop {
+        let mut field = match multipart.next_field().await {
+            Ok(Some(field)) => field,
+            Ok(None) => break,
+            Err(e) => return (StatusCode::BAD_REQUEST, format!("Multipart error: {}", e)).into_response(),
+        };
+        let name = field.file_name().map(str::to_string).unwrap_or_else(|| format!("document-{}.pdf", pdfs.len() + 1));
+        match read_field(&mut field, crate::stamp::MAX_PDF_BYTES).await {
+            Ok(data) => pdfs.push((name, data)),
+            Err(response) => return response,
+        }
+    }
+    if pdfs.is_empty() {
+        return (StatusCode::BAD_REQUEST, "No PDF was sent".to_string()).into_response();
+    }
+
+    let stamped = tokio::task::spawn_blocking(move || {
+        let mut next = options.start;
+        let mut files = Vec::with_capacity(pdfs.len() + 1);
+        let mut manifest = Vec::with_capacity(pdfs.len());
+        for (name, pdf) in pdfs {
+            let (stamped, pages) = crate::stamp::stamp(&pdf, &options, next).map_err(|e| format!("{}: {}", name, e))?;
+            let last = crate::stamp::last_number(next, pages)?;
+            manifest.push(StampedFile {
+                file: name.clone(),
+                pages,
+                first: options.label(next, 1, pages),
+                last: options.label(last, pages, pages),
+            });
+            next = next.checked_add(pages as u64).ok_or_else(|| format!("Numbering past {} overflows", name))?;
+            files.push((name, stamped));
+        }
+        let manifest = StampManifest { files: manifest, next };
+        files.push(("stamp.json".to_string(), serde_json::to_vec_pretty(&manifest).map_err(|e| e.to_string())?));
+        info!("🔢 Stamped {} PDF(s), {} page(s)", files.len() - 1, manifest.next - options.start);
+        render::zip_files(&files)
+    }).await;
+    match stamped {
+        Ok(Ok(zip)) => ([(header::CONTENT_TYPE, "application/zip")], zip).into_response(),
+        Ok(Err(e)) => (StatusCode::BAD_REQUEST, e).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+#[utoipa::path(
+    post, path = "/assets", tag = "tools",
+    request_body = AssetsRequest,
+    responses((status = 200, description = "Graphics, figures and tables found in the sources", body = AssetReport))
+)]
+pub async fn assets_handler(Json(payload): Json<AssetsRequest>) -> Json<AssetReport> {
+    let report = AssetScanner::scan(&payload.sources, &payload.files);
+    info!("🖼️ Asset scan: {} assets, {} unused files", report.assets.len(), report.unused_files.len());
+    Json(report)
+}
+
+#[utoipa::path(
+    post, path = "/assets/qr", tag = "tools",
+    request_body = BarcodeRequest,
+    responses(
+        (status = 200, description = "Barcode stored in the blob store", body = BarcodeResponse),
+        (status = 400, description = "Invalid kind, format or payload, data too long for the kind (2953 bytes for QR, 256 characters for Code 128), or a PNG over 16 megapixels", body = String),
+    )
+)]
+pub async fn barcode_handler(
+    State(state): State<AppState>,
+    Json(payload): Json<BarcodeRequest>,
+) -> Response {
+    let kind = payload.kind.clone().unwrap_or_else(|| "qr".to_string());
+    let format = payload.format.clone().unwrap_or_else(|| "png".to_string());
+    let scale = payload.scale.unwrap_or(8).clamp(1, 64);
+    if !["qr", "code128"].contains(&kind.as_str()) {
+        return (StatusCode::BAD_REQUEST, format!("Unknown barcode kind '{}' (expected qr or code128)", kind)).into_response();
+    }
+    let mime_type = match format.as_str() {
+        "png" => "image/png",
+        "pdf" => "application/pdf",
+        other => return (StatusCode::BAD_REQUEST, format!("Unknown format '{}' (expected png or pdf)", other)).into_response(),
+    };
+
+    // Encoding and rasterizing take a while for large codes: keep them off the async workers
+    let (kind_name, format_name) = (kind.to_string(), format.to_string());
+    let encoded = tokio::task::spawn_blocking(move || {
+        let grid = match kind_name.as_str() {
+            "qr" => BarcodeGenerator::qr(&payload.data),
+            _ => BarcodeGenerator::code128(&payload.data),
+        }.map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+        if format_name == "pdf" {
+            return Ok(BarcodeGenerator::to_pdf(&grid, scale));
+        }
+        BarcodeGenerator::check_png_size(&grid, scale).map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+        BarcodeGenerator::to_png(&grid, scale).map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("PNG encoding failed: {}", e)))
+    }).await;
+    let bytes = match encoded {
+        Ok(Ok(bytes)) => bytes,
+        Ok(Err(e)) => return e.into_response(),
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, format!("Barcode rendering failed: {}", e)).into_response(),
+    };
+
+    let hash = format!("{:x}", xxh64(&bytes, 0));
+    let size_bytes = bytes.len();
+    state.blob_store.put(hash.clone(), bytes).await;
+    info!("🔳 Generated {} {} ({} bytes) -> blob {}", kind, format, size_bytes, hash);
+
+    Json(BarcodeResponse {
+        hash,
+        format,
+        mime_type: mime_type.to_string(),
+        size_bytes,
+    }).into_response()
+}
+
+#[utoipa::path(
+    post, path = "/render/chart", tag = "render",
+    request_body = ChartRequest,
+    responses(
+        (status = 200, description = "Rendered output; the media type follows `format`", content(("application/pdf"), ("image/svg+xml"), ("image/png"), ("application/x-tex"))),
+        (status = 400, description = "Invalid chart specification", body = String),
+    )
+)]
+pub async fn render_chart_handler(
+    State(state): State<AppState>,
+    Json(payload): Json<ChartRequest>,
+) -> Response {
+    let source = match ChartRenderer::to_latex(&payload) {
+        Ok(s) => s,
+        Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+    };
+
+    let format = payload.format.as_deref().unwrap_or("pdf");
+    if format == "tex" {
+        return ([(header::CONTENT_TYPE, "application/x-tex")], source).into_response();
+    }
+
+    render::render_response(&state, &source, &[], format, "chart").await
+}
+
+#[utoipa::path(
+    post, path = "/render/table", tag = "render",
+    request_body = TableRequest,
+    responses(
+        (status = 200, description = "Rendered output; the media type follows `format`", content(("application/pdf"), ("image/svg+xml"), ("image/png"), ("application/x-tex"))),
+        (status = 400, description = "Invalid table data", body = String),
+    )
+)]
+pub async fn render_table_handler(
+    State(state): State<AppState>,
+    Json(payload): Json<TableRequest>,
+) -> Response {
+    let fragment = match TableRenderer::from_request(&payload) {
+        Ok(f) => f,
+        Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+    };
+
+    let format = payload.format.as_deref().unwrap_or("pdf");
+    if format == "tex" {
+        return ([(header::CONTENT_TYPE, "application/x-tex")], fragment).into_response();
+    }
+
+    let source = TableRenderer::to_latex(&fragment);
+    render::render_response(&state, &source, &[], format, "table").await
+}
+
+#[utoipa::path(
+    post, path = "/generate/invoice", tag = "generate",
+    request_body = InvoiceRequest,
+    responses(
+        (status = 200, description = "Rendered output; the media type follows `format`", content(("application/pdf"), ("image/svg+xml"), ("image/png"), ("application/x-tex"))),
+        (status = 400, description = "Invalid invoice", body = String),
+    )
+)]
+pub async fn invoice_handler(
+    State(state): State<AppState>,
+    Json(payload): Json<InvoiceRequest>,
+) -> Response {
+    let branding = match resolve_branding(&state, payload.branding.as_deref()).await {
+        Ok(b) => b,
+        Err(response) => return response,
+    };
+    let (source, files) = match InvoiceGenerator::to_latex(&payload, branding.as_ref()) {
+        Ok(doc) => doc,
+        Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+    };
+
+    let format = payload.format.as_deref().unwrap_or("pdf");
+    if format == "tex" {
+        return ([(header::CONTENT_TYPE, "application/x-tex")], source).into_response();
+    }
+
+    render::render_response(&state, &source, &files, format, &format!("invoice {}", payload.number)).await
+}
+
+#[utoipa::path(
+    post, path = "/generate/report", tag = "generate",
+    request_body = ReportRequest,
+    responses(
+        (status = 200, description = "Rendered output; the media type follows `format`", content(("application/pdf"), ("image/svg+xml"), ("image/png"), ("application/x-tex"))),
+        (status = 400, description = "Invalid data or section", body = String),
+    )
+)]
+pub async fn report_handler(
+    State(state): State<AppState>,
+    Json(payload): Json<ReportRequest>,
+) -> Response {
+    let branding = match resolve_branding(&state, payload.branding.as_deref()).await {
+        Ok(b) => b,
+        Err(response) => return response,
+    };
+    let (source, files) = match ReportGenerator::to_latex(&payload, branding.as_ref()) {
+        Ok(doc) => doc,
+        Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+    };
+
+    let format = payload.format.as_deref().unwrap_or("pdf");
+    if format == "tex" {
+        return ([(header::CONTENT_TYPE, "application/x-tex")], source).into_response();
+    }
+
+    render::render_response(&state, &source, &files, format, &format!("report {}", payload.title)).await
+}
+
+#[utoipa::path(
+    post, path = "/generate/certificate", tag = "generate",
+    request_body = CertificateRequest,
+    responses(
+        (status = 200, description = "Merged certificates (media type follows `format`) or a ZIP with one PDF per recipient", content(("application/pdf"), ("application/zip"), ("image/svg+xml"), ("image/png"), ("application/x-tex"))),
+        (status = 400, description = "Invalid certificate request", body = String),
+    )
+)]
+pub async fn certificate_handler(
+    State(state): State<AppState>,
+    Json(payload): Json<CertificateRequest>,
+) -> Response {
+    let mut recipients = payload.recipients.clone();
+    recipients.extend(payload.recipient.clone());
+    recipients.retain(|r| !r.trim().is_empty());
+    if recipients.is_empty() || recipients.len() > MAX_CERTIFICATES {
+        return (StatusCode::BAD_REQUEST, format!("Provide between 1 and {} recipients", MAX_CERTIFICATES)).into_response();
+    }
+
+    let background = match resolve_image(&state, payload.background_hash.as_deref(), payload.background_base64.as_deref(), "background").await {
+        Ok(b) => b,
+        Err(response) => return response,
+    };
+    let branding = match resolve_branding(&state, payload.branding.as_deref()).await {
+        Ok(b) => b,
+        Err(response) => return response,
+    };
+
+    let ids: Vec<String> = recipients.iter()
+        .map(|r| CertificateGenerator::certificate_id(r, &payload.course, &payload.date))
+        .collect();
+    let ids_header = HeaderValue::from_str(&ids.join(",")).ok();
+
+    let mut response = match payload.output.as_deref().unwrap_or("merged") {
+        "merged" => {
+            let (source, files) = match CertificateGenerator::to_latex(&payload, &recipients, background.as_ref(), branding.as_ref()) {
+                Ok(doc) => doc,
+                Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+            };
+            let format = payload.format.as_deref().unwrap_or("pdf");
+            if format == "tex" {
+                return ([(header::CONTENT_TYPE, "application/x-tex")], source).into_response();
+            }
+            render::render_response(&state, &source, &files, format, &format!("{} certificate(s)", recipients.len())).await
+        }
+        "zip" => {
+            let start = Instant::now();
+            let mut pdfs = Vec::with_capacity(recipients.len());
+            for (recipient, id) in recipients.iter().zip(&ids) {
+                let (source, files) = match CertificateGenerator::to_latex(&payload, std::slice::from_ref(recipient), background.as_ref(), branding.as_ref()) {
+                    Ok(doc) => doc,
+                    Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+                };
+                match render::compile_source(&state, &source, &files, Priority::Batch).await {
+                    (Ok(pdf), _) => pdfs.push((CertificateGenerator::file_name(recipient, id), pdf)),
+                    (Err(e), logs) => {
+                        error!("❌ Certificate for {} failed: {}", recipient, e);
+                        return (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to compile certificate for {}: {}\n\nLogs:\n{}", recipient, e, logs)).into_response();
+                    }
+                }
+            }
+            let archive = match render::zip_files(&pdfs) {
+                Ok(a) => a,
+                Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to build zip: {}", e)).into_response(),
+            };
+            info!("🎓 Generated {} certificates in {}ms", pdfs.len(), start.elapsed().as_millis());
+            Response::builder()
+                .status(StatusCode::OK)
+                .header(header::CONTENT_TYPE, "application/zip")
+                .header(header::CONTENT_DISPOSITION, "attachment; filename=\"certificates.zip\"")
+                .body(axum::body::Body::from(archive))
+                .unwrap()
+        }
+        other => return (StatusCode::BAD_REQUEST, format!("Unknown output '{}' (expected merged or zip)", other)).into_response(),
+    };
+
+    if let (true, Some(value)) = (response.status().is_success(), ids_header) {
+        response.headers_mut().insert("X-Certificate-Ids", value);
+    }
+    response
+}
+
+#[utoipa::path(
+    post, path = "/generate/exam", tag = "generate",
+    request_body = ExamRequest,
+    responses(
+        (status = 200, description = "ZIP with `exam-A.pdf`, `exam-A-key.pdf`, ... (or `.tex` sources); the seed is in `X-Exam-Seed`", content_type = "application/zip"),
+        (status = 400, description = "Invalid question bank", body = String),
+    )
+)]
+pub async fn exam_handler(
+    State(state): State<AppState>,
+    Json(payload): Json<ExamRequest>,
+) -> Response {
+    let branding = match resolve_branding(&state, payload.branding.as_deref()).await {
+        Ok(b) => b,
+        Err(response) => return response,
+    };
+    let seed = payload.seed.unwrap_or_else(|| {
+        let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap_or_default();
+        xxhash_rust::xxh64::xxh64(&now.as_nanos().to_le_bytes(), 0)
+    });
+    let variants = match ExamGenerator::variants(&payload, seed, branding.as_ref()) {
+        Ok(v) => v,
+        Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+    };
+
+    let start = Instant::now();
+    let mut files: Vec<(String, Vec<u8>)> = Vec::with_capacity(variants.len() * 2);
+    match payload.format.as_deref().unwrap_or("pdf") {
+        "tex" => {
+            for variant in variants {
+                files.push((variant.exam_file("tex"), variant.exam.clone().into_bytes()));
+                files.push((variant.key_file("tex"), variant.key.into_bytes()));
+            }
+        }
+        "pdf" => {
+            let aux = Branding::files(branding.as_ref());
+            for variant in &variants {
+                for (name, source) in [(variant.exam_file("pdf"), &variant.exam), (variant.key_file("pdf"), &variant.key)] {
+                    match render::compile_source(&state, source, &aux, Priority::Batch).await {
+                        (Ok(pdf), _) => files.push((name, pdf.to_vec())),
+                        (Err(e), logs) => {
+                            error!("❌ Exam {} failed: {}", name, e);
+                            return (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to compile {}: {}\n\nLogs:\n{}", name, e, logs)).into_response();
+                        }
+                    }
+                }
+            }
+        }
+        other => return (StatusCode::BAD_REQUEST, format!("Unknown format '{}' (expected pdf or tex)", other)).into_response(),
+    }
+
+    let archive = match render::zip_files(&files) {
+        Ok(a) => a,
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to build zip: {}", e)).into_response(),
+    };
+    info!("📝 Generated {} exam files (seed {}) in {}ms", files.len(), seed, start.elapsed().as_millis());
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "application/zip")
+        .header(header::CONTENT_DISPOSITION, "attachment; filename=\"exams.zip\"")
+        .header("X-Exam-Seed", seed.to_string())
+        .body(axum::body::Body::from(archive))
+        .unwrap()
+}
+
+#[utoipa::path(
+    post, path = "/generate/resume", tag = "generate",
+    params(ResumeQuery),
+    request_body = Resume,
+    responses(
+        (status = 200, description = "Rendered output; the media type follows `format`", content(("application/pdf"), ("image/svg+xml"), ("image/png"), ("application/x-tex"))),
+        (status = 400, description = "Invalid resume or unknown template", body = String),
+    )
+)]
+pub async fn resume_handler(
+    State(state): State<AppState>,
+    Query(query): Query<ResumeQuery>,
+    Json(resume): Json<Resume>,
+) -> Response {
+    let source = match ResumeGenerator::to_latex(&resume, query.template.as_deref(), query.locale.as_deref()) {
+        Ok(s) => s,
+        Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+    };
+
+    let format = query.format.as_deref().unwrap_or("pdf");
+    if format == "tex" {
+        return ([(header::CONTENT_TYPE, "application/x-tex")], source).into_response();
+    }
+
+    render::render_response(&state, &source, &[], format, &format!("resume of {}", resume.basics.name)).await
+}
+
+/// Reads a multipart field into memory, answering 413 as soon as it grows past `max_bytes`.
+async fn read_field(field: &mut axum::extract::multipart::Field<'_>, max_bytes: usize) -> Result<Vec<u8>, Response> {
+    let name = field.file_name().or(field.name()).unwrap_or("file").to_string();
+    let mut data = Vec::new();
+    loop {
+        match field.chunk().await {
+            Ok(Some(chunk)) if data.len() + chunk.len() > max_bytes => {
+                return Err((StatusCode::PAYLOAD_TOO_LARGE, format!("{} is larger than {} bytes", name, max_bytes)).into_response());
+            }
+            Ok(Some(chunk)) => data.extend_from_slice(&chunk),
+            Ok(None) => return Ok(data),
+            Err(e) => return Err((StatusCode::BAD_REQUEST, format!("Failed to read {}: {}", name, e)).into_response()),
+        }
+    }
+}
+
+/// Writes an uploaded multipart field to `path` chunk by chunk, passing every chunk to
+/// `on_chunk` for hashing. The next chunk is only read once the previous one is written,
+/// so a slow disk applies backpressure to the client instead of buffering in memory.
+async fn stream_field(field: &mut axum::extract::multipart::Field<'_>, path: &Path, mut on_chunk: impl FnMut(&[u8])) -> Result<(), Response> {
+    let name = field.file_name().unwrap_or("file").to_string();
+    let write_error = |e: std::io::Error| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to write file {}: {}", name, e)).into_response();
+    let mut file = tokio::fs::File::create(path).await.map_err(write_error)?;
+    loop {
+        match field.chunk().await {
+            Ok(Some(chunk)) => {
+                on_chunk(&chunk);
+                file.write_all(&chunk).await.map_err(write_error)?;
+            }
+            Ok(None) => break,
+            Err(e) => {
+                error!("Failed to read chunks for file {}: {}", name, e);
+                return Err((StatusCode::BAD_REQUEST, format!("Failed to read file {}: {}", name, e)).into_response());
+            }
+        }
+    }
+    file.flush().await.map_err(write_error)
+}
+
+/// Loads an image given either as a blob store hash or as base64 and names it `<stem>.<ext>`.
+async fn resolve_image(state: &AppState, hash: Option<&str>, base64: Option<&str>, stem: &str) -> Result<Option<(String, Vec<u8>)>, Response> {
+    let data = match (hash, base64) {
+        (Some(hash), _) => match state.blob_store.get(hash).await {
+            Some(data) => data.to_vec(),
+            None => return Err((StatusCode::NOT_FOUND, format!("Blob {} not found", hash)).into_response()),
+        },
+        (None, Some(b64)) => match general_purpose::STANDARD.decode(b64) {
+            Ok(data) => data,
+            Err(e) => return Err((StatusCode::BAD_REQUEST, format!("Invalid {} base64: {}", stem, e)).into_response()),
+        },
+        (None, None) => return Ok(None),
+    };
+    image_file(stem, data).map(Some).map_err(|e| (StatusCode::BAD_REQUEST, e).into_response())
+}
+
+async fn resolve_branding(state: &AppState, id: Option<&str>) -> Result<Option<BrandingProfile>, Response> {
+    match id {
+        Some(id) => match state.branding.get(id).await {
+            Some(profile) => Ok(Some(profile)),
+            None => Err((StatusCode::NOT_FOUND, format!("Branding profile {} not found", id)).into_response()),
+        },
+        None => Ok(None),
+    }
+}
+
+#[utoipa::path(
+    post, path = "/branding", tag = "branding",
+    request_body = BrandingRequest,
+    responses(
+        (status = 201, description = "Profile created", body = BrandingProfile),
+        (status = 400, description = "Invalid color or logo", body = String),
+    )
+)]
+pub async fn create_branding_handler(
+    State(state): State<AppState>,
+    Json(payload): Json<BrandingRequest>,
+) -> Response {
+    let colors = [
+        payload.primary_color.as_deref().unwrap_or(DEFAULT_PRIMARY_COLOR),
+        payload.secondary_color.as_deref().unwrap_or(DEFAULT_SECONDARY_COLOR),
+    ].map(Branding::parse_color);
+    let [primary_color, secondary_color] = match colors {
+        [Ok(p), Ok(s)] => [p, s],
+        [Err(e), _] | [_, Err(e)] => return (StatusCode::BAD_REQUEST, e).into_response(),
+    };
+    let logo = match resolve_image(&state, payload.logo_hash.as_deref(), payload.logo_base64.as_deref(), "brand-logo").await {
+        Ok(l) => l,
+        Err(response) => return response,
+    };
+
+    let profile = BrandingProfile {
+        id: uuid::Uuid::new_v4().to_string(),
+        name: payload.name,
+        primary_color,
+        secondary_color,
+        footer_text: payload.footer_text,
+        has_logo: logo.is_some(),
+        logo,
+        created_at: unix_now(),
+    };
+    info!("🎨 Created branding profile {}", profile.id);
+    state.branding.put(profile.clone()).await;
+    (StatusCode::CREATED, Json(profile)).into_response()
+}
+
+#[utoipa::path(
+    get, path = "/branding/{id}", tag = "branding",
+    params(("id" = String, Path, description = "Branding profile id")),
+    responses(
+        (status = 200, description = "Profile", body = BrandingProfile),
+        (status = 404, description = "Profile not found", body = String),
+    )
+)]
+pub async fn get_branding_handler(
+    State(state): State<AppState>,
+    UrlPath(id): UrlPath<String>,
+) -> Response {
+    match state.branding.get(&id).await {
+        Some(profile) => Json(profile).into_response(),
+        None => (StatusCode::NOT_FOUND, format!("Branding profile {} not found", id)).into_response(),
+    }
+}
+
+#[utoipa::path(
+    delete, path = "/branding/{id}", tag = "branding",
+    params(("id" = String, Path, description = "Branding profile id")),
+    responses(
+        (status = 204, description = "Profile deleted"),
+        (status = 404, description = "Profile not found"),
+    )
+)]
+pub async fn delete_branding_handler(
+    State(state): State<AppState>,
+    UrlPath(id): UrlPath<String>,
+) -> StatusCode {
+    if state.branding.remove(&id).await { StatusCode::NO_CONTENT } else { StatusCode::NOT_FOUND }
+}
+
+/// Applies the outbound SSRF policy to a webhook URL at registration time.
+async fn check_webhook_target(url: &str, settings: &Settings) -> Result<(), String> {
+    let url = Webhooks::validate_url(url, settings)?;
+    Webhooks::resolve_target(&url, settings).await.map(|_| ())
+}
+
+#[utoipa::path(
+    get, path = "/webhooks", tag = "webhooks",
+    responses((status = 200, description = "All subscriptions (secrets omitted)", body = Vec<WebhookSubscription>))
+)]
+pub async fn list_webhooks_handler(State(state): State<AppState>) -> Json<Vec<WebhookSubscription>> {
+    Json(state.webhooks.read().await.clone())
+}
+
+#[utoipa::path(
+    post, path = "/webhooks", tag = "webhooks",
+    request_body = WebhookRequest,
+    responses(
+        (status = 201, description = "Subscription created; the secret is only returned here", body = WebhookSecretResponse),
+        (status = 400, description = "Invalid URL, events or secret", body = String),
+    )
+)]
+pub async fn create_webhook_handler(
+    State(state): State<AppState>,
+    Json(payload): Json<WebhookRequest>,
+) -> Response {
+    if let Err(e) = check_webhook_target(&payload.url, &state.settings).await {
+        return (StatusCode::BAD_REQUEST, e).into_response();
+    }
+    let events = match Webhooks::normalize_events(&payload.events) {
+        Ok(events) => events,
+        Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+    };
+    if let Err(e) = Webhooks::validate_filter(&payload.filter) {
+        return (StatusCode::BAD_REQUEST, e).into_response();
+    }
+    let secret = match payload.secret {
+        Some(secret) => match Webhooks::validate_secret(&secret) {
+            Ok(()) => secret,
+            Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+        },
+        None => Webhooks::generate_secret(),
+    };
+
+    let now = unix_now();
+    let subscription = WebhookSubscription {
+        id: uuid::Uuid::new_v4().to_string(),
+        url: payload.url,
+        events,
+        secret: secret.clone(),
+        filter: payload.filter,
+        created_at: now,
+        updated_at: now,
+    };
+    info!("🔔 Registered webhook {} -> {}", subscription.id, subscription.url);
+    state.webhooks.write().await.push(subscription.clone());
+    (StatusCode::CREATED, Json(WebhookSecretResponse { subscription, secret })).into_response()
+}
+
+#[utoipa::path(
+    patch, path = "/webhooks/{id}", tag = "webhooks",
+    params(("id" = String, Path, description = "Webhook id")),
+    request_body = WebhookUpdateRequest,
+    responses(
+        (status = 200, description = "Updated subscription", body = WebhookSubscription),
+        (status = 400, description = "Invalid URL, events or secret", body = String),
+        (status = 404, description = "Webhook not found", body = String),
+    )
+)]
+pub async fn update_webhook_handler(
+    State(state): State<AppState>,
+    UrlPath(id): UrlPath<String>,
+    Json(payload): Json<WebhookUpdateRequest>,
+) -> Response {
+    if let Some(url) = &payload.url {
+        if let Err(e) = check_webhook_target(url, &state.settings).await {
+            return (StatusCode::BAD_REQUEST, e).into_response();
+        }
+    }
+    let events = match payload.events.as_deref().map(Webhooks::normalize_events).transpose() {
+        Ok(events) => events,
+        Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+    };
+    if let Some(Err(e)) = payload.secret.as_deref().map(Webhooks::validate_secret) {
+        return (StatusCode::BAD_REQUEST, e).into_response();
+    }
+    if let Some(Err(e)) = payload.filter.as_ref().map(Webhooks::validate_filter) {
+        return (StatusCode::BAD_REQUEST, e).into_response();
+    }
+
+    // Applied under a single write lock so deliveries never see a half-updated subscription
+    let mut webhooks = state.webhooks.write().await;
+    let Some(subscription) = webhooks.iter_mut().find(|w| w.id == id) else {
+        return (StatusCode::NOT_FOUND, format!("Webhook {} not found", id)).into_response();
+    };
+    if let Some(url) = payload.url {
+        subscription.url = url;
+    }
+    if let Some(events) = events {
+        subscription.events = events;
+    }
+    if let Some(secret) = payload.secret {
+        subscription.secret = secret;
+    }
+    if let Some(filter) = payload.filter {
+        subscription.filter = filter;
+    }
+    subscription.updated_at = unix_now();
+    info!("🔔 Updated webhook {}", id);
+    Json(subscription.clone()).into_response()
+}
+
+#[utoipa::path(
+    post, path = "/webhooks/{id}/rotate-secret", tag = "webhooks",
+    params(("id" = String, Path, description = "Webhook id")),
+    responses(
+        (status = 200, description = "Subscription with its new secret", body = WebhookSecretResponse),
+        (status = 404, description = "Webhook not found", body = String),
+    )
+)]
+pub async fn rotate_webhook_secret_handler(
+    State(state): State<AppState>,
+    UrlPath(id): UrlPath<String>,
+) -> Response {
+    let mut webhooks = state.webhooks.write().await;
+    let Some(subscription) = webhooks.iter_mut().find(|w| w.id == id) else {
+        return (StatusCode::NOT_FOUND, format!("Webhook {} not found", id)).into_response();
+    };
+    let secret = Webhooks::generate_secret();
+    subscription.secret = secret.clone();
+    subscription.updated_at = unix_now();
+    info!("🔑 Rotated secret for webhook {}", id);
+    Json(WebhookSecretResponse { subscription: subscription.clone(), secret }).into_response()
+}
+
+#[utoipa::path(
+    delete, path = "/webhooks/{id}", tag = "webhooks",
+    params(("id" = String, Path, description = "Webhook id")),
+    responses(
+        (status = 204, description = "Webhook deleted"),
+        (status = 404, description = "Webhook not found"),
+    )
+)]
+pub async fn delete_webhook_handler(
+    State(state): State<AppState>,
+    UrlPath(id): UrlPath<String>,
+) -> StatusCode {
+    let mut webhooks = state.webhooks.write().await;
+    let before = webhooks.len();
+    webhooks.retain(|w| w.id != id);
+    if webhooks.len() == before {
+        return StatusCode::NOT_FOUND;
+    }
+    state.dead_letters.clear(&id).await;
+    StatusCode::NO_CONTENT
+}
+
+#[utoipa::path(
+    get, path = "/webhooks/{id}/dead-letters", tag = "webhooks",
+    params(("id" = String, Path, description = "Webhook id")),
+    responses(
+        (status = 200, description = "Deliveries that failed every retry", body = Vec<DeadLetter>),
+        (status = 404, description = "Webhook not found", body = String),
+    )
+)]
+pub async fn list_dead_letters_handler(
+    State(state): State<AppState>,
+    UrlPath(id): UrlPath<String>,
+) -> Response {
+    if !state.webhooks.read().await.iter().any(|w| w.id == id) {
+        return (StatusCode::NOT_FOUND, format!("Webhook {} not found", id)).into_response();
+    }
+    Json(state.dead_letters.list(&id).await).into_response()
+}
+
+/// Retries a dead letter once against the subscription's current URL and secret.
+/// Delivered letters are removed; failures stay queued with the new error.
+#[utoipa::path(
+    post, path = "/webhooks/{id}/dead-letters/{letter_id}/redeliver", tag = "webhooks",
+    params(
+        ("id" = String, Path, description = "Webhook id"),
+        ("letter_id" = String, Path, description = "Dead letter id"),
+    ),
+    responses(
+        (status = 200, description = "Delivered; the dead letter is removed", body = DeadLetter),
+        (status = 404, description = "Webhook or dead letter not found", body = String),
+        (status = 502, description = "Delivery failed again", body = DeadLetter),
+    )
+)]
+pub async fn redeliver_dead_letter_handler(
+    State(state): State<AppState>,
+    UrlPath((id, letter_id)): UrlPath<(String, String)>,
+) -> Response {
+    let Some(subscription) = state.webhooks.read().await.iter().find(|w| w.id == id).cloned() else {
+        return (StatusCode::NOT_FOUND, format!("Webhook {} not found", id)).into_response();
+    };
+    let Some(mut letter) = state.dead_letters.get(&id, &letter_id).await else {
+        return (StatusCode::NOT_FOUND, format!("Dead letter {} not found", letter_id)).into_response();
+    };
+
+    let result = Webhooks::deliver(&subscription, &letter.id, &letter.payload, &state.settings).await;
+    letter.attempts += 1;
+    letter.last_attempt_at = unix_now();
+    letter.url = subscription.url;
+    match result {
+        Ok(()) => {
+            info!("📬 Redelivered {} to webhook {}", letter.id, id);
+            state.dead_letters.remove(&id, &letter_id).await;
+            Json(letter).into_response()
+        }
+        Err(e) => {
+            letter.error = e;
+            state.dead_letters.update(letter.clone()).await;
+            (StatusCode::BAD_GATEWAY, Json(letter)).into_response()
+        }
+    }
+}
+
+#[utoipa::path(
+    post, path = "/bib/format", tag = "tools",
+    request_body = BibFormatRequest,
+    responses((status = 200, description = "Normalized .bib and what changed", body = BibFormatResponse))
+)]
+pub async fn bib_format_handler(Json(payload): Json<BibFormatRequest>) -> Json<BibFormatResponse> {
+    let (content, report) = BibFormatter::format(&payload.content);
+    info!("📚 Formatted .bib: {} entries -> {} ({} duplicates)", report.entries_in, report.entries_out, report.duplicates.len());
+    Json(BibFormatResponse { content, report })
+}
+
+#[utoipa::path(
+    post, path = "/escape", tag = "tools",
+    request_body = EscapeRequest,
+    responses(
+        (status = 200, description = "The text and fields escaped for literal use in LaTeX", body = EscapeResponse),
+        (status = 400, description = "Neither text nor fields given", body = String),
+    )
+)]
+pub async fn escape_handler(Json(payload): Json<EscapeRequest>) -> Response {
+    if payload.text.is_none() && payload.fields.is_none() {
+        return (StatusCode::BAD_REQUEST, "Provide text or fields to escape").into_response();
+    }
+    let escape_text: fn(&str) -> String = if payload.newlines { crate::latex::escape_lines } else { crate::latex::escape };
+    Json(EscapeResponse {
+        text: payload.text.as_deref().map(escape_text),
+        fields: payload.fields.as_ref().map(|fields| crate::latex::escape_json(fields, escape_text)),
+    }).into_response()
+}
+
+#[utoipa::path(
+    post, path = "/convert/html", tag = "tools",
+    request_body = HtmlConvertRequest,
+    responses(
+        (status = 200, description = "LaTeX for the HTML and what it needs to compile", body = HtmlConvertResponse),
+        (status = 400, description = "HTML too deeply nested", body = String),
+    )
+)]
+pub async fn convert_html_handler(Json(payload): Json<HtmlConvertRequest>) -> Response {
+    let conversion = match HtmlConverter::convert(&payload.html, payload.numbered) {
+        Ok(c) => c,
+        Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+    };
+    info!("🔁 Converted {} bytes of HTML ({} warnings)", payload.html.len(), conversion.warnings.len());
+    let latex = if payload.document { conversion.to_document() } else { conversion.latex.clone() };
+    Json(HtmlConvertResponse {
+        latex,
+        preamble: conversion.preamble,
+        images: conversion.images,
+        warnings: conversion.warnings,
+    }).into_response()
+}
+
+#[utoipa::path(
+    post, path = "/convert/docx", tag = "tools",
+    params(DocxQuery),
+    request_body(content_type = "multipart/form-data", description = "A single .docx file"),
+    responses(
+        (status = 200, description = "Rendered output; the media type follows `format`. Dropped content is listed in `X-Conversion-Warnings`", content(("application/pdf"), ("image/svg+xml"), ("image/png"), ("application/x-tex"))),
+        (status = 400, description = "Missing or unreadable .docx", body = String),
+    )
+)]
+pub async fn convert_docx_handler(
+    State(state): State<AppState>,
+    Query(query): Query<DocxQuery>,
+    mut multipart: Multipart,
+) -> Response {
+    let (name, data) = loop {
+        match multipart.next_field().await {
+            Ok(Some(field)) if field.file_name().is_some() => {
+                let name = field.file_name().unwrap_or("document.docx").to_string();
+                match field.bytes().await {
+                    Ok(data) => break (name, data),
+                    Err(e) => return (StatusCode::BAD_REQUEST, format!("Failed to read file {}: {}", name, e)).into_response(),
+                }
+            }
+            Ok(Some(_)) => continue,
+            Ok(None) => return (StatusCode::BAD_REQUEST, "No .docx file uploaded".to_string()).into_response(),
+            Err(e) => return (StatusCode::BAD_REQUEST, format!("Multipart error: {}", e)).into_response(),
+        }
+    };
+
+    // Unzipping and parsing the document is CPU-bound
+    let input = data.clone();
+    let conversion = match tokio::task::spawn_blocking(move || DocxConverter::convert(&input)).await {
+        Ok(Ok(c)) => c,
+        Ok(Err(e)) => return (StatusCode::BAD_REQUEST, e).into_response(),
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, format!("Conversion failed: {}", e)).into_response(),
+    };
+    info!("🔁 Converted {} ({} bytes, {} images, {} warnings)", name, data.len(), conversion.files.len(), conversion.warnings.len());
+
+    let format = query.format.as_deref().unwrap_or("pdf");
+    let response = if format == "tex" {
+        ([(header::CONTENT_TYPE, "application/x-tex")], conversion.source).into_response()
+    } else {
+        render::render_response(&state, &conversion.source, &conversion.files, format, &name).await
+    };
+    with_conversion_warnings(response, &conversion.warnings)
+}
+
+#[utoipa::path(
+    post, path = "/import/overleaf", tag = "projects",
+    params(("X-Tenant-Id" = Option<String>, Header, description = "Owner of the project; only requests with the same header can use it")),
+    request_body(content_type = "multipart/form-data", description = "An Overleaf export or any ZIP of a LaTeX project"),
+    responses(
+        (status = 201, description = "Imported project, with the detected main file and engine", body = Project),
+        (status = 400, description = "Missing or unreadable archive, or no main file", body = String),
+    )
+)]
+pub async fn import_overleaf_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    mut multipart: Multipart,
+) -> Response {
+    let (name, data) = loop {
+        match multipart.next_field().await {
+            Ok(Some(field)) if field.file_name().is_some() => {
+                let name = field.file_name().unwrap_or("project.zip").to_string();
+                match field.bytes().await {
+                    Ok(data) => break (name, data),
+                    Err(e) => return (StatusCode::BAD_REQUEST, format!("Failed to read file {}: {}", name, e)).into_response(),
+                }
+            }
+            Ok(Some(_)) => continue,
+            Ok(None) => return (StatusCode::BAD_REQUEST, "No .zip file uploaded".to_string()).into_response(),
+            Err(e) => return (StatusCode::BAD_REQUEST, format!("Multipart error: {}", e)).into_response(),
+        }
+    };
+
+    let mut imported = match tokio::task::spawn_blocking(move || OverleafImporter::import(&data)).await {
+        Ok(Ok(imported)) => imported,
+        Ok(Err(e)) => return (StatusCode::BAD_REQUEST, e).into_response(),
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, format!("Import failed: {}", e)).into_response(),
+    };
+    // Files of a type compiles would refuse are left out rather than failing the import
+    let policy = FilePolicy::new(&state.settings.allowed_file_types);
+    imported.files.retain(|(file_name, bytes)| match policy.check(file_name, &bytes[..bytes.len().min(4096)]) {
+        Some(rejection) => {
+            imported.warnings.push(format!("Dropped {}: {}", rejection.file, rejection.reason));
+            false
+        }
+        None => true,
+    });
+    let mut files = Vec::with_capacity(imported.files.len());
+    for (file_name, bytes) in imported.files {
+        let hash = format!("{:x}", xxh64(&bytes, 0));
+        let size = bytes.len() as u64;
+        state.blob_store.put(hash.clone(), bytes).await;
+        files.push(ProjectFile { name: file_name, hash, size });
+    }
+    files.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let project = Project {
+        id: uuid::Uuid::new_v4().simple().to_string(),
+        main: imported.main,
+        engine: imported.engine,
+        files,
+        warnings: imported.warnings,
+        created_at: unix_now(),
+        last_build: None,
+    };
+    info!("📦 Imported {} as project {} ({} files, main {}, {})", name, project.id, project.files.len(), project.main, project.engine.program);
+    let tenant = headers.get("X-Tenant-Id").and_then(|v| v.to_str().ok()).map(str::to_string);
+    state.projects.insert(tenant, project.clone()).await;
+    (StatusCode::CREATED, Json(project)).into_response()
+}
+
+#[utoipa::path(
+    get, path = "/projects/{id}", tag = "projects",
+    params(
+        ("id" = String, Path, description = "Project id"),
+        ("X-Tenant-Id" = Option<String>, Header, description = "Tenant the project was created for"),
+    ),
+    responses(
+        (status = 200, description = "The project's files, main file and engine", body = Project),
+        (status = 404, description = "No such project for this tenant", body = String),
+    )
+)]
+pub async fn get_project_handler(State(state): State<AppState>, UrlPath(id): UrlPath<String>, headers: HeaderMap) -> Response {
+    let tenant = headers.get("X-Tenant-Id").and_then(|v| v.to_str().ok());
+    match state.projects.get(&id, tenant).await {
+        Some(project) => Json(project).into_response(),
+        None => (StatusCode::NOT_FOUND, format!("Project {} not found", id)).into_response(),
+    }
+}
+
+#[utoipa::path(
+    post, path = "/projects/{id}/compile", tag = "projects",
+    params(
+        ("id" = String, Path, description = "Project id"),
+        ("X-Tenant-Id" = Option<String>, Header, description = "Tenant the project was created for"),
+    ),
+    responses(
+        (status = 200, description = "Compiled PDF, with `X-Output-Hash` and `X-Compile-Time-Ms` headers", content_type = "application/pdf"),
+        (status = 404, description = "No such project for this tenant, or one of its blobs is gone", body = String),
+        (status = 500, description = "Compilation failed; the body holds the error and log", body = String),
+        (status = 502, description = "A data connector could not fetch its rows", body = String),
+    )
+)]
+pub async fn compile_project_handler(State(state): State<AppState>, UrlPath(id): UrlPath<String>, headers: HeaderMap) -> Response {
+    let tenant = headers.get("X-Tenant-Id").and_then(|v| v.to_str().ok());
+    let Some(project) = state.projects.get(&id, tenant).await else {
+        return (StatusCode::NOT_FOUND, format!("Project {} not found", id)).into_response();
+    };
+    let mut files = match project_files(&state, &project).await {
+        Ok(files) => files,
+        Err(e) => return e.into_response(),
+    };
+    if let Err(e) = crate::connectors::add_files(&state, &project.id, tenant, &mut files).await {
+        return (StatusCode::BAD_GATEWAY, e).into_response();
+    }
+
+    let priority = Priority::parse(headers.get("X-Priority").and_then(|v| v.to_str().ok()));
+    match build_project(&state, &project, &files, priority).await {
+        Ok((pdf_data, build)) => Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, "application/pdf")
+            .header("X-Output-Hash", build.output_hash)
+            .header("X-Compile-Time-Ms", build.compile_time_ms.to_string())
+            .body(axum::body::Body::from(pdf_data))
+            .unwrap(),
+        Err(e) => e.into_response(),
+    }
+}
+
+#[utoipa::path(
+    get, path = "/projects/{id}/export", tag = "projects",
+    params(
+        ("id" = String, Path, description = "Project id"),
+        ("X-Tenant-Id" = Option<String>, Header, description = "Tenant the project was created for"),
+    ),
+    responses(
+        (status = 200, description = "ZIP of the sources, `output.pdf` and a `tachyon-export.json` manifest (ExportManifest) with the engine, bundle and package versions of the build", content_type = "application/zip"),
+        (status = 404, description = "No such project for this tenant, or one of its blobs is gone", body = String),
+        (status = 500, description = "The project had to be compiled for the export and failed", body = String),
+    )
+)]
+pub async fn export_project_handler(State(state): State<AppState>, UrlPath(id): UrlPath<String>, headers: HeaderMap) -> Response {
+    let tenant = headers.get("X-Tenant-Id").and_then(|v| v.to_str().ok());
+    let Some(project) = state.projects.get(&id, tenant).await else {
+        return (StatusCode::NOT_FOUND, format!("Project {} not found", id)).into_response();
+    };
+    let files = match project_files(&state, &project).await {
+        Ok(files) => files,
+        Err(e) => return e.into_response(),
+    };
+
+    // Export the last build while its PDF is still stored; otherwise build it now
+    let stored = match &project.last_build {
+        Some(build) => state.output_store.get(&build.output_hash).await.map(|pdf| (pdf, build.clone())),
+        None => None,
+    };
+    let (pdf_data, build) = match stored {
+        Some(stored) => stored,
+        None => match build_project(&state, &project, &files, Priority::parse(headers.get("X-Priority").and_then(|v| v.to_str().ok()))).await {
+            Ok(built) => built,
+            Err(e) => return e.into_response(),
+        },
+    };
+
+    let archive = match tokio::task::spawn_blocking(move || crate::export::bundle(&project, &build, &files, &pdf_data)).await {
+        Ok(Ok(archive)) => archive,
+        Ok(Err(e)) => return (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to create export: {}", e)).into_response(),
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, format!("Export failed: {}", e)).into_response(),
+    };
+    info!("📦 Exported project {} ({} bytes)", id, archive.len());
+    (
+        [
+            (header::CONTENT_TYPE, "application/zip".to_string()),
+            (header::CONTENT_DISPOSITION, format!("attachment; filename=\"{}.zip\"", id)),
+        ],
+        archive,
+    ).into_response()
+}
+
+#[utoipa::path(
+    delete, path = "/tenants/{id}/data", tag = "projects",
+    params(
+        ("id" = String, Path, description = "Tenant whose data is erased"),
+        ("X-Tenant-Id" = Option<String>, Header, description = "Must equal `id`, with the tenant's `X-Tenant-Token`: tenants can only erase their own data"),
+        ("Authorization" = Option<String>, Header, description = "`Bearer <ADMIN_TOKEN>`, to erase any tenant's data"),
+    ),
+    responses(
+        (status = 200, description = "Everything stored for the tenant was deleted; the report counts what was removed", body = DeletionReport),
+        (status = 403, description = "Neither the authenticated tenant nor an admin", body = String),
+    )
+)]
+pub async fn erase_tenant_handler(State(state): State<AppState>, UrlPath(id): UrlPath<String>, headers: HeaderMap) -> Response {
+    // The tenant is only set once `tenancy::tenant_scope` has checked its token
+    let own = crate::tenancy::current_tenant().as_deref() == Some(id.as_str());
+    if !own && require_admin(&state, &headers).is_err() {
+        return (StatusCode::FORBIDDEN, "Only the tenant itself or an admin can erase its data".to_string()).into_response();
+    }
+    let report = crate::retention::purge(&state, &id, None).await;
+    info!("🗑️ Erased tenant {}: {} project(s), {} fingerprint(s), {} object(s), {} shared kept",
+        id, report.projects, report.fingerprints, report.objects.values().sum::<usize>(), report.shared_objects_kept);
+    Json(report).into_response()
+}
+
+#[utoipa::path(
+    put, path = "/projects/{id}/connectors/{name}", tag = "projects",
+    params(
+        ("id" = String, Path, description = "Project id"),
+        ("name" = String, Path, description = "Connector name; its rows are written to `<name>.csv`"),
+        ("X-Tenant-Id" = Option<String>, Header, description = "Tenant the project was created for"),
+    ),
+    request_body = ConnectorConfig,
+    responses(
+        (status = 200, description = "The connector, without its credentials. It runs before every compile of the project", body = ConnectorInfo),
+        (status = 400, description = "An invalid name, missing fields for the type, or a URL or database host the outbound policy rejects", body = String),
+        (status = 404, description = "No such project for this tenant", body = String),
+    )
+)]
+pub async fn put_connector_handler(State(state): State<AppState>, UrlPath((id, name)): UrlPath<(String, String)>, headers: HeaderMap, Json(config): Json<ConnectorConfig>) -> Response {
+    let tenant = headers.get("X-Tenant-Id").and_then(|v| v.to_str().ok());
+    if state.projects.get(&id, tenant).await.is_none() {
+        return (StatusCode::NOT_FOUND, format!("Project {} not found", id)).into_response();
+    }
+    match state.connectors.put(&id, tenant, &name, config, &state.settings).await {
+        Ok(info) => {
+            info!("🔌 Configured {} connector {} for project {}", info.kind, info.name, id);
+            Json(info).into_response()
+        }
+        Err(e) => (StatusCode::BAD_REQUEST, e).into_response(),
+    }
+}
+
+#[utoipa::path(
+    get, path = "/projects/{id}/connectors", tag = "projects",
+    params(
+        ("id" = String, Path, description = "Project id"),
+        ("X-Tenant-Id" = Option<String>, Header, description = "Tenant the project was created for"),
+    ),
+    responses((status = 200, description = "The project's data connectors by name, without credentials", body = Vec<ConnectorInfo>))
+)]
+pub async fn list_connectors_handler(State(state): State<AppState>, UrlPath(id): UrlPath<String>, headers: HeaderMap) -> Response {
+    let tenant = headers.get("X-Tenant-Id").and_then(|v| v.to_str().ok());
+    Json(state.connectors.list(&id, tenant).await).into_response()
+}
+
+#[utoipa::path(
+    delete, path = "/projects/{id}/connectors/{name}", tag = "projects",
+    params(
+        ("id" = String, Path, description = "Project id"),
+        ("name" = String, Path, description = "Connector name"),
+        ("X-Tenant-Id" = Option<String>, Header, description = "Tenant the project was created for"),
+    ),
+    responses(
+        (status = 204, description = "Connector and its credentials deleted"),
+        (status = 404, description = "No such connector for this tenant", body = String),
+    )
+)]
+pub async fn delete_connector_handler(State(state): State<AppState>, UrlPath((id, name)): UrlPath<(String, String)>, headers: HeaderMap) -> Response {
+    let tenant = headers.get("X-Tenant-Id").and_then(|v| v.to_str().ok());
+    if state.connectors.delete(&id, &name, tenant).await {
+        info!("🗑️ Deleted connector {} of project {}", name, id);
+        StatusCode::NO_CONTENT.into_response()
+    } else {
+        (StatusCode::NOT_FOUND, format!("Connector {} not found", name)).into_response()
+    }
+}
+
+#[utoipa::path(
+    post, path = "/projects/{id}/connectors/{name}/preview", tag = "projects",
+    params(
+        ("id" = String, Path, description = "Project id"),
+        ("name" = String, Path, description = "Connector name"),
+        ("X-Tenant-Id" = Option<String>, Header, description = "Tenant the project was created for"),
+    ),
+    responses(
+        (status = 200, description = "The columns and first rows the connector fetches now", body = ConnectorPreview),
+        (status = 404, description = "No such connector for this tenant", body = String),
+        (status = 502, description = "The connector could not fetch its rows", body = String),
+    )
+)]
+pub async fn preview_connector_handler(State(state): State<AppState>, UrlPath((id, name)): UrlPath<(String, String)>, headers: HeaderMap) -> Response {
+    let tenant = headers.get("X-Tenant-Id").and_then(|v| v.to_str().ok());
+    match crate::connectors::preview(&state, &id, &name, tenant).await {
+        Some(Ok(preview)) => Json(preview).into_response(),
+        Some(Err(e)) => (StatusCode::BAD_GATEWAY, e).into_response(),
+        None => (StatusCode::NOT_FOUND, format!("Connector {} not found", name)).into_response(),
+    }
+}
+
+/// Loads a project's files from the blob store.
+async fn project_files(state: &AppState, project: &Project) -> Result<Vec<render::AuxFile>, (StatusCode, String)> {
+    let mut files = Vec::with_capacity(project.files.len());
+    for file in &project.files {
+        match state.blob_store.get(&file.hash).await {
+            Some(data) => files.push((file.name.clone(), data.to_vec())),
+            None => return Err((StatusCode::NOT_FOUND, format!("Blob {} of {} not found", file.hash, file.name))),
+        }
+    }
+    Ok(files)
+}
+
+/// Compiles a project, stores the PDF and records the build on the project.
+async fn build_project(state: &AppState, project: &Project, files: &[render::AuxFile], priority: Priority) -> Result<(bytes::Bytes, ProjectBuild), (StatusCode, String)> {
+    let start = Instant::now();
+    let (result, logs) = render::compile_files(state, &project.main, files, priority).await;
+    let compile_time_ms = start.elapsed().as_millis() as u64;
+    let pdf_data = result.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("LaTeX Error: {}\n\nLogs:\n{}", e, logs)))?;
+    let output_hash = state.output_store.put(&pdf_data).await;
+    info!("🖨️ Compiled project {} in {}ms -> {}", project.id, compile_time_ms, output_hash);
+
+    // A cache hit has no log; the packages are the ones of the build that produced this PDF
+    let mut packages = crate::export::packages_from_log(&logs);
+    if packages.is_empty() {
+        if let Some(last) = project.last_build.as_ref().filter(|b| b.output_hash == output_hash) {
+            packages = last.packages.clone();
+        }
+    }
+    let build = ProjectBuild {
+        output_hash,
+        compile_time_ms,
+        compiled_at: unix_now(),
+        engine_version: crate::receipt::ENGINE_VERSION.to_string(),
+        bundle_version: state.bundles.active().version,
+        packages,
+    };
+    state.projects.set_build(&project.id, build.clone()).await;
+    Ok((pdf_data, build))
+}
+
+#[utoipa::path(
+    post, path = "/convert/ipynb", tag = "tools",
+    params(NotebookQuery),
+    request_body(content = Object, description = "A Jupyter notebook (nbformat 4), as saved in the .ipynb file"),
+    responses(
+        (status = 200, description = "Rendered output; the media type follows `format`. Dropped content is listed in `X-Conversion-Warnings`", content(("application/pdf"), ("image/svg+xml"), ("image/png"), ("application/x-tex"))),
+        (status = 400, description = "Not an nbformat 4 notebook", body = String),
+    )
+)]
+pub async fn convert_ipynb_handler(
+    State(state): State<AppState>,
+    Query(query): Query<NotebookQuery>,
+    Json(notebook): Json<serde_json::Value>,
+) -> Response {
+    let options = NotebookOptions { title: query.title.as_deref(), hide_code: query.hide_code, numbered: query.numbered };
+    let conversion = match NotebookConverter::convert(&notebook, &options) {
+        Ok(c) => c,
+        Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+    };
+    info!("🔁 Converted a notebook ({} images, {} warnings)", conversion.files.len(), conversion.warnings.len());
+
+    let format = query.format.as_deref().unwrap_or("pdf");
+    let response = if format == "tex" {
+        ([(header::CONTENT_TYPE, "application/x-tex")], conversion.source).into_response()
+    } else {
+        render::render_response(&state, &conversion.source, &conversion.files, format, "notebook").await
+    };
+    with_conversion_warnings(response, &conversion.warnings)
+}
+
+/// Lists content a converter dropped in an `X-Conversion-Warnings` JSON header.
+fn with_conversion_warnings(mut response: Response, warnings: &[String]) -> Response {
+    if warnings.is_empty() {
+        return response;
+    }
+    let shown = &warnings[..warnings.len().min(MAX_HEADER_WARNINGS)];
+    if let Some(value) = serde_json::to_string(shown).ok().and_then(|json| HeaderValue::from_str(&json).ok()) {
+        response.headers_mut().insert("X-Conversion-Warnings", value);
+    }
+    response
+}
+
+/// Encodes compile warnings as a compact JSON header value (capped to keep headers small).
+/// Returns `None` when there is nothing to report or the JSON is not a valid header.
+fn warnings_header(warnings: &[CompileWarning]) -> Option<HeaderValue> {
+    if warnings.is_empty() {
+        return None;
+    }
+    let shown = &warnings[..warnings.len().min(MAX_HEADER_WARNINGS)];
+    serde_json::to_string(shown).ok().and_then(|json| HeaderValue::from_str(&json).ok())
+}
+
+/// The fixes self-healing applied during a compile, comma separated; `None` when no
+/// healing was attempted.
+fn healing_header(fixes: Option<&[String]>) -> Option<HeaderValue> {
+    fixes.and_then(|fixes| HeaderValue::from_str(&fixes.join(", ")).ok())
+}
+
+/// Blank and float-only pages of a compiled PDF, as warnings. Parsing the PDF can take a
+/// while, so it runs on the blocking pool.
+async fn ghost_page_warnings(pdf: bytes::Bytes) -> Vec<CompileWarning> {
+    match tokio::task::spawn_blocking(move || crate::pages::ghost_page_warnings(&pdf)).await {
+        Ok(Ok(warnings)) => warnings,
+        Ok(Err(e)) => {
+            warn!("Page analysis failed: {}", e);
+            Vec::new()
+        }
+        Err(e) => {
+            warn!("Page analysis failed: {}", e);
+            Vec::new()
+        }
+    }
+}
+
+/// Signs a provenance receipt for a compile result, base64-encoded for the `X-Compile-Receipt` header.
+fn receipt_header(state: &AppState, files: &[(String, String)], pdf: &[u8], output_hash: &str, compile_time_ms: u64) -> Option<HeaderValue> {
+    let signed = state.receipt_signer.issue(files, pdf, output_hash, &state.bundles.active().version, compile_time_ms, unix_now());
+    let json = serde_json::to_vec(&signed).ok()?;
+    HeaderValue::from_str(&general_purpose::STANDARD.encode(json)).ok()
+}
+
+/// Fingerprints a compiled PDF's text into the tenant's similarity index; returns the SimHash
+/// for the `X-Fingerprint` header, or `None` when the document has too little text.
+async fn index_fingerprint(state: &AppState, headers: &HeaderMap, pdf: &[u8], output_hash: &str) -> Option<String> {
+    let pdf = pdf.to_vec();
+    let fingerprint = match tokio::task::spawn_blocking(move || Fingerprint::of_pdf(&pdf)).await {
+        Ok(Ok(fingerprint)) => fingerprint?,
+        Ok(Err(e)) => {
+            warn!("Cannot fingerprint output {}: {}", output_hash, e);
+            return None;
+        }
+        Err(_) => return None,
+    };
+    let simhash = format!("{:016x}", fingerprint.simhash);
+    let tenant = headers.get("X-Tenant-Id").and_then(|v| v.to_str().ok()).map(str::to_string);
+    state.fingerprints.insert(output_hash, tenant, fingerprint).await;
+    Some(simhash)
+}
+
+/// The PDF to serve: the `print_profile` conversion, `forms`, `impose`, `stamp` and then
+/// the `optimize` pass. Returns it with the `X-Optimization` header; a step that fails is
+/// reported as a warning.
+async fn postprocess_pdf(query: &CompileQuery, profile: Option<&Arc<PrintProfile>>, form_fields: &[FormFieldSpec], mut pdf: bytes::Bytes, warnings: &mut Vec<CompileWarning>) -> (bytes::Bytes, Option<HeaderValue>) {
+    if let Some(profile) = profile {
+        let (input, profile) = (pdf.clone(), profile.clone());
+        match tokio::task::spawn_blocking(move || crate::print::to_cmyk(&input, &profile)).await {
+            Ok(Ok((converted, print_warnings))) => {
+                info!("🖨️ Converted PDF to CMYK ({} RGB item(s) left)", print_warnings.len());
+                warnings.extend(print_warnings);
+                pdf = bytes::Bytes::from(converted);
+            }
+            Ok(Err(e)) => warnings.push(CompileWarning {
+                kind: "print".to_string(),
+                file: None,
+                line: None,
+                measurement: None,
+                message: format!("Not converted to CMYK: {}", e),
+            }),
+            Err(e) => error!("CMYK conversion panicked: {}", e),
+        }
+    }
+    if query.forms {
+        let (input, fields) = (pdf.clone(), form_fields.to_vec());
+        let message = match tokio::task::spawn_blocking(move || crate::forms::add_fields(&input, &fields)).await {
+            Ok(Ok((_, 0))) => Some("forms=true, but no form fields were marked with tachyonform or sent in form_fields".to_string()),
+            Ok(Ok((with_fields, count))) => {
+                info!("📝 Added {} form field(s)", count);
+                pdf = bytes::Bytes::from(with_fields);
+                None
+            }
+            Ok(Err(e)) => Some(format!("No form fields added: {}", e)),
+            Err(e) => {
+                error!("Adding form fields panicked: {}", e);
+                None
+            }
+        };
+        warnings.extend(message.map(|message| CompileWarning { kind: "forms".to_string(), file: None, line: None, measurement: None, message }));
+    }
+    if let Some(layout) = query.impose.clone() {
+        let input = pdf.clone();
+        match tokio::task::spawn_blocking(move || crate::impose::impose(&input, &layout)).await {
+            Ok(Ok(imposed)) => pdf = bytes::Bytes::from(imposed),
+            Ok(Err(e)) => warnings.push(CompileWarning {
+                kind: "impose".to_string(),
+                file: None,
+                line: None,
+                measurement: None,
+                message: format!("Not imposed: {}", e),
+            }),
+            Err(e) => error!("Imposition panicked: {}", e),
+        }
+    }
+    if let Some(Ok(options)) = stamp_options(query) {
+        let input = pdf.clone();
+        match tokio::task::spawn_blocking(move || crate::stamp::stamp(&input, &options, options.start)).await {
+            Ok(Ok((stamped, _))) => pdf = bytes::Bytes::from(stamped),
+            Ok(Err(e)) => warnings.push(CompileWarning {
+                kind: "stamp".to_string(),
+                file: None,
+                line: None,
+                measurement: None,
+                message: format!("Not stamped: {}", e),
+            }),
+            Err(e) => error!("Stamping panicked: {}", e),
+        }
+    }
+    if query.optimize { optimize_pdf(pdf).await } else { (pdf, None) }
+}
+
+/// The `stamp` options of a compile, if it asks for one.
+fn stamp_options(query: &CompileQuery) -> Option<Result<crate::stamp::StampOptions, String>> {
+    let template = query.stamp.as_deref()?;
+    Some(crate::stamp::StampOptions::new(template, query.stamp_start, query.stamp_digits, query.stamp_position.as_deref()))
+}
+
+/// The tenant's signer and where to sign, for `sign=true`; checked before compiling so a
+/// request that cannot be signed fails fast.
+async fn signing_for(state: &AppState, query: &CompileQuery) -> Result<Option<(Signer, SignOptions)>, (StatusCode, String)> {
+    if !query.sign {
+        return Ok(None);
+    }
+    // Only a tenant that proved itself with its token signs with its certificate
+    let Some(tenant) = crate::tenancy::current_tenant() else {
+        return Err((StatusCode::BAD_REQUEST, "sign=true requires X-Tenant-Id".to_string()));
+    };
+    let rect = query.sign_rect.as_deref().map(SignOptions::parse_rect).transpose().map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    let options = SignOptions {
+        reason: query.sign_reason.clone(),
+        location: query.sign_location.clone(),
+        page: query.sign_page.unwrap_or(-1),
+        rect,
+    };
+    Ok(Some((state.signing.signer(&tenant, &state.settings).await?, options)))
+}
+
+/// Applies the `sign=true` signature, the last step before serving. A PDF that should be
+/// signed is never served unsigned.
+async fn sign_pdf(signing: Option<&(Signer, SignOptions)>, pdf: bytes::Bytes) -> Result<bytes::Bytes, (StatusCode, String)> {
+    let Some((signer, options)) = signing else { return Ok(pdf) };
+    match signer.sign(&pdf, options).await {
+        Ok(signed) => {
+            info!("✍️ Signed PDF as {}", signer.name());
+            Ok(bytes::Bytes::from(signed))
+        }
+        Err(e) => {
+            error!("Signing failed: {}", e);
+            Err((StatusCode::INTERNAL_SERVER_ERROR, format!("Signing failed: {}", e)))
+        }
+    }
+}
+
+/// Runs the `optimize=true` pass; returns the PDF to serve and the `X-Optimization`
+/// header. A PDF the pass cannot read is served as compiled.
+async fn optimize_pdf(pdf: bytes::Bytes) -> (bytes::Bytes, Option<HeaderValue>) {
+    let input = pdf.clone();
+    match tokio::task::spawn_blocking(move || crate::optimize::optimize(&input)).await {
+        Ok(Ok((optimized, report))) => {
+            info!("🗜️ Optimized PDF: {} -> {} bytes", report.original_size, report.optimized_size);
+            let header = serde_json::to_string(&report).ok().and_then(|r| HeaderValue::from_str(&r).ok());
+            (bytes::Bytes::from(optimized), header)
+        }
+        Ok(Err(e)) => {
+            warn!("Cannot optimize PDF: {}", e);
+            (pdf, None)
+        }
+        Err(_) => (pdf, None),
+    }
+}
+
+#[utoipa::path(
+    get, path = "/receipts/public-key", tag = "receipts",
+    responses((status = 200, description = "Key used to sign compile receipts", body = ReceiptKeyResponse))
+)]
+pub async fn receipt_key_handler(State(state): State<AppState>) -> Json<ReceiptKeyResponse> {
+    Json(ReceiptKeyResponse {
+        algorithm: crate::receipt::RECEIPT_ALGORITHM.to_string(),
+        key_id: state.receipt_signer.key_id().to_string(),
+        public_key: state.receipt_signer.public_key_base64(),
+    })
+}
+
+#[utoipa::path(
+    post, path = "/receipts/verify", tag = "receipts",
+    request_body = SignedReceipt,
+    responses((status = 200, description = "Whether this server signed the receipt", body = ReceiptVerifyResponse))
+)]
+pub async fn receipt_verify_handler(
+    State(state): State<AppState>,
+    Json(receipt): Json<SignedReceipt>,
+) -> Json<ReceiptVerifyResponse> {
+    Json(ReceiptVerifyResponse {
+        valid: state.receipt_signer.verify(&receipt),
+        key_id: state.receipt_signer.key_id().to_string(),
+    })
+}
+
+#[utoipa::path(
+    post, path = "/compile", tag = "compile",
+    params(
+        CompileQuery,
+        ("X-Priority" = Option<String>, Header, description = "\"interactive\" (default) or \"batch\""),
+        ("X-Project-Id" = Option<String>, Header, description = "Project reported to webhooks"),
+        ("X-Tenant-Id" = Option<String>, Header, description = "Tenant reported to webhooks"),
+    ),
+    request_body(content_type = "multipart/form-data", description = "Project files, one part per file; the main .tex is detected or named by a `main` field. With `forms`, a `form_fields` part may place fields by coordinates: a JSON array of `FormFieldSpec`"),
+    responses(
+        (status = 200, description = "Compiled PDF; cache status, timing, warnings, output hash and the fixes of a self-healed compile (`X-Self-Healed`) are returned in `X-*` headers. With `targets`, `subfiles` or `variants`, a ZIP of one PDF per target plus `manifest.json`", content(("application/pdf"), ("application/zip"))),
+        (status = 400, description = "Malformed multipart body, unknown target or variant; a `ChecksumErrorResponse` when uploaded files do not match their `X-Checksum` part headers or `checksums` field", body = String),
+        (status = 415, description = "Files of a type outside ALLOWED_FILE_TYPES, whose content does not match their extension, or executables", body = FileRejectionResponse),
+        (status = 500, description = "Compilation failed; the body holds the error and log, or a `TutorReport` with `tutor`. With `share_failure`, `X-Failure-Url` links to the shared failure. `X-Self-Healing-Attempted` lists the fixes a failed retry tried", body = String),
+    )
+)]
+pub async fn compile_handler(
+    State(state): State<AppState>,
+    Query(query): Query<CompileQuery>,
+    headers: HeaderMap,
+    multipart: Multipart,
+) -> Response {
+    compile_upload(state, query, headers, multipart, None).await
+}
+
+#[utoipa::path(
+    post, path = "/compile/matrix", tag = "compile",
+    params(MatrixQuery, CompileQuery),
+    request_body(content_type = "multipart/form-data", description = "Project files, as for `POST /compile`"),
+    responses(
+        (status = 200, description = "ZIP of one PDF per compiled cell (a log for failed ones) and `matrix.json`, a MatrixReport comparing the cells", content_type = "application/zip"),
+        (status = 400, description = "Unknown engine, mode or paper size, too many cells, or a main file whose class cannot be rewritten", body = String),
+    )
+)]
+pub async fn compile_matrix_handler(
+    State(state): State<AppState>,
+    Query(matrix): Query<MatrixQuery>,
+    Query(query): Query<CompileQuery>,
+    headers: HeaderMap,
+    multipart: Multipart,
+) -> Response {
+    compile_upload(state, query, headers, multipart, Some(matrix)).await
+}
+
+/// Stores a multipart upload in a workspace and compiles it: once, per target or variant,
+/// or per cell of a build `matrix`.
+async fn compile_upload(state: AppState, query: CompileQuery, headers: HeaderMap, mut multipart: Multipart, matrix: Option<MatrixQuery>) -> Response {
+    let mut files_received = 0;
+    let mut main_tex_data = Vec::new();
+    // Cache key, fed as uploads arrive rather than from a concatenated copy of every file
+    let mut input_hasher = state.compilation_cache.input_hasher();
+    let mut sources = HashMap::new();
+    let mut receipt_files = Vec::new();
+    // Sizes of non-source files, for calibrating POST /estimate
+    let mut assets = Vec::new();
+    let mut uploaded = Vec::new();
+    let mut checksums = HashMap::new();
+    // Files given by blob hash (e.g. finished /uploads) instead of bytes
+    let mut blob_refs = Vec::new();
+    // Form fields placed by coordinates, for forms=true
+    let mut form_fields: Vec<FormFieldSpec> = Vec::new();
+    let mut main_tex_path_relative = String::from("main.tex");
+
+    let temp_dir = match state.janitor.workspace() {
+        Ok(d) => d,
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to create temp dir: {}", e)).into_response(),
+    };
+
+    loop {
+        let mut field = match multipart.next_field().await {
+            Ok(Some(field)) => field,
+            Ok(None) => break,
+            Err(e) => {
+                error!("Multipart error: {}", e);
+                return (StatusCode::BAD_REQUEST, format!("Multipart error: {}", e)).into_response();
+            }
+        };
+
+        if field.file_name().is_none() && field.name() == Some("blobs") {
+            let refs = match field.text().await {
+                Ok(text) => serde_json::from_str::<HashMap<String, String>>(&text)
+                    .map_err(|e| format!("blobs must be a JSON object of file name to blob hash: {}", e)),
+                Err(e) => Err(format!("Failed to read blobs: {}", e)),
+            };
+            match refs {
+                Ok(refs) => blob_refs.extend(refs),
+                Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+            }
+            continue;
+        }
+        if field.file_name().is_none() && field.name() == Some("form_fields") {
+            let fields = match field.text().await {
+                Ok(text) => serde_json::from_str::<Vec<FormFieldSpec>>(&text)
+                    .map_err(|e| format!("form_fields must be a JSON array of fields: {}", e))
+                    .and_then(|fields| crate::forms::validate(&fields).map(|_| fields)),
+                Err(e) => Err(format!("Failed to read form_fields: {}", e)),
+            };
+            match fields {
+                Ok(fields) => form_fields.extend(fields),
+                Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+            }
+            continue;
+        }
+        if field.file_name().is_none() && field.name() == Some("checksums") {
+            let manifest = match field.text().await {
+                Ok(text) => Checksum::parse_manifest(&text),
+                Err(e) => Err(format!("Failed to read checksums: {}", e)),
+            };
+            match manifest {
+                Ok(manifest) => checksums.extend(manifest),
+                Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+            }
+            continue;
+        }
+
+        let file_name = field.file_name().unwrap_or("file.tex").to_string();
+        if let Some(value) = field.headers().get("X-Checksum") {
+            match value.to_str().map_err(|e| e.to_string()).and_then(Checksum::parse) {
+                Ok(checksum) => { checksums.insert(file_name.clone(), checksum); }
+                Err(e) => return (StatusCode::BAD_REQUEST, format!("Invalid X-Checksum for {}: {}", file_name, e)).into_response(),
+            }
+        }
+        uploaded.push(file_name.clone());
+        let path = temp_dir.path().join(&file_name);
+        if let Some(parent) = path.parent() { 
+            if let Err(e) = fs::create_dir_all(parent) {
+                return (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to create directory: {}", e)).into_response();
+            }
+        }
+
+        // Assets (images, fonts, data) can be large: stream them straight to the workspace.
+        // Sources are small and needed in memory for validation and key normalization.
+        if !(file_name.ends_with(".tex") || file_name.ends_with(".bib")) {
+            let mut receipt_hasher = query.receipt.then(Sha256::new);
+            let mut size = 0u64;
+            input_hasher.begin_file(&file_name);
+            let streamed = stream_field(&mut field, &path, |chunk| {
+                size += chunk.len() as u64;
+                input_hasher.update(chunk);
+                if let Some(hasher) = receipt_hasher.as_mut() {
+                    hasher.update(chunk);
+                }
+            }).await;
+            if let Err(response) = streamed {
+                return response;
+            }
+            files_received += 1;
+            assets.push((file_name.clone(), size));
+            if let Some(hasher) = receipt_hasher {
+                receipt_files.push((file_name, hex::encode(hasher.finalize())));
+            }
+            continue;
+        }
+
+        match field.bytes().await {
+            Ok(data) => {
+                files_received += 1;
+                if let Err(e) = fs::write(&path, &data) {
+                    return (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to write file {}: {}", file_name, e)).into_response();
+                }
+                input_hasher.add_file(&file_name, &data);
+                if query.receipt {
+                    receipt_files.push((file_name.clone(), sha256_hex(&data)));
+                }
+                if file_name.ends_with(".tex") || file_name.ends_with(".bib") {
+                    if let Ok(text) = std::str::from_utf8(&data) {
+                        sources.insert(file_name.clone(), text.to_string());
+                    }
+                }
+                if file_name.ends_with(".tex") {
+                    main_tex_data = data.to_vec();
+                    main_tex_path_relative = file_name.clone();
+                }
+            },
+            Err(e) => {
+                error!("Failed to read chunks for file {}: {}", file_name, e);
+                return (StatusCode::BAD_REQUEST, format!("Failed to read file {}: {}", file_name, e)).into_response();
+            }
+        }
+    }
+
+    blob_refs.sort();
+    for (file_name, hash) in blob_refs {
+        if Path::new(&file_name).is_absolute() || file_name.split('/').any(|c| c == "..") {
+            return (StatusCode::BAD_REQUEST, format!("Invalid blob file name {}", file_name)).into_response();
+        }
+        let Some(data) = state.blob_store.get(&hash).await else {
+            return (StatusCode::NOT_FOUND, format!("Blob {} not found", hash)).into_response();
+        };
+        let path = temp_dir.path().join(&file_name);
+        if let Err(e) = path.parent().map_or(Ok(()), fs::create_dir_all).and_then(|_| fs::write(&path, &data)) {
+            return (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to write file {}: {}", file_name, e)).into_response();
+        }
+        files_received += 1;
+        input_hasher.add_file(&file_name, &data);
+        if query.receipt {
+            receipt_files.push((file_name.clone(), sha256_hex(&data)));
+        }
+        if file_name.ends_with(".tex") || file_name.ends_with(".bib") {
+            if let Ok(text) = std::str::from_utf8(&data) {
+                sources.insert(file_name.clone(), text.to_string());
+            }
+            if file_name.ends_with(".tex") && main_tex_data.is_empty() {
+                main_tex_data = data.to_vec();
+                main_tex_path_relative = file_name.clone();
+            }
+        } else {
+            assets.push((file_name.clone(), data.len() as u64));
+        }
+        uploaded.push(file_name);
+    }
+
+    let dir = temp_dir.path().to_path_buf();
+    let settings = state.settings.clone();
+    let names = uploaded.clone();
+    // A check that did not finish accepts nothing
+    let rejected = match tokio::task::spawn_blocking(move || FilePolicy::new(&settings.allowed_file_types).check_dir(&dir, &names)).await {
+        Ok(rejected) => rejected,
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, format!("File type check failed: {}", e)).into_response(),
+    };
+    if !rejected.is_empty() {
+        warn!("🚫 Refused {} uploaded file(s) by type", rejected.len());
+        return (
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            Json(FileRejectionResponse { error: format!("{} file(s) are not of an accepted type", rejected.len()), files: rejected }),
+        ).into_response();
+    }
+
+    if let Some(layout) = query.impose.as_deref().filter(|layout| !crate::impose::LAYOUTS.contains(layout)) {
+        return (StatusCode::BAD_REQUEST, format!("Unknown imposition '{}'; expected one of: {}", layout, crate::impose::LAYOUTS.join(", "))).into_response();
+    }
+    let print_profile = match query.print_profile.as_deref() {
+        Some(name) => match PrintProfile::load(&state.settings.print_profiles_dir, name).await {
+            Ok(profile) => Some(Arc::new(profile)),
+            Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+        },
+        None => None,
+    };
+    let signing = match signing_for(&state, &query).await {
+        Ok(signing) => signing,
+        Err(e) => return e.into_response(),
+    };
+    if let Some(Err(e)) = stamp_options(&query) {
+        return (StatusCode::BAD_REQUEST, e).into_response();
+    }
+    if !form_fields.is_empty() && !query.forms {
+        return (StatusCode::BAD_REQUEST, "form_fields requires forms=true".to_string()).into_response();
+    }
+    let tex_options = match crate::compiler::TexOptions::parse(query.interaction.as_deref(), query.max_print_line, query.jobname.as_deref())
+        .and_then(|options| options.with_healer_rules(query.heal.as_deref()))
+    {
+        Ok(options) => options,
+        Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+    };
+    if !tex_options.is_default() && (matrix.is_some() || query.variants.is_some() || query.targets.is_some() || query.subfiles) {
+        return (StatusCode::BAD_REQUEST, "interaction, max_print_line, jobname and heal apply to single-document compiles only".to_string()).into_response();
+    }
+
+    if !checksums.is_empty() {
+        let dir = temp_dir.path().to_path_buf();
+        let expected = checksums.len();
+        let mismatches = match tokio::task::spawn_blocking(move || crate::checksum::verify(&dir, &checksums, &uploaded)).await {
+            Ok(mismatches) => mismatches,
+            Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, format!("Checksum verification failed: {}", e)).into_response(),
+        };
+        if !mismatches.is_empty() {
+            warn!("🧾 {} of {} uploaded file(s) failed checksum verification", mismatches.len(), expected);
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(ChecksumErrorResponse { error: format!("{} file(s) failed checksum verification", mismatches.len()), files: mismatches }),
+            ).into_response();
+        }
+    }
+
+    let overrides = rewrite::Overrides {
+        paper: query.paper.clone(),
+        font_size: query.font_size.clone(),
+        margin: query.margin.clone(),
+        twoside: query.twoside,
+    };
+    let defines: std::collections::BTreeMap<String, serde_json::Value> = match query.defines.as_deref().map(serde_json::from_str).transpose() {
+        Ok(defines) => defines.unwrap_or_default(),
+        Err(e) => return (StatusCode::BAD_REQUEST, format!("defines must be a JSON object: {}", e)).into_response(),
+    };
+    let mut rewrite_notes: Vec<CompileWarning> = Vec::new();
+    if query.sanitize_unicode {
+        for (name, text) in sources.iter_mut().filter(|(name, _)| name.ends_with(".tex")) {
+            let (clean, substitutions) = crate::sanitize::sanitize(text);
+            if substitutions.is_empty() {
+                continue;
+            }
+            if let Err(e) = fs::write(temp_dir.path().join(name.as_str()), &clean) {
+                return (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to write file {}: {}", name, e)).into_response();
+            }
+            input_hasher.add_file(name, clean.as_bytes());
+            if *name == main_tex_path_relative {
+                main_tex_data = clean.clone().into_bytes();
+            }
+            info!("🧹 Sanitized {} character(s) in {}", substitutions.iter().map(|s| s.count).sum::<usize>(), name);
+            rewrite_notes.extend(substitutions.iter().map(|s| s.warning(name)));
+            *text = clean;
+        }
+    }
+    if !overrides.is_empty() || !defines.is_empty() || query.language.is_some() {
+        let rewritten = std::str::from_utf8(&main_tex_data).map_err(|e| e.to_string())
+            .and_then(|source| overrides.apply(source))
+            .and_then(|source| rewrite::inject_defines(&source, &defines))
+            .and_then(|source| match query.language.as_deref() {
+                Some(requested) => {
+                    let texts = sources.iter().filter(|(name, _)| name.ends_with(".tex")).map(|(_, text)| text.as_str());
+                    let (source, language) = crate::language::apply(&source, requested, texts)?;
+                    if let Some(language) = language {
+                        info!("🌐 Loaded {} hyphenation for {}", language.name, main_tex_path_relative);
+                        rewrite_notes.push(CompileWarning {
+                            kind: "language".to_string(),
+                            file: Some(main_tex_path_relative.clone()),
+                            line: None,
+                            measurement: None,
+                            message: format!("The preamble sets no language; configured {}", language.name),
+                        });
+                    }
+                    Ok(source)
+                }
+                None => Ok(source),
+            });
+        let rewritten = match rewritten {
+            Ok(s) => s,
+            Err(e) => return (StatusCode::BAD_REQUEST, format!("Cannot rewrite {}: {}", main_tex_path_relative, e)).into_response(),
+        };
+        if let Err(e) = fs::write(temp_dir.path().join(&main_tex_path_relative), &rewritten) {
+            return (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to write file {}: {}", main_tex_path_relative, e)).into_response();
+        }
+        // Same name, so the rewritten content replaces the uploaded one in the cache key
+        input_hasher.add_file(&main_tex_path_relative, rewritten.as_bytes());
+        main_tex_data = rewritten.into_bytes();
+    }
+
+    // The bundled form package, unless the project brings its own copy
+    let package = temp_dir.path().join(crate::forms::PACKAGE_FILE);
+    if crate::forms::uses_package(sources.values()) && !package.exists() {
+        if let Err(e) = fs::write(&package, crate::forms::PACKAGE) {
+            return (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to write file {}: {}", crate::forms::PACKAGE_FILE, e)).into_response();
+        }
+        input_hasher.add_file(crate::forms::PACKAGE_FILE, crate::forms::PACKAGE.as_bytes());
+    }
+
+    if let Some(matrix) = matrix {
+        if query.variants.is_some() || query.targets.is_some() || query.subfiles {
+            return (StatusCode::BAD_REQUEST, "A matrix cannot be combined with variants, targets or subfiles").into_response();
+        }
+        let cells = match crate::matrix::cells(&matrix) {
+            Ok(cells) => cells,
+            Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+        };
+        return compile_matrix(&state, &query, &headers, &temp_dir, input_hasher, &main_tex_path_relative, &cells).await;
+    }
+
+    if let Some(variants) = query.variants.as_deref() {
+        if query.targets.is_some() || query.subfiles {
+            return (StatusCode::BAD_REQUEST, "variants cannot be combined with targets or subfiles").into_response();
+        }
+        let mut variants: Vec<String> = variants.split(',').map(|v| v.trim().to_string()).filter(|v| !v.is_empty()).collect();
+        let mut seen = std::collections::HashSet::new();
+        variants.retain(|v| seen.insert(v.clone()));
+        if variants.is_empty() {
+            return (StatusCode::BAD_REQUEST, format!("variants must name at least one of {}", rewrite::BEAMER_VARIANTS.join(", "))).into_response();
+        }
+        return compile_variants(&state, &query, &headers, &temp_dir, input_hasher, &main_tex_path_relative, &variants).await;
+    }
+
+    if query.targets.is_some() || query.subfiles {
+        let mut targets: Vec<String> = query.targets.as_deref().unwrap_or_default().split(',')
+            .map(|t| t.trim().trim_start_matches("./").to_string())
+            .filter(|t| !t.is_empty())
+            .collect();
+        if query.subfiles {
+            if targets.is_empty() {
+                targets.push(main_tex_path_relative.trim_start_matches("./").to_string());
+            }
+            targets.extend(crate::validator::subfile_targets(&sources));
+        }
+        let mut seen = std::collections::HashSet::new();
+        targets.retain(|t| seen.insert(t.clone()));
+        if let Some(unknown) = targets.iter().find(|t| !sources.keys().any(|name| name.trim_start_matches("./") == t.as_str() && name.ends_with(".tex"))) {
+            return (StatusCode::BAD_REQUEST, format!("Target '{}' is not an uploaded .tex file", unknown)).into_response();
+        }
+        return compile_targets(&state, &query, &headers, &temp_dir, input_hasher, &targets).await;
+    }
+
+    let main_tex_path = temp_dir.path().join(&main_tex_path_relative);
+    if !tex_options.is_default() {
+        input_hasher.add_setting("tex-options", &serde_json::to_string(&tex_options).unwrap_or_default());
+    }
+    let input_hash = input_hasher.finish(&main_tex_path_relative);
+    let mut warnings: Vec<CompileWarning> = rewrite_notes;
+    warnings.extend(CitationChecker::check(&sources).into_iter().map(Into::into));
+
+    if query.force {
+        info!("⏩ Forced compile for hash {:016x}, skipping caches", input_hash);
+    } else if let Some(failure) = state.compilation_cache.get_failure(input_hash).await {
+        info!("🚫 Negative cache HIT for hash {:016x}", input_hash);
+        let cache_headers = [("X-Cache", "NEGATIVE".to_string())];
+        return failure_response(&state, &query, &headers, cache_headers, temp_dir.path(), &main_tex_path_relative, &failure.error, &failure.logs, &sources).await;
+    } else if let Some((cached_pdf, original_time, stale, notes)) = state.compilation_cache.get_pdf(input_hash).await {
+        info!("📦 Cache {} for hash {:016x}", if stale { "STALE" } else { "HIT" }, input_hash);
+        if stale && state.compilation_cache.begin_revalidation(input_hash).await {
+            // The workspace moves into the refresh task so the inputs outlive this request
+            let state = state.clone();
+            let (main_tex_path, tex_options) = (main_tex_path.clone(), tex_options.clone());
+            // Compiled for this request's tenant, with its registry packages
+            crate::tenancy::spawn(async move {
+                let permit = state.scheduler.acquire(Priority::Batch).await;
+                let start = Instant::now();
+                let (result, logs) = crate::workers::compile_with_options(&state, temp_dir.path(), &main_tex_path, Priority::Batch, &tex_options, permit).await;
+                let failed = match result {
+                    Ok(pdf_data) => {
+                        let compile_time_ms = start.elapsed().as_millis() as u64;
+                        state.compilation_cache.put_pdf(input_hash, &pdf_data, compile_time_ms, &CompileNotes::from_log(&logs)).await;
+                        info!("♻️ Revalidated cache entry {:016x} in {}ms", input_hash, compile_time_ms);
+                        false
+                    }
+                    Err(e) => {
+                        error!("Revalidation of {:016x} failed: {}", input_hash, e);
+                        true
+                    }
+                };
+                // The stale PDF keeps being served; a failed refresh is retried after a stale period
+                state.compilation_cache.end_revalidation(input_hash, failed).await;
+            });
+        }
+        warnings.extend(notes.warnings);
+        if query.check_pages {
+            warnings.extend(ghost_page_warnings(cached_pdf.clone()).await);
+        }
+        let (cached_pdf, optimization) = postprocess_pdf(&query, print_profile.as_ref(), &form_fields, cached_pdf, &mut warnings).await;
+        let cached_pdf = match sign_pdf(signing.as_ref(), cached_pdf).await {
+            Ok(pdf) => pdf,
+            Err(e) => return e.into_response(),
+        };
+        let output_hash = state.output_store.put(&cached_pdf).await;
+        let mut builder = Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, "application/pdf")
+            .header("X-Output-Hash", output_hash.clone())
+            .header("X-Compile-Time-Ms", original_time.to_string())
+            .header("X-Cache", if stale { "STALE" } else { "HIT" })
+            .header("X-Files-Received", files_received.to_string());
+        if let Some(w) = warnings_header(&warnings) {
+            builder = builder.header("X-Warnings", w);
+        }
+        if let Some(o) = optimization {
+            builder = builder.header("X-Optimization", o);
+        }
+        if let Some(fixes) = healing_header(notes.self_healed.as_deref()) {
+            builder = builder.header("X-Self-Healed", fixes);
+        }
+        if query.receipt {
+            if let Some(r) = receipt_header(&state, &receipt_files, &cached_pdf, &output_hash, original_time) {
+                builder = builder.header("X-Compile-Receipt", r);
+            }
+        }
+        if query.fingerprint {
+            if let Some(f) = index_fingerprint(&state, &headers, &cached_pdf, &output_hash).await {
+                builder = builder.header("X-Fingerprint", f);
+            }
+        }
+        return builder.body(axum::body::Body::from(cached_pdf)).unwrap();
+    }
+
+    let hmr_status;
+    let preamble_hash;
+    if let Ok(content) = String::from_utf8(main_tex_data) {
+        if let Some(preamble) = FormatCache::extract_preamble(&content) {
+            preamble_hash = FormatCache::hash_preamble(preamble);
+            hmr_status = if state.format_cache.check_and_mark(preamble_hash).await { "HIT" } else { "MISS" };
+        } else {
+            hmr_status = "NONE"; preamble_hash = 0;
+        }
+    } else {
+        hmr_status = "ERROR"; preamble_hash = 0;
+    }
+
+    let priority = Priority::parse(headers.get("X-Priority").and_then(|v| v.to_str().ok()));
+    let permit = state.scheduler.acquire(priority).await;
+
+    info!("Compiling {:?} ({} files, HMR: {}, priority: {})...", main_tex_path, files_received, hmr_status, priority.as_str());
+    let start = Instant::now();
+    let meter = ResourceMeter::start();
+
+    let (result, logs) = crate::workers::compile_with_options(&state, temp_dir.path(), &main_tex_path, priority, &tex_options, permit).await;
+
+    let compile_time_ms = start.elapsed().as_millis() as u64;
+    let usage = meter.finish(result.as_ref().map(|pdf| pdf.len()).unwrap_or(0));
+    info!("📈 Resource usage: {:?}", usage);
+    let usage_header = serde_json::to_string(&usage).unwrap_or_default();
+
+    let header_value = |name: &str| headers.get(name).and_then(|v| v.to_str().ok()).map(str::to_string);
+    let webhook_payload = |error: Option<String>, output_hash: Option<String>| WebhookPayload {
+        event: if error.is_none() { "compile.success" } else { "compile.failure" }.to_string(),
+        timestamp: unix_now(),
+        project_id: header_value("X-Project-Id"),
+        tenant: header_value("X-Tenant-Id"),
+        main_file: Some(main_tex_path_relative.clone()),
+        success: error.is_none(),
+        compile_time_ms,
+        error,
+        output_hash,
+        test_id: None,
+        schedule_id: None,
+    };
+
+    match result {
+        Ok(pdf_data) => {
+            let pdf_data = bytes::Bytes::from(pdf_data);
+            let notes = CompileNotes::from_log(&logs);
+            warnings.extend(notes.warnings.iter().cloned());
+            if query.check_pages {
+                warnings.extend(ghost_page_warnings(pdf_data.clone()).await);
+            }
+            // The cache keeps the PDF as compiled; the output store, receipt and fingerprint
+            // cover the one served
+            let (served, optimization) = postprocess_pdf(&query, print_profile.as_ref(), &form_fields, pdf_data.clone(), &mut warnings).await;
+            let served = match sign_pdf(signing.as_ref(), served).await {
+                Ok(pdf) => pdf,
+                Err(e) => {
+                    state.compilation_cache.put_pdf(input_hash, &pdf_data, compile_time_ms, &notes).await;
+                    return e.into_response();
+                }
+            };
+            let predicted = (preamble_hash != 0)
+                .then(|| Estimator::estimate(&sources, &main_tex_path_relative, &assets, hmr_status == "HIT", None));
+            let success = webhook_payload(None, None);
+            let mut builder = Response::builder()
+                .status(StatusCode::OK)
+                .header(header::CONTENT_TYPE, "application/pdf")
+                .header("X-Compile-Time-Ms", compile_time_ms.to_string())
+                .header("X-Cache", "MISS")
+                .header("X-HMR", hmr_status)
+                .header("X-Files-Received", files_received.to_string())
+                .header("X-Resource-Usage", usage_header);
+            if let Some(w) = warnings_header(&warnings) {
+                builder = builder.header("X-Warnings", w);
+            }
+            if let Some(o) = optimization {
+                builder = builder.header("X-Optimization", o);
+            }
+            if let Some(fixes) = healing_header(notes.self_healed.as_deref()) {
+                builder = builder.header("X-Self-Healed", fixes);
+            }
+
+            // Storing, signing and indexing the PDF; streamed responses send the results as trailers
+            let finish = {
+                let (state, pdf_data, served, headers) = (state.clone(), pdf_data.clone(), served.clone(), headers.clone());
+                let (receipt, fingerprint) = (query.receipt, query.fingerprint);
+                async move {
+                    state.compilation_cache.put_pdf(input_hash, &pdf_data, compile_time_ms, &notes).await;
+                    let output_hash = state.output_store.put(&served).await;
+                    if let Some(predicted) = predicted {
+                        state.compile_history.record(preamble_hash, &predicted, compile_time_ms, pdf_data.len()).await;
+                    }
+                    Webhooks::fire(&state, WebhookPayload { output_hash: Some(output_hash.clone()), ..success });
+                    let mut stats = HeaderMap::new();
+                    if let Ok(value) = HeaderValue::from_str(&output_hash) {
+                        stats.insert("X-Output-Hash", value);
+                    }
+                    if receipt {
+                        if let Some(r) = receipt_header(&state, &receipt_files, &served, &output_hash, compile_time_ms) {
+                            stats.insert("X-Compile-Receipt", r);
+                        }
+                    }
+                    if fingerprint {
+                        if let Some(f) = index_fingerprint(&state, &headers, &served, &output_hash).await.and_then(|f| HeaderValue::from_str(&f).ok()) {
+                            stats.insert("X-Fingerprint", f);
+                        }
+                    }
+                    stats
+                }
+            };
+            if query.stream && !query.postprocesses() {
+                let (trailers, stats) = tokio::sync::oneshot::channel();
+                // Stored and indexed for this request's tenant, sealed with its key
+                crate::tenancy::spawn(async move { trailers.send(finish.await).ok() });
+                let pdf_path = temp_dir.path().join(format!("{}.pdf", tex_options.output_stem(&main_tex_path).unwrap_or_default()));
+                let trailer_names = crate::streaming::COMPILE_TRAILERS.map(|name| name.to_string()).join(", ");
+                let body = crate::streaming::pdf_body(temp_dir, &pdf_path, pdf_data, stats).await;
+                return builder.header(header::TRAILER, trailer_names).body(body).unwrap();
+            }
+            for (name, value) in &finish.await {
+                builder = builder.header(name, value);
+            }
+            builder.body(axum::body::Body::from(served)).unwrap()
+        }
+        Err(e) => {
+            state.compilation_cache.put_failure(input_hash, &e, &logs).await;
+            Webhooks::fire(&state, webhook_payload(Some(e.clone()), None));
+            let cache_headers = [("X-Cache", "MISS".to_string()), ("X-Resource-Usage", usage_header)];
+            let mut response = failure_response(&state, &query, &headers, cache_headers, temp_dir.path(), &main_tex_path_relative, &e, &logs, &sources).await;
+            if let Some(fixes) = healing_header(SelfHealer::report(&logs).as_deref()) {
+                response.headers_mut().insert("X-Self-Healing-Attempted", fixes);
+            }
+            response
+        }
+    }
+}
+
+/// The 500 of a failed compile: the error and log as text, or a `TutorReport` with
+/// `?tutor=true`. With `?share_failure=true` the failure is also stored and linked in
+/// `X-Failure-Url`; the response is sent even if that fails.
+#[allow(clippy::too_many_arguments)]
+async fn failure_response<const N: usize>(
+    state: &AppState,
+    query: &CompileQuery,
+    headers: &HeaderMap,
+    cache_headers: [(&'static str, String); N],
+    workspace: &Path,
+    main: &str,
+    error: &str,
+    logs: &str,
+    sources: &HashMap<String, String>,
+) -> Response {
+    let language = ErrorExplainer::negotiate(headers.get(header::ACCEPT_LANGUAGE).and_then(|v| v.to_str().ok()));
+    let mut parsed = Vec::new();
+    if query.tutor || query.share_failure {
+        parsed = parse_log_errors(logs);
+        attach_source_context(&mut parsed, workspace);
+        attach_explanations(&mut parsed, language);
+    }
+
+    let mut response = if query.tutor {
+        (StatusCode::INTERNAL_SERVER_ERROR, cache_headers, Json(Tutor::report(error, &parsed, language))).into_response()
+    } else {
+        (StatusCode::INTERNAL_SERVER_ERROR, cache_headers, format!("LaTeX Error: {}\n\nLogs:\n{}", error, logs)).into_response()
+    };
+
+    if query.share_failure {
+        match state.failures.share(&state.settings, main, error, logs, parsed, sources).await {
+            Ok(failure) => {
+                let url = crate::playground::share_url(&state.settings, &format!("/failures/{}", failure.id));
+                info!("🔗 Shared failed compile {} ({} error(s))", failure.id, failure.errors.len());
+                if let Ok(value) = HeaderValue::from_str(&url) {
+                    response.headers_mut().insert("X-Failure-Url", value);
+                }
+            }
+            Err(e) => warn!("Failed to share compile failure: {}", e),
+        }
+    }
+    response
+}
+
+#[utoipa::path(
+    post, path = "/compile/async", tag = "compile",
+    params(("X-Tenant-Id" = Option<String>, Header, description = "Tenant the job and its output belong to")),
+    request_body = AsyncCompileRequest,
+    responses(
+        (status = 202, description = "Job queued; the finished job is POSTed to `callback_url` and can be polled at the `Location` header", body = AsyncCompileJob),
+        (status = 400, description = "No source or more than one, unsafe file names, or a callback URL the webhook policy refuses", body = String),
+        (status = 503, description = "ASYNC_MAX_JOBS async compiles are already queued or running", body = String),
+    )
+)]
+pub async fn compile_async_handler(State(state): State<AppState>, headers: HeaderMap, Json(request): Json<AsyncCompileRequest>) -> Response {
+    let compile = match crate::jobs::AsyncCompile::from_request(request, &state.settings) {
+        Ok(compile) => compile,
+        Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+    };
+    let tenant = headers.get("X-Tenant-Id").and_then(|v| v.to_str().ok()).map(str::to_string);
+    let job = match crate::jobs::spawn(&state, tenant, compile) {
+        Ok(job) => job,
+        Err(e) => return (StatusCode::SERVICE_UNAVAILABLE, e).into_response(),
+    };
+    info!("📮 Queued async compile {}", job.id);
+    (StatusCode::ACCEPTED, [(header::LOCATION, format!("/compile/async/{}", job.id))], Json(job)).into_response()
+}
+
+#[utoipa::path(
+    get, path = "/compile/async/{id}", tag = "compile",
+    params(
+        ("id" = String, Path, description = "Job id"),
+        ("X-Tenant-Id" = Option<String>, Header, description = "Tenant the job was started for"),
+    ),
+    responses(
+        (status = 200, description = "The job's status, output hash or error, and callback delivery status", body = AsyncCompileJob),
+        (status = 404, description = "No such job for this tenant, or it finished over a day ago", body = String),
+    )
+)]
+pub async fn async_job_handler(State(state): State<AppState>, UrlPath(id): UrlPath<String>, headers: HeaderMap) -> Response {
+    let tenant = headers.get("X-Tenant-Id").and_then(|v| v.to_str().ok());
+    match state.jobs.get(&id, tenant) {
+        Some(job) => Json(job).into_response(),
+        None => (StatusCode::NOT_FOUND, format!("Job {} not found", id)).into_response(),
+    }
+}
+
+#[utoipa::path(
+    post, path = "/playground", tag = "playground",
+    request_body = PlaygroundRequest,
+    responses(
+        (status = 201, description = "Snippet stored; `url` (also in `Location`) opens it in the editor with a live preview", body = PlaygroundSnippet),
+        (status = 400, description = "Neither or both of source and files, unsafe file names, or no main file", body = String),
+        (status = 413, description = "Snippet over PLAYGROUND_MAX_KB", body = String),
+        (status = 429, description = "The client already shared SHARES_PER_HOUR snippets this hour", body = String),
+    )
+)]
+pub async fn create_playground_handler(State(state): State<AppState>, Json(request): Json<PlaygroundRequest>) -> Response {
+    match state.playground.create(request, &state.settings).await {
+        Ok(mut snippet) => {
+            snippet.url = crate::playground::share_url(&state.settings, &format!("/p/{}", snippet.id));
+            info!("🔗 Shared playground snippet {} ({} file(s))", snippet.id, snippet.files.len());
+            (StatusCode::CREATED, [(header::LOCATION, snippet.url.clone())], Json(snippet)).into_response()
+        }
+        Err(e) => e.into_response(),
+    }
+}
+
+#[utoipa::path(
+    get, path = "/playground/{id}", tag = "playground",
+    params(("id" = String, Path, description = "Snippet id")),
+    responses(
+        (status = 200, description = "The snippet's files and expiry", body = PlaygroundSnippet),
+        (status = 404, description = "No such snippet, or it expired", body = String),
+    )
+)]
+pub async fn get_playground_handler(State(state): State<AppState>, UrlPath(id): UrlPath<String>) -> Response {
+    match state.playground.get(&id).await {
+        Some(mut snippet) => {
+            snippet.url = crate::playground::share_url(&state.settings, &format!("/p/{}", snippet.id));
+            Json(snippet).into_response()
+        }
+        None => (StatusCode::NOT_FOUND, format!("Snippet {} not found or expired", id)).into_response(),
+    }
+}
+
+#[utoipa::path(
+    get, path = "/failures/{id}", tag = "compile",
+    params(("id" = String, Path, description = "Shared failure id, from `X-Failure-Url`")),
+    responses(
+        (status = 200, description = "The failure's parsed errors, log and sources; an HTML page when the client accepts `text/html`", body = SharedFailure),
+        (status = 404, description = "No such failure, or it expired", body = String),
+    )
+)]
+pub async fn get_failure_handler(State(state): State<AppState>, UrlPath(id): UrlPath<String>, headers: HeaderMap) -> Response {
+    let Some(mut failure) = state.failures.get(&id).await else {
+        return (StatusCode::NOT_FOUND, format!("Failure {} not found or expired", id)).into_response();
+    };
+    let accept = headers.get(header::ACCEPT).and_then(|v| v.to_str().ok()).unwrap_or_default();
+    if accept.contains("text/html") {
+        return axum::response::Html(crate::failures::render_html(&failure)).into_response();
+    }
+    failure.url = crate::playground::share_url(&state.settings, &format!("/failures/{}", failure.id));
+    Json(failure).into_response()
+}
+
+#[utoipa::path(
+    post, path = "/grade/batch", tag = "grading",
+    params(GradeQuery),
+    request_body(content_type = "multipart/form-data", description = "One file part per submission: a ZIP of the student's project, or a single .tex file"),
+    responses(
+        (status = 200, description = "One report per submission, as JSON or CSV", content((GradeReport = "application/json"), (String = "text/csv"))),
+        (status = 400, description = "Malformed multipart body, no submissions or an unknown format", body = String),
+        (status = 413, description = "More than GRADE_MAX_SUBMISSIONS submissions, or one larger than GRADE_SUBMISSION_MAX_MB", body = String),
+    )
+)]
+pub async fn grade_batch_handler(State(state): State<AppState>, Query(query): Query<GradeQuery>, headers: HeaderMap, mut multipart: Multipart) -> Response {
+    let accept = headers.get(header::ACCEPT).and_then(|v| v.to_str().ok()).unwrap_or_default();
+    let csv = match query.format.as_deref() {
+        Some("csv") => true,
+        Some("json") => false,
+        Some(other) => return (StatusCode::BAD_REQUEST, format!("Unknown format '{}': use json or csv", other)).into_response(),
+        None => accept.contains("text/csv"),
+    };
+
+    let mut submissions = Vec::new();
+    // A submission larger than it may expand to is refused before it is buffered whole
+    let max_bytes = (state.settings.grade_submission_max_mb * 1024 * 1024) as usize;
+    loop {
+        match multipart.next_field().await {
+            Ok(Some(mut field)) => {
+                let Some(file_name) = field.file_name().map(str::to_string) else { continue };
+                if submissions.len() == state.settings.grade_max_submissions {
+                    return (StatusCode::PAYLOAD_TOO_LARGE, format!("At most {} submissions per batch", state.settings.grade_max_submissions)).into_response();
+                }
+                match read_field(&mut field, max_bytes).await {
+                    Ok(data) => submissions.push((crate::grade::submission_name(&file_name, submissions.len()), bytes::Bytes::from(data))),
+                    Err(response) => return response,
+                }
+            }
+            Ok(None) => break,
+            Err(e) => return (StatusCode::BAD_REQUEST, format!("Multipart error: {}", e)).into_response(),
+        }
+    }
+    if submissions.is_empty() {
+        return (StatusCode::BAD_REQUEST, "No submissions uploaded".to_string()).into_response();
+    }
+
+    let timeout = query.timeout_secs.unwrap_or(state.settings.grade_timeout_secs).clamp(1, state.settings.grade_timeout_secs.max(1));
+    let report = crate::grade::grade_batch(&state, submissions, Duration::from_secs(timeout)).await;
+    if csv {
+        return (
+            [(header::CONTENT_TYPE, "text/csv; charset=utf-8"), (header::CONTENT_DISPOSITION, "attachment; filename=\"grades.csv\"")],
+            crate::grade::to_csv(&report),
+        ).into_response();
+    }
+    Json(report).into_response()
+}
+
+#[utoipa::path(
+    post, path = "/tests", tag = "regression",
+    params(("X-Tenant-Id" = Option<String>, Header, description = "Tenant the project was created for")),
+    request_body(content_type = "multipart/form-data", description = "`project_id` (required), `reference` (the expected PDF; without it the project is compiled now and its PDF becomes the reference), `max_changed_pages` (share of pages, 0 to 1, that may differ; default 0) and `min_text_similarity` (0 to 1; default 0.95)"),
+    responses(
+        (status = 201, description = "The registered test; it is rebuilt every REGRESSION_INTERVAL_SECS and after bundle updates", body = RegressionTest),
+        (status = 400, description = "Missing project_id, a threshold outside 0 to 1 or an unreadable reference PDF", body = String),
+        (status = 404, description = "No such project for this tenant, or one of its blobs is gone", body = String),
+        (status = 500, description = "No reference was sent and the project failed to compile", body = String),
+    )
+)]
+pub async fn create_regression_test_handler(State(state): State<AppState>, headers: HeaderMap, mut multipart: Multipart) -> Response {
+    let (mut project_id, mut reference) = (None, None);
+    let mut max_changed_pages = crate::regression::DEFAULT_MAX_CHANGED_PAGES;
+    let mut min_text_similarity = crate::regression::DEFAULT_MIN_TEXT_SIMILARITY;
+    loop {
+        let field = match multipart.next_field().await {
+            Ok(Some(field)) => field,
+            Ok(None) => break,
+            Err(e) => return (StatusCode::BAD_REQUEST, format!("Multipart error: {}", e)).into_response(),
+        };
+        let name = field.name().unwrap_or_default().to_string();
+        if name == "reference" {
+            match field.bytes().await {
+                Ok(data) => reference = Some(data.to_vec()),
+                Err(e) => return (StatusCode::BAD_REQUEST, format!("Failed to read the reference: {}", e)).into_response(),
+            }
+            continue;
+        }
+        let value = match field.text().await {
+            Ok(value) => value.trim().to_string(),
+            Err(e) => return (StatusCode::BAD_REQUEST, format!("Failed to read {}: {}", name, e)).into_response(),
+        };
+        let threshold = match name.as_str() {
+            "project_id" => {
+                project_id = Some(value);
+                continue;
+            }
+            "max_changed_pages" => &mut max_changed_pages,
+            "min_text_similarity" => &mut min_text_similarity,
+            _ => continue,
+        };
+        match value.parse::<f64>() {
+            Ok(parsed) if (0.0..=1.0).contains(&parsed) => *threshold = parsed,
+            _ => return (StatusCode::BAD_REQUEST, format!("{} must be a number from 0 to 1", name)).into_response(),
+        }
+    }
+    let Some(project_id) = project_id.filter(|id| !id.is_empty()) else {
+        return (StatusCode::BAD_REQUEST, "project_id is required".to_string()).into_response();
+    };
+
+    let tenant = headers.get("X-Tenant-Id").and_then(|v| v.to_str().ok());
+    let Some(project) = state.projects.get(&project_id, tenant).await else {
+        return (StatusCode::NOT_FOUND, format!("Project {} not found", project_id)).into_response();
+    };
+    let reference = match reference {
+        Some(reference) => reference,
+        None => {
+            let files = match project_files(&state, &project).await {
+                Ok(files) => files,
+                Err(e) => return e.into_response(),
+            };
+            match build_project(&state, &project, &files, Priority::parse(headers.get("X-Priority").and_then(|v| v.to_str().ok()))).await {
+                Ok((pdf_data, _)) => pdf_data.to_vec(),
+                Err(e) => return e.into_response(),
+            }
+        }
+    };
+    let test = RegressionTest {
+        id: uuid::Uuid::new_v4().simple().to_string(),
+        project_id,
+        main: project.main,
+        files: project.files,
+        reference_pages: 0,
+        max_changed_pages,
+        min_text_similarity,
+        created_at: unix_now(),
+        last_run: None,
+    };
+    match state.regression.create(tenant.map(str::to_string), test, reference).await {
+        Ok(test) => {
+            info!("🧪 Registered regression test {} for project {} ({} reference page(s))", test.id, test.project_id, test.reference_pages);
+            (StatusCode::CREATED, Json(test)).into_response()
+        }
+        Err(e) => e.into_response(),
+    }
+}
+
+#[utoipa::path(
+    get, path = "/tests", tag = "regression",
+    params(("X-Tenant-Id" = Option<String>, Header, description = "Tenant the tests were registered for")),
+    responses((status = 200, description = "The tenant's regression tests, oldest first, each with its last run", body = Vec<RegressionTest>))
+)]
+pub async fn list_regression_tests_handler(State(state): State<AppState>, headers: HeaderMap) -> Response {
+    let tenant = headers.get("X-Tenant-Id").and_then(|v| v.to_str().ok());
+    Json(state.regression.list(tenant).await).into_response()
+}
+
+#[utoipa::path(
+    get, path = "/tests/{id}", tag = "regression",
+    params(
+        ("id" = String, Path, description = "Test id"),
+        ("X-Tenant-Id" = Option<String>, Header, description = "Tenant the test was registered for"),
+    ),
+    responses(
+        (status = 200, description = "The test and its last run", body = RegressionTest),
+        (status = 404, description = "No such test for this tenant", body = String),
+    )
+)]
+pub async fn get_regression_test_handler(State(state): State<AppState>, UrlPath(id): UrlPath<String>, headers: HeaderMap) -> Response {
+    let tenant = headers.get("X-Tenant-Id").and_then(|v| v.to_str().ok());
+    match state.regression.get(&id, tenant).await {
+        Some(test) => Json(test).into_response(),
+        None => (StatusCode::NOT_FOUND, format!("Test {} not found", id)).into_response(),
+    }
+}
+
+#[utoipa::path(
+    delete, path = "/tests/{id}", tag = "regression",
+    params(
+        ("id" = String, Path, description = "Test id"),
+        ("X-Tenant-Id" = Option<String>, Header, description = "Tenant the test was registered for"),
+    ),
+    responses(
+        (status = 204, description = "Test deleted"),
+        (status = 404, description = "No such test for this tenant", body = String),
+    )
+)]
+pub async fn delete_regression_test_handler(State(state): State<AppState>, UrlPath(id): UrlPath<String>, headers: HeaderMap) -> Response {
+    let tenant = headers.get("X-Tenant-Id").and_then(|v| v.to_str().ok());
+    if state.regression.delete(&id, tenant).await {
+        info!("🗑️ Deleted regression test {}", id);
+        StatusCode::NO_CONTENT.into_response()
+    } else {
+        (StatusCode::NOT_FOUND, format!("Test {} not found", id)).into_response()
+    }
+}
+
+#[utoipa::path(
+    post, path = "/tests/{id}/run", tag = "regression",
+    params(
+        ("id" = String, Path, description = "Test id"),
+        ("X-Tenant-Id" = Option<String>, Header, description = "Tenant the test was registered for"),
+    ),
+    responses(
+        (status = 200, description = "The run, also recorded as the test's last run. A failed run fires `regression.failed`", body = RegressionRun),
+        (status = 404, description = "No such test for this tenant", body = String),
+    )
+)]
+pub async fn run_regression_test_handler(State(state): State<AppState>, UrlPath(id): UrlPath<String>, headers: HeaderMap) -> Response {
+    let tenant = headers.get("X-Tenant-Id").and_then(|v| v.to_str().ok());
+    match crate::regression::run(&state, &id, tenant).await {
+        Some(run) => Json(run).into_response(),
+        None => (StatusCode::NOT_FOUND, format!("Test {} not found", id)).into_response(),
+    }
+}
+
+#[utoipa::path(
+    post, path = "/schedules", tag = "schedules",
+    params(("X-Tenant-Id" = Option<String>, Header, description = "Tenant the project was created for")),
+    request_body = CreateScheduleRequest,
+    responses(
+        (status = 201, description = "The schedule, with its first run time", body = Schedule),
+        (status = 400, description = "An invalid or never matching cron expression, an unsafe data file name or a URL the webhook target policy rejects", body = String),
+        (status = 404, description = "No such project for this tenant", body = String),
+    )
+)]
+pub async fn create_schedule_handler(State(state): State<AppState>, headers: HeaderMap, Json(request): Json<CreateScheduleRequest>) -> Response {
+    let cron = match crate::schedules::validate(&request, &state.settings) {
+        Ok(cron) => cron,
+        Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+    };
+    let now = unix_now();
+    let Some(next_run_at) = cron.next_after(now) else {
+        return (StatusCode::BAD_REQUEST, format!("Cron expression '{}' never matches", request.cron)).into_response();
+    };
+    let tenant = headers.get("X-Tenant-Id").and_then(|v| v.to_str().ok());
+    let Some(project) = state.projects.get(&request.project_id, tenant).await else {
+        return (StatusCode::NOT_FOUND, format!("Project {} not found", request.project_id)).into_response();
+    };
+    let schedule = Schedule {
+        id: uuid::Uuid::new_v4().simple().to_string(),
+        project_id: request.project_id,
+        cron: request.cron,
+        main: project.main,
+        files: project.files,
+        data_urls: request.data_urls,
+        deliver_url: request.deliver_url,
+        created_at: now,
+        next_run_at: Some(next_run_at),
+        consecutive_failures: 0,
+        runs: Vec::new(),
+    };
+    match state.schedules.create(tenant.map(str::to_string), schedule).await {
+        Ok(schedule) => {
+            info!("⏰ Scheduled project {} ({}) as {}", schedule.project_id, schedule.cron, schedule.id);
+            (StatusCode::CREATED, Json(schedule)).into_response()
+        }
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e).into_response(),
+    }
+}
+
+#[utoipa::path(
+    get, path = "/schedules", tag = "schedules",
+    params(("X-Tenant-Id" = Option<String>, Header, description = "Tenant the schedules were created for")),
+    responses((status = 200, description = "The tenant's schedules, oldest first, each with its recent runs", body = Vec<Schedule>))
+)]
+pub async fn list_schedules_handler(State(state): State<AppState>, headers: HeaderMap) -> Response {
+    let tenant = headers.get("X-Tenant-Id").and_then(|v| v.to_str().ok());
+    Json(state.schedules.list(tenant).await).into_response()
+}
+
+#[utoipa::path(
+    get, path = "/schedules/{id}", tag = "schedules",
+    params(
+        ("id" = String, Path, description = "Schedule id"),
+        ("X-Tenant-Id" = Option<String>, Header, description = "Tenant the schedule was created for"),
+    ),
+    responses(
+        (status = 200, description = "The schedule and its run history", body = Schedule),
+        (status = 404, description = "No such schedule for this tenant", body = String),
+    )
+)]
+pub async fn get_schedule_handler(State(state): State<AppState>, UrlPath(id): UrlPath<String>, headers: HeaderMap) -> Response {
+    let tenant = headers.get("X-Tenant-Id").and_then(|v| v.to_str().ok());
+    match state.schedules.get(&id, tenant).await {
+        Some(schedule) => Json(schedule).into_response(),
+        None => (StatusCode::NOT_FOUND, format!("Schedule {} not found", id)).into_response(),
+    }
+}
+
+#[utoipa::path(
+    delete, path = "/schedules/{id}", tag = "schedules",
+    params(
+        ("id" = String, Path, description = "Schedule id"),
+        ("X-Tenant-Id" = Option<String>, Header, description = "Tenant the schedule was created for"),
+    ),
+    responses(
+        (status = 204, description = "Schedule deleted"),
+        (status = 404, description = "No such schedule for this tenant", body = String),
+    )
+)]
+pub async fn delete_schedule_handler(State(state): State<AppState>, UrlPath(id): UrlPath<String>, headers: HeaderMap) -> Response {
+    let tenant = headers.get("X-Tenant-Id").and_then(|v| v.to_str().ok());
+    if state.schedules.delete(&id, tenant).await {
+        info!("🗑️ Deleted schedule {}", id);
+        StatusCode::NO_CONTENT.into_response()
+    } else {
+        (StatusCode::NOT_FOUND, format!("Schedule {} not found", id)).into_response()
+    }
+}
+
+#[utoipa::path(
+    post, path = "/schedules/{id}/run", tag = "schedules",
+    params(
+        ("id" = String, Path, description = "Schedule id"),
+        ("X-Tenant-Id" = Option<String>, Header, description = "Tenant the schedule was created for"),
+    ),
+    responses(
+        (status = 200, description = "The run, also recorded in the schedule's history. It fires `schedule.success` or `schedule.failure`; the next scheduled run is unchanged", body = ScheduleRun),
+        (status = 404, description = "No such schedule for this tenant", body = String),
+        (status = 409, description = "The schedule is already running", body = String),
+    )
+)]
+pub async fn run_schedule_handler(State(state): State<AppState>, UrlPath(id): UrlPath<String>, headers: HeaderMap) -> Response {
+    let tenant = headers.get("X-Tenant-Id").and_then(|v| v.to_str().ok());
+    match crate::schedules::run(&state, &id, tenant).await {
+        Ok(Some(run)) => Json(run).into_response(),
+        Ok(None) => (StatusCode::NOT_FOUND, format!("Schedule {} not found", id)).into_response(),
+        Err(e) => (StatusCode::CONFLICT, e).into_response(),
+    }
+}
+
+#[utoipa::path(
+    post, path = "/templates", tag = "templates",
+    params(
+        ("X-Tenant-Id" = Option<String>, Header, description = "Tenant the pack is registered for; without it the pack is public"),
+        ("Authorization" = Option<String>, Header, description = "`Bearer <ADMIN_TOKEN>`, required for a public pack"),
+    ),
+    request_body = RegisterTemplatePackRequest,
+    responses(
+        (status = 201, description = "The pack at its first version; every template's sample compiled", body = TemplatePack),
+        (status = 400, description = "A git_url the webhook target policy rejects, or an invalid version tag", body = String),
+        (status = 401, description = "A public pack without the admin token", body = String),
+        (status = 422, description = "The repository could not be fetched, its manifest is invalid or a sample failed to compile", body = PackSync),
+    )
+)]
+pub async fn register_template_pack_handler(State(state): State<AppState>, headers: HeaderMap, Json(request): Json<RegisterTemplatePackRequest>) -> Response {
+    let tenant = headers.get("X-Tenant-Id").and_then(|v| v.to_str().ok()).map(str::to_string);
+    if let Err(e) = require_admin_for_public(&state, &headers, tenant.as_deref()) {
+        return e.into_response();
+    }
+    match crate::packs::register(&state, tenant, request).await {
+        Ok(Ok(pack)) => (StatusCode::CREATED, Json(pack)).into_response(),
+        Ok(Err(sync)) => (StatusCode::UNPROCESSABLE_ENTITY, Json(sync)).into_response(),
+        Err(e) => e.into_response(),
+    }
+}
+
+#[utoipa::path(
+    get, path = "/templates", tag = "templates",
+    params(("X-Tenant-Id" = Option<String>, Header, description = "Tenant whose packs are listed with the public ones")),
+    responses((status = 200, description = "The tenant's template packs and the public ones, by name", body = Vec<TemplatePack>))
+)]
+pub async fn list_template_packs_handler(State(state): State<AppState>, headers: HeaderMap) -> Response {
+    let tenant = headers.get("X-Tenant-Id").and_then(|v| v.to_str().ok());
+    Json(state.packs.list(tenant).await).into_response()
+}
+
+#[utoipa::path(
+    get, path = "/templates/{id}", tag = "templates",
+    params(
+        ("id" = String, Path, description = "Pack id"),
+        ("X-Tenant-Id" = Option<String>, Header, description = "Tenant the pack was registered for"),
+    ),
+    responses(
+        (status = 200, description = "The pack and its last sync", body = TemplatePack),
+        (status = 404, description = "No such pack for this tenant", body = String),
+    )
+)]
+pub async fn get_template_pack_handler(State(state): State<AppState>, UrlPath(id): UrlPath<String>, headers: HeaderMap) -> Response {
+    let tenant = headers.get("X-Tenant-Id").and_then(|v| v.to_str().ok());
+    match state.packs.get(&id, tenant).await {
+        Some(pack) => Json(pack).into_response(),
+        None => (StatusCode::NOT_FOUND, format!("Template pack {} not found", id)).into_response(),
+    }
+}
+
+#[utoipa::path(
+    delete, path = "/templates/{id}", tag = "templates",
+    params(
+        ("id" = String, Path, description = "Pack id"),
+        ("X-Tenant-Id" = Option<String>, Header, description = "Tenant the pack was registered for"),
+        ("Authorization" = Option<String>, Header, description = "`Bearer <ADMIN_TOKEN>`, required for a public pack"),
+    ),
+    responses(
+        (status = 204, description = "Pack deleted; projects created from it keep their files"),
+        (status = 401, description = "A public pack without the admin token", body = String),
+        (status = 404, description = "No such pack registered by this tenant", body = String),
+    )
+)]
+pub async fn delete_template_pack_handler(State(state): State<AppState>, UrlPath(id): UrlPath<String>, headers: HeaderMap) -> Response {
+    let tenant = headers.get("X-Tenant-Id").and_then(|v| v.to_str().ok());
+    if let Err(e) = require_admin_for_public(&state, &headers, tenant) {
+        return e.into_response();
+    }
+    if state.packs.delete(&id, tenant).await {
+        info!("🗑️ Deleted template pack {}", id);
+        StatusCode::NO_CONTENT.into_response()
+    } else {
+        (StatusCode::NOT_FOUND, format!("Template pack {} not found", id)).into_response()
+    }
+}
+
+#[utoipa::path(
+    post, path = "/templates/{id}/sync", tag = "templates",
+    params(
+        ("id" = String, Path, description = "Pack id"),
+        ("X-Tenant-Id" = Option<String>, Header, description = "Tenant the pack was registered for"),
+        ("Authorization" = Option<String>, Header, description = "`Bearer <ADMIN_TOKEN>`, required for a public pack"),
+    ),
+    responses(
+        (status = 200, description = "The sync, also recorded as the pack's last sync. A failed sync leaves the pack on its previous version", body = PackSync),
+        (status = 401, description = "A public pack without the admin token", body = String),
+        (status = 404, description = "No such pack registered by this tenant", body = String),
+    )
+)]
+pub async fn sync_template_pack_handler(State(state): State<AppState>, UrlPath(id): UrlPath<String>, headers: HeaderMap) -> Response {
+    let tenant = headers.get("X-Tenant-Id").and_then(|v| v.to_str().ok());
+    if let Err(e) = require_admin_for_public(&state, &headers, tenant) {
+        return e.into_response();
+    }
+    match crate::packs::resync(&state, &id, tenant).await {
+        Some(sync) => Json(sync).into_response(),
+        None => (StatusCode::NOT_FOUND, format!("Template pack {} not found", id)).into_response(),
+    }
 }
 
-pub async fn validate_handler(Json(payload): Json<ValidationRequest>) -> Json<ValidationResult> {
-    info!("Validating {} files...", payload.files.len());
-    Json(ValidationResult {
-        valid: true,
-        errors: vec![],
-    })
+#[utoipa::path(
+    post, path = "/templates/{id}/projects", tag = "templates",
+    params(
+        ("id" = String, Path, description = "Pack id"),
+        TemplateProjectQuery,
+        ("X-Tenant-Id" = Option<String>, Header, description = "Owner of the new project"),
+    ),
+    responses(
+        (status = 201, description = "A project holding the pack's files, compiled with `POST /projects/{id}/compile`", body = Project),
+        (status = 404, description = "No such pack or template for this tenant", body = String),
+    )
+)]
+pub async fn create_template_project_handler(State(state): State<AppState>, UrlPath(id): UrlPath<String>, Query(query): Query<TemplateProjectQuery>, headers: HeaderMap) -> Response {
+    let tenant = headers.get("X-Tenant-Id").and_then(|v| v.to_str().ok());
+    let Some(pack) = state.packs.get(&id, tenant).await else {
+        return (StatusCode::NOT_FOUND, format!("Template pack {} not found", id)).into_response();
+    };
+    let template = match query.template.as_deref() {
+        Some(name) => pack.templates.iter().find(|t| t.name == name),
+        None => pack.templates.first(),
+    };
+    let Some(template) = template else {
+        return (StatusCode::NOT_FOUND, format!("Pack {} has no template {}", pack.name, query.template.unwrap_or_default())).into_response();
+    };
+    let main_source = match pack.files.iter().find(|f| f.name == template.main) {
+        Some(file) => state.blob_store.get(&file.hash).await.map(|data| String::from_utf8_lossy(&data).to_string()).unwrap_or_default(),
+        None => String::new(),
+    };
+    let mut warnings = Vec::new();
+    let project = Project {
+        id: uuid::Uuid::new_v4().simple().to_string(),
+        main: template.main.clone(),
+        engine: crate::overleaf::engine_for(&main_source, &mut warnings),
+        files: pack.files.clone(),
+        warnings,
+        created_at: unix_now(),
+        last_build: None,
+    };
+    info!("📦 Created project {} from template {} of pack {} {}", project.id, template.name, pack.name, pack.version);
+    state.projects.insert(tenant.map(str::to_string), project.clone()).await;
+    (StatusCode::CREATED, Json(project)).into_response()
 }
 
-pub async fn compile_handler(
-    State(state): State<AppState>,
-    mut multipart: Multipart,
-) -> Response {
-    let mut files_received = 0;
-    let mut main_tex_data = Vec::new();
-    let mut all_input_data = Vec::new();
-    let mut main_tex_path_relative = String::from("main.tex");
+#[utoipa::path(
+    post, path = "/registry/packages", tag = "registry",
+    params(("X-Tenant-Id" = String, Header, description = "Tenant whose compiles find the packages, with its `X-Tenant-Token`")),
+    request_body(content_type = "multipart/form-data", description = "Class, style and other TeX support files, one part per file; a file replaces the package of the same name"),
+    responses(
+        (status = 201, description = "The tenant's whole registry after the upload", body = Vec<RegistryPackage>),
+        (status = 400, description = "No file, a name that is not a support file or is in the TeX bundle, a refused file type or a registry over its limits; nothing is stored", body = String),
+        (status = 401, description = "No tenant: packages are only uploaded for an authenticated tenant", body = String),
+    )
+)]
+pub async fn upload_registry_packages_handler(State(state): State<AppState>, mut multipart: Multipart) -> Response {
+    // Set by `tenancy::tenant_scope` only once the tenant's token checked out
+    let Some(tenant) = crate::tenancy::current_tenant() else {
+        return (StatusCode::UNAUTHORIZED, "Uploading packages needs X-Tenant-Id with its X-Tenant-Token".to_string()).into_response();
+    };
+    let mut files = Vec::new();
+    loop {
+        let field = match multipart.next_field().await {
+            Ok(Some(field)) => field,
+            Ok(None) => break,
+            Err(e) => return (StatusCode::BAD_REQUEST, format!("Multipart error: {}", e)).into_response(),
+        };
+        let Some(name) = field.file_name().map(str::to_string) else { continue };
+        match field.bytes().await {
+            Ok(data) => files.push((name, data.to_vec())),
+            Err(e) => return (StatusCode::BAD_REQUEST, format!("Failed to read {}: {}", name, e)).into_response(),
+        }
+    }
+    let count = files.len();
+    match state.registry.upload(&state, Some(&tenant), files).await {
+        Ok(packages) => {
+            info!("📚 Registered {} packages for tenant {}", count, tenant);
+            (StatusCode::CREATED, Json(packages)).into_response()
+        }
+        Err(e) => (StatusCode::BAD_REQUEST, e).into_response(),
+    }
+}
 
-    let temp_base = if std::path::Path::new("/dev/shm").exists() {
-        let path = PathBuf::from("/dev/shm/tachyon-compilations");
-        fs::create_dir_all(&path).ok();
-        path
-    } else {
-        std::env::temp_dir()
+#[utoipa::path(
+    get, path = "/registry/packages", tag = "registry",
+    params(("X-Tenant-Id" = Option<String>, Header, description = "Tenant whose registry is listed")),
+    responses((status = 200, description = "The tenant's packages, by name", body = Vec<RegistryPackage>))
+)]
+pub async fn list_registry_packages_handler(State(state): State<AppState>, headers: HeaderMap) -> Response {
+    let tenant = headers.get("X-Tenant-Id").and_then(|v| v.to_str().ok());
+    Json(state.registry.list(tenant).await).into_response()
+}
+
+#[utoipa::path(
+    delete, path = "/registry/packages/{name}", tag = "registry",
+    params(
+        ("name" = String, Path, description = "Package file name, e.g. `acme.cls`"),
+        ("X-Tenant-Id" = Option<String>, Header, description = "Tenant whose registry holds the package"),
+    ),
+    responses(
+        (status = 204, description = "Package removed; later compiles no longer find it"),
+        (status = 404, description = "The tenant's registry has no such package", body = String),
+    )
+)]
+pub async fn delete_registry_package_handler(State(state): State<AppState>, UrlPath(name): UrlPath<String>, headers: HeaderMap) -> Response {
+    let tenant = headers.get("X-Tenant-Id").and_then(|v| v.to_str().ok());
+    match state.registry.delete(tenant, &name).await {
+        Ok(true) => {
+            info!("🗑️ Removed package {} from the registry of tenant {}", name, tenant.unwrap_or("default"));
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Ok(false) => (StatusCode::NOT_FOUND, format!("Package {} not found", name)).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e).into_response(),
+    }
+}
+
+/// Requests without a tenant act on public template packs, which every tenant sees; only
+/// an admin may change those.
+fn require_admin_for_public(state: &AppState, headers: &HeaderMap, tenant: Option<&str>) -> Result<(), (StatusCode, &'static str)> {
+    match tenant {
+        Some(_) => Ok(()),
+        None => require_admin(state, headers),
+    }
+}
+
+/// Checks the `ADMIN_TOKEN` bearer token; the admin endpoints answer 404 while it is unset.
+fn require_admin(state: &AppState, headers: &HeaderMap) -> Result<(), (StatusCode, &'static str)> {
+    let Some(token) = state.settings.admin_token.as_deref() else {
+        return Err((StatusCode::NOT_FOUND, "Admin endpoints are disabled"));
     };
+    let authorized = headers.get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .is_some_and(|given| crate::util::constant_time_eq(given.as_bytes(), token.as_bytes()));
+    if !authorized {
+        return Err((StatusCode::UNAUTHORIZED, "Invalid admin token"));
+    }
+    Ok(())
+}
 
-    let temp_dir = match TempDir::new_in(&temp_base) {
-        Ok(d) => d,
-        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to create temp dir: {}", e)).into_response(),
+#[utoipa::path(
+    get, path = "/admin/bundles", tag = "admin",
+    params(("Authorization" = String, Header, description = "`Bearer <ADMIN_TOKEN>`")),
+    responses(
+        (status = 200, description = "The active, staged and previous bundles", body = BundleStatus),
+        (status = 401, description = "Missing or wrong admin token", body = String),
+        (status = 404, description = "ADMIN_TOKEN is not set", body = String),
+    )
+)]
+pub async fn bundle_status_handler(State(state): State<AppState>, headers: HeaderMap) -> Response {
+    if let Err(e) = require_admin(&state, &headers) {
+        return e.into_response();
+    }
+    Json(state.bundles.status()).into_response()
+}
+
+#[utoipa::path(
+    get, path = "/admin/bundles/updates", tag = "admin",
+    params(("Authorization" = String, Header, description = "`Bearer <ADMIN_TOKEN>`")),
+    responses(
+        (status = 200, description = "Releases newer than the active bundle found at BUNDLE_URL_TEMPLATE", body = BundleUpdates),
+        (status = 401, description = "Missing or wrong admin token", body = String),
+        (status = 409, description = "The active bundle's version is not a release number", body = String),
+        (status = 502, description = "The bundle host could not be reached", body = String),
+    )
+)]
+pub async fn bundle_updates_handler(State(state): State<AppState>, headers: HeaderMap) -> Response {
+    if let Err(e) = require_admin(&state, &headers) {
+        return e.into_response();
+    }
+    let active = state.bundles.active().version;
+    match crate::bundles::check_updates(&state.settings, &active).await {
+        Ok(available) => Json(BundleUpdates { active, available }).into_response(),
+        Err(e) => e.into_response(),
+    }
+}
+
+#[utoipa::path(
+    post, path = "/admin/bundles/stage", tag = "admin",
+    params(("Authorization" = String, Header, description = "`Bearer <ADMIN_TOKEN>`")),
+    request_body = StageBundleRequest,
+    responses(
+        (status = 200, description = "The bundle was opened and staged next to the active one", body = BundleStatus),
+        (status = 401, description = "Missing or wrong admin token", body = String),
+        (status = 409, description = "The bundle is already active", body = String),
+        (status = 502, description = "The bundle could not be opened", body = String),
+    )
+)]
+pub async fn stage_bundle_handler(State(state): State<AppState>, headers: HeaderMap, Json(request): Json<StageBundleRequest>) -> Response {
+    if let Err(e) = require_admin(&state, &headers) {
+        return e.into_response();
+    }
+    let version = request.version.trim().to_string();
+    if version.is_empty() {
+        return (StatusCode::BAD_REQUEST, "version is required".to_string()).into_response();
+    }
+    let url = request.url.unwrap_or_else(|| crate::bundles::release_url(&state.settings, &version));
+    if let Err(e) = crate::bundles::open(&state, &url).await {
+        return (StatusCode::BAD_GATEWAY, e).into_response();
+    }
+    match state.bundles.stage(version.clone(), url.clone()).await {
+        Ok(status) => {
+            info!("📚 Staged TeX bundle {} ({})", version, url);
+            Json(status).into_response()
+        }
+        Err(e) => e.into_response(),
+    }
+}
+
+#[utoipa::path(
+    delete, path = "/admin/bundles/staged", tag = "admin",
+    params(("Authorization" = String, Header, description = "`Bearer <ADMIN_TOKEN>`")),
+    responses(
+        (status = 200, description = "The staged bundle was dropped", body = BundleStatus),
+        (status = 401, description = "Missing or wrong admin token", body = String),
+        (status = 404, description = "No bundle is staged", body = String),
+    )
+)]
+pub async fn unstage_bundle_handler(State(state): State<AppState>, headers: HeaderMap) -> Response {
+    if let Err(e) = require_admin(&state, &headers) {
+        return e.into_response();
+    }
+    match state.bundles.unstage().await {
+        Ok(status) => Json(status).into_response(),
+        Err(e) => e.into_response(),
+    }
+}
+
+#[utoipa::path(
+    post, path = "/admin/bundles/staged/trial", tag = "admin",
+    params(("Authorization" = String, Header, description = "`Bearer <ADMIN_TOKEN>`")),
+    responses(
+        (status = 200, description = "Every regression test rebuilt with the staged bundle; recorded on it for activation", body = BundleTrial),
+        (status = 401, description = "Missing or wrong admin token", body = String),
+        (status = 404, description = "No bundle is staged", body = String),
+        (status = 409, description = "Another bundle was staged while the trial ran", body = String),
+    )
+)]
+pub async fn trial_bundle_handler(State(state): State<AppState>, headers: HeaderMap) -> Response {
+    if let Err(e) = require_admin(&state, &headers) {
+        return e.into_response();
+    }
+    let Some(staged) = state.bundles.staged() else {
+        return (StatusCode::NOT_FOUND, "No bundle is staged".to_string()).into_response();
     };
+    let trial = crate::regression::trial(&state, &staged).await;
+    match state.bundles.record_trial(&staged.version, trial.clone()).await {
+        Ok(_) => Json(trial).into_response(),
+        Err(e) => e.into_response(),
+    }
+}
+
+#[utoipa::path(
+    post, path = "/admin/bundles/activate", tag = "admin",
+    params(ActivateBundleQuery, ("Authorization" = String, Header, description = "`Bearer <ADMIN_TOKEN>`")),
+    responses(
+        (status = 200, description = "Compiles now use the staged bundle; the one it replaced is kept for rollback", body = BundleStatus),
+        (status = 401, description = "Missing or wrong admin token", body = String),
+        (status = 404, description = "No bundle is staged", body = String),
+        (status = 409, description = "The staged bundle has not passed a trial and `force` is not set", body = String),
+    )
+)]
+pub async fn activate_bundle_handler(State(state): State<AppState>, Query(query): Query<ActivateBundleQuery>, headers: HeaderMap) -> Response {
+    if let Err(e) = require_admin(&state, &headers) {
+        return e.into_response();
+    }
+    match state.bundles.activate(query.force).await {
+        Ok(status) => {
+            crate::bundles::activated(&state);
+            Json(status).into_response()
+        }
+        Err(e) => e.into_response(),
+    }
+}
 
+#[utoipa::path(
+    post, path = "/admin/bundles/rollback", tag = "admin",
+    params(("Authorization" = String, Header, description = "`Bearer <ADMIN_TOKEN>`")),
+    responses(
+        (status = 200, description = "Compiles use the previous bundle again", body = BundleStatus),
+        (status = 401, description = "Missing or wrong admin token", body = String),
+        (status = 404, description = "No bundle was replaced yet", body = String),
+    )
+)]
+pub async fn rollback_bundle_handler(State(state): State<AppState>, headers: HeaderMap) -> Response {
+    if let Err(e) = require_admin(&state, &headers) {
+        return e.into_response();
+    }
+    match state.bundles.rollback().await {
+        Ok(status) => {
+            crate::bundles::activated(&state);
+            Json(status).into_response()
+        }
+        Err(e) => e.into_response(),
+    }
+}
+
+#[utoipa::path(
+    put, path = "/admin/signing/{tenant}", tag = "admin",
+    params(
+        ("tenant" = String, Path, description = "Tenant whose `sign=true` compiles use the certificate"),
+        ("Authorization" = String, Header, description = "`Bearer <ADMIN_TOKEN>`"),
+    ),
+    request_body(content_type = "multipart/form-data", description = "`certificate`: a PKCS#12 bundle with `password`, or a PEM chain (signer first) with `signer_url` and optionally `signer_token` for a remote HSM/KMS signer. The remote signer receives `POST {\"algorithm\": \"sha256\", \"digest\", \"data\"}` (base64) and answers `{\"signature\"}` (base64)"),
+    responses(
+        (status = 200, description = "The certificate now signs the tenant's PDFs", body = SignerInfo),
+        (status = 400, description = "Missing fields, wrong password or unreadable certificate", body = String),
+        (status = 401, description = "Missing or wrong admin token", body = String),
+        (status = 404, description = "ADMIN_TOKEN is not set", body = String),
+    )
+)]
+pub async fn configure_signing_handler(State(state): State<AppState>, UrlPath(tenant): UrlPath<String>, headers: HeaderMap, mut multipart: Multipart) -> Response {
+    if let Err(e) = require_admin(&state, &headers) {
+        return e.into_response();
+    }
+    let (mut certificate, mut password, mut signer_url, mut signer_token) = (None, None, None, None);
     loop {
         let field = match multipart.next_field().await {
             Ok(Some(field)) => field,
             Ok(None) => break,
-            Err(e) => {
-                error!("Multipart error: {}", e);
-                return (StatusCode::BAD_REQUEST, format!("Multipart error: {}", e)).into_response();
+            Err(e) => return (StatusCode::BAD_REQUEST, format!("Multipart error: {}", e)).into_response(),
+        };
+        let name = field.name().unwrap_or_default().to_string();
+        if name == "certificate" {
+            match field.bytes().await {
+                Ok(data) => certificate = Some(data.to_vec()),
+                Err(e) => return (StatusCode::BAD_REQUEST, format!("Failed to read the certificate: {}", e)).into_response(),
             }
+            continue;
+        }
+        let value = match field.text().await {
+            Ok(value) => value.trim().to_string(),
+            Err(e) => return (StatusCode::BAD_REQUEST, format!("Failed to read {}: {}", name, e)).into_response(),
         };
+        match name.as_str() {
+            "password" => password = Some(value),
+            "signer_url" => signer_url = Some(value).filter(|url| !url.is_empty()),
+            "signer_token" => signer_token = Some(value).filter(|token| !token.is_empty()),
+            _ => {}
+        }
+    }
+    let Some(certificate) = certificate else {
+        return (StatusCode::BAD_REQUEST, "certificate is required".to_string()).into_response();
+    };
+    let config = match (signer_url, password) {
+        (Some(url), _) => SignerConfig::Remote { chain_pem: certificate, url, token: signer_token },
+        (None, Some(password)) => SignerConfig::Pkcs12 { data: certificate, password },
+        (None, None) => return (StatusCode::BAD_REQUEST, "password (for a PKCS#12 bundle) or signer_url (for a remote signer) is required".to_string()).into_response(),
+    };
+    match state.signing.configure(&tenant, config, &state.settings).await {
+        Ok(info) => {
+            info!("✍️ Signing certificate for tenant {}: {} ({})", tenant, info.subject, info.mode);
+            Json(info).into_response()
+        }
+        Err(e) => e.into_response(),
+    }
+}
 
-        let file_name = field.file_name().unwrap_or("file.tex").to_string();
-        
-        match field.bytes().await {
-            Ok(data) => {
-                files_received += 1;
-                let path = temp_dir.path().join(&file_name);
-                if let Some(parent) = path.parent() { 
-                    if let Err(e) = fs::create_dir_all(parent) {
-                        return (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to create directory: {}", e)).into_response();
-                    }
-                }
-                if let Err(e) = fs::write(&path, &data) {
-                    return (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to write file {}: {}", file_name, e)).into_response();
-                }
-                all_input_data.extend_from_slice(&data);
-                if file_name.ends_with(".tex") {
-                    main_tex_data = data.to_vec();
-                    main_tex_path_relative = file_name.clone();
-                }
-            },
+#[utoipa::path(
+    get, path = "/admin/signing/{tenant}", tag = "admin",
+    params(
+        ("tenant" = String, Path, description = "Tenant id"),
+        ("Authorization" = String, Header, description = "`Bearer <ADMIN_TOKEN>`"),
+    ),
+    responses(
+        (status = 200, description = "The tenant's signing certificate", body = SignerInfo),
+        (status = 401, description = "Missing or wrong admin token", body = String),
+        (status = 404, description = "No certificate is configured for the tenant, or ADMIN_TOKEN is not set", body = String),
+    )
+)]
+pub async fn get_signing_handler(State(state): State<AppState>, UrlPath(tenant): UrlPath<String>, headers: HeaderMap) -> Response {
+    if let Err(e) = require_admin(&state, &headers) {
+        return e.into_response();
+    }
+    match state.signing.info(&tenant).await {
+        Some(info) => Json(info).into_response(),
+        None => (StatusCode::NOT_FOUND, format!("No signing certificate is configured for tenant '{}'", tenant)).into_response(),
+    }
+}
+
+#[utoipa::path(
+    delete, path = "/admin/signing/{tenant}", tag = "admin",
+    params(
+        ("tenant" = String, Path, description = "Tenant id"),
+        ("Authorization" = String, Header, description = "`Bearer <ADMIN_TOKEN>`"),
+    ),
+    responses(
+        (status = 204, description = "The certificate was removed; `sign=true` is refused for the tenant"),
+        (status = 401, description = "Missing or wrong admin token", body = String),
+        (status = 404, description = "No certificate is configured for the tenant, or ADMIN_TOKEN is not set", body = String),
+    )
+)]
+pub async fn delete_signing_handler(State(state): State<AppState>, UrlPath(tenant): UrlPath<String>, headers: HeaderMap) -> Response {
+    if let Err(e) = require_admin(&state, &headers) {
+        return e.into_response();
+    }
+    if state.signing.remove(&tenant).await {
+        info!("✍️ Removed the signing certificate of tenant {}", tenant);
+        StatusCode::NO_CONTENT.into_response()
+    } else {
+        (StatusCode::NOT_FOUND, format!("No signing certificate is configured for tenant '{}'", tenant)).into_response()
+    }
+}
+
+/// Result, log, compile time, cache status and log notes of one build of a multi-target request.
+type BuildOutcome = (Result<Vec<u8>, String>, String, u64, &'static str, CompileNotes);
+
+/// Compiles `main` in `workspace` through the PDF and failure caches.
+async fn build_target(state: &AppState, force: bool, priority: Priority, workspace: &Path, main: &str, input_hash: u64) -> BuildOutcome {
+    let failure = if force { None } else { state.compilation_cache.get_failure(input_hash).await };
+    let hit = if force || failure.is_some() { None } else { state.compilation_cache.get_pdf(input_hash).await };
+    match (failure, hit) {
+        (Some(failure), _) => (Err(failure.error), failure.logs, 0, "NEGATIVE", CompileNotes::default()),
+        (None, Some((pdf, original_time, false, notes))) => (Ok(pdf.to_vec()), String::new(), original_time, "HIT", notes),
+        _ => {
+            let permit = state.scheduler.acquire(priority).await;
+            let start = Instant::now();
+            let (result, logs) = crate::workers::compile(state, workspace, &workspace.join(main), priority, permit).await;
+            let compile_time_ms = start.elapsed().as_millis() as u64;
+            let notes = CompileNotes::from_log(&logs);
+            match &result {
+                Ok(pdf) => state.compilation_cache.put_pdf(input_hash, pdf, compile_time_ms, &notes).await,
+                Err(e) => state.compilation_cache.put_failure(input_hash, e, &logs).await,
+            }
+            (result, logs, compile_time_ms, "MISS", notes)
+        }
+    }
+}
+
+/// Packs the builds of a multi-target request, given as `(target, output stem, outcome)`,
+/// into a ZIP of the PDFs (and logs of failed builds) with a `manifest.json` of per-target
+/// results.
+fn targets_response(builds: Vec<(String, String, BuildOutcome)>, start: Instant) -> Response {
+    let total = builds.len();
+    let mut entries: Vec<(String, Vec<u8>)> = Vec::new();
+    let mut manifest = Vec::with_capacity(total);
+    for (target, stem, (result, logs, compile_time_ms, cache, _)) in builds {
+        let mut entry = TargetResult {
+            target: target.clone(),
+            success: result.is_ok(),
+            pdf: None,
+            log: None,
+            compile_time_ms,
+            cache: cache.to_string(),
+            error: None,
+        };
+        match result {
+            Ok(pdf) => {
+                entry.pdf = Some(format!("{}.pdf", stem));
+                entries.push((format!("{}.pdf", stem), pdf));
+            }
             Err(e) => {
-                error!("Failed to read chunks for file {}: {}", file_name, e);
-                return (StatusCode::BAD_REQUEST, format!("Failed to read file {}: {}", file_name, e)).into_response();
+                error!("❌ Target {} failed: {}", target, e);
+                entry.log = Some(format!("{}.log", stem));
+                entry.error = Some(e);
+                entries.push((format!("{}.log", stem), logs.into_bytes()));
             }
         }
+        manifest.push(entry);
     }
 
-    let main_tex_path = temp_dir.path().join(&main_tex_path_relative);
-    let input_hash = CompilationCache::hash_input(&all_input_data);
+    let succeeded = manifest.iter().filter(|t| t.success).count();
+    entries.push(("manifest.json".to_string(), serde_json::to_vec_pretty(&manifest).unwrap_or_default()));
+    let archive = match render::zip_files(&entries) {
+        Ok(a) => a,
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to build zip: {}", e)).into_response(),
+    };
+    info!("📚 Built {}/{} targets in {}ms", succeeded, total, start.elapsed().as_millis());
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "application/zip")
+        .header(header::CONTENT_DISPOSITION, "attachment; filename=\"targets.zip\"")
+        .header("X-Targets-Succeeded", succeeded.to_string())
+        .header("X-Targets-Failed", (total - succeeded).to_string())
+        .body(axum::body::Body::from(archive))
+        .unwrap()
+}
 
-    if let Some((cached_pdf, original_time)) = state.compilation_cache.get_pdf(input_hash).await {
-        info!("📦 Cache HIT for hash {:016x}", input_hash);
-        return Response::builder()
-            .status(StatusCode::OK)
-            .header(header::CONTENT_TYPE, "application/pdf")
-            .header("X-Compile-Time-Ms", original_time.to_string())
-            .header("X-Cache", "HIT")
-            .header("X-Files-Received", files_received.to_string())
-            .body(axum::body::Body::from(cached_pdf))
-            .unwrap();
+/// Builds each target of one uploaded project in turn, in the shared workspace.
+async fn compile_targets(
+    state: &AppState,
+    query: &CompileQuery,
+    headers: &HeaderMap,
+    workspace: &crate::janitor::Workspace,
+    input_hasher: InputHasher,
+    targets: &[String],
+) -> Response {
+    let priority = Priority::parse(headers.get("X-Priority").and_then(|v| v.to_str().ok()));
+    let start = Instant::now();
+    let mut builds = Vec::with_capacity(targets.len());
+    for target in targets {
+        let input_hash = input_hasher.clone().finish(target);
+        let outcome = build_target(state, query.force, priority, workspace.path(), target, input_hash).await;
+        builds.push((target.clone(), target.strip_suffix(".tex").unwrap_or(target).to_string(), outcome));
     }
+    targets_response(builds, start)
+}
 
-    let hmr_status;
-    let preamble_hash;
-    if let Ok(content) = String::from_utf8(main_tex_data) {
-        if let Some(preamble) = FormatCache::extract_preamble(&content) {
-            preamble_hash = FormatCache::hash_preamble(preamble);
-            hmr_status = if state.format_cache.check_and_mark(preamble_hash).await { "HIT" } else { "MISS" };
-        } else {
-            hmr_status = "NONE"; preamble_hash = 0;
+/// Builds the requested beamer variants of `main` concurrently, each in its own copy of
+/// the workspace with the rewritten main file.
+async fn compile_variants(
+    state: &AppState,
+    query: &CompileQuery,
+    headers: &HeaderMap,
+    workspace: &crate::janitor::Workspace,
+    input_hasher: InputHasher,
+    main: &str,
+    variants: &[String],
+) -> Response {
+    let source = match fs::read_to_string(workspace.path().join(main)) {
+        Ok(s) => s,
+        Err(e) => return (StatusCode::BAD_REQUEST, format!("variants need a UTF-8 main .tex file: {}", e)).into_response(),
+    };
+    let mut copies = Vec::with_capacity(variants.len());
+    for variant in variants {
+        let rewritten = match rewrite::beamer_variant(&source, variant) {
+            Ok(s) => s,
+            Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+        };
+        let copy = match state.janitor.copy_of(workspace.path()) {
+            Ok(c) => c,
+            Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, e).into_response(),
+        };
+        if let Err(e) = fs::write(copy.path().join(main), &rewritten) {
+            return (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to write file {}: {}", main, e)).into_response();
         }
-    } else {
-        hmr_status = "ERROR"; preamble_hash = 0;
+        let mut hasher = input_hasher.clone();
+        hasher.add_file(main, rewritten.as_bytes());
+        copies.push((variant, copy, hasher.finish(main)));
     }
 
-    info!("Compiling {:?} ({} files, HMR: {})...", main_tex_path, files_received, hmr_status);
+    let priority = Priority::parse(headers.get("X-Priority").and_then(|v| v.to_str().ok()));
     let start = Instant::now();
+    let outcomes = futures_util::future::join_all(copies.iter().map(|(_, copy, input_hash)| {
+        build_target(state, query.force, priority, copy.path(), main, *input_hash)
+    })).await;
+    let stem = main.strip_suffix(".tex").unwrap_or(main);
+    let builds = copies.iter().zip(outcomes)
+        .map(|((variant, _, _), outcome)| (variant.to_string(), format!("{}-{}", stem, variant), outcome))
+        .collect();
+    targets_response(builds, start)
+}
 
-    let (result, logs) = Compiler::compile_file(
-        &main_tex_path,
-        temp_dir.path(),
-        &state.format_cache_path,
-        &state.config
-    );
+/// Builds the main file once per matrix cell, concurrently, each in its own copy of the
+/// workspace. Cells for engines this server lacks are reported without compiling.
+async fn compile_matrix(
+    state: &AppState,
+    query: &CompileQuery,
+    headers: &HeaderMap,
+    workspace: &crate::janitor::Workspace,
+    input_hasher: InputHasher,
+    main: &str,
+    cells: &[crate::matrix::Cell],
+) -> Response {
+    let source = match fs::read_to_string(workspace.path().join(main)) {
+        Ok(s) => s,
+        Err(e) => return (StatusCode::BAD_REQUEST, format!("A matrix needs a UTF-8 main .tex file: {}", e)).into_response(),
+    };
+    let mut copies = Vec::new();
+    for cell in cells.iter().filter(|cell| cell.supported()) {
+        let rewritten = match cell.rewrite(&source) {
+            Ok(s) => s,
+            Err(e) => return (StatusCode::BAD_REQUEST, format!("Cannot build {} for {}: {}", main, cell.name(), e)).into_response(),
+        };
+        let copy = match state.janitor.copy_of(workspace.path()) {
+            Ok(c) => c,
+            Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, e).into_response(),
+        };
+        if let Err(e) = fs::write(copy.path().join(main), &rewritten) {
+            return (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to write file {}: {}", main, e)).into_response();
+        }
+        let mut hasher = input_hasher.clone();
+        hasher.add_file(main, rewritten.as_bytes());
+        copies.push((cell.name(), copy, hasher.finish(main)));
+    }
 
-    let compile_time_ms = start.elapsed().as_millis() as u64;
+    let priority = Priority::parse(headers.get("X-Priority").and_then(|v| v.to_str().ok()));
+    let start = Instant::now();
+    let outcomes = futures_util::future::join_all(copies.iter().map(|(_, copy, input_hash)| {
+        build_target(state, query.force, priority, copy.path(), main, *input_hash)
+    })).await;
+    let mut outcomes: HashMap<String, BuildOutcome> = copies.iter().map(|(name, _, _)| name.clone()).zip(outcomes).collect();
 
-    match result {
-        Ok(pdf_data) => {
-            state.compilation_cache.put_pdf(input_hash, &pdf_data, compile_time_ms).await;
-            Response::builder()
-                .status(StatusCode::OK)
-                .header(header::CONTENT_TYPE, "application/pdf")
-                .header("X-Compile-Time-Ms", compile_time_ms.to_string())
-                .header("X-Cache", "MISS")
-                .header("X-HMR", hmr_status)
-                .header("X-Files-Received", files_received.to_string())
-                .body(axum::body::Body::from(pdf_data))
-                .unwrap()
+    let stem = main.strip_suffix(".tex").unwrap_or(main);
+    let mut entries: Vec<(String, Vec<u8>)> = Vec::new();
+    let mut report = MatrixReport { main: main.to_string(), succeeded: 0, failed: 0, unsupported: 0, cells: Vec::with_capacity(cells.len()) };
+    for cell in cells {
+        let name = cell.name();
+        let mut entry = MatrixCell {
+            cell: name.clone(),
+            engine: cell.engine.clone(),
+            mode: cell.mode.clone(),
+            paper: cell.paper.clone(),
+            status: "unsupported".to_string(),
+            pdf: None,
+            log: None,
+            pages: None,
+            warnings: 0,
+            compile_time_ms: 0,
+            cache: None,
+            error: None,
+        };
+        match outcomes.remove(&name) {
+            None => {
+                entry.error = Some(format!("{} is not available on this server; cells compile with {}", cell.engine, crate::matrix::SUPPORTED_ENGINE));
+                report.unsupported += 1;
+            }
+            Some((result, logs, compile_time_ms, cache, notes)) => {
+                entry.compile_time_ms = compile_time_ms;
+                entry.cache = Some(cache.to_string());
+                entry.warnings = notes.warnings.len();
+                match result {
+                    Ok(pdf) => {
+                        entry.status = "ok".to_string();
+                        entry.pages = crate::pages::page_count(&pdf).ok();
+                        entry.pdf = Some(format!("{}-{}.pdf", stem, name));
+                        entries.push((format!("{}-{}.pdf", stem, name), pdf));
+                        report.succeeded += 1;
+                    }
+                    Err(e) => {
+                        error!("❌ Matrix cell {} failed: {}", name, e);
+                        entry.status = "failed".to_string();
+                        entry.error = Some(e);
+                        entry.log = Some(format!("{}-{}.log", stem, name));
+                        entries.push((format!("{}-{}.log", stem, name), logs.into_bytes()));
+                        report.failed += 1;
+                    }
+                }
+            }
         }
-        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("LaTeX Error: {}\n\nLogs:\n{}", e, logs)).into_response()
+        report.cells.push(entry);
+    }
+
+    entries.push(("matrix.json".to_string(), serde_json::to_vec_pretty(&report).unwrap_or_default()));
+    let archive = match render::zip_files(&entries) {
+        Ok(a) => a,
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to build zip: {}", e)).into_response(),
+    };
+    info!("🧮 Built a {}-cell matrix of {} in {}ms: {} ok, {} failed, {} unsupported",
+        cells.len(), main, start.elapsed().as_millis(), report.succeeded, report.failed, report.unsupported);
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "application/zip")
+        .header(header::CONTENT_DISPOSITION, "attachment; filename=\"matrix.zip\"")
+        .header("X-Matrix-Succeeded", report.succeeded.to_string())
+        .header("X-Matrix-Failed", report.failed.to_string())
+        .header("X-Matrix-Unsupported", report.unsupported.to_string())
+        .body(axum::body::Body::from(archive))
+        .unwrap()
+}
+
+/// Worker side of the controller/worker split: compiles an uploaded workspace archive.
+/// Only enabled when `WORKER_TOKEN` is set, and requires it as a bearer token.
+pub async fn worker_compile_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    body: bytes::Bytes,
+) -> Response {
+    let Some(token) = state.settings.worker_token.as_deref() else {
+        return (StatusCode::NOT_FOUND, "Worker mode is disabled").into_response();
+    };
+    let authorized = headers.get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .is_some_and(|given| crate::util::constant_time_eq(given.as_bytes(), token.as_bytes()));
+    if !authorized {
+        return (StatusCode::UNAUTHORIZED, "Invalid worker token").into_response();
+    }
+
+    let temp_dir = match state.janitor.workspace() {
+        Ok(d) => d,
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to create temp dir: {}", e)).into_response(),
+    };
+    if let Err(e) = crate::workers::unpack_workspace(&body, temp_dir.path()) {
+        return (StatusCode::BAD_REQUEST, e).into_response();
+    }
+    let main_file = headers.get("X-Main-File").and_then(|v| v.to_str().ok()).unwrap_or("main.tex");
+    let main_tex_path = temp_dir.path().join(main_file);
+    if !main_tex_path.starts_with(temp_dir.path()) || main_file.split('/').any(|part| part == "..") {
+        return (StatusCode::BAD_REQUEST, format!("Invalid main file '{}'", main_file)).into_response();
     }
+
+    // Validated by the controller, which the worker token vouches for
+    let options = match headers.get("X-Tex-Options").and_then(|v| v.to_str().ok()).map(serde_json::from_str::<crate::compiler::TexOptions>) {
+        Some(Ok(options)) => Ok(options),
+        Some(Err(e)) => Err(format!("Invalid X-Tex-Options: {}", e)),
+        None => Ok(Default::default()),
+    };
+    let options = match options {
+        Ok(options) => options,
+        Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+    };
+
+    // The controller's active bundle, so a rollout reaches the workers too
+    let bundle_url = match headers.get("X-Bundle-Url").and_then(|v| v.to_str().ok()) {
+        Some(url) => Some(url.to_string()),
+        None => state.bundles.active().url,
+    };
+
+    let priority = Priority::parse(headers.get("X-Priority").and_then(|v| v.to_str().ok()));
+    let permit = state.scheduler.acquire(priority).await;
+    info!("🛠️ Worker compiling {} (priority: {})", main_file, priority.as_str());
+    let (result, logs) = crate::workers::compile_with_bundle(&state, temp_dir.path(), &main_tex_path, bundle_url, &options, permit).await;
+    let (pdf_base64, error) = match result {
+        Ok(pdf) => (Some(general_purpose::STANDARD.encode(pdf)), None),
+        Err(e) => (None, Some(e)),
+    };
+    Json(WorkerCompileResponse { pdf_base64, error, logs }).into_response()
 }
 
 pub async fn ws_route_handler(
     ws: axum::extract::ws::WebSocketUpgrade,
     State(state): State<AppState>,
+    headers: HeaderMap,
 ) -> Response {
+    let language = ErrorExplainer::negotiate(
+        headers.get(header::ACCEPT_LANGUAGE).and_then(|v| v.to_str().ok())
+    );
     ws
+        .protocols(WS_PROTOCOLS)
         .max_frame_size(128 * 1024 * 1024)
         .max_message_size(128 * 1024 * 1024)
-        .on_upgrade(move |socket| handle_socket(socket, state))
+        .on_upgrade(move |socket| handle_socket(socket, state, language))
+}
+
+/// Build outputs a live workspace keeps between compiles to preserve its hot state.
+fn is_build_artifact(name: &str) -> bool {
+    [".aux", ".log", ".toc", ".out", ".pdf", ".fls", ".fdb_latexmk", ".synctex.gz"].iter().any(|ext| name.ends_with(ext))
+}
+
+/// Every file of a live workspace but its build outputs, by relative path.
+fn workspace_inputs(root: &Path) -> Vec<(String, PathBuf)> {
+    let mut inputs = Vec::new();
+    let mut dirs = vec![root.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        let Ok(entries) = fs::read_dir(&dir) else { continue };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            match entry.file_type() {
+                Ok(t) if t.is_dir() => dirs.push(path),
+                Ok(t) if t.is_file() => {
+                    let Ok(name) = path.strip_prefix(root).map(|name| name.to_string_lossy().into_owned()) else { continue };
+                    if !is_build_artifact(&name) {
+                        inputs.push((name, path));
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+    inputs
 }
 
-pub async fn handle_socket(mut socket: WebSocket, state: AppState) {
-    info!("\u{1F50C} WebSocket connection established");
+pub async fn handle_socket(mut socket: WebSocket, state: AppState, language: &'static str) {
+    let compression = socket.protocol().and_then(|p| p.to_str().ok()).and_then(Encoding::from_protocol);
+    info!("\u{1F50C} WebSocket connection established (lang: {}, compression: {})",
+        language, compression.map_or("none", |c| c.as_str()));
     
     // Moonshot #4: Persistent Worker Pool
     // Create the workspace ONCE per connection.
     // This preserves .aux, .fmt, and downloaded assets between compilations.
-    let temp_base = if std::path::Path::new("/dev/shm").exists() {
-        let path = PathBuf::from("/dev/shm/tachyon-compilations");
-        fs::create_dir_all(&path).ok();
-        path
-    } else {
-        std::env::temp_dir()
-    };
-
-    let temp_dir = match TempDir::new_in(&temp_base) {
+    let temp_dir = match state.janitor.workspace() {
         Ok(d) => {
             info!("🔥 Hot Worker initialized at {:?}", d.path());
             d
@@ -183,6 +3743,8 @@ pub async fn handle_socket(mut socket: WebSocket, state: AppState) {
              return; // Close connection if we can't create workspace
         }
     };
+    // Page hashes of the last successful compile, to report which pages changed
+    let mut previous_pages: Vec<String> = Vec::new();
     
     while let Some(msg_res) = socket.recv().await {
         let msg = match msg_res {
@@ -195,6 +3757,7 @@ pub async fn handle_socket(mut socket: WebSocket, state: AppState) {
             // TempDir is now persistent (defined outside loop)
 
             let mut uploaded_hashes = std::collections::HashMap::new();
+            let mut sources = HashMap::new();
 
             // Moonshot #5: Workspace Synchronization (Cleanup)
             // The JSON request is the Source of Truth.
@@ -207,12 +3770,7 @@ pub async fn handle_socket(mut socket: WebSocket, state: AppState) {
                         if let Some(name) = path.file_name().and_then(|n| n.to_str()) {
                             // Don't delete if it's in the new list OR if it's a kept artifact
                             let is_in_project = project.files.contains_key(name);
-                            let is_artifact = name.ends_with(".aux") || name.ends_with(".log") || 
-                                              name.ends_with(".toc") || name.ends_with(".out") || 
-                                              name.ends_with(".pdf") || name.ends_with(".fls") ||
-                                              name.ends_with(".fdb_latexmk") || name.ends_with(".synctex.gz");
-
-                            if !is_in_project && !is_artifact {
+                            if !is_in_project && !is_build_artifact(name) {
                                 info!("🗑️ Sync Cleanup: Removing orphaned file '{}'", name);
                                 let _ = fs::remove_file(path);
                             }
@@ -229,6 +3787,23 @@ pub async fn handle_socket(mut socket: WebSocket, state: AppState) {
                     WsFileContent::Raw(data) => {
                         // Text files: write as-is (UTF-8)
                         let _ = fs::write(&path, data);
+                        sources.insert(name.clone(), data.clone());
+                    },
+                    WsFileContent::Compressed { base64: data, encoding } => {
+                        let decoded = general_purpose::STANDARD.decode(data).map_err(|e| e.to_string())
+                            .and_then(|compressed| match Encoding::parse(encoding) {
+                                Some(encoding) => encoding.decompress(&compressed),
+                                None => Err(format!("Unknown encoding '{}'", encoding)),
+                            });
+                        match decoded {
+                            Ok(content) => {
+                                if let Ok(text) = std::str::from_utf8(&content) {
+                                    sources.insert(name.clone(), text.to_string());
+                                }
+                                let _ = fs::write(&path, content);
+                            }
+                            Err(e) => error!("Failed to decode compressed {}: {}", name, e),
+                        }
                     },
                     WsFileContent::Binary { base64: data } => {
                         // Binary files: decode base64 first
@@ -303,33 +3878,111 @@ pub async fn handle_socket(mut socket: WebSocket, state: AppState) {
 
             let main_tex = project.main.clone().unwrap_or_else(|| "main.tex".to_string());
             let main_path = temp_dir.path().join(&main_tex);
-            let start = Instant::now();
+            let mut warnings: Vec<CompileWarning> = CitationChecker::check(&sources).into_iter().map(Into::into).collect();
+
+            // Same cache key as the HTTP path, taken from the whole synced workspace: remote and
+            // blob-referenced assets count by content, and files an earlier message left in
+            // subdirectories count too, since the compile sees them
+            let mut input_hasher = state.compilation_cache.input_hasher();
+            for (name, path) in workspace_inputs(temp_dir.path()) {
+                if let Ok(data) = fs::read(path) {
+                    input_hasher.add_file(&name, &data);
+                }
+            }
+            let input_hash = input_hasher.finish(&main_tex);
 
-            let (result, logs) = Compiler::compile_file(
-                &main_path,
-                temp_dir.path(),
-                &state.format_cache_path,
-                &state.config
-            );
+            // Stale entries count as misses: a live preview should reflect the current bundle
+            let start = Instant::now();
+            let (result, logs, usage, cache_status, notes) = if let Some(failure) = state.compilation_cache.get_failure(input_hash).await {
+                info!("🚫 Live negative cache HIT for hash {:016x}", input_hash);
+                (Err(failure.error), failure.logs, None, "NEGATIVE", CompileNotes::default())
+            } else if let Some((pdf, compile_time_ms, false, notes)) = state.compilation_cache.get_pdf(input_hash).await {
+                info!("📦 Live cache HIT for hash {:016x}", input_hash);
+                (Ok((pdf.to_vec(), compile_time_ms)), String::new(), None, "HIT", notes)
+            } else {
+                // Live preview always runs at interactive priority
+                let permit = state.scheduler.acquire(Priority::Interactive).await;
+                let meter = ResourceMeter::start();
+                let (result, logs) = crate::workers::compile(&state, temp_dir.path(), &main_path, Priority::Interactive, permit).await;
+                let usage = meter.finish(result.as_ref().map(|pdf| pdf.len()).unwrap_or(0));
+                let compile_time_ms = start.elapsed().as_millis() as u64;
+                let notes = CompileNotes::from_log(&logs);
+                match &result {
+                    Ok(pdf_data) => state.compilation_cache.put_pdf(input_hash, pdf_data, compile_time_ms, &notes).await,
+                    Err(e) => state.compilation_cache.put_failure(input_hash, e, &logs).await,
+                }
+                if let Some(fixes) = SelfHealer::report(&logs) {
+                    let _ = socket.send(Message::Text(serde_json::json!({
+                        "type": "healing_report",
+                        "file": main_tex,
+                        "fixes": fixes,
+                        "healed": result.is_ok(),
+                    }).to_string())).await;
+                }
+                (result.map(|pdf| (pdf, compile_time_ms)), logs, Some(usage), "MISS", notes)
+            };
 
             match result {
-                Ok(pdf_data) => {
-                    let duration = start.elapsed().as_millis() as u64;
+                Ok((pdf_data, duration)) => {
+                    warnings.extend(notes.warnings);
+                    if project.check_pages {
+                        warnings.extend(ghost_page_warnings(bytes::Bytes::copy_from_slice(&pdf_data)).await);
+                    }
+                    let output_hash = state.output_store.put(&pdf_data).await;
+                    // Send only a delta when the client still holds a previous version
+                    let mut delta_base = None;
+                    let mut body = None;
+                    if let Some(base_hash) = project.base_hash.as_deref().filter(|h| *h != output_hash) {
+                        if let Some(base_pdf) = state.output_store.get(base_hash).await {
+                            let delta = crate::delta::diff(&base_pdf, &pdf_data);
+                            // Round-trip check so a diff bug can never corrupt the preview
+                            let rebuilds = crate::delta::apply(&base_pdf, &delta).is_ok_and(|rebuilt| rebuilt == pdf_data);
+                            if delta.len() < pdf_data.len() && rebuilds {
+                                info!("🧩 PDF delta against {}: {} bytes instead of {}", base_hash, delta.len(), pdf_data.len());
+                                delta_base = Some(base_hash.to_string());
+                                body = Some(delta);
+                            }
+                        }
+                    }
+                    let page_hashes = crate::pages::page_hashes(&pdf_data)
+                        .map_err(|e| warn!("Page hashing failed: {}", e))
+                        .ok();
+                    let changed_pages = page_hashes.as_ref().map(|hashes| crate::pages::changed_pages(&previous_pages, hashes));
+                    previous_pages = page_hashes.clone().unwrap_or_default();
+                    let (payload, pdf_encoding) = Encoding::maybe_compress(compression, body.as_deref().unwrap_or(&pdf_data));
+                    let payload = general_purpose::STANDARD.encode(&payload);
+                    let (pdf, pdf_delta) = if delta_base.is_some() { (None, Some(payload)) } else { (Some(payload), None) };
                     let _ = socket.send(Message::Text(serde_json::json!({
                         "type": "compile_success",
                         "compile_time_ms": duration,
-                        "pdf": general_purpose::STANDARD.encode(&pdf_data),
-                        "blobs": uploaded_hashes
+                        "cache": cache_status,
+                        "output_hash": output_hash,
+                        "pdf": pdf,
+                        "pdf_delta": pdf_delta,
+                        "delta_base": delta_base,
+                        "page_hashes": page_hashes,
+                        "changed_pages": changed_pages,
+                        "pdf_encoding": pdf_encoding.map(|e| e.as_str()),
+                        "blobs": uploaded_hashes,
+                        "warnings": warnings,
+                        "resource_usage": usage
                     }).to_string())).await;
                 }
                 Err(e) => {
                     error!("Compilation failed logs:\n{}", logs); // Log raw output for debugging
-                    let parsed = parse_log_errors(&logs);
+                    let mut parsed = parse_log_errors(&logs);
+                    attach_source_context(&mut parsed, temp_dir.path());
+                    attach_explanations(&mut parsed, language);
+                    let tutor = project.tutor.then(|| Tutor::report(&e, &parsed, language));
                     let response = serde_json::json!({
                         "type": "compile_error",
+                        "cache": cache_status,
                         "error": e.to_string(),
                         "logs": logs,
-                        "details": parsed
+                        "details": parsed,
+                        "tutor": tutor,
+                        "warnings": warnings,
+                        "resource_usage": usage
                     });
                     let _ = socket.send(Message::Text(response.to_string())).await;
                 }
@@ -343,72 +3996,91 @@ pub async fn handle_socket(mut socket: WebSocket, state: AppState) {
 // ============================================================================
 
 
-fn parse_log_errors(log: &str) -> Vec<serde_json::Value> {
-    let mut errors = Vec::new();
-    // Match structure: [Error] filename.tex:9: Message...
-    let direct_regex = Regex::new(r"^\[Error\] ([^:]+):(\d+): (.*)").unwrap();
-    
-    // Match standard TeX errors "! ..." AND Tectonic "error: ..."
-    let error_regex = Regex::new(r"^(?:!|error:)(.*)").unwrap();
-    let line_regex = Regex::new(r"^l\.(\d+)(.*)").unwrap();
-    let file_regex = Regex::new(r"\(([^)\n]+\.(?:tex|sty|cls))").unwrap();
-    
-    let lines: Vec<&str> = log.lines().collect();
-    
-    for (i, line) in lines.iter().enumerate() {
-        // 1. Try Direct Pattern (Best Quality)
-        if let Some(caps) = direct_regex.captures(line) {
-            let file = caps.get(1).unwrap().as_str().trim().to_string();
-            let line_num: u32 = caps.get(2).unwrap().as_str().parse().unwrap_or(0);
-            let message = caps.get(3).unwrap().as_str().trim().to_string();
-
+/// The errors of a compile log, as JSON objects with the `file` ("unknown" when the log
+/// doesn't say), `line`, `column`, `context` and raising `package` TeX reported.
+pub(crate) fn parse_log_errors(log: &str) -> Vec<serde_json::Value> {
+    SelfHealer::diagnostics(log)
+        .into_iter()
+        .filter(|entry| entry.category == texlog::Category::Error)
+        .map(|entry| {
+            let suggestion = SelfHealer::suggestion(&entry.message);
             let mut error_obj = serde_json::Map::new();
-            error_obj.insert("file".to_string(), serde_json::Value::String(file));
-            error_obj.insert("line".to_string(), serde_json::Value::Number(serde_json::Number::from(line_num)));
-            error_obj.insert("message".to_string(), serde_json::Value::String(message));
-            
-            errors.push(serde_json::Value::Object(error_obj));
+            error_obj.insert("message".to_string(), serde_json::Value::String(entry.message));
+            error_obj.insert("file".to_string(), serde_json::Value::String(entry.file.unwrap_or_else(|| "unknown".to_string())));
+            if let Some(line) = entry.line {
+                error_obj.insert("line".to_string(), serde_json::Value::from(line));
+            }
+            if let Some(column) = entry.column {
+                error_obj.insert("column".to_string(), serde_json::Value::from(column));
+            }
+            if let Some(context) = entry.context {
+                error_obj.insert("context".to_string(), serde_json::Value::String(context));
+            }
+            if let Some(package) = entry.package {
+                error_obj.insert("package".to_string(), serde_json::Value::String(package));
+            }
+            if let Some(suggestion) = suggestion {
+                error_obj.insert("suggestion".to_string(), serde_json::Value::String(suggestion));
+            }
+            serde_json::Value::Object(error_obj)
+        })
+        .collect()
+}
+
+/// Adds a `snippet` with the surrounding source lines to every parsed error whose
+/// file can be found inside the workspace.
+fn attach_source_context(errors: &mut [serde_json::Value], workspace: &Path) {
+    for error in errors.iter_mut() {
+        let obj = match error.as_object_mut() {
+            Some(obj) => obj,
+            None => continue,
+        };
+        let (file, line) = match (obj.get("file").and_then(|f| f.as_str()), obj.get("line").and_then(|l| l.as_u64())) {
+            (Some(file), Some(line)) => (file.trim_start_matches("./").to_string(), line as u32),
+            _ => continue,
+        };
+        // Log paths are untrusted: never read outside the workspace
+        if Path::new(&file).is_absolute() || file.split('/').any(|c| c == "..") {
             continue;
         }
+        if let Ok(content) = fs::read_to_string(workspace.join(&file)) {
+            let column = obj.get("column").and_then(|c| c.as_u64());
+            obj.insert("snippet".to_string(), source_snippet(&content, line, column));
+        }
+    }
+}
 
-        // 2. Try Standard TeX Pattern (Fallback)
-        if let Some(caps) = error_regex.captures(line) {
-            let message = caps.get(1).unwrap().as_str().trim().to_string();
-            // Ignore generic "halted" messages which aren't specific errors
-            if message.contains("halted on potentially-recoverable error") { continue; }
-
-            let mut error_obj = serde_json::Map::new();
-            error_obj.insert("message".to_string(), serde_json::Value::String(message));
-            
-            // Look ahead for line number (heuristic: next 10 lines)
-            for j in i+1..std::cmp::min(i + 10, lines.len()) {
-                if let Some(l_caps) = line_regex.captures(lines[j]) {
-                    if let Ok(line_num) = l_caps.get(1).unwrap().as_str().parse::<u32>() {
-                         error_obj.insert("line".to_string(), serde_json::Value::Number(serde_json::Number::from(line_num)));
-                         let context = l_caps.get(2).map(|m| m.as_str().trim().to_string()).unwrap_or_default();
-                         error_obj.insert("context".to_string(), serde_json::Value::String(context));
-                    }
-                    break;
-                }
-            }
-            
-            // Look backwards for filename (heuristic: find last file opening pattern)
-            let mut found_file = "unknown".to_string();
-            for j in (0..i).rev() {
-                if let Some(f_caps) = file_regex.captures(lines[j]) {
-                    let mut possible_file = f_caps.get(1).unwrap().as_str().to_string();
-                     if let Some(idx) = possible_file.find(' ') {
-                        possible_file = possible_file[..idx].to_string();
-                    }
-                    found_file = possible_file;
-                    break;
-                }
+/// Adds a localized `explanation` to every parsed error the catalog recognizes.
+fn attach_explanations(errors: &mut [serde_json::Value], language: &str) {
+    for error in errors.iter_mut() {
+        let explanation = error.get("message")
+            .and_then(|m| m.as_str())
+            .and_then(|m| ErrorExplainer::explain(m, language));
+        if let (Some(obj), Some(explanation)) = (error.as_object_mut(), explanation) {
+            if let Ok(value) = serde_json::to_value(explanation) {
+                obj.insert("explanation".to_string(), value);
             }
-            error_obj.insert("file".to_string(), serde_json::Value::String(found_file));
-            
-            errors.push(serde_json::Value::Object(error_obj));
         }
     }
-    
-    errors
 }
+
+fn source_snippet(content: &str, line: u32, column: Option<u64>) -> serde_json::Value {
+    let first = line.saturating_sub(SNIPPET_CONTEXT_LINES).max(1);
+    let last = line + SNIPPET_CONTEXT_LINES;
+    let lines: Vec<serde_json::Value> = content.lines()
+        .enumerate()
+        .map(|(idx, text)| (idx as u32 + 1, text))
+        .filter(|(n, _)| *n >= first && *n <= last)
+        .map(|(n, text)| serde_json::json!({
+            "line": n,
+            "text": text,
+            "error": n == line,
+        }))
+        .collect();
+
+    serde_json::json!({
+        "lines": lines,
+        "column": column,
+    })
+}
+
@@ -1,5 +1,34 @@
 use regex::Regex;
+use std::collections::HashMap;
+use std::sync::OnceLock;
 use tracing::info;
+use xxhash_rust::xxh64::xxh64;
+
+use crate::settings::Settings;
+use crate::texlog::{self, LogEntry};
+
+/// Names of the fixes [`SelfHealer::heal`] can apply. Each can be turned off on its own.
+pub const RULES: &[&str] = &["missing_end_document", "undefined_command", "undefined_environment", "unbalanced_brace"];
+
+/// Environments of well-known packages, which are suggested rather than stubbed out: an
+/// empty `align` would only trade the error for worse ones.
+pub const ENVIRONMENT_PACKAGES: &[(&str, &str)] = &[
+    ("align", "amsmath"), ("align*", "amsmath"), ("gather", "amsmath"), ("gather*", "amsmath"),
+    ("multline", "amsmath"), ("multline*", "amsmath"), ("alignat", "amsmath"), ("flalign", "amsmath"),
+    ("equation*", "amsmath"), ("split", "amsmath"), ("cases", "amsmath"), ("pmatrix", "amsmath"),
+    ("bmatrix", "amsmath"), ("vmatrix", "amsmath"), ("proof", "amsthm"), ("tikzpicture", "tikz"),
+    ("axis", "pgfplots"), ("lstlisting", "listings"), ("minted", "minted"), ("algorithm", "algorithm"),
+    ("algorithmic", "algpseudocode"), ("subfigure", "subcaption"), ("longtable", "longtable"),
+    ("tabularx", "tabularx"), ("wrapfigure", "wrapfig"), ("multicols", "multicol"), ("landscape", "pdflscape"),
+    ("sidewaysfigure", "rotating"), ("sidewaystable", "rotating"), ("tcolorbox", "tcolorbox"),
+    ("mdframed", "mdframed"), ("comment", "comment"), ("spacing", "setspace"), ("CJK", "CJK"),
+];
+
+/// Matches "LaTeX Error: Environment foo undefined." capturing the environment name.
+fn undefined_environment() -> &'static Regex {
+    static UNDEFINED_ENVIRONMENT: OnceLock<Regex> = OnceLock::new();
+    UNDEFINED_ENVIRONMENT.get_or_init(|| Regex::new(r"Environment ([A-Za-z@*]+) undefined").unwrap())
+}
 
 /// A list of common LaTeX commands that should never be patched.
 /// These are core commands that, if "undefined", indicate a deeper problem.
@@ -25,23 +54,161 @@ const PROTECTED_COMMANDS: &[&str] = &[
     "tiny", "scriptsize", "footnotesize", "small", "normalsize", "large", "Large", "LARGE", "huge", "Huge",
 ];
 
+/// Prefix of the log line listing the fixes applied by a healing attempt.
+const FIXES_MARKER: &str = "[Self-Healing] Applied fixes: ";
+
+/// Prefix of the log line holding a [`SourceMap`] of the main file (of a `\tachyontable`
+/// expansion, or of a healing ahead of the retry's log); the log after it numbers the
+/// file as mapped.
+const SOURCE_MAP_MARKER: &str = "[Tachyon] Source map: ";
+
+/// The outcome of a healing attempt that applied fixes.
+pub struct Healing {
+    pub content: String,
+    pub fixes: Vec<&'static str>,
+    pub source_map: SourceMap,
+}
+
+/// The lines a healing (or `\tachyontable` expansion) inserted, so line numbers in the
+/// rewritten file can be given in the user's original source.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SourceMap {
+    /// (line the text went into, lines it added), numbered as the content was at the time
+    insertions: Vec<(u32, u32)>,
+}
+
+impl SourceMap {
+    /// Inserts `text` into `content` at byte `pos`, recording the lines it adds.
+    pub(crate) fn insert(&mut self, content: &mut String, pos: usize, text: &str) {
+        self.replace(content, pos..pos, text);
+    }
+
+    /// Replaces `range` of `content` with `text`, recording the lines it adds.
+    pub(crate) fn replace(&mut self, content: &mut String, range: std::ops::Range<usize>, text: &str) {
+        let line = content[..range.start].matches('\n').count() as u32 + 1;
+        let added = (text.matches('\n').count() as u32).saturating_sub(content[range.clone()].matches('\n').count() as u32);
+        content.replace_range(range, text);
+        if added > 0 {
+            self.insertions.push((line, added));
+        }
+    }
+
+    /// The original line of `line` in the healed file. Inserted lines belong to the line
+    /// they were inserted into.
+    pub fn original_line(&self, line: u32) -> u32 {
+        self.insertions.iter().rev().fold(line, |line, &(at, added)| {
+            if line > at + added {
+                line - added
+            } else {
+                line.min(at)
+            }
+        })
+    }
+
+    /// Log line recording the map of `file`, read back by [`SourceMap::from_log_line`].
+    pub fn log_line(&self, file: &str) -> String {
+        let insertions: Vec<String> = self.insertions.iter().map(|(at, added)| format!("{}+{}", at, added)).collect();
+        format!("{}{} {}\n", SOURCE_MAP_MARKER, file, insertions.join(" "))
+    }
+
+    /// The file and map of a line written by [`SourceMap::log_line`].
+    fn from_log_line(line: &str) -> Option<(String, Self)> {
+        let mut parts = line.strip_prefix(SOURCE_MAP_MARKER)?.split_whitespace();
+        let file = parts.next()?.to_string();
+        let insertions = parts
+            .map(|part| {
+                let (at, added) = part.split_once('+')?;
+                Some((at.parse().ok()?, added.parse().ok()?))
+            })
+            .collect::<Option<Vec<_>>>()?;
+        Some((file, Self { insertions }))
+    }
+}
+
+/// The enabled rules, in [`RULES`] order; `None` when all of them are.
+fn enabled_rules(enabled: impl Fn(&str) -> bool) -> Option<Vec<String>> {
+    let rules: Vec<String> = RULES.iter().filter(|rule| enabled(rule)).map(|rule| rule.to_string()).collect();
+    (rules.len() < RULES.len()).then_some(rules)
+}
+
+/// Parses a request's comma-separated rules: rule names, `all` or `none`. `None` when
+/// all rules are enabled.
+pub fn parse_rules(list: &str) -> Result<Option<Vec<String>>, String> {
+    let names: Vec<&str> = list.split(',').map(str::trim).filter(|name| !name.is_empty()).collect();
+    if names == ["all"] {
+        return Ok(None);
+    }
+    if let Some(unknown) = names.iter().find(|name| **name != "none" && !RULES.contains(name)) {
+        return Err(format!("Unknown healer rule '{}'; use all, none or any of {}", unknown, RULES.join(", ")));
+    }
+    Ok(enabled_rules(|rule| names.contains(&rule)))
+}
+
+/// Rules the tenant's compiles may apply: its TENANT_HEALER_RULES if it is listed there,
+/// HEALER_RULES otherwise. `None` when all of them.
+pub fn allowed_rules(settings: &Settings, tenant: Option<&str>) -> Option<Vec<String>> {
+    let own: Vec<&str> = settings.tenant_healer_rules.iter()
+        .filter(|(name, _)| Some(name.as_str()) == tenant)
+        .map(|(_, rule)| rule.as_str())
+        .collect();
+    let names: Vec<&str> = if own.is_empty() { settings.healer_rules.iter().map(String::as_str).collect() } else { own };
+    enabled_rules(|rule| names.contains(&rule))
+}
+
+/// Rules both `requested` and `allowed` enable.
+pub fn intersect(requested: Option<&[String]>, allowed: Option<&[String]>) -> Option<Vec<String>> {
+    let enables = |rules: Option<&[String]>, rule: &str| rules.is_none_or(|rules| rules.iter().any(|r| r == rule));
+    enabled_rules(|rule| enables(requested, rule) && enables(allowed, rule))
+}
+
+/// Cache seeds of the healing policies, so a PDF healed under one is not served under
+/// another: every tenant of TENANT_HEALER_RULES, and `None` for HEALER_RULES unless it
+/// enables all rules.
+pub fn policy_seeds(settings: &Settings) -> HashMap<Option<String>, u64> {
+    let tenants = settings.tenant_healer_rules.iter().map(|(tenant, _)| Some(tenant.clone())).chain([None]);
+    tenants
+        .filter_map(|tenant| {
+            let rules = allowed_rules(settings, tenant.as_deref());
+            if tenant.is_none() && rules.is_none() {
+                return None;
+            }
+            let key = rules.map(|rules| rules.join(",")).unwrap_or_else(|| "all".to_string());
+            Some((tenant, xxh64(key.as_bytes(), 0)))
+        })
+        .collect()
+}
+
 pub struct SelfHealer;
 
 impl SelfHealer {
     /// Attempts to heal common LaTeX errors based on compilation logs.
     /// Returns `Some(fixed_content)` if a fix was applied, `None` otherwise.
     pub fn attempt_heal(content: &str, logs: &str) -> Option<String> {
+        Self::heal(content, logs).map(|healing| healing.content)
+    }
+
+    /// Like [`SelfHealer::attempt_heal`], also returning the names of the applied fixes
+    /// and where they changed the content.
+    pub fn heal(content: &str, logs: &str) -> Option<Healing> {
+        Self::heal_with(content, logs, None)
+    }
+
+    /// Like [`SelfHealer::heal`], applying only the fixes in `rules` (`None`: all of them).
+    pub fn heal_with(content: &str, logs: &str, rules: Option<&[String]>) -> Option<Healing> {
+        let enabled = |rule: &str| rules.is_none_or(|rules| rules.iter().any(|r| r == rule));
         let mut healed = content.to_string();
-        let mut applied_fixes: Vec<&str> = Vec::new();
+        let mut applied_fixes: Vec<&'static str> = Vec::new();
+        let mut source_map = SourceMap::default();
 
         // =========================================================================
         // FIX 1: Missing \end{document}
         // =========================================================================
         // Many "Emergency stop" or EOF errors are caused by a missing \end{document}.
         // This is a very safe fix.
-        if !healed.contains("\\end{document}") && healed.contains("\\begin{document}") {
+        if enabled("missing_end_document") && !healed.contains("\\end{document}") && healed.contains("\\begin{document}") {
             info!("🩹 Self-Healing: Detected missing \\end{{document}}. Appending it.");
-            healed.push_str("\n\\end{document}\n");
+            let end = healed.len();
+            source_map.insert(&mut healed, end, "\n\\end{document}\n");
             applied_fixes.push("missing_end_document");
         }
 
@@ -54,7 +221,7 @@ impl SelfHealer {
         
         let re_undefined_tectonic = Regex::new(r"\[Error\] [^:]+:(\d+): Undefined control sequence").unwrap();
         
-        if let Some(caps) = re_undefined_tectonic.captures(logs) {
+        if let Some(caps) = re_undefined_tectonic.captures(logs).filter(|_| enabled("undefined_command")) {
             if let Ok(line_num) = caps[1].parse::<usize>() {
                 // IMPORTANT: Use the ORIGINAL content for line lookup, since the log refers to the original file.
                 if let Some(line_str) = content.lines().nth(line_num.saturating_sub(1)) {
@@ -86,14 +253,11 @@ impl SelfHealer {
                         
                         // Insert patches BEFORE \begin{document}
                         if let Some(pos) = healed.find("\\begin{document}") {
-                            healed.insert_str(pos, &patches);
+                            source_map.insert(&mut healed, pos, &patches);
                         } else {
                             // Fallback: insert after \documentclass line
-                            if let Some(pos) = healed.find('\n') {
-                                healed.insert_str(pos, &patches);
-                            } else {
-                                healed = format!("{}{}", patches, healed);
-                            }
+                            let pos = healed.find('\n').unwrap_or(0);
+                            source_map.insert(&mut healed, pos, &patches);
                         }
                         applied_fixes.push("undefined_command");
                     }
@@ -102,17 +266,40 @@ impl SelfHealer {
         }
 
         // =========================================================================
-        // FIX 3: Runaway argument (Unbalanced braces)
+        // FIX 3: Undefined environment
+        // =========================================================================
+        // Log pattern: "LaTeX Error: Environment foo undefined." Unknown environments are
+        // defined empty so the document builds; those of a known package are only suggested.
+        if enabled("undefined_environment") {
+            let mut environments: Vec<&str> = Vec::new();
+            for caps in undefined_environment().captures_iter(logs) {
+                let name = caps.get(1).unwrap().as_str();
+                if !environments.contains(&name) && Self::environment_package(name).is_none() {
+                    environments.push(name);
+                }
+            }
+
+            if !environments.is_empty() {
+                let mut patches = String::new();
+                for name in &environments {
+                    info!("🩹 Self-Healing: Defining empty environment '{}'.", name);
+                    patches.push_str(&format!("\n\\newenvironment{{{}}}{{}}{{}}", name));
+                }
+                let pos = healed.find("\\begin{document}").or_else(|| healed.find('\n')).unwrap_or(0);
+                source_map.insert(&mut healed, pos, &patches);
+                applied_fixes.push("undefined_environment");
+            }
+        }
+
+        // =========================================================================
+        // FIX 4: Runaway argument (Unbalanced braces)
         // =========================================================================
         // Log patterns: "Runaway argument?" or "File ended while scanning use of..."
-        if logs.contains("Runaway argument") || logs.contains("File ended while scanning") {
+        if enabled("unbalanced_brace") && (logs.contains("Runaway argument") || logs.contains("File ended while scanning")) {
             info!("🩹 Self-Healing: Detected runaway argument (unbalanced brace?). Appending closing brace.");
             // Insert before \end{document} if it exists, otherwise at end
-            if let Some(pos) = healed.rfind("\\end{document}") {
-                healed.insert_str(pos, "\n}\n");
-            } else {
-                healed.push_str("\n}\n");
-            }
+            let pos = healed.rfind("\\end{document}").unwrap_or(healed.len());
+            source_map.insert(&mut healed, pos, "\n}\n");
             applied_fixes.push("unbalanced_brace");
         }
 
@@ -123,8 +310,64 @@ impl SelfHealer {
             None
         } else {
             info!("🩹 Self-Healing: Applied fixes: {:?}", applied_fixes);
-            Some(healed)
+            Some(Healing { content: healed, fixes: applied_fixes, source_map })
+        }
+    }
+
+    /// Log line recording the applied fixes, read back by [`SelfHealer::report`].
+    pub fn fixes_log_line(fixes: &[&str]) -> String {
+        format!("{}{}\n", FIXES_MARKER, fixes.join(", "))
+    }
+
+    /// Fixes applied during the compile that produced `logs`, if healing was attempted.
+    /// Works on logs returned by remote workers too.
+    pub fn report(logs: &str) -> Option<Vec<String>> {
+        let line = logs.lines().find_map(|line| line.strip_prefix(FIXES_MARKER))?;
+        Some(line.split(", ").map(str::to_string).collect())
+    }
+
+    /// The package that defines `environment`, if it is a well-known one.
+    pub fn environment_package(environment: &str) -> Option<&'static str> {
+        ENVIRONMENT_PACKAGES.iter().find(|(name, _)| *name == environment).map(|(_, package)| *package)
+    }
+
+    /// What to do about an error the healer leaves alone: the package to load for an
+    /// environment of a well-known package.
+    pub fn suggestion(message: &str) -> Option<String> {
+        let environment = undefined_environment().captures(message)?.get(1)?.as_str().to_string();
+        let package = Self::environment_package(&environment)?;
+        Some(format!("Add \\usepackage{{{}}} to the preamble; it defines the {} environment", package, environment))
+    }
+
+    /// The errors, warnings and bad boxes of `logs`. Those reported in the main file after
+    /// a table expansion or healing are given at their lines in the user's original source,
+    /// undoing the latest rewrite first.
+    pub fn diagnostics(logs: &str) -> Vec<LogEntry> {
+        if !logs.contains(SOURCE_MAP_MARKER) {
+            return texlog::parse(logs);
+        }
+        let mut maps: Vec<(String, SourceMap)> = Vec::new();
+        let mut entries = Vec::new();
+        let mut rest = logs;
+        loop {
+            let (segment, next) = rest.split_at(rest.find(SOURCE_MAP_MARKER).unwrap_or(rest.len()));
+            for mut entry in texlog::parse(segment) {
+                for (file, map) in maps.iter().rev() {
+                    let in_main = entry.file.as_deref().is_some_and(|f| f.trim_start_matches("./") == file.trim_start_matches("./"));
+                    if in_main {
+                        entry.line = entry.line.map(|line| map.original_line(line));
+                    }
+                }
+                entries.push(entry);
+            }
+            if next.is_empty() {
+                break;
+            }
+            let (map_line, after) = next.split_once('\n').unwrap_or((next, ""));
+            maps.extend(SourceMap::from_log_line(map_line));
+            rest = after;
         }
+        texlog::merge_duplicates(entries)
     }
 }
 
@@ -158,6 +401,114 @@ Hello World
         assert!(healed.contains("\\providecommand{\\mybrokencommand}"));
     }
 
+    #[test]
+    fn test_report_round_trip() {
+        let fixes = SelfHealer::heal("\\begin{document}\nHi", "Emergency stop").unwrap().fixes;
+        let logs = format!("! Emergency stop\n{}retry log", SelfHealer::fixes_log_line(&fixes));
+        assert_eq!(SelfHealer::report(&logs), Some(vec!["missing_end_document".to_string()]));
+        assert_eq!(SelfHealer::report("! Emergency stop"), None);
+    }
+
+    #[test]
+    fn test_source_map() {
+        let content = "\\documentclass{article}\n\\begin{document}\n\\mybrokencommand\nText";
+        let healing = SelfHealer::heal(content, "[Error] main.tex:3: Undefined control sequence").unwrap();
+        let lines: Vec<&str> = healing.content.lines().collect();
+        assert_eq!(lines[3], "\\mybrokencommand");
+        assert_eq!(lines[5], "\\end{document}");
+
+        let map = &healing.source_map;
+        // Patch lines belong to the \begin{document} line they were inserted before
+        assert_eq!((1..=6).map(|line| map.original_line(line)).collect::<Vec<_>>(), [1, 2, 2, 3, 4, 4]);
+    }
+
+    #[test]
+    fn test_diagnostics_translated() {
+        let healing = SelfHealer::heal("\\begin{document}\n\\foo\n", "[Error] main.tex:2: Undefined control sequence").unwrap();
+        let logs = format!(
+            "[Error] main.tex:2: Undefined control sequence.\n{}{}[Error] main.tex:3: Undefined control sequence.\n[Error] chapter.tex:3: Missing $ inserted.\n",
+            SelfHealer::fixes_log_line(&healing.fixes),
+            healing.source_map.log_line("main.tex"),
+        );
+        let entries = SelfHealer::diagnostics(&logs);
+        let lines: Vec<(Option<&str>, Option<u32>)> = entries.iter().map(|e| (e.file.as_deref(), e.line)).collect();
+        // The retry's error is the one already reported; other files keep their lines
+        assert_eq!(lines, [(Some("main.tex"), Some(2)), (Some("chapter.tex"), Some(3))]);
+    }
+
+    #[test]
+    fn test_diagnostics_through_expansion_and_healing() {
+        // A table expansion added 4 lines after line 2; the healing then one after line 1
+        let mut expansion = SourceMap::default();
+        let mut content = "a\n\\tachyontable{t.csv}\nb\n".to_string();
+        expansion.replace(&mut content, 2..22, "\\begin{tabular}\nx\ny\nz\n\\end{tabular}");
+        assert_eq!(content.lines().count(), 7);
+        let mut healing = SourceMap::default();
+        healing.insert(&mut content, 2, "\\usepackage{x}\n");
+        let logs = format!(
+            "{}[Error] main.tex:4: Misplaced alignment tab character &.\n[Error] main.tex:7: Undefined control sequence.\n{}[Error] main.tex:8: Missing $ inserted.\n",
+            expansion.log_line("main.tex"),
+            healing.log_line("main.tex"),
+        );
+        let lines: Vec<Option<u32>> = SelfHealer::diagnostics(&logs).iter().map(|e| e.line).collect();
+        // Errors inside the table belong to the \tachyontable line
+        assert_eq!(lines, [Some(2), Some(3), Some(3)]);
+    }
+
+    #[test]
+    fn test_rules() {
+        assert_eq!(parse_rules("all"), Ok(None));
+        assert_eq!(parse_rules("unbalanced_brace, missing_end_document,undefined_command,undefined_environment"), Ok(None));
+        assert_eq!(parse_rules("none"), Ok(Some(vec![])));
+        assert_eq!(parse_rules("unbalanced_brace,missing_end_document"), Ok(Some(vec!["missing_end_document".into(), "unbalanced_brace".into()])));
+        assert!(parse_rules("missing_end_document,guess").is_err());
+
+        let content = "\\documentclass{article}\n\\begin{document}\n\\foo\n";
+        let logs = "[Error] main.tex:3: Undefined control sequence";
+        let healing = SelfHealer::heal_with(content, logs, Some(&["undefined_command".to_string()])).unwrap();
+        assert_eq!(healing.fixes, ["undefined_command"]);
+        assert!(!healing.content.contains("\\end{document}"));
+        assert!(SelfHealer::heal_with(content, logs, Some(&[])).is_none());
+    }
+
+    #[test]
+    fn test_policy() {
+        let settings = Settings {
+            healer_rules: vec!["missing_end_document".into(), "undefined_command".into()],
+            tenant_healer_rules: vec![("ci".into(), "none".into()), ("docs".into(), "missing_end_document".into()), ("docs".into(), "unbalanced_brace".into())],
+            ..Settings::from_env()
+        };
+        assert_eq!(allowed_rules(&settings, None), Some(vec!["missing_end_document".into(), "undefined_command".into()]));
+        assert_eq!(allowed_rules(&settings, Some("ci")), Some(vec![]));
+        assert_eq!(allowed_rules(&settings, Some("docs")), Some(vec!["missing_end_document".into(), "unbalanced_brace".into()]));
+
+        // A request only narrows what the tenant allows
+        let requested = vec!["undefined_command".to_string(), "unbalanced_brace".to_string()];
+        assert_eq!(intersect(Some(&requested), allowed_rules(&settings, Some("docs")).as_deref()), Some(vec!["unbalanced_brace".into()]));
+        assert_eq!(intersect(None, None), None);
+
+        let seeds = policy_seeds(&settings);
+        assert_eq!(seeds.len(), 3);
+        assert_ne!(seeds[&Some("ci".to_string())], seeds[&None]);
+        assert!(policy_seeds(&Settings { healer_rules: RULES.iter().map(|r| r.to_string()).collect(), tenant_healer_rules: vec![], ..settings }).is_empty());
+    }
+
+    #[test]
+    fn test_undefined_environment() {
+        let content = "\\documentclass{article}\n\\begin{document}\n\\begin{keypoints}\nA\n\\end{keypoints}\n\\begin{align}x\\end{align}\n\\end{document}\n";
+        let logs = "[Error] main.tex:3: LaTeX Error: Environment keypoints undefined.\n! LaTeX Error: Environment keypoints undefined.\n! LaTeX Error: Environment align undefined.\n";
+        let healing = SelfHealer::heal(content, logs).unwrap();
+        assert_eq!(healing.fixes, ["undefined_environment"]);
+        assert_eq!(healing.content.matches("\\newenvironment{keypoints}{}{}").count(), 1);
+        // align belongs to amsmath: an empty definition would break its contents
+        assert!(!healing.content.contains("\\newenvironment{align}"));
+        assert_eq!((healing.source_map.original_line(3), healing.source_map.original_line(4)), (2, 3));
+
+        assert_eq!(SelfHealer::suggestion("LaTeX Error: Environment align undefined.").as_deref(), Some("Add \\usepackage{amsmath} to the preamble; it defines the align environment"));
+        assert_eq!(SelfHealer::suggestion("LaTeX Error: Environment keypoints undefined."), None);
+        assert!(SelfHealer::heal_with(content, logs, Some(&["undefined_command".to_string()])).is_none());
+    }
+
     #[test]
     fn test_protected_command_not_patched() {
         let content = r#"\documentclass{article}
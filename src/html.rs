@@ -0,0 +1,602 @@
+//! Converts the HTML subset produced by rich-text editors and CMSs (headings, paragraphs,
+//! lists, tables, images, links and inline styles) into a LaTeX fragment for templates.
+
+use regex::Regex;
+use std::collections::BTreeSet;
+
+use crate::latex::{escape, url_argument};
+
+/// Nesting beyond this is rejected rather than recursed into.
+const MAX_DEPTH: usize = 200;
+
+const VOID_TAGS: &[&str] = &["br", "img", "hr", "wbr", "col", "meta", "link", "input", "source"];
+
+/// Elements whose content is never rendered.
+const DROPPED_TAGS: &[&str] = &["script", "style", "head", "title", "template", "noscript", "iframe", "object"];
+
+const BLOCK_TAGS: &[&str] = &[
+    "p", "div", "section", "article", "header", "footer", "main", "aside", "nav", "body", "html",
+    "h1", "h2", "h3", "h4", "h5", "h6", "ul", "ol", "li", "table", "blockquote", "pre", "hr", "figure", "figcaption",
+];
+
+const HEADINGS: [&str; 6] = ["section", "subsection", "subsubsection", "paragraph", "subparagraph", "subparagraph"];
+
+#[derive(Debug)]
+enum Node {
+    Text(String),
+    Element { tag: String, attrs: Vec<(String, String)>, children: Vec<Node> },
+}
+
+impl Node {
+    fn is_block(&self) -> bool {
+        matches!(self, Node::Element { tag, .. } if BLOCK_TAGS.contains(&tag.as_str()))
+    }
+}
+
+/// The converted fragment and what a document needs to compile it.
+#[derive(Debug, Default)]
+pub struct Conversion {
+    pub latex: String,
+    /// `\usepackage` lines the fragment relies on
+    pub preamble: Vec<String>,
+    /// Image paths referenced by `\includegraphics`, to upload next to the document
+    pub images: Vec<String>,
+    /// Content that was dropped or simplified
+    pub warnings: Vec<String>,
+}
+
+impl Conversion {
+    /// Wraps the fragment into a complete article.
+    pub fn to_document(&self) -> String {
+        let mut preamble = self.preamble.join("\n");
+        if !preamble.is_empty() {
+            preamble.push('\n');
+        }
+        format!("\\documentclass{{article}}\n{}\\begin{{document}}\n{}\\end{{document}}\n", preamble, self.latex)
+    }
+}
+
+pub struct HtmlConverter {
+    numbered: bool,
+    packages: BTreeSet<&'static str>,
+    images: Vec<String>,
+    warnings: BTreeSet<String>,
+}
+
+impl HtmlConverter {
+    /// Converts `html`; headings become starred (unnumbered) sections unless `numbered`.
+    pub fn convert(html: &str, numbered: bool) -> Result<Conversion, String> {
+        let nodes = parse(html)?;
+        let mut converter = Self { numbered, packages: BTreeSet::new(), images: Vec::new(), warnings: BTreeSet::new() };
+        let mut latex = converter.flow(&nodes);
+        if !latex.is_empty() {
+            latex.push('\n');
+        }
+        let preamble = converter.packages.iter()
+            .map(|p| match *p {
+                "ulem" => "\\usepackage[normalem]{ulem}".to_string(),
+                p => format!("\\usepackage{{{}}}", p),
+            })
+            .collect();
+        Ok(Conversion { latex, preamble, images: converter.images, warnings: converter.warnings.into_iter().collect() })
+    }
+
+    /// Renders a mix of block and inline nodes: runs of inline content become paragraphs.
+    fn flow(&mut self, nodes: &[Node]) -> String {
+        let mut blocks = Vec::new();
+        let mut run = String::new();
+        for node in nodes {
+            if node.is_block() {
+                push_paragraph(&mut blocks, &mut run);
+                let block = self.block(node);
+                if !block.trim().is_empty() {
+                    blocks.push(block);
+                }
+            } else {
+                run.push_str(&self.inline(node));
+            }
+        }
+        push_paragraph(&mut blocks, &mut run);
+        blocks.join("\n\n")
+    }
+
+    fn block(&mut self, node: &Node) -> String {
+        let Node::Element { tag, attrs, children } = node else {
+            return self.inline(node);
+        };
+        match tag.as_str() {
+            h if h.len() == 2 && h.starts_with('h') && h.as_bytes()[1].is_ascii_digit() => {
+                let level = h[1..].parse::<usize>().unwrap_or(1).clamp(1, 6);
+                let star = if self.numbered { "" } else { "*" };
+                format!("\\{}{}{{{}}}", HEADINGS[level - 1], star, self.inlines(children).trim())
+            }
+            "ul" | "ol" => {
+                let env = if tag == "ul" { "itemize" } else { "enumerate" };
+                let mut items = String::new();
+                for child in children {
+                    match child {
+                        Node::Element { tag, children, .. } if tag == "li" => {
+                            items.push_str(&format!("\\item {}\n", self.flow(children)));
+                        }
+                        Node::Text(text) if text.trim().is_empty() => {}
+                        other => items.push_str(&format!("\\item {}\n", self.flow(std::slice::from_ref(other)))),
+                    }
+                }
+                if items.is_empty() {
+                    return String::new();
+                }
+                format!("\\begin{{{env}}}\n{items}\\end{{{env}}}")
+            }
+            "li" => format!("\\begin{{itemize}}\n\\item {}\n\\end{{itemize}}", self.flow(children)),
+            "table" => self.table(children),
+            "blockquote" => format!("\\begin{{quote}}\n{}\n\\end{{quote}}", self.flow(children)),
+            "pre" => {
+                let mut text = String::new();
+                raw_text(children, &mut text);
+                let text = text.strip_prefix('\n').unwrap_or(&text).trim_end().replace("\\end{verbatim}", "\\end {verbatim}");
+                format!("\\begin{{verbatim}}\n{}\n\\end{{verbatim}}", text)
+            }
+            "hr" => "\\noindent\\rule{\\linewidth}{0.4pt}".to_string(),
+            "figure" => {
+                let (captions, content): (Vec<&Node>, Vec<&Node>) = children.iter()
+                    .partition(|c| matches!(c, Node::Element { tag, .. } if tag == "figcaption"));
+                let mut body = String::new();
+                for node in content {
+                    body.push_str(&self.inline(node));
+                }
+                let caption: String = captions.iter().map(|c| match c {
+                    Node::Element { children, .. } => self.inlines(children),
+                    _ => String::new(),
+                }).collect();
+                let caption = if caption.trim().is_empty() { String::new() } else { format!("\\caption{{{}}}\n", caption.trim()) };
+                format!("\\begin{{figure}}[htbp]\n\\centering\n{}\n{}\\end{{figure}}", body.trim(), caption)
+            }
+            "figcaption" => self.inlines(children).trim().to_string(),
+            _ => {
+                let content = self.flow(children);
+                let (content, align) = self.styled(attrs, content, true);
+                match align.as_deref() {
+                    Some("center") => format!("\\begin{{center}}\n{}\n\\end{{center}}", content),
+                    Some("right") => format!("\\begin{{flushright}}\n{}\n\\end{{flushright}}", content),
+                    _ => content,
+                }
+            }
+        }
+    }
+
+    fn inlines(&mut self, nodes: &[Node]) -> String {
+        nodes.iter().map(|n| self.inline(n)).collect()
+    }
+
+    fn inline(&mut self, node: &Node) -> String {
+        let (tag, attrs, children) = match node {
+            Node::Text(text) => return collapse_whitespace(&escape(text)).replace('\u{A0}', "~"),
+            Node::Element { tag, attrs, children } => (tag.as_str(), attrs, children),
+        };
+        if node.is_block() {
+            return format!(" {} ", self.flow(std::slice::from_ref(node)));
+        }
+        let wrap = |command: &str, content: String| format!("\\{}{{{}}}", command, content);
+        let content = self.inlines(children);
+        let content = match tag {
+            "strong" | "b" => wrap("textbf", content),
+            "em" | "i" | "cite" | "dfn" => wrap("emph", content),
+            "u" | "ins" => wrap("underline", content),
+            "code" | "kbd" | "samp" | "tt" | "var" => wrap("texttt", content),
+            "sub" => wrap("textsubscript", content),
+            "sup" => wrap("textsuperscript", content),
+            "small" => format!("{{\\small {}}}", content),
+            "s" | "del" | "strike" => {
+                self.packages.insert("ulem");
+                wrap("sout", content)
+            }
+            "br" => "\\\\\n".to_string(),
+            "img" => self.image(attrs),
+            "a" => match attr(attrs, "href") {
+                Some(url) if !url.starts_with('#') && !url.trim_start().to_ascii_lowercase().starts_with("javascript:") => {
+                    self.packages.insert("hyperref");
+                    format!("\\href{{{}}}{{{}}}", url_argument(url), content)
+                }
+                _ => content,
+            },
+            "span" | "font" | "mark" | "abbr" | "q" | "label" => content,
+            other => {
+                self.warnings.insert(format!("Unsupported <{}> rendered as its content", other));
+                content
+            }
+        };
+        let content = match (tag, attr(attrs, "color")) {
+            ("font", Some(color)) => match hex_color(color) {
+                Some(hex) => {
+                    self.packages.insert("xcolor");
+                    format!("\\textcolor[HTML]{{{}}}{{{}}}", hex, content)
+                }
+                None => content,
+            },
+            _ => content,
+        };
+        self.styled(attrs, content, false).0
+    }
+
+    /// Applies the `style` attribute: bold, italic, underline and color. Returns the
+    /// wrapped content and any `text-align`, which only blocks use.
+    fn styled(&mut self, attrs: &[(String, String)], mut content: String, block: bool) -> (String, Option<String>) {
+        let mut align = None;
+        let Some(style) = attr(attrs, "style") else {
+            return (content, align);
+        };
+        for declaration in style.split(';') {
+            let Some((property, value)) = declaration.split_once(':') else { continue };
+            let (property, value) = (property.trim().to_ascii_lowercase(), value.trim().to_ascii_lowercase());
+            match property.as_str() {
+                "font-weight" if value == "bold" || value == "bolder" || value.parse::<u32>().is_ok_and(|w| w >= 600) => {
+                    content = if block { format!("{{\\bfseries {}}}", content) } else { format!("\\textbf{{{}}}", content) };
+                }
+                "font-style" if value == "italic" || value == "oblique" => {
+                    content = if block { format!("{{\\itshape {}}}", content) } else { format!("\\emph{{{}}}", content) };
+                }
+                "text-decoration" | "text-decoration-line" if value.contains("underline") && !block => {
+                    content = format!("\\underline{{{}}}", content);
+                }
+                "color" => match hex_color(&value) {
+                    Some(hex) => {
+                        self.packages.insert("xcolor");
+                        content = if block { format!("{{\\color[HTML]{{{}}}{}}}", hex, content) } else { format!("\\textcolor[HTML]{{{}}}{{{}}}", hex, content) };
+                    }
+                    None => {
+                        self.warnings.insert(format!("Ignored color '{}' (only hex colors are supported)", value));
+                    }
+                },
+                "text-align" => align = Some(value),
+                _ => {}
+            }
+        }
+        (content, align)
+    }
+
+    fn image(&mut self, attrs: &[(String, String)]) -> String {
+        let Some(src) = attr(attrs, "src").map(str::trim) else {
+            return String::new();
+        };
+        let local = !src.contains(':') && !src.starts_with('/') && !src.split('/').any(|p| p == "..")
+            && !src.chars().any(|c| matches!(c, '\\' | '{' | '}' | '%' | '#' | '$' | '&' | '^' | '~') || c.is_whitespace());
+        if !local {
+            self.warnings.insert(format!("Skipped image '{}' (only relative paths to uploaded files are supported)", src.chars().take(80).collect::<String>()));
+            return String::new();
+        }
+        self.packages.insert("graphicx");
+        if !self.images.iter().any(|i| i == src) {
+            self.images.push(src.to_string());
+        }
+        let width = attr(attrs, "width").map(str::trim).and_then(|w| {
+            if let Some(percent) = w.strip_suffix('%') {
+                let fraction = percent.trim().parse::<f64>().ok()?.clamp(1.0, 100.0) / 100.0;
+                Some(format!("{}\\linewidth", fraction))
+            } else {
+                // CSS pixels are 1/96 in, 0.75bp
+                let px = w.strip_suffix("px").unwrap_or(w).trim().parse::<f64>().ok()?;
+                Some(format!("{}bp", (px * 0.75).round()))
+            }
+        });
+        match width {
+            Some(width) => format!("\\includegraphics[width={}]{{{}}}", width, src),
+            None => format!("\\includegraphics[width=\\linewidth,keepaspectratio]{{{}}}", src),
+        }
+    }
+
+    fn table(&mut self, children: &[Node]) -> String {
+        let mut rows: Vec<(bool, &Vec<Node>)> = Vec::new();
+        let mut caption = String::new();
+        collect_rows(children, false, &mut rows);
+        for child in children {
+            if let Node::Element { tag, children, .. } = child {
+                if tag == "caption" {
+                    caption = self.inlines(children).trim().to_string();
+                }
+            }
+        }
+
+        let mut columns = 0;
+        let mut align: Vec<char> = Vec::new();
+        let mut lines = Vec::new();
+        let mut header_rows = 0;
+        for (in_head, cells) in &rows {
+            let mut rendered = Vec::new();
+            let mut width = 0;
+            let mut all_th = true;
+            for cell in cells.iter() {
+                let Node::Element { tag, attrs, children } = cell else { continue };
+                if tag != "td" && tag != "th" {
+                    continue;
+                }
+                all_th &= tag == "th";
+                let span = attr(attrs, "colspan").and_then(|s| s.parse::<usize>().ok()).unwrap_or(1).clamp(1, 64);
+                let cell_align = attr(attrs, "align").map(str::to_string)
+                    .or_else(|| attr(attrs, "style").and_then(|s| s.split(';').find_map(|d| {
+                        let (p, v) = d.split_once(':')?;
+                        (p.trim() == "text-align").then(|| v.trim().to_string())
+                    })))
+                    .and_then(|a| match a.as_str() {
+                        "center" => Some('c'),
+                        "right" => Some('r'),
+                        "left" => Some('l'),
+                        _ => None,
+                    });
+                if align.len() < width + 1 {
+                    align.resize(width + 1, 'l');
+                }
+                if let (Some(a), 1) = (cell_align, span) {
+                    align[width] = a;
+                }
+                let content = self.flow(children).replace("\n\n", " ");
+                rendered.push(if span > 1 { format!("\\multicolumn{{{}}}{{{}}}{{{}}}", span, cell_align.unwrap_or('l'), content) } else { content });
+                width += span;
+            }
+            if rendered.is_empty() {
+                continue;
+            }
+            if (*in_head || all_th) && header_rows == lines.len() {
+                header_rows += 1;
+            }
+            columns = columns.max(width);
+            lines.push(format!("{} \\\\\n", rendered.join(" & ")));
+        }
+        if lines.is_empty() {
+            return String::new();
+        }
+        align.resize(columns, 'l');
+        self.packages.insert("booktabs");
+
+        let mut body = String::from("\\toprule\n");
+        for (i, line) in lines.iter().enumerate() {
+            if i == header_rows && header_rows > 0 {
+                body.push_str("\\midrule\n");
+            }
+            body.push_str(line);
+        }
+        body.push_str("\\bottomrule\n");
+        let tabular = format!("\\begin{{tabular}}{{{}}}\n{}\\end{{tabular}}", align.iter().collect::<String>(), body);
+        if caption.is_empty() {
+            tabular
+        } else {
+            format!("\\begin{{table}}[htbp]\n\\centering\n\\caption{{{}}}\n{}\n\\end{{table}}", caption, tabular)
+        }
+    }
+}
+
+/// Closes a paragraph run, dropping it when it holds nothing but whitespace and breaks.
+fn push_paragraph(blocks: &mut Vec<String>, run: &mut String) {
+    let mut text = run.trim();
+    while let Some(rest) = text.strip_prefix("\\\\") {
+        text = rest.trim_start();
+    }
+    if !text.is_empty() {
+        blocks.push(text.to_string());
+    }
+    run.clear();
+}
+
+/// Rows of a table in order, looking through `thead`, `tbody` and `tfoot`.
+fn collect_rows<'a>(nodes: &'a [Node], in_head: bool, rows: &mut Vec<(bool, &'a Vec<Node>)>) {
+    for node in nodes {
+        if let Node::Element { tag, children, .. } = node {
+            match tag.as_str() {
+                "tr" => rows.push((in_head, children)),
+                "thead" => collect_rows(children, true, rows),
+                "tbody" | "tfoot" => collect_rows(children, false, rows),
+                _ => {}
+            }
+        }
+    }
+}
+
+fn raw_text(nodes: &[Node], out: &mut String) {
+    for node in nodes {
+        match node {
+            Node::Text(text) => out.push_str(text),
+            Node::Element { tag, .. } if tag == "br" => out.push('\n'),
+            Node::Element { children, .. } => raw_text(children, out),
+        }
+    }
+}
+
+fn attr<'a>(attrs: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    attrs.iter().find(|(k, _)| k == name).map(|(_, v)| v.as_str())
+}
+
+/// "#1a73e8" or "#abc" as six uppercase hex digits for xcolor's HTML model.
+fn hex_color(value: &str) -> Option<String> {
+    let hex = value.trim().strip_prefix('#')?;
+    if !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    match hex.len() {
+        6 => Some(hex.to_ascii_uppercase()),
+        3 => Some(hex.chars().flat_map(|c| [c, c]).collect::<String>().to_ascii_uppercase()),
+        _ => None,
+    }
+}
+
+fn collapse_whitespace(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut space = false;
+    for c in text.chars() {
+        // Non-breaking spaces are kept, HTML does not collapse them
+        if c.is_whitespace() && c != '\u{A0}' {
+            space = true;
+        } else {
+            if space {
+                out.push(' ');
+                space = false;
+            }
+            out.push(c);
+        }
+    }
+    if space {
+        out.push(' ');
+    }
+    out
+}
+
+/// Decodes character references (`&amp;`, `&#233;`, `&nbsp;`, ...); unknown ones are kept.
+pub fn decode_entities(text: &str) -> String {
+    if !text.contains('&') {
+        return text.to_string();
+    }
+    let re = Regex::new(r"&(#[0-9]{1,7}|#[xX][0-9a-fA-F]{1,6}|[a-zA-Z]+);").unwrap();
+    re.replace_all(text, |caps: &regex::Captures| {
+        let name = &caps[1];
+        let decoded = if let Some(hex) = name.strip_prefix("#x").or_else(|| name.strip_prefix("#X")) {
+            u32::from_str_radix(hex, 16).ok().and_then(char::from_u32)
+        } else if let Some(dec) = name.strip_prefix('#') {
+            dec.parse().ok().and_then(char::from_u32)
+        } else {
+            match name {
+                "amp" => Some('&'), "lt" => Some('<'), "gt" => Some('>'), "quot" => Some('"'), "apos" => Some('\''),
+                "nbsp" => Some('\u{A0}'), "ndash" => Some('–'), "mdash" => Some('—'), "hellip" => Some('…'),
+                "lsquo" => Some('‘'), "rsquo" => Some('’'), "ldquo" => Some('“'), "rdquo" => Some('”'),
+                "laquo" => Some('«'), "raquo" => Some('»'), "copy" => Some('©'), "reg" => Some('®'),
+                "trade" => Some('™'), "euro" => Some('€'), "deg" => Some('°'), "times" => Some('×'),
+                _ => None,
+            }
+        };
+        decoded.map(String::from).unwrap_or_else(|| caps[0].to_string())
+    }).into_owned()
+}
+
+/// Whether an open `open` element ends when a `tag` element starts (`<li>` closes the
+/// previous `<li>`, a block closes an open `<p>`).
+fn implicitly_closed(open: &str, tag: &str) -> bool {
+    match open {
+        "p" => BLOCK_TAGS.contains(&tag) && !matches!(tag, "body" | "html"),
+        "li" => tag == "li",
+        "td" | "th" => matches!(tag, "td" | "th" | "tr"),
+        "tr" => tag == "tr",
+        _ => false,
+    }
+}
+
+/// An element still open while parsing: tag, attributes and children so far.
+type OpenElement = (String, Vec<(String, String)>, Vec<Node>);
+
+/// Parses HTML into a tree, tolerating unclosed and stray tags the way browsers do for
+/// the common cases.
+fn parse(html: &str) -> Result<Vec<Node>, String> {
+    let tag_re = Regex::new(r"^<(/?)([a-zA-Z][a-zA-Z0-9]*)((?:[^>\x22']|\x22[^\x22]*\x22|'[^']*')*)>").unwrap();
+    let attr_re = Regex::new(r#"([a-zA-Z_:][-a-zA-Z0-9_:.]*)(?:\s*=\s*(?:"([^"]*)"|'([^']*)'|([^\s"'=<>`]+)))?"#).unwrap();
+
+    // Open elements; the bottom entry collects the top-level nodes
+    let mut stack: Vec<OpenElement> = vec![(String::new(), Vec::new(), Vec::new())];
+    fn close(stack: &mut Vec<OpenElement>) {
+        if let Some((tag, attrs, children)) = stack.pop() {
+            if let Some(parent) = stack.last_mut() {
+                parent.2.push(Node::Element { tag, attrs, children });
+            }
+        }
+    }
+
+    let mut rest = html;
+    while !rest.is_empty() {
+        if let Some(after) = rest.strip_prefix("<!--") {
+            rest = after.find("-->").map_or("", |end| &after[end + 3..]);
+            continue;
+        }
+        if rest.starts_with("<!") || rest.starts_with("<?") {
+            rest = rest.find('>').map_or("", |end| &rest[end + 1..]);
+            continue;
+        }
+        if let Some(caps) = tag_re.captures(rest) {
+            let whole = caps.get(0).unwrap().as_str();
+            let tag = caps[2].to_ascii_lowercase();
+            let closing = !caps[1].is_empty();
+            let body = caps[3].trim_end();
+            rest = &rest[whole.len()..];
+
+            if closing {
+                if let Some(pos) = stack.iter().rposition(|(open, _, _)| *open == tag) {
+                    if pos > 0 {
+                        while stack.len() > pos {
+                            close(&mut stack);
+                        }
+                    }
+                }
+                continue;
+            }
+            if DROPPED_TAGS.contains(&tag.as_str()) {
+                let end = format!("</{}", tag);
+                rest = rest.to_ascii_lowercase().find(&end)
+                    .map_or("", |at| rest[at..].find('>').map_or("", |gt| &rest[at + gt + 1..]));
+                continue;
+            }
+            while stack.len() > 1 && implicitly_closed(&stack.last().unwrap().0, &tag) {
+                close(&mut stack);
+            }
+            let attrs: Vec<(String, String)> = attr_re.captures_iter(body.trim_end_matches('/'))
+                .map(|a| {
+                    let value = a.get(2).or(a.get(3)).or(a.get(4)).map_or("", |v| v.as_str());
+                    (a[1].to_ascii_lowercase(), decode_entities(value))
+                })
+                .collect();
+            if VOID_TAGS.contains(&tag.as_str()) || body.ends_with('/') {
+                stack.last_mut().unwrap().2.push(Node::Element { tag, attrs, children: Vec::new() });
+            } else {
+                if stack.len() > MAX_DEPTH {
+                    return Err(format!("HTML is nested more than {} levels deep", MAX_DEPTH));
+                }
+                stack.push((tag, attrs, Vec::new()));
+            }
+            continue;
+        }
+        // Text up to the next tag; a '<' that starts no tag is literal
+        let first = rest.chars().next().map_or(0, char::len_utf8);
+        let end = rest[first..].find('<').map_or(rest.len(), |i| i + first);
+        stack.last_mut().unwrap().2.push(Node::Text(decode_entities(&rest[..end])));
+        rest = &rest[end..];
+    }
+    while stack.len() > 1 {
+        close(&mut stack);
+    }
+    Ok(stack.pop().map(|(_, _, children)| children).unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_text_starting_with_multibyte_character() {
+        let out = HtmlConverter::convert("<p>é<b>ü</b>ñ</p>", false).unwrap().latex;
+        assert!(out.contains('é') && out.contains('ü') && out.contains('ñ'), "{}", out);
+    }
+
+    #[test]
+    fn test_blocks_and_inline_styles() {
+        let html = r#"<h2>Q3 &amp; Q4</h2><p>Growth was <strong>50%</strong>, see <a href="https://example.com/a#b">the <em>report</em></a>.<br>Next line
+            <p style="text-align:center; color:#1a73e8">Centered</p>
+            <ul><li>One<li>Two <span style="font-weight:700">bold</span></ul><!-- note --><script>alert(1)</script>"#;
+        let out = HtmlConverter::convert(html, false).unwrap();
+        assert_eq!(out.latex, "\\subsection*{Q3 \\& Q4}\n\nGrowth was \\textbf{50\\%}, see \\href{https://example.com/a\\#b}{the \\emph{report}}.\\\\\nNext line\n\n\\begin{center}\n{\\color[HTML]{1A73E8}Centered}\n\\end{center}\n\n\\begin{itemize}\n\\item One\n\\item Two \\textbf{bold}\n\\end{itemize}\n");
+        assert_eq!(out.preamble, ["\\usepackage{hyperref}", "\\usepackage{xcolor}"]);
+        assert!(out.warnings.is_empty());
+    }
+
+    #[test]
+    fn test_tables_and_images() {
+        let html = r#"<table><caption>Sales</caption><thead><tr><th>Item</th><th align="right">Total</th></tr></thead>
+            <tbody><tr><td>Books</td><td>1,200</td></tr><tr><td colspan="2"><i>none</i></td></tr></tbody></table>
+            <figure><img src="img/chart.png" width="50%"><figcaption>Chart</figcaption></figure><img src="https://x.test/a.png"><marquee>hi</marquee>"#;
+        let out = HtmlConverter::convert(html, true).unwrap();
+        assert!(out.latex.contains("\\caption{Sales}\n\\begin{tabular}{lr}\n\\toprule\nItem & Total \\\\\n\\midrule\nBooks & 1,200 \\\\\n\\multicolumn{2}{l}{\\emph{none}} \\\\\n\\bottomrule\n\\end{tabular}"), "{}", out.latex);
+        assert!(out.latex.contains("\\includegraphics[width=0.5\\linewidth]{img/chart.png}\n\\caption{Chart}"));
+        assert_eq!(out.images, ["img/chart.png"]);
+        assert_eq!(out.warnings.len(), 2);
+        assert!(out.to_document().contains("\\usepackage{booktabs}\n\\usepackage{graphicx}\n\\begin{document}"));
+    }
+
+    #[test]
+    fn test_pre_and_malformed_input() {
+        let out = HtmlConverter::convert("<pre>\nfn main() {\n  x &lt; 1 \\end{verbatim}\n}</pre> a < b </b> <p>", false).unwrap();
+        assert_eq!(out.latex, "\\begin{verbatim}\nfn main() {\n  x < 1 \\end {verbatim}\n}\n\\end{verbatim}\n\na < b\n");
+        assert!(HtmlConverter::convert(&"<div>".repeat(MAX_DEPTH + 5), false).is_err());
+    }
+}
@@ -0,0 +1,200 @@
+//! `impose` post-processing: rearranges the pages of a compiled PDF onto printer sheets.
+//! Each source page becomes a form XObject drawn, scaled and placed, on a new page.
+//!
+//! - `booklet`: spreads for saddle stitching, two pages side by side at full size on a
+//!   sheet twice as wide, ordered so the printed, folded stack reads in sequence; blank
+//!   pages pad the count to a multiple of four.
+//! - `2up`: two pages side by side on a sheet of the document's paper turned sideways.
+//! - `4up`: four pages in a 2×2 grid on a sheet of the document's paper.
+//!
+//! Links, bookmarks and form fields are dropped, since they point into the old pages.
+
+use lopdf::{dictionary, Dictionary, Document, Object, ObjectId, Stream};
+
+pub const LAYOUTS: &[&str] = &["booklet", "2up", "4up"];
+
+/// US Letter, for pages whose MediaBox cannot be read.
+const DEFAULT_MEDIA_BOX: [f32; 4] = [0.0, 0.0, 612.0, 792.0];
+
+/// Imposes `pdf` with one of `LAYOUTS`.
+pub fn impose(pdf: &[u8], layout: &str) -> Result<Vec<u8>, String> {
+    let mut doc = Document::load_mem(pdf).map_err(|e| format!("Failed to parse PDF: {}", e))?;
+    let pages: Vec<ObjectId> = doc.get_pages().into_values().collect();
+    let Some(&first) = pages.first() else { return Err("PDF has no pages".to_string()) };
+    let [x0, y0, x1, y1] = media_box(&doc, first);
+    let (width, height) = (x1 - x0, y1 - y0);
+
+    let forms = pages.iter().map(|&page| page_form(&mut doc, page)).collect::<Result<Vec<_>, _>>()?;
+    // Sheet size, grid and the source pages (None for blank) of each sheet, left to right
+    // and top to bottom
+    let (sheet, columns, rows, order): ((f32, f32), usize, usize, Vec<Option<usize>>) = match layout {
+        "booklet" => ((2.0 * width, height), 2, 1, booklet_order(pages.len())),
+        "2up" => ((height.max(width), height.min(width)), 2, 1, (0..pages.len()).map(Some).collect()),
+        "4up" => ((width, height), 2, 2, (0..pages.len()).map(Some).collect()),
+        other => return Err(format!("Unknown imposition '{}'; expected one of: {}", other, LAYOUTS.join(", "))),
+    };
+
+    let pages_id = doc.new_object_id();
+    let per_sheet = columns * rows;
+    let sheets: Vec<Object> = order.chunks(per_sheet).map(|slots| {
+        let (cell_width, cell_height) = (sheet.0 / columns as f32, sheet.1 / rows as f32);
+        let mut content = String::new();
+        let mut xobjects = Dictionary::new();
+        for (slot, page) in slots.iter().enumerate() {
+            let Some(&(form, box_)) = page.and_then(|page| forms.get(page)) else { continue };
+            let [bx0, by0, bx1, by1] = box_;
+            let scale = (cell_width / (bx1 - bx0)).min(cell_height / (by1 - by0));
+            let (column, row) = (slot % columns, slot / columns);
+            // Centered in its cell; PDF y grows upwards, rows are counted from the top
+            let tx = column as f32 * cell_width + (cell_width - (bx1 - bx0) * scale) / 2.0 - bx0 * scale;
+            let ty = (rows - 1 - row) as f32 * cell_height + (cell_height - (by1 - by0) * scale) / 2.0 - by0 * scale;
+            let name = format!("P{}", slot);
+            content.push_str(&format!("q {:.4} 0 0 {:.4} {:.2} {:.2} cm /{} Do Q\n", scale, scale, tx, ty, name));
+            xobjects.set(name, form);
+        }
+        let mut stream = Stream::new(dictionary! {}, content.into_bytes());
+        let _ = stream.compress();
+        let content = doc.add_object(stream);
+        doc.add_object(dictionary! {
+            "Type" => "Page",
+            "Parent" => pages_id,
+            "MediaBox" => vec![0.into(), 0.into(), sheet.0.into(), sheet.1.into()],
+            "Contents" => content,
+            "Resources" => dictionary! { "XObject" => xobjects },
+        }).into()
+    }).collect();
+    let count = sheets.len() as i64;
+    doc.objects.insert(pages_id, Object::Dictionary(dictionary! { "Type" => "Pages", "Kids" => sheets, "Count" => count }));
+
+    let catalog = doc.catalog_mut().map_err(|e| format!("PDF has no catalog: {}", e))?;
+    catalog.set("Pages", pages_id);
+    for key in [b"Outlines".as_slice(), b"Dests", b"PageLabels", b"OpenAction", b"AcroForm", b"StructTreeRoot"] {
+        catalog.remove(key);
+    }
+    doc.prune_objects();
+    doc.renumber_objects();
+
+    let mut out = Vec::new();
+    doc.save_to(&mut out).map_err(|e| format!("Failed to write PDF: {}", e))?;
+    Ok(out)
+}
+
+/// Page indices of each side of each sheet of a saddle-stitched booklet: the outer sheet
+/// carries the last and first pages, its back the second and second-to-last, and so on.
+fn booklet_order(pages: usize) -> Vec<Option<usize>> {
+    let padded = pages.div_ceil(4) * 4;
+    let page = |index: usize| (index < pages).then_some(index);
+    (0..padded / 4).flat_map(|sheet| {
+        let (outer, inner) = (2 * sheet, padded - 1 - 2 * sheet);
+        [page(inner), page(outer), page(outer + 1), page(inner - 1)]
+    }).collect()
+}
+
+/// Wraps a page's content and resources in a form XObject; returns it with its bounding box.
+fn page_form(doc: &mut Document, page: ObjectId) -> Result<(ObjectId, [f32; 4]), String> {
+    let bbox = media_box(doc, page);
+    let content = doc.get_page_content(page).map_err(|e| format!("Cannot read page content: {}", e))?;
+    let resources = inherited(doc, page, b"Resources").cloned().unwrap_or_else(|| Object::Dictionary(Dictionary::new()));
+    let mut form = Stream::new(dictionary! {
+        "Type" => "XObject",
+        "Subtype" => "Form",
+        "BBox" => bbox.iter().map(|&v| Object::Real(v)).collect::<Vec<_>>(),
+        "Resources" => resources,
+    }, content);
+    let _ = form.compress();
+    Ok((doc.add_object(form), bbox))
+}
+
+/// A page attribute, which may be inherited from the page's ancestors.
+pub(crate) fn inherited<'a>(doc: &'a Document, page: ObjectId, key: &[u8]) -> Option<&'a Object> {
+    let mut node = doc.get_dictionary(page).ok();
+    // Bounded, in case of a cycle in the page tree
+    for _ in 0..32 {
+        let dict = node?;
+        if let Ok(value) = dict.get(key) {
+            return Some(value);
+        }
+        node = dict.get(b"Parent").and_then(Object::as_reference).ok().and_then(|id| doc.get_dictionary(id).ok());
+    }
+    None
+}
+
+fn media_box(doc: &Document, page: ObjectId) -> [f32; 4] {
+    let values = inherited(doc, page, b"MediaBox").and_then(|b| doc.dereference(b).ok()).and_then(|(_, b)| b.as_array().ok());
+    let numbers: Vec<f32> = values.into_iter().flatten().filter_map(|v| v.as_float().ok()).collect();
+    match numbers[..] {
+        [x0, y0, x1, y1] if x1 > x0 && y1 > y0 => [x0, y0, x1, y1],
+        _ => DEFAULT_MEDIA_BOX,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pdf(pages: usize) -> Vec<u8> {
+        let mut doc = Document::with_version("1.5");
+        let pages_id = doc.new_object_id();
+        let kids: Vec<Object> = (1..=pages).map(|n| {
+            let content = doc.add_object(Stream::new(dictionary! {}, format!("BT ({}) Tj ET", n).into_bytes()));
+            doc.add_object(dictionary! { "Type" => "Page", "Parent" => pages_id, "Contents" => content }).into()
+        }).collect();
+        doc.objects.insert(pages_id, Object::Dictionary(dictionary! {
+            "Type" => "Pages",
+            "Kids" => kids,
+            "Count" => pages as i64,
+            "MediaBox" => vec![0.into(), 0.into(), 420.into(), 595.into()],
+            "Resources" => dictionary! {},
+        }));
+        let catalog = doc.add_object(dictionary! { "Type" => "Catalog", "Pages" => pages_id });
+        doc.trailer.set("Root", catalog);
+        let mut out = Vec::new();
+        doc.save_to(&mut out).unwrap();
+        out
+    }
+
+    /// Sheet sizes and, per sheet, the source page numbers drawn in placement order.
+    fn sheets(pdf: &[u8]) -> Vec<([f32; 4], Vec<String>)> {
+        let doc = Document::load_mem(pdf).unwrap();
+        doc.get_pages().into_values().map(|page| {
+            let content = doc.get_and_decode_page_content(page).unwrap();
+            let (inline, _) = doc.get_page_resources(page).unwrap();
+            let xobjects = inline.unwrap().get(b"XObject").unwrap().as_dict().unwrap();
+            let drawn = content.operations.iter().filter(|op| op.operator == "Do").map(|op| {
+                let form = doc.get_object(xobjects.get(op.operands[0].as_name().unwrap()).unwrap().as_reference().unwrap()).unwrap();
+                let text = form.as_stream().unwrap().get_plain_content().unwrap();
+                String::from_utf8(text).unwrap().trim_start_matches("BT (").trim_end_matches(") Tj ET").to_string()
+            }).collect();
+            (media_box(&doc, page), drawn)
+        }).collect()
+    }
+
+    #[test]
+    fn test_booklet() {
+        let booklet = sheets(&impose(&pdf(6), "booklet").unwrap());
+        assert_eq!(booklet.len(), 4, "6 pages pad to 8, four sides");
+        assert_eq!(booklet[0].0, [0.0, 0.0, 840.0, 595.0]);
+        let order: Vec<Vec<String>> = booklet.into_iter().map(|(_, drawn)| drawn).collect();
+        assert_eq!(order, vec![vec!["1".to_string()], vec!["2".into()], vec!["6".into(), "3".into()], vec!["4".into(), "5".into()]]);
+    }
+
+    #[test]
+    fn test_n_up() {
+        let two = sheets(&impose(&pdf(3), "2up").unwrap());
+        assert_eq!(two.len(), 2);
+        assert_eq!(two[0], ([0.0, 0.0, 595.0, 420.0], vec!["1".to_string(), "2".into()]));
+        assert_eq!(two[1].1, vec!["3"]);
+
+        let four = sheets(&impose(&pdf(5), "4up").unwrap());
+        assert_eq!(four.len(), 2);
+        assert_eq!(four[0], ([0.0, 0.0, 420.0, 595.0], vec!["1".to_string(), "2".into(), "3".into(), "4".into()]));
+        assert!(impose(&pdf(1), "8up").is_err());
+    }
+
+    #[test]
+    fn test_booklet_order() {
+        let order: Vec<Option<usize>> = booklet_order(8);
+        assert_eq!(order, [7, 0, 1, 6, 5, 2, 3, 4].map(Some).to_vec());
+        assert_eq!(booklet_order(2), vec![None, Some(0), Some(1), None]);
+    }
+}
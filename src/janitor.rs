@@ -0,0 +1,280 @@
+//! Bookkeeping for per-compile workspaces under [`workspace_base`]. The base directory
+//! may be shared by several instances, so each janitor works in its own subdirectory.
+//! Every workspace there is registered while in use, so anything else in it was leaked
+//! by a crashed or killed compile and can be removed. Other instances' directories are
+//! only removed once they have gone untouched for [`ORPHAN_TTL`]. The janitor also
+//! enforces a size quota, refusing new workspaces while this instance is over budget.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+use tempfile::TempDir;
+
+use crate::services::workspace_base;
+
+/// Untracked entries younger than this are left alone: they may belong to a workspace
+/// that is still being registered.
+pub const LEAK_GRACE: Duration = Duration::from_secs(60);
+
+/// Another instance's directory untouched for this long belongs to an instance that is
+/// gone. Creating or removing a workspace refreshes the directory's modification time.
+pub const ORPHAN_TTL: Duration = Duration::from_secs(6 * 3600);
+
+/// Space counted for each live workspace on top of the measured usage, so concurrent
+/// requests cannot all pass the quota check before any of them has written a file.
+const WORKSPACE_RESERVATION: u64 = 1024 * 1024;
+
+/// A registered workspace, deleted and unregistered on drop.
+pub struct Workspace {
+    dir: TempDir,
+    janitor: Janitor,
+}
+
+impl Workspace {
+    pub fn path(&self) -> &Path {
+        self.dir.path()
+    }
+}
+
+impl Drop for Workspace {
+    fn drop(&mut self) {
+        // Forget the workspace's last measured size along with it, so the quota frees up
+        // without measuring the disk here
+        let size = self.janitor.live.lock().unwrap().remove(self.dir.path()).unwrap_or(0);
+        let _ = self.janitor.used_bytes.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |used| Some(used.saturating_sub(size)));
+        self.janitor.reserved_bytes.fetch_sub(WORKSPACE_RESERVATION, Ordering::Relaxed);
+    }
+}
+
+/// This instance's directory under the base, removed when the last janitor goes away
+/// (unless something was leaked into it).
+struct InstanceRoot(PathBuf);
+
+impl Drop for InstanceRoot {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir(&self.0);
+    }
+}
+
+/// Outcome of a [`Janitor::sweep`].
+#[derive(Debug, Default, PartialEq)]
+pub struct SweepReport {
+    pub live: usize,
+    pub removed: usize,
+    pub removed_bytes: u64,
+    pub used_bytes: u64,
+}
+
+#[derive(Clone)]
+pub struct Janitor {
+    base: PathBuf,
+    root: Arc<InstanceRoot>,
+    /// 0 disables the quota
+    quota_bytes: u64,
+    /// Live workspaces and their size at the last sweep
+    live: Arc<Mutex<HashMap<PathBuf, u64>>>,
+    /// Size of this instance's directory at the last sweep, less the workspaces dropped since
+    used_bytes: Arc<AtomicU64>,
+    /// [`WORKSPACE_RESERVATION`] for every live workspace
+    reserved_bytes: Arc<AtomicU64>,
+}
+
+impl Janitor {
+    pub fn new(quota_mb: u64) -> Self {
+        Self::with_base(workspace_base(), quota_mb)
+    }
+
+    pub fn with_base(base: PathBuf, quota_mb: u64) -> Self {
+        let root = base.join(format!("instance-{}", uuid::Uuid::new_v4().simple()));
+        Self {
+            base,
+            root: Arc::new(InstanceRoot(root)),
+            quota_bytes: quota_mb * 1024 * 1024,
+            live: Arc::new(Mutex::new(HashMap::new())),
+            used_bytes: Arc::new(AtomicU64::new(0)),
+            reserved_bytes: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// Creates a tracked workspace, failing when the quota is exhausted. The check and the
+    /// reservation are one atomic step; usage itself is only measured by [`Janitor::sweep`].
+    pub fn workspace(&self) -> Result<Workspace, String> {
+        let used = self.used_bytes.load(Ordering::Relaxed);
+        let reserved = self.reserved_bytes.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |reserved| {
+            let fits = self.quota_bytes == 0 || used + reserved + WORKSPACE_RESERVATION <= self.quota_bytes;
+            fits.then_some(reserved + WORKSPACE_RESERVATION)
+        });
+        if reserved.is_err() {
+            return Err(format!("Workspace quota exceeded ({} of {} MB in use)", used / 1024 / 1024, self.quota_bytes / 1024 / 1024));
+        }
+        let release = || { self.reserved_bytes.fetch_sub(WORKSPACE_RESERVATION, Ordering::Relaxed); };
+        // Recreated in case an instance that thought this one gone removed it
+        let dir = match std::fs::create_dir_all(&self.root.0).and_then(|_| TempDir::new_in(&self.root.0)) {
+            Ok(dir) => dir,
+            Err(e) => {
+                release();
+                return Err(e.to_string());
+            }
+        };
+        self.live.lock().unwrap().insert(dir.path().to_path_buf(), 0);
+        Ok(Workspace { dir, janitor: self.clone() })
+    }
+
+    /// Creates a tracked workspace holding a copy of `source`, for builds that must not
+    /// see each other's rewrites or outputs.
+    pub fn copy_of(&self, source: &Path) -> Result<Workspace, String> {
+        let workspace = self.workspace()?;
+        copy_dir(source, workspace.path()).map_err(|e| format!("Failed to copy workspace: {}", e))?;
+        Ok(workspace)
+    }
+
+    /// Removes untracked entries of this instance older than `grace` and refreshes the
+    /// usage figures. Blocks on the filesystem, so async callers run it on a blocking thread.
+    pub fn sweep(&self, grace: Duration) -> SweepReport {
+        let mut report = SweepReport::default();
+        remove_older_than(&self.root.0, grace, |path| self.live.lock().unwrap().contains_key(path), &mut report);
+        let mut live = self.live.lock().unwrap();
+        for (path, size) in live.iter_mut() {
+            *size = dir_size(path);
+        }
+        report.live = live.len();
+        report.used_bytes = dir_size(&self.root.0);
+        self.used_bytes.store(report.used_bytes, Ordering::Relaxed);
+        report
+    }
+
+    /// Removes what other instances left in the base directory once untouched for `ttl`
+    /// (see [`ORPHAN_TTL`]); meant for startup. Blocks like [`Janitor::sweep`].
+    pub fn sweep_orphans(&self, ttl: Duration) -> SweepReport {
+        let mut report = SweepReport::default();
+        remove_older_than(&self.base, ttl, |path| path == self.root.0, &mut report);
+        report
+    }
+}
+
+/// Removes the entries of `dir` older than `min_age` that are not `kept`.
+fn remove_older_than(dir: &Path, min_age: Duration, kept: impl Fn(&Path) -> bool, report: &mut SweepReport) {
+    let now = SystemTime::now();
+    let Ok(entries) = std::fs::read_dir(dir) else { return };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if kept(&path) {
+            continue;
+        }
+        let age = entry.metadata().and_then(|m| m.modified()).ok()
+            .and_then(|modified| now.duration_since(modified).ok())
+            .unwrap_or_default();
+        if age < min_age {
+            continue;
+        }
+        let size = dir_size(&path);
+        let removed = if path.is_dir() { std::fs::remove_dir_all(&path) } else { std::fs::remove_file(&path) };
+        if removed.is_ok() {
+            report.removed += 1;
+            report.removed_bytes += size;
+        }
+    }
+}
+
+fn copy_dir(from: &Path, to: &Path) -> std::io::Result<()> {
+    for entry in std::fs::read_dir(from)? {
+        let entry = entry?;
+        let target = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            std::fs::create_dir_all(&target)?;
+            copy_dir(&entry.path(), &target)?;
+        } else {
+            std::fs::copy(entry.path(), target)?;
+        }
+    }
+    Ok(())
+}
+
+/// Total size of the files under `path` (or of `path` itself), without following symlinks.
+pub(crate) fn dir_size(path: &Path) -> u64 {
+    let Ok(metadata) = std::fs::symlink_metadata(path) else { return 0 };
+    if !metadata.is_dir() {
+        return metadata.len();
+    }
+    std::fs::read_dir(path)
+        .map(|entries| entries.flatten().map(|entry| dir_size(&entry.path())).sum())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sweep_removes_only_leaked_workspaces() {
+        let base = TempDir::new().unwrap();
+        let janitor = Janitor::with_base(base.path().to_path_buf(), 0);
+        let workspace = janitor.workspace().unwrap();
+        std::fs::write(workspace.path().join("main.tex"), "live").unwrap();
+        let leaked = janitor.root.0.join("crashed");
+        std::fs::create_dir(&leaked).unwrap();
+        std::fs::write(leaked.join("main.aux"), "leaked").unwrap();
+
+        assert_eq!(janitor.sweep(Duration::from_secs(3600)).removed, 0, "recent entries are kept");
+        let report = janitor.sweep(Duration::ZERO);
+        assert_eq!((report.live, report.removed, report.removed_bytes, report.used_bytes), (1, 1, 6, 4));
+        assert!(workspace.path().exists() && !leaked.exists());
+
+        let path = workspace.path().to_path_buf();
+        drop(workspace);
+        assert!(!path.exists());
+        assert_eq!(janitor.sweep(Duration::ZERO).live, 0);
+    }
+
+    #[test]
+    fn test_quota_refuses_new_workspaces() {
+        let base = TempDir::new().unwrap();
+        let janitor = Janitor::with_base(base.path().to_path_buf(), 1);
+        let workspace = janitor.workspace().unwrap();
+        std::fs::write(workspace.path().join("big.pdf"), vec![0u8; 1024 * 1024]).unwrap();
+        janitor.sweep(Duration::ZERO);
+        assert!(janitor.workspace().err().unwrap().contains("quota exceeded"));
+
+        drop(workspace);
+        assert!(janitor.workspace().is_ok(), "a dropped workspace frees its share of the quota");
+    }
+
+    #[test]
+    fn test_quota_reserves_before_anything_is_written() {
+        let base = TempDir::new().unwrap();
+        let janitor = Janitor::with_base(base.path().to_path_buf(), 2);
+        let _first = janitor.workspace().unwrap();
+        let _second = janitor.workspace().unwrap();
+        assert!(janitor.workspace().is_err(), "empty workspaces still hold a reservation");
+    }
+
+    #[test]
+    fn test_sweep_leaves_other_instances_alone() {
+        let base = TempDir::new().unwrap();
+        let janitor = Janitor::with_base(base.path().to_path_buf(), 0);
+        let other = Janitor::with_base(base.path().to_path_buf(), 0);
+        let theirs = other.workspace().unwrap();
+        let _ours = janitor.workspace().unwrap();
+
+        assert_eq!(janitor.sweep(Duration::ZERO).removed, 0);
+        assert_eq!(janitor.sweep_orphans(ORPHAN_TTL).removed, 0, "a recently used instance is kept");
+        assert!(theirs.path().exists());
+        assert_eq!(janitor.sweep_orphans(Duration::ZERO).removed, 1, "an idle instance's directory goes");
+        assert!(janitor.workspace().is_ok());
+    }
+
+    #[test]
+    fn test_copy_of_workspace() {
+        let base = TempDir::new().unwrap();
+        let janitor = Janitor::with_base(base.path().to_path_buf(), 0);
+        let original = janitor.workspace().unwrap();
+        std::fs::create_dir(original.path().join("chapters")).unwrap();
+        std::fs::write(original.path().join("chapters/one.tex"), "one").unwrap();
+
+        let copy = janitor.copy_of(original.path()).unwrap();
+        assert_eq!(std::fs::read_to_string(copy.path().join("chapters/one.tex")).unwrap(), "one");
+        assert_eq!(janitor.sweep(Duration::ZERO).live, 2);
+    }
+}
@@ -0,0 +1,382 @@
+//! Fire-and-forget compiles: `POST /compile/async` answers 202 with a job id at once, then
+//! fetches the sources, compiles at batch priority and POSTs the finished job to the
+//! caller's callback URL, retrying like webhook deliveries.
+
+use base64::{engine::general_purpose, Engine as _};
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tracing::{info, warn};
+
+use crate::filetypes::FilePolicy;
+use crate::models::{AsyncCompileJob, AsyncCompileRequest};
+use crate::overleaf::OverleafImporter;
+use crate::render::{self, AuxFile};
+use crate::services::{AppState, Priority};
+use crate::settings::Settings;
+use crate::webhooks::Webhooks;
+use crate::util::unix_now;
+
+/// Event name sent in `X-Tachyon-Event` with callbacks.
+pub const CALLBACK_EVENT: &str = "compile.async";
+
+/// How long finished jobs stay queryable.
+const JOB_TTL_SECS: u64 = 86_400;
+
+/// Timeout for downloading a source URL.
+const DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Delay before the first callback retry; doubled after every failed attempt.
+const RETRY_BASE_DELAY: Duration = Duration::from_secs(2);
+
+pub enum Source {
+    Url(String),
+    Zip(Vec<u8>),
+    /// File name and blob hash
+    Blobs(Vec<(String, String)>),
+}
+
+/// A validated `POST /compile/async` request.
+pub struct AsyncCompile {
+    pub source: Source,
+    pub main: Option<String>,
+    pub callback_url: String,
+    pub callback_secret: Option<String>,
+}
+
+impl AsyncCompile {
+    /// Checks everything that can be checked before answering: one source, safe file
+    /// names and a callback URL the webhook target policy accepts.
+    pub fn from_request(request: AsyncCompileRequest, settings: &Settings) -> Result<Self, String> {
+        let source = match (request.url, request.zip, request.blobs) {
+            (Some(url), None, None) => {
+                Webhooks::validate_url(&url, settings).map_err(|e| format!("Source URL rejected: {}", e))?;
+                Source::Url(url)
+            }
+            (None, Some(zip), None) => {
+                Source::Zip(general_purpose::STANDARD.decode(zip.trim()).map_err(|e| format!("zip is not valid base64: {}", e))?)
+            }
+            (None, None, Some(blobs)) if !blobs.is_empty() => {
+                let mut blobs: Vec<(String, String)> = blobs.into_iter().collect();
+                blobs.sort();
+                if let Some((name, _)) = blobs.iter().find(|(name, _)| !is_safe_path(name)) {
+                    return Err(format!("Invalid blob file name {}", name));
+                }
+                Source::Blobs(blobs)
+            }
+            _ => return Err("Give exactly one source: url, zip or blobs".to_string()),
+        };
+        if let Some(main) = request.main.as_deref().filter(|main| !is_safe_path(main)) {
+            return Err(format!("Invalid main file {}", main));
+        }
+        Webhooks::validate_url(&request.callback_url, settings).map_err(|e| format!("Callback URL rejected: {}", e))?;
+        if let Some(secret) = &request.callback_secret {
+            Webhooks::validate_secret(secret)?;
+        }
+        Ok(Self { source, main: request.main, callback_url: request.callback_url, callback_secret: request.callback_secret })
+    }
+}
+
+pub(crate) fn is_safe_path(name: &str) -> bool {
+    !name.is_empty() && !Path::new(name).is_absolute() && !name.split('/').any(|c| c == "..")
+}
+
+struct Entry {
+    tenant: Option<String>,
+    job: AsyncCompileJob,
+}
+
+/// Jobs by id, kept in memory for a day after they finish.
+#[derive(Clone)]
+pub struct JobStore {
+    jobs: Arc<Mutex<HashMap<String, Entry>>>,
+    /// One permit per unfinished job
+    slots: Arc<Semaphore>,
+}
+
+impl JobStore {
+    pub fn new(max_jobs: usize) -> Self {
+        Self { jobs: Arc::default(), slots: Arc::new(Semaphore::new(max_jobs)) }
+    }
+
+    pub fn create(&self, tenant: Option<String>, main: Option<String>) -> AsyncCompileJob {
+        let now = unix_now();
+        let job = AsyncCompileJob {
+            id: uuid::Uuid::new_v4().simple().to_string(),
+            status: "queued".to_string(),
+            main,
+            output_hash: None,
+            compile_time_ms: None,
+            error: None,
+            warnings: Vec::new(),
+            callback: "pending".to_string(),
+            created_at: now,
+            finished_at: None,
+        };
+        let mut jobs = self.jobs.lock().unwrap();
+        jobs.retain(|_, entry| entry.job.finished_at.is_none_or(|at| at + JOB_TTL_SECS > now));
+        jobs.insert(job.id.clone(), Entry { tenant, job: job.clone() });
+        job
+    }
+
+    /// A job of `tenant`; other tenants' jobs are not found.
+    pub fn get(&self, id: &str, tenant: Option<&str>) -> Option<AsyncCompileJob> {
+        self.jobs.lock().unwrap().get(id).filter(|entry| entry.tenant.as_deref() == tenant).map(|entry| entry.job.clone())
+    }
+
+    fn update(&self, id: &str, change: impl FnOnce(&mut AsyncCompileJob)) -> Option<AsyncCompileJob> {
+        let mut jobs = self.jobs.lock().unwrap();
+        let entry = jobs.get_mut(id)?;
+        change(&mut entry.job);
+        Some(entry.job.clone())
+    }
+
+    /// Drops the tenant's jobs created before `before` (all when `None`), returning how many.
+    pub fn remove_tenant(&self, tenant: &str, before: Option<u64>) -> usize {
+        let mut jobs = self.jobs.lock().unwrap();
+        let count = jobs.len();
+        jobs.retain(|_, entry| entry.tenant.as_deref() != Some(tenant) || before.is_some_and(|b| entry.job.created_at >= b));
+        count - jobs.len()
+    }
+}
+
+/// Queues the compile and returns the job as first reported, or an error when ASYNC_MAX_JOBS
+/// jobs are already queued or running.
+pub fn spawn(state: &AppState, tenant: Option<String>, compile: AsyncCompile) -> Result<AsyncCompileJob, String> {
+    let slot = state.jobs.slots.clone().try_acquire_owned()
+        .map_err(|_| format!("{} async compiles are already in progress", state.settings.async_max_jobs))?;
+    let job = state.jobs.create(tenant.clone(), compile.main.clone());
+    // Spawned tasks leave the request's scope; carry the tenant over for the storage layers
+    tokio::spawn(crate::tenancy::TENANT.scope(tenant, run(state.clone(), job.id.clone(), compile, slot)));
+    Ok(job)
+}
+
+async fn run(state: AppState, id: String, compile: AsyncCompile, _slot: OwnedSemaphorePermit) {
+    state.jobs.update(&id, |job| job.status = "running".to_string());
+    let start = Instant::now();
+    let result = match load_sources(&state, &compile).await {
+        Ok((main, files, warnings)) => {
+            let (result, logs) = render::compile_files(&state, &main, &files, Priority::Batch).await;
+            match result {
+                Ok(pdf_data) => Ok((main, pdf_data, warnings)),
+                Err(e) => Err((Some(main), format!("LaTeX Error: {}\n\nLogs:\n{}", e, logs))),
+            }
+        }
+        Err(e) => Err((compile.main.clone(), e)),
+    };
+    let compile_time_ms = start.elapsed().as_millis() as u64;
+
+    let output_hash = match &result {
+        Ok((_, pdf_data, _)) => Some(state.output_store.put(pdf_data).await),
+        Err(_) => None,
+    };
+    let Some(job) = state.jobs.update(&id, |job| {
+        job.compile_time_ms = Some(compile_time_ms);
+        job.finished_at = Some(unix_now());
+        match result {
+            Ok((main, _, warnings)) => {
+                job.status = "succeeded".to_string();
+                job.main = Some(main);
+                job.output_hash = output_hash;
+                job.warnings = warnings;
+            }
+            Err((main, e)) => {
+                job.status = "failed".to_string();
+                job.main = main;
+                job.error = Some(e);
+            }
+        }
+    }) else {
+        return; // Erased meanwhile
+    };
+    info!("📮 Async compile {} {} in {}ms", id, job.status, compile_time_ms);
+
+    let delivered = deliver_callback(&state, &compile, &job).await;
+    state.jobs.update(&id, |job| job.callback = if delivered { "delivered" } else { "failed" }.to_string());
+}
+
+/// Resolves the job's source to its main file, files and import warnings.
+async fn load_sources(state: &AppState, compile: &AsyncCompile) -> Result<(String, Vec<AuxFile>, Vec<String>), String> {
+    let (main, files, warnings) = match &compile.source {
+        Source::Url(url) => {
+            // Downloaded to disk: a large archive is never held in memory as a whole
+            let workspace = state.janitor.workspace()?;
+            let path = workspace.path().join("download");
+            download_to(url, reqwest::header::HeaderMap::new(), &state.settings, &path).await?;
+            let mut head = Vec::with_capacity(4096);
+            let mut file = tokio::fs::File::open(&path).await.map_err(|e| format!("Failed to read {}: {}", url, e))?;
+            (&mut file).take(4096).read_to_end(&mut head).await.map_err(|e| format!("Failed to read {}: {}", url, e))?;
+            if crate::filetypes::sniff(&head) == Some("zip") {
+                let main = compile.main.clone();
+                let project = tokio::task::spawn_blocking(move || {
+                    let _workspace = workspace;
+                    let file = std::fs::File::open(&path).map_err(|e| format!("Import failed: {}", e))?;
+                    OverleafImporter::import_reader(std::io::BufReader::new(file))
+                })
+                .await
+                .map_err(|e| format!("Import failed: {}", e))??;
+                (main.unwrap_or(project.main), project.files, project.warnings)
+            } else {
+                let data = tokio::fs::read(&path).await.map_err(|e| format!("Failed to read {}: {}", url, e))?;
+                let name = url.split(['?', '#']).next().unwrap_or_default().rsplit('/').next().unwrap_or_default();
+                let name = if name.ends_with(".tex") { name.to_string() } else { "main.tex".to_string() };
+                (compile.main.clone().unwrap_or_else(|| name.clone()), vec![(name, data)], Vec::new())
+            }
+        }
+        Source::Zip(data) => import(data.clone(), compile.main.clone()).await?,
+        Source::Blobs(blobs) => {
+            let mut files = Vec::with_capacity(blobs.len());
+            for (name, hash) in blobs {
+                let data = state.blob_store.get(hash).await.ok_or_else(|| format!("Blob {} not found", hash))?;
+                files.push((name.clone(), data.to_vec()));
+            }
+            (compile.main.clone().unwrap_or_else(|| "main.tex".to_string()), files, Vec::new())
+        }
+    };
+
+    let policy = FilePolicy::new(&state.settings.allowed_file_types);
+    if let Some(rejection) = files.iter().find_map(|(name, data)| policy.check(name, &data[..data.len().min(4096)])) {
+        return Err(format!("File {} refused: {}", rejection.file, rejection.reason));
+    }
+    if !files.iter().any(|(name, _)| *name == main) {
+        return Err(format!("Main file {} is not in the sources", main));
+    }
+    Ok((main, files, warnings))
+}
+
+async fn import(data: Vec<u8>, main: Option<String>) -> Result<(String, Vec<AuxFile>, Vec<String>), String> {
+    let project = tokio::task::spawn_blocking(move || OverleafImporter::import(&data))
+        .await
+        .map_err(|e| format!("Import failed: {}", e))??;
+    Ok((main.unwrap_or(project.main), project.files, project.warnings))
+}
+
+/// Downloads a source under the webhook target policy, up to UPLOAD_MAX_MB.
+pub(crate) async fn download(url: &str, headers: reqwest::header::HeaderMap, settings: &Settings) -> Result<Vec<u8>, String> {
+    let mut response = open(url, headers, settings).await?;
+    let max_bytes = settings.upload_max_mb * 1024 * 1024;
+    let mut data = Vec::new();
+    while let Some(chunk) = response.chunk().await.map_err(|e| format!("Failed to download {}: {}", url, e))? {
+        if (data.len() + chunk.len()) as u64 > max_bytes {
+            return Err(format!("{} is larger than {} MB", url, settings.upload_max_mb));
+        }
+        data.extend_from_slice(&chunk);
+    }
+    Ok(data)
+}
+
+/// Like [`download`], writing the body to `path` as it arrives.
+async fn download_to(url: &str, headers: reqwest::header::HeaderMap, settings: &Settings, path: &Path) -> Result<(), String> {
+    let mut response = open(url, headers, settings).await?;
+    let max_bytes = settings.upload_max_mb * 1024 * 1024;
+    let write_error = |e: std::io::Error| format!("Failed to store {}: {}", url, e);
+    let mut file = tokio::fs::File::create(path).await.map_err(write_error)?;
+    let mut written = 0u64;
+    while let Some(chunk) = response.chunk().await.map_err(|e| format!("Failed to download {}: {}", url, e))? {
+        written += chunk.len() as u64;
+        if written > max_bytes {
+            return Err(format!("{} is larger than {} MB", url, settings.upload_max_mb));
+        }
+        file.write_all(&chunk).await.map_err(write_error)?;
+    }
+    file.flush().await.map_err(write_error)
+}
+
+/// Sends the GET for a download, checking the target and the response status.
+async fn open(url: &str, headers: reqwest::header::HeaderMap, settings: &Settings) -> Result<reqwest::Response, String> {
+    let parsed = Webhooks::validate_url(url, settings).map_err(|e| format!("Source URL rejected: {}", e))?;
+    let addrs = Webhooks::resolve_target(&parsed, settings).await.map_err(|e| format!("Source URL rejected: {}", e))?;
+    let client = reqwest::Client::builder()
+        .timeout(DOWNLOAD_TIMEOUT)
+        .redirect(reqwest::redirect::Policy::none())
+        .resolve_to_addrs(parsed.host_str().unwrap_or_default(), &addrs)
+        .build()
+        .map_err(|e| e.to_string())?;
+    let response = client.get(parsed).headers(headers).send().await.map_err(|e| format!("Failed to download {}: {}", url, e))?;
+    if !response.status().is_success() {
+        return Err(format!("Failed to download {}: HTTP {}", url, response.status()));
+    }
+    Ok(response)
+}
+
+/// POSTs the finished job to the callback, with WEBHOOK_MAX_ATTEMPTS attempts and
+/// exponential backoff. The job id is the delivery id, so retries can be deduplicated.
+async fn deliver_callback(state: &AppState, compile: &AsyncCompile, job: &AsyncCompileJob) -> bool {
+    let body = match serde_json::to_string(job) {
+        Ok(body) => body,
+        Err(e) => {
+            warn!("Async compile {} callback not sent: {}", job.id, e);
+            return false;
+        }
+    };
+    let max_attempts = state.settings.webhook_max_attempts;
+    let mut delay = RETRY_BASE_DELAY;
+    for attempt in 1..=max_attempts {
+        let secret = compile.callback_secret.as_deref();
+        match Webhooks::post(&compile.callback_url, secret, CALLBACK_EVENT, &job.id, body.clone(), &state.settings).await {
+            Ok(()) => {
+                info!("🔔 Delivered async compile {} to its callback (attempt {})", job.id, attempt);
+                return true;
+            }
+            Err(e) => warn!("Async compile {} callback attempt {}/{} failed: {}", job.id, attempt, max_attempts, e),
+        }
+        if attempt < max_attempts {
+            tokio::time::sleep(delay).await;
+            delay *= 2;
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(json: serde_json::Value) -> AsyncCompileRequest {
+        serde_json::from_value(json).unwrap()
+    }
+
+    #[test]
+    fn test_request_validation() {
+        let settings = Settings { webhook_allowed_hosts: vec![], webhook_require_https: false, ..Settings::from_env() };
+        let zip = general_purpose::STANDARD.encode(b"PK\x03\x04");
+        let ok = AsyncCompile::from_request(request(serde_json::json!({"zip": zip, "callback_url": "https://example.com/done"})), &settings).unwrap();
+        assert!(matches!(ok.source, Source::Zip(ref data) if data == b"PK\x03\x04"));
+
+        let blobs = AsyncCompile::from_request(request(serde_json::json!({
+            "blobs": {"main.tex": "a1", "figs/x.png": "b2"}, "main": "main.tex", "callback_url": "https://example.com/done"
+        })), &settings).unwrap();
+        assert!(matches!(blobs.source, Source::Blobs(ref b) if b[0].0 == "figs/x.png"));
+
+        for (body, error) in [
+            (serde_json::json!({"callback_url": "https://example.com/done"}), "exactly one source"),
+            (serde_json::json!({"url": "https://example.com/p.zip", "zip": zip, "callback_url": "https://example.com/done"}), "exactly one source"),
+            (serde_json::json!({"zip": "not base64!", "callback_url": "https://example.com/done"}), "not valid base64"),
+            (serde_json::json!({"blobs": {"../etc/passwd": "a1"}, "callback_url": "https://example.com/done"}), "Invalid blob file name"),
+            (serde_json::json!({"zip": zip, "main": "/main.tex", "callback_url": "https://example.com/done"}), "Invalid main file"),
+            (serde_json::json!({"zip": zip, "callback_url": "ftp://example.com/done"}), "Callback URL rejected"),
+            (serde_json::json!({"zip": zip, "callback_url": "https://example.com/done", "callback_secret": "short"}), "at least"),
+        ] {
+            let e = AsyncCompile::from_request(request(body), &settings).err().unwrap();
+            assert!(e.contains(error), "{} does not mention {}", e, error);
+        }
+    }
+
+    #[test]
+    fn test_jobs_are_scoped_to_their_tenant() {
+        let jobs = JobStore::new(4);
+        let job = jobs.create(Some("acme".into()), None);
+        assert_eq!(job.status, "queued");
+        assert!(jobs.get(&job.id, Some("acme")).is_some());
+        assert!(jobs.get(&job.id, None).is_none());
+        assert!(jobs.get(&job.id, Some("globex")).is_none());
+
+        jobs.create(None, None);
+        assert_eq!(jobs.remove_tenant("acme", Some(job.created_at)), 0);
+        assert_eq!(jobs.remove_tenant("acme", None), 1);
+        assert!(jobs.get(&job.id, Some("acme")).is_none());
+    }
+}
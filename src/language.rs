@@ -0,0 +1,165 @@
+//! Detection of a document's language, so documents that never load `babel` or
+//! `polyglossia` still get the right hyphenation patterns and typographic conventions.
+
+use regex::Regex;
+use std::collections::HashMap;
+
+use crate::bib::strip_comment;
+
+pub struct Language {
+    /// ISO 639-1 code
+    pub code: &'static str,
+    pub name: &'static str,
+    babel: &'static str,
+    polyglossia: &'static str,
+    /// Frequent function words that rarely appear in the other languages
+    stopwords: &'static [&'static str],
+}
+
+pub const LANGUAGES: &[Language] = &[
+    Language {
+        code: "en", name: "English", babel: "english", polyglossia: "english",
+        stopwords: &["the", "and", "of", "to", "is", "that", "it", "with", "for", "are", "this", "be", "on", "as", "was", "which", "by", "from"],
+    },
+    Language {
+        code: "es", name: "Spanish", babel: "spanish", polyglossia: "spanish",
+        stopwords: &["el", "los", "las", "que", "y", "en", "un", "una", "por", "con", "para", "es", "del", "se", "al", "como", "más", "su"],
+    },
+    Language {
+        code: "de", name: "German", babel: "ngerman", polyglossia: "german",
+        stopwords: &["der", "die", "das", "und", "ist", "nicht", "mit", "ein", "eine", "zu", "den", "von", "sich", "auf", "für", "dem", "auch", "wird"],
+    },
+    Language {
+        code: "fr", name: "French", babel: "french", polyglossia: "french",
+        stopwords: &["le", "les", "et", "des", "est", "du", "pour", "dans", "qui", "pas", "sur", "au", "avec", "ce", "une", "sont", "aux", "cette"],
+    },
+    Language {
+        code: "it", name: "Italian", babel: "italian", polyglossia: "italian",
+        stopwords: &["il", "di", "che", "e", "per", "del", "della", "non", "sono", "gli", "è", "nel", "anche", "questo", "alla", "delle", "degli", "una"],
+    },
+    Language {
+        code: "pt", name: "Portuguese", babel: "portuguese", polyglossia: "portuguese",
+        stopwords: &["o", "os", "que", "e", "do", "da", "em", "um", "uma", "para", "com", "não", "por", "dos", "das", "é", "ao", "mais"],
+    },
+];
+
+/// Below this many stopword hits the text is too short to tell.
+const MIN_HITS: usize = 8;
+
+/// Finds a language by ISO code ("es", "pt-BR") or English name.
+pub fn find(requested: &str) -> Option<&'static Language> {
+    let code = requested.trim().split(['-', '_']).next().unwrap_or("").to_ascii_lowercase();
+    LANGUAGES.iter().find(|l| l.code == code || l.name.eq_ignore_ascii_case(&code))
+}
+
+/// The dominant language of LaTeX sources, by counting stopwords in the prose (comments,
+/// math and command names are skipped). `None` when no language clearly wins.
+pub fn detect<'a>(sources: impl IntoIterator<Item = &'a str>) -> Option<&'static Language> {
+    let command = Regex::new(r"\\[a-zA-Z@]+\*?|\\.").unwrap();
+    let math = Regex::new(r"\$\$[^$]*\$\$|\$[^$]*\$").unwrap();
+
+    let mut counts: HashMap<&str, usize> = HashMap::new();
+    for source in sources {
+        let prose: String = source.lines().map(strip_comment).collect::<Vec<_>>().join("\n");
+        let prose = math.replace_all(&prose, " ");
+        let prose = command.replace_all(&prose, " ");
+        for word in prose.split(|c: char| !c.is_alphabetic()).filter(|w| !w.is_empty()) {
+            let word = word.to_lowercase();
+            for language in LANGUAGES {
+                if language.stopwords.contains(&word.as_str()) {
+                    *counts.entry(language.code).or_default() += 1;
+                }
+            }
+        }
+    }
+
+    let mut ranked: Vec<(&str, usize)> = counts.into_iter().collect();
+    ranked.sort_by_key(|(_, hits)| std::cmp::Reverse(*hits));
+    match ranked.as_slice() {
+        [(code, hits), rest @ ..] if *hits >= MIN_HITS && rest.first().is_none_or(|(_, second)| second < hits) => find(code),
+        _ => None,
+    }
+}
+
+/// Whether the preamble already loads `babel` or `polyglossia`.
+pub fn declares_language(source: &str) -> bool {
+    let re = Regex::new(r"\\usepackage\s*(?:\[[^\]]*\])?\s*\{[^}]*\b(babel|polyglossia)\b").unwrap();
+    preamble(source).lines().map(strip_comment).any(|line| re.is_match(line))
+}
+
+/// The lines to load `language`: `polyglossia` when the document already uses `fontspec`,
+/// `babel` otherwise. Both pull in the language's hyphenation patterns.
+pub fn configuration(source: &str, language: &Language) -> String {
+    let fontspec = Regex::new(r"\\usepackage\s*(?:\[[^\]]*\])?\s*\{[^}]*\b(fontspec|unicode-math)\b").unwrap();
+    if preamble(source).lines().map(strip_comment).any(|line| fontspec.is_match(line)) {
+        format!("\\usepackage{{polyglossia}}\n\\setdefaultlanguage{{{}}}", language.polyglossia)
+    } else {
+        format!("\\usepackage[{}]{{babel}}", language.babel)
+    }
+}
+
+/// Loads `requested` (a code or name, or "auto" to detect it from `sources`) into `main`
+/// right after `\documentclass`, unless its preamble already selects a language. Detected
+/// English is left alone since LaTeX hyphenates in English by default. Returns the
+/// rewritten source and the language loaded, if any.
+pub fn apply<'a>(main: &str, requested: &str, sources: impl IntoIterator<Item = &'a str>) -> Result<(String, Option<&'static Language>), String> {
+    if declares_language(main) {
+        return Ok((main.to_string(), None));
+    }
+    let language = if requested.eq_ignore_ascii_case("auto") {
+        match detect(sources) {
+            Some(language) if language.code != "en" => language,
+            _ => return Ok((main.to_string(), None)),
+        }
+    } else {
+        let codes: Vec<&str> = LANGUAGES.iter().map(|l| l.code).collect();
+        find(requested).ok_or_else(|| format!("Unknown language '{}' (expected auto or one of {})", requested, codes.join(", ")))?
+    };
+    let source = crate::rewrite::insert_after_class(main, &configuration(main, language))?;
+    Ok((source, Some(language)))
+}
+
+fn preamble(source: &str) -> &str {
+    source.find("\\begin{document}").map_or(source, |end| &source[..end])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SPANISH: &str = "\\documentclass{article}\n\\begin{document}\n\\section{Introducción}\nEl objetivo de este trabajo es analizar la evolución de los precios en el mercado, con especial atención a las variaciones que se producen durante el verano. % the end\nLos resultados muestran que la demanda crece más que la oferta, por lo que los precios suben $x = y$.\n\\end{document}\n";
+
+    #[test]
+    fn test_detect() {
+        assert_eq!(detect([SPANISH]).map(|l| l.code), Some("es"));
+        let english = "The aim of this work is to analyse the evolution of prices in the market, with special attention to the changes that happen during the summer, and it shows that demand grows faster than supply.";
+        assert_eq!(detect([english]).map(|l| l.code), Some("en"));
+        let german = "Das Ziel dieser Arbeit ist es, die Entwicklung der Preise auf dem Markt zu untersuchen, und die Ergebnisse zeigen, dass die Nachfrage schneller wächst als das Angebot, was sich auch auf den Handel auswirkt.";
+        assert_eq!(detect([german]).map(|l| l.code), Some("de"));
+        assert!(detect(["\\documentclass{article}\\begin{document}$E = mc^2$\\end{document}"]).is_none());
+    }
+
+    #[test]
+    fn test_configuration() {
+        assert!(!declares_language(SPANISH));
+        assert!(declares_language("\\documentclass{article}\n\\usepackage[T1]{fontenc}\n\\usepackage[spanish]{babel}\n"));
+        assert!(!declares_language("\\documentclass{article}\n% \\usepackage{babel}\n"));
+
+        let spanish = find("es-MX").unwrap();
+        assert_eq!(configuration(SPANISH, spanish), "\\usepackage[spanish]{babel}");
+        assert_eq!(configuration("\\usepackage{fontspec}\n\\begin{document}", find("German").unwrap()), "\\usepackage{polyglossia}\n\\setdefaultlanguage{german}");
+        assert!(find("xx").is_none());
+    }
+
+    #[test]
+    fn test_apply() {
+        let (source, language) = apply(SPANISH, "auto", [SPANISH]).unwrap();
+        assert_eq!(language.map(|l| l.name), Some("Spanish"));
+        assert!(source.starts_with("\\documentclass{article}\n\\usepackage[spanish]{babel}\n"));
+
+        let (again, language) = apply(&source, "fr", [SPANISH]).unwrap();
+        assert!(language.is_none());
+        assert_eq!(again, source);
+        assert!(apply(SPANISH, "klingon", [SPANISH]).is_err());
+    }
+}
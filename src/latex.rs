@@ -0,0 +1,103 @@
+/// Escapes text so it is typeset literally inside a LaTeX document.
+pub fn escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' => out.push_str("\\textbackslash{}"),
+            '{' => out.push_str("\\{"),
+            '}' => out.push_str("\\}"),
+            '$' => out.push_str("\\$"),
+            '&' => out.push_str("\\&"),
+            '#' => out.push_str("\\#"),
+            '%' => out.push_str("\\%"),
+            '_' => out.push_str("\\_"),
+            '^' => out.push_str("\\textasciicircum{}"),
+            '~' => out.push_str("\\textasciitilde{}"),
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+/// Like [`escape`], but keeps line breaks: single newlines become `\\` and blank lines
+/// stay paragraph breaks.
+pub fn escape_lines(text: &str) -> String {
+    text.replace("\r\n", "\n").split("\n\n")
+        .map(|paragraph| paragraph.lines().map(escape).collect::<Vec<_>>().join("\\\\\n"))
+        .collect::<Vec<_>>()
+        .join("\n\n")
+}
+
+/// Escapes every string in a JSON value (object keys are left as they are), so a whole
+/// set of template fields can be made safe at once.
+pub fn escape_json(value: &serde_json::Value, escape_text: fn(&str) -> String) -> serde_json::Value {
+    use serde_json::Value;
+    match value {
+        Value::String(text) => Value::String(escape_text(text)),
+        Value::Array(items) => Value::Array(items.iter().map(|v| escape_json(v, escape_text)).collect()),
+        Value::Object(fields) => Value::Object(fields.iter().map(|(k, v)| (k.clone(), escape_json(v, escape_text))).collect()),
+        other => other.clone(),
+    }
+}
+
+/// Builds a hyperref link around plain text.
+pub fn href(url: &str, text: &str) -> String {
+    format!("\\href{{{}}}{{{}}}", url_argument(url), escape(text))
+}
+
+/// A URL as a hyperref argument: characters that would break out of it are dropped, and
+/// `%`/`#` are escaped as hyperref expects.
+pub fn url_argument(url: &str) -> String {
+    let mut target = String::with_capacity(url.len());
+    for c in url.chars() {
+        match c {
+            '\\' | '{' | '}' => {}
+            c if c.is_whitespace() => {}
+            '%' => target.push_str("\\%"),
+            '#' => target.push_str("\\#"),
+            _ => target.push(c),
+        }
+    }
+    target
+}
+
+/// Substitutes `<<NAME>>` placeholders in a single pass, so values that happen to
+/// contain placeholder syntax are left alone. Unknown placeholders become empty.
+pub fn fill_template(template: &str, values: &[(&str, &str)]) -> String {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("<<") {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        match after.find(">>") {
+            Some(end) if after[..end].chars().all(|c| c.is_ascii_uppercase() || c == '_') => {
+                let name = &after[..end];
+                out.push_str(values.iter().find(|(k, _)| *k == name).map(|(_, v)| *v).unwrap_or(""));
+                rest = &after[end + 2..];
+            }
+            _ => {
+                out.push_str("<<");
+                rest = after;
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_escape() {
+        assert_eq!(escape(r"50% of $x_1 & #2 {a} ~^\"), r"50\% of \$x\_1 \& \#2 \{a\} \textasciitilde{}\textasciicircum{}\textbackslash{}");
+        assert_eq!(escape_lines("a&b\r\nc\n\nd"), "a\\&b\\\\\nc\n\nd");
+    }
+
+    #[test]
+    fn test_escape_json() {
+        let fields = serde_json::json!({ "name": "R&D", "tags": ["50%", 3], "nested": { "a_b": "x_y" }, "ok": true });
+        assert_eq!(escape_json(&fields, escape), serde_json::json!({ "name": "R\\&D", "tags": ["50\\%", 3], "nested": { "a_b": "x\\_y" }, "ok": true }));
+    }
+}
@@ -0,0 +1,118 @@
+//! Request body limits: a server default, overridable per route and per tenant, enforced
+//! while the body streams in so an oversized upload is answered with a JSON 413 as soon as
+//! it crosses the limit instead of failing somewhere inside the handler.
+
+use axum::body::Body;
+use axum::extract::{Request, State};
+use axum::http::{header, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use futures_util::StreamExt;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use tracing::warn;
+
+use crate::models::PayloadTooLarge;
+use crate::settings::Settings;
+
+const MB: u64 = 1024 * 1024;
+
+/// The body limit for a request, in bytes. A tenant limit (`X-Tenant-Id`) applies to every
+/// route; otherwise the longest matching route prefix wins, then the server default.
+pub fn body_limit(settings: &Settings, path: &str, tenant: Option<&str>) -> u64 {
+    if let Some((_, mb)) = tenant.and_then(|t| settings.tenant_body_limits_mb.iter().find(|(name, _)| name == t)) {
+        return mb * MB;
+    }
+    settings
+        .route_body_limits_mb
+        .iter()
+        .filter(|(prefix, _)| path == prefix || path.starts_with(&format!("{}/", prefix.trim_end_matches('/'))))
+        .max_by_key(|(prefix, _)| prefix.len())
+        .map_or(settings.body_limit_mb, |(_, mb)| *mb)
+        * MB
+}
+
+/// Middleware rejecting bodies over `body_limit`: up front when `Content-Length` says so,
+/// otherwise once the streamed bytes cross it, replacing whatever the handler answered.
+pub async fn enforce(State(settings): State<Arc<Settings>>, request: Request, next: Next) -> Response {
+    let tenant = request.headers().get("X-Tenant-Id").and_then(|v| v.to_str().ok());
+    let limit = body_limit(&settings, request.uri().path(), tenant);
+    let declared = request.headers().get(header::CONTENT_LENGTH).and_then(|v| v.to_str().ok()).and_then(|v| v.parse::<u64>().ok());
+    if let Some(declared) = declared.filter(|d| *d > limit) {
+        warn!("🚫 Rejected {} byte body for {} (limit {})", declared, request.uri().path(), limit);
+        return too_large(limit, declared);
+    }
+
+    let received = Arc::new(AtomicU64::new(0));
+    let exceeded = Arc::new(AtomicBool::new(false));
+    let (parts, body) = request.into_parts();
+    let body = limited_body(body, limit, received.clone(), exceeded.clone());
+    let response = next.run(Request::from_parts(parts, body)).await;
+    if exceeded.load(Ordering::Relaxed) {
+        let received = received.load(Ordering::Relaxed);
+        warn!("🚫 Cut off a body after {} bytes (limit {})", received, limit);
+        return too_large(limit, received);
+    }
+    response
+}
+
+/// Passes `body` through, counting bytes into `received`; past `limit` it sets `exceeded`
+/// and ends with an error, so extractors stop reading.
+fn limited_body(body: Body, limit: u64, received: Arc<AtomicU64>, exceeded: Arc<AtomicBool>) -> Body {
+    Body::from_stream(body.into_data_stream().map(move |chunk| {
+        let chunk = chunk?;
+        let total = received.fetch_add(chunk.len() as u64, Ordering::Relaxed) + chunk.len() as u64;
+        if total > limit {
+            exceeded.store(true, Ordering::Relaxed);
+            return Err(axum::Error::new(format!("request body exceeds the limit of {} bytes", limit)));
+        }
+        Ok(chunk)
+    }))
+}
+
+fn too_large(limit: u64, received: u64) -> Response {
+    (
+        StatusCode::PAYLOAD_TOO_LARGE,
+        Json(PayloadTooLarge {
+            error: format!("Request body exceeds the limit of {} bytes", limit),
+            limit_bytes: limit,
+            received_bytes: received,
+        }),
+    ).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_body_limit_precedence() {
+        let settings = Settings {
+            body_limit_mb: 100,
+            route_body_limits_mb: vec![("/convert".into(), 20), ("/convert/docx".into(), 30)],
+            tenant_body_limits_mb: vec![("acme".into(), 500)],
+            ..Settings::from_env()
+        };
+        assert_eq!(body_limit(&settings, "/compile", None), 100 * MB);
+        assert_eq!(body_limit(&settings, "/convert/html", None), 20 * MB);
+        assert_eq!(body_limit(&settings, "/convert/docx", None), 30 * MB);
+        assert_eq!(body_limit(&settings, "/converter", None), 100 * MB);
+        assert_eq!(body_limit(&settings, "/convert/docx", Some("acme")), 500 * MB);
+        assert_eq!(body_limit(&settings, "/compile", Some("other")), 100 * MB);
+    }
+
+    #[tokio::test]
+    async fn test_streamed_body_cut_off() {
+        let (received, exceeded) = (Arc::new(AtomicU64::new(0)), Arc::new(AtomicBool::new(false)));
+        let body = limited_body(Body::from("0123456789"), 5, received.clone(), exceeded.clone());
+        assert!(axum::body::to_bytes(body, usize::MAX).await.is_err());
+        assert!(exceeded.load(Ordering::Relaxed));
+        assert_eq!(received.load(Ordering::Relaxed), 10);
+
+        let exceeded = Arc::new(AtomicBool::new(false));
+        let body = limited_body(Body::from("0123456789"), 10, Arc::new(AtomicU64::new(0)), exceeded.clone());
+        assert_eq!(axum::body::to_bytes(body, usize::MAX).await.unwrap(), "0123456789");
+        assert!(!exceeded.load(Ordering::Relaxed));
+    }
+}
@@ -0,0 +1,214 @@
+//! Where the HTTP API listens (LISTEN): any number of TCP addresses, IPv4 or IPv6 (`[::]`
+//! accepts both), `https://` addresses terminated here with rustls, and Unix domain
+//! sockets for sidecar deployments behind nginx or another local proxy.
+
+use axum::extract::ConnectInfo;
+use axum::{Extension, Router};
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use hyper_util::server::conn::auto;
+use hyper_util::service::TowerToHyperService;
+use std::fmt;
+use std::net::{Ipv6Addr, SocketAddr};
+use std::os::unix::fs::PermissionsExt;
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_rustls::TlsAcceptor;
+use tracing::{debug, info};
+
+use crate::services::AppState;
+
+/// Time a client has to complete the TLS handshake.
+const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Pause after a failed accept (e.g. out of file descriptors), as `axum::serve` does.
+const ACCEPT_ERROR_DELAY: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Listener {
+    Http(SocketAddr),
+    Https(SocketAddr),
+    Unix(PathBuf),
+}
+
+impl Listener {
+    /// Parses a LISTEN entry; a bare port listens on every IPv6 and IPv4 address.
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        if let Some(path) = spec.strip_prefix("unix:") {
+            return match path.trim() {
+                "" => Err("unix: listener needs a socket path".to_string()),
+                path => Ok(Listener::Unix(PathBuf::from(path))),
+            };
+        }
+        let (tls, address) = match spec.strip_prefix("https://") {
+            Some(address) => (true, address),
+            None => (false, spec.strip_prefix("http://").unwrap_or(spec)),
+        };
+        let address = address.trim_end_matches('/');
+        let address = match address.parse::<u16>() {
+            Ok(port) => SocketAddr::from((Ipv6Addr::UNSPECIFIED, port)),
+            Err(_) => address.parse().map_err(|_| format!("Invalid listen address '{}' (expected host:port, [v6]:port or unix:/path)", spec))?,
+        };
+        Ok(if tls { Listener::Https(address) } else { Listener::Http(address) })
+    }
+}
+
+impl fmt::Display for Listener {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Listener::Http(address) => write!(f, "http://{}", address),
+            Listener::Https(address) => write!(f, "https://{}", address),
+            Listener::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+enum Bound {
+    Http(tokio::net::TcpListener),
+    Https(tokio::net::TcpListener, TlsAcceptor),
+    Unix(tokio::net::UnixListener),
+}
+
+/// Binds every LISTEN entry, failing before anything is served if one cannot be bound,
+/// then serves `app` on all of them.
+pub async fn serve(app: Router, state: &AppState) -> Result<(), String> {
+    let settings = &state.settings;
+    let listeners = settings.listen.iter().map(|spec| Listener::parse(spec)).collect::<Result<Vec<_>, _>>()?;
+    if listeners.is_empty() {
+        return Err("LISTEN names no listener".to_string());
+    }
+    let mut acceptor = None;
+    let mut bound = Vec::with_capacity(listeners.len());
+    for listener in &listeners {
+        bound.push(match listener {
+            Listener::Http(address) => Bound::Http(bind_tcp(*address)?),
+            Listener::Https(address) => {
+                if acceptor.is_none() {
+                    acceptor = Some(crate::tls::acceptor(state).await?);
+                }
+                Bound::Https(bind_tcp(*address)?, acceptor.clone().unwrap())
+            }
+            Listener::Unix(path) => Bound::Unix(bind_unix(path, settings.unix_socket_mode)?),
+        });
+        info!("🚀 Tachyon-Tex Server listening on {}", listener);
+    }
+
+    let tasks: Vec<_> = bound.into_iter().map(|bound| tokio::spawn(accept_loop(bound, app.clone()))).collect();
+    for task in tasks {
+        task.await.map_err(|e| e.to_string())??;
+    }
+    Ok(())
+}
+
+fn bind_tcp(address: SocketAddr) -> Result<tokio::net::TcpListener, String> {
+    let socket = socket2::Socket::new(socket2::Domain::for_address(address), socket2::Type::STREAM, Some(socket2::Protocol::TCP))
+        .map_err(|e| format!("Failed to bind {}: {}", address, e))?;
+    if address.is_ipv6() && address.ip().is_unspecified() {
+        // Dual-stack whatever the system default (net.ipv6.bindv6only) is
+        socket.set_only_v6(false).map_err(|e| format!("Failed to bind {}: {}", address, e))?;
+    }
+    socket.set_reuse_address(true).ok();
+    socket.set_nonblocking(true).map_err(|e| format!("Failed to bind {}: {}", address, e))?;
+    socket.bind(&address.into()).map_err(|e| format!("Failed to bind {}: {}", address, e))?;
+    socket.listen(1024).map_err(|e| format!("Failed to listen on {}: {}", address, e))?;
+    tokio::net::TcpListener::from_std(socket.into()).map_err(|e| format!("Failed to bind {}: {}", address, e))
+}
+
+fn bind_unix(path: &std::path::Path, mode: u32) -> Result<tokio::net::UnixListener, String> {
+    // A socket file left by a previous run would make bind fail
+    if std::fs::symlink_metadata(path).is_ok_and(|m| std::os::unix::fs::FileTypeExt::is_socket(&m.file_type())) {
+        std::fs::remove_file(path).ok();
+    }
+    let listener = tokio::net::UnixListener::bind(path).map_err(|e| format!("Failed to bind unix:{}: {}", path.display(), e))?;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))
+        .map_err(|e| format!("Failed to set permissions of {}: {}", path.display(), e))?;
+    Ok(listener)
+}
+
+async fn accept_loop(bound: Bound, app: Router) -> Result<(), String> {
+    match bound {
+        // The peer address identifies clients for per-client quotas; Unix sockets have none
+        Bound::Http(listener) => axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>()).await.map_err(|e| e.to_string()),
+        Bound::Https(listener, acceptor) => loop {
+            let (stream, peer) = match listener.accept().await {
+                Ok(connection) => connection,
+                Err(_) => {
+                    tokio::time::sleep(ACCEPT_ERROR_DELAY).await;
+                    continue;
+                }
+            };
+            let (acceptor, app) = (acceptor.clone(), app.clone().layer(Extension(ConnectInfo(peer))));
+            tokio::spawn(async move {
+                match tokio::time::timeout(TLS_HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                    Ok(Ok(stream)) => serve_connection(stream, app).await,
+                    Ok(Err(e)) => debug!("TLS handshake with {} failed: {}", peer, e),
+                    Err(_) => debug!("TLS handshake with {} timed out", peer),
+                }
+            });
+        },
+        Bound::Unix(listener) => loop {
+            let stream = match listener.accept().await {
+                Ok((stream, _)) => stream,
+                Err(_) => {
+                    tokio::time::sleep(ACCEPT_ERROR_DELAY).await;
+                    continue;
+                }
+            };
+            tokio::spawn(serve_connection(stream, app.clone()));
+        },
+    }
+}
+
+/// Serves HTTP/1.1 (with upgrades, for /ws) or HTTP/2 on one connection.
+async fn serve_connection<I>(io: I, app: Router)
+where
+    I: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let service = TowerToHyperService::new(app);
+    if let Err(e) = auto::Builder::new(TokioExecutor::new()).serve_connection_with_upgrades(TokioIo::new(io), service).await {
+        debug!("Connection error: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(Listener::parse("0.0.0.0:8080"), Ok(Listener::Http("0.0.0.0:8080".parse().unwrap())));
+        assert_eq!(Listener::parse("8080"), Ok(Listener::Http("[::]:8080".parse().unwrap())));
+        assert_eq!(Listener::parse("https://[::1]:8443/"), Ok(Listener::Https("[::1]:8443".parse().unwrap())));
+        assert_eq!(Listener::parse("unix:/run/tachyon.sock"), Ok(Listener::Unix("/run/tachyon.sock".into())));
+        assert!(Listener::parse("localhost:8080").is_err());
+        assert!(Listener::parse("unix:").is_err());
+        assert_eq!(Listener::parse("https://127.0.0.1:8443").unwrap().to_string(), "https://127.0.0.1:8443");
+    }
+
+    #[tokio::test]
+    async fn test_serves_unix_socket() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("tachyon.sock");
+        let listener = bind_unix(&path, 0o600).unwrap();
+        assert_eq!(std::fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        let app = Router::new().route("/health", axum::routing::get(|| async { "ok" }));
+        tokio::spawn(accept_loop(Bound::Unix(listener), app));
+
+        let mut stream = tokio::net::UnixStream::connect(&path).await.unwrap();
+        stream.write_all(b"GET /health HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+        assert!(response.ends_with("ok"));
+
+        // A stale socket from a previous run is replaced
+        assert!(bind_unix(&path, 0o660).is_ok());
+    }
+
+    #[test]
+    fn test_https_needs_certificate() {
+        let settings = crate::settings::Settings { tls_cert_file: None, tls_key_file: None, ..crate::settings::Settings::from_env() };
+        assert!(crate::tls::certificate_files(&settings).err().unwrap().contains("TLS_CERT_FILE"));
+    }
+}
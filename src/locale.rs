@@ -0,0 +1,179 @@
+//! Locale-aware formatting of numbers, money and dates for generated documents, done in
+//! Rust so templates receive finished LaTeX text instead of relying on TeX packages.
+
+use crate::latex::escape;
+
+/// Formatting conventions of one language. Strings are already LaTeX.
+pub struct Locale {
+    pub code: &'static str,
+    decimal: &'static str,
+    /// French uses a thin space
+    thousands: &'static str,
+    symbol_first: bool,
+    months: [&'static str; 12],
+    short_months: [&'static str; 12],
+    /// Full date with `{d}`, `{month}` and `{y}` placeholders
+    date_pattern: &'static str,
+    /// End of a date range that is still ongoing
+    pub present: &'static str,
+}
+
+const LOCALES: &[Locale] = &[
+    Locale {
+        code: "en", decimal: ".", thousands: ",", symbol_first: true,
+        months: ["January", "February", "March", "April", "May", "June", "July", "August", "September", "October", "November", "December"],
+        short_months: ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"],
+        date_pattern: "{month} {d}, {y}", present: "Present",
+    },
+    Locale {
+        code: "es", decimal: ",", thousands: ".", symbol_first: false,
+        months: ["enero", "febrero", "marzo", "abril", "mayo", "junio", "julio", "agosto", "septiembre", "octubre", "noviembre", "diciembre"],
+        short_months: ["ene.", "feb.", "mar.", "abr.", "may.", "jun.", "jul.", "ago.", "sept.", "oct.", "nov.", "dic."],
+        date_pattern: "{d} de {month} de {y}", present: "Actualidad",
+    },
+    Locale {
+        code: "de", decimal: ",", thousands: ".", symbol_first: false,
+        months: ["Januar", "Februar", "März", "April", "Mai", "Juni", "Juli", "August", "September", "Oktober", "November", "Dezember"],
+        short_months: ["Jan.", "Feb.", "März", "Apr.", "Mai", "Juni", "Juli", "Aug.", "Sept.", "Okt.", "Nov.", "Dez."],
+        date_pattern: "{d}.~{month} {y}", present: "heute",
+    },
+    Locale {
+        code: "fr", decimal: ",", thousands: "\\,", symbol_first: false,
+        months: ["janvier", "février", "mars", "avril", "mai", "juin", "juillet", "août", "septembre", "octobre", "novembre", "décembre"],
+        short_months: ["janv.", "févr.", "mars", "avr.", "mai", "juin", "juil.", "août", "sept.", "oct.", "nov.", "déc."],
+        date_pattern: "{d} {month} {y}", present: "aujourd'hui",
+    },
+];
+
+/// Currency symbols (already LaTeX) and minor unit digits; other ISO codes print as the code.
+const CURRENCIES: &[(&str, &str, usize)] = &[
+    ("USD", "\\$", 2),
+    ("EUR", "€", 2),
+    ("GBP", "£", 2),
+    ("JPY", "¥", 0),
+    ("CHF", "CHF", 2),
+    ("MXN", "MX\\$", 2),
+];
+
+/// Symbol and minor unit digits of an ISO 4217 code.
+pub fn currency(code: &str) -> Result<(String, usize), String> {
+    if code.len() != 3 || !code.chars().all(|c| c.is_ascii_uppercase()) {
+        return Err(format!("Currency must be an ISO 4217 code such as EUR, got '{}'", code));
+    }
+    Ok(CURRENCIES.iter().find(|(c, _, _)| *c == code)
+        .map(|(_, symbol, digits)| (symbol.to_string(), *digits))
+        .unwrap_or_else(|| (code.to_string(), 2)))
+}
+
+impl Locale {
+    /// Resolves "en", "es-MX", "de_DE", ... to a supported locale (default English).
+    pub fn resolve(requested: Option<&str>) -> Result<&'static Locale, String> {
+        let code = requested.unwrap_or("en").split(['-', '_']).next().unwrap_or("").to_ascii_lowercase();
+        LOCALES.iter().find(|l| l.code == code)
+            .ok_or_else(|| format!("Unsupported locale '{}' (expected one of en, es, de, fr)", requested.unwrap_or_default()))
+    }
+
+    /// Formats with grouped thousands and the locale's decimal mark.
+    pub fn number(&self, value: f64, digits: usize) -> String {
+        let fixed = format!("{:.*}", digits, value.abs());
+        let (int_part, frac_part) = fixed.split_once('.').unwrap_or((&fixed, ""));
+
+        let mut grouped = String::new();
+        for (i, c) in int_part.chars().enumerate() {
+            if i > 0 && (int_part.len() - i) % 3 == 0 {
+                grouped.push_str(self.thousands);
+            }
+            grouped.push(c);
+        }
+
+        let sign = if value < 0.0 && fixed.chars().any(|c| c.is_ascii_digit() && c != '0') { "-" } else { "" };
+        if frac_part.is_empty() {
+            format!("{}{}", sign, grouped)
+        } else {
+            format!("{}{}{}{}", sign, grouped, self.decimal, frac_part)
+        }
+    }
+
+    /// An amount in `currency`, e.g. `\$1,234.56` or `1.234,56~€`.
+    pub fn money(&self, amount: f64, currency_code: &str) -> String {
+        let (symbol, digits) = currency(currency_code).unwrap_or_else(|_| (escape(currency_code), 2));
+        let number = self.number(amount, digits);
+        let separator = if symbol.chars().all(|c| c.is_ascii_uppercase()) { "~" } else { "" };
+        if self.symbol_first {
+            format!("{}{}{}", symbol, separator, number)
+        } else {
+            format!("{}~{}", number, symbol)
+        }
+    }
+
+    /// Long form of an ISO 8601 date: "2024-03-01" becomes "March 1, 2024" or "1 de marzo
+    /// de 2024"; "2024-03" and "2024" lose the missing parts. Anything else is kept as text.
+    pub fn date(&self, date: &str) -> String {
+        match parse_date(date) {
+            Some((year, Some(month), Some(day))) => self.date_pattern
+                .replace("{d}", &day.to_string())
+                .replace("{month}", self.months[month - 1])
+                .replace("{y}", year),
+            Some((year, Some(month), None)) => format!("{} {}", self.months[month - 1], year),
+            Some((year, _, _)) => year.to_string(),
+            None => escape(date),
+        }
+    }
+
+    /// Short month and year ("Mar 2019", "mar. 2019"), as used in CV date ranges.
+    pub fn month_year(&self, date: &str) -> String {
+        match parse_date(date) {
+            Some((year, Some(month), _)) => format!("{} {}", self.short_months[month - 1], year),
+            Some((year, None, _)) => year.to_string(),
+            None => escape(date),
+        }
+    }
+}
+
+/// Splits "YYYY", "YYYY-MM" or "YYYY-MM-DD" into validated parts.
+fn parse_date(date: &str) -> Option<(&str, Option<usize>, Option<u32>)> {
+    let mut parts = date.trim().split('-');
+    let year = parts.next().filter(|y| y.len() == 4 && y.chars().all(|c| c.is_ascii_digit()))?;
+    let month = match parts.next() {
+        Some(m) => Some(m.parse::<usize>().ok().filter(|m| (1..=12).contains(m))?),
+        None => None,
+    };
+    let day = match parts.next() {
+        Some(d) => Some(d.get(..2).unwrap_or(d).parse::<u32>().ok().filter(|d| (1..=31).contains(d))?),
+        None => None,
+    };
+    Some((year, month, day))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_numbers_and_money() {
+        let en = Locale::resolve(None).unwrap();
+        let es = Locale::resolve(Some("es-MX")).unwrap();
+        let fr = Locale::resolve(Some("fr_FR")).unwrap();
+        assert_eq!(en.money(1234.56, "USD"), "\\$1,234.56");
+        assert_eq!(es.money(1234.56, "EUR"), "1.234,56~€");
+        assert_eq!(fr.number(-1234567.0, 0), "-1\\,234\\,567");
+        assert_eq!(en.money(99.0, "CHF"), "CHF~99.00");
+        assert!(Locale::resolve(Some("pt")).is_err());
+        assert!(currency("eur").is_err());
+    }
+
+    #[test]
+    fn test_dates() {
+        let en = Locale::resolve(Some("en")).unwrap();
+        let es = Locale::resolve(Some("es")).unwrap();
+        let de = Locale::resolve(Some("de")).unwrap();
+        assert_eq!(en.date("2024-03-01"), "March 1, 2024");
+        assert_eq!(es.date("2024-03-01"), "1 de marzo de 2024");
+        assert_eq!(de.date("2024-03-01T10:00:00Z"), "1.~März 2024");
+        assert_eq!(es.date("2024-03"), "marzo 2024");
+        assert_eq!(en.date("Spring & Summer"), "Spring \\& Summer");
+        assert_eq!(en.date("2024-13-01"), "2024-13-01");
+        assert_eq!(es.month_year("2019-08-15"), "ago. 2019");
+        assert_eq!(en.month_year("2019"), "2019");
+    }
+}
@@ -1,32 +1,100 @@
 use axum::{
     extract::DefaultBodyLimit,
-    routing::{get, post},
+    routing::{delete, get, patch, post, put},
     Router,
 };
 use std::sync::Arc;
 use tokio::sync::RwLock;
 use tracing::{info, Level};
 use tracing_subscriber::FmtSubscriber;
-use tower_http::cors::CorsLayer;
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
 use tower_http::compression::CompressionLayer;  // Moonshot #3: Zstd compression
-use tower_http::services::ServeDir;
 use std::time::Duration;
 
 mod models;
 mod services;
 mod handlers;
 mod mcp;
+mod bib;
+mod assets;
+mod explain;
+mod settings;
+mod barcode;
+mod latex;
+mod generate;
+mod render;
+mod webhooks;
+mod receipt;
+mod storage;
+mod workers;
+mod grpc;
+mod openapi;
+mod cli;
+mod compression;
+mod delta;
+mod pages;
+mod janitor;
+mod shard;
+mod validator;
+mod rewrite;
+mod locale;
+mod language;
+mod sanitize;
+mod html;
+mod docx;
+mod notebook;
+mod exam;
+mod checksum;
+mod estimate;
+mod fingerprint;
+mod limits;
+mod uploads;
+mod overleaf;
+mod export;
+mod sandbox;
+mod filetypes;
+mod encryption;
+mod tenancy;
+mod retention;
+mod jobs;
+mod streaming;
+mod listeners;
+mod tls;
+mod acme;
+mod cors;
+mod frontend;
+mod playground;
+mod failures;
+mod tutor;
+mod grade;
+mod matrix;
+mod regression;
+mod cron;
+mod schedules;
+mod connectors;
+mod packs;
+mod registry;
+mod texlog;
+mod warnings;
+mod bundles;
+mod optimize;
+mod print;
+mod impose;
+mod signing;
+mod forms;
+mod stamp;
+mod util;
 pub mod compiler;
 pub mod healer;
 
 use crate::models::*;
 use crate::services::*;
 use crate::handlers::*;
+use crate::settings::Settings;
 
 const CACHE_CLEANUP_INTERVAL_SECS: u64 = 3600; // 1 hour
 
 use clap::{Parser, Subcommand};
-use crate::compiler::Compiler;
 use std::path::PathBuf;
 
 #[derive(Parser)]
@@ -39,29 +107,91 @@ struct Cli {
     /// Run in warmup mode (exit after caching resources)
     #[arg(long, global = true)]
     warmup: bool,
+
+    /// Run the MCP server over stdin/stdout as the main process (logs go to stderr)
+    #[arg(long, alias = "mcp", conflicts_with = "mcp_http")]
+    mcp_stdio: bool,
+
+    /// Run only the MCP server, over streamable HTTP (with SSE) at `<ADDR>/mcp`
+    #[arg(long, value_name = "ADDR")]
+    mcp_http: Option<String>,
+
+    /// With --mcp-stdio or --mcp-http, also serve the HTTP API, sharing the same state
+    #[arg(long)]
+    http: bool,
 }
 
 #[derive(Subcommand)]
 enum Commands {
     /// Start the backend server (default)
     Serve,
-    /// Compile a LaTeX file directly
+    /// Compile a LaTeX file (with every file in its directory) to PDF
     Compile {
-        /// Input file path
+        /// Main .tex file
+        file: PathBuf,
+        /// Output path; defaults to the input with a .pdf extension
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        /// Compile on a running server instead of the embedded engine
+        #[arg(long, env = "TACHYON_SERVER")]
+        server: Option<String>,
+        /// Recompile whenever a project file changes
+        #[arg(long)]
+        watch: bool,
+        /// Bypass the server's caches
+        #[arg(long)]
+        force: bool,
+    },
+    /// Compile, then recompile on every change (same as `compile --watch`)
+    Watch {
+        /// Main .tex file
         file: PathBuf,
+        /// Output path; defaults to the input with a .pdf extension
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        /// Compile on a running server instead of the embedded engine
+        #[arg(long, env = "TACHYON_SERVER")]
+        server: Option<String>,
+    },
+    /// Run static checks (citations against .bib files) over a project
+    Validate {
+        /// Main .tex file; its directory is checked
+        file: PathBuf,
+        /// Validate on a running server instead of locally
+        #[arg(long, env = "TACHYON_SERVER")]
+        server: Option<String>,
+    },
+    /// Render a math expression to PDF, SVG or PNG
+    RenderMath {
+        /// LaTeX math, without delimiters
+        expression: String,
+        /// Output path
+        #[arg(short, long, default_value = "math.pdf")]
+        output: PathBuf,
+        /// "pdf", "svg" or "png"; defaults to the output extension
+        #[arg(long)]
+        format: Option<String>,
+        /// Text style instead of display style
+        #[arg(long)]
+        inline: bool,
+        /// Compile on a running server instead of the embedded engine
+        #[arg(long, env = "TACHYON_SERVER")]
+        server: Option<String>,
     },
 }
 
 #[tokio::main]
 async fn main() {
-    // 1. Initialize Logging
+    let cli = Cli::parse();
+
+    // 1. Initialize Logging (stdout belongs to the protocol in MCP stdio mode)
+    let writer = if cli.mcp_stdio { BoxMakeWriter::new(std::io::stderr) } else { BoxMakeWriter::new(std::io::stdout) };
     let subscriber = FmtSubscriber::builder()
         .with_max_level(Level::INFO)
+        .with_writer(writer)
         .finish();
     tracing::subscriber::set_global_default(subscriber).expect("setting default subscriber failed");
 
-    let cli = Cli::parse();
-
     if cli.warmup {
         info!("🌌 Tachyon-Tex starting in WARMUP mode...");
     } else {
@@ -78,84 +208,387 @@ async fn main() {
         return;
     }
 
+    if cli.mcp_stdio || cli.mcp_http.is_some() {
+        let state = init_state(config, format_cache_path).await;
+        if cli.http {
+            tokio::spawn(serve_http(state.clone()));
+        }
+        match cli.mcp_http {
+            Some(addr) => serve_mcp_http(state, &addr).await,
+            None => serve_mcp_stdio(state).await,
+        }
+        return;
+    }
+
     match cli.command.unwrap_or(Commands::Serve) {
         Commands::Serve => {
-             run_server(config, format_cache_path).await;
-        }
-        Commands::Compile { file } => {
-            info!("📄 Compiling file: {:?}", file);
-            let output_dir = std::env::current_dir().unwrap();
-            let (result, logs) = Compiler::compile_file(
-                &file,
-                &output_dir,
-                &format_cache_path,
-                &config
-            );
-            
-            match result {
-                Ok(_) => info!("✅ Compilation successful!"),
-                Err(e) => {
-                    tracing::error!("❌ Compilation failed: {}", e);
-                    println!("{}", logs);
+            serve_http(init_state(config, format_cache_path).await).await;
+        }
+        Commands::Compile { file, output, server, watch, force } => {
+            let engine = cli::Engine::new(server, config, format_cache_path);
+            exit_on_error(cli::compile(&engine, &file, output, force, watch).await);
+        }
+        Commands::Watch { file, output, server } => {
+            let engine = cli::Engine::new(server, config, format_cache_path);
+            exit_on_error(cli::compile(&engine, &file, output, false, true).await);
+        }
+        Commands::Validate { file, server } => {
+            let engine = cli::Engine::new(server, config, format_cache_path);
+            match engine.validate(&file).await {
+                Ok(warnings) if warnings.is_empty() => info!("✅ No issues found"),
+                Ok(warnings) => {
+                    for warning in &warnings {
+                        println!("{}", warning);
+                    }
                     std::process::exit(1);
                 }
+                Err(e) => exit_on_error(Err(e)),
             }
         }
+        Commands::RenderMath { expression, output, format, inline, server } => {
+            let format = format.unwrap_or_else(|| {
+                output.extension().and_then(|e| e.to_str()).unwrap_or("pdf").to_ascii_lowercase()
+            });
+            let engine = cli::Engine::new(server, config, format_cache_path);
+            exit_on_error(cli::render_math(&engine, &expression, inline, &format, &output).await);
+        }
+    }
+}
+
+fn exit_on_error(result: Result<(), String>) {
+    if let Err(e) = result {
+        tracing::error!("❌ {}", e);
+        std::process::exit(1);
     }
 }
 
-async fn run_server(config: tectonic::config::PersistentConfig, format_cache_path: PathBuf) {
-     // 2. Initialize State and Services
-    let pdf_cache_enabled = std::env::var("PDF_CACHE_ENABLED").unwrap_or_else(|_| "true".to_string()) == "true";
-    let compilation_cache = CompilationCache::new(pdf_cache_enabled);
+/// Builds the shared state and starts its background tasks.
+async fn init_state(config: tectonic::config::PersistentConfig, format_cache_path: PathBuf) -> AppState {
+    // 2. Initialize State and Services
+    let settings = Settings::from_env();
+    let storage = match crate::storage::from_settings(&settings) {
+        Ok(storage) => storage,
+        Err(e) => {
+            tracing::error!("❌ {}", e);
+            std::process::exit(1);
+        }
+    };
+    info!("💾 Storage backend: {}", storage.name());
+    let storage = match crate::encryption::wrap(storage, &settings) {
+        Ok(storage) => storage,
+        Err(e) => {
+            tracing::error!("❌ {}", e);
+            std::process::exit(1);
+        }
+    };
+    let ledger = crate::tenancy::TenantLedger::new();
+    let storage: Arc<dyn crate::storage::Storage> = Arc::new(crate::tenancy::LedgerStorage::new(storage, ledger.clone()));
+    let compilation_cache = CompilationCache::new(settings.pdf_cache_enabled, storage.clone())
+        .with_stale_after(settings.stale_while_revalidate_secs)
+        .with_normalized_keys(settings.cache_normalize_keys)
+        .with_negative_ttl(settings.negative_cache_ttl_secs)
+        .with_compression_level(settings.cache_compression_level)
+        .with_healer_seeds(crate::healer::policy_seeds(&settings));
     let webhooks = Arc::new(RwLock::new(Vec::<WebhookSubscription>::new()));
-    let format_cache = FormatCache::new();
-    let blob_store = BlobStore::new();
+    let dead_letters = DeadLetterStore::new();
+    let mut format_cache = FormatCache::new();
+    if settings.shared_format_cache {
+        format_cache = format_cache.with_shared_storage(storage.clone());
+        info!("🔗 Sharing preamble registry and format files via {} storage", storage.name());
+    }
+    let blob_store = BlobStore::new(storage.clone());
+    let uploads = crate::uploads::UploadStore::new(blob_store.clone(), settings.upload_max_mb, settings.upload_ttl_secs);
+    let output_store = OutputStore::new(settings.output_store_max_mb, storage.clone());
+    let outputs = output_store.load().await;
+    if outputs > 0 {
+        info!("📦 Indexed {} stored output(s)", outputs);
+    }
+    let branding = BrandingStore::new();
+    let fingerprints = FingerprintIndex::new(settings.fingerprint_max_entries);
+    let compile_history = CompileHistory::new();
+    let registry = crate::registry::PackageRegistry::new(storage.clone(), compilation_cache.clone());
+    let registry_tenants = registry.load().await;
+    if registry_tenants > 0 {
+        info!("📚 Loaded the package registries of {} tenants", registry_tenants);
+    }
+    let projects = ProjectStore::new();
+    let bundles = crate::bundles::BundleManager::load(storage.clone(), &settings).await;
+    let sandbox = crate::sandbox::Sandbox::from_settings(&settings);
+    sandbox.report();
+    let signing = match crate::signing::SigningStore::new(storage.clone(), &settings) {
+        Ok(signing) => signing,
+        Err(e) => {
+            tracing::error!("❌ {}", e);
+            std::process::exit(1);
+        }
+    };
+    let receipt_signer = match crate::receipt::ReceiptSigner::load(settings.receipt_signing_key.as_deref(), storage.as_ref()).await {
+        Ok(signer) => signer,
+        Err(e) => {
+            tracing::error!("❌ {}", e);
+            std::process::exit(1);
+        }
+    };
+    info!("🔏 Compile receipts signed with key {}", receipt_signer.key_id());
+    let scheduler = CompileScheduler::new(&settings);
+    let workers = (!settings.compile_workers.is_empty()).then(|| {
+        let pool = crate::workers::WorkerPool::new(&settings.compile_workers, settings.worker_token.clone(), settings.worker_fallback_local);
+        info!("🛰️ Controller mode: dispatching compiles to {} worker(s)", pool.len());
+        Arc::new(pool)
+    });
+    let janitor = crate::janitor::Janitor::new(settings.workspace_quota_mb);
+    // Other instances may share the workspace directory: only what they left long ago goes
+    let sweeper = janitor.clone();
+    if let Ok(report) = tokio::task::spawn_blocking(move || sweeper.sweep_orphans(crate::janitor::ORPHAN_TTL)).await {
+        if report.removed > 0 {
+            info!("🧹 Removed {} leaked workspace(s) ({:.2} MB) from previous runs", report.removed, report.removed_bytes as f64 / 1024.0 / 1024.0);
+        }
+    }
+    info!("⚙️ Compile slots: {} (interactive share {:.0}%, batch share {:.0}%)",
+        settings.compile_concurrency, settings.interactive_share * 100.0, settings.batch_share * 100.0);
 
     let state = AppState { 
         compilation_cache: compilation_cache.clone(),
         webhooks: webhooks.clone(),
+        dead_letters,
         format_cache,
         blob_store,
+        uploads,
+        projects,
+        jobs: crate::jobs::JobStore::new(settings.async_max_jobs),
+        acme_challenges: crate::acme::Challenges::new(),
+        playground: crate::playground::PlaygroundStore::new(storage.clone()),
+        failures: crate::failures::FailureStore::new(storage.clone()),
+        regression: crate::regression::RegressionStore::new(storage.clone()),
+        schedules: crate::schedules::ScheduleStore::new(storage.clone()),
+        connectors: crate::connectors::ConnectorStore::new(storage.clone()),
+        packs: crate::packs::PackStore::new(storage.clone()),
+        registry,
+        bundles,
+        signing,
+        output_store,
+        branding,
+        fingerprints,
+        compile_history,
+        receipt_signer,
+        ledger,
+        storage,
+        sandbox,
+        scheduler,
+        janitor,
+        workers,
+        settings: Arc::new(settings),
         config: Arc::new(config),
         format_cache_path,
     };
 
     // 3. Background Tasks
     tokio::spawn(cache_cleanup_task(compilation_cache));
+    tokio::spawn(crate::retention::retention_task(state.clone()));
+    tokio::spawn(crate::playground::sweep_task(state.playground.clone()));
+    tokio::spawn(crate::failures::sweep_task(state.failures.clone()));
+    crate::bundles::activated(&state);
+    tokio::spawn(crate::regression::schedule_task(state.clone()));
+    tokio::spawn(crate::schedules::schedule_task(state.clone()));
+    tokio::spawn(crate::packs::sync_task(state.clone()));
+    tokio::spawn(janitor_task(state.janitor.clone(), Duration::from_secs(state.settings.workspace_sweep_interval_secs)));
+    if state.settings.shared_format_cache {
+        tokio::spawn(format_sync_task(
+            state.format_cache.clone(),
+            state.format_cache_path.clone(),
+            Duration::from_secs(state.settings.format_sync_interval_secs),
+        ));
+    }
+
+    state
+}
+
+async fn serve_http(state: AppState) {
+    if state.settings.grpc_port != 0 {
+        let grpc_addr = std::net::SocketAddr::new(state.settings.grpc_host, state.settings.grpc_port);
+        let grpc_service = crate::grpc::TachyonGrpc::server(state.clone());
+        tokio::spawn(async move {
+            info!("📡 gRPC API listening on {}", grpc_addr);
+            if let Err(e) = tonic::transport::Server::builder().add_service(grpc_service).serve(grpc_addr).await {
+                tracing::error!("gRPC server failed: {}", e);
+            }
+        });
+    }
 
     // 4. MCP Setup
-    let ct = tokio_util::sync::CancellationToken::new();
-    let mcp_state = state.clone();
-    let mcp_service = rmcp::transport::streamable_http_server::StreamableHttpService::new(
-        move || Ok(crate::mcp::TachyonMcpServer::new(mcp_state.clone())),
-        rmcp::transport::streamable_http_server::session::local::LocalSessionManager::default().into(),
-        rmcp::transport::streamable_http_server::StreamableHttpServerConfig {
-            cancellation_token: ct.child_token(),
-            ..Default::default()
-        },
-    );
+    let mcp_service = mcp_http_service(state.clone());
+
+    let cors = match crate::cors::layer(&state.settings) {
+        Ok(cors) => cors,
+        Err(e) => {
+            tracing::error!("❌ {}", e);
+            std::process::exit(1);
+        }
+    };
 
     // 5. Build API Router - Moonshot #3: Add compression for 70% smaller responses
     let app = Router::new()
         .route("/health", get(health_handler))
+        .route("/.well-known/acme-challenge/:token", get(acme_challenge_handler))
         .route("/compile", post(compile_handler))
+        .route("/compile/async", post(compile_async_handler))
+        .route("/compile/matrix", post(compile_matrix_handler))
+        .route("/compile/async/:id", get(async_job_handler))
+        .route("/playground", post(create_playground_handler))
+        .route("/playground/:id", get(get_playground_handler))
+        .route("/failures/:id", get(get_failure_handler))
+        .route("/grade/batch", post(grade_batch_handler))
+        .route("/tests", get(list_regression_tests_handler).post(create_regression_test_handler))
+        .route("/tests/:id", get(get_regression_test_handler).delete(delete_regression_test_handler))
+        .route("/tests/:id/run", post(run_regression_test_handler))
+        .route("/schedules", get(list_schedules_handler).post(create_schedule_handler))
+        .route("/schedules/:id", get(get_schedule_handler).delete(delete_schedule_handler))
+        .route("/schedules/:id/run", post(run_schedule_handler))
+        .route("/templates", get(list_template_packs_handler).post(register_template_pack_handler))
+        .route("/templates/:id", get(get_template_pack_handler).delete(delete_template_pack_handler))
+        .route("/templates/:id/sync", post(sync_template_pack_handler))
+        .route("/templates/:id/projects", post(create_template_project_handler))
+        .route("/registry/packages", get(list_registry_packages_handler).post(upload_registry_packages_handler))
+        .route("/registry/packages/:name", delete(delete_registry_package_handler))
+        .route("/admin/bundles", get(bundle_status_handler))
+        .route("/admin/bundles/updates", get(bundle_updates_handler))
+        .route("/admin/bundles/stage", post(stage_bundle_handler))
+        .route("/admin/bundles/staged", delete(unstage_bundle_handler))
+        .route("/admin/bundles/staged/trial", post(trial_bundle_handler))
+        .route("/admin/bundles/activate", post(activate_bundle_handler))
+        .route("/admin/bundles/rollback", post(rollback_bundle_handler))
+        .route("/admin/signing/:tenant", get(get_signing_handler).put(configure_signing_handler).delete(delete_signing_handler))
         .route("/validate", post(validate_handler))
+        .route("/bib/format", post(bib_format_handler))
+        .route("/escape", post(escape_handler))
+        .route("/convert/html", post(convert_html_handler))
+        .route("/convert/docx", post(convert_docx_handler))
+        .route("/convert/ipynb", post(convert_ipynb_handler))
+        .route("/stamp", post(stamp_handler))
+        .route("/assets", post(assets_handler))
+        .route("/assets/qr", post(barcode_handler))
+        .route("/outputs/:hash", get(output_handler))
+        .route("/similar/:hash", get(similar_handler))
+        .route("/estimate", post(estimate_handler))
+        .route("/import/overleaf", post(import_overleaf_handler))
+        .route("/projects/:id", get(get_project_handler))
+        .route("/projects/:id/compile", post(compile_project_handler))
+        .route("/projects/:id/export", get(export_project_handler))
+        .route("/projects/:id/connectors", get(list_connectors_handler))
+        .route("/projects/:id/connectors/:name", put(put_connector_handler).delete(delete_connector_handler))
+        .route("/projects/:id/connectors/:name/preview", post(preview_connector_handler))
+        .route("/tenants/:id/data", delete(erase_tenant_handler))
+        .route("/uploads", post(create_upload_handler))
+        .route("/uploads/:id", get(upload_status_handler).patch(patch_upload_handler).delete(delete_upload_handler))
+        .route("/receipts/public-key", get(receipt_key_handler))
+        .route("/receipts/verify", post(receipt_verify_handler))
+        .route("/render/chart", post(render_chart_handler))
+        .route("/render/table", post(render_table_handler))
+        .route("/generate/invoice", post(invoice_handler))
+        .route("/generate/certificate", post(certificate_handler))
+        .route("/generate/resume", post(resume_handler))
+        .route("/generate/report", post(report_handler))
+        .route("/generate/exam", post(exam_handler))
+        .route("/branding", post(create_branding_handler))
+        .route("/branding/:id", get(get_branding_handler).delete(delete_branding_handler))
+        .route("/webhooks", get(list_webhooks_handler).post(create_webhook_handler))
+        .route("/webhooks/:id", patch(update_webhook_handler).delete(delete_webhook_handler))
+        .route("/webhooks/:id/rotate-secret", post(rotate_webhook_secret_handler))
+        .route("/webhooks/:id/dead-letters", get(list_dead_letters_handler))
+        .route("/webhooks/:id/dead-letters/:letter_id/redeliver", post(redeliver_dead_letter_handler))
         .route("/ws", get(ws_route_handler))
-        .nest_service("/mcp", mcp_service)
-        .fallback_service(ServeDir::new("public"))  // Serve static files from /public
+        .merge(utoipa_swagger_ui::SwaggerUi::new("/docs").url("/openapi.json", <crate::openapi::ApiDoc as utoipa::OpenApi>::openapi()))
+        .route("/internal/worker/compile", post(worker_compile_handler))
+        .nest_service("/mcp", mcp_service);
+    // The browser editor and live preview, embedded from public/
+    let app = if state.settings.frontend_enabled {
+        app.route("/p/:id", get(crate::frontend::playground_page_handler)).fallback(crate::frontend::asset_handler)
+    } else {
+        app
+    };
+    let app = app
         .layer(CompressionLayer::new())  // Moonshot #3: ~70% smaller responses
-        .layer(CorsLayer::permissive())
-        .layer(DefaultBodyLimit::max(100 * 1024 * 1024)) // 100MB limit
-        .with_state(state);
+        // Storage writes are encrypted with the key of, and attributed to, the request's
+        // tenant; inside CORS so browsers can read a refused tenant token
+        .layer(axum::middleware::from_fn_with_state(state.settings.clone(), crate::tenancy::tenant_scope))
+        .layer(cors)
+        // Body size is enforced by `limits::enforce`, per route and tenant
+        .layer(axum::middleware::from_fn_with_state(state.settings.clone(), crate::limits::enforce))
+        .layer(DefaultBodyLimit::disable())
+        .with_state(state.clone());
 
     // 5. Start Server
-    let addr = "0.0.0.0:8080";
-    let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
-    info!("🚀 Tachyon-Tex Server listening on http://{}", addr);
+    if let Err(e) = crate::listeners::serve(app, &state).await {
+        tracing::error!("❌ {}", e);
+        std::process::exit(1);
+    }
+}
+
+type McpHttpService = rmcp::transport::streamable_http_server::StreamableHttpService<
+    crate::mcp::TachyonMcpServer,
+    rmcp::transport::streamable_http_server::session::local::LocalSessionManager,
+>;
+
+fn mcp_http_service(state: AppState) -> McpHttpService {
+    rmcp::transport::streamable_http_server::StreamableHttpService::new(
+        move || Ok(crate::mcp::TachyonMcpServer::new(state.clone())),
+        rmcp::transport::streamable_http_server::session::local::LocalSessionManager::default().into(),
+        Default::default(),
+    )
+}
+
+/// Serves MCP over stdin/stdout until the client disconnects.
+async fn serve_mcp_stdio(state: AppState) {
+    use rmcp::ServiceExt;
+    info!("🔌 MCP server on stdio");
+    let service = match crate::mcp::TachyonMcpServer::new(state).serve(rmcp::transport::stdio()).await {
+        Ok(service) => service,
+        Err(e) => {
+            tracing::error!("❌ MCP stdio handshake failed: {}", e);
+            std::process::exit(1);
+        }
+    };
+    if let Err(e) = service.waiting().await {
+        tracing::error!("❌ MCP stdio session ended with error: {}", e);
+    }
+}
+
+/// Serves only the MCP endpoint, over streamable HTTP, at `addr`.
+async fn serve_mcp_http(state: AppState, addr: &str) {
+    let app = Router::new().nest_service("/mcp", mcp_http_service(state));
+    let listener = match tokio::net::TcpListener::bind(addr).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            tracing::error!("❌ Failed to bind {}: {}", addr, e);
+            std::process::exit(1);
+        }
+    };
+    info!("🔌 MCP server listening on http://{}/mcp", addr);
     axum::serve(listener, app).await.unwrap();
 }
 
+async fn format_sync_task(format_cache: FormatCache, format_dir: PathBuf, interval: Duration) {
+    loop {
+        let (pulled, pushed) = format_cache.sync_formats(&format_dir).await;
+        if pulled + pushed > 0 {
+            info!("🔄 Format sync: pulled {}, pushed {}", pulled, pushed);
+        }
+        tokio::time::sleep(interval).await;
+    }
+}
+
+async fn janitor_task(janitor: crate::janitor::Janitor, interval: Duration) {
+    loop {
+        tokio::time::sleep(interval).await;
+        let sweeper = janitor.clone();
+        let Ok(report) = tokio::task::spawn_blocking(move || sweeper.sweep(crate::janitor::LEAK_GRACE)).await else { continue };
+        if report.removed > 0 {
+            info!("🧹 Workspace cleanup: removed {} leaked workspace(s), {:.2} MB", report.removed, report.removed_bytes as f64 / 1024.0 / 1024.0);
+        }
+        info!("📂 Workspaces: {} in use, {:.2} MB on disk", report.live, report.used_bytes as f64 / 1024.0 / 1024.0);
+    }
+}
+
 async fn cache_cleanup_task(cache: CompilationCache) {
     loop {
         tokio::time::sleep(Duration::from_secs(CACHE_CLEANUP_INTERVAL_SECS)).await;
@@ -0,0 +1,136 @@
+//! Build matrices (`POST /compile/matrix`): one upload compiled under every combination of
+//! engine, draft/final mode and paper size, so template maintainers can check that a
+//! template builds everywhere it claims to.
+
+use crate::models::MatrixQuery;
+use crate::rewrite::{self, Overrides, PAPER_SIZES};
+
+/// Engines a matrix may name. Only XeTeX (Tectonic) is installed here; cells for the
+/// others are reported as unsupported rather than failing the whole matrix.
+pub const ENGINES: &[&str] = &["xelatex", "pdflatex", "lualatex"];
+
+/// The engine cells actually compile with.
+pub const SUPPORTED_ENGINE: &str = "xelatex";
+
+pub const MODES: &[&str] = &["final", "draft"];
+
+/// Paper axis value that keeps the document's own paper size.
+pub const DEFAULT_PAPER: &str = "default";
+
+/// Upper bound on the cells of one matrix.
+pub const MAX_CELLS: usize = 24;
+
+/// One combination of the matrix.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cell {
+    pub engine: String,
+    pub mode: String,
+    pub paper: String,
+}
+
+impl Cell {
+    /// `engine-mode-paper`, used for the cell's files in the ZIP.
+    pub fn name(&self) -> String {
+        format!("{}-{}-{}", self.engine, self.mode, self.paper)
+    }
+
+    pub fn supported(&self) -> bool {
+        self.engine == SUPPORTED_ENGINE
+    }
+
+    /// The main source as this cell builds it.
+    pub fn rewrite(&self, source: &str) -> Result<String, String> {
+        let source = rewrite::draft_mode(source, self.mode == "draft")?;
+        if self.paper == DEFAULT_PAPER {
+            return Ok(source);
+        }
+        Overrides { paper: Some(self.paper.clone()), ..Default::default() }.apply(&source)
+    }
+}
+
+/// Every combination of the requested axes, engines outermost. Unset axes default to
+/// `xelatex`, `final` and the document's own paper.
+pub fn cells(query: &MatrixQuery) -> Result<Vec<Cell>, String> {
+    let engines = axis(query.engines.as_deref(), SUPPORTED_ENGINE, |engine| match engine {
+        "xetex" | "tectonic" => Ok("xelatex".to_string()),
+        engine if ENGINES.contains(&engine) => Ok(engine.to_string()),
+        engine => Err(format!("Unknown engine '{}' (expected {})", engine, ENGINES.join(", "))),
+    })?;
+    let modes = axis(query.modes.as_deref(), "final", |mode| match mode {
+        mode if MODES.contains(&mode) => Ok(mode.to_string()),
+        mode => Err(format!("Unknown mode '{}' (expected {})", mode, MODES.join(", "))),
+    })?;
+    let papers = axis(query.papers.as_deref(), DEFAULT_PAPER, |paper| {
+        let name = paper.strip_suffix("paper").unwrap_or(paper);
+        if name == DEFAULT_PAPER || PAPER_SIZES.contains(&name) {
+            Ok(name.to_string())
+        } else {
+            Err(format!("Unknown paper size '{}' (expected {} or {})", paper, PAPER_SIZES.join(", "), DEFAULT_PAPER))
+        }
+    })?;
+
+    let count = engines.len() * modes.len() * papers.len();
+    if count > MAX_CELLS {
+        return Err(format!("The matrix has {} cells, over the limit of {}", count, MAX_CELLS));
+    }
+    let mut cells = Vec::with_capacity(count);
+    for engine in &engines {
+        for mode in &modes {
+            for paper in &papers {
+                cells.push(Cell { engine: engine.clone(), mode: mode.clone(), paper: paper.clone() });
+            }
+        }
+    }
+    Ok(cells)
+}
+
+/// The distinct values of a comma-separated axis, lowercased and validated by `parse`.
+fn axis(value: Option<&str>, default: &str, parse: impl Fn(&str) -> Result<String, String>) -> Result<Vec<String>, String> {
+    let mut values = Vec::new();
+    for part in value.unwrap_or(default).split(',').map(|p| p.trim().to_ascii_lowercase()).filter(|p| !p.is_empty()) {
+        let value = parse(&part)?;
+        if !values.contains(&value) {
+            values.push(value);
+        }
+    }
+    if values.is_empty() {
+        values.push(parse(default)?);
+    }
+    Ok(values)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn query(engines: Option<&str>, modes: Option<&str>, papers: Option<&str>) -> MatrixQuery {
+        MatrixQuery { engines: engines.map(str::to_string), modes: modes.map(str::to_string), papers: papers.map(str::to_string) }
+    }
+
+    #[test]
+    fn test_cells() {
+        let defaults = cells(&query(None, None, None)).unwrap();
+        assert_eq!(defaults, vec![Cell { engine: "xelatex".into(), mode: "final".into(), paper: "default".into() }]);
+
+        let matrix = cells(&query(Some("XeTeX, pdflatex"), Some("final,draft,final"), Some("a4paper,letter"))).unwrap();
+        assert_eq!(matrix.len(), 8);
+        assert_eq!(matrix[0].name(), "xelatex-final-a4");
+        assert_eq!(matrix[7].name(), "pdflatex-draft-letter");
+        assert!(matrix[0].supported() && !matrix[7].supported());
+
+        assert!(cells(&query(Some("context"), None, None)).is_err());
+        assert!(cells(&query(None, Some("fast"), None)).is_err());
+        assert!(cells(&query(None, None, Some("tabloid"))).is_err());
+        assert!(cells(&query(Some("xelatex,pdflatex,lualatex"), Some("final,draft"), Some("a4,a5,letter,legal,default"))).is_err());
+    }
+
+    #[test]
+    fn test_rewrite() {
+        let source = "\\documentclass[draft]{article}\n\\begin{document}\nHi\n\\end{document}\n";
+        let cell = Cell { engine: "xelatex".into(), mode: "final".into(), paper: "default".into() };
+        assert_eq!(cell.rewrite(source).unwrap(), source.replace("[draft]", "[final]"));
+        let letter = Cell { mode: "draft".into(), paper: "letter".into(), ..cell }.rewrite(source).unwrap();
+        assert!(letter.starts_with("\\documentclass[draft]{article}\n"));
+        assert!(letter.contains("letterpaper"));
+    }
+}
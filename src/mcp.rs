@@ -1,11 +1,9 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::sync::Arc;
 use std::time::Instant;
 use tokio::sync::Mutex;
 use tracing::{info, error};
-use tempfile::TempDir;
 use std::fs;
-use std::path::PathBuf;
 use base64::Engine;
 
 use rmcp::{
@@ -23,7 +21,8 @@ use rmcp::{
 use serde::Deserialize;
 use crate::models::*;
 use crate::services::*;
-use crate::compiler::Compiler;
+use crate::bib::CitationChecker;
+use crate::validator::Validator;
 
 #[derive(Deserialize, schemars::JsonSchema)]
 pub struct CompileArgs {
@@ -31,12 +30,28 @@ pub struct CompileArgs {
     pub main: Option<String>,
     /// A map of filenames to their contents
     pub files: HashMap<String, String>,
+    /// Recompile even if the result (or a recent failure) is cached
+    #[serde(default)]
+    pub force: bool,
+    /// Build flags injected before \documentclass: booleans become \newif toggles
+    /// (`{"solutions": true}` sets \ifsolutions), strings and numbers \def macros
+    #[serde(default)]
+    pub defines: BTreeMap<String, serde_json::Value>,
+    /// "auto" to detect the language from the text, or a code such as "es": loads babel
+    /// when the preamble sets no language, for correct hyphenation
+    pub language: Option<String>,
+    /// Replace smart quotes, invisible spaces, emoji and other characters the default
+    /// fonts lack with LaTeX equivalents in every .tex file
+    #[serde(default)]
+    pub sanitize_unicode: bool,
 }
 
 #[derive(Deserialize, schemars::JsonSchema)]
 pub struct ValidateArgs {
-    /// List of files to validate
-    pub files: Vec<String>,
+    /// The main .tex file; defaults to the source declaring \documentclass
+    pub main: Option<String>,
+    /// A map of filenames to their contents
+    pub files: HashMap<String, String>,
 }
 
 #[derive(Clone)]
@@ -64,21 +79,32 @@ impl TachyonMcpServer {
     async fn compile(&self, Parameters(args): Parameters<CompileArgs>) -> Result<CallToolResult, McpError> {
         let files_received = args.files.len();
         let main_tex_name = args.main.unwrap_or_else(|| "main.tex".to_string());
+        let mut files = args.files;
+        let mut warnings: Vec<CompileWarning> = Vec::new();
+        if args.sanitize_unicode {
+            for (name, content) in files.iter_mut().filter(|(name, _)| name.ends_with(".tex")) {
+                let (clean, substitutions) = crate::sanitize::sanitize(content);
+                warnings.extend(substitutions.iter().map(|s| s.warning(name)));
+                *content = clean;
+            }
+        }
+        if !args.defines.is_empty() || args.language.is_some() {
+            let source = files.get(&main_tex_name)
+                .ok_or_else(|| McpError::invalid_params(format!("defines and language need the main file {}", main_tex_name), None))?;
+            let mut rewritten = crate::rewrite::inject_defines(source, &args.defines).map_err(|e| McpError::invalid_params(e, None))?;
+            if let Some(requested) = &args.language {
+                let texts = files.iter().filter(|(name, _)| name.ends_with(".tex")).map(|(_, text)| text.as_str());
+                rewritten = crate::language::apply(&rewritten, requested, texts).map_err(|e| McpError::invalid_params(e, None))?.0;
+            }
+            files.insert(main_tex_name.clone(), rewritten);
+        }
         
-        let temp_base = if std::path::Path::new("/dev/shm").exists() {
-            let path = PathBuf::from("/dev/shm/tachyon-compilations");
-            let _ = fs::create_dir_all(&path);
-            path
-        } else {
-            std::env::temp_dir()
-        };
-
-        let temp_dir = TempDir::new_in(&temp_base).map_err(|e| {
+        let temp_dir = self.state.janitor.workspace().map_err(|e| {
             McpError::internal_error(format!("Failed to create temp dir: {}", e), None)
         })?;
 
-        let mut all_input_data = Vec::new();
-        for (name, content) in &args.files {
+        let mut input_hasher = self.state.compilation_cache.input_hasher();
+        for (name, content) in &files {
             let path = temp_dir.path().join(name);
             if let Some(parent) = path.parent() {
                 let _ = fs::create_dir_all(parent);
@@ -86,52 +112,75 @@ impl TachyonMcpServer {
             if let Err(e) = fs::write(&path, content) {
                 return Err(McpError::internal_error(format!("Failed to write file {}: {}", name, e), None));
             }
-            all_input_data.extend_from_slice(content.as_bytes());
+            input_hasher.add_file(name, content.as_bytes());
         }
 
         let main_tex_path = temp_dir.path().join(&main_tex_name);
-        let input_hash = CompilationCache::hash_input(&all_input_data);
+        let input_hash = input_hasher.finish(&main_tex_name);
+        warnings.extend(CitationChecker::check(&files).into_iter().map(Into::into));
+
+        if !args.force {
+            if let Some(failure) = self.state.compilation_cache.get_failure(input_hash).await {
+                info!("🚫 MCP Negative cache HIT for hash {:016x}", input_hash);
+                return Ok(CallToolResult::error(vec![
+                    Content::text(format!("LaTeX Error (CACHED): {}", failure.error)),
+                    Content::text(format!("Logs:\n{}", failure.logs))
+                ]));
+            }
+        }
 
-        if let Some((cached_pdf, original_time)) = self.state.compilation_cache.get_pdf(input_hash).await {
+        // Stale entries are treated as misses here: the recompile below refreshes them
+        let cached = if args.force { None } else { self.state.compilation_cache.get_pdf(input_hash).await };
+        if let Some((cached_pdf, original_time, false, notes)) = cached {
             info!("📦 MCP Cache HIT for hash {:016x}", input_hash);
-            return Ok(CallToolResult::success(vec![
-                Content::text(format!("Compilation successful (CACHED). Time: {}ms", original_time)),
+            warnings.extend(notes.warnings);
+            let output_hash = self.state.output_store.put(&cached_pdf).await;
+            let mut contents = vec![
+                Content::text(format!("Compilation successful (CACHED). Time: {}ms. Output hash: {}", original_time, output_hash)),
                 Content::resource(ResourceContents::BlobResourceContents {
                     blob: base64::engine::general_purpose::STANDARD.encode(cached_pdf),
                     uri: format!("file:///{}.pdf", main_tex_name.replace(".tex", "")),
                     mime_type: Some("application/pdf".to_string()),
                     meta: None,
                 })
-            ]));
+            ];
+            if !warnings.is_empty() {
+                contents.push(Content::text(format_warnings(&warnings)));
+            }
+            return Ok(CallToolResult::success(contents));
         }
 
+        let permit = self.state.scheduler.acquire(Priority::Interactive).await;
         info!("MCP Compiling {:?} ({} files)...", main_tex_path, files_received);
         let start = Instant::now();
 
-        let (result, logs) = Compiler::compile_file(
-            &main_tex_path,
-            temp_dir.path(),
-            &self.state.format_cache_path,
-            &self.state.config
-        );
+        let (result, logs) = crate::workers::compile(&self.state, temp_dir.path(), &main_tex_path, Priority::Interactive, permit).await;
 
         let compile_time_ms = start.elapsed().as_millis() as u64;
 
         match result {
             Ok(pdf_data) => {
-                self.state.compilation_cache.put_pdf(input_hash, &pdf_data, compile_time_ms).await;
-                Ok(CallToolResult::success(vec![
-                    Content::text(format!("Compilation successful. Time: {}ms", compile_time_ms)),
+                let notes = CompileNotes::from_log(&logs);
+                self.state.compilation_cache.put_pdf(input_hash, &pdf_data, compile_time_ms, &notes).await;
+                warnings.extend(notes.warnings);
+                let output_hash = self.state.output_store.put(&pdf_data).await;
+                let mut contents = vec![
+                    Content::text(format!("Compilation successful. Time: {}ms. Output hash: {}", compile_time_ms, output_hash)),
                     Content::resource(ResourceContents::BlobResourceContents {
                         blob: base64::engine::general_purpose::STANDARD.encode(pdf_data),
                         uri: format!("file:///{}.pdf", main_tex_name.replace(".tex", "")),
                         mime_type: Some("application/pdf".to_string()),
                         meta: None,
                     })
-                ]))
+                ];
+                if !warnings.is_empty() {
+                    contents.push(Content::text(format_warnings(&warnings)));
+                }
+                Ok(CallToolResult::success(contents))
             }
             Err(e) => {
                 error!("MCP Compilation failed:\n{}", logs);
+                self.state.compilation_cache.put_failure(input_hash, &e, &logs).await;
                 Ok(CallToolResult::error(vec![
                     Content::text(format!("LaTeX Error: {}", e)),
                     Content::text(format!("Logs:\n{}", logs))
@@ -143,8 +192,18 @@ impl TachyonMcpServer {
     #[tool(description = "Validate LaTeX files for common errors")]
     async fn validate(&self, Parameters(args): Parameters<ValidateArgs>) -> Result<CallToolResult, McpError> {
         info!("MCP Validating {} files...", args.files.len());
-        // Simple validation for now, matching the existing handler
-        Ok(CallToolResult::success(vec![Content::text("Validation passed (placeholder)")]))
+        let result = Validator::validate(&args.files, args.main.as_deref());
+        let format = |messages: &[ValidationMessage]| -> String {
+            messages.iter().map(|m| format!("{}:{}: {}", m.file, m.line, m.message)).collect::<Vec<_>>().join("\n")
+        };
+        let mut contents = vec![Content::text(if result.valid { "Validation passed" } else { "Validation failed" })];
+        if !result.errors.is_empty() {
+            contents.push(Content::text(format!("Errors:\n{}", format(&result.errors))));
+        }
+        if !result.warnings.is_empty() {
+            contents.push(Content::text(format!("Warnings:\n{}", format(&result.warnings))));
+        }
+        Ok(if result.valid { CallToolResult::success(contents) } else { CallToolResult::error(contents) })
     }
 
     #[tool(description = "Check status of the Tachyon-Tex engine")]
@@ -153,6 +212,20 @@ impl TachyonMcpServer {
     }
 }
 
+/// Renders compile warnings as a plain-text block for tool output.
+fn format_warnings(warnings: &[CompileWarning]) -> String {
+    let lines: Vec<String> = warnings.iter().map(|w| {
+        let location = match (&w.file, w.line) {
+            (Some(f), Some(l)) => format!("{}:{}: ", f, l),
+            (Some(f), None) => format!("{}: ", f),
+            (None, Some(l)) => format!("line {}: ", l),
+            (None, None) => String::new(),
+        };
+        format!("{}[{}] {}", location, w.kind, w.message)
+    }).collect();
+    format!("Warnings:\n{}", lines.join("\n"))
+}
+
 #[prompt_router]
 impl TachyonMcpServer {}
 
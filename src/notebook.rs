@@ -0,0 +1,543 @@
+//! Renders Jupyter notebooks (nbformat 4) as LaTeX reports: markdown cells become text,
+//! code cells syntax-highlighted listings and their outputs text, tables and images.
+
+use base64::{engine::general_purpose, Engine as _};
+use regex::Regex;
+use serde_json::Value;
+use std::collections::BTreeSet;
+
+use crate::html::HtmlConverter;
+use crate::latex::{escape, fill_template, url_argument};
+use crate::render::AuxFile;
+
+const NOTEBOOK_TEMPLATE: &str = include_str!("../templates/notebook.tex");
+
+/// Kernel languages with a `listings` definition.
+const LISTINGS_LANGUAGES: &[(&str, &str)] = &[
+    ("python", "Python"),
+    ("r", "R"),
+    ("matlab", "Matlab"),
+    ("octave", "Octave"),
+    ("sql", "SQL"),
+    ("bash", "bash"),
+    ("sh", "bash"),
+    ("scala", "Scala"),
+    ("java", "Java"),
+    ("c++", "C++"),
+    ("c", "C"),
+    ("haskell", "Haskell"),
+    ("ruby", "Ruby"),
+];
+
+/// Math environments passed through from markdown cells, as MathJax renders them.
+const MATH_ENVIRONMENTS: &[&str] = &["equation", "equation*", "align", "align*", "gather", "gather*", "multline", "multline*"];
+
+/// Cell tags honoured the way nbconvert's TagRemovePreprocessor does.
+const REMOVE_CELL: &str = "remove-cell";
+const REMOVE_INPUT: &str = "remove-input";
+const REMOVE_OUTPUT: &str = "remove-output";
+
+const HEADINGS: [&str; 6] = ["section", "subsection", "subsubsection", "paragraph", "subparagraph", "subparagraph"];
+
+const IMAGE_WIDTH: &str = "width=\\linewidth,height=0.45\\textheight,keepaspectratio";
+
+/// A notebook converted to LaTeX.
+pub struct NotebookConversion {
+    pub source: String,
+    /// Output and attachment images referenced by the source
+    pub files: Vec<AuxFile>,
+    pub warnings: Vec<String>,
+}
+
+/// How a notebook is rendered.
+#[derive(Default)]
+pub struct NotebookOptions<'a> {
+    /// Overrides `metadata.title`
+    pub title: Option<&'a str>,
+    /// Leave out the source of code cells, keeping their outputs
+    pub hide_code: bool,
+    /// Number the sections made from markdown headings
+    pub numbered: bool,
+}
+
+pub struct NotebookConverter {
+    language: Option<&'static str>,
+    numbered: bool,
+    preamble: BTreeSet<String>,
+    files: Vec<AuxFile>,
+    warnings: BTreeSet<String>,
+}
+
+impl NotebookConverter {
+    pub fn convert(notebook: &Value, options: &NotebookOptions) -> Result<NotebookConversion, String> {
+        let cells = notebook.get("cells").and_then(Value::as_array)
+            .ok_or("Not a Jupyter notebook: \"cells\" is missing (only nbformat 4 is supported)")?;
+        let metadata = notebook.get("metadata");
+        let kernel_language = metadata
+            .and_then(|m| m.pointer("/language_info/name").or_else(|| m.pointer("/kernelspec/language")))
+            .and_then(Value::as_str)
+            .unwrap_or("python")
+            .to_ascii_lowercase();
+        let mut converter = Self {
+            language: listings_language(&kernel_language),
+            numbered: options.numbered,
+            preamble: BTreeSet::new(),
+            files: Vec::new(),
+            warnings: BTreeSet::new(),
+        };
+
+        let mut blocks = Vec::new();
+        for (index, cell) in cells.iter().enumerate() {
+            let tags: Vec<&str> = cell.pointer("/metadata/tags").and_then(Value::as_array)
+                .map(|t| t.iter().filter_map(Value::as_str).collect())
+                .unwrap_or_default();
+            if tags.contains(&REMOVE_CELL) {
+                continue;
+            }
+            let source = multiline(cell.get("source"));
+            match cell.get("cell_type").and_then(Value::as_str) {
+                Some("markdown") => blocks.push(converter.markdown(&source, cell.get("attachments"), index)),
+                Some("code") => {
+                    if !options.hide_code && !tags.contains(&REMOVE_INPUT) && !source.trim().is_empty() {
+                        let prompt = match cell.get("execution_count").and_then(Value::as_u64) {
+                            Some(n) => format!("In [{}]:", n),
+                            None => "In [ ]:".to_string(),
+                        };
+                        blocks.push(format!("\\prompt{{{}}}\n{}", prompt, converter.listing(&source, "code", converter.language)));
+                    }
+                    if !tags.contains(&REMOVE_OUTPUT) {
+                        for (k, output) in cell.get("outputs").and_then(Value::as_array).into_iter().flatten().enumerate() {
+                            blocks.push(converter.output(output, index, k));
+                        }
+                    }
+                }
+                Some("raw") => {
+                    let format = cell.pointer("/metadata/format").or_else(|| cell.pointer("/metadata/raw_mimetype")).and_then(Value::as_str);
+                    if matches!(format, Some("text/latex") | Some("latex")) {
+                        blocks.push(source.trim().to_string());
+                    }
+                }
+                _ => {
+                    converter.warnings.insert(format!("Skipped cell {} with an unknown type", index + 1));
+                }
+            }
+        }
+        blocks.retain(|b| !b.trim().is_empty());
+
+        let title = options.title.map(str::to_string)
+            .or_else(|| metadata.and_then(|m| m.get("title")).and_then(Value::as_str).map(str::to_string))
+            .filter(|t| !t.trim().is_empty());
+        let authors: Vec<String> = metadata.and_then(|m| m.get("authors")).and_then(Value::as_array).into_iter().flatten()
+            .filter_map(|a| a.get("name").and_then(Value::as_str).or_else(|| a.as_str()))
+            .map(escape)
+            .collect();
+        let (title, maketitle) = match title {
+            Some(title) => (format!("\\title{{{}}}\n\\author{{{}}}\n\\date{{}}", escape(title.trim()), authors.join(" \\and ")), "\\maketitle"),
+            None => (String::new(), ""),
+        };
+
+        let preamble: Vec<String> = converter.preamble.into_iter().collect();
+        let source = fill_template(NOTEBOOK_TEMPLATE, &[
+            ("PREAMBLE", &preamble.join("\n")),
+            ("TITLE", &title),
+            ("MAKETITLE", maketitle),
+            ("BODY", &blocks.join("\n\n")),
+        ]);
+        Ok(NotebookConversion { source, files: converter.files, warnings: converter.warnings.into_iter().collect() })
+    }
+
+    fn listing(&self, text: &str, style: &str, language: Option<&str>) -> String {
+        let language = language.map(|l| format!(", language={}", l)).unwrap_or_default();
+        // A literal end marker would close the listing early
+        let text = text.trim_end().replace("\\end{lstlisting}", "\\end {lstlisting}");
+        format!("\\begin{{lstlisting}}[style={}{}]\n{}\n\\end{{lstlisting}}", style, language, text)
+    }
+
+    fn output(&mut self, output: &Value, cell: usize, k: usize) -> String {
+        let ansi = Regex::new(r"\x1b\[[0-9;]*[A-Za-z]").unwrap();
+        match output.get("output_type").and_then(Value::as_str) {
+            Some("stream") => {
+                let text = ansi.replace_all(&multiline(output.get("text")), "").to_string();
+                if text.trim().is_empty() { String::new() } else { self.listing(&text, "output", None) }
+            }
+            Some("error") => {
+                let traceback: Vec<String> = output.get("traceback").and_then(Value::as_array).into_iter().flatten()
+                    .filter_map(Value::as_str)
+                    .map(|line| ansi.replace_all(line, "").to_string())
+                    .collect();
+                let text = if traceback.is_empty() {
+                    format!("{}: {}", output.get("ename").and_then(Value::as_str).unwrap_or("Error"), output.get("evalue").and_then(Value::as_str).unwrap_or(""))
+                } else {
+                    traceback.join("\n")
+                };
+                self.listing(&text, "error", None)
+            }
+            Some("execute_result") | Some("display_data") => {
+                let Some(data) = output.get("data") else { return String::new() };
+                for (mime, extension) in [("image/png", "png"), ("image/jpeg", "jpg")] {
+                    if let Some(encoded) = data.get(mime) {
+                        let name = format!("ipynb-cell{}-{}.{}", cell + 1, k + 1, extension);
+                        if let Some(image) = self.image(&multiline(Some(encoded)), name) {
+                            return format!("\\begin{{center}}\n{}\n\\end{{center}}", image);
+                        }
+                    }
+                }
+                if let Some(html) = data.get("text/html").map(|h| multiline(Some(h))).filter(|h| h.contains("<table")) {
+                    if let Ok(conversion) = HtmlConverter::convert(&html, false) {
+                        self.preamble.extend(conversion.preamble);
+                        return format!("\\begin{{center}}\n\\small\n{}\\end{{center}}", conversion.latex);
+                    }
+                }
+                if let Some(latex) = data.get("text/latex").map(|l| multiline(Some(l))) {
+                    let trimmed = latex.trim();
+                    if trimmed.starts_with('$') || trimmed.starts_with("\\begin{") {
+                        return trimmed.to_string();
+                    }
+                }
+                if data.get("image/svg+xml").is_some() && data.get("text/plain").is_none() {
+                    self.warnings.insert(format!("Skipped an SVG output in cell {} (only PNG and JPEG outputs are supported)", cell + 1));
+                }
+                match data.get("text/plain") {
+                    Some(text) => self.listing(&multiline(Some(text)), "output", None),
+                    None => String::new(),
+                }
+            }
+            _ => String::new(),
+        }
+    }
+
+    /// Decodes a base64 image into `files`, returning its `\includegraphics`.
+    fn image(&mut self, encoded: &str, name: String) -> Option<String> {
+        let compact: String = encoded.chars().filter(|c| !c.is_whitespace()).collect();
+        match general_purpose::STANDARD.decode(compact) {
+            Ok(bytes) => {
+                let graphic = format!("\\includegraphics[{}]{{{}}}", IMAGE_WIDTH, name);
+                self.files.push((name, bytes));
+                Some(graphic)
+            }
+            Err(_) => {
+                self.warnings.insert(format!("Skipped {}: invalid base64 data", name));
+                None
+            }
+        }
+    }
+
+    fn markdown(&mut self, text: &str, attachments: Option<&Value>, cell: usize) -> String {
+        let heading = Regex::new(r"^(#{1,6})\s+(.*?)\s*#*\s*$").unwrap();
+        let rule = Regex::new(r"^(?:(?:\*\s*){3,}|(?:-\s*){3,}|(?:_\s*){3,})$").unwrap();
+        let item = Regex::new(r"^(\s*)([-*+]|\d+[.)])\s+(.*)$").unwrap();
+        let delimiter = Regex::new(r"^\s*\|?\s*:?-+:?\s*(\|\s*:?-+:?\s*)*\|?\s*$").unwrap();
+
+        let lines: Vec<&str> = text.lines().collect();
+        let mut blocks: Vec<String> = Vec::new();
+        let mut paragraph: Vec<&str> = Vec::new();
+        // Open lists as (indent, environment), innermost last
+        let mut lists: Vec<(usize, &'static str)> = Vec::new();
+        let mut list = String::new();
+        let mut i = 0;
+
+        while i < lines.len() {
+            let line = lines[i];
+            let trimmed = line.trim();
+            let indent = line.len() - line.trim_start().len();
+
+            if trimmed.is_empty() {
+                self.flush_paragraph(&mut paragraph, &mut blocks, attachments, cell);
+                i += 1;
+                continue;
+            }
+            if !lists.is_empty() && indent == 0 && !item.is_match(line) {
+                close_lists(&mut lists, &mut list, &mut blocks, 0);
+            }
+
+            if let Some(fence) = ["```", "~~~"].into_iter().find(|f| trimmed.starts_with(f)) {
+                self.flush_paragraph(&mut paragraph, &mut blocks, attachments, cell);
+                close_lists(&mut lists, &mut list, &mut blocks, 0);
+                let language = listings_language(&trimmed[3..].trim().to_ascii_lowercase());
+                let end = lines[i + 1..].iter().position(|l| l.trim().starts_with(fence)).map_or(lines.len(), |p| i + 1 + p);
+                blocks.push(self.listing(&lines[i + 1..end].join("\n"), "code", language));
+                i = end + 1;
+                continue;
+            }
+            if let Some(rest) = trimmed.strip_prefix("$$") {
+                self.flush_paragraph(&mut paragraph, &mut blocks, attachments, cell);
+                let end = if rest.contains("$$") {
+                    i
+                } else {
+                    lines[i + 1..].iter().position(|l| l.contains("$$")).map_or(lines.len() - 1, |p| i + 1 + p)
+                };
+                let math = lines[i..=end].join("\n");
+                let math = math.trim().trim_start_matches("$$").trim_end_matches("$$").trim();
+                push_block(&mut lists, &mut list, &mut blocks, format!("\\[\n{}\n\\]", math));
+                i = end + 1;
+                continue;
+            }
+            if let Some(env) = MATH_ENVIRONMENTS.iter().find(|e| trimmed.starts_with(&format!("\\begin{{{}}}", e))) {
+                self.flush_paragraph(&mut paragraph, &mut blocks, attachments, cell);
+                let close = format!("\\end{{{}}}", env);
+                let end = lines[i..].iter().position(|l| l.contains(&close)).map_or(lines.len() - 1, |p| i + p);
+                push_block(&mut lists, &mut list, &mut blocks, lines[i..=end].join("\n").trim().to_string());
+                i = end + 1;
+                continue;
+            }
+            if let Some(caps) = heading.captures(trimmed) {
+                self.flush_paragraph(&mut paragraph, &mut blocks, attachments, cell);
+                close_lists(&mut lists, &mut list, &mut blocks, 0);
+                let star = if self.numbered { "" } else { "*" };
+                blocks.push(format!("\\{}{}{{{}}}", HEADINGS[caps[1].len() - 1], star, self.inline(&caps[2], attachments, cell)));
+                i += 1;
+                continue;
+            }
+            if rule.is_match(trimmed) && paragraph.is_empty() {
+                close_lists(&mut lists, &mut list, &mut blocks, 0);
+                blocks.push("\\noindent\\rule{\\linewidth}{0.4pt}".to_string());
+                i += 1;
+                continue;
+            }
+            if trimmed.starts_with('>') {
+                self.flush_paragraph(&mut paragraph, &mut blocks, attachments, cell);
+                close_lists(&mut lists, &mut list, &mut blocks, 0);
+                let end = lines[i..].iter().position(|l| !l.trim_start().starts_with('>')).map_or(lines.len(), |p| i + p);
+                let quoted: Vec<&str> = lines[i..end].iter().map(|l| {
+                    let l = l.trim_start().trim_start_matches('>');
+                    l.strip_prefix(' ').unwrap_or(l)
+                }).collect();
+                blocks.push(format!("\\begin{{quote}}\n{}\n\\end{{quote}}", self.markdown(&quoted.join("\n"), attachments, cell)));
+                i = end;
+                continue;
+            }
+            if trimmed.contains('|') && lines.get(i + 1).is_some_and(|next| next.contains('-') && delimiter.is_match(next)) {
+                self.flush_paragraph(&mut paragraph, &mut blocks, attachments, cell);
+                close_lists(&mut lists, &mut list, &mut blocks, 0);
+                let end = lines[i + 2..].iter().position(|l| !l.contains('|') || l.trim().is_empty()).map_or(lines.len(), |p| i + 2 + p);
+                blocks.push(self.table(&lines[i..end], attachments, cell));
+                i = end;
+                continue;
+            }
+            if let Some(caps) = item.captures(line) {
+                self.flush_paragraph(&mut paragraph, &mut blocks, attachments, cell);
+                let indent = caps[1].len();
+                let environment = if caps[2].starts_with(|c: char| c.is_ascii_digit()) { "enumerate" } else { "itemize" };
+                close_lists(&mut lists, &mut list, &mut blocks, indent + 1);
+                if lists.last().is_none_or(|(open, _)| *open < indent) {
+                    list.push_str(&format!("\\begin{{{}}}\n", environment));
+                    lists.push((indent, environment));
+                }
+                let text = self.inline(&caps[3], attachments, cell);
+                list.push_str(&format!("\\item {}\n", text));
+                i += 1;
+                continue;
+            }
+            if !lists.is_empty() {
+                // Continuation of the current item
+                let text = self.inline(trimmed, attachments, cell);
+                list.push_str(&format!("{}\n", text));
+            } else {
+                paragraph.push(trimmed);
+            }
+            i += 1;
+        }
+        self.flush_paragraph(&mut paragraph, &mut blocks, attachments, cell);
+        close_lists(&mut lists, &mut list, &mut blocks, 0);
+        blocks.join("\n\n")
+    }
+
+    fn flush_paragraph(&mut self, paragraph: &mut Vec<&str>, blocks: &mut Vec<String>, attachments: Option<&Value>, cell: usize) {
+        if !paragraph.is_empty() {
+            let text = paragraph.join("\n");
+            paragraph.clear();
+            blocks.push(self.inline(&text, attachments, cell));
+        }
+    }
+
+    /// A pipe table; cells keep their inline formatting.
+    fn table(&mut self, lines: &[&str], attachments: Option<&Value>, cell: usize) -> String {
+        let cells = |line: &str| -> Vec<String> {
+            let line = line.trim();
+            let line = line.strip_prefix('|').unwrap_or(line);
+            let line = line.strip_suffix('|').unwrap_or(line);
+            line.split('|').map(|c| c.trim().to_string()).collect()
+        };
+        let align: String = cells(lines[1]).iter()
+            .map(|d| match (d.starts_with(':'), d.ends_with(':')) {
+                (true, true) => 'c',
+                (false, true) => 'r',
+                _ => 'l',
+            })
+            .collect();
+        let width = align.len();
+        let mut row = |line: &str| -> String {
+            let mut values: Vec<String> = cells(line).iter().take(width).map(|c| self.inline(c, attachments, cell)).collect();
+            values.resize(width, String::new());
+            format!("{} \\\\\n", values.join(" & "))
+        };
+        let header = row(lines[0]);
+        let body: String = lines[2..].iter().map(|l| row(l)).collect();
+        format!("\\begin{{center}}\n\\begin{{tabular}}{{{}}}\n\\toprule\n{}\\midrule\n{}\\bottomrule\n\\end{{tabular}}\n\\end{{center}}", align, header, body)
+    }
+
+    /// Inline markdown: math passes through untouched, everything else is escaped.
+    fn inline(&mut self, text: &str, attachments: Option<&Value>, cell: usize) -> String {
+        let re = Regex::new(concat!(
+            r"\\([\\`*_{}\[\]()#+\-.!$|])",
+            r"|\$\$(.+?)\$\$",
+            r"|\$([^$\n]+?)\$",
+            r"|`([^`]+)`",
+            r"|!\[([^\]]*)\]\(\s*<?([^)\s>]+)>?[^)]*\)",
+            r"|\[([^\]]+)\]\(\s*<?([^)\s>]+)>?[^)]*\)",
+            r"|\*\*(.+?)\*\*|__(.+?)__",
+            r"|\*([^*\s](?:[^*]*[^*\s])?)\*|\b_([^_\s](?:[^_]*[^_\s])?)_\b",
+            r"|<br\s*/?>",
+        )).unwrap();
+
+        let mut out = String::new();
+        let mut last = 0;
+        for caps in re.captures_iter(text) {
+            let whole = caps.get(0).unwrap();
+            out.push_str(&escape(&text[last..whole.start()]));
+            last = whole.end();
+            let group = |n: usize| caps.get(n).map(|m| m.as_str());
+            if let Some(c) = group(1) {
+                out.push_str(&escape(c));
+            } else if let Some(math) = group(2) {
+                out.push_str(&format!("\\[{}\\]", math));
+            } else if group(3).is_some() {
+                out.push_str(whole.as_str());
+            } else if let Some(code) = group(4) {
+                out.push_str(&format!("\\texttt{{{}}}", escape(code)));
+            } else if let Some(src) = group(6) {
+                out.push_str(&self.markdown_image(src, group(5).unwrap_or_default(), attachments, cell));
+            } else if let (Some(label), Some(url)) = (group(7), group(8)) {
+                let label = self.inline(label, attachments, cell);
+                out.push_str(&format!("\\href{{{}}}{{{}}}", url_argument(url), label));
+            } else if let Some(bold) = group(9).or(group(10)) {
+                out.push_str(&format!("\\textbf{{{}}}", self.inline(bold, attachments, cell)));
+            } else if let Some(italic) = group(11).or(group(12)) {
+                out.push_str(&format!("\\emph{{{}}}", self.inline(italic, attachments, cell)));
+            } else {
+                out.push_str("\\\\\n");
+            }
+        }
+        out.push_str(&escape(&text[last..]));
+        out
+    }
+
+    /// Only images attached to the cell are available; linked files are not uploaded.
+    fn markdown_image(&mut self, src: &str, alt: &str, attachments: Option<&Value>, cell: usize) -> String {
+        let Some(name) = src.strip_prefix("attachment:") else {
+            self.warnings.insert(format!("Skipped image {} in cell {} (only cell attachments are embedded)", src, cell + 1));
+            return escape(alt);
+        };
+        let attachment = attachments.and_then(|a| a.get(name));
+        for (mime, extension) in [("image/png", "png"), ("image/jpeg", "jpg")] {
+            if let Some(encoded) = attachment.and_then(|a| a.get(mime)) {
+                let stem: String = name.chars().take_while(|c| *c != '.').filter(|c| c.is_ascii_alphanumeric() || *c == '-').collect();
+                let file = format!("ipynb-cell{}-{}.{}", cell + 1, stem, extension);
+                if let Some(image) = self.image(&multiline(Some(encoded)), file) {
+                    return image;
+                }
+            }
+        }
+        self.warnings.insert(format!("Skipped attachment {} in cell {} (only PNG and JPEG are supported)", name, cell + 1));
+        escape(alt)
+    }
+}
+
+/// Writes a display block into the open list item, or as a block of its own.
+fn push_block(lists: &mut [(usize, &'static str)], list: &mut String, blocks: &mut Vec<String>, block: String) {
+    if lists.is_empty() {
+        blocks.push(block);
+    } else {
+        list.push_str(&block);
+        list.push('\n');
+    }
+}
+
+/// Closes lists nested deeper than `indent` (all of them for 0).
+fn close_lists(lists: &mut Vec<(usize, &'static str)>, list: &mut String, blocks: &mut Vec<String>, indent: usize) {
+    while lists.last().is_some_and(|(open, _)| indent == 0 || *open >= indent) {
+        let (_, environment) = lists.pop().unwrap();
+        list.push_str(&format!("\\end{{{}}}\n", environment));
+    }
+    if lists.is_empty() && !list.is_empty() {
+        blocks.push(std::mem::take(list).trim_end().to_string());
+    }
+}
+
+fn listings_language(name: &str) -> Option<&'static str> {
+    let name = name.trim();
+    let name = match name {
+        "py" | "python3" | "ipython" | "ipython3" => "python",
+        "shell" | "zsh" => "bash",
+        "cpp" => "c++",
+        other => other,
+    };
+    LISTINGS_LANGUAGES.iter().find(|(kernel, _)| *kernel == name).map(|(_, listings)| *listings)
+}
+
+/// Notebook text fields are either a string or an array of lines.
+fn multiline(value: Option<&Value>) -> String {
+    match value {
+        Some(Value::String(s)) => s.clone(),
+        Some(Value::Array(lines)) => lines.iter().filter_map(Value::as_str).collect(),
+        _ => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn convert(cells: Value) -> NotebookConversion {
+        let notebook = json!({"nbformat": 4, "metadata": {"language_info": {"name": "python"}, "title": "Sales & Co"}, "cells": cells});
+        NotebookConverter::convert(&notebook, &NotebookOptions::default()).unwrap()
+    }
+
+    #[test]
+    fn test_markdown() {
+        let out = convert(json!([{"cell_type": "markdown", "source": [
+            "# Results\n", "Growth of **50%** in $x_1$, see [docs](https://example.com/a_b) and `df.head()`.\n", "\n",
+            "- one\n", "  1. nested\n", "- two\n", "\n",
+            "$$\n", "E = mc^2\n", "$$\n",
+            "| Name | Total |\n", "|:-----|------:|\n", "| *a* | 3 |\n",
+        ]}]));
+        let body = out.source.split("\\begin{document}\n").nth(1).unwrap();
+        assert!(body.starts_with(concat!(
+            "\\maketitle\n\\section*{Results}\n\n",
+            "Growth of \\textbf{50\\%} in $x_1$, see \\href{https://example.com/a_b}{docs} and \\texttt{df.head()}.\n\n",
+            "\\begin{itemize}\n\\item one\n\\begin{enumerate}\n\\item nested\n\\end{enumerate}\n\\item two\n\\end{itemize}\n\n",
+            "\\[\nE = mc^2\n\\]\n\n",
+            "\\begin{center}\n\\begin{tabular}{lr}\n\\toprule\nName & Total \\\\\n\\midrule\n\\emph{a} & 3 \\\\\n\\bottomrule\n",
+        )), "{}", body);
+        assert!(out.source.contains("\\title{Sales \\& Co}"));
+    }
+
+    #[test]
+    fn test_code_and_outputs() {
+        let out = convert(json!([
+            {"cell_type": "code", "execution_count": 3, "metadata": {}, "source": "print('hi')\nplot()", "outputs": [
+                {"output_type": "stream", "name": "stdout", "text": ["hi\n"]},
+                {"output_type": "display_data", "data": {"image/png": "iVBORw0K\nGgo=", "text/plain": "<Figure>"}},
+                {"output_type": "error", "ename": "ValueError", "evalue": "bad", "traceback": ["\u{1b}[0;31mValueError\u{1b}[0m: bad"]},
+            ]},
+            {"cell_type": "code", "metadata": {"tags": ["remove-input"]}, "source": "secret()", "outputs": [
+                {"output_type": "execute_result", "data": {"text/html": "<table><tr><th>a</th></tr><tr><td>1</td></tr></table>", "text/plain": "a\n1"}},
+            ]},
+            {"cell_type": "markdown", "metadata": {"tags": ["remove-cell"]}, "source": "hidden"},
+        ]));
+        assert!(out.source.contains("\\prompt{In [3]:}\n\\begin{lstlisting}[style=code, language=Python]\nprint('hi')\nplot()\n\\end{lstlisting}"));
+        assert!(out.source.contains("\\begin{lstlisting}[style=output]\nhi\n\\end{lstlisting}"));
+        assert!(out.source.contains("\\includegraphics[width=\\linewidth,height=0.45\\textheight,keepaspectratio]{ipynb-cell1-2.png}"));
+        assert!(out.source.contains("\\begin{lstlisting}[style=error]\nValueError: bad\n\\end{lstlisting}"));
+        assert!(!out.source.contains("secret") && !out.source.contains("hidden"));
+        assert!(out.source.contains("\\toprule"));
+        assert_eq!(out.files.len(), 1);
+        assert_eq!(out.files[0].1, general_purpose::STANDARD.decode("iVBORw0KGgo=").unwrap());
+    }
+
+    #[test]
+    fn test_not_a_notebook() {
+        assert!(NotebookConverter::convert(&json!({"nbformat": 3, "worksheets": []}), &NotebookOptions::default()).is_err());
+    }
+}
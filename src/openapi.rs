@@ -0,0 +1,132 @@
+//! OpenAPI document generated from the handler annotations, served at `/openapi.json`
+//! and browsable through Swagger UI at `/docs`.
+
+use utoipa::OpenApi;
+
+use crate::handlers;
+
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "Tachyon-Tex", description = "High-performance LaTeX compilation, rendering and document generation API"),
+    paths(
+        handlers::health_handler,
+        handlers::compile_handler,
+        handlers::compile_async_handler,
+        handlers::async_job_handler,
+        handlers::validate_handler,
+        handlers::output_handler,
+        handlers::similar_handler,
+        handlers::estimate_handler,
+        handlers::import_overleaf_handler,
+        handlers::get_project_handler,
+        handlers::compile_project_handler,
+        handlers::export_project_handler,
+        handlers::put_connector_handler,
+        handlers::list_connectors_handler,
+        handlers::delete_connector_handler,
+        handlers::preview_connector_handler,
+        handlers::create_playground_handler,
+        handlers::get_playground_handler,
+        handlers::get_failure_handler,
+        handlers::compile_matrix_handler,
+        handlers::grade_batch_handler,
+        handlers::create_regression_test_handler,
+        handlers::list_regression_tests_handler,
+        handlers::get_regression_test_handler,
+        handlers::delete_regression_test_handler,
+        handlers::run_regression_test_handler,
+        handlers::create_schedule_handler,
+        handlers::list_schedules_handler,
+        handlers::get_schedule_handler,
+        handlers::delete_schedule_handler,
+        handlers::run_schedule_handler,
+        handlers::register_template_pack_handler,
+        handlers::list_template_packs_handler,
+        handlers::get_template_pack_handler,
+        handlers::delete_template_pack_handler,
+        handlers::sync_template_pack_handler,
+        handlers::create_template_project_handler,
+        handlers::upload_registry_packages_handler,
+        handlers::list_registry_packages_handler,
+        handlers::delete_registry_package_handler,
+        handlers::bundle_status_handler,
+        handlers::bundle_updates_handler,
+        handlers::stage_bundle_handler,
+        handlers::unstage_bundle_handler,
+        handlers::trial_bundle_handler,
+        handlers::activate_bundle_handler,
+        handlers::rollback_bundle_handler,
+        handlers::configure_signing_handler,
+        handlers::get_signing_handler,
+        handlers::delete_signing_handler,
+        handlers::erase_tenant_handler,
+        handlers::create_upload_handler,
+        handlers::upload_status_handler,
+        handlers::patch_upload_handler,
+        handlers::delete_upload_handler,
+        handlers::bib_format_handler,
+        handlers::escape_handler,
+        handlers::convert_html_handler,
+        handlers::convert_docx_handler,
+        handlers::convert_ipynb_handler,
+        handlers::stamp_handler,
+        handlers::assets_handler,
+        handlers::barcode_handler,
+        handlers::receipt_key_handler,
+        handlers::receipt_verify_handler,
+        handlers::render_chart_handler,
+        handlers::render_table_handler,
+        handlers::invoice_handler,
+        handlers::certificate_handler,
+        handlers::resume_handler,
+        handlers::report_handler,
+        handlers::exam_handler,
+        handlers::create_branding_handler,
+        handlers::get_branding_handler,
+        handlers::delete_branding_handler,
+        handlers::list_webhooks_handler,
+        handlers::create_webhook_handler,
+        handlers::update_webhook_handler,
+        handlers::delete_webhook_handler,
+        handlers::rotate_webhook_secret_handler,
+        handlers::list_dead_letters_handler,
+        handlers::redeliver_dead_letter_handler,
+    ),
+    components(schemas(crate::models::WebhookPayload, crate::models::TargetResult, crate::models::PayloadTooLarge, crate::models::ChecksumErrorResponse, crate::models::FileRejectionResponse, crate::models::ExportManifest, crate::models::TutorReport, crate::models::MatrixReport, crate::models::FormFieldSpec, crate::models::StampManifest)),
+    tags(
+        (name = "compile", description = "Compile LaTeX projects and fetch stored outputs"),
+        (name = "render", description = "Charts and tables rendered from data"),
+        (name = "generate", description = "Invoices, certificates and resumes from templates"),
+        (name = "branding", description = "Letterhead profiles used by the generators"),
+        (name = "webhooks", description = "Compile event subscriptions and dead letters"),
+        (name = "projects", description = "Imported projects, compiled by id"),
+        (name = "regression", description = "Template regression tests rebuilt against a reference PDF"),
+        (name = "schedules", description = "Projects rebuilt on a cron schedule, with fresh data and delivery"),
+        (name = "templates", description = "Template packs synced from Git and validated by compiling their samples"),
+        (name = "registry", description = "Private classes and styles every compile of a tenant finds"),
+        (name = "grading", description = "Batch compiles of student submissions for instructors"),
+        (name = "playground", description = "Shareable snippets opened in the editor with a live preview"),
+        (name = "uploads", description = "Resumable chunked uploads into the blob store"),
+        (name = "receipts", description = "Signed compile provenance"),
+        (name = "tools", description = "Bibliography, escaping, HTML conversion, asset, barcode and page stamping utilities"),
+        (name = "admin", description = "Operator endpoints, behind ADMIN_TOKEN: TeX bundle upgrades and tenant signing certificates"),
+        (name = "system", description = "Health"),
+    ),
+)]
+pub struct ApiDoc;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_spec_covers_routes() {
+        let spec = ApiDoc::openapi();
+        for path in ["/compile", "/render/chart", "/webhooks/{id}", "/receipts/verify"] {
+            assert!(spec.paths.paths.contains_key(path), "missing {}", path);
+        }
+        let schemas = spec.components.expect("components").schemas;
+        assert!(schemas.contains_key("WebhookPayload"));
+        assert!(schemas.contains_key("ChartRequest"));
+    }
+}
@@ -0,0 +1,209 @@
+//! `optimize=true` size pass over compiled PDFs. Generated certificates and invoices tend
+//! to carry the same logo, background or font program once per inclusion, streams
+//! compressed at a low level and objects nothing references. This merges identical
+//! streams, recompresses every Flate (and uncompressed) stream at the best level and drops
+//! unreferenced objects.
+//!
+//! The pass is structural and lossless: it neither subsets fonts nor re-encodes images.
+//! The engine already subsets the fonts it embeds; fonts embedded in full (usually by
+//! included PDFs) are only reported. JPEG and other non-Flate images are kept byte for byte.
+
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use lopdf::{Document, Object, ObjectId, Stream};
+use std::collections::{BTreeSet, HashMap};
+use std::io::{Read, Write};
+use xxhash_rust::xxh64::xxh64;
+
+use crate::models::PdfOptimization;
+
+/// Optimizes `pdf`, returning the smaller of the result and the original.
+pub fn optimize(pdf: &[u8]) -> Result<(Vec<u8>, PdfOptimization), String> {
+    let mut doc = Document::load_mem(pdf).map_err(|e| format!("Failed to parse PDF: {}", e))?;
+    // Cross-reference and object streams are rebuilt on save, so they do not count as removed
+    doc.objects.retain(|_, object| !object.type_name().is_ok_and(|t| matches!(t, "XRef" | "ObjStm")));
+    let merged_streams = merge_identical_streams(&mut doc);
+    let recompressed_streams = doc.objects.values_mut()
+        .filter_map(|object| object.as_stream_mut().ok())
+        .map(recompress)
+        .filter(|&recompressed| recompressed)
+        .count();
+    let unsubset_fonts = unsubset_fonts(&doc);
+    let removed_objects = doc.prune_objects().len();
+    doc.renumber_objects();
+
+    let mut out = Vec::new();
+    doc.save_to(&mut out).map_err(|e| format!("Failed to write PDF: {}", e))?;
+    if out.len() >= pdf.len() {
+        out = pdf.to_vec();
+    }
+    let report = PdfOptimization {
+        original_size: pdf.len(),
+        optimized_size: out.len(),
+        removed_objects,
+        merged_streams,
+        recompressed_streams,
+        unsubset_fonts,
+    };
+    Ok((out, report))
+}
+
+/// Points every reference to a stream at the first stream with the same dictionary and
+/// content, so duplicates become unreferenced. Returns how many were merged.
+fn merge_identical_streams(doc: &mut Document) -> usize {
+    let mut seen: HashMap<(String, u64), Vec<ObjectId>> = HashMap::new();
+    let mut replaced: HashMap<ObjectId, ObjectId> = HashMap::new();
+    for (&id, object) in &doc.objects {
+        let Ok(stream) = object.as_stream() else { continue };
+        let candidates = seen.entry((format!("{:?}", stream.dict), xxh64(&stream.content, 0))).or_default();
+        let original = candidates.iter().find(|other| {
+            doc.objects.get(other).and_then(|o| o.as_stream().ok()).is_some_and(|o| o.content == stream.content)
+        });
+        match original {
+            Some(&original) => {
+                replaced.insert(id, original);
+            }
+            None => candidates.push(id),
+        }
+    }
+    if !replaced.is_empty() {
+        doc.traverse_objects(|object| {
+            if let Object::Reference(id) = object {
+                if let Some(original) = replaced.get(id) {
+                    *id = *original;
+                }
+            }
+        });
+    }
+    replaced.len()
+}
+
+/// Deflates an uncompressed stream, or re-deflates a Flate one, at the best level when that
+/// makes it smaller. Metadata stays readable, as PDF/A expects.
+fn recompress(stream: &mut Stream) -> bool {
+    if stream.dict.get(b"Type").and_then(Object::as_name).is_ok_and(|t| t == b"Metadata") {
+        return false;
+    }
+    let filters = stream.filters().unwrap_or_default();
+    let raw = match filters.as_slice() {
+        [] => stream.content.clone(),
+        [filter] if filter == "FlateDecode" => {
+            // Only the Flate layer is undone, so any DecodeParms predictor still applies
+            let mut raw = Vec::new();
+            if ZlibDecoder::new(stream.content.as_slice()).read_to_end(&mut raw).is_err() {
+                return false;
+            }
+            raw
+        }
+        _ => return false,
+    };
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::best());
+    let Ok(compressed) = encoder.write_all(&raw).and_then(|_| encoder.finish()) else { return false };
+    // "/Filter /FlateDecode" costs 20 bytes in an uncompressed stream's dictionary
+    let overhead = if filters.is_empty() { 20 } else { 0 };
+    if compressed.len() + overhead >= stream.content.len() {
+        return false;
+    }
+    stream.dict.set("Filter", "FlateDecode");
+    stream.set_content(compressed);
+    true
+}
+
+/// Names of embedded fonts without a subset tag (`ABCDEF+Name`).
+fn unsubset_fonts(doc: &Document) -> Vec<String> {
+    let mut fonts = BTreeSet::new();
+    for object in doc.objects.values() {
+        let Ok(descriptor) = object.as_dict() else { continue };
+        if descriptor.get(b"Type").and_then(Object::as_name).ok() != Some(b"FontDescriptor".as_slice()) {
+            continue;
+        }
+        if ![b"FontFile".as_slice(), b"FontFile2", b"FontFile3"].iter().any(|key| descriptor.has(key)) {
+            continue;
+        }
+        let Ok(name) = descriptor.get(b"FontName").and_then(Object::as_name_str) else { continue };
+        let subset = name.split_once('+').is_some_and(|(tag, _)| tag.len() == 6 && tag.bytes().all(|b| b.is_ascii_uppercase()));
+        if !subset {
+            fonts.insert(name.to_string());
+        }
+    }
+    fonts.into_iter().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lopdf::dictionary;
+
+    /// Two pages drawing the same (uncompressed) logo, each embedded separately, plus a
+    /// full font and an orphaned object.
+    fn certificate() -> Vec<u8> {
+        let mut doc = Document::with_version("1.5");
+        let pages_id = doc.new_object_id();
+        let logo = "0123456789abcdef".repeat(512).into_bytes();
+        let font_file = doc.add_object(Stream::new(dictionary! {}, b"font program".to_vec()));
+        let descriptor = doc.add_object(dictionary! {
+            "Type" => "FontDescriptor",
+            "FontName" => "Helvetica",
+            "FontFile3" => font_file,
+        });
+        let subset = doc.add_object(dictionary! { "Type" => "FontDescriptor", "FontName" => "ABCDEF+LMRoman10", "FontFile" => font_file });
+        let kids: Vec<Object> = (0..2).map(|_| {
+            let image = doc.add_object(Stream::new(dictionary! { "Subtype" => "Image" }, logo.clone()));
+            let content = doc.add_object(Stream::new(dictionary! {}, b"q /Im1 Do Q".to_vec()));
+            doc.add_object(dictionary! {
+                "Type" => "Page",
+                "Parent" => pages_id,
+                "Contents" => content,
+                "Resources" => dictionary! {
+                    "XObject" => dictionary! { "Im1" => image },
+                    "Font" => dictionary! {
+                        "F1" => dictionary! { "Type" => "Font", "FontDescriptor" => descriptor },
+                        "F2" => dictionary! { "Type" => "Font", "FontDescriptor" => subset },
+                    },
+                },
+            }).into()
+        }).collect();
+        doc.objects.insert(pages_id, Object::Dictionary(dictionary! { "Type" => "Pages", "Kids" => kids, "Count" => 2 }));
+        let catalog = doc.add_object(dictionary! { "Type" => "Catalog", "Pages" => pages_id });
+        doc.add_object(Stream::new(dictionary! {}, vec![b'x'; 4096]));
+        doc.trailer.set("Root", catalog);
+        let mut out = Vec::new();
+        doc.save_to(&mut out).unwrap();
+        out
+    }
+
+    #[test]
+    fn test_optimize_certificate() {
+        let pdf = certificate();
+        let (optimized, report) = optimize(&pdf).unwrap();
+        assert_eq!(report.original_size, pdf.len());
+        assert_eq!(report.optimized_size, optimized.len());
+        assert!(optimized.len() * 5 < pdf.len(), "{} -> {}", pdf.len(), optimized.len());
+        assert_eq!(report.merged_streams, 2, "logo and page content");
+        assert_eq!(report.removed_objects, 3, "duplicates and the orphan");
+        assert_eq!(report.unsubset_fonts, vec!["Helvetica"]);
+
+        // Both pages still draw the logo, now from one stream
+        let doc = Document::load_mem(&optimized).unwrap();
+        assert_eq!(doc.get_pages().len(), 2);
+        let images: Vec<&Stream> = doc.objects.values()
+            .filter_map(|o| o.as_stream().ok())
+            .filter(|s| s.dict.get(b"Subtype").and_then(Object::as_name).ok() == Some(b"Image".as_slice()))
+            .collect();
+        assert_eq!(images.len(), 1);
+        let mut logo = Vec::new();
+        ZlibDecoder::new(images[0].content.as_slice()).read_to_end(&mut logo).unwrap();
+        assert_eq!(logo, "0123456789abcdef".repeat(512).into_bytes());
+    }
+
+    #[test]
+    fn test_never_grows() {
+        let pdf = certificate();
+        let (once, _) = optimize(&pdf).unwrap();
+        let (twice, report) = optimize(&once).unwrap();
+        assert!(twice.len() <= once.len());
+        assert_eq!(report.optimized_size, twice.len());
+        assert!(optimize(b"not a pdf").is_err());
+    }
+}
@@ -0,0 +1,318 @@
+//! Import of Overleaf project exports (and other latexmk-style ZIPs). The archive is
+//! flattened into project files, build output is dropped, and the main file and engine are
+//! picked from `.latexmain` markers, `latexmkrc` and `% !TEX` magic comments. Exports made
+//! by `GET /projects/{id}/export` import back with the main file their manifest names.
+
+use regex::Regex;
+use std::collections::HashSet;
+use std::io::{Read, Seek};
+
+use crate::bib::strip_comment;
+use crate::models::ProjectEngine;
+use crate::render::AuxFile;
+
+/// Uncompressed size limit of an imported archive.
+const MAX_IMPORT_BYTES: u64 = 512 * 1024 * 1024;
+
+/// Extensions of LaTeX build by-products, never part of a project's sources.
+const BUILD_EXTENSIONS: &[&str] = &[
+    "aux", "log", "out", "toc", "lof", "lot", "fls", "fdb_latexmk", "synctex.gz", "synctex", "blg",
+    "bcf", "run.xml", "xdv", "dvi", "nav", "snm", "vrb", "idx", "ilg", "ind", "glg", "glo", "gls",
+];
+
+/// Names commonly given to a project's main file, most likely first.
+const MAIN_NAMES: &[&str] = &["main.tex", "thesis.tex", "paper.tex", "report.tex", "article.tex", "document.tex"];
+
+pub struct OverleafProject {
+    /// Project files, with paths relative to the project root
+    pub files: Vec<AuxFile>,
+    pub main: String,
+    pub engine: ProjectEngine,
+    pub warnings: Vec<String>,
+}
+
+/// Settings found in a `latexmkrc`.
+#[derive(Default, Debug, PartialEq)]
+struct Latexmkrc {
+    default_file: Option<String>,
+    program: Option<&'static str>,
+    shell_escape: bool,
+    biber: bool,
+    out_dirs: Vec<String>,
+}
+
+pub struct OverleafImporter;
+
+impl OverleafImporter {
+    pub fn import(data: &[u8]) -> Result<OverleafProject, String> {
+        Self::import_reader(std::io::Cursor::new(data))
+    }
+
+    /// Like [`OverleafImporter::import`], reading the archive from e.g. a downloaded file.
+    pub fn import_reader(reader: impl Read + Seek) -> Result<OverleafProject, String> {
+        let mut zip = zip::ZipArchive::new(reader).map_err(|e| format!("Not a ZIP archive: {}", e))?;
+        let mut entries: Vec<AuxFile> = Vec::new();
+        let mut total = 0u64;
+        for i in 0..zip.len() {
+            let mut entry = zip.by_index(i).map_err(|e| format!("Corrupt archive: {}", e))?;
+            if entry.is_dir() {
+                continue;
+            }
+            let Some(path) = entry.enclosed_name().map(|p| p.to_string_lossy().replace('\\', "/")) else { continue };
+            if path.starts_with("__MACOSX/") || path.split('/').any(|part| part == ".DS_Store" || part == ".git") {
+                continue;
+            }
+            let mut bytes = Vec::new();
+            (&mut entry).take(MAX_IMPORT_BYTES - total + 1).read_to_end(&mut bytes).map_err(|e| format!("Cannot read {}: {}", path, e))?;
+            total += bytes.len() as u64;
+            if total > MAX_IMPORT_BYTES {
+                return Err(format!("Archive expands to more than {} MB", MAX_IMPORT_BYTES / 1024 / 1024));
+            }
+            entries.push((path, bytes));
+        }
+        strip_common_folder(&mut entries);
+
+        let rc = entries
+            .iter()
+            .find(|(name, _)| name == "latexmkrc" || name == ".latexmkrc")
+            .map(|(_, data)| parse_latexmkrc(&String::from_utf8_lossy(data)))
+            .unwrap_or_default();
+
+        let mut warnings = Vec::new();
+        let main = pick_main(&entries, &rc, &mut warnings)?;
+        let main_stem = main.trim_end_matches(".tex").to_string();
+
+        // Drop build output: by-products, Overleaf's output.* files, latexmk's output
+        // directories and a PDF left over from compiling the main file
+        let before = entries.len();
+        entries.retain(|(name, _)| {
+            let lower = name.to_ascii_lowercase();
+            let build_product = BUILD_EXTENSIONS.iter().any(|ext| lower.ends_with(&format!(".{}", ext)))
+                || lower.starts_with("output.")
+                || lower == format!("{}.pdf", main_stem.to_ascii_lowercase())
+                || rc.out_dirs.iter().any(|dir| name.starts_with(&format!("{}/", dir)))
+                || name.ends_with(".latexmain")
+                || name == crate::export::MANIFEST_NAME
+                || name == "latexmkrc"
+                || name == ".latexmkrc";
+            !build_product
+        });
+        if entries.len() < before {
+            warnings.push(format!("Dropped {} build file(s) (logs, aux files and compiled output)", before - entries.len()));
+        }
+
+        let main_source = entries.iter().find(|(name, _)| *name == main).map(|(_, data)| String::from_utf8_lossy(data).to_string()).unwrap_or_default();
+        let engine = detect_engine(&main_source, &rc, &mut warnings);
+        Ok(OverleafProject { files: entries, main, engine, warnings })
+    }
+}
+
+/// Removes a single folder wrapping every entry, as produced by zipping a project directory.
+fn strip_common_folder(entries: &mut [AuxFile]) {
+    let Some(first) = entries.first().and_then(|(name, _)| name.split_once('/')).map(|(folder, _)| format!("{}/", folder)) else { return };
+    if entries.iter().all(|(name, _)| name.starts_with(&first)) {
+        for (name, _) in entries.iter_mut() {
+            *name = name[first.len()..].to_string();
+        }
+    }
+}
+
+fn parse_latexmkrc(text: &str) -> Latexmkrc {
+    let mut rc = Latexmkrc::default();
+    let default_files = Regex::new(r#"@default_files\s*=\s*\(\s*['"]([^'"]+)['"]"#).unwrap();
+    let pdf_mode = Regex::new(r"\$pdf_mode\s*=\s*(\d)").unwrap();
+    let out_dir = Regex::new(r#"\$(?:out_dir|aux_dir)\s*=\s*['"]([^'"]+)['"]"#).unwrap();
+    for line in text.lines() {
+        let line = line.split('#').next().unwrap_or("");
+        if let Some(c) = default_files.captures(line) {
+            let file = c[1].trim_start_matches("./").to_string();
+            rc.default_file = Some(if file.ends_with(".tex") { file } else { format!("{}.tex", file) });
+        }
+        if let Some(c) = pdf_mode.captures(line) {
+            rc.program = match &c[1] {
+                "1" => Some("pdflatex"),
+                "4" => Some("lualatex"),
+                "5" => Some("xelatex"),
+                _ => rc.program,
+            };
+        }
+        if let Some(c) = out_dir.captures(line) {
+            rc.out_dirs.push(c[1].trim_start_matches("./").trim_end_matches('/').to_string());
+        }
+        if line.contains("shell-escape") || line.contains("shell_escape") {
+            rc.shell_escape = true;
+        }
+        if line.contains("biber") {
+            rc.biber = true;
+        }
+    }
+    rc
+}
+
+/// The `% !TEX <key> = <value>` magic comment of a source, if any.
+fn magic_comment(source: &str, key: &str) -> Option<String> {
+    let re = Regex::new(&format!(r"(?im)^\s*%\s*!\s*TEX\s+(?:TS-)?{}\s*=\s*(\S+)", key)).unwrap();
+    re.captures(source).map(|c| c[1].trim().to_string())
+}
+
+/// Resolves `target` relative to the folder of `from`, as `% !TEX root` paths are.
+fn resolve(from: &str, target: &str) -> String {
+    let mut parts: Vec<&str> = from.split('/').collect();
+    parts.pop();
+    for part in target.split('/') {
+        match part {
+            "." | "" => {}
+            ".." => { parts.pop(); }
+            part => parts.push(part),
+        }
+    }
+    parts.join("/")
+}
+
+fn pick_main(entries: &[AuxFile], rc: &Latexmkrc, warnings: &mut Vec<String>) -> Result<String, String> {
+    let tex: Vec<(&str, String)> = entries
+        .iter()
+        .filter(|(name, _)| name.ends_with(".tex"))
+        .map(|(name, data)| (name.as_str(), String::from_utf8_lossy(data).to_string()))
+        .collect();
+    let exists: HashSet<&str> = tex.iter().map(|(name, _)| *name).collect();
+
+    // Our own exports record the main file in their manifest
+    if let Some((_, manifest)) = entries.iter().find(|(name, _)| name == crate::export::MANIFEST_NAME) {
+        let main = serde_json::from_slice::<serde_json::Value>(manifest).ok().and_then(|m| m["main"].as_str().map(str::to_string));
+        if let Some(main) = main.filter(|main| exists.contains(main.as_str())) {
+            return Ok(main);
+        }
+    }
+    // A `.latexmain` marker (TeXShop, TeXstudio) names its file explicitly
+    if let Some((marker, _)) = entries.iter().find(|(name, _)| name.ends_with(".tex.latexmain")) {
+        let main = marker.trim_end_matches(".latexmain");
+        if exists.contains(main) {
+            return Ok(main.to_string());
+        }
+        warnings.push(format!("{} points at a missing file", marker));
+    }
+    if let Some(main) = &rc.default_file {
+        if exists.contains(main.as_str()) {
+            return Ok(main.clone());
+        }
+        warnings.push(format!("latexmkrc names {} as the main file, but it is missing", main));
+    }
+    // Chapters often carry `% !TEX root = ../main.tex`
+    let mut roots: Vec<String> = tex
+        .iter()
+        .filter_map(|(name, source)| magic_comment(source, "root").map(|root| resolve(name, &root)))
+        .filter(|root| exists.contains(root.as_str()))
+        .collect();
+    roots.sort();
+    roots.dedup();
+    if let [root] = roots.as_slice() {
+        return Ok(root.clone());
+    }
+
+    let document = Regex::new(r"\\documentclass\b").unwrap();
+    let mut candidates: Vec<&str> = tex
+        .iter()
+        .filter(|(_, source)| {
+            let code: String = source.lines().map(strip_comment).collect::<Vec<_>>().join("\n");
+            document.is_match(&code) && code.contains("\\begin{document}")
+        })
+        .map(|(name, _)| *name)
+        .collect();
+    candidates.sort_by_key(|name| {
+        let file = name.rsplit('/').next().unwrap_or(name);
+        (name.matches('/').count(), MAIN_NAMES.iter().position(|m| *m == file).unwrap_or(MAIN_NAMES.len()), name.to_string())
+    });
+    match candidates.as_slice() {
+        [] => Err("No .tex file with \\documentclass and \\begin{document} in the archive".to_string()),
+        [main] => Ok(main.to_string()),
+        [main, others @ ..] => {
+            warnings.push(format!("Picked {} as the main file among {} candidates ({})", main, others.len() + 1, others.join(", ")));
+            Ok(main.to_string())
+        }
+    }
+}
+
+/// The engine a main file asks for in its magic comments and packages, for projects that
+/// come without a latexmkrc.
+pub fn engine_for(main_source: &str, warnings: &mut Vec<String>) -> ProjectEngine {
+    detect_engine(main_source, &Latexmkrc::default(), warnings)
+}
+
+fn detect_engine(main_source: &str, rc: &Latexmkrc, warnings: &mut Vec<String>) -> ProjectEngine {
+    let program = magic_comment(main_source, "program")
+        .map(|p| p.to_ascii_lowercase())
+        .or_else(|| rc.program.map(str::to_string))
+        .unwrap_or_else(|| "pdflatex".to_string());
+    let biber = rc.biber
+        || Regex::new(r"\\usepackage\s*\[[^\]]*backend\s*=\s*biber").unwrap().is_match(main_source)
+        || (main_source.contains("{biblatex}") && !main_source.contains("backend=bibtex"));
+    let engine = ProjectEngine {
+        program,
+        shell_escape: rc.shell_escape,
+        bibliography: if biber { "biber" } else { "bibtex" }.to_string(),
+    };
+    if engine.program != "xelatex" {
+        warnings.push(format!("The project was set up for {}; it compiles here with XeTeX (tectonic), which handles most such documents", engine.program));
+    }
+    if engine.shell_escape {
+        warnings.push("latexmkrc enables shell escape, which is not available: packages such as minted will fail".to_string());
+    }
+    if biber {
+        warnings.push("biblatex with biber is not supported; use backend=bibtex".to_string());
+    }
+    engine
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn archive(files: &[(&str, &str)]) -> Vec<u8> {
+        let files: Vec<(String, &str)> = files.iter().map(|(n, c)| (n.to_string(), *c)).collect();
+        crate::render::zip_files(&files).unwrap()
+    }
+
+    const DOC: &str = "\\documentclass{article}\n\\begin{document}\nHi\n\\end{document}\n";
+
+    #[test]
+    fn test_overleaf_export() {
+        let data = archive(&[
+            ("thesis/latexmkrc", "$pdf_mode = 5;\n$out_dir = 'build';\n"),
+            ("thesis/thesis.tex", DOC),
+            ("thesis/template.tex", DOC),
+            ("thesis/chapters/intro.tex", "% !TEX root = ../thesis.tex\n\\section{Intro}"),
+            ("thesis/build/thesis.pdf", "%PDF"),
+            ("thesis/thesis.aux", "\\relax"),
+            ("thesis/output.log", "log"),
+            ("thesis/figures/plot.png", "png"),
+        ]);
+        let project = OverleafImporter::import(&data).unwrap();
+        assert_eq!(project.main, "thesis.tex");
+        assert_eq!(project.engine.program, "xelatex");
+        let mut names: Vec<&str> = project.files.iter().map(|(n, _)| n.as_str()).collect();
+        names.sort();
+        assert_eq!(names, ["chapters/intro.tex", "figures/plot.png", "template.tex", "thesis.tex"]);
+        assert!(project.warnings.iter().any(|w| w.contains("Dropped 4 build file(s)")), "{:?}", project.warnings);
+    }
+
+    #[test]
+    fn test_main_file_markers() {
+        let marked = archive(&[("a.tex", DOC), ("b.tex", DOC), ("b.tex.latexmain", "")]);
+        assert_eq!(OverleafImporter::import(&marked).unwrap().main, "b.tex");
+
+        let named = archive(&[("appendix.tex", DOC), ("main.tex", "% !TEX program = lualatex\n\\documentclass{article}\n\\begin{document}\\end{document}")]);
+        let project = OverleafImporter::import(&named).unwrap();
+        assert_eq!((project.main.as_str(), project.engine.program.as_str()), ("main.tex", "lualatex"));
+        assert!(project.warnings.iter().any(|w| w.contains("among 2 candidates")));
+
+        assert!(OverleafImporter::import(&archive(&[("notes.tex", "% \\documentclass{article}\n")])).is_err());
+        assert!(OverleafImporter::import(b"not a zip").is_err());
+    }
+
+    #[test]
+    fn test_latexmkrc() {
+        let rc = parse_latexmkrc("@default_files = ('report');\n$pdflatex = 'pdflatex -shell-escape %O %S'; # comment biber\n$pdf_mode = 1;\n");
+        assert_eq!(rc, Latexmkrc { default_file: Some("report.tex".into()), program: Some("pdflatex"), shell_escape: true, biber: false, out_dirs: vec![] });
+    }
+}
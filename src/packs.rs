@@ -0,0 +1,518 @@
+//! Template packs (`/templates`): LaTeX templates published as Git repositories with a
+//! `tachyon-templates.json` manifest at their root. A pack follows a version tag, the newest
+//! one unless it is pinned, and is re-synced every TEMPLATE_SYNC_INTERVAL_SECS. A sync only
+//! replaces the pack's files once every template's sample document compiles, so a broken
+//! release never reaches the projects created from the pack.
+//!
+//! ```json
+//! {"name": "Acme letters", "description": "Letterheads for Acme Inc.", "author": "Acme", "license": "MIT",
+//!  "templates": [{"name": "letter", "main": "letter/letter.tex", "sample": "letter/sample.tex"}]}
+//! ```
+
+use axum::http::StatusCode;
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::io::Read;
+use std::process::{Command, Stdio};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{info, warn};
+use xxhash_rust::xxh64::xxh64;
+
+use crate::filetypes::FilePolicy;
+use crate::models::{PackSync, PackTemplate, ProjectFile, RegisterTemplatePackRequest, SampleResult, TemplatePack};
+use crate::render::AuxFile;
+use crate::sandbox::Sandbox;
+use crate::services::{AppState, Priority};
+use crate::storage::Storage;
+use crate::tenancy::TENANT;
+use crate::webhooks::Webhooks;
+use crate::util::unix_now;
+
+const PREFIX: &str = "template-packs/";
+
+pub const MANIFEST_NAME: &str = "tachyon-templates.json";
+
+/// Longest a sample document may compile for.
+const SAMPLE_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Directory of the checkout inside the sync's workspace.
+const CHECKOUT_DIR: &str = "checkout";
+
+/// How often a running clone's size is checked against TEMPLATE_PACK_MAX_MB.
+const CLONE_POLL: Duration = Duration::from_millis(200);
+
+#[derive(Debug, Deserialize)]
+struct Manifest {
+    name: String,
+    description: Option<String>,
+    author: Option<String>,
+    license: Option<String>,
+    templates: Vec<PackTemplate>,
+}
+
+/// A pack as stored: with its tenant and the tag object synced, which tells whether the
+/// tag moved since.
+#[derive(Serialize, Deserialize)]
+struct StoredPack {
+    pack: TemplatePack,
+    tenant: Option<String>,
+    tag_object: String,
+}
+
+/// A version fetched from the repository, before its samples are compiled.
+struct Checkout {
+    version: String,
+    tag_object: String,
+    commit: String,
+    manifest: Manifest,
+    files: Vec<AuxFile>,
+    warnings: Vec<String>,
+}
+
+#[derive(Clone)]
+pub struct PackStore {
+    storage: Arc<dyn Storage>,
+}
+
+impl PackStore {
+    pub fn new(storage: Arc<dyn Storage>) -> Self {
+        Self { storage }
+    }
+
+    /// A pack of the tenant, or a public one.
+    pub async fn get(&self, id: &str, tenant: Option<&str>) -> Option<TemplatePack> {
+        let stored = self.load(id).await?;
+        (stored.pack.public || stored.tenant.as_deref() == tenant).then_some(stored.pack)
+    }
+
+    /// The tenant's packs and the public ones, by name.
+    pub async fn list(&self, tenant: Option<&str>) -> Vec<TemplatePack> {
+        let mut packs: Vec<TemplatePack> = self.all().await.into_iter()
+            .filter(|stored| stored.pack.public || stored.tenant.as_deref() == tenant)
+            .map(|stored| stored.pack)
+            .collect();
+        packs.sort_by(|a, b| a.name.cmp(&b.name).then(a.created_at.cmp(&b.created_at)));
+        packs
+    }
+
+    /// Whether the tenant had registered a pack with this id; public packs can only be
+    /// deleted by requests without a tenant, which the handlers hold to the admin token.
+    pub async fn delete(&self, id: &str, tenant: Option<&str>) -> bool {
+        self.owned(id, tenant).await.is_some() && self.storage.delete(&key(id)).await.is_ok()
+    }
+
+    async fn owned(&self, id: &str, tenant: Option<&str>) -> Option<StoredPack> {
+        self.load(id).await.filter(|stored| stored.tenant.as_deref() == tenant)
+    }
+
+    async fn load(&self, id: &str) -> Option<StoredPack> {
+        if id.is_empty() || !id.chars().all(|c| c.is_ascii_alphanumeric()) {
+            return None;
+        }
+        serde_json::from_slice(&self.storage.get(&key(id)).await.ok()??).ok()
+    }
+
+    async fn save(&self, stored: &StoredPack) -> Result<(), String> {
+        let data = serde_json::to_vec(stored).map_err(|e| e.to_string())?;
+        self.storage.put(&key(&stored.pack.id), data).await
+    }
+
+    async fn all(&self) -> Vec<StoredPack> {
+        let keys = match self.storage.list(PREFIX).await {
+            Ok(keys) => keys,
+            Err(e) => {
+                warn!("Failed to list template packs: {}", e);
+                return Vec::new();
+            }
+        };
+        let mut packs = Vec::with_capacity(keys.len());
+        for key in keys {
+            if let Some(stored) = self.storage.get(&key).await.ok().flatten().and_then(|data| serde_json::from_slice(&data).ok()) {
+                packs.push(stored);
+            }
+        }
+        packs
+    }
+}
+
+fn key(id: &str) -> String {
+    format!("{}{}", PREFIX, id)
+}
+
+/// Registers a pack from its first successful sync. The outer error is a request the
+/// server refuses outright; the inner one a sync that failed, with its report.
+pub async fn register(state: &AppState, tenant: Option<String>, request: RegisterTemplatePackRequest) -> Result<Result<TemplatePack, PackSync>, (StatusCode, String)> {
+    Webhooks::validate_url(&request.git_url, &state.settings).map_err(|e| (StatusCode::BAD_REQUEST, format!("git_url rejected: {}", e)))?;
+    if let Some(version) = request.version.as_deref().filter(|v| !is_valid_tag(v)) {
+        return Err((StatusCode::BAD_REQUEST, format!("Invalid version tag '{}'", version)));
+    }
+    let (sync, checkout) = sync(state, &request.git_url, request.version.as_deref(), None).await;
+    let Some((checkout, files)) = checkout else {
+        info!("📦 Template pack {} was not registered: {}", request.git_url, sync.error.as_deref().unwrap_or_default());
+        return Ok(Err(sync));
+    };
+    let now = sync.at;
+    let pack = TemplatePack {
+        id: uuid::Uuid::new_v4().simple().to_string(),
+        git_url: request.git_url,
+        pinned_version: request.version,
+        version: checkout.version,
+        commit: checkout.commit,
+        name: checkout.manifest.name,
+        description: checkout.manifest.description,
+        author: checkout.manifest.author,
+        license: checkout.manifest.license,
+        templates: checkout.manifest.templates,
+        files,
+        public: tenant.is_none(),
+        created_at: now,
+        synced_at: now,
+        last_sync: Some(sync),
+    };
+    let stored = StoredPack { pack, tenant, tag_object: checkout.tag_object };
+    state.packs.save(&stored).await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))?;
+    info!("📦 Registered template pack {} {} ({} template(s)) as {}", stored.pack.name, stored.pack.version, stored.pack.templates.len(), stored.pack.id);
+    Ok(Ok(stored.pack))
+}
+
+/// Syncs a pack of the tenant now, recording the attempt. `None` if the tenant did not
+/// register a pack with this id.
+pub async fn resync(state: &AppState, id: &str, tenant: Option<&str>) -> Option<PackSync> {
+    let stored = state.packs.owned(id, tenant).await?;
+    let pack = &stored.pack;
+    let known = (pack.version.as_str(), stored.tag_object.as_str());
+    let (sync, checkout) = sync(state, &pack.git_url, pack.pinned_version.as_deref(), Some(known)).await;
+    let mut stored = state.packs.owned(id, tenant).await?;
+    if let Some((checkout, files)) = checkout {
+        info!("📦 Template pack {} synced to {}", stored.pack.name, checkout.version);
+        let pack = &mut stored.pack;
+        pack.version = checkout.version;
+        pack.commit = checkout.commit;
+        pack.name = checkout.manifest.name;
+        pack.description = checkout.manifest.description;
+        pack.author = checkout.manifest.author;
+        pack.license = checkout.manifest.license;
+        pack.templates = checkout.manifest.templates;
+        pack.files = files;
+        pack.synced_at = sync.at;
+        stored.tag_object = checkout.tag_object;
+    } else if !sync.success {
+        warn!("📦 Sync of template pack {} failed; it stays on {}: {}", stored.pack.name, stored.pack.version, sync.error.as_deref().unwrap_or_default());
+    }
+    stored.pack.last_sync = Some(sync.clone());
+    if let Err(e) = state.packs.save(&stored).await {
+        warn!("Failed to record the sync of template pack {}: {}", id, e);
+    }
+    Some(sync)
+}
+
+/// Fetches the version to sync and compiles its samples. The checkout and the pack's
+/// stored files come back only when the version was accepted and is new.
+async fn sync(state: &AppState, git_url: &str, pinned: Option<&str>, known: Option<(&str, &str)>) -> (PackSync, Option<(Checkout, Vec<ProjectFile>)>) {
+    let mut sync = PackSync {
+        at: unix_now(),
+        version: None,
+        commit: None,
+        success: false,
+        up_to_date: false,
+        samples: Vec::new(),
+        warnings: Vec::new(),
+        error: None,
+    };
+    let checkout = match fetch(state, git_url, pinned, known).await {
+        Ok(Some(checkout)) => checkout,
+        Ok(None) => {
+            let (version, _) = known.unwrap_or_default();
+            return (PackSync { version: Some(version.to_string()), success: true, up_to_date: true, ..sync }, None);
+        }
+        Err(e) => return (PackSync { error: Some(e), ..sync }, None),
+    };
+    sync.version = Some(checkout.version.clone());
+    sync.commit = Some(checkout.commit.clone());
+    sync.warnings = checkout.warnings.clone();
+
+    for template in &checkout.manifest.templates {
+        let file = template.sample.clone().unwrap_or_else(|| template.main.clone());
+        let result = SampleResult { template: template.name.clone(), file: file.clone(), success: false, compile_time_ms: 0, output_hash: None, error: None };
+        let result = match crate::render::compile_uncached(state, &file, &checkout.files, Priority::Batch, SAMPLE_TIMEOUT, None).await {
+            Ok((Ok(pdf), _, compile_time_ms)) => SampleResult { success: true, compile_time_ms, output_hash: Some(state.output_store.put(&pdf).await), ..result },
+            Ok((Err(e), _, compile_time_ms)) => SampleResult { compile_time_ms, error: Some(e), ..result },
+            Err(e) => SampleResult { error: Some(e), ..result },
+        };
+        sync.samples.push(result);
+    }
+    let failed: Vec<&str> = sync.samples.iter().filter(|s| !s.success).map(|s| s.template.as_str()).collect();
+    if !failed.is_empty() {
+        sync.error = Some(format!("The sample of {} failed to compile", failed.join(", ")));
+        return (sync, None);
+    }
+
+    let mut files = Vec::with_capacity(checkout.files.len());
+    for (name, data) in &checkout.files {
+        let hash = format!("{:x}", xxh64(data, 0));
+        files.push(ProjectFile { name: name.clone(), hash: hash.clone(), size: data.len() as u64 });
+        state.blob_store.put(hash, data.clone()).await;
+    }
+    sync.success = true;
+    (sync, Some((checkout, files)))
+}
+
+/// Resolves the repository host under the webhook target policy, then lists its tags and
+/// checks out the version to sync. `None` when that is still the `known` version and tag.
+async fn fetch(state: &AppState, git_url: &str, pinned: Option<&str>, known: Option<(&str, &str)>) -> Result<Option<Checkout>, String> {
+    let url = Webhooks::validate_url(git_url, &state.settings)?;
+    let addrs = Webhooks::resolve_target(&url, &state.settings).await?;
+    let workspace = state.janitor.workspace()?;
+    // Git needs the network, but stays confined to the workspace
+    let sandbox = Sandbox { allow_network: true, ..state.sandbox.clone() };
+    let max_bytes = state.settings.template_pack_max_mb * 1024 * 1024;
+    let allowed = state.settings.allowed_file_types.clone();
+    let (git_url, pinned, known) = (git_url.to_string(), pinned.map(str::to_string), known.map(|(v, t)| (v.to_string(), t.to_string())));
+    tokio::task::spawn_blocking(move || {
+        let git = Git { url: &git_url, pin: pin(&url, &addrs), sandbox: &sandbox, work_dir: workspace.path() };
+        let tags = parse_tags(&git.run(&["ls-remote", "--tags", "--refs", &git_url])?);
+        let (version, tag_object) = match &pinned {
+            Some(version) => tags.into_iter().find(|(tag, _)| tag == version).ok_or_else(|| format!("The repository has no tag {}", version))?,
+            None => newest(tags).ok_or_else(|| "The repository has no version tags (such as v1.2.0)".to_string())?,
+        };
+        if known.as_ref().is_some_and(|(v, t)| *v == version && *t == tag_object) {
+            return Ok(None);
+        }
+        let checkout = workspace.path().join(CHECKOUT_DIR);
+        // Git metadata counts too: the cap holds while the clone downloads
+        git.run_capped(&["clone", "--depth", "1", "--single-branch", "--no-tags", "--branch", &version, "--", &git_url, CHECKOUT_DIR], &checkout, max_bytes)?;
+        let commit = git.run(&["-C", CHECKOUT_DIR, "rev-parse", "HEAD"])?.trim().to_string();
+        let (manifest, files, warnings) = read_checkout(&checkout, max_bytes, &FilePolicy::new(&allowed))?;
+        Ok(Some(Checkout { version, tag_object, commit, manifest, files, warnings }))
+    })
+    .await
+    .map_err(|e| format!("Sync failed: {}", e))?
+}
+
+/// The `host:port:addresses` entry that makes Git connect to the addresses the policy checked.
+fn pin(url: &reqwest::Url, addrs: &[SocketAddr]) -> String {
+    let host = url.host_str().unwrap_or_default();
+    let port = url.port_or_known_default().unwrap_or(443);
+    let addresses: Vec<String> = addrs.iter().map(|addr| match addr {
+        SocketAddr::V4(v4) => v4.ip().to_string(),
+        SocketAddr::V6(v6) => format!("[{}]", v6.ip()),
+    }).collect();
+    format!("{}:{}:{}", host, port, addresses.join(","))
+}
+
+struct Git<'a> {
+    url: &'a str,
+    pin: String,
+    sandbox: &'a Sandbox,
+    work_dir: &'a Path,
+}
+
+impl Git<'_> {
+    /// Runs git without prompts, redirects, local protocols or the system configuration.
+    fn run(&self, args: &[&str]) -> Result<String, String> {
+        let output = self.command(args).output().map_err(|e| format!("git is not available: {}", e))?;
+        if !output.status.success() {
+            return Err(format!("git failed on {}: {}", self.url, String::from_utf8_lossy(&output.stderr).trim()));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).to_string())
+    }
+
+    /// Like [`Git::run`], killing git as soon as `dir` holds more than `max_bytes`.
+    fn run_capped(&self, args: &[&str], dir: &Path, max_bytes: u64) -> Result<(), String> {
+        let mut child = self.command(args)
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("git is not available: {}", e))?;
+        // Drained on its own thread so a chatty git never blocks on a full pipe
+        let stderr = child.stderr.take().map(|mut pipe| std::thread::spawn(move || {
+            let mut text = String::new();
+            let _ = pipe.read_to_string(&mut text);
+            text
+        }));
+        let status = loop {
+            if let Some(status) = child.try_wait().map_err(|e| e.to_string())? {
+                break status;
+            }
+            if crate::janitor::dir_size(dir) > max_bytes {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(format!("The pack is larger than {} MB", max_bytes / 1024 / 1024));
+            }
+            std::thread::sleep(CLONE_POLL);
+        };
+        if !status.success() {
+            let stderr = stderr.and_then(|reader| reader.join().ok()).unwrap_or_default();
+            return Err(format!("git failed on {}: {}", self.url, stderr.trim()));
+        }
+        Ok(())
+    }
+
+    fn command(&self, args: &[&str]) -> Command {
+        let mut cmd = Command::new("git");
+        self.sandbox.apply(&mut cmd, self.work_dir);
+        cmd.env("GIT_TERMINAL_PROMPT", "0")
+            .env("GIT_CONFIG_NOSYSTEM", "1")
+            .args(["-c", "protocol.allow=never", "-c", "protocol.https.allow=always", "-c", "protocol.http.allow=always"])
+            .args(["-c", "http.followRedirects=false", "-c", "http.lowSpeedLimit=1000", "-c", "http.lowSpeedTime=30", "-c", "credential.helper="])
+            .arg("-c").arg(format!("http.curloptResolve={}", self.pin));
+        cmd.args(args);
+        cmd
+    }
+}
+
+/// Tags and the objects they point to, from `git ls-remote --tags --refs`.
+fn parse_tags(output: &str) -> Vec<(String, String)> {
+    output.lines()
+        .filter_map(|line| line.split_once('\t'))
+        .filter_map(|(object, reference)| Some((reference.strip_prefix("refs/tags/")?.to_string(), object.to_string())))
+        .collect()
+}
+
+/// The highest release tag: `1.2.0` or `v1.2.0`, with any number of parts. Pre-release
+/// tags such as `v2.0.0-rc1` are skipped.
+fn newest(tags: Vec<(String, String)>) -> Option<(String, String)> {
+    let version = |tag: &str| -> Option<Vec<u64>> {
+        tag.strip_prefix(['v', 'V']).unwrap_or(tag).split('.').map(|part| part.parse().ok()).collect()
+    };
+    tags.into_iter().filter_map(|(tag, object)| Some((version(&tag)?, tag, object))).max().map(|(_, tag, object)| (tag, object))
+}
+
+fn is_valid_tag(tag: &str) -> bool {
+    !tag.is_empty() && !tag.starts_with('-') && !tag.contains("..") && tag.chars().all(|c| c.is_ascii_alphanumeric() || "._-/".contains(c))
+}
+
+/// Reads the manifest and the files of a checkout, leaving out Git metadata, symbolic
+/// links and files compiles would refuse.
+fn read_checkout(dir: &Path, max_bytes: u64, policy: &FilePolicy) -> Result<(Manifest, Vec<AuxFile>, Vec<String>), String> {
+    let mut files = Vec::new();
+    let mut warnings = Vec::new();
+    let mut total = 0u64;
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(current) = pending.pop() {
+        for entry in std::fs::read_dir(&current).map_err(|e| e.to_string())? {
+            let entry = entry.map_err(|e| e.to_string())?;
+            let path = entry.path();
+            let name = path.strip_prefix(dir).map_err(|e| e.to_string())?.to_string_lossy().replace('\\', "/");
+            let file_type = entry.file_type().map_err(|e| e.to_string())?;
+            if name == ".git" || name.starts_with(".git/") {
+                continue;
+            } else if file_type.is_symlink() {
+                warnings.push(format!("Dropped {}: symbolic links are not followed", name));
+            } else if file_type.is_dir() {
+                pending.push(path);
+            } else {
+                total += entry.metadata().map_err(|e| e.to_string())?.len();
+                if total > max_bytes {
+                    return Err(format!("The pack is larger than {} MB", max_bytes / 1024 / 1024));
+                }
+                files.push((name, std::fs::read(&path).map_err(|e| e.to_string())?));
+            }
+        }
+    }
+
+    let manifest_data = files.iter().find(|(name, _)| name == MANIFEST_NAME).map(|(_, data)| data.clone())
+        .ok_or_else(|| format!("The repository has no {}", MANIFEST_NAME))?;
+    let manifest: Manifest = serde_json::from_slice(&manifest_data).map_err(|e| format!("Invalid {}: {}", MANIFEST_NAME, e))?;
+    files.retain(|(name, data)| match policy.check(name, &data[..data.len().min(4096)]) {
+        _ if name == MANIFEST_NAME || name.rsplit('/').next().is_some_and(|file| file.starts_with('.')) => false,
+        Some(rejection) => {
+            warnings.push(format!("Dropped {}: {}", rejection.file, rejection.reason));
+            false
+        }
+        None => true,
+    });
+    files.sort_by(|a, b| a.0.cmp(&b.0));
+
+    if manifest.name.trim().is_empty() || manifest.templates.is_empty() {
+        return Err(format!("{} needs a name and at least one template", MANIFEST_NAME));
+    }
+    for (i, template) in manifest.templates.iter().enumerate() {
+        if template.name.is_empty() || manifest.templates[..i].iter().any(|t| t.name == template.name) {
+            return Err(format!("Template names must be present and unique; '{}' is not", template.name));
+        }
+        for file in std::iter::once(&template.main).chain(&template.sample) {
+            if !files.iter().any(|(name, _)| name == file) {
+                return Err(format!("Template {} names {}, which is not in the pack", template.name, file));
+            }
+        }
+    }
+    Ok((manifest, files, warnings))
+}
+
+/// Re-syncs every pack that follows its newest version tag each TEMPLATE_SYNC_INTERVAL_SECS.
+pub async fn sync_task(state: AppState) {
+    let interval = state.settings.template_sync_interval_secs;
+    if interval == 0 {
+        return;
+    }
+    loop {
+        tokio::time::sleep(Duration::from_secs(interval)).await;
+        let (mut synced, mut failed) = (0, 0);
+        for stored in state.packs.all().await.into_iter().filter(|stored| stored.pack.pinned_version.is_none()) {
+            // Blobs and samples are written for the pack's tenant, as in its requests
+            let tenant = stored.tenant.clone();
+            let sync = TENANT.scope(tenant.clone(), resync(&state, &stored.pack.id, tenant.as_deref())).await;
+            match sync {
+                Some(sync) if !sync.success => failed += 1,
+                Some(sync) if !sync.up_to_date => synced += 1,
+                _ => {}
+            }
+        }
+        if synced + failed > 0 {
+            info!("📦 Synced {} template pack(s), {} failed", synced, failed);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tags(names: &[&str]) -> Vec<(String, String)> {
+        names.iter().map(|name| (name.to_string(), format!("{}-object", name))).collect()
+    }
+
+    #[test]
+    fn test_tags() {
+        let listed = parse_tags("a1b2\trefs/tags/v1.2.0\nc3d4\trefs/tags/v1.10.0\ne5f6\trefs/heads/main\n");
+        assert_eq!(listed, [("v1.2.0".to_string(), "a1b2".to_string()), ("v1.10.0".to_string(), "c3d4".to_string())]);
+        assert_eq!(newest(listed).unwrap().0, "v1.10.0");
+        assert_eq!(newest(tags(&["1.9", "v2.0.0-rc1", "release", "2.0"])).unwrap().0, "2.0");
+        assert!(newest(tags(&["latest", "stable"])).is_none());
+
+        assert!(is_valid_tag("v1.2.0") && is_valid_tag("release/2026-10"));
+        assert!(!is_valid_tag("--upload-pack=x") && !is_valid_tag("a..b") && !is_valid_tag("v1 .0"));
+    }
+
+    #[test]
+    fn test_read_checkout() {
+        let dir = tempfile::tempdir().unwrap();
+        let write = |name: &str, data: &[u8]| {
+            let path = dir.path().join(name);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, data).unwrap();
+        };
+        write(MANIFEST_NAME, br#"{"name": "Acme letters", "license": "MIT", "templates": [{"name": "letter", "main": "letter/letter.tex", "sample": "letter/sample.tex"}]}"#);
+        write("letter/letter.tex", b"\\documentclass{letter}");
+        write("letter/sample.tex", b"\\input{letter}");
+        write("letter/logo.exe", b"MZ\x90\x00");
+        write(".git/config", b"[core]");
+        write(".gitignore", b"*.pdf");
+        std::os::unix::fs::symlink("/etc/passwd", dir.path().join("passwd.tex")).unwrap();
+        let allowed = vec!["tex".to_string()];
+        let policy = FilePolicy::new(&allowed);
+
+        let (manifest, files, warnings) = read_checkout(dir.path(), 1024 * 1024, &policy).unwrap();
+        assert_eq!(manifest.name, "Acme letters");
+        let names: Vec<&str> = files.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["letter/letter.tex", "letter/sample.tex"]);
+        assert_eq!(warnings.len(), 2, "{:?}", warnings);
+
+        assert!(read_checkout(dir.path(), 10, &policy).unwrap_err().contains("larger than"));
+        write(MANIFEST_NAME, br#"{"name": "Acme letters", "templates": [{"name": "memo", "main": "memo.tex"}]}"#);
+        assert!(read_checkout(dir.path(), 1024 * 1024, &policy).unwrap_err().contains("memo.tex"));
+    }
+}
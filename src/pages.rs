@@ -0,0 +1,178 @@
+//! Per-page content hashes of compiled PDFs, so live-preview viewers can re-render only
+//! the pages that changed between compiles. A page hash covers its decoded content
+//! streams and the XObjects (images, included PDFs) it draws; object numbers are left
+//! out because they shift whenever anything earlier in the document changes.
+//!
+//! Pages are also checked for what templates emit without anyone noticing until the
+//! document is printed: blank pages and pages holding nothing but a float.
+
+use lopdf::{Dictionary, Document, Object, ObjectId};
+use xxhash_rust::xxh64::Xxh64;
+
+use crate::models::CompileWarning;
+
+/// Glyphs a page may show and still count as blank: a page number.
+const BLANK_PAGE_MAX_GLYPHS: usize = 4;
+
+/// Glyphs a page drawing an image or included PDF may show and still count as holding
+/// only a float: a caption and the page number.
+const FLOAT_PAGE_MAX_GLYPHS: usize = 150;
+
+/// Number of pages of a PDF.
+pub fn page_count(pdf: &[u8]) -> Result<usize, String> {
+    let doc = Document::load_mem(pdf).map_err(|e| format!("Failed to parse PDF: {}", e))?;
+    Ok(doc.get_pages().len())
+}
+
+/// Hex content hash of every page, in page order.
+pub fn page_hashes(pdf: &[u8]) -> Result<Vec<String>, String> {
+    let doc = Document::load_mem(pdf).map_err(|e| format!("Failed to parse PDF: {}", e))?;
+    doc.get_pages().into_values().map(|page_id| {
+        let mut hasher = Xxh64::new(0);
+        hasher.update(&doc.get_page_content(page_id).map_err(|e| e.to_string())?);
+
+        let (inline, resource_ids) = doc.get_page_resources(page_id).map_err(|e| e.to_string())?;
+        let resources = inline.into_iter().chain(resource_ids.into_iter().filter_map(|id| doc.get_dictionary(id).ok()));
+        for resources in resources {
+            hash_xobjects(&doc, resources, &mut hasher);
+        }
+        Ok(format!("{:016x}", hasher.digest()))
+    }).collect()
+}
+
+fn hash_xobjects(doc: &Document, resources: &Dictionary, hasher: &mut Xxh64) {
+    let Ok(xobjects) = doc.get_dict_in_dict(resources, b"XObject") else { return };
+    for (name, object) in xobjects.iter() {
+        if let Ok(stream) = doc.dereference(object).and_then(|(_, o)| Object::as_stream(o)) {
+            hasher.update(name);
+            hasher.update(&stream.content);
+        }
+    }
+}
+
+/// What a page's content stream draws.
+#[derive(Default)]
+struct Marks {
+    glyphs: usize,
+    images: usize,
+    paths: bool,
+}
+
+fn page_marks(doc: &Document, page_id: ObjectId) -> Result<Marks, String> {
+    let content = doc.get_and_decode_page_content(page_id).map_err(|e| e.to_string())?;
+    let fonts = doc.get_page_fonts(page_id).unwrap_or_default();
+    let mut marks = Marks::default();
+    // Type0 fonts, which XeTeX uses for OpenType, take two bytes per glyph
+    let mut glyph_bytes = 1;
+    let shown = |string: &Object| string.as_str().map_or(0, <[u8]>::len);
+    for operation in &content.operations {
+        match operation.operator.as_str() {
+            "Tf" => {
+                let font = operation.operands.first().and_then(|name| name.as_name().ok()).and_then(|name| fonts.get(name));
+                let type0 = font.is_some_and(|font| font.get(b"Subtype").and_then(Object::as_name).ok() == Some(b"Type0".as_slice()));
+                glyph_bytes = if type0 { 2 } else { 1 };
+            }
+            "Tj" | "'" | "\"" => marks.glyphs += operation.operands.last().map_or(0, shown) / glyph_bytes,
+            "TJ" => {
+                let parts = operation.operands.first().and_then(|array| array.as_array().ok());
+                marks.glyphs += parts.map_or(0, |parts| parts.iter().map(shown).sum::<usize>()) / glyph_bytes;
+            }
+            "Do" | "BI" => marks.images += 1,
+            "S" | "s" | "f" | "F" | "f*" | "B" | "B*" | "b" | "b*" | "sh" => marks.paths = true,
+            _ => {}
+        }
+    }
+    Ok(marks)
+}
+
+/// `blank_page` warnings for pages that draw nothing but perhaps a page number, and
+/// `float_page` warnings for pages after the first that draw an image or included PDF
+/// with no more text than a caption.
+pub fn ghost_page_warnings(pdf: &[u8]) -> Result<Vec<CompileWarning>, String> {
+    let doc = Document::load_mem(pdf).map_err(|e| format!("Failed to parse PDF: {}", e))?;
+    let mut warnings = Vec::new();
+    for (number, page_id) in doc.get_pages() {
+        let marks = page_marks(&doc, page_id)?;
+        let (kind, message) = if marks.images == 0 && !marks.paths && marks.glyphs == 0 {
+            ("blank_page", format!("Page {} is blank", number))
+        } else if marks.images == 0 && !marks.paths && marks.glyphs <= BLANK_PAGE_MAX_GLYPHS {
+            ("blank_page", format!("Page {} is blank apart from {} character(s), probably its page number", number, marks.glyphs))
+        } else if number > 1 && marks.images > 0 && marks.glyphs <= FLOAT_PAGE_MAX_GLYPHS {
+            ("float_page", format!("Page {} holds nothing but a float; it may have been pushed to a page of its own", number))
+        } else {
+            continue;
+        };
+        warnings.push(CompileWarning { kind: kind.to_string(), file: None, line: None, measurement: None, message });
+    }
+    Ok(warnings)
+}
+
+/// 1-based numbers of the pages in `current` that are new or differ from `previous`.
+pub fn changed_pages(previous: &[String], current: &[String]) -> Vec<usize> {
+    current.iter().enumerate()
+        .filter(|(i, hash)| previous.get(*i) != Some(*hash))
+        .map(|(i, _)| i + 1)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lopdf::{dictionary, Stream};
+
+    fn pdf(pages: &[&str]) -> Vec<u8> {
+        let mut doc = Document::with_version("1.5");
+        let pages_id = doc.new_object_id();
+        let kids: Vec<Object> = pages.iter().map(|text| {
+            let content = doc.add_object(Stream::new(dictionary! {}, text.as_bytes().to_vec()));
+            doc.add_object(dictionary! {
+                "Type" => "Page",
+                "Parent" => pages_id,
+                "Contents" => content,
+                "Resources" => dictionary! {},
+            }).into()
+        }).collect();
+        doc.objects.insert(pages_id, Object::Dictionary(dictionary! {
+            "Type" => "Pages",
+            "Kids" => kids,
+            "Count" => pages.len() as i64,
+        }));
+        let catalog = doc.add_object(dictionary! { "Type" => "Catalog", "Pages" => pages_id });
+        doc.trailer.set("Root", catalog);
+        let mut out = Vec::new();
+        doc.save_to(&mut out).unwrap();
+        out
+    }
+
+    #[test]
+    fn test_only_edited_pages_change() {
+        let before = page_hashes(&pdf(&["BT (one) Tj ET", "BT (two) Tj ET", "BT (three) Tj ET"])).unwrap();
+        let after = page_hashes(&pdf(&["BT (one) Tj ET", "BT (2) Tj ET", "BT (three) Tj ET", "BT (four) Tj ET"])).unwrap();
+        assert_eq!(before.len(), 3);
+        assert_eq!(before[0], after[0]);
+        assert_eq!(changed_pages(&before, &after), vec![2, 4]);
+        assert_eq!(changed_pages(&[], &before), vec![1, 2, 3]);
+        assert!(page_hashes(b"not a pdf").is_err());
+    }
+
+    #[test]
+    fn test_ghost_pages() {
+        let body = format!("BT ({}) Tj ET", "Running text of the chapter. ".repeat(20));
+        let with_figure = format!("{} q /Im1 Do Q", body);
+        let warnings = ghost_page_warnings(&pdf(&[
+            "q /Im1 Do Q BT (Title) Tj ET",
+            &body,
+            "",
+            "BT [(1)-250(2)] TJ ET",
+            "q /Im1 Do Q BT (Figure 1: Results) Tj ET",
+            &with_figure,
+            "0 0 m 100 0 l S BT (7) Tj ET",
+        ])).unwrap();
+        let flagged: Vec<(&str, &str)> = warnings.iter().map(|w| (w.kind.as_str(), w.message.as_str())).collect();
+        assert_eq!(flagged, vec![
+            ("blank_page", "Page 3 is blank"),
+            ("blank_page", "Page 4 is blank apart from 2 character(s), probably its page number"),
+            ("float_page", "Page 5 holds nothing but a float; it may have been pushed to a page of its own"),
+        ]);
+    }
+}
@@ -0,0 +1,263 @@
+//! Shared snippets (`POST /playground`): a few text files kept in storage until they
+//! expire, opened in the browser editor with a live preview at `/p/{id}`. Handy for bug
+//! reports and teaching. Snippets go through the same storage as everything else, so they
+//! are encrypted at rest and erased with their tenant's data.
+
+use axum::http::StatusCode;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use ring::rand::{SecureRandom, SystemRandom};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::{info, warn};
+
+use crate::jobs::is_safe_path;
+use crate::models::{PlaygroundRequest, PlaygroundSnippet};
+use crate::settings::Settings;
+use crate::storage::Storage;
+use crate::tenancy::current_client;
+use crate::util::unix_now;
+
+const PREFIX: &str = "playground/";
+
+/// Shortest lifetime a snippet can ask for.
+const MIN_TTL_SECS: u64 = 60;
+
+const SWEEP_INTERVAL_SECS: u64 = 3600;
+
+/// Random bytes in a share id: 11 URL-safe characters, not guessable.
+const ID_BYTES: usize = 8;
+
+/// Shares each client may create per hour (SHARES_PER_HOUR), counted in fixed hourly
+/// windows. Kept in memory, so each instance counts on its own.
+#[derive(Clone, Default)]
+pub struct ShareQuota {
+    /// Client -> (hour, shares created in it)
+    windows: Arc<Mutex<HashMap<String, (u64, u64)>>>,
+}
+
+impl ShareQuota {
+    /// Counts a share by `client`, or refuses it once `per_hour` were made this hour.
+    pub fn take(&self, client: &str, per_hour: u64) -> Result<(), String> {
+        if per_hour == 0 {
+            return Ok(());
+        }
+        let hour = unix_now() / 3600;
+        let mut windows = self.windows.lock().unwrap();
+        windows.retain(|_, (window, _)| *window == hour);
+        let (_, count) = windows.entry(client.to_string()).or_insert((hour, 0));
+        if *count >= per_hour {
+            return Err(format!("Limit of {} shares per hour reached; try again later", per_hour));
+        }
+        *count += 1;
+        Ok(())
+    }
+}
+
+#[derive(Clone)]
+pub struct PlaygroundStore {
+    storage: Arc<dyn Storage>,
+    quota: ShareQuota,
+}
+
+impl PlaygroundStore {
+    pub fn new(storage: Arc<dyn Storage>) -> Self {
+        Self { storage, quota: ShareQuota::default() }
+    }
+
+    /// Validates and stores a snippet. Its `url` is left for the handler to fill in.
+    pub async fn create(&self, request: PlaygroundRequest, settings: &Settings) -> Result<PlaygroundSnippet, (StatusCode, String)> {
+        let files = match (request.source, request.files) {
+            (Some(source), None) => HashMap::from([("main.tex".to_string(), source)]),
+            (None, Some(files)) if !files.is_empty() => files,
+            (None, Some(_)) => return Err((StatusCode::BAD_REQUEST, "files is empty".to_string())),
+            _ => return Err((StatusCode::BAD_REQUEST, "Send exactly one of source or files".to_string())),
+        };
+        if let Some(name) = files.keys().find(|name| !is_safe_path(name)) {
+            return Err((StatusCode::BAD_REQUEST, format!("Unsafe file name '{}'", name)));
+        }
+        let size: usize = files.iter().map(|(name, content)| name.len() + content.len()).sum();
+        let limit = settings.playground_max_kb as usize * 1024;
+        if size > limit {
+            return Err((StatusCode::PAYLOAD_TOO_LARGE, format!("Snippet is {} bytes, over the limit of {} bytes", size, limit)));
+        }
+        let main = match request.main {
+            Some(main) if files.contains_key(&main) => main,
+            Some(main) => return Err((StatusCode::BAD_REQUEST, format!("Main file '{}' is not in files", main))),
+            None => default_main(&files).ok_or((StatusCode::BAD_REQUEST, "Name the main file: there is no main.tex and several .tex files".to_string()))?,
+        };
+
+        self.quota.take(&current_client(), settings.shares_per_hour).map_err(|e| (StatusCode::TOO_MANY_REQUESTS, e))?;
+
+        let ttl = request.ttl_secs.unwrap_or(settings.playground_ttl_secs).clamp(MIN_TTL_SECS, settings.playground_max_ttl_secs.max(MIN_TTL_SECS));
+        let created_at = unix_now();
+        let snippet = PlaygroundSnippet { id: short_id(), url: String::new(), main, files, created_at, expires_at: created_at + ttl };
+        let data = serde_json::to_vec(&snippet).map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+        self.storage
+            .put(&format!("{}{}", PREFIX, snippet.id), data)
+            .await
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to store snippet: {}", e)))?;
+        Ok(snippet)
+    }
+
+    /// An unexpired snippet; an expired one is deleted on the way.
+    pub async fn get(&self, id: &str) -> Option<PlaygroundSnippet> {
+        if !is_short_id(id) {
+            return None;
+        }
+        let key = format!("{}{}", PREFIX, id);
+        let snippet: PlaygroundSnippet = serde_json::from_slice(&self.storage.get(&key).await.ok()??).ok()?;
+        if snippet.expires_at <= unix_now() {
+            self.storage.delete(&key).await.ok();
+            return None;
+        }
+        Some(snippet)
+    }
+
+    async fn sweep(&self) -> usize {
+        sweep_expired(self.storage.as_ref(), PREFIX).await
+    }
+}
+
+/// Removes expired snippets hourly; reads already skip them.
+pub async fn sweep_task(store: PlaygroundStore) {
+    loop {
+        tokio::time::sleep(Duration::from_secs(SWEEP_INTERVAL_SECS)).await;
+        let removed = store.sweep().await;
+        if removed > 0 {
+            info!("🧹 Removed {} expired playground snippet(s)", removed);
+        }
+    }
+}
+
+/// Deletes the JSON objects under `prefix` whose `expires_at` has passed (or that do not
+/// parse), returning how many.
+pub(crate) async fn sweep_expired(storage: &dyn Storage, prefix: &str) -> usize {
+    let keys = match storage.list(prefix).await {
+        Ok(keys) => keys,
+        Err(e) => {
+            warn!("Failed to list {}: {}", prefix, e);
+            return 0;
+        }
+    };
+    let mut removed = 0;
+    for key in keys {
+        let expired = match storage.get(&key).await {
+            Ok(Some(data)) => serde_json::from_slice::<serde_json::Value>(&data)
+                .ok()
+                .and_then(|object| object["expires_at"].as_u64())
+                .is_none_or(|expires_at| expires_at <= unix_now()),
+            _ => false,
+        };
+        if expired && storage.delete(&key).await.is_ok() {
+            removed += 1;
+        }
+    }
+    removed
+}
+
+/// A link to `path` under PUBLIC_URL, else relative to the server. The request's `Host`
+/// and `X-Forwarded-Proto` are not trusted: a client could point share links elsewhere.
+pub fn share_url(settings: &Settings, path: &str) -> String {
+    match &settings.public_url {
+        Some(base) => format!("{}{}", base.trim_end_matches('/'), path),
+        None => path.to_string(),
+    }
+}
+
+fn default_main(files: &HashMap<String, String>) -> Option<String> {
+    if files.contains_key("main.tex") {
+        return Some("main.tex".to_string());
+    }
+    let mut tex = files.keys().filter(|name| name.ends_with(".tex"));
+    match (tex.next(), tex.next()) {
+        (Some(only), None) => Some(only.clone()),
+        _ => None,
+    }
+}
+
+/// A new random id for a shared object.
+pub(crate) fn short_id() -> String {
+    let mut bytes = [0u8; ID_BYTES];
+    SystemRandom::new().fill(&mut bytes).expect("system randomness");
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+/// Whether `id` could have come from `short_id`, so it is safe in a storage key.
+pub(crate) fn is_short_id(id: &str) -> bool {
+    id.len() == 11 && id.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+
+    fn request(source: Option<&str>, files: Option<&[(&str, &str)]>) -> PlaygroundRequest {
+        PlaygroundRequest {
+            source: source.map(str::to_string),
+            files: files.map(|files| files.iter().map(|(name, content)| (name.to_string(), content.to_string())).collect()),
+            main: None,
+            ttl_secs: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_create_and_expire() {
+        let storage: Arc<dyn Storage> = Arc::new(MemoryStorage::new());
+        let store = PlaygroundStore::new(storage.clone());
+        let settings = Settings::from_env();
+
+        let snippet = store.create(request(Some("\\documentclass{article}"), None), &settings).await.unwrap();
+        assert!(is_short_id(&snippet.id));
+        assert_eq!(snippet.main, "main.tex");
+        assert_eq!(snippet.expires_at - snippet.created_at, settings.playground_ttl_secs);
+        assert_eq!(store.get(&snippet.id).await.unwrap().files, snippet.files);
+        assert!(store.get("../outputs/x").await.is_none());
+
+        let short = store.create(PlaygroundRequest { ttl_secs: Some(1), ..request(None, Some(&[("paper.tex", "x"), ("refs.bib", "")])) }, &settings).await.unwrap();
+        assert_eq!(short.main, "paper.tex");
+        assert_eq!(short.expires_at - short.created_at, MIN_TTL_SECS);
+
+        // An expired snippet is swept and no longer served
+        let mut stale = store.get(&short.id).await.unwrap();
+        stale.expires_at = unix_now() - 1;
+        storage.put(&format!("{}{}", PREFIX, short.id), serde_json::to_vec(&stale).unwrap()).await.unwrap();
+        assert_eq!(store.sweep().await, 1);
+        assert!(store.get(&short.id).await.is_none());
+        assert!(store.get(&snippet.id).await.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_share_quota() {
+        let store = PlaygroundStore::new(Arc::new(MemoryStorage::new()));
+        let settings = Settings { shares_per_hour: 2, ..Settings::from_env() };
+        for _ in 0..2 {
+            store.create(request(Some("x"), None), &settings).await.unwrap();
+        }
+        assert_eq!(store.create(request(Some("x"), None), &settings).await.unwrap_err().0, StatusCode::TOO_MANY_REQUESTS);
+
+        let quota = ShareQuota::default();
+        assert!(quota.take("203.0.113.7", 1).is_ok());
+        assert!(quota.take("203.0.113.7", 1).is_err());
+        assert!(quota.take("tenant:acme", 1).is_ok());
+        assert!(quota.take("203.0.113.7", 0).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_rejects_bad_snippets() {
+        let store = PlaygroundStore::new(Arc::new(MemoryStorage::new()));
+        let settings = Settings { playground_max_kb: 1, ..Settings::from_env() };
+        let status = |result: Result<PlaygroundSnippet, (StatusCode, String)>| result.unwrap_err().0;
+
+        assert_eq!(status(store.create(request(None, None), &settings).await), StatusCode::BAD_REQUEST);
+        assert_eq!(status(store.create(request(None, Some(&[("../x.tex", "")])), &settings).await), StatusCode::BAD_REQUEST);
+        assert_eq!(status(store.create(request(None, Some(&[("a.tex", ""), ("b.tex", "")])), &settings).await), StatusCode::BAD_REQUEST);
+        assert_eq!(status(store.create(request(Some(&"x".repeat(2048)), None), &settings).await), StatusCode::PAYLOAD_TOO_LARGE);
+
+        let settings = Settings { public_url: None, ..settings };
+        assert_eq!(share_url(&settings, "/p/abc"), "/p/abc");
+        let settings = Settings { public_url: Some("https://play.example.com/".into()), ..settings };
+        assert_eq!(share_url(&settings, "/p/abc"), "https://play.example.com/p/abc");
+    }
+}
@@ -0,0 +1,296 @@
+//! `print_profile` conversion of compiled PDFs for professional printing. RGB fill and
+//! stroke colours in page and form content are rewritten as CMYK, the chosen ICC profile
+//! is attached as the document's output intent so the printer's RIP renders against it,
+//! and RGB images and shadings, which cannot be converted without resampling, are flagged.
+//! Colours are converted with the device formula; the profile decides how the printer
+//! reproduces them.
+
+use lopdf::content::{Content, Operation};
+use lopdf::{dictionary, Dictionary, Document, Object, ObjectId, Stream};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use crate::models::CompileWarning;
+
+/// A CMYK output profile from PRINT_PROFILES_DIR.
+pub struct PrintProfile {
+    pub name: String,
+    icc: Vec<u8>,
+}
+
+impl PrintProfile {
+    /// Loads `<name>.icc` (or `.icm`) from `dir`, refusing anything that is not a CMYK
+    /// output profile.
+    pub async fn load(dir: &Path, name: &str) -> Result<Self, String> {
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.')) || name.starts_with('.') {
+            return Err(format!("Invalid print profile name '{}'", name));
+        }
+        for extension in ["icc", "icm"] {
+            if let Ok(icc) = tokio::fs::read(dir.join(format!("{}.{}", name, extension))).await {
+                validate(&icc).map_err(|e| format!("Print profile '{}' {}", name, e))?;
+                return Ok(Self { name: name.to_string(), icc });
+            }
+        }
+        let available = available(dir).await;
+        Err(format!("Unknown print profile '{}'; available: {}", name, if available.is_empty() { "none".to_string() } else { available.join(", ") }))
+    }
+}
+
+/// Names of the profiles in `dir`.
+pub async fn available(dir: &Path) -> Vec<String> {
+    let mut names = Vec::new();
+    if let Ok(mut entries) = tokio::fs::read_dir(dir).await {
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            let path = entry.path();
+            if matches!(path.extension().and_then(|e| e.to_str()), Some("icc" | "icm")) {
+                if let Some(stem) = path.file_stem().and_then(|s| s.to_str()) {
+                    names.push(stem.to_string());
+                }
+            }
+        }
+    }
+    names.sort();
+    names
+}
+
+/// Checks the ICC header: the `acsp` signature, an output (`prtr`) device class and a CMYK
+/// colour space.
+fn validate(icc: &[u8]) -> Result<(), String> {
+    if icc.len() < 128 || &icc[36..40] != b"acsp" {
+        return Err("is not an ICC profile".to_string());
+    }
+    if &icc[12..16] != b"prtr" || &icc[16..20] != b"CMYK" {
+        return Err("is not a CMYK output profile".to_string());
+    }
+    Ok(())
+}
+
+/// Converts `pdf` for printing with `profile`. Returns the new PDF and `print` warnings for
+/// what stays RGB.
+pub fn to_cmyk(pdf: &[u8], profile: &PrintProfile) -> Result<(Vec<u8>, Vec<CompileWarning>), String> {
+    let mut doc = Document::load_mem(pdf).map_err(|e| format!("Failed to parse PDF: {}", e))?;
+    let mut warnings = rgb_image_warnings(&doc);
+
+    let pages = doc.get_pages();
+    let mut streams: Vec<ObjectId> = pages.values().flat_map(|&page| doc.get_page_contents(page)).collect();
+    streams.extend(doc.objects.iter().filter(|(_, o)| o.as_stream().is_ok_and(|s| is_subtype(&s.dict, b"Form"))).map(|(&id, _)| id));
+    streams.sort();
+    streams.dedup();
+    for id in streams {
+        let Some(Object::Stream(stream)) = doc.objects.get_mut(&id) else { continue };
+        let Ok(data) = stream.get_plain_content() else { continue };
+        let Ok(mut content) = Content::decode(&data) else { continue };
+        if convert_operations(&mut content.operations) {
+            let encoded = content.encode().map_err(|e| e.to_string())?;
+            stream.set_plain_content(encoded);
+            let _ = stream.compress();
+        }
+    }
+
+    let shadings = doc.objects.values()
+        .filter_map(|o| o.as_dict().or_else(|_| o.as_stream().map(|s| &s.dict)).ok())
+        .filter(|d| d.has(b"ShadingType") && d.get(b"ColorSpace").is_ok_and(|cs| is_rgb(&doc, cs)))
+        .count();
+    if shadings > 0 {
+        warnings.push(warning(format!("{} shading(s) (gradients) are RGB and were left unconverted", shadings)));
+    }
+
+    let mut icc = Stream::new(dictionary! { "N" => 4 }, profile.icc.clone());
+    let _ = icc.compress();
+    let icc = doc.add_object(icc);
+    let intent = dictionary! {
+        "Type" => "OutputIntent",
+        "S" => "GTS_PDFX",
+        "OutputConditionIdentifier" => Object::string_literal(profile.name.as_str()),
+        "Info" => Object::string_literal(profile.name.as_str()),
+        "DestOutputProfile" => icc,
+    };
+    let catalog = doc.catalog_mut().map_err(|e| format!("PDF has no catalog: {}", e))?;
+    catalog.set("OutputIntents", vec![Object::Dictionary(intent)]);
+
+    let mut out = Vec::new();
+    doc.save_to(&mut out).map_err(|e| format!("Failed to write PDF: {}", e))?;
+    Ok((out, warnings))
+}
+
+/// Rewrites `rg`/`RG` and DeviceRGB `sc`/`scn` colours as CMYK. Returns whether anything
+/// changed.
+fn convert_operations(operations: &mut [Operation]) -> bool {
+    let mut changed = false;
+    // Whether the current fill and stroke colour spaces are DeviceRGB
+    let (mut fill_rgb, mut stroke_rgb) = (false, false);
+    for operation in operations.iter_mut() {
+        let operator = operation.operator.as_str();
+        match operator {
+            "cs" | "CS" => {
+                let rgb = operation.operands.first().and_then(|o| o.as_name().ok()) == Some(b"DeviceRGB".as_slice());
+                if rgb {
+                    operation.operands = vec![Object::Name(b"DeviceCMYK".to_vec())];
+                    changed = true;
+                }
+                if operator == "cs" { fill_rgb = rgb } else { stroke_rgb = rgb }
+            }
+            "rg" | "RG" | "sc" | "scn" | "SC" | "SCN" => {
+                let device = matches!(operator, "rg" | "RG") || if operator.starts_with('s') { fill_rgb } else { stroke_rgb };
+                let Some(cmyk) = device.then(|| rgb_operands(&operation.operands)).flatten() else { continue };
+                operation.operands = cmyk.into_iter().map(Object::Real).collect();
+                operation.operator = match operator {
+                    "rg" => "k".to_string(),
+                    "RG" => "K".to_string(),
+                    other => other.to_string(),
+                };
+                changed = true;
+            }
+            _ => {}
+        }
+    }
+    changed
+}
+
+fn rgb_operands(operands: &[Object]) -> Option<[f32; 4]> {
+    let [r, g, b] = operands else { return None };
+    Some(rgb_to_cmyk(r.as_float().ok()?, g.as_float().ok()?, b.as_float().ok()?))
+}
+
+fn rgb_to_cmyk(r: f32, g: f32, b: f32) -> [f32; 4] {
+    let k = 1.0 - r.max(g).max(b);
+    if k >= 1.0 {
+        return [0.0, 0.0, 0.0, 1.0];
+    }
+    let round = |v: f32| (v.clamp(0.0, 1.0) * 10000.0).round() / 10000.0;
+    [round((1.0 - r - k) / (1.0 - k)), round((1.0 - g - k) / (1.0 - k)), round((1.0 - b - k) / (1.0 - k)), round(k)]
+}
+
+/// One warning per RGB image, on the first page that draws it.
+fn rgb_image_warnings(doc: &Document) -> Vec<CompileWarning> {
+    let mut images: BTreeMap<ObjectId, (u32, Vec<u8>)> = BTreeMap::new();
+    for (number, page) in doc.get_pages() {
+        let Ok((inline, resource_ids)) = doc.get_page_resources(page) else { continue };
+        let resources = inline.into_iter().chain(resource_ids.into_iter().filter_map(|id| doc.get_dictionary(id).ok()));
+        for resources in resources {
+            let Ok(xobjects) = doc.get_dict_in_dict(resources, b"XObject") else { continue };
+            for (name, object) in xobjects.iter() {
+                if let Object::Reference(id) = object {
+                    images.entry(*id).or_insert((number, name.clone()));
+                }
+            }
+        }
+    }
+    images.into_iter().filter_map(|(id, (page, name))| {
+        let image = doc.get_object(id).ok()?.as_stream().ok()?;
+        if !is_subtype(&image.dict, b"Image") || !is_rgb(doc, image.dict.get(b"ColorSpace").ok()?) {
+            return None;
+        }
+        let size = |key: &[u8]| image.dict.get(key).and_then(Object::as_i64).unwrap_or(0);
+        Some(warning(format!(
+            "Page {}: image /{} ({}x{}) is RGB; convert it to CMYK for accurate print colours",
+            page, String::from_utf8_lossy(&name), size(b"Width"), size(b"Height"),
+        )))
+    }).collect()
+}
+
+fn is_subtype(dict: &Dictionary, subtype: &[u8]) -> bool {
+    dict.get(b"Subtype").and_then(Object::as_name).ok() == Some(subtype)
+}
+
+/// Whether a colour space is DeviceRGB, CalRGB, a 3-component ICC profile or indexed
+/// over one of those.
+fn is_rgb(doc: &Document, color_space: &Object) -> bool {
+    let Ok((_, color_space)) = doc.dereference(color_space) else { return false };
+    match color_space {
+        Object::Name(name) => name == b"DeviceRGB",
+        Object::Array(parts) => match parts.first().and_then(|p| p.as_name().ok()) {
+            Some(b"CalRGB") => true,
+            Some(b"ICCBased") => parts.get(1)
+                .and_then(|profile| doc.dereference(profile).ok())
+                .and_then(|(_, profile)| profile.as_stream().ok())
+                .is_some_and(|profile| profile.dict.get(b"N").and_then(Object::as_i64).ok() == Some(3)),
+            Some(b"Indexed") => parts.get(1).is_some_and(|base| is_rgb(doc, base)),
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+fn warning(message: String) -> CompileWarning {
+    CompileWarning { kind: "print".to_string(), file: None, line: None, measurement: None, message }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn profile() -> PrintProfile {
+        let mut icc = vec![0u8; 128];
+        icc[12..16].copy_from_slice(b"prtr");
+        icc[16..20].copy_from_slice(b"CMYK");
+        icc[36..40].copy_from_slice(b"acsp");
+        PrintProfile { name: "FOGRA39".to_string(), icc }
+    }
+
+    fn pdf() -> Vec<u8> {
+        let mut doc = Document::with_version("1.5");
+        let pages_id = doc.new_object_id();
+        let photo = doc.add_object(Stream::new(dictionary! {
+            "Subtype" => "Image", "ColorSpace" => "DeviceRGB", "Width" => 640, "Height" => 480, "BitsPerComponent" => 8,
+        }, vec![0; 16]));
+        let logo = doc.add_object(Stream::new(dictionary! {
+            "Subtype" => "Image", "ColorSpace" => "DeviceCMYK", "Width" => 10, "Height" => 10, "BitsPerComponent" => 8,
+        }, vec![0; 16]));
+        let content = doc.add_object(Stream::new(dictionary! {}, b"1 0 0 rg 0 0 1 RG 0 g /DeviceRGB cs 0 1 0 sc q /Im1 Do /Im2 Do Q".to_vec()));
+        let page = doc.add_object(dictionary! {
+            "Type" => "Page",
+            "Parent" => pages_id,
+            "Contents" => content,
+            "Resources" => dictionary! { "XObject" => dictionary! { "Im1" => photo, "Im2" => logo } },
+        });
+        doc.objects.insert(pages_id, Object::Dictionary(dictionary! { "Type" => "Pages", "Kids" => vec![page.into()], "Count" => 1 }));
+        let catalog = doc.add_object(dictionary! { "Type" => "Catalog", "Pages" => pages_id });
+        doc.trailer.set("Root", catalog);
+        let mut out = Vec::new();
+        doc.save_to(&mut out).unwrap();
+        out
+    }
+
+    #[test]
+    fn test_to_cmyk() {
+        let (converted, warnings) = to_cmyk(&pdf(), &profile()).unwrap();
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].message, "Page 1: image /Im1 (640x480) is RGB; convert it to CMYK for accurate print colours");
+
+        let doc = Document::load_mem(&converted).unwrap();
+        let page = *doc.get_pages().values().next().unwrap();
+        let operations = doc.get_and_decode_page_content(page).unwrap().operations;
+        let ops: Vec<String> = operations.iter()
+            .map(|o| format!("{} {}", o.operands.iter().map(|x| x.as_float().map(|f| f.to_string()).unwrap_or_else(|_| format!("{:?}", x))).collect::<Vec<_>>().join(" "), o.operator))
+            .collect();
+        assert_eq!(&ops[..4], ["0 1 1 0 k", "1 1 0 0 K", "0 g", "/DeviceCMYK cs"]);
+        assert_eq!(ops[4], "1 0 1 0 sc");
+
+        let intents = doc.catalog().unwrap().get(b"OutputIntents").unwrap().as_array().unwrap();
+        let intent = intents[0].as_dict().unwrap();
+        assert_eq!(intent.get(b"S").unwrap().as_name().unwrap(), b"GTS_PDFX");
+        assert!(intent.get(b"DestOutputProfile").unwrap().as_reference().is_ok());
+    }
+
+    #[test]
+    fn test_rgb_to_cmyk() {
+        assert_eq!(rgb_to_cmyk(0.0, 0.0, 0.0), [0.0, 0.0, 0.0, 1.0]);
+        assert_eq!(rgb_to_cmyk(1.0, 1.0, 1.0), [0.0, 0.0, 0.0, 0.0]);
+        assert_eq!(rgb_to_cmyk(0.5, 0.25, 0.5), [0.0, 0.5, 0.0, 0.5]);
+    }
+
+    #[tokio::test]
+    async fn test_load_profile() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut rgb = profile().icc;
+        rgb[16..20].copy_from_slice(b"RGB ");
+        std::fs::write(dir.path().join("FOGRA39.icc"), profile().icc).unwrap();
+        std::fs::write(dir.path().join("sRGB.icm"), rgb).unwrap();
+
+        assert_eq!(PrintProfile::load(dir.path(), "FOGRA39").await.unwrap().name, "FOGRA39");
+        assert_eq!(PrintProfile::load(dir.path(), "sRGB").await.err().unwrap(), "Print profile 'sRGB' is not a CMYK output profile");
+        assert_eq!(PrintProfile::load(dir.path(), "GRACoL").await.err().unwrap(), "Unknown print profile 'GRACoL'; available: FOGRA39, sRGB");
+        assert!(PrintProfile::load(dir.path(), "../etc/passwd").await.is_err());
+    }
+}
@@ -0,0 +1,168 @@
+//! Signed compile receipts: Ed25519 signatures binding a PDF to the sources it was built from.
+
+use base64::{engine::general_purpose, Engine as _};
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier};
+use rand::rngs::OsRng;
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+use tracing::{info, warn};
+
+use crate::models::{CompileReceipt, ReceiptFile, SignedReceipt};
+use crate::storage::Storage;
+
+/// Engine recorded in receipts.
+pub const ENGINE_VERSION: &str = "tectonic 0.15";
+
+pub const RECEIPT_ALGORITHM: &str = "ed25519";
+
+/// Storage key of the generated seed used when RECEIPT_SIGNING_KEY is unset.
+const SEED_KEY: &str = "receipts/signing-key";
+
+#[derive(Clone)]
+pub struct ReceiptSigner {
+    key: SigningKey,
+    key_id: String,
+}
+
+impl ReceiptSigner {
+    /// The key from RECEIPT_SIGNING_KEY; without it, the seed kept in storage, generated on
+    /// first start, so receipts stay verifiable across restarts.
+    pub async fn load(seed_hex: Option<&str>, storage: &dyn Storage) -> Result<Self, String> {
+        if seed_hex.is_some() {
+            return Self::new(seed_hex);
+        }
+        match storage.get(SEED_KEY).await {
+            Ok(Some(stored)) => {
+                let stored = std::str::from_utf8(&stored).map_err(|_| format!("Stored receipt key {} is not hex", SEED_KEY))?;
+                return Self::new(Some(stored));
+            }
+            Ok(None) => {}
+            Err(e) => warn!("Failed to read the stored receipt key: {}", e),
+        }
+        let mut seed = [0u8; 32];
+        OsRng.fill_bytes(&mut seed);
+        match storage.put(SEED_KEY, hex::encode(seed).into_bytes()).await {
+            Ok(()) => info!("🔏 Generated a receipt signing key and stored it as {}", SEED_KEY),
+            Err(e) => warn!("Failed to store the receipt signing key, receipts are signed with an ephemeral key: {}", e),
+        }
+        Self::new(Some(&hex::encode(seed)))
+    }
+
+    /// Loads the key from a hex-encoded 32-byte seed, or generates a throwaway one when unset.
+    pub fn new(seed_hex: Option<&str>) -> Result<Self, String> {
+        let seed: [u8; 32] = match seed_hex {
+            Some(hex_seed) => hex::decode(hex_seed.trim())
+                .map_err(|e| format!("RECEIPT_SIGNING_KEY is not valid hex: {}", e))?
+                .try_into()
+                .map_err(|_| "RECEIPT_SIGNING_KEY must be 32 bytes (64 hex characters)".to_string())?,
+            None => {
+                let mut seed = [0u8; 32];
+                OsRng.fill_bytes(&mut seed);
+                seed
+            }
+        };
+        let key = SigningKey::from_bytes(&seed);
+        let key_id = sha256_hex(key.verifying_key().as_bytes())[..16].to_string();
+        Ok(Self { key, key_id })
+    }
+
+    pub fn key_id(&self) -> &str {
+        &self.key_id
+    }
+
+    pub fn public_key_base64(&self) -> String {
+        general_purpose::STANDARD.encode(self.key.verifying_key().as_bytes())
+    }
+
+    /// Builds and signs a receipt; `files` are (name, SHA-256 hex) pairs in any order.
+    pub fn issue(&self, files: &[(String, String)], pdf: &[u8], output_id: &str, bundle_version: &str, compile_time_ms: u64, timestamp: u64) -> SignedReceipt {
+        let mut files: Vec<ReceiptFile> = files.iter()
+            .map(|(name, sha256)| ReceiptFile { name: name.clone(), sha256: sha256.clone() })
+            .collect();
+        files.sort_by(|a, b| a.name.cmp(&b.name));
+        let manifest: String = files.iter().map(|f| format!("{}\0{}\n", f.name, f.sha256)).collect();
+
+        let receipt = CompileReceipt {
+            input_hash: format!("sha256:{}", sha256_hex(manifest.as_bytes())),
+            files,
+            output_hash: format!("sha256:{}", sha256_hex(pdf)),
+            output_id: output_id.to_string(),
+            bundle_version: bundle_version.to_string(),
+            engine_version: ENGINE_VERSION.to_string(),
+            server_version: env!("CARGO_PKG_VERSION").to_string(),
+            timestamp,
+            compile_time_ms,
+        };
+        let signature = self.key.sign(&Self::canonical(&receipt));
+        SignedReceipt {
+            receipt,
+            algorithm: RECEIPT_ALGORITHM.to_string(),
+            key_id: self.key_id.clone(),
+            signature: general_purpose::STANDARD.encode(signature.to_bytes()),
+        }
+    }
+
+    /// Checks that the receipt was signed by this server's key and has not been altered.
+    pub fn verify(&self, signed: &SignedReceipt) -> bool {
+        let Ok(bytes) = general_purpose::STANDARD.decode(&signed.signature) else { return false };
+        let Ok(signature) = Signature::from_slice(&bytes) else { return false };
+        signed.algorithm == RECEIPT_ALGORITHM && self.key.verifying_key().verify(&Self::canonical(&signed.receipt), &signature).is_ok()
+    }
+
+    /// The signed bytes: the receipt as compact JSON in declaration order.
+    fn canonical(receipt: &CompileReceipt) -> Vec<u8> {
+        serde_json::to_vec(receipt).expect("receipt serializes")
+    }
+}
+
+pub fn sha256_hex(data: &[u8]) -> String {
+    hex::encode(Sha256::digest(data))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SEED: &str = "9d61b19deffd5a60ba844af492ec2cc44449c5697b326919703bac031cae7f60";
+
+    #[test]
+    fn test_receipt_round_trip() {
+        let signer = ReceiptSigner::new(Some(SEED)).unwrap();
+        let files = vec![("main.tex".to_string(), sha256_hex(b"\\relax"))];
+        let signed = signer.issue(&files, b"%PDF-1.5", "abc", "default", 120, 1700000000);
+
+        assert!(signed.receipt.output_hash.starts_with("sha256:"));
+        assert_eq!(signed.key_id, signer.key_id());
+        assert!(signer.verify(&signed));
+
+        let mut tampered = signed.clone();
+        tampered.receipt.output_hash = format!("sha256:{}", sha256_hex(b"other"));
+        assert!(!signer.verify(&tampered));
+
+        let other = ReceiptSigner::new(None).unwrap();
+        assert!(!other.verify(&signed));
+    }
+
+    #[tokio::test]
+    async fn test_generated_key_is_kept() {
+        let storage = crate::storage::MemoryStorage::new();
+        let first = ReceiptSigner::load(None, &storage).await.unwrap();
+        assert_eq!(storage.get(SEED_KEY).await.unwrap().unwrap().len(), 64);
+        let second = ReceiptSigner::load(None, &storage).await.unwrap();
+        assert_eq!(first.key_id(), second.key_id());
+
+        let configured = ReceiptSigner::load(Some(SEED), &storage).await.unwrap();
+        assert_eq!(configured.key_id(), ReceiptSigner::new(Some(SEED)).unwrap().key_id());
+    }
+
+    #[test]
+    fn test_input_hash_ignores_upload_order() {
+        let signer = ReceiptSigner::new(Some(SEED)).unwrap();
+        let a = ("a.tex".to_string(), sha256_hex(b"a"));
+        let b = ("b.bib".to_string(), sha256_hex(b"b"));
+        let first = signer.issue(&[a.clone(), b.clone()], b"", "", "", 0, 0);
+        let second = signer.issue(&[b, a], b"", "", "", 0, 0);
+        assert_eq!(first.receipt.input_hash, second.receipt.input_hash);
+        assert!(ReceiptSigner::new(Some("abcd")).is_err());
+    }
+}
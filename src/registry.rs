@@ -0,0 +1,261 @@
+//! Per-tenant package registry (`/registry/packages`): house classes and styles uploaded
+//! once and put on the search path of every compile of the tenant, after the project's
+//! own files. The index of each tenant is kept in memory and in storage; the files
+//! themselves live in the blob store. The registry is searched before the bundle, so files
+//! the bundle has (`article.cls`, `hyperref.sty`) cannot be uploaded.
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Arc, OnceLock};
+use tokio::sync::{Mutex, RwLock};
+use tracing::{info, warn};
+use xxhash_rust::xxh64::{xxh64, Xxh64};
+
+use crate::compiler::{Compiler, SUPPORT_FILE_EXTENSIONS};
+use crate::filetypes::FilePolicy;
+use crate::models::RegistryPackage;
+use crate::services::{AppState, CompilationCache};
+use crate::settings::Settings;
+use crate::storage::Storage;
+use crate::tenancy::current_tenant;
+use crate::util::unix_now;
+
+const PREFIX: &str = "registry/";
+
+/// Workspace directory the tenant's packages are written to. Its leading dot keeps it out
+/// of the project's own search paths.
+pub const REGISTRY_DIR: &str = ".tachyon-registry";
+
+#[derive(Serialize, Deserialize)]
+struct StoredRegistry {
+    tenant: Option<String>,
+    packages: Vec<RegistryPackage>,
+}
+
+#[derive(Clone)]
+pub struct PackageRegistry {
+    storage: Arc<dyn Storage>,
+    cache: CompilationCache,
+    tenants: Arc<RwLock<HashMap<Option<String>, Vec<RegistryPackage>>>>,
+    /// Held by uploads and deletes while they write, so `tenants` is only locked to swap
+    /// an index and compiles never wait for storage
+    writes: Arc<Mutex<()>>,
+}
+
+impl PackageRegistry {
+    pub fn new(storage: Arc<dyn Storage>, cache: CompilationCache) -> Self {
+        Self { storage, cache, tenants: Arc::new(RwLock::new(HashMap::new())), writes: Arc::new(Mutex::new(())) }
+    }
+
+    /// Reads every tenant's index from storage; returns how many tenants have packages.
+    pub async fn load(&self) -> usize {
+        let keys = match self.storage.list(PREFIX).await {
+            Ok(keys) => keys,
+            Err(e) => {
+                warn!("Failed to list package registries: {}", e);
+                return 0;
+            }
+        };
+        let mut tenants = self.tenants.write().await;
+        for key in keys {
+            let Some(stored) = self.storage.get(&key).await.ok().flatten().and_then(|data| serde_json::from_slice::<StoredRegistry>(&data).ok()) else {
+                continue;
+            };
+            self.cache.set_registry_seed(stored.tenant.clone(), seed(&stored.packages));
+            tenants.insert(stored.tenant, stored.packages);
+        }
+        tenants.len()
+    }
+
+    /// The tenant's packages, by name.
+    pub async fn list(&self, tenant: Option<&str>) -> Vec<RegistryPackage> {
+        self.tenants.read().await.get(&tenant.map(str::to_string)).cloned().unwrap_or_default()
+    }
+
+    /// Adds `files` to the tenant's registry, replacing packages of the same name. Nothing
+    /// is stored unless every file is accepted. Returns the whole registry.
+    pub async fn upload(&self, state: &AppState, tenant: Option<&str>, files: Vec<(String, Vec<u8>)>) -> Result<Vec<RegistryPackage>, String> {
+        if files.is_empty() {
+            return Err("No package files uploaded".to_string());
+        }
+        let policy = FilePolicy::new(&state.settings.allowed_file_types);
+        for (name, data) in &files {
+            check_name(name)?;
+            if let Some(rejection) = policy.check(name, &data[..data.len().min(4096)]) {
+                return Err(format!("{}: {}", rejection.file, rejection.reason));
+            }
+        }
+        let names: Vec<String> = files.iter().map(|(name, _)| name.clone()).collect();
+        let (config, bundle_url) = (state.config.clone(), state.bundles.active().url);
+        let shadowed = tokio::task::spawn_blocking(move || Compiler::bundle_files(&config, bundle_url.as_deref(), &names))
+            .await
+            .map_err(|e| format!("Bundle check failed: {}", e))??;
+        if !shadowed.is_empty() {
+            return Err(format!("{} already in the TeX bundle and cannot be replaced", shadowed.join(", ")));
+        }
+
+        let tenant = tenant.map(str::to_string);
+        let _writing = self.writes.lock().await;
+        let mut packages = self.list(tenant.as_deref()).await;
+        let mut blobs = Vec::with_capacity(files.len());
+        for (name, data) in files {
+            let hash = format!("{:x}", xxh64(&data, 0));
+            packages.retain(|package| package.name != name);
+            packages.push(RegistryPackage { provides: provides(&data), name, hash: hash.clone(), size: data.len() as u64, uploaded_at: unix_now() });
+            blobs.push((hash, data));
+        }
+        packages.sort_by(|a, b| a.name.cmp(&b.name));
+        check_limits(&packages, &state.settings)?;
+
+        for (hash, data) in blobs {
+            state.blob_store.put(hash, data).await;
+        }
+        self.save(&tenant, &packages).await?;
+        self.cache.set_registry_seed(tenant.clone(), seed(&packages));
+        self.tenants.write().await.insert(tenant, packages.clone());
+        Ok(packages)
+    }
+
+    /// Whether the tenant's registry had a package with this name.
+    pub async fn delete(&self, tenant: Option<&str>, name: &str) -> Result<bool, String> {
+        let tenant = tenant.map(str::to_string);
+        let _writing = self.writes.lock().await;
+        let mut packages = self.list(tenant.as_deref()).await;
+        let before = packages.len();
+        packages.retain(|package| package.name != name);
+        if packages.len() == before {
+            return Ok(false);
+        }
+        if packages.is_empty() {
+            self.storage.delete(&key(tenant.as_deref())).await?;
+            self.tenants.write().await.remove(&tenant);
+        } else {
+            self.save(&tenant, &packages).await?;
+            self.tenants.write().await.insert(tenant.clone(), packages.clone());
+        }
+        self.cache.set_registry_seed(tenant, seed(&packages));
+        Ok(true)
+    }
+
+    /// Writes the current tenant's packages into `workspace` before a compile. Workspaces
+    /// that already have them (a remote worker's copy) are left alone.
+    pub async fn install(&self, state: &AppState, workspace: &Path) {
+        let dir = workspace.join(REGISTRY_DIR);
+        if dir.exists() {
+            return;
+        }
+        let packages = self.list(current_tenant().as_deref()).await;
+        if packages.is_empty() {
+            return;
+        }
+        if let Err(e) = tokio::fs::create_dir(&dir).await {
+            warn!("Failed to create the package registry directory: {}", e);
+            return;
+        }
+        for package in &packages {
+            match state.blob_store.get(&package.hash).await {
+                Some(data) => {
+                    if let Err(e) = tokio::fs::write(dir.join(&package.name), &data).await {
+                        warn!("Failed to install registry package {}: {}", package.name, e);
+                    }
+                }
+                None => warn!("Registry package {} ({}) is missing from the blob store", package.name, package.hash),
+            }
+        }
+        info!("📚 Installed {} registry packages", packages.len());
+    }
+
+    async fn save(&self, tenant: &Option<String>, packages: &[RegistryPackage]) -> Result<(), String> {
+        let stored = StoredRegistry { tenant: tenant.clone(), packages: packages.to_vec() };
+        let data = serde_json::to_vec(&stored).map_err(|e| e.to_string())?;
+        self.storage.put(&key(tenant.as_deref()), data).await
+    }
+}
+
+fn key(tenant: Option<&str>) -> String {
+    match tenant {
+        Some(tenant) => format!("{}{}", PREFIX, hex::encode(tenant)),
+        None => format!("{}_default", PREFIX),
+    }
+}
+
+/// Only plain file names of the kinds TeX looks up by name are accepted.
+fn check_name(name: &str) -> Result<(), String> {
+    let extension = name.rsplit_once('.').map(|(_, extension)| extension).unwrap_or("");
+    if name.starts_with('.') || !name.chars().all(|c| c.is_ascii_alphanumeric() || "._-".contains(c)) {
+        return Err(format!("Invalid package file name '{}'", name));
+    }
+    if !SUPPORT_FILE_EXTENSIONS.contains(&extension) {
+        return Err(format!("{} is not a package file; accepted extensions are {}", name, SUPPORT_FILE_EXTENSIONS.join(", ")));
+    }
+    Ok(())
+}
+
+fn check_limits(packages: &[RegistryPackage], settings: &Settings) -> Result<(), String> {
+    if packages.len() > settings.registry_max_packages {
+        return Err(format!("A package registry holds at most {} files", settings.registry_max_packages));
+    }
+    let total: u64 = packages.iter().map(|package| package.size).sum();
+    if total > settings.registry_max_mb * 1024 * 1024 {
+        return Err(format!("A package registry holds at most {} MB", settings.registry_max_mb));
+    }
+    Ok(())
+}
+
+/// The optional argument of the file's `\ProvidesPackage`, `\ProvidesClass` or `\ProvidesFile`.
+fn provides(data: &[u8]) -> Option<String> {
+    static PROVIDES: OnceLock<Regex> = OnceLock::new();
+    let re = PROVIDES.get_or_init(|| Regex::new(r"\\Provides(?:Package|Class|File)\s*\{[^}]*\}\s*\[([^\]]*)\]").unwrap());
+    let text = String::from_utf8_lossy(data);
+    re.captures(&text).map(|caps| caps[1].split_whitespace().collect::<Vec<_>>().join(" "))
+}
+
+/// Cache seed of a registry: its file names and contents (`None` when empty).
+fn seed(packages: &[RegistryPackage]) -> Option<u64> {
+    if packages.is_empty() {
+        return None;
+    }
+    let mut hasher = Xxh64::new(0);
+    for package in packages {
+        hasher.update(package.name.as_bytes());
+        hasher.update(&[0]);
+        hasher.update(package.hash.as_bytes());
+        hasher.update(&[0]);
+    }
+    Some(hasher.digest())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn package(name: &str, hash: &str) -> RegistryPackage {
+        RegistryPackage { name: name.into(), hash: hash.into(), size: 10, provides: None, uploaded_at: 0 }
+    }
+
+    #[test]
+    fn test_check_name() {
+        assert!(check_name("acme-letter.cls").is_ok());
+        assert!(check_name("acme.sty").is_ok());
+        for name in ["acme.tex", "styles/acme.sty", ".acme.sty", "../acme.sty", "acme"] {
+            assert!(check_name(name).is_err(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_provides() {
+        let sty = b"\\NeedsTeXFormat{LaTeX2e}\n\\ProvidesPackage{acme}[2026/05/01 v2.1\n  Acme house style]\n";
+        assert_eq!(provides(sty).as_deref(), Some("2026/05/01 v2.1 Acme house style"));
+        assert_eq!(provides(b"\\ProvidesClass{acme}"), None);
+    }
+
+    #[test]
+    fn test_seed() {
+        assert_eq!(seed(&[]), None);
+        let v1 = seed(&[package("a.sty", "1"), package("b.cls", "2")]);
+        assert_ne!(v1, seed(&[package("a.sty", "1"), package("b.cls", "3")]), "a new version changes the seed");
+        assert_ne!(v1, seed(&[package("a.sty", "1"), package("c.cls", "2")]));
+    }
+}
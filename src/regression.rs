@@ -0,0 +1,360 @@
+//! Template regression tests (`/tests`): a project registered with a reference PDF is rebuilt
+//! every REGRESSION_INTERVAL_SECS, and whenever the active TeX bundle changes. A rebuild whose
+//! pages or text drift from the reference beyond the test's thresholds fires a
+//! `regression.failed` webhook, so a bundle update cannot silently break a production template.
+
+use axum::http::StatusCode;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{info, warn};
+
+use crate::fingerprint::{extract_text, Fingerprint};
+use crate::models::{BundleRelease, BundleTrial, BundleTrialResult, RegressionRun, RegressionTest, WebhookPayload};
+use crate::pages::{changed_pages, page_hashes};
+use crate::services::{AppState, Priority};
+use crate::storage::Storage;
+use crate::tenancy::TENANT;
+use crate::webhooks::Webhooks;
+use crate::util::unix_now;
+
+const PREFIX: &str = "regression/";
+
+/// Share of pages a rebuild may change unless the test says otherwise: none.
+pub const DEFAULT_MAX_CHANGED_PAGES: f64 = 0.0;
+
+/// Text similarity a rebuild must keep unless the test says otherwise.
+pub const DEFAULT_MIN_TEXT_SIMILARITY: f64 = 0.95;
+
+/// Longest a rebuild may compile for.
+const RUN_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Bytes of the reference's text kept for comparisons.
+const MAX_TEXT_BYTES: usize = 1024 * 1024;
+
+/// A test as stored: with its tenant and the reference's page hashes and text, which the
+/// API does not show.
+#[derive(Serialize, Deserialize)]
+struct StoredTest {
+    test: RegressionTest,
+    tenant: Option<String>,
+    reference_hashes: Vec<String>,
+    reference_text: String,
+}
+
+/// What two PDFs are compared by.
+struct Snapshot {
+    hashes: Vec<String>,
+    text: String,
+}
+
+impl Snapshot {
+    fn of_pdf(pdf: &[u8]) -> Result<Self, String> {
+        let hashes = page_hashes(pdf)?;
+        // Undecodable text only weakens the text comparison; the pages are still compared
+        let mut text = extract_text(pdf).unwrap_or_default();
+        let mut end = text.len().min(MAX_TEXT_BYTES);
+        while !text.is_char_boundary(end) {
+            end -= 1;
+        }
+        text.truncate(end);
+        Ok(Self { hashes, text })
+    }
+}
+
+#[derive(Clone)]
+pub struct RegressionStore {
+    storage: Arc<dyn Storage>,
+}
+
+impl RegressionStore {
+    pub fn new(storage: Arc<dyn Storage>) -> Self {
+        Self { storage }
+    }
+
+    /// Stores a new test with `reference` as the PDF its rebuilds should match.
+    pub async fn create(&self, tenant: Option<String>, mut test: RegressionTest, reference: Vec<u8>) -> Result<RegressionTest, (StatusCode, String)> {
+        let snapshot = match tokio::task::spawn_blocking(move || Snapshot::of_pdf(&reference)).await {
+            Ok(Ok(snapshot)) => snapshot,
+            Ok(Err(e)) => return Err((StatusCode::BAD_REQUEST, format!("The reference is not a readable PDF: {}", e))),
+            Err(e) => return Err((StatusCode::INTERNAL_SERVER_ERROR, format!("Reading the reference failed: {}", e))),
+        };
+        test.reference_pages = snapshot.hashes.len();
+        let stored = StoredTest { test, tenant, reference_hashes: snapshot.hashes, reference_text: snapshot.text };
+        self.save(&stored).await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))?;
+        Ok(stored.test)
+    }
+
+    pub async fn get(&self, id: &str, tenant: Option<&str>) -> Option<RegressionTest> {
+        self.load(id, tenant).await.map(|stored| stored.test)
+    }
+
+    /// The tenant's tests, oldest first.
+    pub async fn list(&self, tenant: Option<&str>) -> Vec<RegressionTest> {
+        let mut tests: Vec<RegressionTest> = self.all().await.into_iter()
+            .filter(|stored| stored.tenant.as_deref() == tenant)
+            .map(|stored| stored.test)
+            .collect();
+        tests.sort_by_key(|test| test.created_at);
+        tests
+    }
+
+    /// Whether the tenant had a test with this id.
+    pub async fn delete(&self, id: &str, tenant: Option<&str>) -> bool {
+        self.load(id, tenant).await.is_some() && self.storage.delete(&key(id)).await.is_ok()
+    }
+
+    async fn load(&self, id: &str, tenant: Option<&str>) -> Option<StoredTest> {
+        if id.is_empty() || !id.chars().all(|c| c.is_ascii_alphanumeric()) {
+            return None;
+        }
+        let stored: StoredTest = serde_json::from_slice(&self.storage.get(&key(id)).await.ok()??).ok()?;
+        (stored.tenant.as_deref() == tenant).then_some(stored)
+    }
+
+    async fn save(&self, stored: &StoredTest) -> Result<(), String> {
+        let data = serde_json::to_vec(stored).map_err(|e| e.to_string())?;
+        self.storage.put(&key(&stored.test.id), data).await
+    }
+
+    async fn all(&self) -> Vec<StoredTest> {
+        let keys = match self.storage.list(PREFIX).await {
+            Ok(keys) => keys,
+            Err(e) => {
+                warn!("Failed to list regression tests: {}", e);
+                return Vec::new();
+            }
+        };
+        let mut tests = Vec::with_capacity(keys.len());
+        for key in keys {
+            if let Some(stored) = self.storage.get(&key).await.ok().flatten().and_then(|data| serde_json::from_slice(&data).ok()) {
+                tests.push(stored);
+            }
+        }
+        tests
+    }
+}
+
+fn key(id: &str) -> String {
+    format!("{}{}", PREFIX, id)
+}
+
+/// Rebuilds a test and compares the PDF with its reference, recording the run on the test
+/// and firing `regression.failed` when it does not pass. `None` if the tenant has no such test.
+pub async fn run(state: &AppState, id: &str, tenant: Option<&str>) -> Option<RegressionRun> {
+    let mut stored = state.regression.load(id, tenant).await?;
+    let run = rebuild(state, &stored, None).await;
+    let test = &stored.test;
+    if run.passed {
+        info!("🧪 Regression test {} passed ({} page(s) changed)", test.id, run.changed_pages.len());
+    } else {
+        info!("🚨 Regression test {} failed: {}", test.id, run.error.as_deref().unwrap_or_default());
+        Webhooks::fire(state, WebhookPayload {
+            event: "regression.failed".to_string(),
+            timestamp: run.ran_at,
+            project_id: Some(test.project_id.clone()),
+            tenant: stored.tenant.clone(),
+            main_file: Some(test.main.clone()),
+            success: false,
+            compile_time_ms: run.compile_time_ms,
+            error: run.error.clone(),
+            output_hash: run.output_hash.clone(),
+            test_id: Some(test.id.clone()),
+            schedule_id: None,
+        });
+    }
+    stored.test.last_run = Some(run.clone());
+    if let Err(e) = state.regression.save(&stored).await {
+        warn!("Failed to record the run of regression test {}: {}", id, e);
+    }
+    Some(run)
+}
+
+/// Rebuilds every test with a staged bundle, without recording the runs or firing webhooks.
+pub async fn trial(state: &AppState, bundle: &BundleRelease) -> BundleTrial {
+    let mut results = Vec::new();
+    for stored in state.regression.all().await {
+        let run = rebuild(state, &stored, Some(bundle)).await;
+        results.push(BundleTrialResult { test_id: stored.test.id, project_id: stored.test.project_id, tenant: stored.tenant, run });
+    }
+    let passed = results.iter().filter(|r| r.run.passed).count();
+    info!("🧪 Bundle {} passed {} of {} regression test(s)", bundle.version, passed, results.len());
+    BundleTrial { ran_at: unix_now(), tests: results.len(), passed, failed: results.len() - passed, results }
+}
+
+/// Compiles with `bundle` when given, otherwise with the active bundle.
+async fn rebuild(state: &AppState, stored: &StoredTest, bundle: Option<&BundleRelease>) -> RegressionRun {
+    let test = &stored.test;
+    let run = RegressionRun {
+        ran_at: unix_now(),
+        bundle_version: bundle.map_or_else(|| state.bundles.active().version, |b| b.version.clone()),
+        passed: false,
+        output_hash: None,
+        pages: None,
+        changed_pages: Vec::new(),
+        changed_fraction: 0.0,
+        text_similarity: 0.0,
+        compile_time_ms: 0,
+        error: None,
+    };
+    let mut files = Vec::with_capacity(test.files.len());
+    for file in &test.files {
+        match state.blob_store.get(&file.hash).await {
+            Some(data) => files.push((file.name.clone(), data.to_vec())),
+            None => return RegressionRun { error: Some(format!("Blob {} of {} not found", file.hash, file.name)), ..run },
+        }
+    }
+    // The PDF cache is bypassed: it is not keyed by bundle, and bundle changes are what tests catch
+    let (pdf, compile_time_ms) = match crate::render::compile_uncached(state, &test.main, &files, Priority::Batch, RUN_TIMEOUT, bundle).await {
+        Ok((Ok(pdf), _, compile_time_ms)) => (pdf, compile_time_ms),
+        Ok((Err(e), _, compile_time_ms)) => return RegressionRun { compile_time_ms, error: Some(format!("Compilation failed: {}", e)), ..run },
+        Err(e) => return RegressionRun { error: Some(e), ..run },
+    };
+    let output_hash = state.output_store.put(&pdf).await;
+    let run = RegressionRun { output_hash: Some(output_hash), compile_time_ms, ..run };
+    let snapshot = match tokio::task::spawn_blocking(move || Snapshot::of_pdf(&pdf)).await {
+        Ok(Ok(snapshot)) => snapshot,
+        Ok(Err(e)) => return RegressionRun { error: Some(e), ..run },
+        Err(e) => return RegressionRun { error: Some(format!("Reading the rebuild failed: {}", e)), ..run },
+    };
+
+    let (changed_pages, changed_fraction, text_similarity) = compare(&stored.reference_hashes, &stored.reference_text, &snapshot);
+    let error = verdict(test, changed_pages.len(), snapshot.hashes.len().max(stored.reference_hashes.len()), changed_fraction, text_similarity);
+    RegressionRun {
+        passed: error.is_none(),
+        pages: Some(snapshot.hashes.len()),
+        changed_pages,
+        changed_fraction,
+        text_similarity,
+        error,
+        ..run
+    }
+}
+
+/// The pages that differ from the reference (dropped ones included), the share of pages
+/// they are and the text similarity.
+fn compare(reference_hashes: &[String], reference_text: &str, current: &Snapshot) -> (Vec<usize>, f64, f64) {
+    let mut changed = changed_pages(reference_hashes, &current.hashes);
+    changed.extend(current.hashes.len() + 1..=reference_hashes.len());
+    let pages = reference_hashes.len().max(current.hashes.len()).max(1);
+    let fraction = changed.len() as f64 / pages as f64;
+    (changed, fraction, text_similarity(reference_text, &current.text))
+}
+
+/// Estimated share of text two documents have in common. Texts too short to fingerprint
+/// must match word for word.
+fn text_similarity(reference: &str, current: &str) -> f64 {
+    match (Fingerprint::of_text(reference), Fingerprint::of_text(current)) {
+        (Some(reference), Some(current)) => reference.similarity(&current),
+        _ if reference.split_whitespace().eq(current.split_whitespace()) => 1.0,
+        _ => 0.0,
+    }
+}
+
+/// Why a rebuild with this diff fails the test, `None` when it passes.
+fn verdict(test: &RegressionTest, changed: usize, pages: usize, changed_fraction: f64, text_similarity: f64) -> Option<String> {
+    let mut reasons = Vec::new();
+    if changed_fraction > test.max_changed_pages {
+        reasons.push(format!(
+            "{} of {} page(s) changed ({:.0}%, {:.0}% allowed)",
+            changed, pages, changed_fraction * 100.0, test.max_changed_pages * 100.0
+        ));
+    }
+    if text_similarity < test.min_text_similarity {
+        reasons.push(format!("text similarity {:.2} is below {:.2}", text_similarity, test.min_text_similarity));
+    }
+    (!reasons.is_empty()).then(|| reasons.join("; "))
+}
+
+/// Rebuilds the tests never run or last run with another bundle than the active one.
+pub async fn run_stale(state: AppState) {
+    let bundle_version = state.bundles.active().version;
+    run_all(&state, |test| test.last_run.as_ref().is_none_or(|run| run.bundle_version != bundle_version)).await;
+}
+
+/// Rebuilds every test each REGRESSION_INTERVAL_SECS.
+pub async fn schedule_task(state: AppState) {
+    let interval = state.settings.regression_interval_secs;
+    if interval == 0 {
+        return;
+    }
+    loop {
+        tokio::time::sleep(Duration::from_secs(interval)).await;
+        run_all(&state, |_| true).await;
+    }
+}
+
+async fn run_all(state: &AppState, due: impl Fn(&RegressionTest) -> bool) {
+    let (mut ran, mut failed) = (0, 0);
+    for stored in state.regression.all().await.into_iter().filter(|stored| due(&stored.test)) {
+        // Storage writes are attributed to, and encrypted for, the test's tenant as in its requests
+        let tenant = stored.tenant.clone();
+        let outcome = TENANT.scope(tenant.clone(), run(state, &stored.test.id, tenant.as_deref())).await;
+        ran += 1;
+        if outcome.is_some_and(|run| !run.passed) {
+            failed += 1;
+        }
+    }
+    if ran > 0 {
+        info!("🧪 Ran {} regression test(s), {} failed", ran, failed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+
+    fn test(max_changed_pages: f64, min_text_similarity: f64) -> RegressionTest {
+        RegressionTest {
+            id: "t1".into(),
+            project_id: "p1".into(),
+            main: "main.tex".into(),
+            files: Vec::new(),
+            reference_pages: 0,
+            max_changed_pages,
+            min_text_similarity,
+            created_at: 0,
+            last_run: None,
+        }
+    }
+
+    #[test]
+    fn test_compare() {
+        let hashes = |list: &[&str]| list.iter().map(|h| h.to_string()).collect::<Vec<_>>();
+        let text = "The quick brown fox jumps over the lazy dog while the cat watches from the warm windowsill and the bird sings";
+        let current = Snapshot { hashes: hashes(&["a", "x"]), text: text.to_string() };
+        let (changed, fraction, similarity) = compare(&hashes(&["a", "b", "c", "d"]), text, &current);
+        assert_eq!(changed, vec![2, 3, 4], "changed and dropped pages");
+        assert_eq!(fraction, 0.75);
+        assert_eq!(similarity, 1.0);
+
+        assert_eq!(text_similarity("Short  title", "Short title"), 1.0);
+        assert_eq!(text_similarity("Short title", "Other title"), 0.0);
+    }
+
+    #[test]
+    fn test_verdict() {
+        assert_eq!(verdict(&test(0.0, 0.95), 0, 3, 0.0, 0.99), None);
+        assert_eq!(verdict(&test(0.5, 0.95), 1, 3, 1.0 / 3.0, 1.0), None);
+        assert_eq!(verdict(&test(0.0, 0.95), 1, 4, 0.25, 1.0).unwrap(), "1 of 4 page(s) changed (25%, 0% allowed)");
+        assert_eq!(verdict(&test(1.0, 0.95), 4, 4, 1.0, 0.5).unwrap(), "text similarity 0.50 is below 0.95");
+    }
+
+    #[tokio::test]
+    async fn test_store_is_tenant_scoped() {
+        let store = RegressionStore::new(Arc::new(MemoryStorage::new()));
+        let stored = StoredTest { test: test(0.0, 0.95), tenant: Some("acme".into()), reference_hashes: Vec::new(), reference_text: String::new() };
+        store.save(&stored).await.unwrap();
+        assert!(store.get("t1", Some("acme")).await.is_some());
+        assert!(store.get("t1", None).await.is_none());
+        assert!(store.get("../t1", Some("acme")).await.is_none());
+        assert_eq!(store.list(Some("acme")).await.len(), 1);
+        assert!(!store.delete("t1", Some("other")).await);
+        assert!(store.delete("t1", Some("acme")).await);
+        assert!(store.list(Some("acme")).await.is_empty());
+
+        let rejected = store.create(None, test(0.0, 0.95), b"not a pdf".to_vec()).await.unwrap_err();
+        assert_eq!(rejected.0, StatusCode::BAD_REQUEST);
+    }
+}
@@ -0,0 +1,619 @@
+use axum::{
+    http::{header, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+};
+use bytes::Bytes;
+use regex::Regex;
+use std::fs;
+use std::io::Write;
+use std::path::{Component, Path};
+use std::time::{Duration, Instant};
+use tracing::{error, info};
+
+use crate::bib::strip_comment;
+use crate::compiler::Compiler;
+use crate::healer::SourceMap;
+use crate::latex::escape;
+use crate::models::{ChartRequest, TableRequest};
+use crate::services::*;
+
+/// A file placed next to a generated `main.tex`: (name, contents).
+pub type AuxFile = (String, Vec<u8>);
+
+/// Compiles a generated `main.tex` through the scheduler and the PDF cache, like a
+/// regular compile. `files` are written next to it (images, QR codes, ...) and are
+/// part of the cache key.
+pub async fn compile_source(state: &AppState, source: &str, files: &[AuxFile], priority: Priority) -> (Result<Bytes, String>, String) {
+    let mut project = Vec::with_capacity(files.len() + 1);
+    project.push(("main.tex".to_string(), source.as_bytes().to_vec()));
+    project.extend_from_slice(files);
+    compile_files(state, "main.tex", &project, priority).await
+}
+
+/// Compiles a project whose files (paths may include folders) are all in `files`,
+/// through the scheduler and the PDF cache.
+pub async fn compile_files(state: &AppState, main: &str, files: &[AuxFile], priority: Priority) -> (Result<Bytes, String>, String) {
+    let mut input_hasher = state.compilation_cache.input_hasher();
+    for (name, data) in files {
+        input_hasher.add_file(name, data);
+    }
+    let input_hash = input_hasher.finish(main);
+    if let Some((pdf_data, _, _, _)) = state.compilation_cache.get_pdf(input_hash).await {
+        return (Ok(pdf_data), String::new());
+    }
+
+    let temp_dir = match state.janitor.workspace() {
+        Ok(d) => d,
+        Err(e) => return (Err(format!("Failed to create temp dir: {}", e)), String::new()),
+    };
+    for (name, data) in files {
+        let path = temp_dir.path().join(name);
+        if let Err(e) = path.parent().map_or(Ok(()), fs::create_dir_all).and_then(|_| fs::write(&path, data)) {
+            return (Err(format!("Failed to write {}: {}", name, e)), String::new());
+        }
+    }
+    let main_path = temp_dir.path().join(main);
+
+    let permit = state.scheduler.acquire(priority).await;
+    let start = Instant::now();
+    let (result, logs) = crate::workers::compile(state, temp_dir.path(), &main_path, priority, permit).await;
+    if let Ok(pdf_data) = &result {
+        state.compilation_cache.put_pdf(input_hash, pdf_data, start.elapsed().as_millis() as u64, &CompileNotes::from_log(&logs)).await;
+    }
+    (result.map(Bytes::from), logs)
+}
+
+/// Compiles in a fresh workspace without the PDF cache, giving up after `timeout`. A given
+/// `bundle` is compiled with locally; otherwise the active bundle is used, on a worker when
+/// configured. Returns the result, the log and the compile time in milliseconds.
+pub async fn compile_uncached(
+    state: &AppState,
+    main: &str,
+    files: &[AuxFile],
+    priority: Priority,
+    timeout: Duration,
+    bundle: Option<&crate::models::BundleRelease>,
+) -> Result<(Result<Vec<u8>, String>, String, u64), String> {
+    let workspace = state.janitor.workspace()?;
+    for (name, data) in files {
+        let path = workspace.path().join(name);
+        path.parent().map_or(Ok(()), fs::create_dir_all).and_then(|_| fs::write(&path, data)).map_err(|e| format!("Failed to write {}: {}", name, e))?;
+    }
+    let main_path = workspace.path().join(main);
+    state.registry.install(state, workspace.path()).await;
+    let permit = state.scheduler.acquire(priority).await;
+    let start = Instant::now();
+    // Dropping the compile future on timeout cancels it
+    let compile = async {
+        match bundle {
+            Some(bundle) => crate::workers::compile_with_bundle(state, workspace.path(), &main_path, bundle.url.clone(), &Default::default(), permit).await,
+            None => crate::workers::compile(state, workspace.path(), &main_path, priority, permit).await,
+        }
+    };
+    match tokio::time::timeout(timeout, compile).await {
+        Ok((result, logs)) => Ok((result, logs, start.elapsed().as_millis() as u64)),
+        Err(_) => Err(format!("Compilation timed out after {}s", timeout.as_secs())),
+    }
+}
+
+/// Converts a compiled PDF to the requested format ("pdf", "svg" or "png"; the
+/// latter two from the first page), returning the bytes and their content type.
+pub fn convert_output(state: &AppState, pdf_data: Bytes, format: &str) -> Result<(Bytes, &'static str), (StatusCode, String)> {
+    match format {
+        "pdf" => Ok((pdf_data, "application/pdf")),
+        "svg" | "png" => state.janitor.workspace()
+            .and_then(|dir| Compiler::convert_pdf(&pdf_data, format, dir.path(), &state.sandbox))
+            .map(|bytes| (Bytes::from(bytes), if format == "svg" { "image/svg+xml" } else { "image/png" }))
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Conversion to {} failed: {}", format, e))),
+        other => Err((StatusCode::BAD_REQUEST, format!("Unknown format '{}' (expected pdf, svg, png or tex)", other))),
+    }
+}
+
+/// Builds the response for a rendered document in the requested format.
+pub fn output_response(state: &AppState, pdf_data: Bytes, format: &str) -> Response {
+    let (bytes, content_type) = match convert_output(state, pdf_data, format) {
+        Ok(output) => output,
+        Err(e) => return e.into_response(),
+    };
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, content_type)
+        .body(axum::body::Body::from(bytes))
+        .unwrap()
+}
+
+/// Compiles a generated document and answers in the requested format with an
+/// `X-Compile-Time-Ms` header; `what` names the document in logs and errors.
+pub async fn render_response(state: &AppState, source: &str, files: &[AuxFile], format: &str, what: &str) -> Response {
+    let start = Instant::now();
+    let (result, logs) = compile_source(state, source, files, Priority::Interactive).await;
+    let pdf_data = match result {
+        Ok(pdf) => pdf,
+        Err(e) => {
+            error!("❌ Rendering {} failed: {}", what, e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to compile {}: {}\n\nLogs:\n{}", what, e, logs)).into_response();
+        }
+    };
+
+    let mut response = output_response(state, pdf_data, format);
+    let compile_time_ms = start.elapsed().as_millis();
+    info!("🖨️ Rendered {} as {} in {}ms", what, format, compile_time_ms);
+    if let Ok(value) = HeaderValue::from_str(&compile_time_ms.to_string()) {
+        response.headers_mut().insert("X-Compile-Time-Ms", value);
+    }
+    response
+}
+
+/// Packs named files into an in-memory zip archive.
+pub fn zip_files<T: AsRef<[u8]>>(files: &[(String, T)]) -> Result<Vec<u8>, String> {
+    let mut zip = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+    for (name, data) in files {
+        zip.start_file(name.as_str(), zip::write::FileOptions::default()).map_err(|e| e.to_string())?;
+        zip.write_all(data.as_ref()).map_err(|e| e.to_string())?;
+    }
+    Ok(zip.finish().map_err(|e| e.to_string())?.into_inner())
+}
+
+/// Validates a TeX length such as "10cm" so it can be inserted verbatim.
+pub fn tex_length(value: Option<&str>, default: &str) -> Result<String, String> {
+    let value = value.unwrap_or(default).trim();
+    let re = Regex::new(r"^\d+(\.\d+)?(cm|mm|pt|in|em|ex|bp)$").unwrap();
+    if re.is_match(value) {
+        Ok(value.to_string())
+    } else {
+        Err(format!("Invalid length '{}' (expected e.g. 10cm)", value))
+    }
+}
+
+// ============================================================================
+// Charts (pgfplots)
+// ============================================================================
+
+const LEGEND_POSITIONS: &[&str] = &["north west", "north east", "south west", "south east", "outer north east"];
+
+pub struct ChartRenderer;
+
+impl ChartRenderer {
+    /// Generates a standalone pgfplots document for the chart spec.
+    pub fn to_latex(spec: &ChartRequest) -> Result<String, String> {
+        Ok(format!(
+            "\\documentclass[tikz,border=4pt]{{standalone}}\n\\usepackage{{pgfplots}}\n\\pgfplotsset{{compat=1.17}}\n\\begin{{document}}\n{}\\end{{document}}\n",
+            Self::to_picture(spec)?
+        ))
+    }
+
+    /// The chart's `tikzpicture`, for documents that load pgfplots themselves.
+    pub fn to_picture(spec: &ChartRequest) -> Result<String, String> {
+        if spec.series.is_empty() {
+            return Err("Chart needs at least one series".to_string());
+        }
+
+        let chart_type = spec.chart_type.as_deref().unwrap_or("line");
+        if !matches!(chart_type, "line" | "bar" | "scatter" | "area") {
+            return Err(format!("Unknown chart type '{}' (expected line, bar, scatter or area)", chart_type));
+        }
+        let legend_pos = spec.legend_position.as_deref().unwrap_or("north west");
+        if !LEGEND_POSITIONS.contains(&legend_pos) {
+            return Err(format!("Unknown legend position '{}'", legend_pos));
+        }
+
+        // String x values switch the axis to symbolic (categorical) coordinates
+        let symbolic = spec.series.iter().flat_map(|s| &s.x).any(|x| x.is_string());
+        let mut categories: Vec<String> = Vec::new();
+
+        let mut plots = String::new();
+        for (idx, series) in spec.series.iter().enumerate() {
+            if !series.x.is_empty() && series.x.len() != series.y.len() {
+                return Err(format!("Series {} has {} x values but {} y values", idx + 1, series.x.len(), series.y.len()));
+            }
+            if series.y.iter().any(|y| !y.is_finite()) {
+                return Err(format!("Series {} contains non-finite values", idx + 1));
+            }
+
+            let mut coords = Vec::with_capacity(series.y.len());
+            for (i, y) in series.y.iter().enumerate() {
+                let x = match series.x.get(i) {
+                    None => (i + 1).to_string(),
+                    Some(serde_json::Value::Number(n)) if !symbolic => n.to_string(),
+                    Some(serde_json::Value::String(s)) if symbolic => escape(s).replace(',', " "),
+                    Some(serde_json::Value::Number(n)) => n.to_string(),
+                    Some(other) => return Err(format!("Unsupported x value {} in series {}", other, idx + 1)),
+                };
+                if symbolic && !categories.contains(&x) {
+                    categories.push(x.clone());
+                }
+                coords.push(format!("({},{})", x, y));
+            }
+
+            let (options, suffix) = match chart_type {
+                "scatter" => ("only marks", ""),
+                "area" => ("fill, fill opacity=0.3", " \\closedcycle"),
+                "bar" => ("", ""),
+                _ => ("mark=*", ""),
+            };
+            plots.push_str(&format!("\\addplot+[{}] coordinates {{{}}}{};\n", options, coords.join(" "), suffix));
+            if let Some(name) = &series.name {
+                plots.push_str(&format!("\\addlegendentry{{{}}}\n", escape(name)));
+            }
+        }
+
+        let mut axis = vec![
+            format!("width={}", tex_length(spec.width.as_deref(), "10cm")?),
+            format!("height={}", tex_length(spec.height.as_deref(), "6cm")?),
+            format!("legend pos={}", legend_pos),
+            "grid=major".to_string(),
+        ];
+        if let Some(title) = &spec.title { axis.push(format!("title={{{}}}", escape(title))); }
+        if let Some(label) = &spec.x_label { axis.push(format!("xlabel={{{}}}", escape(label))); }
+        if let Some(label) = &spec.y_label { axis.push(format!("ylabel={{{}}}", escape(label))); }
+        if chart_type == "bar" { axis.push("ybar".to_string()); }
+        if symbolic {
+            axis.push(format!("symbolic x coords={{{}}}", categories.join(",")));
+            axis.push("xtick=data".to_string());
+        }
+
+        Ok(format!(
+            "\\begin{{tikzpicture}}\n\\begin{{axis}}[\n  {}\n]\n{}\\end{{axis}}\n\\end{{tikzpicture}}\n",
+            axis.join(",\n  "),
+            plots
+        ))
+    }
+}
+
+// ============================================================================
+// Tables (booktabs)
+// ============================================================================
+
+/// Tables with more rows than this are typeset as a page-breaking longtable.
+pub const DEFAULT_LONG_TABLE_ROWS: usize = 40;
+
+/// Tabular data with a header row; every cell is plain (unescaped) text.
+#[derive(Debug, PartialEq)]
+pub struct Table {
+    pub header: Vec<String>,
+    pub rows: Vec<Vec<String>>,
+}
+
+impl Table {
+    /// Parses RFC 4180 style CSV; the first record is the header.
+    pub fn from_csv(text: &str) -> Result<Self, String> {
+        let mut records: Vec<Vec<String>> = Vec::new();
+        let mut record = Vec::new();
+        let mut field = String::new();
+        let mut in_quotes = false;
+        let mut chars = text.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '"' if in_quotes && chars.peek() == Some(&'"') => {
+                    field.push('"');
+                    chars.next();
+                }
+                '"' if in_quotes => in_quotes = false,
+                '"' if field.is_empty() => in_quotes = true,
+                ',' if !in_quotes => record.push(std::mem::take(&mut field)),
+                '\r' if !in_quotes => {}
+                '\n' if !in_quotes => {
+                    record.push(std::mem::take(&mut field));
+                    records.push(std::mem::take(&mut record));
+                }
+                _ => field.push(c),
+            }
+        }
+        if in_quotes {
+            return Err("Unterminated quoted CSV field".to_string());
+        }
+        if !field.is_empty() || !record.is_empty() {
+            record.push(field);
+            records.push(record);
+        }
+        records.retain(|r| !(r.len() == 1 && r[0].trim().is_empty()));
+
+        let mut records = records.into_iter();
+        let header = records.next().ok_or("CSV data is empty")?;
+        Self::new(header, records.collect())
+    }
+
+    /// Accepts `{"columns": [...], "rows": [[...]]}`, an array of arrays (first one is
+    /// the header) or an array of objects (columns in key order of the first object).
+    pub fn from_json(value: &serde_json::Value) -> Result<Self, String> {
+        use serde_json::Value;
+
+        match value {
+            Value::Object(obj) => {
+                let columns = obj.get("columns").and_then(Value::as_array).ok_or("JSON table needs a \"columns\" array")?;
+                let rows = obj.get("rows").and_then(Value::as_array).ok_or("JSON table needs a \"rows\" array")?;
+                let rows = rows.iter()
+                    .map(|r| r.as_array().map(|cells| cells.iter().map(cell_text).collect()).ok_or("Each row must be an array"))
+                    .collect::<Result<Vec<_>, _>>()?;
+                Self::new(columns.iter().map(cell_text).collect(), rows)
+            }
+            Value::Array(items) => match items.first() {
+                Some(Value::Object(first)) => {
+                    let header: Vec<String> = first.keys().cloned().collect();
+                    let rows = items.iter()
+                        .map(|item| item.as_object()
+                            .map(|o| header.iter().map(|k| o.get(k).map(cell_text).unwrap_or_default()).collect())
+                            .ok_or("Array items must all be objects"))
+                        .collect::<Result<Vec<_>, _>>()?;
+                    Self::new(header, rows)
+                }
+                Some(Value::Array(_)) => {
+                    let mut records = items.iter()
+                        .map(|r| r.as_array().map(|cells| cells.iter().map(cell_text).collect()).ok_or("Array items must all be arrays"))
+                        .collect::<Result<Vec<Vec<String>>, _>>()?
+                        .into_iter();
+                    let header = records.next().unwrap_or_default();
+                    Self::new(header, records.collect())
+                }
+                _ => Err("JSON table must be an array of objects or arrays".to_string()),
+            },
+            _ => Err("JSON table must be an object or an array".to_string()),
+        }
+    }
+
+    /// Reads the `csv` or `data` field of a request; exactly one must be given.
+    pub fn from_request(csv: Option<&str>, data: Option<&serde_json::Value>) -> Result<Self, String> {
+        match (csv, data) {
+            (Some(csv), None) => Self::from_csv(csv),
+            (None, Some(data)) => Self::from_json(data),
+            _ => Err("Provide exactly one of \"csv\" or \"data\"".to_string()),
+        }
+    }
+
+    /// Pads short rows with empty cells; rows wider than the header are rejected.
+    pub fn new(header: Vec<String>, mut rows: Vec<Vec<String>>) -> Result<Self, String> {
+        if header.is_empty() {
+            return Err("Table needs at least one column".to_string());
+        }
+        for (i, row) in rows.iter_mut().enumerate() {
+            if row.len() > header.len() {
+                return Err(format!("Row {} has {} cells but the header has {}", i + 1, row.len(), header.len()));
+            }
+            row.resize(header.len(), String::new());
+        }
+        Ok(Self { header, rows })
+    }
+}
+
+fn cell_text(value: &serde_json::Value) -> String {
+    match value {
+        serde_json::Value::Null => String::new(),
+        serde_json::Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+pub struct TableRenderer;
+
+impl TableRenderer {
+    /// Builds the table fragment for a `/render/table` request.
+    pub fn from_request(req: &TableRequest) -> Result<String, String> {
+        let table = Table::from_request(req.csv.as_deref(), req.data.as_ref())?;
+        Self::to_fragment(
+            &table,
+            req.align.as_deref(),
+            req.caption.as_deref(),
+            req.long_threshold.unwrap_or(DEFAULT_LONG_TABLE_ROWS),
+        )
+    }
+
+    /// Right-aligns columns whose non-empty cells are all numeric, left-aligns the rest.
+    pub fn infer_alignment(table: &Table) -> String {
+        (0..table.header.len())
+            .map(|col| {
+                let mut cells = table.rows.iter().map(|r| r[col].trim()).filter(|c| !c.is_empty()).peekable();
+                let numeric = cells.peek().is_some() && cells.all(|c| c.replace(',', "").parse::<f64>().is_ok());
+                if numeric { 'r' } else { 'l' }
+            })
+            .collect()
+    }
+
+    /// Renders a booktabs tabular, or a longtable once the rows exceed `long_threshold`.
+    pub fn to_fragment(table: &Table, align: Option<&str>, caption: Option<&str>, long_threshold: usize) -> Result<String, String> {
+        let align = match align {
+            Some(a) if a.len() != table.header.len() || !a.chars().all(|c| matches!(c, 'l' | 'c' | 'r')) => {
+                return Err(format!("Alignment '{}' must have one of l, c or r per column ({} columns)", a, table.header.len()));
+            }
+            Some(a) => a.to_string(),
+            None => Self::infer_alignment(table),
+        };
+
+        let escaped = |cells: &[String]| cells.iter().map(|c| escape(c.trim())).collect::<Vec<_>>();
+        let rows: Vec<Vec<String>> = table.rows.iter().map(|r| escaped(r)).collect();
+        let caption = caption.map(escape);
+        Ok(Self::typeset(&align, &escaped(&table.header), &rows, None, caption.as_deref(), long_threshold))
+    }
+
+    /// Lays out cells that are already LaTeX as a booktabs tabular, or a longtable once the
+    /// rows exceed `long_threshold`. A `footer` row (e.g. totals) closes the table.
+    pub fn typeset(align: &str, header: &[String], rows: &[Vec<String>], footer: Option<&[String]>, caption: Option<&str>, long_threshold: usize) -> String {
+        let row_line = |cells: &[String]| format!("{} \\\\\n", cells.join(" & "));
+        let header = format!("\\toprule\n{}\\midrule\n", row_line(header));
+        let body: String = rows.iter().map(|r| row_line(r)).collect();
+        let footer = footer.map(|f| format!("\\midrule\n{}", row_line(f))).unwrap_or_default();
+        let caption = caption.map(|c| format!("\\caption{{{}}}", c));
+
+        if rows.len() > long_threshold {
+            // Header repeats on every page; the footer and closing rule only on the last one
+            return format!(
+                "\\begin{{longtable}}{{{}}}\n{}{}\\endfirsthead\n{}\\endhead\n{}\\bottomrule\n\\endlastfoot\n{}\\end{{longtable}}\n",
+                align,
+                caption.map(|c| format!("{} \\\\\n", c)).unwrap_or_default(),
+                header,
+                header,
+                footer,
+                body
+            );
+        }
+
+        let tabular = format!("\\begin{{tabular}}{{{}}}\n{}{}{}\\bottomrule\n\\end{{tabular}}\n", align, header, body, footer);
+        match caption {
+            Some(c) => format!("\\begin{{table}}[htbp]\n\\centering\n{}\n{}\\end{{table}}\n", c, tabular),
+            None => tabular,
+        }
+    }
+
+    /// Wraps a table fragment into a complete document.
+    pub fn to_latex(fragment: &str) -> String {
+        format!(
+            "\\documentclass{{article}}\n\\usepackage{{booktabs}}\n\\usepackage{{longtable}}\n\\pagestyle{{empty}}\n\\begin{{document}}\n\\centering\n{}\\end{{document}}\n",
+            fragment
+        )
+    }
+
+    /// Expands `\tachyontable[align]{data.csv}` (or `.json`) in the main file for the
+    /// compile, loading booktabs/longtable when the document doesn't already. Macros in
+    /// `%` comments are left alone. Returns the original content, to be written back once
+    /// compiled, and the map of the lines the expansion added; `None` if nothing expanded.
+    pub fn preprocess(main_tex_path: &Path) -> Result<Option<(String, SourceMap)>, String> {
+        let content = match fs::read_to_string(main_tex_path) {
+            Ok(c) if c.contains("\\tachyontable") => c,
+            _ => return Ok(None),
+        };
+        let work_dir = main_tex_path.parent().unwrap_or(Path::new("."));
+
+        let re = Regex::new(r"\\tachyontable(?:\[([lcr]+)\])?\{([^}]+)\}").unwrap();
+        let mut tables = Vec::new();
+        for cap in re.captures_iter(&content) {
+            let m = cap.get(0).unwrap();
+            let line_start = content[..m.start()].rfind('\n').map_or(0, |i| i + 1);
+            if strip_comment(&content[line_start..m.start()]).len() < m.start() - line_start {
+                continue;
+            }
+            let file = cap[2].trim();
+            if Path::new(file).components().any(|c| !matches!(c, Component::Normal(_))) {
+                return Err(format!("\\tachyontable: invalid path '{}'", file));
+            }
+            let data = fs::read_to_string(work_dir.join(file))
+                .map_err(|e| format!("\\tachyontable: cannot read '{}': {}", file, e))?;
+            let table = if file.ends_with(".json") {
+                let value = serde_json::from_str(&data).map_err(|e| format!("\\tachyontable: invalid JSON in '{}': {}", file, e))?;
+                Table::from_json(&value)
+            } else {
+                Table::from_csv(&data)
+            }.map_err(|e| format!("\\tachyontable: {}: {}", file, e))?;
+            tables.push((m.range(), Self::to_fragment(&table, cap.get(1).map(|a| a.as_str()), None, DEFAULT_LONG_TABLE_ROWS)?));
+        }
+        if tables.is_empty() {
+            return Ok(None);
+        }
+
+        // Replaced from the last, so the ranges of the others still hold
+        let mut expanded = content.clone();
+        let mut source_map = SourceMap::default();
+        for (range, fragment) in tables.iter().rev() {
+            source_map.replace(&mut expanded, range.clone(), fragment);
+        }
+
+        let mut packages = String::new();
+        for package in ["booktabs", "longtable"] {
+            if !Regex::new(&format!(r"\\usepackage(\[[^\]]*\])?\{{[^}}]*\b{}\b", package)).unwrap().is_match(&expanded) {
+                packages.push_str(&format!("\\usepackage{{{}}}\n", package));
+            }
+        }
+        if let Some(pos) = expanded.find("\\begin{document}") {
+            source_map.insert(&mut expanded, pos, &packages);
+        }
+
+        fs::write(main_tex_path, expanded).map_err(|e| format!("Failed to write expanded {:?}: {}", main_tex_path, e))?;
+        Ok(Some((content, source_map)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::ChartSeries;
+    use tempfile::TempDir;
+
+    fn series(x: Vec<serde_json::Value>, y: Vec<f64>) -> ChartSeries {
+        ChartSeries { name: Some("Sales & Co".to_string()), x, y }
+    }
+
+    #[test]
+    fn test_bar_chart_uses_symbolic_coords() {
+        let spec = ChartRequest {
+            chart_type: Some("bar".to_string()),
+            title: Some("Q1_2024".to_string()),
+            x_label: None,
+            y_label: None,
+            series: vec![series(vec!["Jan".into(), "Feb".into()], vec![1.0, 2.5])],
+            width: None,
+            height: None,
+            legend_position: None,
+            format: None,
+        };
+        let tex = ChartRenderer::to_latex(&spec).unwrap();
+        assert!(tex.contains("ybar"));
+        assert!(tex.contains("symbolic x coords={Jan,Feb}"));
+        assert!(tex.contains("coordinates {(Jan,1) (Feb,2.5)}"));
+        assert!(tex.contains("title={Q1\\_2024}"));
+        assert!(tex.contains("\\addlegendentry{Sales \\& Co}"));
+    }
+
+    #[test]
+    fn test_rejects_bad_input() {
+        let mut spec = ChartRequest {
+            chart_type: None,
+            title: None,
+            x_label: None,
+            y_label: None,
+            series: vec![series(vec![1.into()], vec![1.0, 2.0])],
+            width: None,
+            height: None,
+            legend_position: None,
+            format: None,
+        };
+        assert!(ChartRenderer::to_latex(&spec).is_err());
+        spec.series = vec![series(vec![], vec![1.0])];
+        spec.width = Some("10cm}\\input{/etc/passwd".to_string());
+        assert!(ChartRenderer::to_latex(&spec).is_err());
+    }
+
+    #[test]
+    fn test_csv_parsing_and_alignment() {
+        let table = Table::from_csv("Item,Price\r\n\"Widget, large\",\"1,200.50\"\n\"Say \"\"hi\"\"\",3\n\n").unwrap();
+        assert_eq!(table.header, vec!["Item", "Price"]);
+        assert_eq!(table.rows, vec![vec!["Widget, large", "1,200.50"], vec!["Say \"hi\"", "3"]]);
+        assert_eq!(TableRenderer::infer_alignment(&table), "lr");
+
+        let tex = TableRenderer::to_fragment(&table, None, None, DEFAULT_LONG_TABLE_ROWS).unwrap();
+        assert!(tex.starts_with("\\begin{tabular}{lr}\n\\toprule\nItem & Price \\\\\n\\midrule\n"));
+        assert!(TableRenderer::to_fragment(&table, Some("lcr"), None, DEFAULT_LONG_TABLE_ROWS).is_err());
+    }
+
+    #[test]
+    fn test_long_tables_split_pages() {
+        let value = serde_json::json!([{"n": 1}, {"n": 2}, {"n": 3}]);
+        let table = Table::from_json(&value).unwrap();
+        let tex = TableRenderer::to_fragment(&table, None, Some("Totals"), 2).unwrap();
+        assert!(tex.starts_with("\\begin{longtable}{r}\n\\caption{Totals} \\\\\n"));
+        assert!(tex.contains("\\endhead"));
+    }
+
+    #[test]
+    fn test_preprocess_expands_macro() {
+        let dir = TempDir::new().unwrap();
+        fs::write(dir.path().join("data.csv"), "a,b\nx,1\n").unwrap();
+        let main = dir.path().join("main.tex");
+        fs::write(&main, "\\documentclass{article}\n\\begin{document}\n\\tachyontable[cc]{data.csv}\n\\end{document}\n").unwrap();
+        let (original, source_map) = TableRenderer::preprocess(&main).unwrap().unwrap();
+        assert_eq!(original, "\\documentclass{article}\n\\begin{document}\n\\tachyontable[cc]{data.csv}\n\\end{document}\n");
+        let out = fs::read_to_string(&main).unwrap();
+        assert!(out.contains("\\usepackage{booktabs}\n\\usepackage{longtable}\n\\begin{document}"));
+        assert!(out.contains("\\begin{tabular}{cc}"));
+        let end = out.lines().position(|line| line == "\\end{document}").unwrap() as u32 + 1;
+        assert_eq!(source_map.original_line(end), 4);
+
+        // Commented-out macros are neither expanded nor read
+        let commented = "\\begin{document}\n% \\tachyontable{missing.csv}\n50\\% done % \\tachyontable{missing.csv}\n\\end{document}\n";
+        fs::write(&main, commented).unwrap();
+        assert!(TableRenderer::preprocess(&main).unwrap().is_none());
+        assert_eq!(fs::read_to_string(&main).unwrap(), commented);
+
+        fs::write(&main, "\\tachyontable{../secret.csv}").unwrap();
+        assert!(TableRenderer::preprocess(&main).is_err());
+    }
+}
@@ -0,0 +1,97 @@
+//! Retention policies and erasure of a tenant's data: projects, content fingerprints, async
+//! compile jobs and every stored object (cached PDFs, blobs, outputs, playground snippets) its requests wrote. Objects another
+//! tenant also wrote are kept for that tenant.
+
+use std::collections::BTreeMap;
+use std::time::Duration;
+use crate::util::unix_now;
+use tracing::{info, warn};
+
+use crate::models::DeletionReport;
+use crate::services::AppState;
+use crate::settings::Settings;
+
+const RETENTION_INTERVAL_SECS: u64 = 3600;
+
+/// Days a tenant's data is kept, `None` for no limit.
+pub fn retention_days(settings: &Settings, tenant: &str) -> Option<u64> {
+    let days = settings
+        .tenant_retention_days
+        .iter()
+        .find(|(name, _)| name == tenant)
+        .map_or(settings.retention_days, |(_, days)| *days);
+    (days > 0).then_some(days)
+}
+
+/// Deletes the tenant's data last written before `before`, or all of it when `None`.
+pub async fn purge(state: &AppState, tenant: &str, before: Option<u64>) -> DeletionReport {
+    let projects = state.projects.remove_tenant(tenant, before).await;
+    let fingerprints = state.fingerprints.remove_tenant(tenant, before).await;
+    let jobs = state.jobs.remove_tenant(tenant, before);
+
+    let (keys, shared) = state.ledger.take(tenant, before);
+    let mut objects: BTreeMap<String, usize> = BTreeMap::new();
+    let mut failed = Vec::new();
+    for key in keys {
+        match state.storage.delete(&key).await {
+            Ok(()) if key.starts_with("outputs/") => {
+                state.output_store.forget(&key["outputs/".len()..]).await;
+                *objects.entry("outputs".to_string()).or_default() += 1;
+            }
+            Ok(()) => *objects.entry(key.split('/').next().unwrap_or_default().to_string()).or_default() += 1,
+            Err(e) => {
+                warn!("Failed to delete {} for tenant {}: {}", key, tenant, e);
+                failed.push(key);
+            }
+        }
+    }
+
+    DeletionReport {
+        tenant: tenant.to_string(),
+        projects,
+        fingerprints,
+        jobs,
+        objects,
+        shared_objects_kept: shared.len(),
+        failed,
+        deleted_at: unix_now(),
+    }
+}
+
+/// Applies RETENTION_DAYS / TENANT_RETENTION_DAYS to every known tenant, hourly.
+pub async fn retention_task(state: AppState) {
+    loop {
+        tokio::time::sleep(Duration::from_secs(RETENTION_INTERVAL_SECS)).await;
+        let now = unix_now();
+        let mut tenants = state.ledger.tenants();
+        tenants.extend(state.projects.tenants().await);
+        tenants.sort();
+        tenants.dedup();
+        for tenant in tenants {
+            let Some(days) = retention_days(&state.settings, &tenant) else { continue };
+            let report = purge(&state, &tenant, Some(now.saturating_sub(days * 86_400))).await;
+            let objects: usize = report.objects.values().sum();
+            if report.projects + report.fingerprints + objects > 0 {
+                info!("🗓️ Retention ({} days) for {}: removed {} project(s), {} fingerprint(s), {} object(s)", days, tenant, report.projects, report.fingerprints, objects);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_retention_days() {
+        let settings = Settings {
+            retention_days: 90,
+            tenant_retention_days: vec![("acme".into(), 30), ("archive".into(), 0)],
+            ..Settings::from_env()
+        };
+        assert_eq!(retention_days(&settings, "acme"), Some(30));
+        assert_eq!(retention_days(&settings, "other"), Some(90));
+        assert_eq!(retention_days(&settings, "archive"), None);
+        assert_eq!(retention_days(&Settings { retention_days: 0, ..settings }, "other"), None);
+    }
+}
@@ -0,0 +1,300 @@
+//! Rewrites applied to a main `.tex` file before compiling, so one uploaded source can be
+//! built in several variants without edits.
+
+use regex::Regex;
+use std::collections::BTreeMap;
+use std::ops::Range;
+
+use crate::bib::strip_comment;
+
+/// Beamer builds accepted by `?variants=`.
+pub const BEAMER_VARIANTS: &[&str] = &["slides", "handout", "notes"];
+
+/// Paper sizes accepted as overrides, as `geometry` names them without the `paper` suffix.
+pub const PAPER_SIZES: &[&str] = &["a4", "a5", "b5", "letter", "legal", "executive"];
+
+/// Base font sizes the standard classes support.
+pub const FONT_SIZES: &[&str] = &["10pt", "11pt", "12pt"];
+
+/// Upper bound on the number of `defines` of one request.
+pub const MAX_DEFINES: usize = 64;
+
+/// Longest name of a define.
+const MAX_DEFINE_NAME: usize = 32;
+
+/// Layout settings imposed on a document regardless of its own preamble.
+#[derive(Debug, Default, Clone)]
+pub struct Overrides {
+    /// One of [`PAPER_SIZES`], with or without the `paper` suffix
+    pub paper: Option<String>,
+    /// One of [`FONT_SIZES`]
+    pub font_size: Option<String>,
+    /// A TeX length applied to all four margins
+    pub margin: Option<String>,
+    pub twoside: Option<bool>,
+}
+
+impl Overrides {
+    pub fn is_empty(&self) -> bool {
+        self.paper.is_none() && self.font_size.is_none() && self.margin.is_none() && self.twoside.is_none()
+    }
+
+    /// Applies the overrides: font size and sidedness become class options; paper and margins
+    /// go through `geometry` at the end of the preamble, so they win over the document's
+    /// own `geometry` setup (and set the PDF page size, which class options alone do not).
+    pub fn apply(&self, source: &str) -> Result<String, String> {
+        let mut class_options = Vec::new();
+        let mut geometry = Vec::new();
+        if let Some(paper) = &self.paper {
+            let name = paper.trim().to_ascii_lowercase();
+            let name = name.strip_suffix("paper").unwrap_or(&name);
+            if !PAPER_SIZES.contains(&name) {
+                return Err(format!("Unknown paper size '{}' (expected {})", paper, PAPER_SIZES.join(", ")));
+            }
+            geometry.push(format!("{}paper", name));
+        }
+        if let Some(size) = &self.font_size {
+            let size = size.trim();
+            if !FONT_SIZES.contains(&size) {
+                return Err(format!("Unsupported font size '{}' (expected {})", size, FONT_SIZES.join(", ")));
+            }
+            class_options.push(size.to_string());
+        }
+        if let Some(margin) = &self.margin {
+            geometry.push(format!("margin={}", crate::render::tex_length(Some(margin), "")?));
+        }
+        if let Some(twoside) = self.twoside {
+            class_options.push(if twoside { "twoside" } else { "oneside" }.to_string());
+        }
+
+        let mut source = source.to_string();
+        if !class_options.is_empty() {
+            let options: Vec<&str> = class_options.iter().map(String::as_str).collect();
+            source = replace_class_options(&source, &options)?;
+        }
+        if !geometry.is_empty() {
+            let keys = geometry.join(",");
+            source = insert_before_document(&source, &format!(
+                "\\makeatletter\\@ifpackageloaded{{geometry}}{{\\geometry{{{keys}}}}}{{\\usepackage[{keys}]{{geometry}}}}\\makeatother"
+            ))?;
+        }
+        Ok(source)
+    }
+}
+
+/// The first uncommented `\documentclass[options]{class}` of a source.
+struct DocumentClass {
+    range: Range<usize>,
+    options: Vec<String>,
+    class: String,
+}
+
+fn document_class(source: &str) -> Option<DocumentClass> {
+    let re = Regex::new(r"\\documentclass\s*(?:\[([^\]]*)\])?\s*\{([^}]*)\}").unwrap();
+    let class = re.captures_iter(source).find_map(|caps| {
+        let whole = caps.get(0)?;
+        let line_start = source[..whole.start()].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = source[whole.start()..].find('\n').map_or(source.len(), |i| whole.start() + i);
+        if strip_comment(&source[line_start..line_end]).len() < whole.start() - line_start {
+            return None;
+        }
+        Some(DocumentClass {
+            range: whole.range(),
+            options: caps.get(1)
+                .map(|m| m.as_str().split(',').map(str::trim).filter(|o| !o.is_empty()).map(str::to_string).collect())
+                .unwrap_or_default(),
+            class: caps[2].trim().to_string(),
+        })
+    });
+    class
+}
+
+/// Adds `options` to the `\documentclass` options, skipping ones already given.
+pub fn add_class_options(source: &str, options: &[&str]) -> Result<String, String> {
+    let class = document_class(source).ok_or("No \\documentclass found in the main file")?;
+    let mut merged = class.options;
+    for option in options {
+        if !merged.iter().any(|o| o == option) {
+            merged.push(option.to_string());
+        }
+    }
+    Ok(format!("{}\\documentclass[{}]{{{}}}{}", &source[..class.range.start], merged.join(","), class.class, &source[class.range.end..]))
+}
+
+/// Sets `options` on `\documentclass`, dropping existing options of the same family
+/// (other font sizes for a size, `oneside` for `twoside`, `final` for `draft` and vice versa).
+fn replace_class_options(source: &str, options: &[&str]) -> Result<String, String> {
+    let conflicts = |existing: &str| options.iter().any(|o| {
+        (FONT_SIZES.contains(o) && FONT_SIZES.contains(&existing))
+            || (matches!(*o, "twoside" | "oneside") && matches!(existing, "twoside" | "oneside"))
+            || (matches!(*o, "draft" | "final") && matches!(existing, "draft" | "final"))
+    });
+    let class = document_class(source).ok_or("No \\documentclass found in the main file")?;
+    let kept: Vec<String> = class.options.into_iter().filter(|o| !conflicts(o)).collect();
+    let stripped = format!("{}\\documentclass[{}]{{{}}}{}", &source[..class.range.start], kept.join(","), class.class, &source[class.range.end..]);
+    add_class_options(&stripped, options)
+}
+
+/// Builds the document in draft mode (the class and packages such as graphicx and hyperref
+/// skip images and links) or in final mode, replacing the mode the source sets.
+pub fn draft_mode(source: &str, draft: bool) -> Result<String, String> {
+    replace_class_options(source, &[if draft { "draft" } else { "final" }])
+}
+
+/// Inserts `text` on its own line before the first uncommented `\begin{document}`.
+pub fn insert_before_document(source: &str, text: &str) -> Result<String, String> {
+    let mut offset = 0;
+    for line in source.split_inclusive('\n') {
+        if let Some(at) = strip_comment(line).find("\\begin{document}") {
+            let at = offset + at;
+            return Ok(format!("{}{}\n{}", &source[..at], text, &source[at..]));
+        }
+        offset += line.len();
+    }
+    Err("No \\begin{document} found in the main file".to_string())
+}
+
+/// Injects build flags before `\documentclass`: booleans become `\newif` toggles
+/// (`{"solutions": true}` gives `\ifsolutions` set to true), strings and numbers become
+/// macros (`{"lang": "es"}` gives `\lang`, typeset literally), so documents can branch with
+/// `\ifsolutions` or `\ifdefined\lang`. A define whose control sequences already exist
+/// (`\input`, `\def`, `\iftrue`, ...) stops the compile with an error instead of
+/// redefining them.
+pub fn inject_defines(source: &str, defines: &BTreeMap<String, serde_json::Value>) -> Result<String, String> {
+    if defines.is_empty() {
+        return Ok(source.to_string());
+    }
+    if defines.len() > MAX_DEFINES {
+        return Err(format!("At most {} defines are allowed", MAX_DEFINES));
+    }
+    let mut lines = Vec::with_capacity(defines.len());
+    for (name, value) in defines {
+        if name.is_empty() || name.len() > MAX_DEFINE_NAME || !name.chars().all(|c| c.is_ascii_alphabetic()) {
+            return Err(format!("Invalid define name '{}' (up to {} letters)", name, MAX_DEFINE_NAME));
+        }
+        lines.push(match value {
+            serde_json::Value::Bool(flag) => guard_define(
+                name,
+                &[&format!("if{name}"), &format!("{name}true"), &format!("{name}false")],
+                &format!("\\expandafter\\newif\\csname if{name}\\endcsname\\csname {name}{}\\endcsname", if *flag { "true" } else { "false" }),
+            ),
+            serde_json::Value::String(text) => guard_define(name, &[name], &format!("\\expandafter\\def\\csname {name}\\endcsname{{{}}}", crate::latex::escape(text))),
+            serde_json::Value::Number(number) => guard_define(name, &[name], &format!("\\expandafter\\def\\csname {name}\\endcsname{{{}}}", number)),
+            _ => return Err(format!("Define '{}' must be a boolean, string or number", name)),
+        });
+    }
+    let class = document_class(source).ok_or("No \\documentclass found in the main file")?;
+    Ok(format!("{}{}\n{}", &source[..class.range.start], lines.join("\n"), &source[class.range.start..]))
+}
+
+/// `definition`, run only when none of `control_sequences` exists yet. Control sequences
+/// are only named through `\csname`: a conditional such as `\iftrue` written out in a
+/// skipped branch would unbalance the `\fi`s.
+fn guard_define(name: &str, control_sequences: &[&str], definition: &str) -> String {
+    let mut guarded = String::new();
+    for control_sequence in control_sequences {
+        guarded.push_str(&format!(
+            "\\ifcsname {}\\endcsname\\errmessage{{Define '{}' would redefine an existing command}}\\else",
+            control_sequence, name
+        ));
+    }
+    guarded.push_str(definition);
+    guarded.push_str(&"\\fi".repeat(control_sequences.len()));
+    guarded
+}
+
+/// Inserts `text` on its own line right after `\documentclass`, where the class is loaded
+/// but the rest of the preamble has not run yet.
+pub fn insert_after_class(source: &str, text: &str) -> Result<String, String> {
+    let class = document_class(source).ok_or("No \\documentclass found in the main file")?;
+    Ok(format!("{}\n{}\n{}", &source[..class.range.end], text, &source[class.range.end..]))
+}
+
+/// The source of one beamer build: "slides" as written, "handout" with the `handout` class
+/// option, "notes" with the speaker notes shown.
+pub fn beamer_variant(source: &str, variant: &str) -> Result<String, String> {
+    let class = document_class(source).ok_or("No \\documentclass found in the main file")?;
+    if class.class != "beamer" {
+        return Err(format!("Variants need a beamer document, found class '{}'", class.class));
+    }
+    match variant {
+        "slides" => Ok(source.to_string()),
+        "handout" => add_class_options(source, &["handout"]),
+        "notes" => insert_after_class(source, "\\setbeameroption{show notes}"),
+        other => Err(format!("Unknown variant '{}' (expected {})", other, BEAMER_VARIANTS.join(", "))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SLIDES: &str = "% \\documentclass{article}\n\\documentclass[aspectratio=169]{beamer}\n\\begin{document}\n\\end{document}\n";
+
+    #[test]
+    fn test_add_class_options() {
+        let handout = add_class_options(SLIDES, &["handout", "aspectratio=169"]).unwrap();
+        assert!(handout.contains("\\documentclass[aspectratio=169,handout]{beamer}"));
+        assert!(handout.starts_with("% \\documentclass{article}\n"));
+        assert_eq!(add_class_options("\\documentclass{article}", &["a4paper"]).unwrap(), "\\documentclass[a4paper]{article}");
+        assert!(add_class_options("no class here", &["a4paper"]).is_err());
+    }
+
+    #[test]
+    fn test_overrides() {
+        let source = "\\documentclass[12pt,oneside]{report}\n\\usepackage[margin=1in]{geometry}\n\\begin{document}\nHi\n\\end{document}\n";
+        let overrides = Overrides { paper: Some("A4".into()), font_size: Some("10pt".into()), margin: Some("2cm".into()), twoside: Some(true) };
+        let rewritten = overrides.apply(source).unwrap();
+        assert!(rewritten.starts_with("\\documentclass[10pt,twoside]{report}\n"), "{}", rewritten);
+        assert!(rewritten.contains("\\geometry{a4paper,margin=2cm}}{\\usepackage[a4paper,margin=2cm]{geometry}}\\makeatother\n\\begin{document}"), "{}", rewritten);
+
+        assert!(Overrides { paper: Some("tabloid".into()), ..Default::default() }.apply(source).is_err());
+        assert!(Overrides { margin: Some("2 cm; \\input".into()), ..Default::default() }.apply(source).is_err());
+        assert!(Overrides::default().is_empty());
+    }
+
+    #[test]
+    fn test_draft_mode() {
+        let source = "\\documentclass[draft,11pt]{article}\n";
+        assert_eq!(draft_mode(source, false).unwrap(), "\\documentclass[11pt,final]{article}\n");
+        assert_eq!(draft_mode(source, true).unwrap(), "\\documentclass[11pt,draft]{article}\n");
+    }
+
+    #[test]
+    fn test_inject_defines() {
+        let defines: BTreeMap<String, serde_json::Value> = serde_json::from_str(r#"{"solutions": true, "watermark": "DRAFT_1", "copies": 3}"#).unwrap();
+        let rewritten = inject_defines("%!TEX program = xelatex\n\\documentclass{article}\n", &defines).unwrap();
+        assert_eq!(rewritten, concat!(
+            "%!TEX program = xelatex\n",
+            "\\ifcsname copies\\endcsname\\errmessage{Define 'copies' would redefine an existing command}\\else",
+            "\\expandafter\\def\\csname copies\\endcsname{3}\\fi\n",
+            "\\ifcsname ifsolutions\\endcsname\\errmessage{Define 'solutions' would redefine an existing command}\\else",
+            "\\ifcsname solutionstrue\\endcsname\\errmessage{Define 'solutions' would redefine an existing command}\\else",
+            "\\ifcsname solutionsfalse\\endcsname\\errmessage{Define 'solutions' would redefine an existing command}\\else",
+            "\\expandafter\\newif\\csname ifsolutions\\endcsname\\csname solutionstrue\\endcsname\\fi\\fi\\fi\n",
+            "\\ifcsname watermark\\endcsname\\errmessage{Define 'watermark' would redefine an existing command}\\else",
+            "\\expandafter\\def\\csname watermark\\endcsname{DRAFT\\_1}\\fi\n",
+            "\\documentclass{article}\n",
+        ));
+        // Primitives are never written out, only guarded by name
+        let primitive: BTreeMap<String, serde_json::Value> = serde_json::from_str(r#"{"input": "x", "true": false}"#).unwrap();
+        let rewritten = inject_defines("\\documentclass{article}", &primitive).unwrap();
+        assert!(rewritten.contains("\\ifcsname input\\endcsname\\errmessage") && rewritten.contains("\\ifcsname iftrue\\endcsname\\errmessage"));
+        assert!(!rewritten.contains("\\input") && !rewritten.contains("\\iftrue"));
+        let long: BTreeMap<String, serde_json::Value> = serde_json::from_str(&format!(r#"{{"{}": true}}"#, "a".repeat(33))).unwrap();
+        assert!(inject_defines("\\documentclass{article}", &long).is_err());
+
+        let bad: BTreeMap<String, serde_json::Value> = serde_json::from_str(r#"{"bad name": true}"#).unwrap();
+        assert!(inject_defines("\\documentclass{article}", &bad).is_err());
+        let nested: BTreeMap<String, serde_json::Value> = serde_json::from_str(r#"{"list": [1]}"#).unwrap();
+        assert!(inject_defines("\\documentclass{article}", &nested).is_err());
+    }
+
+    #[test]
+    fn test_beamer_variants() {
+        assert_eq!(beamer_variant(SLIDES, "slides").unwrap(), SLIDES);
+        assert!(beamer_variant(SLIDES, "notes").unwrap().contains("{beamer}\n\\setbeameroption{show notes}\n"));
+        assert!(beamer_variant(SLIDES, "poster").is_err());
+        assert!(beamer_variant("\\documentclass{article}", "handout").is_err());
+    }
+}
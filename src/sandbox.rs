@@ -0,0 +1,227 @@
+//! Confinement for the external programs we spawn (poppler's `pdftocairo`). The child runs
+//! in its work directory with a scrubbed environment and resource limits, may only create,
+//! write or delete files inside that directory (Landlock), and has no network (a fresh
+//! network namespace). Landlock needs kernel support; where it is missing the child runs
+//! with the remaining restrictions and a warning is logged once at startup. The network
+//! namespace fails closed: a child that cannot get one is not started, unless
+//! SANDBOX_ALLOW_NETWORK is set.
+
+use std::ffi::CString;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::process::CommandExt;
+use std::path::Path;
+use std::process::Command;
+use tracing::{info, warn};
+
+use crate::settings::Settings;
+
+const MB: u64 = 1024 * 1024;
+const DEFAULT_PATH: &str = "/usr/local/bin:/usr/bin:/bin";
+
+// Landlock ABI v1 (Linux 5.13); the libc crate does not define these
+const LANDLOCK_CREATE_RULESET_VERSION: u32 = 1;
+const LANDLOCK_RULE_PATH_BENEATH: libc::c_int = 1;
+const LANDLOCK_ACCESS_FS_WRITE_FILE: u64 = 1 << 1;
+/// WRITE_FILE, REMOVE_DIR, REMOVE_FILE and every MAKE_* right
+const LANDLOCK_WRITE_ACCESS: u64 = LANDLOCK_ACCESS_FS_WRITE_FILE | (0x1ff << 4);
+
+#[repr(C)]
+struct LandlockRulesetAttr {
+    handled_access_fs: u64,
+}
+
+#[repr(C, packed)]
+struct LandlockPathBeneathAttr {
+    allowed_access: u64,
+    parent_fd: libc::c_int,
+}
+
+#[derive(Clone, Debug)]
+pub struct Sandbox {
+    pub enabled: bool,
+    pub cpu_secs: u64,
+    pub memory_mb: u64,
+    pub file_mb: u64,
+    pub allow_network: bool,
+}
+
+impl Sandbox {
+    pub fn from_settings(settings: &Settings) -> Self {
+        Self {
+            enabled: settings.sandbox_enabled,
+            cpu_secs: settings.sandbox_cpu_secs,
+            memory_mb: settings.sandbox_memory_mb,
+            file_mb: settings.sandbox_file_mb,
+            allow_network: settings.sandbox_allow_network,
+        }
+    }
+
+    /// Logs which restrictions this kernel can enforce.
+    pub fn report(&self) {
+        if !self.enabled {
+            warn!("⚠️ Sandbox disabled: external programs run with full filesystem access");
+            return;
+        }
+        match landlock_abi() {
+            Some(abi) => info!("🔒 Sandbox: Landlock ABI v{}, {}s CPU, {} MB memory, {} MB files", abi, self.cpu_secs, self.memory_mb, self.file_mb),
+            None => warn!("⚠️ Landlock unavailable: external programs are not confined to their work directory"),
+        }
+        if !self.allow_network && !network_namespace_available() {
+            warn!("⚠️ Network namespaces unavailable: external programs will fail to start (set SANDBOX_ALLOW_NETWORK=true to run them with network access)");
+        }
+    }
+
+    /// Confines `cmd` to `work_dir`. Must be applied before any other environment is set.
+    pub fn apply(&self, cmd: &mut Command, work_dir: &Path) {
+        cmd.current_dir(work_dir);
+        if !self.enabled {
+            return;
+        }
+        cmd.env_clear()
+            .env("PATH", std::env::var_os("PATH").unwrap_or_else(|| DEFAULT_PATH.into()))
+            .env("HOME", work_dir)
+            .env("TMPDIR", work_dir)
+            .env("XDG_CACHE_HOME", work_dir)
+            .env("LANG", "C.UTF-8");
+
+        // Allocated here: only async-signal-safe calls are allowed between fork and exec
+        let writable = [
+            CString::new(work_dir.as_os_str().as_bytes()).ok(),
+            CString::new("/dev/null").ok(),
+        ];
+        let limits = [
+            (libc::RLIMIT_CPU, self.cpu_secs),
+            (libc::RLIMIT_AS, self.memory_mb * MB),
+            (libc::RLIMIT_FSIZE, self.file_mb * MB),
+            (libc::RLIMIT_CORE, 0),
+        ];
+        let allow_network = self.allow_network;
+        let confine = move || {
+            for (resource, value) in limits {
+                let limit = libc::rlimit { rlim_cur: value as libc::rlim_t, rlim_max: value as libc::rlim_t };
+                if unsafe { libc::setrlimit(resource, &limit) } != 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+            }
+            if !allow_network {
+                // A user namespace lets unprivileged processes create the network namespace
+                if unsafe { libc::unshare(libc::CLONE_NEWUSER | libc::CLONE_NEWNET) } != 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+            }
+            if unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) } != 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            restrict_writes(&writable);
+            Ok(())
+        };
+        // SAFETY: the closure only makes async-signal-safe system calls on memory allocated
+        // before the fork
+        unsafe { cmd.pre_exec(confine) };
+    }
+}
+
+/// The kernel's Landlock ABI version, `None` when Landlock is unavailable.
+pub fn landlock_abi() -> Option<i64> {
+    let abi = unsafe {
+        libc::syscall(libc::SYS_landlock_create_ruleset, std::ptr::null::<LandlockRulesetAttr>(), 0usize, LANDLOCK_CREATE_RULESET_VERSION)
+    };
+    (abi > 0).then_some(abi)
+}
+
+/// Whether a child can be given its own network namespace, probed by starting `true` in one.
+pub fn network_namespace_available() -> bool {
+    let mut cmd = Command::new("true");
+    let unshare = || match unsafe { libc::unshare(libc::CLONE_NEWUSER | libc::CLONE_NEWNET) } {
+        0 => Ok(()),
+        _ => Err(std::io::Error::last_os_error()),
+    };
+    // SAFETY: unshare is a single system call
+    unsafe { cmd.pre_exec(unshare) };
+    cmd.status().is_ok_and(|status| status.success())
+}
+
+/// Denies file creation, writes and deletion outside `writable`, if the kernel supports Landlock.
+fn restrict_writes(writable: &[Option<CString>]) {
+    let attr = LandlockRulesetAttr { handled_access_fs: LANDLOCK_WRITE_ACCESS };
+    let ruleset = unsafe {
+        libc::syscall(libc::SYS_landlock_create_ruleset, &attr as *const LandlockRulesetAttr, std::mem::size_of::<LandlockRulesetAttr>(), 0u32)
+    } as libc::c_int;
+    if ruleset < 0 {
+        return;
+    }
+    for path in writable.iter().flatten() {
+        let fd = unsafe { libc::open(path.as_ptr(), libc::O_PATH | libc::O_CLOEXEC) };
+        if fd < 0 {
+            continue;
+        }
+        let is_dir = path.as_bytes() != b"/dev/null";
+        let rule = LandlockPathBeneathAttr {
+            allowed_access: if is_dir { LANDLOCK_WRITE_ACCESS } else { LANDLOCK_ACCESS_FS_WRITE_FILE },
+            parent_fd: fd,
+        };
+        unsafe {
+            libc::syscall(libc::SYS_landlock_add_rule, ruleset, LANDLOCK_RULE_PATH_BENEATH, &rule as *const LandlockPathBeneathAttr, 0u32);
+            libc::close(fd);
+        }
+    }
+    unsafe {
+        libc::syscall(libc::SYS_landlock_restrict_self, ruleset, 0u32);
+        libc::close(ruleset);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sandbox() -> Sandbox {
+        Sandbox { enabled: true, cpu_secs: 7, memory_mb: 512, file_mb: 1, allow_network: false }
+    }
+
+    #[test]
+    fn test_environment_and_limits() {
+        let dir = tempfile::TempDir::new().unwrap();
+        std::env::set_var("TACHYON_SANDBOX_SECRET", "leaked");
+        let mut cmd = Command::new("sh");
+        cmd.args(["-c", "env; ulimit -t; pwd"]);
+        sandbox().apply(&mut cmd, dir.path());
+        let output = cmd.output().unwrap();
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        assert!(!stdout.contains("TACHYON_SANDBOX_SECRET"));
+        assert!(stdout.contains(&format!("HOME={}", dir.path().display())));
+        assert!(stdout.lines().any(|line| line == "7"));
+    }
+
+    #[test]
+    fn test_writes_confined_to_work_dir() {
+        if landlock_abi().is_none() {
+            return;
+        }
+        let (work, outside) = (tempfile::TempDir::new().unwrap(), tempfile::TempDir::new().unwrap());
+        let mut cmd = Command::new("sh");
+        cmd.arg("-c").arg(format!("echo in > inside.txt; echo out > {}/outside.txt; echo quiet > /dev/null", outside.path().display()));
+        sandbox().apply(&mut cmd, work.path());
+        cmd.output().unwrap();
+        assert!(work.path().join("inside.txt").exists());
+        assert!(!outside.path().join("outside.txt").exists());
+    }
+
+    #[test]
+    fn test_no_network() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let mut cmd = Command::new("cat");
+        cmd.arg("/proc/net/dev");
+        sandbox().apply(&mut cmd, dir.path());
+        let Ok(output) = cmd.output() else { return };
+        if !network_namespace_available() {
+            // Fails closed rather than running with the host's network
+            assert!(!output.status.success());
+            return;
+        }
+        let interfaces: Vec<&str> = std::str::from_utf8(&output.stdout).unwrap().lines().skip(2)
+            .filter_map(|line| line.split(':').next()).map(str::trim).collect();
+        assert_eq!(interfaces, vec!["lo"]);
+    }
+}
@@ -0,0 +1,145 @@
+//! Replaces Unicode that the default fonts cannot typeset (smart quotes pasted from word
+//! processors, invisible spaces, emoji) with LaTeX equivalents before compiling.
+
+use crate::models::CompileWarning;
+use crate::validator::VERBATIM_ENVIRONMENTS;
+
+/// Characters with a LaTeX spelling that works with the default fonts.
+const REPLACEMENTS: &[(char, &str)] = &[
+    ('\u{2018}', "`"),
+    ('\u{2019}', "'"),
+    ('\u{201C}', "``"),
+    ('\u{201D}', "''"),
+    ('\u{201E}', ",,"),
+    ('\u{00AB}', "\\guillemotleft{}"),
+    ('\u{00BB}', "\\guillemotright{}"),
+    ('\u{2013}', "--"),
+    ('\u{2014}', "---"),
+    ('\u{2212}', "\\ensuremath{-}"),
+    ('\u{2026}', "\\ldots{}"),
+    ('\u{2022}', "\\textbullet{}"),
+    ('\u{00A0}', "~"),
+    ('\u{2009}', "\\,"),
+    ('\u{202F}', "\\,"),
+    ('\u{2002}', "\\enspace{}"),
+    ('\u{2003}', "\\quad{}"),
+    ('\u{00AD}', "\\-"),
+    ('\u{200B}', ""),
+    ('\u{200C}', ""),
+    ('\u{200D}', ""),
+    ('\u{2060}', ""),
+    ('\u{FEFF}', ""),
+    ('\u{2122}', "\\texttrademark{}"),
+    ('\u{00A9}', "\\textcopyright{}"),
+    ('\u{00AE}', "\\textregistered{}"),
+    ('\u{00B0}', "\\textdegree{}"),
+    ('\u{20AC}', "\\texteuro{}"),
+    ('\u{00D7}', "\\texttimes{}"),
+    ('\u{00F7}', "\\textdiv{}"),
+    ('\u{00B1}', "\\textpm{}"),
+    ('\u{2192}', "\\textrightarrow{}"),
+    ('\u{2190}', "\\textleftarrow{}"),
+    ('\u{2264}', "\\ensuremath{\\leq}"),
+    ('\u{2265}', "\\ensuremath{\\geq}"),
+    ('\u{2260}', "\\ensuremath{\\neq}"),
+    ('\u{2248}', "\\ensuremath{\\approx}"),
+    ('\u{221E}', "\\ensuremath{\\infty}"),
+];
+
+/// Emoji, pictographs and dingbats: no bundled font has them, so they are dropped.
+fn is_pictograph(c: char) -> bool {
+    matches!(c as u32, 0x1F000..=0x1FAFF | 0x2600..=0x27BF | 0xFE0F)
+}
+
+/// All replacements of one character in a file.
+#[derive(Debug, PartialEq)]
+pub struct Substitution {
+    pub character: char,
+    /// Empty when the character was removed
+    pub replacement: &'static str,
+    /// Line of the first occurrence
+    pub line: u32,
+    pub count: usize,
+}
+
+impl Substitution {
+    pub fn message(&self) -> String {
+        let times = if self.count > 1 { format!(" ({} times)", self.count) } else { String::new() };
+        if self.replacement.is_empty() {
+            format!("Removed U+{:04X}{}", self.character as u32, times)
+        } else {
+            format!("Replaced U+{:04X} '{}' with {}{}", self.character as u32, self.character, self.replacement, times)
+        }
+    }
+
+    pub fn warning(&self, file: &str) -> CompileWarning {
+        CompileWarning {
+            kind: "unicode".to_string(),
+            file: Some(file.to_string()),
+            line: Some(self.line),
+            measurement: None,
+            message: self.message(),
+        }
+    }
+}
+
+/// Rewrites problem characters outside verbatim environments. Returns the new source and
+/// what was replaced, in order of first occurrence.
+pub fn sanitize(source: &str) -> (String, Vec<Substitution>) {
+    let mut out = String::with_capacity(source.len());
+    let mut substitutions: Vec<Substitution> = Vec::new();
+    let mut verbatim: Option<&str> = None;
+
+    for (index, line) in source.split_inclusive('\n').enumerate() {
+        if let Some(env) = verbatim {
+            if line.contains(&format!("\\end{{{}}}", env)) {
+                verbatim = None;
+            }
+            out.push_str(line);
+            continue;
+        }
+        verbatim = VERBATIM_ENVIRONMENTS.iter().copied()
+            .find(|env| line.contains(&format!("\\begin{{{}}}", env)) && !line.contains(&format!("\\end{{{}}}", env)));
+
+        for c in line.chars() {
+            let replacement = match REPLACEMENTS.iter().find(|(from, _)| *from == c) {
+                Some((_, to)) => *to,
+                None if is_pictograph(c) => "",
+                None => {
+                    out.push(c);
+                    continue;
+                }
+            };
+            out.push_str(replacement);
+            match substitutions.iter_mut().find(|s| s.character == c) {
+                Some(existing) => existing.count += 1,
+                None => substitutions.push(Substitution { character: c, replacement, line: index as u32 + 1, count: 1 }),
+            }
+        }
+    }
+    (out, substitutions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sanitize() {
+        let source = "He said \u{201C}don\u{2019}t\u{201D} \u{2014} twice\u{2026}\u{00A0}\u{1F600}\n\\begin{verbatim}\n\u{201C}raw\u{201D}\n\\end{verbatim}\n\u{201C}again\u{201D}\n";
+        let (clean, substitutions) = sanitize(source);
+        assert_eq!(clean, "He said ``don't'' --- twice\\ldots{}~\n\\begin{verbatim}\n\u{201C}raw\u{201D}\n\\end{verbatim}\n``again''\n");
+        assert_eq!(substitutions[0], Substitution { character: '\u{201C}', replacement: "``", line: 1, count: 2 });
+        assert_eq!(substitutions.len(), 7);
+        assert_eq!(substitutions[6].message(), "Removed U+1F600");
+        assert_eq!(substitutions[0].message(), "Replaced U+201C '\u{201C}' with `` (2 times)");
+    }
+
+    #[test]
+    fn test_clean_source_is_untouched() {
+        let source = "\\documentclass{article}\n\\begin{document}\nCañón, Straße, naïve.\n\\end{document}\n";
+        let (clean, substitutions) = sanitize(source);
+        assert_eq!(clean, source);
+        assert!(substitutions.is_empty());
+    }
+}
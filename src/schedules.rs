@@ -0,0 +1,379 @@
+//! Recurring builds (`/schedules`): a project recompiled on a cron expression, such as a
+//! nightly report that downloads fresh data from its `data_urls` first. Every PDF goes to
+//! the output store and, when the schedule has a `deliver_url`, is PUT there as well; each
+//! run fires `schedule.success` or `schedule.failure` and is kept in the schedule's history.
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::{info, warn};
+
+use crate::cron::Cron;
+use crate::jobs::is_safe_path;
+use crate::models::{CreateScheduleRequest, Schedule, ScheduleRun, WebhookPayload};
+use crate::services::{AppState, Priority};
+use crate::settings::Settings;
+use crate::storage::Storage;
+use crate::tenancy::TENANT;
+use crate::webhooks::Webhooks;
+use crate::util::unix_now;
+
+const PREFIX: &str = "schedules/";
+
+/// Runs kept on a schedule.
+const MAX_RUNS: usize = 20;
+
+/// Most data URLs a schedule may download before each run.
+const MAX_DATA_URLS: usize = 20;
+
+/// How often due schedules are looked for.
+const TICK: Duration = Duration::from_secs(30);
+
+/// Longest a run may compile for.
+const RUN_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Timeout for uploading a PDF to a schedule's `deliver_url`.
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// A schedule as stored, with its tenant.
+#[derive(Serialize, Deserialize)]
+struct StoredSchedule {
+    schedule: Schedule,
+    tenant: Option<String>,
+}
+
+#[derive(Clone)]
+pub struct ScheduleStore {
+    storage: Arc<dyn Storage>,
+    /// Schedules with a run in progress, which are not started again
+    running: Arc<Mutex<HashSet<String>>>,
+}
+
+/// Marks a schedule as running until dropped.
+struct Running {
+    running: Arc<Mutex<HashSet<String>>>,
+    id: String,
+}
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        self.running.lock().unwrap().remove(&self.id);
+    }
+}
+
+impl ScheduleStore {
+    pub fn new(storage: Arc<dyn Storage>) -> Self {
+        Self { storage, running: Arc::default() }
+    }
+
+    /// Marks the schedule as running; `None` if a run is already in progress.
+    fn start(&self, id: &str) -> Option<Running> {
+        self.running.lock().unwrap().insert(id.to_string())
+            .then(|| Running { running: self.running.clone(), id: id.to_string() })
+    }
+
+    pub async fn create(&self, tenant: Option<String>, schedule: Schedule) -> Result<Schedule, String> {
+        let stored = StoredSchedule { schedule, tenant };
+        self.save(&stored).await?;
+        Ok(stored.schedule)
+    }
+
+    pub async fn get(&self, id: &str, tenant: Option<&str>) -> Option<Schedule> {
+        self.load(id, tenant).await.map(|stored| stored.schedule)
+    }
+
+    /// The tenant's schedules, oldest first.
+    pub async fn list(&self, tenant: Option<&str>) -> Vec<Schedule> {
+        let mut schedules: Vec<Schedule> = self.all().await.into_iter()
+            .filter(|stored| stored.tenant.as_deref() == tenant)
+            .map(|stored| stored.schedule)
+            .collect();
+        schedules.sort_by_key(|schedule| schedule.created_at);
+        schedules
+    }
+
+    /// Whether the tenant had a schedule with this id.
+    pub async fn delete(&self, id: &str, tenant: Option<&str>) -> bool {
+        self.load(id, tenant).await.is_some() && self.storage.delete(&key(id)).await.is_ok()
+    }
+
+    async fn load(&self, id: &str, tenant: Option<&str>) -> Option<StoredSchedule> {
+        if id.is_empty() || !id.chars().all(|c| c.is_ascii_alphanumeric()) {
+            return None;
+        }
+        let stored: StoredSchedule = serde_json::from_slice(&self.storage.get(&key(id)).await.ok()??).ok()?;
+        (stored.tenant.as_deref() == tenant).then_some(stored)
+    }
+
+    async fn save(&self, stored: &StoredSchedule) -> Result<(), String> {
+        let data = serde_json::to_vec(stored).map_err(|e| e.to_string())?;
+        self.storage.put(&key(&stored.schedule.id), data).await
+    }
+
+    async fn all(&self) -> Vec<StoredSchedule> {
+        let keys = match self.storage.list(PREFIX).await {
+            Ok(keys) => keys,
+            Err(e) => {
+                warn!("Failed to list schedules: {}", e);
+                return Vec::new();
+            }
+        };
+        let mut schedules = Vec::with_capacity(keys.len());
+        for key in keys {
+            if let Some(stored) = self.storage.get(&key).await.ok().flatten().and_then(|data| serde_json::from_slice(&data).ok()) {
+                schedules.push(stored);
+            }
+        }
+        schedules
+    }
+}
+
+fn key(id: &str) -> String {
+    format!("{}{}", PREFIX, id)
+}
+
+/// Checks a new schedule's cron expression, data file names and URLs against the webhook
+/// target policy, returning the parsed expression.
+pub fn validate(request: &CreateScheduleRequest, settings: &Settings) -> Result<Cron, String> {
+    let cron = Cron::parse(&request.cron)?;
+    if request.data_urls.len() > MAX_DATA_URLS {
+        return Err(format!("At most {} data_urls are allowed", MAX_DATA_URLS));
+    }
+    for (name, url) in &request.data_urls {
+        if !is_safe_path(name) {
+            return Err(format!("Invalid data file name {}", name));
+        }
+        Webhooks::validate_url(url, settings).map_err(|e| format!("Data URL for {} rejected: {}", name, e))?;
+    }
+    if let Some(url) = &request.deliver_url {
+        Webhooks::validate_url(url, settings).map_err(|e| format!("deliver_url rejected: {}", e))?;
+    }
+    Ok(cron)
+}
+
+/// Runs a schedule now, recording the run in its history and firing `schedule.success` or
+/// `schedule.failure`. `None` if the tenant has no such schedule, an error if it is running.
+pub async fn run(state: &AppState, id: &str, tenant: Option<&str>) -> Result<Option<ScheduleRun>, String> {
+    if state.schedules.load(id, tenant).await.is_none() {
+        return Ok(None);
+    }
+    let running = state.schedules.start(id).ok_or_else(|| format!("Schedule {} is already running", id))?;
+    Ok(execute(state, id, tenant, running).await)
+}
+
+async fn execute(state: &AppState, id: &str, tenant: Option<&str>, _running: Running) -> Option<ScheduleRun> {
+    let stored = state.schedules.load(id, tenant).await?;
+    let run = build(state, &stored.schedule, tenant).await;
+    let schedule = &stored.schedule;
+    if run.success {
+        info!("⏰ Schedule {} built project {} in {}ms", schedule.id, schedule.project_id, run.compile_time_ms);
+    } else {
+        info!("🚨 Schedule {} failed: {}", schedule.id, run.error.as_deref().unwrap_or_default());
+    }
+    Webhooks::fire(state, WebhookPayload {
+        event: if run.success { "schedule.success" } else { "schedule.failure" }.to_string(),
+        timestamp: run.ran_at,
+        project_id: Some(schedule.project_id.clone()),
+        tenant: stored.tenant.clone(),
+        main_file: Some(schedule.main.clone()),
+        success: run.success,
+        compile_time_ms: run.compile_time_ms,
+        error: run.error.clone(),
+        output_hash: run.output_hash.clone(),
+        test_id: None,
+        schedule_id: Some(schedule.id.clone()),
+    });
+
+    // Reloaded so a next run time the ticker set meanwhile is kept
+    match state.schedules.load(id, tenant).await {
+        Some(mut stored) => {
+            record(&mut stored.schedule, run.clone());
+            if let Err(e) = state.schedules.save(&stored).await {
+                warn!("Failed to record the run of schedule {}: {}", id, e);
+            }
+        }
+        None => info!("Schedule {} was deleted while it ran", id),
+    }
+    Some(run)
+}
+
+fn record(schedule: &mut Schedule, run: ScheduleRun) {
+    schedule.consecutive_failures = if run.success { 0 } else { schedule.consecutive_failures + 1 };
+    schedule.runs.insert(0, run);
+    schedule.runs.truncate(MAX_RUNS);
+}
+
+/// Downloads the data, compiles the sources with it and delivers the PDF.
+async fn build(state: &AppState, schedule: &Schedule, tenant: Option<&str>) -> ScheduleRun {
+    let run = ScheduleRun {
+        ran_at: unix_now(),
+        success: false,
+        output_hash: None,
+        compile_time_ms: 0,
+        delivered: None,
+        error: None,
+    };
+    let mut files = Vec::with_capacity(schedule.files.len() + schedule.data_urls.len());
+    for file in schedule.files.iter().filter(|file| !schedule.data_urls.contains_key(&file.name)) {
+        match state.blob_store.get(&file.hash).await {
+            Some(data) => files.push((file.name.clone(), data.to_vec())),
+            None => return ScheduleRun { error: Some(format!("Blob {} of {} not found", file.hash, file.name)), ..run },
+        }
+    }
+    for (name, url) in &schedule.data_urls {
+        match crate::jobs::download(url, reqwest::header::HeaderMap::new(), &state.settings).await {
+            Ok(data) => files.push((name.clone(), data)),
+            Err(e) => return ScheduleRun { error: Some(format!("Data for {}: {}", name, e)), ..run },
+        }
+    }
+    if let Err(e) = crate::connectors::add_files(state, &schedule.project_id, tenant, &mut files).await {
+        return ScheduleRun { error: Some(e), ..run };
+    }
+    // Fresh data is the point of a run, so the PDF cache is bypassed
+    let (pdf, compile_time_ms) = match crate::render::compile_uncached(state, &schedule.main, &files, Priority::Batch, RUN_TIMEOUT, None).await {
+        Ok((Ok(pdf), _, compile_time_ms)) => (pdf, compile_time_ms),
+        Ok((Err(e), _, compile_time_ms)) => return ScheduleRun { compile_time_ms, error: Some(format!("Compilation failed: {}", e)), ..run },
+        Err(e) => return ScheduleRun { error: Some(e), ..run },
+    };
+    let output_hash = state.output_store.put(&pdf).await;
+    let run = ScheduleRun { output_hash: Some(output_hash), compile_time_ms, ..run };
+    let Some(url) = &schedule.deliver_url else {
+        return ScheduleRun { success: true, ..run };
+    };
+    match deliver(url, pdf, &state.settings).await {
+        Ok(()) => ScheduleRun { success: true, delivered: Some(true), ..run },
+        Err(e) => ScheduleRun { delivered: Some(false), error: Some(format!("Delivery failed: {}", e)), ..run },
+    }
+}
+
+/// PUTs a PDF under the webhook target policy, as presigned S3 URLs expect.
+async fn deliver(url: &str, pdf: Vec<u8>, settings: &Settings) -> Result<(), String> {
+    let parsed = Webhooks::validate_url(url, settings)?;
+    let addrs = Webhooks::resolve_target(&parsed, settings).await?;
+    let client = reqwest::Client::builder()
+        .timeout(DELIVERY_TIMEOUT)
+        .redirect(reqwest::redirect::Policy::none())
+        .resolve_to_addrs(parsed.host_str().unwrap_or_default(), &addrs)
+        .build()
+        .map_err(|e| e.to_string())?;
+    let response = client.put(parsed)
+        .header(reqwest::header::CONTENT_TYPE, "application/pdf")
+        .body(pdf)
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+    if !response.status().is_success() {
+        return Err(format!("HTTP {}", response.status()));
+    }
+    Ok(())
+}
+
+/// Starts the schedules that are due every TICK.
+pub async fn schedule_task(state: AppState) {
+    loop {
+        tokio::time::sleep(TICK).await;
+        run_due(&state, unix_now()).await;
+    }
+}
+
+async fn run_due(state: &AppState, now: u64) {
+    for mut stored in state.schedules.all().await {
+        let schedule = &mut stored.schedule;
+        if schedule.next_run_at.is_none_or(|at| at > now) {
+            continue;
+        }
+        let Some(running) = state.schedules.start(&schedule.id) else { continue };
+        // Advanced before the run starts, so a slow run is not started twice
+        schedule.next_run_at = Cron::parse(&schedule.cron).ok().and_then(|cron| cron.next_after(now));
+        let id = schedule.id.clone();
+        if let Err(e) = state.schedules.save(&stored).await {
+            warn!("Failed to advance schedule {}: {}", id, e);
+            continue;
+        }
+        let state = state.clone();
+        tokio::spawn(async move {
+            // Storage writes are attributed to, and encrypted for, the schedule's tenant as in its requests
+            let tenant = stored.tenant;
+            TENANT.scope(tenant.clone(), execute(&state, &id, tenant.as_deref(), running)).await;
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+
+    fn schedule() -> Schedule {
+        Schedule {
+            id: "s1".into(),
+            project_id: "p1".into(),
+            cron: "@daily".into(),
+            main: "main.tex".into(),
+            files: Vec::new(),
+            data_urls: Default::default(),
+            deliver_url: None,
+            created_at: 0,
+            next_run_at: Some(86_400),
+            consecutive_failures: 0,
+            runs: Vec::new(),
+        }
+    }
+
+    fn run(success: bool) -> ScheduleRun {
+        ScheduleRun { ran_at: 0, success, output_hash: None, compile_time_ms: 0, delivered: None, error: None }
+    }
+
+    #[test]
+    fn test_record() {
+        let mut schedule = schedule();
+        for _ in 0..MAX_RUNS + 5 {
+            record(&mut schedule, run(false));
+        }
+        assert_eq!(schedule.runs.len(), MAX_RUNS);
+        assert_eq!(schedule.consecutive_failures, MAX_RUNS as u32 + 5);
+        record(&mut schedule, run(true));
+        assert_eq!(schedule.consecutive_failures, 0);
+        assert!(schedule.runs[0].success, "newest first");
+    }
+
+    #[test]
+    fn test_one_run_at_a_time() {
+        let store = ScheduleStore::new(Arc::new(MemoryStorage::new()));
+        let running = store.start("s1").unwrap();
+        assert!(store.start("s1").is_none());
+        assert!(store.start("s2").is_some());
+        drop(running);
+        assert!(store.start("s1").is_some());
+    }
+
+    #[test]
+    fn test_validate() {
+        let settings = Settings::from_env();
+        let request = |json: serde_json::Value| serde_json::from_value::<CreateScheduleRequest>(json).unwrap();
+        let valid = request(serde_json::json!({
+            "project_id": "p1",
+            "cron": "0 6 * * mon-fri",
+            "data_urls": {"data/sales.csv": "https://example.com/sales.csv"},
+            "deliver_url": "https://bucket.s3.amazonaws.com/report.pdf?X-Amz-Signature=abc",
+        }));
+        assert!(validate(&valid, &settings).is_ok());
+        assert!(validate(&request(serde_json::json!({"project_id": "p1", "cron": "daily"})), &settings).is_err());
+        assert!(validate(&request(serde_json::json!({"project_id": "p1", "cron": "@daily", "data_urls": {"../x.csv": "https://example.com/x"}})), &settings).is_err());
+        assert!(validate(&request(serde_json::json!({"project_id": "p1", "cron": "@daily", "deliver_url": "ftp://example.com/x"})), &settings).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_store_is_tenant_scoped() {
+        let store = ScheduleStore::new(Arc::new(MemoryStorage::new()));
+        store.create(Some("acme".into()), schedule()).await.unwrap();
+        assert!(store.get("s1", Some("acme")).await.is_some());
+        assert!(store.get("s1", None).await.is_none());
+        assert!(store.get("../s1", Some("acme")).await.is_none());
+        assert_eq!(store.list(Some("acme")).await.len(), 1);
+        assert!(!store.delete("s1", Some("other")).await);
+        assert!(store.delete("s1", Some("acme")).await);
+        assert!(store.list(Some("acme")).await.is_empty());
+    }
+}
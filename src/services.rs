@@ -1,36 +1,227 @@
-use std::collections::{HashMap, HashSet};
-use std::path::PathBuf;
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use std::sync::atomic::{AtomicU64, Ordering};
-use tokio::sync::RwLock;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use tokio::sync::{Notify, OwnedSemaphorePermit, RwLock, Semaphore};
+use tracing::error;
 use std::time::{SystemTime, UNIX_EPOCH};
-use xxhash_rust::xxh64::xxh64;
-use crate::models::WebhookSubscription;
+use xxhash_rust::xxh64::{xxh64, Xxh64};
+use crate::estimate::History;
+use crate::fingerprint::Fingerprint;
+use crate::models::{BrandingProfile, CompileWarning, DeadLetter, EstimateResponse, Project, ProjectBuild, SimilarDocument, WebhookSubscription};
+use crate::receipt::ReceiptSigner;
+use crate::settings::Settings;
+use crate::shard::ShardedMap;
+use crate::storage::Storage;
+use crate::tenancy::current_tenant;
+use crate::workers::WorkerPool;
+
+/// Directory where per-compile workspaces are created: a RAM disk when available. Instances
+/// on the same host share it; each janitor keeps to its own subdirectory.
+pub fn workspace_base() -> PathBuf {
+    let path = if std::path::Path::new("/dev/shm").exists() {
+        PathBuf::from("/dev/shm/tachyon-compilations")
+    } else {
+        std::env::temp_dir().join("tachyon-compilations")
+    };
+    std::fs::create_dir_all(&path).ok();
+    path
+}
 
 // ============================================================================
 // Blob Store (Image Fingerprinting)
 // ============================================================================
 
+/// Hashes are used as storage keys, so only plain hex/alphanumeric ones are accepted.
+fn valid_hash(hash: &str) -> bool {
+    !hash.is_empty() && hash.chars().all(|c| c.is_ascii_alphanumeric())
+}
+
 #[derive(Clone)]
 pub struct BlobStore {
-    pub cache: Arc<RwLock<HashMap<String, Vec<u8>>>>,
+    storage: Arc<dyn Storage>,
 }
 
 impl BlobStore {
+    pub fn new(storage: Arc<dyn Storage>) -> Self {
+        Self { storage }
+    }
+
+    pub async fn get(&self, hash: &str) -> Option<Bytes> {
+        if !valid_hash(hash) {
+            return None;
+        }
+        self.storage.get(&format!("blobs/{}", hash)).await
+            .unwrap_or_else(|e| { error!("Blob store read failed: {}", e); None })
+    }
+
+    pub async fn put(&self, hash: String, data: Vec<u8>) {
+        if !valid_hash(&hash) {
+            return;
+        }
+        if let Err(e) = self.storage.put(&format!("blobs/{}", hash), data).await {
+            error!("Blob store write failed: {}", e);
+        }
+    }
+
+    /// Like [`BlobStore::put`], streaming the file at `path` where the backend allows.
+    pub async fn put_file(&self, hash: String, path: &Path) {
+        if !valid_hash(&hash) {
+            return;
+        }
+        if let Err(e) = self.storage.put_file(&format!("blobs/{}", hash), path).await {
+            error!("Blob store write failed: {}", e);
+        }
+    }
+}
+
+// ============================================================================
+// Output Store (Content-Addressable PDFs)
+// ============================================================================
+
+/// Compiled PDFs keyed by the SHA-256 of their bytes, so clients can fetch results
+/// later via `GET /outputs/:hash` (served as immutable). Oldest outputs are evicted
+/// past `max_bytes`.
+#[derive(Clone)]
+pub struct OutputStore {
+    pub max_bytes: usize,
+    storage: Arc<dyn Storage>,
+    /// Stored outputs with their sizes, oldest first
+    order: Arc<RwLock<VecDeque<(String, usize)>>>,
+}
+
+impl OutputStore {
+    pub fn new(max_mb: usize, storage: Arc<dyn Storage>) -> Self {
+        Self {
+            max_bytes: max_mb * 1024 * 1024,
+            storage,
+            order: Arc::new(RwLock::new(VecDeque::new())),
+        }
+    }
+
+    pub fn hash_output(pdf_data: &[u8]) -> String {
+        crate::receipt::sha256_hex(pdf_data)
+    }
+
+    /// Rebuilds the index from what storage holds, e.g. at startup, returning how many
+    /// outputs were found. Their age is unknown, so they are evicted in listing order.
+    pub async fn load(&self) -> usize {
+        let keys = match self.storage.list("outputs/").await {
+            Ok(keys) => keys,
+            Err(e) => {
+                error!("Output store listing failed: {}", e);
+                return 0;
+            }
+        };
+        let mut found = Vec::with_capacity(keys.len());
+        for key in keys {
+            if let Ok(Some(size)) = self.storage.head(&key).await {
+                found.push((key["outputs/".len()..].to_string(), size as usize));
+            }
+        }
+        let count = found.len();
+        let victims = {
+            let mut order = self.order.write().await;
+            order.extend(found);
+            self.evict(&mut order)
+        };
+        self.delete(victims).await;
+        count
+    }
+
+    /// Stores a PDF (idempotent) and returns its hash. A stored output is never rewritten.
+    /// Storage is only accessed with the index unlocked.
+    pub async fn put(&self, pdf_data: &[u8]) -> String {
+        let hash = Self::hash_output(pdf_data);
+        if self.order.read().await.iter().any(|(h, _)| *h == hash) {
+            return hash;
+        }
+        // Stored by another replica sharing the storage
+        let key = format!("outputs/{}", hash);
+        let stored = self.storage.head(&key).await.is_ok_and(|size| size.is_some());
+        if !stored {
+            if let Err(e) = self.storage.put(&key, pdf_data.to_vec()).await {
+                error!("Output store write failed: {}", e);
+                return hash;
+            }
+        }
+
+        let victims = {
+            let mut order = self.order.write().await;
+            if order.iter().any(|(h, _)| *h == hash) {
+                return hash;
+            }
+            order.push_back((hash.clone(), pdf_data.len()));
+            self.evict(&mut order)
+        };
+        self.delete(victims).await;
+        hash
+    }
+
+    /// Drops the oldest outputs from the index until it fits `max_bytes`, never the newest
+    /// one, returning the dropped hashes.
+    fn evict(&self, order: &mut VecDeque<(String, usize)>) -> Vec<String> {
+        let mut total: usize = order.iter().map(|(_, size)| size).sum();
+        let mut victims = Vec::new();
+        while total > self.max_bytes && order.len() > 1 {
+            if let Some((oldest, size)) = order.pop_front() {
+                total -= size;
+                victims.push(oldest);
+            }
+        }
+        victims
+    }
+
+    async fn delete(&self, hashes: Vec<String>) {
+        for hash in hashes {
+            if let Err(e) = self.storage.delete(&format!("outputs/{}", hash)).await {
+                error!("Output store eviction failed: {}", e);
+            }
+        }
+    }
+
+    /// Drops an output deleted from storage behind the store's back, so a later `put` stores it again.
+    pub async fn forget(&self, hash: &str) {
+        self.order.write().await.retain(|(h, _)| h != hash);
+    }
+
+    pub async fn get(&self, hash: &str) -> Option<Bytes> {
+        if !valid_hash(hash) {
+            return None;
+        }
+        self.storage.get(&format!("outputs/{}", hash)).await
+            .unwrap_or_else(|e| { error!("Output store read failed: {}", e); None })
+    }
+}
+
+// ============================================================================
+// Branding Profiles
+// ============================================================================
+
+#[derive(Clone)]
+pub struct BrandingStore {
+    pub profiles: Arc<RwLock<HashMap<String, BrandingProfile>>>,
+}
+
+impl BrandingStore {
     pub fn new() -> Self {
         Self {
-            cache: Arc::new(RwLock::new(HashMap::new())),
+            profiles: Arc::new(RwLock::new(HashMap::new())),
         }
     }
 
-    pub async fn get(&self, hash: &str) -> Option<Vec<u8>> {
-        let cache = self.cache.read().await;
-        cache.get(hash).cloned()
+    pub async fn get(&self, id: &str) -> Option<BrandingProfile> {
+        self.profiles.read().await.get(id).cloned()
     }
 
-    pub async fn put(&self, hash: String, data: Vec<u8>) {
-        let mut cache = self.cache.write().await;
-        cache.insert(hash, data);
+    pub async fn put(&self, profile: BrandingProfile) {
+        self.profiles.write().await.insert(profile.id.clone(), profile);
+    }
+
+    pub async fn remove(&self, id: &str) -> bool {
+        self.profiles.write().await.remove(id).is_some()
     }
 }
 
@@ -38,19 +229,23 @@ impl BlobStore {
 // PDF Compilation Cache
 // ============================================================================
 
-// Moonshot #1: In-memory cache - store PDF bytes directly, no fs::read on HIT
+/// Environments whose contents are typeset literally and must never be normalized.
+const VERBATIM_ENVIRONMENTS: &[&str] = &[
+    "verbatim", "Verbatim", "lstlisting", "minted", "comment", "filecontents", "alltt",
+];
+
+/// Index entry for a cached PDF; the bytes live in the cache's `Storage` under `pdf/<hash>`.
 pub struct CacheEntry {
-    pub pdf_data: Vec<u8>,
     pub created_at: u64,
     pub last_accessed: AtomicU64,  // Moonshot #4: LRU tracking
     pub compile_time_ms: u64,
+    /// Stored (possibly compressed) size, which is what counts against `max_cache_mb`
     pub size_bytes: usize,
 }
 
 impl Clone for CacheEntry {
     fn clone(&self) -> Self {
         Self {
-            pdf_data: self.pdf_data.clone(),
             created_at: self.created_at,
             last_accessed: AtomicU64::new(self.last_accessed.load(Ordering::Relaxed)),
             compile_time_ms: self.compile_time_ms,
@@ -59,92 +254,485 @@ impl Clone for CacheEntry {
     }
 }
 
+/// Incremental cache key over a project. Content is hashed per file, in chunks as it
+/// arrives; the key is built from the canonical `(file name, content hash)` sequence sorted
+/// by name, so upload order does not matter and a re-uploaded name keeps its last content,
+/// just like the workspace on disk.
+#[derive(Clone)]
+pub struct InputHasher {
+    normalize_keys: bool,
+    /// Seed of the project key: the active bundle's, so outputs of other bundles never match,
+    /// mixed with the tenant's package registry
+    seed: u64,
+    files: BTreeMap<String, u64>,
+    current: Option<(String, Xxh64)>,
+}
+
+impl InputHasher {
+    /// Adds a complete file, normalizing `.tex` content when the cache is configured to.
+    pub fn add_file(&mut self, name: &str, data: &[u8]) {
+        self.begin_file(name);
+        if self.normalize_keys && name.ends_with(".tex") {
+            if let Ok(text) = std::str::from_utf8(data) {
+                self.update(CompilationCache::normalize_tex(text).as_bytes());
+                return self.end_file();
+            }
+        }
+        self.update(data);
+        self.end_file();
+    }
+
+    /// Starts a file whose content follows through [`InputHasher::update`].
+    pub fn begin_file(&mut self, name: &str) {
+        self.end_file();
+        self.current = Some((name.trim_start_matches("./").to_string(), Xxh64::new(0)));
+    }
+
+    pub fn update(&mut self, chunk: &[u8]) {
+        if let Some((_, content)) = self.current.as_mut() {
+            content.update(chunk);
+        }
+    }
+
+    /// Adds a setting that changes the output without being a file. Its name cannot clash
+    /// with a file's.
+    pub fn add_setting(&mut self, name: &str, value: &str) {
+        self.end_file();
+        self.files.insert(format!("\0{}", name), xxh64(value.as_bytes(), 0));
+    }
+
+    pub fn end_file(&mut self) {
+        if let Some((name, content)) = self.current.take() {
+            self.files.insert(name, content.digest());
+        }
+    }
+
+    /// The key of the project compiled from `main`.
+    pub fn finish(mut self, main: &str) -> u64 {
+        self.end_file();
+        let mut project = Xxh64::new(self.seed);
+        for (name, content) in &self.files {
+            project.update(name.as_bytes());
+            project.update(&[0]);
+            project.update(&content.to_le_bytes());
+        }
+        project.update(main.trim_start_matches("./").as_bytes());
+        project.digest()
+    }
+}
+
+/// What the log of a compile reported about its PDF, cached with it so hits answer like
+/// the miss that stored it.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct CompileNotes {
+    pub warnings: Vec<CompileWarning>,
+    /// Fixes self-healing applied before the retry that produced the PDF
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub self_healed: Option<Vec<String>>,
+}
+
+impl CompileNotes {
+    pub fn from_log(logs: &str) -> Self {
+        Self { warnings: crate::warnings::parse_log_warnings(logs), self_healed: crate::healer::SelfHealer::report(logs) }
+    }
+}
+
+/// A remembered compile failure (negative cache).
+#[derive(Clone)]
+pub struct FailureEntry {
+    pub error: String,
+    pub logs: String,
+    pub created_at: u64,
+}
+
 #[derive(Clone)]
 pub struct CompilationCache {
     pub enabled: bool,
     pub max_cache_mb: usize,  // Moonshot #4: Memory limit for LRU
-    pub entries: Arc<RwLock<HashMap<u64, CacheEntry>>>,
+    /// Entries older than this are served as STALE and refreshed in the background (0 = never stale)
+    pub stale_after_secs: u64,
+    /// Hash .tex inputs with comments and insignificant whitespace removed
+    pub normalize_keys: bool,
+    /// How long a failing input is answered from `failures` (0 disables negative caching)
+    pub negative_ttl_secs: u64,
+    /// zstd level applied to stored PDFs (0 stores them uncompressed)
+    pub compression_level: i32,
+    pub failures: Arc<RwLock<HashMap<u64, FailureEntry>>>,
+    /// Index of cached PDFs, sharded so hits on different inputs never contend
+    pub entries: Arc<ShardedMap<u64, CacheEntry>>,
+    /// Sum of `size_bytes` over `entries`
+    total_bytes: Arc<AtomicUsize>,
+    /// Mixed into every key; see [`CompilationCache::set_bundle_seed`]
+    bundle_seed: Arc<AtomicU64>,
+    /// Seeds of the tenants' package registries; see [`CompilationCache::set_registry_seed`]
+    registry_seeds: Arc<std::sync::RwLock<HashMap<Option<String>, u64>>>,
+    /// Seeds of the healing policies; see [`CompilationCache::with_healer_seeds`]
+    healer_seeds: Arc<HashMap<Option<String>, u64>>,
+    /// Hashes with a background refresh in flight (`None`), so each is only recompiled
+    /// once, or whose last refresh failed at the given time, so a broken project is not
+    /// recompiled on every hit
+    pub revalidating: Arc<RwLock<HashMap<u64, Option<u64>>>>,
+    storage: Arc<dyn Storage>,
 }
 
+/// Bytes of cache metadata stored in front of each PDF: created_at and compile_time_ms.
+const CACHE_HEADER_LEN: usize = 16;
+
+/// Frame magic of zstd-compressed cache bodies; uncompressed ones start with `%PDF`.
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+/// Marks cache bodies that open with [`CompileNotes`]: the marker, the length of their JSON
+/// as a little-endian u32, then the JSON. Bodies without notes start with the PDF.
+const NOTES_MAGIC: [u8; 4] = *b"TXN1";
+
 impl CompilationCache {
-    pub fn new(enabled: bool) -> Self {
+    pub fn new(enabled: bool, storage: Arc<dyn Storage>) -> Self {
         Self {
             enabled,
+            storage,
             max_cache_mb: 512,  // 512MB default limit
-            entries: Arc::new(RwLock::new(HashMap::new())),
+            stale_after_secs: 0,
+            normalize_keys: false,
+            negative_ttl_secs: 0,
+            compression_level: 0,
+            failures: Arc::new(RwLock::new(HashMap::new())),
+            entries: Arc::new(ShardedMap::default()),
+            total_bytes: Arc::new(AtomicUsize::new(0)),
+            bundle_seed: Arc::new(AtomicU64::new(0)),
+            registry_seeds: Arc::new(std::sync::RwLock::new(HashMap::new())),
+            healer_seeds: Arc::new(HashMap::new()),
+            revalidating: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+
+    pub fn with_stale_after(mut self, secs: u64) -> Self {
+        self.stale_after_secs = secs;
+        self
+    }
+
+    pub fn with_normalized_keys(mut self, normalize: bool) -> Self {
+        self.normalize_keys = normalize;
+        self
+    }
+
+    pub fn with_negative_ttl(mut self, secs: u64) -> Self {
+        self.negative_ttl_secs = secs;
+        self
+    }
+
+    pub fn with_compression_level(mut self, level: i32) -> Self {
+        self.compression_level = level;
+        self
+    }
+
+    /// Keys inputs for the self-healing rules of the tenant, or of the default policy (the
+    /// `None` seed) for tenants without their own.
+    pub fn with_healer_seeds(mut self, seeds: HashMap<Option<String>, u64>) -> Self {
+        self.healer_seeds = Arc::new(seeds);
+        self
+    }
+
+    /// Starts a cache key for a set of uploaded files, compiled for the current tenant.
+    pub fn input_hasher(&self) -> InputHasher {
+        let bundle_seed = self.bundle_seed.load(Ordering::Relaxed);
+        let tenant = current_tenant();
+        let mut seed = match self.registry_seeds.read().unwrap().get(&tenant) {
+            Some(registry) => xxh64(&registry.to_le_bytes(), bundle_seed),
+            None => bundle_seed,
+        };
+        if let Some(healer) = self.healer_seeds.get(&tenant).or_else(|| self.healer_seeds.get(&None)) {
+            seed = xxh64(&healer.to_le_bytes(), seed);
+        }
+        InputHasher {
+            normalize_keys: self.normalize_keys,
+            seed,
+            files: BTreeMap::new(),
+            current: None,
         }
     }
 
-    pub fn hash_input(data: &[u8]) -> u64 {
-        xxh64(data, 0)
+    /// Keys inputs for the bundle compiles now use, so PDFs and failures of another bundle
+    /// are not served after a switch (and are again after a rollback).
+    pub fn set_bundle_seed(&self, seed: u64) {
+        self.bundle_seed.store(seed, Ordering::Relaxed);
     }
 
-    // Moonshot #1: Direct memory access - no fs::read, 10-50x faster
-    // Moonshot #4: LRU with 7-day TTL based on last access
-    pub async fn get_pdf(&self, hash: u64) -> Option<(Vec<u8>, u64)> {
+    /// Keys the tenant's inputs for the packages its registry holds now (`None`: no
+    /// packages), so PDFs built with other versions of its house styles are not served.
+    pub fn set_registry_seed(&self, tenant: Option<String>, seed: Option<u64>) {
+        let mut seeds = self.registry_seeds.write().unwrap();
+        match seed {
+            Some(seed) => seeds.insert(tenant, seed),
+            None => seeds.remove(&tenant),
+        };
+    }
+
+    /// Removes differences TeX itself ignores: comment text, leading/trailing
+    /// whitespace on a line, and runs of blank lines. The `%` marker is kept because
+    /// it suppresses the end-of-line space. Verbatim-like lines are left untouched.
+    pub fn normalize_tex(content: &str) -> String {
+        let mut out = String::with_capacity(content.len());
+        let mut verbatim_depth = 0usize;
+        let mut previous_blank = false;
+
+        for line in content.lines() {
+            let opens = VERBATIM_ENVIRONMENTS.iter().filter(|e| line.contains(&format!("\\begin{{{}}}", e))).count();
+            let closes = VERBATIM_ENVIRONMENTS.iter().filter(|e| line.contains(&format!("\\end{{{}}}", e))).count();
+            let literal = verbatim_depth > 0 || opens > 0 || line.contains("\\verb") || line.contains("\\lstinline");
+            verbatim_depth = (verbatim_depth + opens).saturating_sub(closes);
+
+            let normalized = if literal {
+                line
+            } else {
+                let code = crate::bib::strip_comment(line).len();
+                if code < line.len() {
+                    line[..=code].trim_start()
+                } else {
+                    line.trim()
+                }
+            };
+
+            let blank = normalized.is_empty();
+            if blank && previous_blank {
+                continue;
+            }
+            previous_blank = blank;
+            out.push_str(normalized);
+            out.push('\n');
+        }
+
+        out
+    }
+
+    fn storage_key(hash: u64) -> String {
+        format!("pdf/{:016x}", hash)
+    }
+
+    /// Returns `(pdf, original_compile_time_ms, stale, notes)`.
+    /// Entries missing from the in-memory index are read through from storage, so
+    /// durable backends keep serving hits across restarts.
+    /// Uncompressed entries are returned as a view into the stored object, without copying.
+    pub async fn get_pdf(&self, hash: u64) -> Option<(Bytes, u64, bool, CompileNotes)> {
         if !self.enabled { return None; }
 
-        let entries = self.entries.read().await;
-        if let Some(entry) = entries.get(&hash) {
-            let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
-            // Update last_accessed on every HIT for LRU
-            entry.last_accessed.store(now, Ordering::Relaxed);
-            // Return directly from memory - no fs::read!
-            return Some((entry.pdf_data.clone(), entry.compile_time_ms));
+        let stored = match self.storage.get(&Self::storage_key(hash)).await {
+            Ok(Some(stored)) if stored.len() >= CACHE_HEADER_LEN => stored,
+            Ok(_) => {
+                self.remove_entry(hash).await;
+                return None;
+            }
+            Err(e) => {
+                error!("PDF cache read failed: {}", e);
+                return None;
+            }
+        };
+        let created_at = u64::from_le_bytes(stored[..8].try_into().unwrap());
+        let compile_time_ms = u64::from_le_bytes(stored[8..CACHE_HEADER_LEN].try_into().unwrap());
+        let size_bytes = stored.len() - CACHE_HEADER_LEN;
+        let Some((notes, body)) = Self::split_notes(stored.slice(CACHE_HEADER_LEN..)) else {
+            error!("PDF cache entry {:016x} has corrupt notes", hash);
+            return None;
+        };
+        // Entries written with compression disabled (or by older versions) are plain PDFs
+        let pdf_data = if body.starts_with(&ZSTD_MAGIC) {
+            match zstd::stream::decode_all(&body[..]) {
+                Ok(pdf_data) => Bytes::from(pdf_data),
+                Err(e) => {
+                    error!("PDF cache entry {:016x} is corrupt: {}", hash, e);
+                    return None;
+                }
+            }
+        } else {
+            body.clone()
+        };
+
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+        // Update last_accessed on every HIT for LRU; the timestamp is atomic, so a
+        // shared lock suffices unless the entry was read through from storage
+        let touched = self.entries.shard(&hash).read().await.get(&hash)
+            .map(|entry| entry.last_accessed.store(now, Ordering::Relaxed))
+            .is_some();
+        if !touched {
+            self.insert_entry(hash, CacheEntry {
+                created_at,
+                last_accessed: AtomicU64::new(now),
+                compile_time_ms,
+                size_bytes,
+            }).await;
+        }
+        let stale = self.stale_after_secs > 0 && now.saturating_sub(created_at) >= self.stale_after_secs;
+        Some((pdf_data, compile_time_ms, stale, notes))
+    }
+
+    /// Splits the [`CompileNotes`] off the front of a stored body; `None` if they are
+    /// truncated or unreadable.
+    fn split_notes(body: Bytes) -> Option<(CompileNotes, Bytes)> {
+        let Some(rest) = body.strip_prefix(&NOTES_MAGIC) else { return Some((CompileNotes::default(), body)) };
+        let len = u32::from_le_bytes(rest.get(..4)?.try_into().unwrap()) as usize;
+        let notes = serde_json::from_slice(rest.get(4..4 + len)?).ok()?;
+        Some((notes, body.slice(NOTES_MAGIC.len() + 4 + len..)))
+    }
+
+    /// Returns the stored failure for an input that failed within the negative TTL.
+    pub async fn get_failure(&self, hash: u64) -> Option<FailureEntry> {
+        if !self.enabled || self.negative_ttl_secs == 0 { return None; }
+
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+        let failures = self.failures.read().await;
+        failures.get(&hash)
+            .filter(|f| now.saturating_sub(f.created_at) < self.negative_ttl_secs)
+            .cloned()
+    }
+
+    pub async fn put_failure(&self, hash: u64, error: &str, logs: &str) {
+        if !self.enabled || self.negative_ttl_secs == 0 { return; }
+
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+        self.failures.write().await.insert(hash, FailureEntry {
+            error: error.to_string(),
+            logs: logs.to_string(),
+            created_at: now,
+        });
+    }
+
+    /// Marks a hash as being refreshed. Returns `false` if a refresh is already running, or
+    /// the last one failed less than a stale period ago.
+    pub async fn begin_revalidation(&self, hash: u64) -> bool {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+        let mut revalidating = self.revalidating.write().await;
+        match revalidating.get(&hash) {
+            Some(None) => false,
+            Some(Some(failed_at)) if now.saturating_sub(*failed_at) < self.stale_after_secs => false,
+            _ => {
+                revalidating.insert(hash, None);
+                true
+            }
+        }
+    }
+
+    /// Ends a refresh; a failed one is remembered so the next is retried after a stale period.
+    pub async fn end_revalidation(&self, hash: u64, failed: bool) {
+        let mut revalidating = self.revalidating.write().await;
+        if failed {
+            revalidating.insert(hash, Some(SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs()));
+        } else {
+            revalidating.remove(&hash);
         }
-        None
     }
 
-    // Moonshot #1: Store PDF bytes directly in memory
-    pub async fn put_pdf(&self, hash: u64, pdf_data: &[u8], compile_time_ms: u64) {
+    pub async fn put_pdf(&self, hash: u64, pdf_data: &[u8], compile_time_ms: u64, notes: &CompileNotes) {
         if !self.enabled { return; }
 
         let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
-        let mut entries = self.entries.write().await;
-        
+        // A successful build supersedes any remembered failure (e.g. a remote asset came back)
+        self.failures.write().await.remove(&hash);
+
+        // Keep the compressed form only when it actually saves space
+        let compressed = (self.compression_level > 0)
+            .then(|| zstd::bulk::compress(pdf_data, self.compression_level).ok())
+            .flatten()
+            .filter(|compressed| compressed.len() < pdf_data.len());
+        let body = compressed.as_deref().unwrap_or(pdf_data);
+
+        let mut stored = Vec::with_capacity(CACHE_HEADER_LEN + body.len());
+        stored.extend_from_slice(&now.to_le_bytes());
+        stored.extend_from_slice(&compile_time_ms.to_le_bytes());
+        if *notes != CompileNotes::default() {
+            let json = serde_json::to_vec(notes).unwrap_or_default();
+            stored.extend_from_slice(&NOTES_MAGIC);
+            stored.extend_from_slice(&(json.len() as u32).to_le_bytes());
+            stored.extend_from_slice(&json);
+        }
+        stored.extend_from_slice(body);
+        let size_bytes = stored.len() - CACHE_HEADER_LEN;
+        if let Err(e) = self.storage.put(&Self::storage_key(hash), stored).await {
+            error!("PDF cache write failed: {}", e);
+            return;
+        }
+
         // Check memory limit and evict LRU if needed
-        let current_size: usize = entries.values().map(|e| e.size_bytes).sum();
-        if current_size + pdf_data.len() > self.max_cache_mb * 1024 * 1024 {
-            // Evict least recently accessed entry
-            if let Some((&lru_hash, _)) = entries.iter()
-                .min_by_key(|(_, e)| e.last_accessed.load(Ordering::Relaxed)) {
-                entries.remove(&lru_hash);
+        if self.total_bytes.load(Ordering::Relaxed) + size_bytes > self.max_cache_mb * 1024 * 1024 {
+            if let Some(lru_hash) = self.least_recently_used(hash).await {
+                if self.remove_entry(lru_hash).await {
+                    if let Err(e) = self.storage.delete(&Self::storage_key(lru_hash)).await {
+                        error!("PDF cache eviction failed: {}", e);
+                    }
+                }
             }
         }
-        
-        entries.insert(hash, CacheEntry {
-            pdf_data: pdf_data.to_vec(),
+
+        self.insert_entry(hash, CacheEntry {
             created_at: now,
             last_accessed: AtomicU64::new(now),
             compile_time_ms,
-            size_bytes: pdf_data.len(),
-        });
+            size_bytes,
+        }).await;
+    }
+
+    async fn insert_entry(&self, hash: u64, entry: CacheEntry) {
+        self.total_bytes.fetch_add(entry.size_bytes, Ordering::Relaxed);
+        if let Some(previous) = self.entries.shard(&hash).write().await.insert(hash, entry) {
+            self.total_bytes.fetch_sub(previous.size_bytes, Ordering::Relaxed);
+        }
+    }
+
+    /// Drops a hash from the index; returns whether it was present.
+    async fn remove_entry(&self, hash: u64) -> bool {
+        match self.entries.shard(&hash).write().await.remove(&hash) {
+            Some(entry) => {
+                self.total_bytes.fetch_sub(entry.size_bytes, Ordering::Relaxed);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Least recently accessed entry other than `except`, scanning one shard at a time.
+    async fn least_recently_used(&self, except: u64) -> Option<u64> {
+        let mut lru: Option<(u64, u64)> = None;
+        for shard in self.entries.shards() {
+            for (&hash, entry) in shard.read().await.iter() {
+                let accessed = entry.last_accessed.load(Ordering::Relaxed);
+                if hash != except && lru.is_none_or(|(_, oldest)| accessed < oldest) {
+                    lru = Some((hash, accessed));
+                }
+            }
+        }
+        lru.map(|(hash, _)| hash)
     }
 
     // Moonshot #4: LRU cleanup - only evict if not accessed in 7 days
     pub async fn cleanup_expired(&self) -> usize {
         let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
-        let mut entries = self.entries.write().await;
         let mut to_remove = Vec::new();
-
-        for (hash, entry) in entries.iter() {
-            // 7 days = 604800 seconds, based on last_accessed not created_at
-            if now - entry.last_accessed.load(Ordering::Relaxed) >= 604800 {
-                to_remove.push(*hash);
-            }
+        for shard in self.entries.shards() {
+            shard.write().await.retain(|hash, entry| {
+                // 7 days = 604800 seconds, based on last_accessed not created_at
+                let expired = now.saturating_sub(entry.last_accessed.load(Ordering::Relaxed)) >= 604800;
+                if expired {
+                    self.total_bytes.fetch_sub(entry.size_bytes, Ordering::Relaxed);
+                    to_remove.push(*hash);
+                }
+                !expired
+            });
         }
 
         let count = to_remove.len();
         for hash in to_remove {
-            entries.remove(&hash);
+            if let Err(e) = self.storage.delete(&Self::storage_key(hash)).await {
+                error!("PDF cache cleanup failed: {}", e);
+            }
         }
+
+        let ttl = self.negative_ttl_secs;
+        self.failures.write().await.retain(|_, f| now.saturating_sub(f.created_at) < ttl);
+        let backoff = self.stale_after_secs;
+        self.revalidating.write().await.retain(|_, failed_at| failed_at.is_none_or(|at| now.saturating_sub(at) < backoff));
         count
     }
 
     pub async fn stats(&self) -> (usize, usize) {
-        let entries = self.entries.read().await;
-        let total_size = entries.values().map(|e| e.size_bytes).sum();
-        (entries.len(), total_size)
+        (self.entries.len().await, self.total_bytes.load(Ordering::Relaxed))
     }
 }
 
@@ -152,18 +740,31 @@ impl CompilationCache {
 // HMR v2 Format Cache (Preamble tracking)
 // ============================================================================
 
+/// Tracks which preambles have been compiled (HMR status) and, when given shared storage,
+/// mirrors that registry and tectonic's format files so every replica benefits from the
+/// others' warmups. The local set is a read-through cache in front of the shared one.
 #[derive(Clone)]
 pub struct FormatCache {
     pub seen_preambles: Arc<RwLock<HashSet<u64>>>,
+    shared: Option<Arc<dyn Storage>>,
+    /// Format file names already known to be identical locally and in shared storage
+    synced_formats: Arc<RwLock<HashSet<String>>>,
 }
 
 impl FormatCache {
     pub fn new() -> Self {
         Self {
             seen_preambles: Arc::new(RwLock::new(HashSet::new())),
+            shared: None,
+            synced_formats: Arc::new(RwLock::new(HashSet::new())),
         }
     }
 
+    pub fn with_shared_storage(mut self, storage: Arc<dyn Storage>) -> Self {
+        self.shared = Some(storage);
+        self
+    }
+
     pub fn extract_preamble(content: &str) -> Option<&str> {
         content.find("\\begin{document}").map(|pos| &content[..pos])
     }
@@ -172,13 +773,418 @@ impl FormatCache {
         xxh64(preamble.as_bytes(), 0)
     }
 
+    /// Whether this replica has already built the preamble's format; unlike
+    /// `check_and_mark`, does not mark it.
+    pub async fn is_warm(&self, preamble_hash: u64) -> bool {
+        self.seen_preambles.read().await.contains(&preamble_hash)
+    }
+
     pub async fn check_and_mark(&self, preamble_hash: u64) -> bool {
-        let mut seen = self.seen_preambles.write().await;
-        if seen.contains(&preamble_hash) {
-            true // HIT
-        } else {
-            seen.insert(preamble_hash);
-            false // MISS
+        if self.seen_preambles.read().await.contains(&preamble_hash) {
+            return true; // HIT
+        }
+        let Some(shared) = &self.shared else {
+            return !self.seen_preambles.write().await.insert(preamble_hash);
+        };
+
+        let key = format!("preambles/{:016x}", preamble_hash);
+        let seen_elsewhere = match shared.get(&key).await {
+            Ok(marker) => marker.is_some(),
+            Err(e) => {
+                error!("Shared preamble registry read failed: {}", e);
+                false
+            }
+        };
+        if !seen_elsewhere {
+            if let Err(e) = shared.put(&key, Vec::new()).await {
+                error!("Shared preamble registry write failed: {}", e);
+            }
+        }
+        !self.seen_preambles.write().await.insert(preamble_hash) || seen_elsewhere
+    }
+
+    /// Downloads format files other replicas produced and uploads new local ones.
+    /// Returns `(pulled, pushed)`; a no-op without shared storage.
+    pub async fn sync_formats(&self, format_dir: &std::path::Path) -> (usize, usize) {
+        let Some(shared) = &self.shared else { return (0, 0) };
+        let remote = match shared.list("formats/").await {
+            Ok(keys) => keys,
+            Err(e) => {
+                error!("Format sync listing failed: {}", e);
+                return (0, 0);
+            }
+        };
+        let remote: HashSet<String> = remote.iter().filter_map(|k| k.strip_prefix("formats/")).map(str::to_string).collect();
+        let mut synced = self.synced_formats.write().await;
+        let (mut pulled, mut pushed) = (0, 0);
+
+        for name in &remote {
+            if synced.contains(name) {
+                continue;
+            }
+            let path = format_dir.join(name);
+            if path.exists() {
+                synced.insert(name.clone());
+                continue;
+            }
+            match shared.get(&format!("formats/{}", name)).await {
+                Ok(Some(data)) => {
+                    // Write then rename so a concurrent compile never loads a partial format
+                    let tmp = format_dir.join(format!(".{}.partial", name));
+                    let written = match tokio::fs::write(&tmp, &data).await {
+                        Ok(()) => tokio::fs::rename(&tmp, &path).await,
+                        Err(e) => Err(e),
+                    };
+                    match written {
+                        Ok(()) => {
+                            synced.insert(name.clone());
+                            pulled += 1;
+                        }
+                        Err(e) => {
+                            let _ = tokio::fs::remove_file(&tmp).await;
+                            error!("Format install of {} failed: {}", name, e);
+                        }
+                    }
+                }
+                Ok(None) => {}
+                Err(e) => error!("Format download of {} failed: {}", name, e),
+            }
+        }
+
+        let Ok(mut entries) = tokio::fs::read_dir(format_dir).await else { return (pulled, pushed) };
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            let name = entry.file_name().to_string_lossy().to_string();
+            if name.starts_with('.') || synced.contains(&name) || !entry.file_type().await.is_ok_and(|t| t.is_file()) {
+                continue;
+            }
+            if remote.contains(&name) {
+                synced.insert(name);
+                continue;
+            }
+            let Ok(data) = tokio::fs::read(entry.path()).await else { continue };
+            match shared.put(&format!("formats/{}", name), data).await {
+                Ok(()) => {
+                    synced.insert(name);
+                    pushed += 1;
+                }
+                Err(e) => error!("Format upload of {} failed: {}", name, e),
+            }
+        }
+        (pulled, pushed)
+    }
+}
+
+// ============================================================================
+// Compile History
+// ============================================================================
+
+/// Preambles whose calibration is kept; past this, new preambles are not tracked.
+pub const MAX_HISTORY_PREAMBLES: usize = 10_000;
+
+/// Per-preamble calibration of `POST /estimate`, learned from finished compiles.
+#[derive(Clone, Default)]
+pub struct CompileHistory {
+    preambles: Arc<RwLock<HashMap<u64, History>>>,
+}
+
+impl CompileHistory {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub async fn get(&self, preamble_hash: u64) -> Option<History> {
+        self.preambles.read().await.get(&preamble_hash).cloned()
+    }
+
+    /// Records a compile against the estimate the heuristics gave for it.
+    pub async fn record(&self, preamble_hash: u64, predicted: &EstimateResponse, compile_time_ms: u64, output_bytes: usize) {
+        let mut preambles = self.preambles.write().await;
+        if preambles.len() >= MAX_HISTORY_PREAMBLES && !preambles.contains_key(&preamble_hash) {
+            return;
+        }
+        let updated = History::record(preambles.get(&preamble_hash), predicted, compile_time_ms, output_bytes);
+        preambles.insert(preamble_hash, updated);
+    }
+}
+
+// ============================================================================
+// Projects
+// ============================================================================
+
+/// Imported projects, with their files in the blob store. Each project belongs to the
+/// tenant (`X-Tenant-Id`) that created it.
+#[derive(Clone, Default)]
+pub struct ProjectStore {
+    projects: Arc<RwLock<HashMap<String, OwnedProject>>>,
+}
+
+struct OwnedProject {
+    tenant: Option<String>,
+    project: Project,
+}
+
+impl ProjectStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub async fn insert(&self, tenant: Option<String>, project: Project) {
+        self.projects.write().await.insert(project.id.clone(), OwnedProject { tenant, project });
+    }
+
+    pub async fn get(&self, id: &str, tenant: Option<&str>) -> Option<Project> {
+        self.projects.read().await.get(id).filter(|p| p.tenant.as_deref() == tenant).map(|p| p.project.clone())
+    }
+
+    pub async fn set_build(&self, id: &str, build: ProjectBuild) {
+        if let Some(owned) = self.projects.write().await.get_mut(id) {
+            owned.project.last_build = Some(build);
+        }
+    }
+
+    pub async fn tenants(&self) -> Vec<String> {
+        self.projects.read().await.values().filter_map(|p| p.tenant.clone()).collect()
+    }
+
+    /// Removes the tenant's projects last used before `before` (all when `None`), returning how many.
+    pub async fn remove_tenant(&self, tenant: &str, before: Option<u64>) -> usize {
+        let mut projects = self.projects.write().await;
+        let count = projects.len();
+        projects.retain(|_, p| {
+            let last_used = p.project.last_build.as_ref().map_or(p.project.created_at, |b| b.compiled_at.max(p.project.created_at));
+            p.tenant.as_deref() != Some(tenant) || before.is_some_and(|b| last_used >= b)
+        });
+        count - projects.len()
+    }
+}
+
+// ============================================================================
+// Content Fingerprints
+// ============================================================================
+
+struct FingerprintEntry {
+    output_hash: String,
+    tenant: Option<String>,
+    fingerprint: Fingerprint,
+    indexed_at: u64,
+}
+
+/// Fingerprints of compiled outputs, searched for documents with overlapping text.
+/// Bounded by entry count; the oldest are evicted first.
+#[derive(Clone)]
+pub struct FingerprintIndex {
+    entries: Arc<RwLock<VecDeque<FingerprintEntry>>>,
+    max_entries: usize,
+}
+
+impl FingerprintIndex {
+    pub fn new(max_entries: usize) -> Self {
+        Self { entries: Arc::new(RwLock::new(VecDeque::new())), max_entries }
+    }
+
+    /// Indexes an output, replacing any earlier fingerprint of the same output and tenant.
+    pub async fn insert(&self, output_hash: &str, tenant: Option<String>, fingerprint: Fingerprint) {
+        let mut entries = self.entries.write().await;
+        entries.retain(|e| !(e.output_hash == output_hash && e.tenant == tenant));
+        entries.push_back(FingerprintEntry {
+            output_hash: output_hash.to_string(),
+            tenant,
+            fingerprint,
+            indexed_at: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs(),
+        });
+        while entries.len() > self.max_entries {
+            entries.pop_front();
+        }
+    }
+
+    /// Other outputs of the same tenant whose similarity to `output_hash` reaches
+    /// `threshold`, most similar first. `None` if the output was never indexed for the tenant.
+    pub async fn similar(&self, output_hash: &str, tenant: Option<&str>, threshold: f64, limit: usize) -> Option<Vec<SimilarDocument>> {
+        let entries = self.entries.read().await;
+        let same_tenant = |e: &&FingerprintEntry| e.tenant.as_deref() == tenant;
+        let target = &entries.iter().filter(same_tenant).find(|e| e.output_hash == output_hash)?.fingerprint;
+        let mut matches: Vec<SimilarDocument> = entries
+            .iter()
+            .filter(same_tenant)
+            .filter(|e| e.output_hash != output_hash)
+            .map(|e| SimilarDocument {
+                output_hash: e.output_hash.clone(),
+                similarity: target.similarity(&e.fingerprint),
+                simhash_distance: target.simhash_distance(&e.fingerprint),
+                indexed_at: e.indexed_at,
+            })
+            .filter(|m| m.similarity >= threshold)
+            .collect();
+        matches.sort_by(|a, b| b.similarity.total_cmp(&a.similarity));
+        matches.truncate(limit);
+        Some(matches)
+    }
+
+    /// Drops the tenant's fingerprints indexed before `before` (all when `None`), returning how many.
+    pub async fn remove_tenant(&self, tenant: &str, before: Option<u64>) -> usize {
+        let mut entries = self.entries.write().await;
+        let count = entries.len();
+        entries.retain(|e| e.tenant.as_deref() != Some(tenant) || before.is_some_and(|b| e.indexed_at >= b));
+        count - entries.len()
+    }
+}
+
+// ============================================================================
+// Webhook Dead Letters
+// ============================================================================
+
+/// Dead letters kept per subscription; the oldest are dropped beyond this.
+pub const MAX_DEAD_LETTERS_PER_WEBHOOK: usize = 100;
+
+#[derive(Clone, Default)]
+pub struct DeadLetterStore {
+    letters: Arc<RwLock<HashMap<String, VecDeque<DeadLetter>>>>,
+}
+
+impl DeadLetterStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub async fn push(&self, letter: DeadLetter) {
+        let mut letters = self.letters.write().await;
+        let queue = letters.entry(letter.webhook_id.clone()).or_default();
+        if queue.len() >= MAX_DEAD_LETTERS_PER_WEBHOOK {
+            queue.pop_front();
+        }
+        queue.push_back(letter);
+    }
+
+    pub async fn list(&self, webhook_id: &str) -> Vec<DeadLetter> {
+        self.letters.read().await.get(webhook_id).map(|q| q.iter().cloned().collect()).unwrap_or_default()
+    }
+
+    pub async fn get(&self, webhook_id: &str, letter_id: &str) -> Option<DeadLetter> {
+        self.letters.read().await.get(webhook_id)?.iter().find(|l| l.id == letter_id).cloned()
+    }
+
+    /// Replaces a stored letter (e.g. after a failed redelivery); no-op if it was removed meanwhile.
+    pub async fn update(&self, letter: DeadLetter) {
+        if let Some(queue) = self.letters.write().await.get_mut(&letter.webhook_id) {
+            if let Some(existing) = queue.iter_mut().find(|l| l.id == letter.id) {
+                *existing = letter;
+            }
+        }
+    }
+
+    pub async fn remove(&self, webhook_id: &str, letter_id: &str) {
+        if let Some(queue) = self.letters.write().await.get_mut(webhook_id) {
+            queue.retain(|l| l.id != letter_id);
+        }
+    }
+
+    pub async fn clear(&self, webhook_id: &str) {
+        self.letters.write().await.remove(webhook_id);
+    }
+}
+
+// ============================================================================
+// Compile Scheduler (Priority Classes)
+// ============================================================================
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Priority {
+    /// Live preview and editor-driven compiles
+    Interactive,
+    /// Background jobs (bulk document generation, scheduled reports)
+    Batch,
+}
+
+impl Priority {
+    /// Parses an `X-Priority` style value; anything unknown is interactive.
+    pub fn parse(value: Option<&str>) -> Self {
+        match value.map(|v| v.trim().to_ascii_lowercase()) {
+            Some(v) if v == "batch" => Priority::Batch,
+            _ => Priority::Interactive,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Priority::Interactive => "interactive",
+            Priority::Batch => "batch",
+        }
+    }
+}
+
+/// Held for the duration of a compile; releases its slots on drop.
+pub struct CompilePermit {
+    _slot: OwnedSemaphorePermit,
+    _class: OwnedSemaphorePermit,
+}
+
+/// Bounds concurrent compiles. Each priority class may use its configured share of
+/// the slots, and queued batch jobs yield any freed slot to waiting interactive compiles.
+#[derive(Clone)]
+pub struct CompileScheduler {
+    slots: Arc<Semaphore>,
+    interactive_slots: Arc<Semaphore>,
+    batch_slots: Arc<Semaphore>,
+    /// Interactive compiles holding their class permit and queued for a slot
+    interactive_waiting: Arc<AtomicUsize>,
+    /// Signalled when the last of them stops waiting
+    interactive_idle: Arc<Notify>,
+}
+
+/// Counts an interactive compile as queued for a slot until dropped, including when the
+/// caller stops waiting.
+struct InteractiveWaiting<'a>(&'a CompileScheduler);
+
+impl Drop for InteractiveWaiting<'_> {
+    fn drop(&mut self) {
+        if self.0.interactive_waiting.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.0.interactive_idle.notify_waiters();
+        }
+    }
+}
+
+impl CompileScheduler {
+    pub fn new(settings: &Settings) -> Self {
+        let total = settings.compile_concurrency;
+        let share = |fraction: f64| ((total as f64 * fraction).floor() as usize).clamp(1, total);
+        Self {
+            slots: Arc::new(Semaphore::new(total)),
+            interactive_slots: Arc::new(Semaphore::new(share(settings.interactive_share))),
+            batch_slots: Arc::new(Semaphore::new(share(settings.batch_share))),
+            interactive_waiting: Arc::new(AtomicUsize::new(0)),
+            interactive_idle: Arc::new(Notify::new()),
+        }
+    }
+
+    pub async fn acquire(&self, priority: Priority) -> CompilePermit {
+        match priority {
+            Priority::Interactive => {
+                // Only compiles within their class share wait for a slot, so only they hold batch back
+                let class = self.interactive_slots.clone().acquire_owned().await.expect("scheduler closed");
+                self.interactive_waiting.fetch_add(1, Ordering::SeqCst);
+                let _waiting = InteractiveWaiting(self);
+                let slot = self.slots.clone().acquire_owned().await.expect("scheduler closed");
+                CompilePermit { _slot: slot, _class: class }
+            }
+            Priority::Batch => {
+                let class = self.batch_slots.clone().acquire_owned().await.expect("scheduler closed");
+                loop {
+                    let idle = self.interactive_idle.notified();
+                    tokio::pin!(idle);
+                    // Registered before the check, so a wakeup in between is not missed
+                    idle.as_mut().enable();
+                    if self.interactive_waiting.load(Ordering::SeqCst) > 0 {
+                        idle.await;
+                        continue;
+                    }
+                    let slot = self.slots.clone().acquire_owned().await.expect("scheduler closed");
+                    if self.interactive_waiting.load(Ordering::SeqCst) == 0 {
+                        return CompilePermit { _slot: slot, _class: class };
+                    }
+                    // Hand the slot back so the (fair) semaphore passes it to the interactive waiter
+                    drop(slot);
+                }
+            }
         }
     }
 }
@@ -191,8 +1197,272 @@ impl FormatCache {
 pub struct AppState {
     pub compilation_cache: CompilationCache,
     pub webhooks: Arc<RwLock<Vec<WebhookSubscription>>>,
+    pub dead_letters: DeadLetterStore,
     pub format_cache: FormatCache,
     pub blob_store: BlobStore,
+    pub uploads: crate::uploads::UploadStore,
+    pub projects: ProjectStore,
+    /// Compiles started with POST /compile/async
+    pub jobs: crate::jobs::JobStore,
+    /// Pending ACME HTTP-01 challenges, answered at /.well-known/acme-challenge/{token}
+    pub acme_challenges: crate::acme::Challenges,
+    /// Snippets shared with POST /playground
+    pub playground: crate::playground::PlaygroundStore,
+    /// Failed compiles shared with ?share_failure=true
+    pub failures: crate::failures::FailureStore,
+    /// Template regression tests registered with POST /tests
+    pub regression: crate::regression::RegressionStore,
+    /// Recurring project builds registered with POST /schedules
+    pub schedules: crate::schedules::ScheduleStore,
+    /// Projects' data connectors, with their credentials
+    pub connectors: crate::connectors::ConnectorStore,
+    /// Template packs synced from Git, listed at /templates
+    pub packs: crate::packs::PackStore,
+    /// Per-tenant class and style files put on every compile's search path
+    pub registry: crate::registry::PackageRegistry,
+    /// Active, staged and previous TeX bundles, switched through /admin/bundles
+    pub bundles: crate::bundles::BundleManager,
+    /// Tenants' signing certificates for `sign=true`, set through /admin/signing
+    pub signing: crate::signing::SigningStore,
+    pub output_store: OutputStore,
+    pub branding: BrandingStore,
+    pub fingerprints: FingerprintIndex,
+    pub compile_history: CompileHistory,
+    pub receipt_signer: ReceiptSigner,
+    /// Stored objects written by each tenant, for retention and erasure
+    pub ledger: crate::tenancy::TenantLedger,
+    /// The storage behind every store, for deleting objects directly
+    pub storage: Arc<dyn Storage>,
+    /// Confinement for external programs
+    pub sandbox: crate::sandbox::Sandbox,
+    pub scheduler: CompileScheduler,
+    pub janitor: crate::janitor::Janitor,
+    /// Remote compile workers; `None` compiles in-process
+    pub workers: Option<Arc<WorkerPool>>,
+    pub settings: Arc<Settings>,
     pub config: Arc<tectonic::config::PersistentConfig>,
     pub format_cache_path: PathBuf,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_shared_format_cache_across_replicas() {
+        let shared: Arc<dyn Storage> = Arc::new(crate::storage::MemoryStorage::new());
+        let a = FormatCache::new().with_shared_storage(shared.clone());
+        let b = FormatCache::new().with_shared_storage(shared);
+        assert!(!a.check_and_mark(42).await);
+        assert!(a.check_and_mark(42).await);
+        assert!(b.check_and_mark(42).await, "replica b sees a's warmup");
+
+        let (dir_a, dir_b) = (tempfile::TempDir::new().unwrap(), tempfile::TempDir::new().unwrap());
+        std::fs::write(dir_a.path().join("latex.fmt"), b"format").unwrap();
+        assert_eq!(a.sync_formats(dir_a.path()).await, (0, 1));
+        assert_eq!(b.sync_formats(dir_b.path()).await, (1, 0));
+        assert_eq!(std::fs::read(dir_b.path().join("latex.fmt")).unwrap(), b"format");
+        assert_eq!(a.sync_formats(dir_a.path()).await, (0, 0));
+    }
+
+    #[tokio::test]
+    async fn test_output_store_is_content_addressed() {
+        let storage: Arc<dyn Storage> = Arc::new(crate::storage::MemoryStorage::new());
+        let store = OutputStore::new(10, storage.clone());
+        let hash = store.put(b"%PDF-1.5").await;
+        assert_eq!((hash.len(), hash.clone()), (64, crate::receipt::sha256_hex(b"%PDF-1.5")));
+        assert_eq!(store.get(&hash).await.as_deref(), Some(&b"%PDF-1.5"[..]));
+
+        // After a restart the stored output is found and left as it is
+        storage.put(&format!("outputs/{}", hash), b"%PDF-1.5 kept".to_vec()).await.unwrap();
+        let restarted = OutputStore::new(10, storage.clone());
+        assert_eq!(restarted.put(b"%PDF-1.5").await, hash);
+        assert_eq!(restarted.get(&hash).await.as_deref(), Some(&b"%PDF-1.5 kept"[..]));
+
+        // The index is rebuilt at startup, so old outputs still count towards the limit
+        let small = OutputStore { max_bytes: 20, ..OutputStore::new(0, storage.clone()) };
+        assert_eq!(small.load().await, 1);
+        let newer = small.put(b"%PDF-1.7 newer").await;
+        assert!(small.get(&hash).await.is_none(), "the older output is evicted");
+        assert!(small.get(&newer).await.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_compressed_cache_entries() {
+        let storage: Arc<dyn Storage> = Arc::new(crate::storage::MemoryStorage::new());
+        let pdf = b"%PDF-1.5\n".repeat(1000);
+        let plain = CompilationCache::new(true, storage.clone());
+        plain.put_pdf(1, &pdf, 10, &CompileNotes::default()).await;
+        let compressed = CompilationCache::new(true, storage).with_compression_level(3);
+        compressed.put_pdf(2, &pdf, 20, &CompileNotes::default()).await;
+
+        assert!(compressed.stats().await.1 < pdf.len() / 10);
+        assert_eq!(compressed.get_pdf(2).await.map(|(data, ms, _, _)| (data.to_vec(), ms)), Some((pdf.clone(), 20)));
+        // Uncompressed entries from before the setting was enabled still read back
+        assert_eq!(compressed.get_pdf(1).await.map(|(data, _, _, _)| data.to_vec()), Some(pdf));
+    }
+
+    #[tokio::test]
+    async fn test_cache_entries_keep_notes() {
+        let cache = CompilationCache::new(true, Arc::new(crate::storage::MemoryStorage::new())).with_compression_level(3);
+        let pdf = b"%PDF-1.5\n".repeat(1000);
+        let notes = CompileNotes::from_log(include_str!("../tests/logs/warnings.log"));
+        assert!(!notes.warnings.is_empty());
+        cache.put_pdf(1, &pdf, 10, &notes).await;
+        cache.put_pdf(2, &pdf, 10, &CompileNotes::default()).await;
+
+        let (data, _, _, cached) = cache.get_pdf(1).await.unwrap();
+        assert_eq!((data.to_vec(), cached), (pdf.clone(), notes));
+        assert_eq!(cache.get_pdf(2).await.map(|(_, _, _, notes)| notes), Some(CompileNotes::default()));
+    }
+
+    #[tokio::test]
+    async fn test_failed_revalidation_backs_off() {
+        let cache = CompilationCache::new(true, Arc::new(crate::storage::MemoryStorage::new())).with_stale_after(60);
+        assert!(cache.begin_revalidation(1).await);
+        assert!(!cache.begin_revalidation(1).await, "a refresh is already running");
+        cache.end_revalidation(1, true).await;
+        assert!(!cache.begin_revalidation(1).await, "the refresh just failed");
+
+        cache.revalidating.write().await.insert(1, Some(0));
+        assert!(cache.begin_revalidation(1).await, "retried a stale period later");
+        cache.end_revalidation(1, false).await;
+        assert!(cache.begin_revalidation(1).await);
+    }
+
+    #[tokio::test]
+    async fn test_cache_entries_keep_healing_fixes() {
+        let cache = CompilationCache::new(true, Arc::new(crate::storage::MemoryStorage::new()));
+        let logs = format!("! Emergency stop.\n{}", crate::healer::SelfHealer::fixes_log_line(&["missing_end_document", "undefined_command"]));
+        cache.put_pdf(1, b"%PDF-1.5", 10, &CompileNotes::from_log(&logs)).await;
+
+        let (_, _, _, notes) = cache.get_pdf(1).await.unwrap();
+        assert_eq!(notes.self_healed, Some(vec!["missing_end_document".to_string(), "undefined_command".to_string()]));
+        // Notes stored before fixes were kept read back as an unhealed compile
+        assert_eq!(serde_json::from_str::<CompileNotes>(r#"{"warnings":[]}"#).unwrap(), CompileNotes::default());
+    }
+
+    #[tokio::test]
+    async fn test_sharded_index_evicts_least_recently_used() {
+        let mut cache = CompilationCache::new(true, Arc::new(crate::storage::MemoryStorage::new()));
+        cache.max_cache_mb = 1;
+        let pdf = |seed: u64| (0..400_000u64).map(|i| xxh64(&i.to_le_bytes(), seed) as u8).collect::<Vec<u8>>();
+        cache.put_pdf(1, &pdf(1), 0, &CompileNotes::default()).await;
+        cache.put_pdf(2, &pdf(2), 0, &CompileNotes::default()).await;
+        cache.entries.shard(&1).read().await[&1].last_accessed.store(0, Ordering::Relaxed);
+        cache.put_pdf(3, &pdf(3), 0, &CompileNotes::default()).await;
+
+        assert_eq!(cache.stats().await, (2, 800_000));
+        assert!(cache.get_pdf(1).await.is_none(), "oldest entry was evicted");
+        assert!(cache.get_pdf(2).await.is_some() && cache.get_pdf(3).await.is_some());
+    }
+
+    #[test]
+    fn test_input_hasher_keys() {
+        let cache = CompilationCache::new(true, Arc::new(crate::storage::MemoryStorage::new()));
+        let key = |files: &[(&str, &[u8])]| {
+            let mut hasher = cache.input_hasher();
+            for (name, data) in files {
+                hasher.add_file(name, data);
+            }
+            hasher.finish("main.tex")
+        };
+        let project = key(&[("main.tex", b"doc"), ("fig.png", b"png")]);
+        assert_eq!(project, key(&[("fig.png", b"png"), ("main.tex", b"doc")]), "upload order is irrelevant");
+        assert_ne!(project, key(&[("main.tex", b"doc"), ("logo.png", b"png")]), "names are part of the key");
+        assert_ne!(project, key(&[("main.tex", b"docf"), ("ig.png", b"png")]));
+
+        let mut streamed = cache.input_hasher();
+        streamed.add_file("main.tex", b"doc");
+        streamed.begin_file("fig.png");
+        streamed.update(b"p");
+        streamed.update(b"ng");
+        assert_eq!(streamed.finish("main.tex"), project);
+
+        // The same name uploaded twice is overwritten on disk, so only the last copy counts
+        let mut duplicate = cache.input_hasher();
+        duplicate.add_file("fig.png", b"old");
+        duplicate.add_file("./main.tex", b"doc");
+        duplicate.add_file("fig.png", b"png");
+        assert_eq!(duplicate.finish("main.tex"), project);
+    }
+
+    #[test]
+    fn test_normalize_tex_ignores_comments_and_whitespace() {
+        let a = "\\section{Intro}   \nHello % draft note\n\n\n  World%\n";
+        let b = "\\section{Intro}\nHello % another note\n\nWorld%\n";
+        assert_eq!(CompilationCache::normalize_tex(a), CompilationCache::normalize_tex(b));
+    }
+
+    #[test]
+    fn test_normalize_tex_escaped_percent() {
+        let normalize = CompilationCache::normalize_tex;
+        // `\\%` is a line break followed by a comment
+        assert_eq!(normalize("a\\\\% note\n"), "a\\\\%\n");
+        assert_eq!(normalize("a\\\\% note\n"), normalize("a\\\\% other note\n"));
+        // `\\\%` is a line break followed by a literal percent sign
+        assert_eq!(normalize("50\\\\\\% done  \n"), "50\\\\\\% done\n");
+        assert_ne!(normalize("50\\\\\\% done\n"), normalize("50\\\\\\% gone\n"));
+        assert_eq!(normalize("50\\% done\n"), "50\\% done\n");
+    }
+
+    #[test]
+    fn test_normalize_tex_keeps_verbatim() {
+        let a = "\\begin{verbatim}\n  x % y\n\\end{verbatim}\n\\verb|%a|\n";
+        let b = "\\begin{verbatim}\n  x % z\n\\end{verbatim}\n\\verb|%b|\n";
+        assert_ne!(CompilationCache::normalize_tex(a), CompilationCache::normalize_tex(b));
+        assert!(CompilationCache::normalize_tex(a).contains("  x % y"));
+    }
+
+    fn scheduler(total: usize, interactive_share: f64) -> CompileScheduler {
+        CompileScheduler::new(&Settings { compile_concurrency: total, interactive_share, batch_share: 1.0, ..Settings::from_env() })
+    }
+
+    #[tokio::test]
+    async fn test_batch_yields_to_queued_interactive() {
+        let scheduler = scheduler(1, 1.0);
+        let running = scheduler.acquire(Priority::Batch).await;
+        let (batch, interactive) = (scheduler.clone(), scheduler.clone());
+        let batch = tokio::spawn(async move { batch.acquire(Priority::Batch).await });
+        tokio::task::yield_now().await;
+        let interactive = tokio::spawn(async move { interactive.acquire(Priority::Interactive).await });
+        tokio::task::yield_now().await;
+
+        drop(running);
+        let permit = tokio::time::timeout(std::time::Duration::from_secs(1), interactive).await.expect("interactive got the slot").unwrap();
+        assert!(!batch.is_finished(), "the queued batch job waits for the interactive compile");
+        drop(permit);
+        tokio::time::timeout(std::time::Duration::from_secs(1), batch).await.expect("batch runs afterwards").unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_batch_proceeds_when_interactive_is_capped() {
+        // One of two slots for interactive compiles: a second one waits on its share, not a slot
+        let scheduler = scheduler(2, 0.5);
+        let _running = scheduler.acquire(Priority::Interactive).await;
+        let capped = scheduler.clone();
+        let capped = tokio::spawn(async move { capped.acquire(Priority::Interactive).await });
+        tokio::task::yield_now().await;
+
+        tokio::time::timeout(std::time::Duration::from_secs(1), scheduler.acquire(Priority::Batch)).await.expect("batch takes the idle slot");
+        assert!(!capped.is_finished());
+    }
+
+    #[tokio::test]
+    async fn test_fingerprint_index_scoped_to_tenant() {
+        let text = "one two three four five six seven eight nine ten eleven twelve thirteen fourteen fifteen sixteen seventeen eighteen nineteen twenty";
+        let fp = Fingerprint::of_text(text).unwrap();
+        let index = FingerprintIndex::new(3);
+        index.insert("a", Some("acme".into()), fp.clone()).await;
+        index.insert("b", Some("acme".into()), fp.clone()).await;
+        index.insert("c", Some("other".into()), fp.clone()).await;
+
+        let matches = index.similar("a", Some("acme"), 0.5, 10).await.unwrap();
+        assert_eq!(matches.iter().map(|m| m.output_hash.as_str()).collect::<Vec<_>>(), ["b"]);
+        assert_eq!((matches[0].similarity, matches[0].simhash_distance), (1.0, 0));
+        assert!(index.similar("a", Some("other"), 0.5, 10).await.is_none());
+
+        index.insert("d", None, fp).await;
+        assert!(index.similar("a", Some("acme"), 0.5, 10).await.is_none(), "oldest entry evicted");
+    }
+}
@@ -0,0 +1,363 @@
+use std::path::PathBuf;
+use std::str::FromStr;
+
+/// Runtime configuration, read once from environment variables at startup.
+#[derive(Clone, Debug)]
+pub struct Settings {
+    /// PDF_CACHE_ENABLED: serve repeated inputs from the in-memory PDF cache
+    pub pdf_cache_enabled: bool,
+    /// STALE_WHILE_REVALIDATE_SECS: age after which cached PDFs are served stale
+    /// and recompiled in the background (0 disables the mode)
+    pub stale_while_revalidate_secs: u64,
+    /// CACHE_NORMALIZE_KEYS: ignore comments and whitespace in .tex files when hashing
+    pub cache_normalize_keys: bool,
+    /// CACHE_COMPRESSION_LEVEL: zstd level for cached PDFs, 1-22 (0 stores them uncompressed)
+    pub cache_compression_level: i32,
+    /// NEGATIVE_CACHE_TTL_SECS: how long failing inputs are answered from cache (0 disables)
+    pub negative_cache_ttl_secs: u64,
+    /// OUTPUT_STORE_MAX_MB: memory budget for PDFs retrievable via GET /outputs/:hash
+    pub output_store_max_mb: usize,
+    /// FINGERPRINT_MAX_ENTRIES: content fingerprints kept for GET /similar/:hash
+    pub fingerprint_max_entries: usize,
+    /// BODY_LIMIT_MB: largest request body accepted
+    pub body_limit_mb: u64,
+    /// ROUTE_BODY_LIMITS_MB: comma-separated `path=MB` overrides of BODY_LIMIT_MB
+    /// (e.g. `/compile=200,/convert=20`); the longest matching path prefix wins
+    pub route_body_limits_mb: Vec<(String, u64)>,
+    /// TENANT_BODY_LIMITS_MB: comma-separated `tenant=MB` limits for requests carrying
+    /// `X-Tenant-Id`, taking precedence over route limits
+    pub tenant_body_limits_mb: Vec<(String, u64)>,
+    /// ALLOWED_FILE_TYPES: comma-separated extensions accepted in compile uploads and imports
+    /// (`*` accepts any); executables and scripts are refused regardless
+    pub allowed_file_types: Vec<String>,
+    /// UPLOAD_MAX_MB: largest resumable upload accepted by POST /uploads
+    pub upload_max_mb: u64,
+    /// UPLOAD_TTL_SECS: how long a resumable upload may take before it is discarded
+    pub upload_ttl_secs: u64,
+    /// COMPILE_CONCURRENCY: maximum number of compiles running at once
+    pub compile_concurrency: usize,
+    /// INTERACTIVE_CONCURRENCY_SHARE: fraction of compile slots live-preview compiles may use
+    pub interactive_share: f64,
+    /// BATCH_CONCURRENCY_SHARE: fraction of compile slots background compiles may use
+    pub batch_share: f64,
+    /// ASYNC_MAX_JOBS: async compiles queued or running at once; POST /compile/async answers
+    /// 503 beyond it
+    pub async_max_jobs: usize,
+    /// WEBHOOK_MAX_ATTEMPTS: delivery attempts before a webhook event is dead-lettered
+    pub webhook_max_attempts: u32,
+    /// WEBHOOK_ALLOWED_HOSTS: comma-separated hosts (`*.example.com` for subdomains) webhooks
+    /// may target; when set, only these are accepted and they may resolve to private addresses
+    pub webhook_allowed_hosts: Vec<String>,
+    /// WEBHOOK_ALLOWED_PORTS: comma-separated destination ports (empty allows any)
+    pub webhook_allowed_ports: Vec<u16>,
+    /// WEBHOOK_ALLOW_PRIVATE: permit loopback, private and link-local webhook targets
+    pub webhook_allow_private: bool,
+    /// WEBHOOK_REQUIRE_HTTPS: reject plain http:// webhook URLs
+    pub webhook_require_https: bool,
+    /// RECEIPT_SIGNING_KEY: hex Ed25519 seed for compile receipts; when unset, one is
+    /// generated on first start and kept in storage
+    pub receipt_signing_key: Option<String>,
+    /// BUNDLE_VERSION: TeX bundle identifier recorded in compile receipts; the bundle used
+    /// until another is activated through /admin/bundles
+    pub bundle_version: String,
+    /// BUNDLE_URL: URL of that bundle (default: the one the Tectonic config names)
+    pub bundle_url: Option<String>,
+    /// BUNDLE_URL_TEMPLATE: where numbered bundle releases are found, `{version}` standing
+    /// for the number; probed by GET /admin/bundles/updates
+    pub bundle_url_template: String,
+    /// ADMIN_TOKEN: bearer token for the /admin endpoints, which are disabled when unset
+    pub admin_token: Option<String>,
+    /// PRINT_PROFILES_DIR: CMYK ICC profiles (`<name>.icc`) selectable with `print_profile`
+    pub print_profiles_dir: PathBuf,
+    /// STORAGE_BACKEND: where cached PDFs, blobs and outputs live: memory, disk or s3
+    pub storage_backend: String,
+    /// STORAGE_DIR: root directory for the disk backend
+    pub storage_dir: PathBuf,
+    /// S3_BUCKET / S3_REGION / S3_ENDPOINT / S3_PREFIX: target for the s3 backend
+    /// (S3_ENDPOINT selects S3-compatible services such as MinIO)
+    pub s3_bucket: Option<String>,
+    pub s3_region: String,
+    pub s3_endpoint: Option<String>,
+    pub s3_prefix: String,
+    /// S3_ACCESS_KEY_ID / S3_SECRET_ACCESS_KEY, falling back to the AWS_* variables
+    pub s3_access_key_id: Option<String>,
+    pub s3_secret_access_key: Option<String>,
+    /// STORAGE_ENCRYPTION_KEY: hex AES-256 key sealing stored objects (unencrypted when unset)
+    pub storage_encryption_key: Option<String>,
+    /// SIGNING_ENCRYPTION_KEY: hex AES-256 key sealing stored signing credentials
+    /// (default: STORAGE_ENCRYPTION_KEY; without either, signers cannot be configured)
+    pub signing_encryption_key: Option<String>,
+    /// TENANT_TOKENS: comma-separated `tenant=token` pairs; a request naming a tenant in
+    /// `X-Tenant-Id` must carry that tenant's token in `X-Tenant-Token`, and tenants not
+    /// listed are refused
+    pub tenant_tokens: Vec<(String, String)>,
+    /// TENANT_ENCRYPTION_KEYS: comma-separated `tenant=hexkey` pairs; objects written by a
+    /// request with that `X-Tenant-Id` are sealed with the tenant's key instead, except
+    /// content-addressed ones (blobs, outputs, formats, cached PDFs)
+    pub tenant_encryption_keys: Vec<(String, String)>,
+    /// ENCRYPTION_KEYS_COMMAND: shell command run at startup (e.g. a KMS client) printing
+    /// `tenant=hexkey` lines, `default=hexkey` for the server key
+    pub encryption_keys_command: Option<String>,
+    /// RETENTION_DAYS: days a tenant's projects, fingerprints and stored objects are kept
+    /// after their last use (0 keeps them until evicted)
+    pub retention_days: u64,
+    /// TENANT_RETENTION_DAYS: comma-separated `tenant=days` overrides of RETENTION_DAYS
+    pub tenant_retention_days: Vec<(String, u64)>,
+    /// SHARED_FORMAT_CACHE: share the preamble registry and format files through the storage
+    /// backend, so replicas behind a load balancer reuse each other's warmups
+    pub shared_format_cache: bool,
+    /// FORMAT_SYNC_INTERVAL_SECS: how often format files are exchanged with shared storage
+    pub format_sync_interval_secs: u64,
+    /// COMPILE_WORKERS: comma-separated worker base URLs; when set, compiles run remotely
+    pub compile_workers: Vec<String>,
+    /// WORKER_TOKEN: bearer token shared by controller and workers; setting it on an
+    /// instance enables its /internal/worker/compile endpoint
+    pub worker_token: Option<String>,
+    /// WORKER_FALLBACK_LOCAL: compile locally when no worker is reachable
+    pub worker_fallback_local: bool,
+    /// GRPC_PORT: port for the gRPC API (0 disables it)
+    pub grpc_port: u16,
+    /// GRPC_HOST: address the gRPC API binds to. It has no authentication of its own, so it
+    /// listens on loopback unless pointed elsewhere (e.g. `0.0.0.0` behind a trusted network)
+    pub grpc_host: std::net::IpAddr,
+    /// LISTEN: comma-separated HTTP listeners: `host:port` (`[::]:port` or a bare port for
+    /// IPv6 and IPv4), `https://host:port` terminated with TLS, or `unix:/path/to.sock`
+    pub listen: Vec<String>,
+    /// TLS_CERT_FILE: PEM certificate chain for `https://` listeners
+    pub tls_cert_file: Option<String>,
+    /// TLS_KEY_FILE: PEM private key for `https://` listeners
+    pub tls_key_file: Option<String>,
+    /// UNIX_SOCKET_MODE: permissions of Unix socket listeners, in octal
+    pub unix_socket_mode: u32,
+    /// ACME_DOMAINS: comma-separated domains to obtain a certificate for over ACME instead
+    /// of TLS_CERT_FILE / TLS_KEY_FILE (needs a listener on port 80 for HTTP-01)
+    pub acme_domains: Vec<String>,
+    /// ACME_EMAIL: contact address for the ACME account
+    pub acme_email: Option<String>,
+    /// ACME_DIRECTORY: directory URL of the ACME CA
+    pub acme_directory: String,
+    /// ACME_CACHE_DIR: where the ACME account key and certificates are kept
+    pub acme_cache_dir: String,
+    /// FRONTEND_ENABLED: serve the browser editor and live preview at `/`
+    pub frontend_enabled: bool,
+    /// PUBLIC_URL: external base URL of the server for links it hands out; links are
+    /// relative to the server otherwise
+    pub public_url: Option<String>,
+    /// PLAYGROUND_TTL_SECS: how long shared playground snippets are kept by default
+    pub playground_ttl_secs: u64,
+    /// PLAYGROUND_MAX_TTL_SECS: longest lifetime a snippet may ask for
+    pub playground_max_ttl_secs: u64,
+    /// PLAYGROUND_MAX_KB: size limit of a playground snippet's files
+    pub playground_max_kb: u64,
+    /// SHARES_PER_HOUR: playground snippets, and separately shared failures, each client
+    /// (tenant, else IP address) may create per hour (0 disables the limit)
+    pub shares_per_hour: u64,
+    /// FAILURE_SHARE_TTL_SECS: how long failures shared with `?share_failure=true` are kept
+    pub failure_share_ttl_secs: u64,
+    /// FAILURE_SHARE_MAX_KB: size limit of the sources kept with a shared failure
+    pub failure_share_max_kb: u64,
+    /// GRADE_MAX_SUBMISSIONS: most submissions one POST /grade/batch may carry
+    pub grade_max_submissions: usize,
+    /// GRADE_SUBMISSION_MAX_MB: size limit of a graded submission's extracted files
+    pub grade_submission_max_mb: u64,
+    /// GRADE_TIMEOUT_SECS: longest a graded submission may compile for
+    pub grade_timeout_secs: u64,
+    /// GRADE_CONCURRENCY: submissions of a batch compiled at the same time
+    pub grade_concurrency: usize,
+    /// REGRESSION_INTERVAL_SECS: how often every regression test is rebuilt (0 only runs them
+    /// at startup when BUNDLE_VERSION changed, and on request)
+    pub regression_interval_secs: u64,
+    /// CONNECTOR_DATABASE_HOSTS: comma-separated database hosts (`*.example.com` for
+    /// subdomains) PostgreSQL data connectors may reach; empty disables them
+    pub connector_database_hosts: Vec<String>,
+    /// CONNECTOR_TIMEOUT_SECS: longest a data connector may take to fetch its rows
+    pub connector_timeout_secs: u64,
+    /// CONNECTOR_MAX_ROWS: rows a data connector may return
+    pub connector_max_rows: usize,
+    /// TEMPLATE_SYNC_INTERVAL_SECS: how often template packs that follow their newest
+    /// version tag are synced from Git (0 only syncs on request)
+    pub template_sync_interval_secs: u64,
+    /// TEMPLATE_PACK_MAX_MB: largest template pack checkout accepted
+    pub template_pack_max_mb: u64,
+    /// REGISTRY_MAX_PACKAGES: most files a tenant's package registry holds
+    pub registry_max_packages: usize,
+    /// REGISTRY_MAX_MB: largest total size of a tenant's package registry
+    pub registry_max_mb: u64,
+    /// HEALER_RULES: comma-separated self-healing rules failed compiles are retried with
+    /// (`none` for no healing); default: all of them
+    pub healer_rules: Vec<String>,
+    /// TENANT_HEALER_RULES: comma-separated `tenant=rule` pairs (repeat a tenant for more
+    /// rules, `tenant=none` for none); a listed tenant's compiles only apply its own rules
+    pub tenant_healer_rules: Vec<(String, String)>,
+    /// CORS_ALLOWED_ORIGINS: comma-separated origins browsers may call the API from
+    /// (`https://*.example.com` for subdomains, `*` for any)
+    pub cors_allowed_origins: Vec<String>,
+    /// TENANT_CORS_ORIGINS: comma-separated `tenant=origin` pairs (repeat a tenant for more
+    /// origins); a listed tenant's requests are only allowed from its own origins
+    pub tenant_cors_origins: Vec<(String, String)>,
+    /// CORS_ALLOWED_METHODS: comma-separated methods allowed cross-origin, or `*`
+    pub cors_allowed_methods: Vec<String>,
+    /// CORS_ALLOWED_HEADERS: comma-separated request headers allowed cross-origin, or `*`
+    pub cors_allowed_headers: Vec<String>,
+    /// CORS_EXPOSE_HEADERS: comma-separated response headers readable by browsers, or `*`
+    pub cors_expose_headers: Vec<String>,
+    /// CORS_ALLOW_CREDENTIALS: allow cookies and HTTP auth on cross-origin requests
+    pub cors_allow_credentials: bool,
+    /// WORKSPACE_QUOTA_MB: total size of this instance's compile workspaces before new compiles are
+    /// refused (0 disables the quota)
+    pub workspace_quota_mb: u64,
+    /// WORKSPACE_SWEEP_INTERVAL_SECS: how often leaked workspaces are removed
+    pub workspace_sweep_interval_secs: u64,
+    /// SANDBOX_ENABLED: confine external programs (pdftocairo) to their work directory
+    /// with a scrubbed environment and the limits below
+    pub sandbox_enabled: bool,
+    /// SANDBOX_CPU_SECS: CPU time an external program may use
+    pub sandbox_cpu_secs: u64,
+    /// SANDBOX_MEMORY_MB: address space an external program may map
+    pub sandbox_memory_mb: u64,
+    /// SANDBOX_FILE_MB: largest file an external program may write
+    pub sandbox_file_mb: u64,
+    /// SANDBOX_ALLOW_NETWORK: keep network access for external programs; without it they
+    /// only start where a network namespace can be created
+    pub sandbox_allow_network: bool,
+}
+
+impl Settings {
+    pub fn from_env() -> Self {
+        let default_concurrency = std::thread::available_parallelism().map(|n| n.get()).unwrap_or(4);
+        Self {
+            pdf_cache_enabled: env_or("PDF_CACHE_ENABLED", true),
+            stale_while_revalidate_secs: env_or("STALE_WHILE_REVALIDATE_SECS", 0),
+            cache_normalize_keys: env_or("CACHE_NORMALIZE_KEYS", false),
+            cache_compression_level: env_or("CACHE_COMPRESSION_LEVEL", 3).clamp(0, 22),
+            negative_cache_ttl_secs: env_or("NEGATIVE_CACHE_TTL_SECS", 60),
+            output_store_max_mb: env_or("OUTPUT_STORE_MAX_MB", 256),
+            fingerprint_max_entries: env_or("FINGERPRINT_MAX_ENTRIES", 10_000),
+            body_limit_mb: env_or("BODY_LIMIT_MB", 100),
+            route_body_limits_mb: env_limits("ROUTE_BODY_LIMITS_MB"),
+            tenant_body_limits_mb: env_limits("TENANT_BODY_LIMITS_MB"),
+            allowed_file_types: env_list("ALLOWED_FILE_TYPES", crate::filetypes::DEFAULT_ALLOWED)
+                .into_iter()
+                .map(|ext| ext.trim_start_matches('.').to_ascii_lowercase())
+                .collect(),
+            upload_max_mb: env_or("UPLOAD_MAX_MB", 1024),
+            upload_ttl_secs: env_or("UPLOAD_TTL_SECS", 86_400),
+            compile_concurrency: env_or("COMPILE_CONCURRENCY", default_concurrency).max(1),
+            interactive_share: env_or("INTERACTIVE_CONCURRENCY_SHARE", 1.0_f64).clamp(0.0, 1.0),
+            batch_share: env_or("BATCH_CONCURRENCY_SHARE", 0.5_f64).clamp(0.0, 1.0),
+            async_max_jobs: env_or("ASYNC_MAX_JOBS", 16).max(1),
+            webhook_max_attempts: env_or("WEBHOOK_MAX_ATTEMPTS", 5).max(1),
+            webhook_allowed_hosts: env_list("WEBHOOK_ALLOWED_HOSTS", "").into_iter().map(|h| h.to_ascii_lowercase()).collect(),
+            webhook_allowed_ports: env_list("WEBHOOK_ALLOWED_PORTS", "80,443").iter().filter_map(|p| p.parse().ok()).collect(),
+            webhook_allow_private: env_or("WEBHOOK_ALLOW_PRIVATE", false),
+            webhook_require_https: env_or("WEBHOOK_REQUIRE_HTTPS", false),
+            receipt_signing_key: env_opt("RECEIPT_SIGNING_KEY"),
+            bundle_version: env_or("BUNDLE_VERSION", "default".to_string()),
+            bundle_url: env_opt("BUNDLE_URL"),
+            bundle_url_template: env_or("BUNDLE_URL_TEMPLATE", "https://relay.fullyjustified.net/default_bundle_v{version}.tar".to_string()),
+            admin_token: env_opt("ADMIN_TOKEN"),
+            print_profiles_dir: env_or("PRINT_PROFILES_DIR", PathBuf::from("/usr/share/color/icc")),
+            storage_backend: env_or("STORAGE_BACKEND", "memory".to_string()).to_ascii_lowercase(),
+            storage_dir: env_or("STORAGE_DIR", PathBuf::from("/var/lib/tachyon/storage")),
+            s3_bucket: env_opt("S3_BUCKET"),
+            s3_region: env_or("S3_REGION", "us-east-1".to_string()),
+            s3_endpoint: env_opt("S3_ENDPOINT"),
+            s3_prefix: env_or("S3_PREFIX", String::new()),
+            s3_access_key_id: env_opt("S3_ACCESS_KEY_ID").or_else(|| env_opt("AWS_ACCESS_KEY_ID")),
+            s3_secret_access_key: env_opt("S3_SECRET_ACCESS_KEY").or_else(|| env_opt("AWS_SECRET_ACCESS_KEY")),
+            storage_encryption_key: env_opt("STORAGE_ENCRYPTION_KEY"),
+            signing_encryption_key: env_opt("SIGNING_ENCRYPTION_KEY"),
+            tenant_tokens: env_pairs("TENANT_TOKENS"),
+            tenant_encryption_keys: env_pairs("TENANT_ENCRYPTION_KEYS"),
+            encryption_keys_command: env_opt("ENCRYPTION_KEYS_COMMAND"),
+            retention_days: env_or("RETENTION_DAYS", 0),
+            tenant_retention_days: env_limits("TENANT_RETENTION_DAYS"),
+            shared_format_cache: env_or("SHARED_FORMAT_CACHE", false),
+            format_sync_interval_secs: env_or("FORMAT_SYNC_INTERVAL_SECS", 60).max(1),
+            compile_workers: env_list("COMPILE_WORKERS", ""),
+            worker_token: env_opt("WORKER_TOKEN"),
+            worker_fallback_local: env_or("WORKER_FALLBACK_LOCAL", true),
+            grpc_port: env_or("GRPC_PORT", 50051),
+            grpc_host: env_or("GRPC_HOST", std::net::IpAddr::from([127, 0, 0, 1])),
+            listen: env_list("LISTEN", "0.0.0.0:8080"),
+            tls_cert_file: env_opt("TLS_CERT_FILE"),
+            tls_key_file: env_opt("TLS_KEY_FILE"),
+            unix_socket_mode: env_opt("UNIX_SOCKET_MODE").and_then(|mode| u32::from_str_radix(&mode, 8).ok()).unwrap_or(0o660),
+            acme_domains: env_list("ACME_DOMAINS", ""),
+            acme_email: env_opt("ACME_EMAIL"),
+            acme_directory: env_or("ACME_DIRECTORY", "https://acme-v02.api.letsencrypt.org/directory".to_string()),
+            acme_cache_dir: env_or("ACME_CACHE_DIR", "acme".to_string()),
+            frontend_enabled: env_or("FRONTEND_ENABLED", true),
+            public_url: env_opt("PUBLIC_URL"),
+            playground_ttl_secs: env_or("PLAYGROUND_TTL_SECS", 7 * 86_400),
+            playground_max_ttl_secs: env_or("PLAYGROUND_MAX_TTL_SECS", 30 * 86_400),
+            playground_max_kb: env_or("PLAYGROUND_MAX_KB", 256),
+            shares_per_hour: env_or("SHARES_PER_HOUR", 30),
+            failure_share_ttl_secs: env_or("FAILURE_SHARE_TTL_SECS", 7 * 86_400),
+            failure_share_max_kb: env_or("FAILURE_SHARE_MAX_KB", 1024),
+            grade_max_submissions: env_or("GRADE_MAX_SUBMISSIONS", 200),
+            grade_submission_max_mb: env_or("GRADE_SUBMISSION_MAX_MB", 25),
+            grade_timeout_secs: env_or("GRADE_TIMEOUT_SECS", 60),
+            grade_concurrency: env_or("GRADE_CONCURRENCY", 4),
+            regression_interval_secs: env_or("REGRESSION_INTERVAL_SECS", 86_400),
+            connector_database_hosts: env_list("CONNECTOR_DATABASE_HOSTS", "").into_iter().map(|h| h.to_ascii_lowercase()).collect(),
+            connector_timeout_secs: env_or("CONNECTOR_TIMEOUT_SECS", 30),
+            connector_max_rows: env_or("CONNECTOR_MAX_ROWS", 10_000),
+            template_sync_interval_secs: env_or("TEMPLATE_SYNC_INTERVAL_SECS", 86_400),
+            template_pack_max_mb: env_or("TEMPLATE_PACK_MAX_MB", 100),
+            registry_max_packages: env_or("REGISTRY_MAX_PACKAGES", 200),
+            registry_max_mb: env_or("REGISTRY_MAX_MB", 20),
+            healer_rules: env_list("HEALER_RULES", &crate::healer::RULES.join(",")),
+            tenant_healer_rules: env_pairs("TENANT_HEALER_RULES"),
+            cors_allowed_origins: env_list("CORS_ALLOWED_ORIGINS", "*"),
+            tenant_cors_origins: env_pairs("TENANT_CORS_ORIGINS"),
+            cors_allowed_methods: env_list("CORS_ALLOWED_METHODS", "*"),
+            cors_allowed_headers: env_list("CORS_ALLOWED_HEADERS", "*"),
+            cors_expose_headers: env_list("CORS_EXPOSE_HEADERS", "*"),
+            cors_allow_credentials: env_or("CORS_ALLOW_CREDENTIALS", false),
+            workspace_quota_mb: env_or("WORKSPACE_QUOTA_MB", 2048),
+            workspace_sweep_interval_secs: env_or("WORKSPACE_SWEEP_INTERVAL_SECS", 300).max(1),
+            sandbox_enabled: env_or("SANDBOX_ENABLED", true),
+            sandbox_cpu_secs: env_or("SANDBOX_CPU_SECS", 30).max(1),
+            sandbox_memory_mb: env_or("SANDBOX_MEMORY_MB", 1024).max(64),
+            sandbox_file_mb: env_or("SANDBOX_FILE_MB", 256).max(1),
+            sandbox_allow_network: env_or("SANDBOX_ALLOW_NETWORK", false),
+        }
+    }
+}
+
+/// Parses an environment variable, falling back to `default` when unset or invalid.
+pub fn env_or<T: FromStr>(name: &str, default: T) -> T {
+    std::env::var(name).ok().and_then(|v| v.trim().parse().ok()).unwrap_or(default)
+}
+
+/// Reads an environment variable, treating empty values as unset.
+pub fn env_opt(name: &str) -> Option<String> {
+    std::env::var(name).ok().map(|v| v.trim().to_string()).filter(|v| !v.is_empty())
+}
+
+/// Splits a comma-separated environment variable, using `default` when unset.
+pub fn env_list(name: &str, default: &str) -> Vec<String> {
+    std::env::var(name).unwrap_or_else(|_| default.to_string())
+        .split(',')
+        .map(|item| item.trim().to_string())
+        .filter(|item| !item.is_empty())
+        .collect()
+}
+
+/// Reads a comma-separated list of `name=number` pairs, skipping malformed entries.
+fn env_limits(name: &str) -> Vec<(String, u64)> {
+    env_pairs(name)
+        .into_iter()
+        .filter_map(|(key, value)| Some((key, value.parse().ok()?)))
+        .collect()
+}
+
+/// Reads a comma-separated list of `name=value` pairs, skipping entries without `=`.
+fn env_pairs(name: &str) -> Vec<(String, String)> {
+    env_list(name, "")
+        .iter()
+        .filter_map(|entry| entry.split_once('='))
+        .map(|(key, value)| (key.trim().to_string(), value.trim().to_string()))
+        .collect()
+}
@@ -0,0 +1,68 @@
+//! A hash map split into independently locked shards, so concurrent operations on
+//! different keys rarely wait on each other.
+
+use std::borrow::Borrow;
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::hash::{BuildHasher, Hash};
+use tokio::sync::RwLock;
+
+/// Shard count for the shared maps: comfortably above typical core counts.
+pub const DEFAULT_SHARDS: usize = 32;
+
+pub struct ShardedMap<K, V> {
+    shards: Box<[RwLock<HashMap<K, V>>]>,
+    hasher: RandomState,
+}
+
+impl<K: Hash + Eq, V> ShardedMap<K, V> {
+    pub fn new(shards: usize) -> Self {
+        Self {
+            shards: (0..shards.max(1)).map(|_| RwLock::new(HashMap::new())).collect(),
+            hasher: RandomState::new(),
+        }
+    }
+
+    /// The shard holding `key`.
+    pub fn shard<Q>(&self, key: &Q) -> &RwLock<HashMap<K, V>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        &self.shards[self.hasher.hash_one(key) as usize % self.shards.len()]
+    }
+
+    pub fn shards(&self) -> impl Iterator<Item = &RwLock<HashMap<K, V>>> {
+        self.shards.iter()
+    }
+
+    pub async fn len(&self) -> usize {
+        let mut len = 0;
+        for shard in self.shards() {
+            len += shard.read().await.len();
+        }
+        len
+    }
+}
+
+impl<K: Hash + Eq, V> Default for ShardedMap<K, V> {
+    fn default() -> Self {
+        Self::new(DEFAULT_SHARDS)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_keys_map_to_stable_shards() {
+        let map: ShardedMap<String, u32> = ShardedMap::new(8);
+        for i in 0..100 {
+            map.shard(&format!("key{}", i)).write().await.insert(format!("key{}", i), i);
+        }
+        assert_eq!(map.len().await, 100);
+        assert_eq!(map.shard("key42").read().await.get("key42"), Some(&42));
+        assert!(map.shards().filter(|shard| shard.try_read().is_ok_and(|s| !s.is_empty())).count() > 1);
+    }
+}
@@ -0,0 +1,695 @@
+//! PAdES signatures on compiled PDFs (`sign=true`), for contracts and diplomas that must
+//! be legally signed. Each tenant's signer is configured through `/admin/signing/{tenant}`:
+//! a PKCS#12 bundle whose key signs here, or a certificate chain plus the URL of a remote
+//! signer (an HSM or KMS behind a small hook) that signs the digest it is sent. Stored
+//! signers are sealed with their own key, since they hold private keys and credentials.
+//!
+//! The signature is a detached CMS (`ETSI.CAdES.detached`) carrying the PAdES baseline
+//! attributes: content type, message digest and the signing certificate (ESS v2). The
+//! signing time goes in the signature dictionary, as PAdES requires.
+
+use axum::http::StatusCode;
+use base64::{engine::general_purpose, Engine as _};
+use lopdf::content::{Content, Operation};
+use lopdf::{dictionary, Document, Object, ObjectId, Stream, StringFormat};
+use openssl::hash::MessageDigest;
+use openssl::nid::Nid;
+use openssl::pkcs12::Pkcs12;
+use openssl::pkey::{Id, PKey, Private};
+use openssl::x509::X509;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::cron::civil;
+use crate::encryption::Keyring;
+use crate::models::SignerInfo;
+use crate::settings::Settings;
+use crate::storage::Storage;
+use crate::webhooks::Webhooks;
+use crate::util::unix_now;
+
+const PREFIX: &str = "signing/";
+
+/// Bytes reserved in the PDF for the CMS signature: room for a chain of several
+/// certificates with 4096-bit keys.
+const SIGNATURE_SIZE: usize = 16 * 1024;
+
+/// Stand-in for the byte range offsets until the PDF is written; ten digits each.
+const BYTE_RANGE_PLACEHOLDER: i64 = 9_999_999_999;
+
+const REMOTE_SIGNER_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A tenant's signer as stored.
+#[derive(Serialize, Deserialize)]
+struct StoredSigner {
+    tenant: String,
+    /// Base64 PKCS#12 bundle
+    pkcs12: Option<String>,
+    password: Option<String>,
+    /// PEM chain, signer first, for a remote signer
+    chain: Option<String>,
+    signer_url: Option<String>,
+    signer_token: Option<String>,
+    created_at: u64,
+}
+
+/// Signer settings sent to `/admin/signing/{tenant}`.
+pub enum SignerConfig {
+    Pkcs12 { data: Vec<u8>, password: String },
+    Remote { chain_pem: Vec<u8>, url: String, token: Option<String> },
+}
+
+#[derive(Clone)]
+pub struct SigningStore {
+    storage: Arc<dyn Storage>,
+    /// Seals stored signers; `None` when no key is configured, and then none can be
+    sealer: Option<Arc<Keyring>>,
+}
+
+impl SigningStore {
+    /// Seals signers with SIGNING_ENCRYPTION_KEY, or STORAGE_ENCRYPTION_KEY without it.
+    pub fn new(storage: Arc<dyn Storage>, settings: &Settings) -> Result<Self, String> {
+        let sealer = match (&settings.signing_encryption_key, &settings.storage_encryption_key) {
+            (Some(key), _) => Some(Arc::new(Keyring::single("SIGNING_ENCRYPTION_KEY", key)?)),
+            (None, Some(key)) => Some(Arc::new(Keyring::single("STORAGE_ENCRYPTION_KEY", key)?)),
+            (None, None) => None,
+        };
+        Ok(Self { storage, sealer })
+    }
+
+    /// Checks and stores a tenant's signer, replacing any before. A remote signer's URL
+    /// must pass the webhook target policy.
+    pub async fn configure(&self, tenant: &str, config: SignerConfig, settings: &Settings) -> Result<SignerInfo, (StatusCode, String)> {
+        let Some(sealer) = &self.sealer else {
+            return Err((StatusCode::CONFLICT, "Set SIGNING_ENCRYPTION_KEY to store signing certificates".to_string()));
+        };
+        let stored = match config {
+            SignerConfig::Pkcs12 { data, password } => StoredSigner {
+                tenant: tenant.to_string(),
+                pkcs12: Some(general_purpose::STANDARD.encode(data)),
+                password: Some(password),
+                chain: None,
+                signer_url: None,
+                signer_token: None,
+                created_at: unix_now(),
+            },
+            SignerConfig::Remote { chain_pem, url, token } => {
+                resolve_signer(&url, settings).await.map_err(|e| (StatusCode::BAD_REQUEST, format!("signer_url rejected: {}", e)))?;
+                StoredSigner {
+                    tenant: tenant.to_string(),
+                    pkcs12: None,
+                    password: None,
+                    chain: Some(String::from_utf8(chain_pem).map_err(|_| (StatusCode::BAD_REQUEST, "certificate must be a PEM chain".to_string()))?),
+                    signer_url: Some(url),
+                    signer_token: token,
+                    created_at: unix_now(),
+                }
+            }
+        };
+        let info = Signer::open(&stored).map_err(|e| (StatusCode::BAD_REQUEST, e))?.info(&stored);
+        let key = Self::key(tenant);
+        let data = serde_json::to_vec(&stored).map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+        let sealed = sealer.seal(&key, None, data).map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))?;
+        self.storage.put(&key, sealed).await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))?;
+        Ok(info)
+    }
+
+    pub async fn info(&self, tenant: &str) -> Option<SignerInfo> {
+        let stored = self.load(tenant).await?;
+        Signer::open(&stored).ok().map(|signer| signer.info(&stored))
+    }
+
+    pub async fn remove(&self, tenant: &str) -> bool {
+        if self.load(tenant).await.is_none() {
+            return false;
+        }
+        self.storage.delete(&Self::key(tenant)).await.is_ok()
+    }
+
+    /// The signer of `tenant`, ready to sign. A remote signer's URL is checked under the
+    /// webhook target policy again and its host resolved now; signing connects only to
+    /// those addresses.
+    pub async fn signer(&self, tenant: &str, settings: &Settings) -> Result<Signer, (StatusCode, String)> {
+        let stored = self.load(tenant).await
+            .ok_or_else(|| (StatusCode::CONFLICT, format!("No signing certificate is configured for tenant '{}'", tenant)))?;
+        let mut signer = Signer::open(&stored).map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))?;
+        if let SigningKey::Remote { url, target, .. } = &mut signer.key {
+            *target = Some(resolve_signer(url, settings).await.map_err(|e| (StatusCode::BAD_GATEWAY, format!("Remote signer rejected: {}", e)))?);
+        }
+        Ok(signer)
+    }
+
+    async fn load(&self, tenant: &str) -> Option<StoredSigner> {
+        let key = Self::key(tenant);
+        let sealed = self.storage.get(&key).await.ok().flatten()?;
+        let data = self.sealer.as_ref()?.open(&key, sealed).ok()?;
+        serde_json::from_slice(&data).ok()
+    }
+
+    fn key(tenant: &str) -> String {
+        format!("{}{}", PREFIX, hex::encode(tenant))
+    }
+}
+
+/// Checks a remote signer URL under the webhook target policy and resolves its host.
+async fn resolve_signer(url: &str, settings: &Settings) -> Result<(reqwest::Url, Vec<SocketAddr>), String> {
+    let parsed = Webhooks::validate_url(url, settings)?;
+    let addrs = Webhooks::resolve_target(&parsed, settings).await?;
+    Ok((parsed, addrs))
+}
+
+enum SigningKey {
+    Local(PKey<Private>),
+    /// `target` is set by [`SigningStore::signer`]: the checked URL and the addresses
+    /// its host resolved to
+    Remote { url: String, token: Option<String>, target: Option<(reqwest::Url, Vec<SocketAddr>)> },
+}
+
+pub struct Signer {
+    /// Signer certificate first
+    chain: Vec<X509>,
+    key: SigningKey,
+}
+
+/// Where and why `sign=true` signs.
+pub struct SignOptions {
+    pub reason: Option<String>,
+    pub location: Option<String>,
+    /// 1-based; negative counts from the end
+    pub page: i64,
+    /// `[x, y, width, height]` in points from the bottom left; none for an invisible
+    /// signature
+    pub rect: Option<[f32; 4]>,
+}
+
+impl SignOptions {
+    /// Parses `sign_rect` (`x,y,width,height`).
+    pub fn parse_rect(rect: &str) -> Result<[f32; 4], String> {
+        let values: Vec<f32> = rect.split(',').map(|v| v.trim().parse::<f32>()).collect::<Result<_, _>>()
+            .map_err(|_| format!("Invalid sign_rect '{}': expected x,y,width,height in points", rect))?;
+        match values[..] {
+            [x, y, width, height] if width > 0.0 && height > 0.0 && x >= 0.0 && y >= 0.0 => Ok([x, y, width, height]),
+            _ => Err(format!("Invalid sign_rect '{}': expected x,y,width,height in points", rect)),
+        }
+    }
+}
+
+impl Signer {
+    fn open(stored: &StoredSigner) -> Result<Self, String> {
+        if let (Some(pkcs12), Some(password)) = (&stored.pkcs12, &stored.password) {
+            let der = general_purpose::STANDARD.decode(pkcs12).map_err(|e| e.to_string())?;
+            let parsed = Pkcs12::from_der(&der)
+                .and_then(|p| p.parse2(password))
+                .map_err(|_| "Cannot open the PKCS#12 bundle: wrong password or not a PKCS#12 file".to_string())?;
+            let (Some(key), Some(cert)) = (parsed.pkey, parsed.cert) else {
+                return Err("The PKCS#12 bundle must hold a private key and its certificate".to_string());
+            };
+            let matches = cert.public_key().map(|public| public.public_eq(&key)).unwrap_or(false);
+            if !matches {
+                return Err("The PKCS#12 certificate does not belong to its private key".to_string());
+            }
+            let mut chain = vec![cert];
+            chain.extend(parsed.ca.into_iter().flatten());
+            return Ok(Self { chain, key: SigningKey::Local(key) });
+        }
+        let (Some(pem), Some(url)) = (&stored.chain, &stored.signer_url) else {
+            return Err("Signer has neither a PKCS#12 bundle nor a remote signer".to_string());
+        };
+        let chain = X509::stack_from_pem(pem.as_bytes()).map_err(|_| "certificate must be a PEM chain".to_string())?;
+        if chain.is_empty() {
+            return Err("certificate must hold at least the signer's certificate".to_string());
+        }
+        Ok(Self { chain, key: SigningKey::Remote { url: url.clone(), token: stored.signer_token.clone(), target: None } })
+    }
+
+    fn certificate(&self) -> &X509 {
+        &self.chain[0]
+    }
+
+    /// Common name of the signer's certificate.
+    pub fn name(&self) -> String {
+        self.certificate().subject_name().entries_by_nid(Nid::COMMONNAME).next()
+            .map(|entry| String::from_utf8_lossy(entry.data().as_slice()).into_owned())
+            .unwrap_or_else(|| "Unknown signer".to_string())
+    }
+
+    fn info(&self, stored: &StoredSigner) -> SignerInfo {
+        let certificate = self.certificate();
+        let name = |n: &openssl::x509::X509NameRef| {
+            n.entries()
+                .map(|e| format!("{}={}", e.object().nid().short_name().unwrap_or("?"), String::from_utf8_lossy(e.data().as_slice())))
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        SignerInfo {
+            tenant: stored.tenant.clone(),
+            mode: if stored.pkcs12.is_some() { "pkcs12" } else { "remote" }.to_string(),
+            subject: name(certificate.subject_name()),
+            issuer: name(certificate.issuer_name()),
+            not_after: certificate.not_after().to_string(),
+            chain_length: self.chain.len(),
+            signer_url: stored.signer_url.clone(),
+            created_at: stored.created_at,
+        }
+    }
+
+    /// The signature of `data` (the DER signed attributes) with SHA-256.
+    async fn sign_bytes(&self, data: &[u8]) -> Result<Vec<u8>, String> {
+        match &self.key {
+            SigningKey::Local(key) => {
+                let mut signer = openssl::sign::Signer::new(MessageDigest::sha256(), key).map_err(|e| e.to_string())?;
+                signer.update(data).map_err(|e| e.to_string())?;
+                signer.sign_to_vec().map_err(|e| e.to_string())
+            }
+            SigningKey::Remote { target, token, .. } => {
+                let (url, addrs) = target.as_ref().ok_or("Remote signer was not resolved")?;
+                let client = reqwest::Client::builder()
+                    .timeout(REMOTE_SIGNER_TIMEOUT)
+                    .redirect(reqwest::redirect::Policy::none())
+                    .resolve_to_addrs(url.host_str().unwrap_or_default(), addrs)
+                    .build()
+                    .map_err(|e| e.to_string())?;
+                let mut request = client.post(url.clone()).json(&serde_json::json!({
+                    "algorithm": "sha256",
+                    "digest": general_purpose::STANDARD.encode(Sha256::digest(data)),
+                    "data": general_purpose::STANDARD.encode(data),
+                }));
+                if let Some(token) = token {
+                    request = request.bearer_auth(token);
+                }
+                let response = request.send().await.map_err(|e| format!("Remote signer unreachable: {}", e))?;
+                if !response.status().is_success() {
+                    return Err(format!("Remote signer answered {}", response.status()));
+                }
+                #[derive(Deserialize)]
+                struct RemoteSignature {
+                    signature: String,
+                }
+                let body: RemoteSignature = response.json().await.map_err(|e| format!("Invalid remote signer response: {}", e))?;
+                general_purpose::STANDARD.decode(body.signature).map_err(|e| format!("Invalid remote signature: {}", e))
+            }
+        }
+    }
+
+    /// Signs `pdf`, adding a signature field (visible with `options.rect`).
+    pub async fn sign(&self, pdf: &[u8], options: &SignOptions) -> Result<Vec<u8>, String> {
+        let certificate = self.certificate();
+        let algorithm = match certificate.public_key().map_err(|e| e.to_string())?.id() {
+            Id::RSA => der::sequence(&[der::oid(der::RSA_ENCRYPTION), der::null()]),
+            Id::EC => der::sequence(&[der::oid(der::ECDSA_WITH_SHA256)]),
+            other => return Err(format!("Unsupported signing key type {:?}", other)),
+        };
+        let (mut out, [start, end]) = prepare(pdf, &self.name(), options, unix_now())?;
+        let digest = Sha256::new().chain_update(&out[..start]).chain_update(&out[end..]).finalize();
+
+        let certificate_der = certificate.to_der().map_err(|e| e.to_string())?;
+        let signed_attributes = der::set(&[
+            der::sequence(&[der::oid(der::CONTENT_TYPE), der::set(&[der::oid(der::DATA)])]),
+            der::sequence(&[der::oid(der::MESSAGE_DIGEST), der::set(&[der::octet_string(&digest)])]),
+            der::sequence(&[
+                der::oid(der::SIGNING_CERTIFICATE_V2),
+                der::set(&[der::sequence(&[der::sequence(&[der::sequence(&[der::octet_string(&Sha256::digest(&certificate_der))])])])]),
+            ]),
+        ]);
+        let signature = self.sign_bytes(&signed_attributes).await?;
+
+        let serial = certificate.serial_number().to_bn().map_err(|e| e.to_string())?.to_vec();
+        let issuer = certificate.issuer_name().to_der().map_err(|e| e.to_string())?;
+        let sha256 = der::sequence(&[der::oid(der::SHA256), der::null()]);
+        let signer_info = der::sequence(&[
+            der::integer(&[1]),
+            der::sequence(&[issuer, der::integer(&serial)]),
+            sha256.clone(),
+            // [0] IMPLICIT: the signed attributes were signed with their SET tag
+            [&[0xA0], &signed_attributes[1..]].concat(),
+            algorithm,
+            der::octet_string(&signature),
+        ]);
+        let certificates = self.chain.iter().map(|c| c.to_der()).collect::<Result<Vec<_>, _>>().map_err(|e| e.to_string())?;
+        let signed_data = der::sequence(&[
+            der::integer(&[1]),
+            der::set(&[sha256]),
+            der::sequence(&[der::oid(der::DATA)]),
+            der::tlv(0xA0, &certificates.concat()),
+            der::set(&[signer_info]),
+        ]);
+        let cms = der::sequence(&[der::oid(der::SIGNED_DATA), der::tlv(0xA0, &signed_data)]);
+
+        let hex = hex::encode_upper(&cms);
+        if hex.len() > end - start - 2 {
+            return Err(format!("Signature of {} bytes exceeds the {} reserved", cms.len(), SIGNATURE_SIZE));
+        }
+        out[start + 1..start + 1 + hex.len()].copy_from_slice(hex.as_bytes());
+        Ok(out)
+    }
+}
+
+/// Writes `pdf` with a signature field and a zeroed `/Contents`, with `/ByteRange` filled
+/// in. Returns it and the offsets of `/Contents`, from `<` to past `>`.
+fn prepare(pdf: &[u8], name: &str, options: &SignOptions, now: u64) -> Result<(Vec<u8>, [usize; 2]), String> {
+    let mut doc = Document::load_mem(pdf).map_err(|e| format!("Failed to parse PDF: {}", e))?;
+    let pages = doc.get_pages();
+    let count = pages.len() as i64;
+    let number = if options.page < 0 { count + 1 + options.page } else { options.page };
+    let page_id = *u32::try_from(number).ok().and_then(|n| pages.get(&n))
+        .ok_or_else(|| format!("Cannot sign on page {}: the document has {} page(s)", options.page, count))?;
+
+    let text = |value: &str| Object::string_literal(value);
+    let mut signature = dictionary! {
+        "Type" => "Sig",
+        "Filter" => "Adobe.PPKLite",
+        "SubFilter" => "ETSI.CAdES.detached",
+        "ByteRange" => vec![0.into(), BYTE_RANGE_PLACEHOLDER.into(), BYTE_RANGE_PLACEHOLDER.into(), BYTE_RANGE_PLACEHOLDER.into()],
+        "Contents" => Object::String(vec![0; SIGNATURE_SIZE], StringFormat::Hexadecimal),
+        "M" => text(&pdf_date(now)),
+        "Name" => text(name),
+    };
+    if let Some(reason) = &options.reason {
+        signature.set("Reason", text(reason));
+    }
+    if let Some(location) = &options.location {
+        signature.set("Location", text(location));
+    }
+    let signature = doc.add_object(signature);
+
+    let [x, y, width, height] = options.rect.unwrap_or_default();
+    let mut widget = dictionary! {
+        "Type" => "Annot",
+        "Subtype" => "Widget",
+        "FT" => "Sig",
+        "T" => text("Signature1"),
+        "V" => signature,
+        "P" => page_id,
+        "Rect" => vec![x.into(), y.into(), (x + width).into(), (y + height).into()],
+        // Print, Locked
+        "F" => 132,
+    };
+    if options.rect.is_some() {
+        let appearance = appearance(&mut doc, name, options, now, width, height)?;
+        widget.set("AP", dictionary! { "N" => appearance });
+    }
+    let widget = doc.add_object(widget);
+    push_to_array(&mut doc, page_id, b"Annots", widget)?;
+
+    let acroform = match doc.catalog().map_err(|e| e.to_string())?.get(b"AcroForm") {
+        Ok(Object::Reference(id)) => *id,
+        Ok(Object::Dictionary(form)) => {
+            let form = form.clone();
+            doc.add_object(form)
+        }
+        _ => doc.add_object(dictionary! {}),
+    };
+    doc.catalog_mut().map_err(|e| e.to_string())?.set("AcroForm", acroform);
+    push_to_array(&mut doc, acroform, b"Fields", widget)?;
+    // SignaturesExist, AppendOnly
+    doc.get_dictionary_mut(acroform).map_err(|e| e.to_string())?.set("SigFlags", 3);
+
+    let mut out = Vec::new();
+    doc.save_to(&mut out).map_err(|e| format!("Failed to write PDF: {}", e))?;
+
+    let contents = format!("<{}>", "00".repeat(SIGNATURE_SIZE));
+    let start = find(&out, contents.as_bytes()).ok_or("Signature placeholder not found")?;
+    let end = start + contents.len();
+    let placeholder = format!("0 {0} {0} {0}", BYTE_RANGE_PLACEHOLDER);
+    let range_at = find(&out, placeholder.as_bytes()).ok_or("Byte range placeholder not found")?;
+    let range = format!("0 {} {} {}", start, end, out.len() - end);
+    out[range_at..range_at + placeholder.len()].copy_from_slice(format!("{:<width$}", range, width = placeholder.len()).as_bytes());
+    Ok((out, [start, end]))
+}
+
+/// Appends `item` to the array at `key` of the dictionary `id`, creating it if missing.
+fn push_to_array(doc: &mut Document, id: ObjectId, key: &[u8], item: ObjectId) -> Result<(), String> {
+    let existing = doc.get_dictionary(id).map_err(|e| e.to_string())?.get(key).ok().cloned();
+    match existing {
+        Some(Object::Reference(array)) => doc.get_object_mut(array).and_then(Object::as_array_mut).map_err(|e| e.to_string())?.push(item.into()),
+        Some(Object::Array(mut items)) => {
+            items.push(item.into());
+            doc.get_dictionary_mut(id).map_err(|e| e.to_string())?.set(key, items);
+        }
+        _ => doc.get_dictionary_mut(id).map_err(|e| e.to_string())?.set(key, vec![item.into()]),
+    }
+    Ok(())
+}
+
+/// Appearance of a visible signature: a framed box naming the signer, date, reason and
+/// location in Helvetica.
+fn appearance(doc: &mut Document, name: &str, options: &SignOptions, now: u64, width: f32, height: f32) -> Result<ObjectId, String> {
+    let mut lines = vec![format!("Digitally signed by {}", name), format!("Date: {}", display_date(now))];
+    lines.extend(options.reason.iter().map(|reason| format!("Reason: {}", reason)));
+    lines.extend(options.location.iter().map(|location| format!("Location: {}", location)));
+    let size = ((height - 4.0) / (lines.len() as f32 * 1.25)).min(10.0);
+    // WinAnsi covers Latin-1; other characters print as '?'
+    let latin1 = |line: &str| line.chars().map(|c| u8::try_from(u32::from(c)).unwrap_or(b'?')).collect::<Vec<u8>>();
+
+    let mut operations = vec![
+        Operation::new("q", vec![]),
+        Operation::new("G", vec![0.3.into()]),
+        Operation::new("w", vec![0.5.into()]),
+        Operation::new("re", vec![0.25.into(), 0.25.into(), (width - 0.5).into(), (height - 0.5).into()]),
+        Operation::new("S", vec![]),
+        Operation::new("Q", vec![]),
+        Operation::new("BT", vec![]),
+        Operation::new("Tf", vec!["F1".into(), size.into()]),
+        Operation::new("Td", vec![4.into(), (height - 2.0 - size).into()]),
+    ];
+    for (index, line) in lines.iter().enumerate() {
+        if index > 0 {
+            operations.push(Operation::new("Td", vec![0.into(), (-1.25 * size).into()]));
+        }
+        operations.push(Operation::new("Tj", vec![Object::String(latin1(line), StringFormat::Literal)]));
+    }
+    operations.push(Operation::new("ET", vec![]));
+    let content = Content { operations }.encode().map_err(|e| e.to_string())?;
+    let font = doc.add_object(dictionary! {
+        "Type" => "Font",
+        "Subtype" => "Type1",
+        "BaseFont" => "Helvetica",
+        "Encoding" => "WinAnsiEncoding",
+    });
+    Ok(doc.add_object(Stream::new(dictionary! {
+        "Type" => "XObject",
+        "Subtype" => "Form",
+        "BBox" => vec![0.into(), 0.into(), width.into(), height.into()],
+        "Resources" => dictionary! { "Font" => dictionary! { "F1" => font } },
+    }, content)))
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+fn pdf_date(unix: u64) -> String {
+    let (year, month, day, hour, minute, second) = civil(unix);
+    format!("D:{:04}{:02}{:02}{:02}{:02}{:02}Z", year, month, day, hour, minute, second)
+}
+
+fn display_date(unix: u64) -> String {
+    let (year, month, day, hour, minute, _) = civil(unix);
+    format!("{:04}-{:02}-{:02} {:02}:{:02} UTC", year, month, day, hour, minute)
+}
+
+/// Just enough DER to write a CMS SignedData.
+mod der {
+    pub const SIGNED_DATA: &[u8] = &[0x2A, 0x86, 0x48, 0x86, 0xF7, 0x0D, 0x01, 0x07, 0x02];
+    pub const DATA: &[u8] = &[0x2A, 0x86, 0x48, 0x86, 0xF7, 0x0D, 0x01, 0x07, 0x01];
+    pub const SHA256: &[u8] = &[0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x01];
+    pub const CONTENT_TYPE: &[u8] = &[0x2A, 0x86, 0x48, 0x86, 0xF7, 0x0D, 0x01, 0x09, 0x03];
+    pub const MESSAGE_DIGEST: &[u8] = &[0x2A, 0x86, 0x48, 0x86, 0xF7, 0x0D, 0x01, 0x09, 0x04];
+    pub const SIGNING_CERTIFICATE_V2: &[u8] = &[0x2A, 0x86, 0x48, 0x86, 0xF7, 0x0D, 0x01, 0x09, 0x10, 0x02, 0x2F];
+    pub const RSA_ENCRYPTION: &[u8] = &[0x2A, 0x86, 0x48, 0x86, 0xF7, 0x0D, 0x01, 0x01, 0x01];
+    pub const ECDSA_WITH_SHA256: &[u8] = &[0x2A, 0x86, 0x48, 0xCE, 0x3D, 0x04, 0x03, 0x02];
+
+    pub fn tlv(tag: u8, content: &[u8]) -> Vec<u8> {
+        let mut out = vec![tag];
+        if content.len() < 0x80 {
+            out.push(content.len() as u8);
+        } else {
+            let length: Vec<u8> = content.len().to_be_bytes().into_iter().skip_while(|&b| b == 0).collect();
+            out.push(0x80 | length.len() as u8);
+            out.extend(length);
+        }
+        out.extend_from_slice(content);
+        out
+    }
+
+    pub fn sequence(items: &[Vec<u8>]) -> Vec<u8> {
+        tlv(0x30, &items.concat())
+    }
+
+    /// A SET OF, its elements in DER order.
+    pub fn set(items: &[Vec<u8>]) -> Vec<u8> {
+        let mut items = items.to_vec();
+        items.sort();
+        tlv(0x31, &items.concat())
+    }
+
+    pub fn oid(encoded: &[u8]) -> Vec<u8> {
+        tlv(0x06, encoded)
+    }
+
+    /// A non-negative INTEGER from its big-endian magnitude.
+    pub fn integer(magnitude: &[u8]) -> Vec<u8> {
+        let trimmed: Vec<u8> = magnitude.iter().copied().skip_while(|&b| b == 0).collect();
+        let mut content = if trimmed.first().is_none_or(|&b| b & 0x80 != 0) { vec![0] } else { Vec::new() };
+        content.extend(trimmed);
+        tlv(0x02, &content)
+    }
+
+    pub fn octet_string(bytes: &[u8]) -> Vec<u8> {
+        tlv(0x04, bytes)
+    }
+
+    pub fn null() -> Vec<u8> {
+        vec![0x05, 0x00]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+    use openssl::asn1::Asn1Time;
+    use openssl::cms::{CMSOptions, CmsContentInfo};
+    use openssl::ec::{EcGroup, EcKey};
+    use openssl::x509::{X509Builder, X509NameBuilder};
+
+    fn pkcs12() -> Vec<u8> {
+        let key = PKey::from_ec_key(EcKey::generate(&EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap()).unwrap()).unwrap();
+        let mut name = X509NameBuilder::new().unwrap();
+        name.append_entry_by_nid(Nid::COMMONNAME, "Registrar, Example University").unwrap();
+        let name = name.build();
+        let mut builder = X509Builder::new().unwrap();
+        builder.set_version(2).unwrap();
+        builder.set_subject_name(&name).unwrap();
+        builder.set_issuer_name(&name).unwrap();
+        builder.set_pubkey(&key).unwrap();
+        builder.set_not_before(&Asn1Time::days_from_now(0).unwrap()).unwrap();
+        builder.set_not_after(&Asn1Time::days_from_now(365).unwrap()).unwrap();
+        builder.sign(&key, MessageDigest::sha256()).unwrap();
+        let cert = builder.build();
+        Pkcs12::builder().name("signer").pkey(&key).cert(&cert).build2("secret").unwrap().to_der().unwrap()
+    }
+
+    fn pdf() -> Vec<u8> {
+        let mut doc = Document::with_version("1.5");
+        let pages_id = doc.new_object_id();
+        let kids: Vec<Object> = (0..2).map(|_| {
+            let content = doc.add_object(Stream::new(dictionary! {}, b"BT (Diploma) Tj ET".to_vec()));
+            doc.add_object(dictionary! { "Type" => "Page", "Parent" => pages_id, "Contents" => content, "MediaBox" => vec![0.into(), 0.into(), 595.into(), 842.into()] }).into()
+        }).collect();
+        doc.objects.insert(pages_id, Object::Dictionary(dictionary! { "Type" => "Pages", "Kids" => kids, "Count" => 2 }));
+        let catalog = doc.add_object(dictionary! { "Type" => "Catalog", "Pages" => pages_id });
+        doc.trailer.set("Root", catalog);
+        let mut out = Vec::new();
+        doc.save_to(&mut out).unwrap();
+        out
+    }
+
+    fn policy() -> Settings {
+        Settings {
+            signing_encryption_key: Some("000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f".into()),
+            webhook_allowed_hosts: vec![],
+            webhook_allowed_ports: vec![80, 443],
+            webhook_allow_private: false,
+            webhook_require_https: false,
+            ..Settings::from_env()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_sign_and_verify() {
+        let settings = policy();
+        let store = SigningStore::new(Arc::new(MemoryStorage::new()), &settings).unwrap();
+        let wrong = store.configure("acme", SignerConfig::Pkcs12 { data: pkcs12(), password: "wrong".into() }, &settings).await;
+        assert_eq!(wrong.unwrap_err().0, StatusCode::BAD_REQUEST);
+        assert_eq!(store.signer("acme", &settings).await.err().unwrap().0, StatusCode::CONFLICT);
+
+        let info = store.configure("acme", SignerConfig::Pkcs12 { data: pkcs12(), password: "secret".into() }, &settings).await.unwrap();
+        assert_eq!(info.mode, "pkcs12");
+        // The key and its password are not stored in the clear
+        let raw = store.storage.get(&SigningStore::key("acme")).await.unwrap().unwrap();
+        assert!(!raw.windows(6).any(|w| w == b"secret") && !raw.windows(6).any(|w| w == b"pkcs12"));
+        let unsealed = SigningStore::new(store.storage.clone(), &Settings { signing_encryption_key: None, storage_encryption_key: None, ..policy() }).unwrap();
+        assert!(unsealed.info("acme").await.is_none());
+        assert_eq!(unsealed.configure("acme", SignerConfig::Pkcs12 { data: pkcs12(), password: "secret".into() }, &settings).await.unwrap_err().0, StatusCode::CONFLICT);
+        assert_eq!(info.subject, "CN=Registrar, Example University");
+        let signer = store.signer("acme", &settings).await.unwrap();
+        assert_eq!(signer.name(), "Registrar, Example University");
+
+        let options = SignOptions { reason: Some("Diploma".into()), location: None, page: -1, rect: Some([360.0, 40.0, 200.0, 50.0]) };
+        let signed = signer.sign(&pdf(), &options).await.unwrap();
+
+        let doc = Document::load_mem(&signed).unwrap();
+        let form = doc.catalog().unwrap().get(b"AcroForm").and_then(|f| doc.dereference(f)).unwrap().1.as_dict().unwrap().clone();
+        let widget = doc.get_dictionary(form.get(b"Fields").unwrap().as_array().unwrap()[0].as_reference().unwrap()).unwrap();
+        let page_two = doc.get_pages()[&2];
+        assert_eq!(widget.get(b"P").unwrap().as_reference().unwrap(), page_two);
+        assert!(widget.has(b"AP"));
+        let signature = doc.get_dictionary(widget.get(b"V").unwrap().as_reference().unwrap()).unwrap();
+        let range: Vec<usize> = signature.get(b"ByteRange").unwrap().as_array().unwrap().iter().map(|v| v.as_i64().unwrap() as usize).collect();
+        assert_eq!(range[0], 0);
+        assert_eq!(range[2] + range[3], signed.len());
+        let cms_der = signature.get(b"Contents").unwrap().as_str().unwrap();
+
+        let signed_bytes = [&signed[..range[1]], &signed[range[2]..]].concat();
+        let mut cms = CmsContentInfo::from_der(cms_der).unwrap();
+        cms.verify(None, None, Some(&signed_bytes), None, CMSOptions::BINARY | CMSOptions::NO_SIGNER_CERT_VERIFY).unwrap();
+
+        let tampered = [&signed[..range[1]], b"x".as_slice(), &signed[range[2] + 1..]].concat();
+        let mut cms = CmsContentInfo::from_der(cms_der).unwrap();
+        assert!(cms.verify(None, None, Some(&tampered[..range[1]]), None, CMSOptions::BINARY | CMSOptions::NO_SIGNER_CERT_VERIFY).is_err());
+
+        assert!(store.remove("acme").await);
+        assert!(store.info("acme").await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_remote_signer_target_policy() {
+        let settings = policy();
+        let store = SigningStore::new(Arc::new(MemoryStorage::new()), &settings).unwrap();
+        let chain_pem = Pkcs12::from_der(&pkcs12()).unwrap().parse2("secret").unwrap().cert.unwrap().to_pem().unwrap();
+        for url in ["http://127.0.0.1/sign", "http://169.254.169.254/latest", "https://example.com:6379/", "file:///etc/passwd"] {
+            let config = SignerConfig::Remote { chain_pem: chain_pem.clone(), url: url.into(), token: None };
+            let rejected = store.configure("acme", config, &settings).await.unwrap_err();
+            assert_eq!(rejected.0, StatusCode::BAD_REQUEST, "{}", url);
+            assert!(rejected.1.starts_with("signer_url rejected"), "{}", rejected.1);
+        }
+
+        // Stored while the policy allowed it: checked again, and not reached, at sign time
+        let stored = StoredSigner {
+            tenant: "acme".into(),
+            pkcs12: None,
+            password: None,
+            chain: Some(String::from_utf8(chain_pem).unwrap()),
+            signer_url: Some("http://127.0.0.1/sign".into()),
+            signer_token: None,
+            created_at: 0,
+        };
+        let key = SigningStore::key("acme");
+        let sealed = store.sealer.as_ref().unwrap().seal(&key, None, serde_json::to_vec(&stored).unwrap()).unwrap();
+        store.storage.put(&key, sealed).await.unwrap();
+        assert_eq!(store.info("acme").await.unwrap().mode, "remote");
+        assert_eq!(store.signer("acme", &settings).await.err().unwrap().0, StatusCode::BAD_GATEWAY);
+        let trusted = Settings { webhook_allow_private: true, ..policy() };
+        assert!(store.signer("acme", &trusted).await.is_ok());
+    }
+
+    #[test]
+    fn test_sign_options() {
+        assert_eq!(SignOptions::parse_rect("360, 40,200,50").unwrap(), [360.0, 40.0, 200.0, 50.0]);
+        assert!(SignOptions::parse_rect("360,40,200").is_err());
+        assert!(SignOptions::parse_rect("0,0,-5,10").is_err());
+        let options = SignOptions { reason: None, location: None, page: 5, rect: None };
+        assert_eq!(prepare(&pdf(), "x", &options, 0).unwrap_err(), "Cannot sign on page 5: the document has 2 page(s)");
+    }
+
+    #[test]
+    fn test_dates() {
+        assert_eq!(pdf_date(0), "D:19700101000000Z");
+        assert_eq!(pdf_date(1_792_238_400), "D:20261017120000Z");
+        assert_eq!(display_date(951_782_400), "2000-02-29 00:00 UTC");
+    }
+}
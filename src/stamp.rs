@@ -0,0 +1,242 @@
+//! Page stamping without touching the sources: sequential Bates numbers for legal
+//! productions, or page labels such as "Page 3 of 12", printed in a corner of every page.
+//! Used by `stamp` on `POST /compile` and by `POST /stamp`, which numbers several PDFs in
+//! one continuous sequence.
+
+use lopdf::content::{Content, Operation};
+use lopdf::{dictionary, Document, Object, ObjectId, Stream, StringFormat};
+
+use crate::impose::inherited;
+
+pub const POSITIONS: &[&str] = &["bottom-right", "bottom-center", "bottom-left", "top-right", "top-center", "top-left"];
+
+pub const DEFAULT_DIGITS: usize = 6;
+/// Largest PDF `POST /stamp` reads into memory.
+pub const MAX_PDF_BYTES: usize = 100 * 1024 * 1024;
+const MAX_DIGITS: usize = 12;
+const FONT_SIZE: f32 = 10.0;
+/// Distance of the stamp from the page edges, in points
+const MARGIN: f32 = 18.0;
+/// Resource name of the stamp's font, unlikely to clash with the page's own
+const FONT: &str = "TachyonStamp";
+/// US Letter, for pages whose MediaBox cannot be read.
+const DEFAULT_MEDIA_BOX: [f32; 4] = [0.0, 0.0, 612.0, 792.0];
+
+/// Helvetica advance widths of ASCII 32..=126, per 1000 units of font size.
+const HELVETICA_WIDTHS: [u16; 95] = [
+    278, 278, 355, 556, 556, 889, 667, 191, 333, 333, 389, 584, 278, 333, 278, 278,
+    556, 556, 556, 556, 556, 556, 556, 556, 556, 556, 278, 278, 584, 584, 584, 556,
+    1015, 667, 667, 722, 722, 667, 611, 778, 722, 278, 500, 667, 556, 833, 722, 778,
+    667, 778, 722, 667, 611, 722, 667, 944, 667, 667, 611, 278, 278, 278, 469, 556,
+    333, 556, 556, 500, 556, 556, 278, 556, 556, 222, 222, 500, 222, 833, 556, 556,
+    556, 556, 333, 500, 278, 556, 500, 722, 500, 500, 500, 334, 260, 334, 584,
+];
+
+/// What to print on each page. The template's `{n}` is the sequence number, zero-padded
+/// to `digits`; `{page}` and `{pages}` are the page within the document and its count.
+#[derive(Debug, Clone)]
+pub struct StampOptions {
+    template: String,
+    pub start: u64,
+    digits: usize,
+    position: String,
+}
+
+impl StampOptions {
+    pub fn new(template: &str, start: Option<u64>, digits: Option<usize>, position: Option<&str>) -> Result<Self, String> {
+        if template.trim().is_empty() {
+            return Err("stamp must not be empty".to_string());
+        }
+        if template.chars().any(|c| u32::from(c) > 0xFF || c.is_control()) {
+            return Err("stamp may only hold printable Latin-1 characters".to_string());
+        }
+        let digits = digits.unwrap_or(DEFAULT_DIGITS);
+        if digits > MAX_DIGITS {
+            return Err(format!("stamp_digits must be at most {}", MAX_DIGITS));
+        }
+        let position = position.unwrap_or(POSITIONS[0]);
+        if !POSITIONS.contains(&position) {
+            return Err(format!("Unknown stamp_position '{}'; expected one of: {}", position, POSITIONS.join(", ")));
+        }
+        Ok(Self { template: template.to_string(), start: start.unwrap_or(1), digits, position: position.to_string() })
+    }
+
+    /// The text stamped on `page` of `pages`, numbered `n`.
+    pub fn label(&self, n: u64, page: usize, pages: usize) -> String {
+        self.template
+            .replace("{n}", &format!("{:0width$}", n, width = self.digits))
+            .replace("{pages}", &pages.to_string())
+            .replace("{page}", &page.to_string())
+    }
+}
+
+/// The number stamped on the last of `pages` pages numbered from `first`, or an error when
+/// the sequence would run past `u64::MAX`.
+pub fn last_number(first: u64, pages: usize) -> Result<u64, String> {
+    first.checked_add(pages.saturating_sub(1) as u64).ok_or_else(|| format!("Numbering {} page(s) from {} overflows", pages, first))
+}
+
+/// Stamps every page of `pdf`, numbering from `first`. Returns the PDF and its page count.
+pub fn stamp(pdf: &[u8], options: &StampOptions, first: u64) -> Result<(Vec<u8>, usize), String> {
+    let mut doc = Document::load_mem(pdf).map_err(|e| format!("Failed to parse PDF: {}", e))?;
+    let pages: Vec<ObjectId> = doc.get_pages().into_values().collect();
+    // The last number must exist before any page is stamped
+    last_number(first, pages.len())?;
+    let font = doc.add_object(dictionary! {
+        "Type" => "Font",
+        "Subtype" => "Type1",
+        "BaseFont" => "Helvetica",
+        "Encoding" => "WinAnsiEncoding",
+    });
+    // Shared by every page: the page's own content is isolated in q ... Q so a state it
+    // leaves set cannot move or recolour the stamp
+    let save = doc.add_object(Stream::new(dictionary! {}, b"q".to_vec()));
+    for (index, &page) in pages.iter().enumerate() {
+        let label = options.label(first + index as u64, index + 1, pages.len());
+        let [x0, y0, x1, y1] = page_box(&doc, page);
+        let width = text_width(&label);
+        let x = match options.position.rsplit('-').next() {
+            Some("left") => x0 + MARGIN,
+            Some("center") => (x0 + x1 - width) / 2.0,
+            _ => x1 - MARGIN - width,
+        };
+        let y = if options.position.starts_with("top") { y1 - MARGIN - FONT_SIZE } else { y0 + MARGIN };
+        let operations = vec![
+            Operation::new("Q", vec![]),
+            Operation::new("q", vec![]),
+            Operation::new("BT", vec![]),
+            Operation::new("g", vec![0.into()]),
+            Operation::new("Tf", vec![FONT.into(), FONT_SIZE.into()]),
+            Operation::new("Td", vec![x.into(), y.into()]),
+            Operation::new("Tj", vec![Object::String(label.chars().map(|c| c as u8).collect(), StringFormat::Literal)]),
+            Operation::new("ET", vec![]),
+            Operation::new("Q", vec![]),
+        ];
+        let content = Content { operations }.encode().map_err(|e| e.to_string())?;
+        let overlay = doc.add_object(Stream::new(dictionary! {}, content));
+
+        let mut resources = inherited(&doc, page, b"Resources")
+            .and_then(|r| doc.dereference(r).ok())
+            .and_then(|(_, r)| r.as_dict().ok())
+            .cloned()
+            .unwrap_or_default();
+        let mut fonts = resources.get(b"Font").ok()
+            .and_then(|f| doc.dereference(f).ok())
+            .and_then(|(_, f)| f.as_dict().ok())
+            .cloned()
+            .unwrap_or_default();
+        fonts.set(FONT, font);
+        resources.set("Font", fonts);
+
+        let mut contents = match doc.get_dictionary(page).and_then(|p| p.get(b"Contents")) {
+            Ok(Object::Array(streams)) => streams.clone(),
+            Ok(Object::Reference(id)) => match doc.get_object(*id) {
+                Ok(Object::Array(streams)) => streams.clone(),
+                _ => vec![Object::Reference(*id)],
+            },
+            _ => Vec::new(),
+        };
+        contents.insert(0, save.into());
+        contents.push(overlay.into());
+        let page_dict = doc.get_dictionary_mut(page).map_err(|e| e.to_string())?;
+        page_dict.set("Contents", contents);
+        page_dict.set("Resources", resources);
+    }
+
+    let mut out = Vec::new();
+    doc.save_to(&mut out).map_err(|e| format!("Failed to write PDF: {}", e))?;
+    Ok((out, pages.len()))
+}
+
+/// Width of `text` in Helvetica at the stamp's size.
+fn text_width(text: &str) -> f32 {
+    let units: u32 = text.chars().map(|c| {
+        let index = (c as usize).wrapping_sub(32);
+        u32::from(HELVETICA_WIDTHS.get(index).copied().unwrap_or(556))
+    }).sum();
+    units as f32 * FONT_SIZE / 1000.0
+}
+
+/// The visible area of a page: its CropBox, or else its MediaBox.
+fn page_box(doc: &Document, page: ObjectId) -> [f32; 4] {
+    for key in [b"CropBox".as_slice(), b"MediaBox"] {
+        let values = inherited(doc, page, key).and_then(|b| doc.dereference(b).ok()).and_then(|(_, b)| b.as_array().ok());
+        let numbers: Vec<f32> = values.into_iter().flatten().filter_map(|v| v.as_float().ok()).collect();
+        if let [x0, y0, x1, y1] = numbers[..] {
+            if x1 > x0 && y1 > y0 {
+                return [x0, y0, x1, y1];
+            }
+        }
+    }
+    DEFAULT_MEDIA_BOX
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pdf(pages: usize) -> Vec<u8> {
+        let mut doc = Document::with_version("1.5");
+        let pages_id = doc.new_object_id();
+        let kids: Vec<Object> = (0..pages).map(|_| {
+            let content = doc.add_object(Stream::new(dictionary! {}, b"1 0 0 rg BT /F1 12 Tf (Exhibit) Tj ET".to_vec()));
+            doc.add_object(dictionary! { "Type" => "Page", "Parent" => pages_id, "Contents" => content }).into()
+        }).collect();
+        doc.objects.insert(pages_id, Object::Dictionary(dictionary! {
+            "Type" => "Pages",
+            "Kids" => kids,
+            "Count" => pages as i64,
+            "MediaBox" => vec![0.into(), 0.into(), 612.into(), 792.into()],
+            "Resources" => dictionary! { "Font" => dictionary! { "F1" => dictionary! { "Type" => "Font", "Subtype" => "Type1", "BaseFont" => "Times-Roman" } } },
+        }));
+        let catalog = doc.add_object(dictionary! { "Type" => "Catalog", "Pages" => pages_id });
+        doc.trailer.set("Root", catalog);
+        let mut out = Vec::new();
+        doc.save_to(&mut out).unwrap();
+        out
+    }
+
+    /// The stamped text of each page.
+    fn stamps(pdf: &[u8]) -> Vec<String> {
+        let doc = Document::load_mem(pdf).unwrap();
+        doc.get_pages().into_values().map(|page| {
+            let content = doc.get_and_decode_page_content(page).unwrap();
+            let shown: Vec<String> = content.operations.iter()
+                .filter(|op| op.operator == "Tj")
+                .map(|op| String::from_utf8(op.operands[0].as_str().unwrap().to_vec()).unwrap())
+                .collect();
+            assert_eq!(shown[0], "Exhibit", "the page keeps its own content");
+            let (inline, _) = doc.get_page_resources(page).unwrap();
+            let fonts = inline.unwrap().get(b"Font").unwrap().as_dict().unwrap();
+            assert!(fonts.has(b"F1") && fonts.has(FONT.as_bytes()));
+            shown[1].clone()
+        }).collect()
+    }
+
+    #[test]
+    fn test_bates() {
+        let options = StampOptions::new("ACME{n}", Some(41), None, None).unwrap();
+        let (stamped, pages) = stamp(&pdf(3), &options, options.start).unwrap();
+        assert_eq!(pages, 3);
+        assert_eq!(stamps(&stamped), ["ACME000041", "ACME000042", "ACME000043"]);
+        assert!(stamp(&pdf(3), &options, u64::MAX - 1).unwrap_err().contains("overflows"));
+        assert_eq!(last_number(u64::MAX - 2, 3), Ok(u64::MAX));
+    }
+
+    #[test]
+    fn test_page_labels() {
+        let options = StampOptions::new("Page {page} of {pages}", None, None, Some("bottom-center")).unwrap();
+        let (stamped, _) = stamp(&pdf(2), &options, 1).unwrap();
+        assert_eq!(stamps(&stamped), ["Page 1 of 2", "Page 2 of 2"]);
+    }
+
+    #[test]
+    fn test_options() {
+        assert!(StampOptions::new(" ", None, None, None).is_err());
+        assert!(StampOptions::new("{n}", None, Some(20), None).is_err());
+        assert!(StampOptions::new("{n}", None, None, Some("middle")).is_err());
+        assert!(StampOptions::new("Σ{n}", None, None, None).is_err());
+        assert_eq!(StampOptions::new("X-{n}", None, Some(3), None).unwrap().label(7, 1, 1), "X-007");
+        assert!((text_width("00") - 11.12).abs() < 1e-4);
+    }
+}
@@ -0,0 +1,503 @@
+//! Byte storage behind the caches and artifact stores: in-memory, local disk or S3.
+
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use bytes::Bytes;
+use futures_util::future::BoxFuture;
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+use tokio::io::AsyncReadExt;
+
+use crate::settings::Settings;
+use crate::shard::ShardedMap;
+
+/// A flat key/value byte store. Keys are `/`-separated paths such as `outputs/<hash>`.
+/// Reads return `Bytes` so in-memory objects are shared rather than copied.
+pub trait Storage: Send + Sync {
+    fn name(&self) -> &'static str;
+    fn get<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<Option<Bytes>, String>>;
+    fn put<'a>(&'a self, key: &'a str, data: Vec<u8>) -> BoxFuture<'a, Result<(), String>>;
+    /// Size of the object under `key` as stored, `None` when there is none. Cheaper than
+    /// `get` or `list` for an existence check (a HEAD request on S3).
+    fn head<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<Option<u64>, String>>;
+    /// Removing a missing key is not an error.
+    fn delete<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<(), String>>;
+    /// Keys starting with `prefix`, in no particular order.
+    fn list<'a>(&'a self, prefix: &'a str) -> BoxFuture<'a, Result<Vec<String>, String>>;
+    /// Stores the file at `path` under `key`. Backends that can stream it override this;
+    /// the default reads the whole file into memory.
+    fn put_file<'a>(&'a self, key: &'a str, path: &'a Path) -> BoxFuture<'a, Result<(), String>> {
+        Box::pin(async move {
+            let data = tokio::fs::read(path).await.map_err(|e| format!("Failed to read {:?}: {}", path, e))?;
+            self.put(key, data).await
+        })
+    }
+}
+
+/// Builds the backend selected by `STORAGE_BACKEND` (`memory`, `disk` or `s3`).
+pub fn from_settings(settings: &Settings) -> Result<Arc<dyn Storage>, String> {
+    match settings.storage_backend.as_str() {
+        "memory" => Ok(Arc::new(MemoryStorage::new())),
+        "disk" => Ok(Arc::new(DiskStorage::new(&settings.storage_dir)?)),
+        "s3" => Ok(Arc::new(S3Storage::from_settings(settings)?)),
+        other => Err(format!("Unknown STORAGE_BACKEND '{}' (expected memory, disk or s3)", other)),
+    }
+}
+
+// ============================================================================
+// In-Memory
+// ============================================================================
+
+#[derive(Default)]
+pub struct MemoryStorage {
+    objects: ShardedMap<String, Bytes>,
+}
+
+impl MemoryStorage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Storage for MemoryStorage {
+    fn name(&self) -> &'static str {
+        "memory"
+    }
+
+    fn get<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<Option<Bytes>, String>> {
+        Box::pin(async move { Ok(self.objects.shard(key).read().await.get(key).cloned()) })
+    }
+
+    fn put<'a>(&'a self, key: &'a str, data: Vec<u8>) -> BoxFuture<'a, Result<(), String>> {
+        Box::pin(async move {
+            self.objects.shard(key).write().await.insert(key.to_string(), Bytes::from(data));
+            Ok(())
+        })
+    }
+
+    fn head<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<Option<u64>, String>> {
+        Box::pin(async move { Ok(self.objects.shard(key).read().await.get(key).map(|data| data.len() as u64)) })
+    }
+
+    fn delete<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<(), String>> {
+        Box::pin(async move {
+            self.objects.shard(key).write().await.remove(key);
+            Ok(())
+        })
+    }
+
+    fn list<'a>(&'a self, prefix: &'a str) -> BoxFuture<'a, Result<Vec<String>, String>> {
+        Box::pin(async move {
+            let mut keys = Vec::new();
+            for shard in self.objects.shards() {
+                keys.extend(shard.read().await.keys().filter(|k| k.starts_with(prefix)).cloned());
+            }
+            Ok(keys)
+        })
+    }
+}
+
+// ============================================================================
+// Local Disk
+// ============================================================================
+
+pub struct DiskStorage {
+    root: PathBuf,
+}
+
+impl DiskStorage {
+    pub fn new(root: &Path) -> Result<Self, String> {
+        std::fs::create_dir_all(root).map_err(|e| format!("Cannot create storage dir {:?}: {}", root, e))?;
+        Ok(Self { root: root.to_path_buf() })
+    }
+
+    /// Maps a key under the root, refusing anything that could escape it.
+    fn path(&self, key: &str) -> Result<PathBuf, String> {
+        let relative = Path::new(key);
+        if key.is_empty() || !relative.components().all(|c| matches!(c, Component::Normal(_))) {
+            return Err(format!("Invalid storage key '{}'", key));
+        }
+        Ok(self.root.join(relative))
+    }
+}
+
+impl Storage for DiskStorage {
+    fn name(&self) -> &'static str {
+        "disk"
+    }
+
+    fn get<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<Option<Bytes>, String>> {
+        Box::pin(async move {
+            match tokio::fs::read(self.path(key)?).await {
+                Ok(data) => Ok(Some(Bytes::from(data))),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+                Err(e) => Err(format!("Failed to read {}: {}", key, e)),
+            }
+        })
+    }
+
+    fn put<'a>(&'a self, key: &'a str, data: Vec<u8>) -> BoxFuture<'a, Result<(), String>> {
+        Box::pin(async move {
+            let path = self.path(key)?;
+            if let Some(parent) = path.parent() {
+                tokio::fs::create_dir_all(parent).await.map_err(|e| format!("Failed to create {:?}: {}", parent, e))?;
+            }
+            // Write then rename so readers never observe a partial object
+            let tmp = path.with_extension(format!("tmp-{}", uuid::Uuid::new_v4().simple()));
+            tokio::fs::write(&tmp, data).await.map_err(|e| format!("Failed to write {}: {}", key, e))?;
+            tokio::fs::rename(&tmp, &path).await.map_err(|e| format!("Failed to write {}: {}", key, e))
+        })
+    }
+
+    fn head<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<Option<u64>, String>> {
+        Box::pin(async move {
+            match tokio::fs::metadata(self.path(key)?).await {
+                Ok(metadata) => Ok(Some(metadata.len())),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+                Err(e) => Err(format!("Failed to stat {}: {}", key, e)),
+            }
+        })
+    }
+
+    fn put_file<'a>(&'a self, key: &'a str, source: &'a Path) -> BoxFuture<'a, Result<(), String>> {
+        Box::pin(async move {
+            let path = self.path(key)?;
+            if let Some(parent) = path.parent() {
+                tokio::fs::create_dir_all(parent).await.map_err(|e| format!("Failed to create {:?}: {}", parent, e))?;
+            }
+            let tmp = path.with_extension(format!("tmp-{}", uuid::Uuid::new_v4().simple()));
+            let copied = match tokio::fs::copy(source, &tmp).await {
+                Ok(_) => tokio::fs::rename(&tmp, &path).await,
+                Err(e) => Err(e),
+            };
+            if copied.is_err() {
+                tokio::fs::remove_file(&tmp).await.ok();
+            }
+            copied.map_err(|e| format!("Failed to write {}: {}", key, e))
+        })
+    }
+
+    fn delete<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<(), String>> {
+        Box::pin(async move {
+            match tokio::fs::remove_file(self.path(key)?).await {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(format!("Failed to delete {}: {}", key, e)),
+                _ => Ok(()),
+            }
+        })
+    }
+
+    fn list<'a>(&'a self, prefix: &'a str) -> BoxFuture<'a, Result<Vec<String>, String>> {
+        Box::pin(async move {
+            // Only walk the directory the prefix points into
+            let dir = match prefix.rfind('/') {
+                Some(i) => &prefix[..i],
+                None => "",
+            };
+            let mut keys = Vec::new();
+            let mut pending = vec![dir.to_string()];
+            while let Some(dir) = pending.pop() {
+                let mut entries = match tokio::fs::read_dir(self.root.join(&dir)).await {
+                    Ok(entries) => entries,
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                    Err(e) => return Err(format!("Failed to list {}: {}", dir, e)),
+                };
+                while let Ok(Some(entry)) = entries.next_entry().await {
+                    let name = entry.file_name().to_string_lossy().to_string();
+                    let key = if dir.is_empty() { name } else { format!("{}/{}", dir, name) };
+                    match entry.file_type().await {
+                        Ok(t) if t.is_dir() => pending.push(key),
+                        Ok(_) if key.starts_with(prefix) && !key.contains(".tmp-") => keys.push(key),
+                        _ => {}
+                    }
+                }
+            }
+            Ok(keys)
+        })
+    }
+}
+
+// ============================================================================
+// S3 (and S3-compatible: MinIO, R2, ...)
+// ============================================================================
+
+/// Path-style S3 client signed with AWS Signature Version 4.
+pub struct S3Storage {
+    client: reqwest::Client,
+    endpoint: reqwest::Url,
+    bucket: String,
+    prefix: String,
+    region: String,
+    access_key: String,
+    secret_key: String,
+}
+
+impl S3Storage {
+    pub fn from_settings(settings: &Settings) -> Result<Self, String> {
+        let required = |value: &Option<String>, name: &str| {
+            value.clone().ok_or_else(|| format!("{} is required for the s3 storage backend", name))
+        };
+        let region = settings.s3_region.clone();
+        let endpoint = settings.s3_endpoint.clone()
+            .unwrap_or_else(|| format!("https://s3.{}.amazonaws.com", region));
+        Ok(Self {
+            client: reqwest::Client::new(),
+            endpoint: reqwest::Url::parse(&endpoint).map_err(|e| format!("Invalid S3_ENDPOINT '{}': {}", endpoint, e))?,
+            bucket: required(&settings.s3_bucket, "S3_BUCKET")?,
+            prefix: settings.s3_prefix.clone(),
+            region,
+            access_key: required(&settings.s3_access_key_id, "S3_ACCESS_KEY_ID")?,
+            secret_key: required(&settings.s3_secret_access_key, "S3_SECRET_ACCESS_KEY")?,
+        })
+    }
+
+    fn object_path(&self, key: &str) -> String {
+        format!("/{}/{}{}", self.bucket, self.prefix, key)
+    }
+
+    async fn send(&self, method: reqwest::Method, path: &str, query: &[(&str, &str)], body: Vec<u8>) -> Result<reqwest::Response, String> {
+        let payload_hash = hex::encode(Sha256::digest(&body));
+        self.signed(method, path, query, payload_hash)
+            .body(body)
+            .send()
+            .await
+            .map_err(|e| format!("S3 request for {} failed: {}", path, e))
+    }
+
+    /// A request for `path` signed for a payload whose SHA-256 is `payload_hash`.
+    fn signed(&self, method: reqwest::Method, path: &str, query: &[(&str, &str)], payload_hash: String) -> reqwest::RequestBuilder {
+        let canonical_uri: String = path.split('/').map(uri_encode).collect::<Vec<_>>().join("/");
+        let mut query: Vec<String> = query.iter().map(|(k, v)| format!("{}={}", uri_encode(k), uri_encode(v))).collect();
+        query.sort();
+        let canonical_query = query.join("&");
+        let mut url = self.endpoint.clone();
+        url.set_path(&canonical_uri);
+        url.set_query(Some(&canonical_query).filter(|q| !q.is_empty()).map(|q| q.as_str()));
+        let host = match url.port() {
+            Some(port) => format!("{}:{}", url.host_str().unwrap_or_default(), port),
+            None => url.host_str().unwrap_or_default().to_string(),
+        };
+
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+        let (amz_date, date) = amz_timestamp(now);
+        let signed_headers = "host;x-amz-content-sha256;x-amz-date";
+        let canonical_request = format!(
+            "{}\n{}\n{}\nhost:{}\nx-amz-content-sha256:{}\nx-amz-date:{}\n\n{}\n{}",
+            method, canonical_uri, canonical_query, host, payload_hash, amz_date, signed_headers, payload_hash
+        );
+        let scope = format!("{}/{}/s3/aws4_request", date, self.region);
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+            amz_date, scope, hex::encode(Sha256::digest(canonical_request.as_bytes()))
+        );
+        let signing_key = signing_key(&self.secret_key, &date, &self.region, "s3");
+        let signature = hex::encode(hmac_sha256(&signing_key, string_to_sign.as_bytes()));
+
+        self.client.request(method, url)
+            .header("x-amz-content-sha256", payload_hash)
+            .header("x-amz-date", amz_date)
+            .header(reqwest::header::AUTHORIZATION, format!(
+                "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+                self.access_key, scope, signed_headers, signature
+            ))
+    }
+}
+
+impl Storage for S3Storage {
+    fn name(&self) -> &'static str {
+        "s3"
+    }
+
+    fn get<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<Option<Bytes>, String>> {
+        Box::pin(async move {
+            let response = self.send(reqwest::Method::GET, &self.object_path(key), &[], Vec::new()).await?;
+            match response.status() {
+                reqwest::StatusCode::NOT_FOUND => Ok(None),
+                status if status.is_success() => response.bytes().await
+                    .map(Some)
+                    .map_err(|e| format!("S3 read of {} failed: {}", key, e)),
+                status => Err(format!("S3 GET {} returned {}", key, status)),
+            }
+        })
+    }
+
+    fn put<'a>(&'a self, key: &'a str, data: Vec<u8>) -> BoxFuture<'a, Result<(), String>> {
+        Box::pin(async move {
+            let response = self.send(reqwest::Method::PUT, &self.object_path(key), &[], data).await?;
+            if response.status().is_success() {
+                Ok(())
+            } else {
+                Err(format!("S3 PUT {} returned {}", key, response.status()))
+            }
+        })
+    }
+
+    fn head<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<Option<u64>, String>> {
+        Box::pin(async move {
+            let response = self.send(reqwest::Method::HEAD, &self.object_path(key), &[], Vec::new()).await?;
+            match response.status() {
+                reqwest::StatusCode::NOT_FOUND => Ok(None),
+                status if status.is_success() => Ok(Some(response.content_length().unwrap_or(0))),
+                status => Err(format!("S3 HEAD {} returned {}", key, status)),
+            }
+        })
+    }
+
+    fn put_file<'a>(&'a self, key: &'a str, source: &'a Path) -> BoxFuture<'a, Result<(), String>> {
+        Box::pin(async move {
+            let read_error = |e: std::io::Error| format!("Failed to read {:?}: {}", source, e);
+            // The signature covers the payload hash: one pass to hash, a second to send
+            let mut file = tokio::fs::File::open(source).await.map_err(read_error)?;
+            let mut hasher = Sha256::new();
+            let mut buffer = vec![0u8; 64 * 1024];
+            loop {
+                let read = file.read(&mut buffer).await.map_err(read_error)?;
+                if read == 0 {
+                    break;
+                }
+                hasher.update(&buffer[..read]);
+            }
+            let file = tokio::fs::File::open(source).await.map_err(read_error)?;
+            let length = file.metadata().await.map_err(read_error)?.len();
+            let path = self.object_path(key);
+            let response = self.signed(reqwest::Method::PUT, &path, &[], hex::encode(hasher.finalize()))
+                .header(reqwest::header::CONTENT_LENGTH, length)
+                .body(reqwest::Body::from(file))
+                .send()
+                .await
+                .map_err(|e| format!("S3 request for {} failed: {}", path, e))?;
+            if response.status().is_success() {
+                Ok(())
+            } else {
+                Err(format!("S3 PUT {} returned {}", key, response.status()))
+            }
+        })
+    }
+
+    fn delete<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<(), String>> {
+        Box::pin(async move {
+            let response = self.send(reqwest::Method::DELETE, &self.object_path(key), &[], Vec::new()).await?;
+            if response.status().is_success() || response.status() == reqwest::StatusCode::NOT_FOUND {
+                Ok(())
+            } else {
+                Err(format!("S3 DELETE {} returned {}", key, response.status()))
+            }
+        })
+    }
+
+    fn list<'a>(&'a self, prefix: &'a str) -> BoxFuture<'a, Result<Vec<String>, String>> {
+        Box::pin(async move {
+            let full_prefix = format!("{}{}", self.prefix, prefix);
+            let bucket_path = format!("/{}", self.bucket);
+            let key_re = regex::Regex::new(r"<Key>([^<]*)</Key>").unwrap();
+            let token_re = regex::Regex::new(r"<NextContinuationToken>([^<]*)</NextContinuationToken>").unwrap();
+            let mut keys = Vec::new();
+            let mut token: Option<String> = None;
+            loop {
+                let mut query = vec![("list-type", "2"), ("prefix", full_prefix.as_str())];
+                if let Some(token) = &token {
+                    query.push(("continuation-token", token.as_str()));
+                }
+                let response = self.send(reqwest::Method::GET, &bucket_path, &query, Vec::new()).await?;
+                if !response.status().is_success() {
+                    return Err(format!("S3 LIST {} returned {}", prefix, response.status()));
+                }
+                let xml = response.text().await.map_err(|e| format!("S3 LIST {} failed: {}", prefix, e))?;
+                keys.extend(key_re.captures_iter(&xml)
+                    .map(|c| xml_unescape(&c[1]))
+                    .filter_map(|k| k.strip_prefix(&self.prefix).map(str::to_string)));
+                token = token_re.captures(&xml).map(|c| xml_unescape(&c[1]));
+                if token.is_none() {
+                    return Ok(keys);
+                }
+            }
+        })
+    }
+}
+
+fn xml_unescape(text: &str) -> String {
+    text.replace("&lt;", "<").replace("&gt;", ">").replace("&quot;", "\"").replace("&apos;", "'").replace("&amp;", "&")
+}
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts any key length");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+fn signing_key(secret: &str, date: &str, region: &str, service: &str) -> Vec<u8> {
+    let k_date = hmac_sha256(format!("AWS4{}", secret).as_bytes(), date.as_bytes());
+    let k_region = hmac_sha256(&k_date, region.as_bytes());
+    let k_service = hmac_sha256(&k_region, service.as_bytes());
+    hmac_sha256(&k_service, b"aws4_request")
+}
+
+/// Percent-encodes one path segment as SigV4 expects (everything but unreserved characters).
+fn uri_encode(segment: &str) -> String {
+    segment.bytes().map(|b| match b {
+        b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (b as char).to_string(),
+        _ => format!("%{:02X}", b),
+    }).collect()
+}
+
+/// Returns (`YYYYMMDDTHHMMSSZ`, `YYYYMMDD`) for a unix timestamp.
+fn amz_timestamp(secs: u64) -> (String, String) {
+    let days = (secs / 86400) as i64;
+    let rem = secs % 86400;
+    // Civil-from-days (Howard Hinnant), valid for any date after 1970
+    let z = days + 719468;
+    let era = z / 146097;
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    let date = format!("{:04}{:02}{:02}", year, month, day);
+    (format!("{}T{:02}{:02}{:02}Z", date, rem / 3600, rem % 3600 / 60, rem % 60), date)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_disk_storage_round_trip() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let storage = DiskStorage::new(dir.path()).unwrap();
+        assert_eq!(storage.get("outputs/abc").await.unwrap(), None);
+        assert_eq!(storage.head("outputs/abc").await.unwrap(), None);
+        storage.put("outputs/abc", b"pdf".to_vec()).await.unwrap();
+        assert_eq!(storage.get("outputs/abc").await.unwrap().as_deref(), Some(&b"pdf"[..]));
+        assert_eq!(storage.head("outputs/abc").await.unwrap(), Some(3));
+        storage.put("formats/latex.fmt", b"fmt".to_vec()).await.unwrap();
+        let mut keys = storage.list("outputs/").await.unwrap();
+        keys.sort();
+        assert_eq!(keys, vec!["outputs/abc"]);
+        assert_eq!(storage.list("formats/").await.unwrap(), vec!["formats/latex.fmt"]);
+        storage.delete("outputs/abc").await.unwrap();
+        storage.delete("outputs/abc").await.unwrap();
+        assert!(storage.list("outputs/").await.unwrap().is_empty());
+        assert_eq!(storage.get("outputs/abc").await.unwrap(), None);
+        assert!(storage.put("../escape", vec![]).await.is_err());
+        assert!(storage.get("/etc/passwd").await.is_err());
+
+        let upload = dir.path().join("upload.bin");
+        std::fs::write(&upload, b"streamed").unwrap();
+        storage.put_file("blobs/up", &upload).await.unwrap();
+        assert_eq!(storage.get("blobs/up").await.unwrap().as_deref(), Some(&b"streamed"[..]));
+        assert!(storage.put_file("blobs/missing", &dir.path().join("missing")).await.is_err());
+        assert_eq!(storage.list("blobs/").await.unwrap(), vec!["blobs/up"], "no temporary file is left behind");
+    }
+
+    #[test]
+    fn test_sigv4_helpers() {
+        assert_eq!(amz_timestamp(1369353600), ("20130524T000000Z".to_string(), "20130524".to_string()));
+        assert_eq!(amz_timestamp(951827696).0, "20000229T123456Z");
+        // Derived-key example from the AWS Signature Version 4 documentation
+        let key = signing_key("wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY", "20120215", "us-east-1", "iam");
+        assert_eq!(hex::encode(key), "f4780e2d9f65fa895f9c67b32ce1baf0b0d8a43505a000a1a9e090d414db404d");
+        assert_eq!(uri_encode("a b+c~"), "a%20b%2Bc~");
+    }
+}
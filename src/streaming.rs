@@ -0,0 +1,90 @@
+//! Chunked PDF responses: the PDF is read from the workspace as it is sent, and stats only
+//! known once the output is stored (hash, receipt, fingerprint) follow as HTTP trailers.
+//! Trailers are only sent to clients that ask for them with `TE: trailers`.
+
+use axum::body::Body;
+use axum::http::{HeaderMap, HeaderName};
+use bytes::Bytes;
+use futures_util::{stream, StreamExt};
+use http_body::Frame;
+use http_body_util::StreamBody;
+use std::path::Path;
+use tokio::sync::oneshot;
+use tokio_util::io::ReaderStream;
+
+use crate::janitor::Workspace;
+
+/// Bytes per chunk of the response body.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Trailer fields announced in the `Trailer` header of streamed compiles.
+pub const COMPILE_TRAILERS: [HeaderName; 3] = [
+    HeaderName::from_static("x-output-hash"),
+    HeaderName::from_static("x-compile-receipt"),
+    HeaderName::from_static("x-fingerprint"),
+];
+
+/// Streams the PDF at `pdf_path`, or `pdf` when it is not on disk (a remote worker built
+/// it), then `trailers` once they arrive. The workspace lives until the body is done.
+pub async fn pdf_body(workspace: Workspace, pdf_path: &Path, pdf: Bytes, trailers: oneshot::Receiver<HeaderMap>) -> Body {
+    // A file of another size is an upload of the same name that no local compile replaced
+    let file = match tokio::fs::File::open(pdf_path).await {
+        Ok(file) if file.metadata().await.is_ok_and(|m| m.len() == pdf.len() as u64) => Some(file),
+        _ => None,
+    };
+    let data = match file {
+        Some(file) => ReaderStream::with_capacity(file, CHUNK_SIZE).map(|chunk| chunk.map(Frame::data)).boxed(),
+        None => stream::iter(chunks(pdf).map(|chunk| Ok(Frame::data(chunk)))).boxed(),
+    };
+    let trailers = stream::once(async move {
+        drop(workspace);
+        trailers.await.ok().map(|map| Ok(Frame::trailers(map)))
+    })
+    .filter_map(futures_util::future::ready);
+    Body::new(StreamBody::new(data.chain(trailers)))
+}
+
+fn chunks(data: Bytes) -> impl Iterator<Item = Bytes> {
+    (0..data.len()).step_by(CHUNK_SIZE).map(move |start| data.slice(start..(start + CHUNK_SIZE).min(data.len())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http_body_util::BodyExt;
+
+    #[tokio::test]
+    async fn test_streams_file_then_trailers() {
+        let janitor = crate::janitor::Janitor::new(0);
+        let workspace = janitor.workspace().unwrap();
+        let pdf_path = workspace.path().join("main.pdf");
+        let pdf = b"%PDF-1.7\n".repeat(20_000);
+        std::fs::write(&pdf_path, &pdf).unwrap();
+
+        let (tx, rx) = oneshot::channel();
+        let mut stats = HeaderMap::new();
+        stats.insert(COMPILE_TRAILERS[0].clone(), "abc123".parse().unwrap());
+        tx.send(stats).unwrap();
+
+        let collected = pdf_body(workspace, &pdf_path, Bytes::from(pdf.clone()), rx).await.collect().await.unwrap();
+        assert_eq!(collected.trailers().unwrap()["x-output-hash"], "abc123");
+        assert_eq!(collected.to_bytes(), pdf);
+        assert!(!pdf_path.exists(), "workspace removed once the body is done");
+    }
+
+    #[tokio::test]
+    async fn test_falls_back_to_bytes_without_trailers() {
+        let workspace = crate::janitor::Janitor::new(0).workspace().unwrap();
+        // An uploaded main.pdf the remote build did not replace is not sent
+        let pdf_path = workspace.path().join("main.pdf");
+        std::fs::write(&pdf_path, b"%PDF-1.4 uploaded").unwrap();
+        let pdf = Bytes::from(vec![7u8; CHUNK_SIZE * 2 + 5]);
+        let (tx, rx) = oneshot::channel::<HeaderMap>();
+        drop(tx);
+
+        let collected = pdf_body(workspace, &pdf_path, pdf.clone(), rx).await.collect().await.unwrap();
+        assert!(collected.trailers().is_none());
+        assert_eq!(collected.to_bytes(), pdf);
+        assert_eq!(chunks(pdf).count(), 3);
+    }
+}
@@ -0,0 +1,247 @@
+//! The tenant behind a request (`X-Tenant-Id`, proven by `X-Tenant-Token`) and what it has
+//! stored. Storage is content-addressed and shared, so a ledger records which objects each
+//! tenant wrote and when, for retention policies and erasure requests.
+
+use axum::extract::{ConnectInfo, Request, State};
+use axum::http::StatusCode;
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use bytes::Bytes;
+use futures_util::future::BoxFuture;
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use crate::settings::Settings;
+use crate::storage::Storage;
+use crate::util::{constant_time_eq, unix_now};
+
+tokio::task_local! {
+    /// Tenant of the request being served, set by [`tenant_scope`].
+    pub static TENANT: Option<String>;
+    /// Who sent the request being served, for per-client quotas; see [`client_key`].
+    static CLIENT: String;
+}
+
+/// Middleware recording the request's tenant for the storage layers, once `X-Tenant-Token`
+/// has proven it. A request naming a tenant without its token is refused before any
+/// handler sees it, so handlers may trust the `X-Tenant-Id` header.
+pub async fn tenant_scope(State(settings): State<Arc<Settings>>, request: Request, next: Next) -> Response {
+    let headers = request.headers();
+    let tenant = match headers.get("X-Tenant-Id").map(|v| v.to_str()) {
+        None => None,
+        Some(Ok(tenant)) if authenticate(&settings, tenant, headers.get("X-Tenant-Token").and_then(|v| v.to_str().ok())) => Some(tenant.to_string()),
+        Some(_) => return (StatusCode::UNAUTHORIZED, "Missing or wrong X-Tenant-Token for X-Tenant-Id".to_string()).into_response(),
+    };
+    let peer = request.extensions().get::<ConnectInfo<SocketAddr>>().map(|info| info.0.ip());
+    let client = client_key(tenant.as_deref(), peer);
+    TENANT.scope(tenant, CLIENT.scope(client, next.run(request))).await
+}
+
+/// The tenant, else the peer's IPv4 address or IPv6 /64 (one host's usual allocation),
+/// else `local` for Unix socket clients.
+fn client_key(tenant: Option<&str>, peer: Option<IpAddr>) -> String {
+    match (tenant, peer.map(|ip| ip.to_canonical())) {
+        (Some(tenant), _) => format!("tenant:{}", tenant),
+        (None, Some(IpAddr::V4(ip))) => ip.to_string(),
+        (None, Some(IpAddr::V6(ip))) => {
+            let [a, b, c, d, ..] = ip.segments();
+            format!("{:x}:{:x}:{:x}:{:x}::/64", a, b, c, d)
+        }
+        (None, None) => "local".to_string(),
+    }
+}
+
+/// The client of the current request (`local` outside one).
+pub fn current_client() -> String {
+    CLIENT.try_with(String::clone).unwrap_or_else(|_| "local".to_string())
+}
+
+/// Whether `token` is the one TENANT_TOKENS gives `tenant`.
+fn authenticate(settings: &Settings, tenant: &str, token: Option<&str>) -> bool {
+    let Some(token) = token else {
+        return false;
+    };
+    settings.tenant_tokens.iter()
+        .filter(|(name, _)| name == tenant)
+        .any(|(_, expected)| constant_time_eq(token.as_bytes(), expected.as_bytes()))
+}
+
+pub fn current_tenant() -> Option<String> {
+    TENANT.try_with(|tenant| tenant.clone()).ok().flatten()
+}
+
+/// Spawns `future` as a task of the current request's tenant: task-locals do not follow
+/// `tokio::spawn`, and without the tenant its writes are neither sealed with the tenant's
+/// key nor recorded in the ledger.
+pub fn spawn<F>(future: F) -> tokio::task::JoinHandle<F::Output>
+where
+    F: std::future::Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    tokio::spawn(TENANT.scope(current_tenant(), future))
+}
+
+/// Storage keys written by each tenant, with the time of the last write. Kept in memory:
+/// objects written before a restart are left to the stores' own expiry.
+#[derive(Clone, Default)]
+pub struct TenantLedger {
+    tenants: Arc<Mutex<HashMap<String, HashMap<String, u64>>>>,
+}
+
+impl TenantLedger {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&self, tenant: &str, key: &str, at: u64) {
+        self.tenants.lock().unwrap().entry(tenant.to_string()).or_default().insert(key.to_string(), at);
+    }
+
+    pub fn tenants(&self) -> Vec<String> {
+        self.tenants.lock().unwrap().keys().cloned().collect()
+    }
+
+    /// Takes the tenant's keys last written before `before` (all when `None`) out of the
+    /// ledger, split into keys no other tenant wrote and keys shared with another tenant.
+    pub fn take(&self, tenant: &str, before: Option<u64>) -> (Vec<String>, Vec<String>) {
+        let mut tenants = self.tenants.lock().unwrap();
+        let Some(keys) = tenants.get_mut(tenant) else { return (Vec::new(), Vec::new()) };
+        let taken: Vec<String> = keys.iter().filter(|(_, at)| before.is_none_or(|b| **at < b)).map(|(key, _)| key.clone()).collect();
+        for key in &taken {
+            keys.remove(key);
+        }
+        if keys.is_empty() {
+            tenants.remove(tenant);
+        }
+        taken.into_iter().partition(|key| !tenants.values().any(|other| other.contains_key(key)))
+    }
+
+    fn forget(&self, key: &str) {
+        let mut tenants = self.tenants.lock().unwrap();
+        for keys in tenants.values_mut() {
+            keys.remove(key);
+        }
+        tenants.retain(|_, keys| !keys.is_empty());
+    }
+}
+
+/// Records in `ledger` every write made while serving a tenant's request.
+pub struct LedgerStorage {
+    inner: Arc<dyn Storage>,
+    ledger: TenantLedger,
+}
+
+impl LedgerStorage {
+    pub fn new(inner: Arc<dyn Storage>, ledger: TenantLedger) -> Self {
+        Self { inner, ledger }
+    }
+}
+
+impl Storage for LedgerStorage {
+    fn name(&self) -> &'static str {
+        self.inner.name()
+    }
+
+    fn get<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<Option<Bytes>, String>> {
+        self.inner.get(key)
+    }
+
+    fn put<'a>(&'a self, key: &'a str, data: Vec<u8>) -> BoxFuture<'a, Result<(), String>> {
+        Box::pin(async move {
+            self.inner.put(key, data).await?;
+            if let Some(tenant) = current_tenant() {
+                self.ledger.record(&tenant, key, unix_now());
+            }
+            Ok(())
+        })
+    }
+
+    fn head<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<Option<u64>, String>> {
+        self.inner.head(key)
+    }
+
+    fn put_file<'a>(&'a self, key: &'a str, path: &'a Path) -> BoxFuture<'a, Result<(), String>> {
+        Box::pin(async move {
+            self.inner.put_file(key, path).await?;
+            if let Some(tenant) = current_tenant() {
+                self.ledger.record(&tenant, key, unix_now());
+            }
+            Ok(())
+        })
+    }
+
+    fn delete<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<(), String>> {
+        Box::pin(async move {
+            self.inner.delete(key).await?;
+            self.ledger.forget(key);
+            Ok(())
+        })
+    }
+
+    fn list<'a>(&'a self, prefix: &'a str) -> BoxFuture<'a, Result<Vec<String>, String>> {
+        self.inner.list(prefix)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tenant_needs_its_token() {
+        let settings = Settings { tenant_tokens: vec![("acme".into(), "s3cret".into())], ..Settings::from_env() };
+        assert!(authenticate(&settings, "acme", Some("s3cret")));
+        assert!(!authenticate(&settings, "acme", Some("wrong")));
+        assert!(!authenticate(&settings, "acme", None));
+        assert!(!authenticate(&settings, "globex", Some("s3cret")));
+    }
+
+    #[test]
+    fn test_client_key() {
+        assert_eq!(client_key(Some("acme"), Some([10, 0, 0, 1].into())), "tenant:acme");
+        assert_eq!(client_key(None, Some([203, 0, 113, 7].into())), "203.0.113.7");
+        assert_eq!(client_key(None, Some("::ffff:203.0.113.7".parse().unwrap())), "203.0.113.7");
+        assert_eq!(client_key(None, Some("2001:db8:1:2:3:4:5:6".parse().unwrap())), "2001:db8:1:2::/64");
+        assert_eq!(client_key(None, None), "local");
+    }
+
+    #[tokio::test]
+    async fn test_ledger_records_tenant_writes() {
+        let ledger = TenantLedger::new();
+        let storage = LedgerStorage::new(Arc::new(crate::storage::MemoryStorage::new()), ledger.clone());
+        storage.put("cache/anonymous", b"x".to_vec()).await.unwrap();
+        TENANT.scope(Some("acme".into()), async {
+            storage.put("blobs/a", b"a".to_vec()).await.unwrap();
+            storage.put("blobs/shared", b"s".to_vec()).await.unwrap();
+        }).await;
+        TENANT.scope(Some("globex".into()), storage.put("blobs/shared", b"s".to_vec())).await.unwrap();
+        assert_eq!(ledger.tenants().len(), 2);
+
+        assert_eq!(ledger.take("acme", Some(0)), (Vec::new(), Vec::new()));
+        let (mut own, shared) = ledger.take("acme", None);
+        own.sort();
+        assert_eq!((own, shared), (vec!["blobs/a".to_string()], vec!["blobs/shared".to_string()]));
+        assert_eq!(ledger.tenants(), ["globex"]);
+
+        storage.delete("blobs/shared").await.unwrap();
+        assert!(ledger.tenants().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_spawned_tasks_keep_tenant() {
+        let ledger = TenantLedger::new();
+        let storage = Arc::new(LedgerStorage::new(Arc::new(crate::storage::MemoryStorage::new()), ledger.clone()));
+        let task = {
+            let storage = storage.clone();
+            TENANT.sync_scope(Some("acme".into()), || spawn(async move {
+                storage.put("outputs/streamed", b"pdf".to_vec()).await.unwrap();
+                current_tenant()
+            }))
+        };
+        // The request's scope has ended by the time the task runs
+        assert_eq!(task.await.unwrap().as_deref(), Some("acme"));
+        assert_eq!(ledger.take("acme", None).0, ["outputs/streamed"]);
+    }
+}
@@ -0,0 +1,363 @@
+//! TeX log parser: errors, warnings and bad boxes, with the file and line they come from.
+//!
+//! Lines the engine wrapped at 79 columns are joined first, so long messages and paths
+//! are read whole. A stack of the files TeX has open is kept from the `(file` and `)` in
+//! the log, and the lines following a message are read in the state it leaves the parser
+//! in: an error's continuation and `l.N` context, a warning's `(package)` continuation
+//! lines, or a bad box's contents.
+
+use regex::Regex;
+use std::sync::OnceLock;
+
+use crate::compiler::rewrap_log;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Category {
+    Error,
+    Warning,
+    BadBox,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogEntry {
+    pub category: Category,
+    /// Package or class that raised it, e.g. `babel` for `Package babel Error`
+    pub package: Option<String>,
+    /// File TeX was reading, as the log names it
+    pub file: Option<String>,
+    pub line: Option<u32>,
+    pub column: Option<usize>,
+    /// The source TeX had read when it stopped, shown after `l.N`
+    pub context: Option<String>,
+    pub message: String,
+}
+
+impl LogEntry {
+    fn new(category: Category, message: &str, file: Option<String>) -> Self {
+        Self { category, package: None, file, line: None, column: None, context: None, message: message.trim().to_string() }
+    }
+}
+
+/// Where the parser is: what the next lines belong to.
+enum State {
+    Normal,
+    /// After `! ...`: continuation lines of a package error, then the `l.N` context
+    Error { index: usize, continued: bool, lines: usize },
+    /// After the `l.N` line: the rest of the context and TeX's help text, up to a blank line
+    Help { lines: usize },
+    /// After a warning: lines starting with `prefix` continue it
+    Warning { index: usize, prefix: String },
+    /// After a bad box: the box's contents, up to a blank line
+    BadBox,
+}
+
+/// How many lines after an error its `l.N` context is looked for.
+const MAX_CONTEXT_LINES: usize = 20;
+
+/// How many lines of help text follow an error at most.
+const MAX_HELP_LINES: usize = 12;
+
+struct Patterns {
+    located: Regex,
+    package_error: Regex,
+    warning: Regex,
+    bad_box: Regex,
+    context: Regex,
+    input_line: Regex,
+    box_lines: Regex,
+    extension: Regex,
+}
+
+impl Patterns {
+    /// Compiled once, on the first parse.
+    fn get() -> &'static Self {
+        static PATTERNS: OnceLock<Patterns> = OnceLock::new();
+        PATTERNS.get_or_init(Self::new)
+    }
+
+    fn new() -> Self {
+        Self {
+            // Tectonic's own report of an engine message: "[Error] main.tex:9: ..."
+            located: Regex::new(r"^\[(Error|Warning)\] ([^:]+):(\d+): (.*)").unwrap(),
+            package_error: Regex::new(r"^(?:Package|Class) (\S+) Error:").unwrap(),
+            warning: Regex::new(r"^(?:(?:Package|Class) (\S+)|LaTeX(?: (\S+))?) Warning:").unwrap(),
+            bad_box: Regex::new(r"^(?:Overfull|Underfull) \\[hv]box").unwrap(),
+            context: Regex::new(r"^l\.(\d+)(.*)").unwrap(),
+            input_line: Regex::new(r"on input line (\d+)").unwrap(),
+            box_lines: Regex::new(r"at lines? (\d+)").unwrap(),
+            extension: Regex::new(r"\.[A-Za-z][A-Za-z0-9_-]*").unwrap(),
+        }
+    }
+}
+
+/// Parses a TeX log, or build logs holding one, into its errors, warnings and bad boxes
+/// in the order they occurred. A message Tectonic reported and the log repeats is given
+/// once.
+pub fn parse(log: &str) -> Vec<LogEntry> {
+    let patterns = Patterns::get();
+    let text = rewrap_log(log, 0);
+    let mut entries: Vec<LogEntry> = Vec::new();
+    let mut files: Vec<Option<String>> = Vec::new();
+    let mut state = State::Normal;
+
+    for line in text.lines() {
+        state = match state {
+            State::Error { index, continued, lines } => {
+                if let Some(caps) = patterns.context.captures(line) {
+                    let entry = &mut entries[index];
+                    entry.line = entry.line.or_else(|| caps[1].parse().ok());
+                    entry.context = Some(caps[2].trim().to_string());
+                    entry.column = Some(context_column(&caps[2]));
+                    State::Help { lines: 0 }
+                } else if line.starts_with("! ") || lines >= MAX_CONTEXT_LINES {
+                    read_line(line, patterns, &mut entries, &mut files)
+                } else if continued && !line.trim().is_empty() && entries[index].package.as_ref().is_some_and(|p| line.starts_with(&format!("({})", p))) {
+                    let prefix = entries[index].package.as_ref().map(|p| p.len() + 2);
+                    append(&mut entries[index], line, prefix);
+                    State::Error { index, continued, lines: lines + 1 }
+                } else {
+                    State::Error { index, continued: false, lines: lines + 1 }
+                }
+            }
+            State::Help { lines } => {
+                if line.trim().is_empty() || lines >= MAX_HELP_LINES {
+                    State::Normal
+                } else if line.starts_with("! ") {
+                    read_line(line, patterns, &mut entries, &mut files)
+                } else {
+                    State::Help { lines: lines + 1 }
+                }
+            }
+            State::Warning { index, prefix } => {
+                if line.starts_with(&prefix) {
+                    append(&mut entries[index], line, Some(prefix.len()));
+                    State::Warning { index, prefix }
+                } else {
+                    read_line(line, patterns, &mut entries, &mut files)
+                }
+            }
+            State::BadBox => {
+                if line.trim().is_empty() {
+                    State::Normal
+                } else if line.starts_with("! ") || patterns.bad_box.is_match(line) || patterns.warning.is_match(line) {
+                    read_line(line, patterns, &mut entries, &mut files)
+                } else {
+                    State::BadBox
+                }
+            }
+            State::Normal => read_line(line, patterns, &mut entries, &mut files),
+        };
+    }
+
+    for entry in &mut entries {
+        if entry.line.is_none() {
+            let pattern = if entry.category == Category::BadBox { &patterns.box_lines } else { &patterns.input_line };
+            entry.line = pattern.captures(&entry.message).and_then(|caps| caps[1].parse().ok());
+        }
+    }
+    merge_duplicates(entries)
+}
+
+/// Reads a line outside any message: the start of a new one, or file openings and
+/// closings.
+fn read_line(line: &str, patterns: &Patterns, entries: &mut Vec<LogEntry>, files: &mut Vec<Option<String>>) -> State {
+    let current = || files.iter().rev().find_map(|file| file.clone());
+
+    if let Some(caps) = patterns.located.captures(line) {
+        let category = match (&caps[1], patterns.bad_box.is_match(&caps[4])) {
+            ("Error", _) => Category::Error,
+            (_, true) => Category::BadBox,
+            _ => Category::Warning,
+        };
+        let mut entry = LogEntry::new(category, &caps[4], Some(caps[2].trim().to_string()));
+        entry.line = caps[3].parse().ok();
+        entry.package = package_of(&entry.message, patterns);
+        entries.push(entry);
+        return State::Normal;
+    }
+
+    let error = line.strip_prefix('!').or_else(|| line.strip_prefix("error:"));
+    if let Some(message) = error {
+        // Tectonic's note that it stopped, not an error of its own
+        if message.contains("halted on potentially-recoverable error") {
+            return State::Normal;
+        }
+        let mut entry = LogEntry::new(Category::Error, message, current());
+        entry.package = package_of(&entry.message, patterns);
+        let continued = entry.package.is_some();
+        entries.push(entry);
+        return State::Error { index: entries.len() - 1, continued, lines: 0 };
+    }
+
+    if let Some(caps) = patterns.warning.captures(line) {
+        let mut entry = LogEntry::new(Category::Warning, line, current());
+        // `LaTeX Font Warning` continues with `(Font)`, LaTeX's own with `(LaTeX)`
+        let name = caps.get(1).or(caps.get(2)).map(|m| m.as_str().to_string());
+        entry.package = caps.get(1).map(|m| m.as_str().to_string());
+        entries.push(entry);
+        return State::Warning { index: entries.len() - 1, prefix: format!("({})", name.unwrap_or_else(|| "LaTeX".to_string())) };
+    }
+
+    if patterns.bad_box.is_match(line) {
+        entries.push(LogEntry::new(Category::BadBox, line, current()));
+        return State::BadBox;
+    }
+
+    track_files(line, patterns, files);
+    State::Normal
+}
+
+/// Follows the files TeX opens, `(path`, and closes, `)`. Parentheses that do not open a
+/// file are kept on the stack too, so their `)` does not close one.
+fn track_files(line: &str, patterns: &Patterns, files: &mut Vec<Option<String>>) {
+    for (i, c) in line.char_indices() {
+        match c {
+            '(' => files.push(file_at(&line[i + 1..], patterns)),
+            ')' => {
+                files.pop();
+            }
+            _ => {}
+        }
+    }
+}
+
+/// The file named right after a `(`: text ending in an extension, which may hold
+/// spaces only when it is a path (`C:\Program Files\...\article.cls`).
+fn file_at(rest: &str, patterns: &Patterns) -> Option<String> {
+    let rest = &rest[..rest.find(['(', ')']).unwrap_or(rest.len())];
+    if rest.starts_with(char::is_whitespace) {
+        return None;
+    }
+    patterns.extension.find_iter(rest)
+        .map(|m| &rest[..m.end()])
+        .filter(|candidate| rest[candidate.len()..].chars().next().is_none_or(char::is_whitespace))
+        .find(|candidate| !candidate.contains(char::is_whitespace) || candidate.contains(['/', '\\']))
+        .map(str::to_string)
+}
+
+fn package_of(message: &str, patterns: &Patterns) -> Option<String> {
+    patterns.package_error.captures(message)
+        .or_else(|| patterns.warning.captures(message).filter(|caps| caps.get(1).is_some()))
+        .map(|caps| caps[1].to_string())
+}
+
+/// Adds a continuation line, without its `(package)` prefix, to the message.
+fn append(entry: &mut LogEntry, line: &str, prefix_len: Option<usize>) {
+    let text = line.get(prefix_len.unwrap_or(0)..).unwrap_or(line).trim();
+    if !text.is_empty() {
+        entry.message.push(' ');
+        entry.message.push_str(text);
+    }
+}
+
+/// Merges each message Tectonic reported with the same one read from the log dumped after
+/// it, or reported again by a later run, keeping the details either has.
+pub fn merge_duplicates(entries: Vec<LogEntry>) -> Vec<LogEntry> {
+    let same = |a: &LogEntry, b: &LogEntry| {
+        let file = |e: &LogEntry| e.file.as_deref().map(|f| f.trim_start_matches("./").to_string());
+        a.category == b.category
+            && a.line == b.line
+            && a.message.trim_end_matches('.') == b.message.trim_end_matches('.')
+            && (file(a) == file(b) || a.file.is_none() || b.file.is_none())
+    };
+    let mut merged: Vec<LogEntry> = Vec::with_capacity(entries.len());
+    for entry in entries {
+        match merged.iter_mut().find(|existing| same(existing, &entry)) {
+            Some(existing) => {
+                existing.file = existing.file.take().or(entry.file);
+                existing.column = existing.column.or(entry.column);
+                existing.context = existing.context.take().or(entry.context);
+                existing.package = existing.package.take().or(entry.package);
+            }
+            None => merged.push(entry),
+        }
+    }
+    merged
+}
+
+/// TeX prints "l.N <text read so far>", breaking the line where it stopped reading,
+/// so the length of that text is the (1-based) column of the offending token's end.
+pub fn context_column(context: &str) -> usize {
+    context.strip_prefix(' ').unwrap_or(context).chars().count().max(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn of(entries: &[LogEntry], category: Category) -> Vec<&LogEntry> {
+        entries.iter().filter(|e| e.category == category).collect()
+    }
+
+    #[test]
+    fn test_undefined_control_sequence() {
+        let entries = parse(include_str!("../tests/logs/undefined-control-sequence.log"));
+        let errors = of(&entries, Category::Error);
+        assert_eq!(errors.len(), 2, "{:#?}", errors);
+
+        // Raised in an \input chapter whose path was wrapped at 79 columns
+        assert_eq!(errors[0].message, "Undefined control sequence.");
+        assert_eq!(errors[0].file.as_deref(), Some("./chapters/introduction-and-motivation-for-the-proposed-method-in-this-thesis.tex"));
+        assert_eq!((errors[0].line, errors[0].column), (Some(14), Some(21)));
+        assert_eq!(errors[0].context.as_deref(), Some("The results in \\citep"));
+
+        // Back in main.tex after the chapter closed; the message spans three wrapped lines
+        assert_eq!(errors[1].package.as_deref(), Some("pgfkeys"));
+        assert_eq!(errors[1].file.as_deref(), Some("./main.tex"));
+        assert_eq!(errors[1].line, Some(31));
+        assert!(errors[1].message.starts_with("Package pgfkeys Error: I do not know the key '/tikz/"));
+        assert!(errors[1].message.ends_with("Perhaps you misspelled it."));
+    }
+
+    #[test]
+    fn test_package_error_continuation() {
+        let entries = parse(include_str!("../tests/logs/babel-error.log"));
+        let errors = of(&entries, Category::Error);
+        assert_eq!(errors.len(), 1, "{:#?}", errors);
+        assert_eq!(errors[0].package.as_deref(), Some("babel"));
+        assert_eq!(errors[0].message, "Package babel Error: Unknown option 'klingon'. Either you misspelled it or the language definition file klingon.ldf was not found.");
+        assert_eq!((errors[0].file.as_deref(), errors[0].line), (Some("./main.tex"), Some(4)));
+    }
+
+    #[test]
+    fn test_warnings_and_bad_boxes() {
+        let entries = parse(include_str!("../tests/logs/warnings.log"));
+        let warnings = of(&entries, Category::Warning);
+        let messages: Vec<&str> = warnings.iter().map(|w| w.message.as_str()).collect();
+        assert_eq!(messages, [
+            "LaTeX Warning: Reference `fig:results' on page 2 undefined on input line 48.",
+            "Package natbib Warning: Citation `knuth1984' on page 3 undefined on input line 57.",
+            "LaTeX Warning: Label `eq:main' multiply defined.",
+            "Package hyperref Warning: Token not allowed in a PDF string (Unicode): removing `math shift' on input line 70.",
+            "LaTeX Font Warning: Font shape `TU/lmr/bx/sc' undefined using `TU/lmr/bx/n' instead on input line 75.",
+            "LaTeX Warning: There were undefined references.",
+        ]);
+        assert_eq!(warnings.iter().map(|w| w.line).collect::<Vec<_>>(), [Some(48), Some(57), None, Some(70), Some(75), None]);
+        assert_eq!(warnings[1].package.as_deref(), Some("natbib"));
+        assert_eq!(warnings[3].file.as_deref(), Some("./sections/results.tex"));
+
+        let boxes = of(&entries, Category::BadBox);
+        assert_eq!(boxes.iter().map(|b| b.line).collect::<Vec<_>>(), [Some(61), None, Some(80)]);
+        assert!(boxes[0].message.starts_with("Overfull \\hbox (15.11617pt too wide)"));
+        assert!(of(&entries, Category::Error).is_empty(), "unbalanced text in a box does not derail the parser");
+    }
+
+    #[test]
+    fn test_real_log() {
+        // pdfTeX on MiKTeX, with paths holding spaces
+        let entries = parse(include_str!("../test/main.log"));
+        assert!(of(&entries, Category::Error).is_empty());
+        let boxes = of(&entries, Category::BadBox);
+        assert_eq!(boxes.len(), 5);
+        assert_eq!(boxes[1].line, Some(247));
+        assert!(boxes.iter().all(|b| b.file.as_deref() == Some("main.tex")), "{:#?}", boxes);
+    }
+
+    #[test]
+    fn test_tectonic_reports_merge_with_the_log() {
+        let log = "[Error] main.tex:9: Undefined control sequence\n[Error] halted on potentially-recoverable error as specified\n(./main.tex\n! Undefined control sequence.\nl.9 \\foo\n          \n\n)";
+        let entries = parse(log);
+        assert_eq!(entries.len(), 1, "{:#?}", entries);
+        assert_eq!((entries[0].file.as_deref(), entries[0].line, entries[0].column), (Some("main.tex"), Some(9), Some(4)));
+    }
+}
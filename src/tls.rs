@@ -0,0 +1,114 @@
+//! Certificates for `https://` listeners (and so `wss://` live preview): PEM files from
+//! TLS_CERT_FILE / TLS_KEY_FILE, re-read when they change so renewals by certbot or a
+//! secret mount apply without a restart, or certificates obtained over ACME (ACME_DOMAINS).
+
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+use tokio_rustls::rustls;
+use tokio_rustls::rustls::pki_types::pem::PemObject;
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use tokio_rustls::rustls::server::{ClientHello, ResolvesServerCert};
+use tokio_rustls::rustls::sign::CertifiedKey;
+use tokio_rustls::TlsAcceptor;
+use tracing::{info, warn};
+
+use crate::services::AppState;
+use crate::settings::Settings;
+
+/// How often TLS_CERT_FILE and TLS_KEY_FILE are checked for changes.
+const RELOAD_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The certificate served to every client; swapped in place on renewal.
+#[derive(Debug, Default)]
+pub struct Certificates {
+    current: RwLock<Option<Arc<CertifiedKey>>>,
+}
+
+impl Certificates {
+    pub fn set(&self, key: CertifiedKey) {
+        *self.current.write().unwrap() = Some(Arc::new(key));
+    }
+}
+
+impl ResolvesServerCert for Certificates {
+    fn resolve(&self, _client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        self.current.read().unwrap().clone()
+    }
+}
+
+/// Parses a PEM certificate chain and private key.
+pub fn certified_key(cert_pem: &[u8], key_pem: &[u8]) -> Result<CertifiedKey, String> {
+    let chain = CertificateDer::pem_slice_iter(cert_pem).collect::<Result<Vec<_>, _>>().map_err(|e| format!("Invalid certificate: {}", e))?;
+    if chain.is_empty() {
+        return Err("No certificate in PEM data".to_string());
+    }
+    let key = PrivateKeyDer::from_pem_slice(key_pem).map_err(|e| format!("Invalid private key: {}", e))?;
+    let key = rustls::crypto::ring::sign::any_supported_type(&key).map_err(|e| format!("Unsupported private key: {}", e))?;
+    Ok(CertifiedKey::new(chain, key))
+}
+
+/// TLS for `https://` listeners, offering HTTP/2 and HTTP/1.1 over ALPN. With ACME_DOMAINS
+/// the certificate is obtained (or loaded from ACME_CACHE_DIR) in the background; otherwise
+/// TLS_CERT_FILE and TLS_KEY_FILE must load now.
+pub async fn acceptor(state: &AppState) -> Result<TlsAcceptor, String> {
+    let settings = &state.settings;
+    let certificates = Arc::new(Certificates::default());
+    if !settings.acme_domains.is_empty() {
+        crate::acme::start(state, certificates.clone());
+    } else {
+        let (cert_file, key_file) = certificate_files(settings)?;
+        certificates.set(load_files(&cert_file, &key_file).await?);
+        tokio::spawn(reload_task(certificates.clone(), cert_file, key_file));
+    }
+
+    let mut config = rustls::ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .map_err(|e| e.to_string())?
+        .with_no_client_auth()
+        .with_cert_resolver(certificates);
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+/// TLS_CERT_FILE and TLS_KEY_FILE, which `https://` listeners need without ACME_DOMAINS.
+pub fn certificate_files(settings: &Settings) -> Result<(PathBuf, PathBuf), String> {
+    match (&settings.tls_cert_file, &settings.tls_key_file) {
+        (Some(cert_file), Some(key_file)) => Ok((PathBuf::from(cert_file), PathBuf::from(key_file))),
+        _ => Err("https:// listeners need TLS_CERT_FILE and TLS_KEY_FILE, or ACME_DOMAINS".to_string()),
+    }
+}
+
+async fn load_files(cert_file: &PathBuf, key_file: &PathBuf) -> Result<CertifiedKey, String> {
+    let cert_pem = tokio::fs::read(cert_file).await.map_err(|e| format!("Failed to read TLS_CERT_FILE {}: {}", cert_file.display(), e))?;
+    let key_pem = tokio::fs::read(key_file).await.map_err(|e| format!("Failed to read TLS_KEY_FILE {}: {}", key_file.display(), e))?;
+    certified_key(&cert_pem, &key_pem)
+}
+
+async fn modified(files: [&PathBuf; 2]) -> [Option<SystemTime>; 2] {
+    let mut times = [None; 2];
+    for (time, file) in times.iter_mut().zip(files) {
+        *time = tokio::fs::metadata(file).await.and_then(|m| m.modified()).ok();
+    }
+    times
+}
+
+/// Reloads the certificate when either file changes; a half-written pair keeps the old one.
+async fn reload_task(certificates: Arc<Certificates>, cert_file: PathBuf, key_file: PathBuf) {
+    let mut seen = modified([&cert_file, &key_file]).await;
+    loop {
+        tokio::time::sleep(RELOAD_INTERVAL).await;
+        let now = modified([&cert_file, &key_file]).await;
+        if now == seen {
+            continue;
+        }
+        match load_files(&cert_file, &key_file).await {
+            Ok(key) => {
+                certificates.set(key);
+                seen = now;
+                info!("🔐 Reloaded TLS certificate from {}", cert_file.display());
+            }
+            Err(e) => warn!("Keeping the current TLS certificate: {}", e),
+        }
+    }
+}
@@ -0,0 +1,273 @@
+//! Education mode (`tutor=true`): turns the parsed errors of a failed compile into
+//! numbered steps, each saying what the error means, where it is and what to try, for
+//! students who have not learned to read TeX logs yet.
+
+use regex::Regex;
+
+use crate::explain::ErrorExplainer;
+use crate::healer::ENVIRONMENT_PACKAGES;
+use crate::models::{TutorReport, TutorStep};
+
+/// Errors past this many are left out: they are usually caused by the earlier ones.
+const MAX_STEPS: usize = 5;
+
+/// Catalog errors that only report that TeX gave up after an earlier error.
+const FOLLOW_UP_ERRORS: &[&str] = &["emergency_stop"];
+
+/// Commands students often use without loading their package.
+const COMMAND_PACKAGES: &[(&str, &str)] = &[
+    ("includegraphics", "graphicx"), ("href", "hyperref"), ("url", "url"), ("autoref", "hyperref"),
+    ("textcolor", "xcolor"), ("color", "xcolor"), ("colorbox", "xcolor"),
+    ("mathbb", "amssymb"), ("checkmark", "amssymb"), ("text", "amsmath"), ("eqref", "amsmath"),
+    ("boldsymbol", "amsmath"), ("SI", "siunitx"), ("si", "siunitx"), ("qty", "siunitx"), ("unit", "siunitx"),
+    ("toprule", "booktabs"), ("midrule", "booktabs"), ("bottomrule", "booktabs"), ("multirow", "multirow"),
+    ("cref", "cleveref"), ("citep", "natbib"), ("citet", "natbib"), ("printbibliography", "biblatex"),
+    ("addbibresource", "biblatex"), ("captionof", "caption"), ("lstinline", "listings"), ("sout", "ulem"),
+    ("cancel", "cancel"), ("ce", "mhchem"), ("lipsum", "lipsum"), ("todo", "todonotes"),
+    ("FloatBarrier", "placeins"), ("newgeometry", "geometry"), ("degree", "gensymb"), ("euro", "eurosym"),
+];
+
+/// The sentences of a report in one language.
+struct Phrases {
+    language: &'static str,
+    summary_none: &'static str,
+    summary_one: &'static str,
+    /// `{}`: number of errors
+    summary_many: &'static str,
+    /// `{}`: number of steps shown
+    summary_truncated: &'static str,
+    /// `{}`: the TeX message
+    unknown_meaning: &'static str,
+    line: &'static str,
+    column: &'static str,
+    /// `{}`: the command, with its backslash
+    command_named: &'static str,
+    /// `{0}`: the command, `{1}`: the package
+    command_package: &'static str,
+    /// `{0}`: the environment, `{1}`: the package
+    environment_package: &'static str,
+    /// `{}`: the file
+    upload_file: &'static str,
+    look_around: &'static str,
+    comment_out: &'static str,
+}
+
+const PHRASES: &[Phrases] = &[
+    Phrases {
+        language: "en",
+        summary_none: "TeX stopped without pointing at a line. Read the end of the log: the last lines before it stopped usually name the problem.",
+        summary_one: "The document has 1 error. The step below explains what it means, where it is, and what to try.",
+        summary_many: "The document has {} errors. Start with step 1: later errors are often caused by an earlier one and disappear once it is fixed. Recompile after each fix.",
+        summary_truncated: "Only the first {} are shown.",
+        unknown_meaning: "TeX reported \"{}\". This error is not in our catalog, so read the message closely: it usually names the command or file involved.",
+        line: "line",
+        column: "column",
+        command_named: "The undefined command is {}.",
+        command_package: "{0} comes from the {1} package: add \\usepackage{{1}} to the preamble.",
+        environment_package: "The {0} environment comes from the {1} package: add \\usepackage{{1}} to the preamble.",
+        upload_file: "Upload {} with the project, in the folder the document expects it.",
+        look_around: "Look at the marked line and the one before it for a typo or a missing }, ] or $.",
+        comment_out: "If you are stuck, comment the line out with % and recompile to check that it is the cause.",
+    },
+    Phrases {
+        language: "es",
+        summary_none: "TeX se detuvo sin señalar una línea. Lee el final del log: las últimas líneas antes de detenerse suelen nombrar el problema.",
+        summary_one: "El documento tiene 1 error. El paso de abajo explica qué significa, dónde está y qué probar.",
+        summary_many: "El documento tiene {} errores. Empieza por el paso 1: los errores posteriores suelen deberse a uno anterior y desaparecen al corregirlo. Recompila después de cada corrección.",
+        summary_truncated: "Solo se muestran los primeros {}.",
+        unknown_meaning: "TeX informó \"{}\". Este error no está en nuestro catálogo, así que lee el mensaje con atención: suele nombrar el comando o el archivo implicado.",
+        line: "línea",
+        column: "columna",
+        command_named: "El comando no definido es {}.",
+        command_package: "{0} pertenece al paquete {1}: añade \\usepackage{{1}} al preámbulo.",
+        environment_package: "El entorno {0} pertenece al paquete {1}: añade \\usepackage{{1}} al preámbulo.",
+        upload_file: "Sube {} con el proyecto, en la carpeta donde el documento lo espera.",
+        look_around: "Revisa la línea marcada y la anterior en busca de una errata o de un }, ] o $ que falte.",
+        comment_out: "Si no avanzas, comenta la línea con % y recompila para comprobar que es la causa.",
+    },
+];
+
+pub struct Tutor;
+
+impl Tutor {
+    /// Builds the steps for `errors` as parsed from the log, with their `snippet` source
+    /// context attached. `language` is one negotiated by [`ErrorExplainer::negotiate`].
+    pub fn report(error: &str, errors: &[serde_json::Value], language: &str) -> TutorReport {
+        let phrases = PHRASES.iter().find(|p| p.language == language).unwrap_or(&PHRASES[0]);
+
+        let mut seen = std::collections::HashSet::new();
+        let mut relevant: Vec<&serde_json::Value> = errors
+            .iter()
+            .filter(|e| seen.insert((e["file"].as_str(), e["line"].as_u64(), e["message"].as_str())))
+            .collect();
+        let is_follow_up = |e: &serde_json::Value| {
+            ErrorExplainer::explain(e["message"].as_str().unwrap_or_default(), language).is_some_and(|x| FOLLOW_UP_ERRORS.contains(&x.id.as_str()))
+        };
+        if relevant.iter().any(|e| !is_follow_up(e)) {
+            relevant.retain(|e| !is_follow_up(e));
+        }
+
+        let mut summary = match relevant.len() {
+            0 => phrases.summary_none.to_string(),
+            1 => phrases.summary_one.to_string(),
+            n => phrases.summary_many.replace("{}", &n.to_string()),
+        };
+        if relevant.len() > MAX_STEPS {
+            summary = format!("{} {}", summary, phrases.summary_truncated.replace("{}", &MAX_STEPS.to_string()));
+        }
+
+        let steps = relevant.iter().take(MAX_STEPS).enumerate().map(|(i, e)| step(i + 1, e, language, phrases)).collect();
+        TutorReport { language: phrases.language.to_string(), error: error.to_string(), summary, steps }
+    }
+}
+
+fn step(number: usize, error: &serde_json::Value, language: &str, phrases: &Phrases) -> TutorStep {
+    let message = error["message"].as_str().unwrap_or_default().trim().to_string();
+    let explanation = ErrorExplainer::explain(&message, language);
+    let (line, column) = (error["line"].as_u64(), error["snippet"]["column"].as_u64().or(error["column"].as_u64()));
+
+    let location = error["file"].as_str().filter(|file| *file != "unknown").map(|file| {
+        let mut location = file.trim_start_matches("./").to_string();
+        if let Some(line) = line {
+            location.push_str(&format!(", {} {}", phrases.line, line));
+            if let Some(column) = column {
+                location.push_str(&format!(", {} {}", phrases.column, column));
+            }
+        }
+        location
+    });
+    let error_line = error["snippet"]["lines"]
+        .as_array()
+        .and_then(|lines| lines.iter().find(|l| l["error"].as_bool() == Some(true)))
+        .and_then(|l| l["text"].as_str());
+
+    let mut hints = Vec::new();
+    match explanation.as_ref().map(|e| e.id.as_str()) {
+        Some("undefined_control_sequence") => {
+            // TeX's context line ends with the undefined command; else cut the source at the column
+            let read = error["context"].as_str().map(str::to_string).or_else(|| {
+                error_line.map(|text| text.chars().take(column.unwrap_or(u64::MAX) as usize).collect())
+            });
+            if let Some(command) = read.as_deref().and_then(last_command) {
+                hints.push(phrases.command_named.replace("{}", &format!("\\{}", command)));
+                if let Some((_, package)) = COMMAND_PACKAGES.iter().find(|(name, _)| *name == command) {
+                    hints.push(phrases.command_package.replace("{0}", &format!("\\{}", command)).replace("{1}", package));
+                }
+            }
+        }
+        Some("undefined_environment") => {
+            let name = Regex::new(r"Environment (\S+) undefined").unwrap().captures(&message).map(|c| c[1].to_string());
+            if let Some((environment, package)) = name.and_then(|name| ENVIRONMENT_PACKAGES.iter().find(|(env, _)| *env == name)) {
+                hints.push(phrases.environment_package.replace("{0}", environment).replace("{1}", package));
+            }
+        }
+        Some("file_not_found") => {
+            if let Some(caps) = Regex::new(r"[`'\x22]([^`'\x22]+)'? not found").unwrap().captures(&message) {
+                hints.push(phrases.upload_file.replace("{}", &caps[1]));
+            }
+        }
+        _ => {}
+    }
+    if let Some(explanation) = &explanation {
+        hints.insert(0, explanation.suggestion.clone());
+    } else if line.is_some() {
+        hints.push(phrases.look_around.to_string());
+    }
+    if line.is_some() {
+        hints.push(phrases.comment_out.to_string());
+    }
+
+    TutorStep {
+        number,
+        meaning: explanation.as_ref().map(|e| e.explanation.clone()).unwrap_or_else(|| phrases.unknown_meaning.replace("{}", &message)),
+        error_id: explanation.map(|e| e.id),
+        message,
+        location,
+        excerpt: excerpt(&error["snippet"]),
+        hints,
+    }
+}
+
+/// Name of the last control word in `text`, without its backslash.
+fn last_command(text: &str) -> Option<&str> {
+    let start = text.rfind('\\')?;
+    let name = &text[start + 1..];
+    let end = name.find(|c: char| !c.is_ascii_alphabetic() && c != '@').unwrap_or(name.len());
+    (end > 0).then(|| &name[..end])
+}
+
+/// The snippet's lines with numbers, the error line marked with `>` and a caret under
+/// the column TeX stopped at.
+fn excerpt(snippet: &serde_json::Value) -> Option<String> {
+    let lines = snippet["lines"].as_array().filter(|lines| !lines.is_empty())?;
+    let width = lines.iter().filter_map(|l| l["line"].as_u64()).max().unwrap_or(0).to_string().len();
+    let mut out = String::new();
+    for line in lines {
+        let hit = line["error"].as_bool() == Some(true);
+        let text = line["text"].as_str().unwrap_or_default();
+        out.push_str(&format!("{} {:>width$} | {}\n", if hit { '>' } else { ' ' }, line["line"].as_u64().unwrap_or(0), text, width = width));
+        if let (true, Some(column)) = (hit, snippet["column"].as_u64()) {
+            let indent: String = text.chars().take(column.saturating_sub(1) as usize).map(|c| if c == '\t' { '\t' } else { ' ' }).collect();
+            out.push_str(&format!("  {:>width$} | {}^\n", "", indent, width = width));
+        }
+    }
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn undefined_command() -> serde_json::Value {
+        json!({
+            "file": "main.tex", "line": 3, "column": 18, "message": "Undefined control sequence.",
+            "snippet": {"column": 18, "lines": [
+                {"line": 2, "text": "\\begin{document}", "error": false},
+                {"line": 3, "text": "\\includegraphics{cat.png}", "error": true},
+            ]},
+        })
+    }
+
+    #[test]
+    fn test_report_steps() {
+        let errors = vec![undefined_command(), json!({"message": "Emergency stop."}), json!({"file": "main.tex", "line": 9, "message": "Something odd"})];
+        let report = Tutor::report("Compilation failed", &errors, "en");
+        assert_eq!(report.steps.len(), 2, "the emergency stop follows from the first error");
+        assert!(report.summary.starts_with("The document has 2 errors."));
+
+        let first = &report.steps[0];
+        assert_eq!(first.error_id.as_deref(), Some("undefined_control_sequence"));
+        assert_eq!(first.location.as_deref(), Some("main.tex, line 3, column 18"));
+        assert!(first.hints.contains(&"The undefined command is \\includegraphics.".to_string()));
+        assert!(first.hints.contains(&"\\includegraphics comes from the graphicx package: add \\usepackage{graphicx} to the preamble.".to_string()));
+        assert_eq!(first.excerpt.as_deref(), Some("  2 | \\begin{document}\n> 3 | \\includegraphics{cat.png}\n    |                  ^\n"));
+
+        let second = &report.steps[1];
+        assert_eq!(second.error_id, None);
+        assert!(second.meaning.contains("\"Something odd\""));
+        assert_eq!(second.hints.len(), 2);
+    }
+
+    #[test]
+    fn test_report_language_and_limits() {
+        let missing = json!({"file": "./main.tex", "line": 4, "message": "LaTeX Error: Environment align undefined."});
+        let report = Tutor::report("x", &[missing], "es");
+        assert_eq!(report.language, "es");
+        assert_eq!(report.steps[0].location.as_deref(), Some("main.tex, línea 4"));
+        assert!(report.steps[0].hints.iter().any(|h| h.contains("\\usepackage{amsmath}")));
+
+        let many: Vec<_> = (1..=8).map(|n| json!({"file": "main.tex", "line": n, "message": "Missing $ inserted."})).collect();
+        let report = Tutor::report("x", &many, "en");
+        assert_eq!(report.steps.len(), MAX_STEPS);
+        assert!(report.summary.ends_with("Only the first 5 are shown."));
+        assert!(Tutor::report("x", &[], "fr").steps.is_empty());
+    }
+
+    #[test]
+    fn test_last_command() {
+        assert_eq!(last_command("\\foo{x} \\bar@baz"), Some("bar@baz"));
+        assert_eq!(last_command("\\SI{3}"), Some("SI"));
+        assert_eq!(last_command("no commands"), None);
+    }
+}
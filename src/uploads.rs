@@ -0,0 +1,281 @@
+//! Resumable uploads in the style of tus: the client announces the total size with
+//! `POST /uploads`, then sends the bytes in any number of `PATCH /uploads/:id` requests,
+//! resuming from the offset the server reports after a dropped connection. A completed
+//! upload moves into the blob store, where compiles reference it by hash.
+
+use axum::http::StatusCode;
+use bytes::Bytes;
+use futures_util::{Stream, StreamExt};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use xxhash_rust::xxh64::Xxh64;
+
+use crate::models::UploadStatus;
+use crate::services::BlobStore;
+use crate::util::unix_now;
+
+struct Session {
+    length: u64,
+    offset: u64,
+    created_at: u64,
+    /// A PATCH is writing; a second one would interleave bytes
+    busy: bool,
+    /// Blob hash once every byte arrived
+    hash: Option<String>,
+}
+
+/// Ends a PATCH: records the bytes written and frees the upload for the next one, also
+/// when the request is dropped mid-stream, which is what a client disconnect does.
+struct Writing<'a> {
+    sessions: &'a Mutex<HashMap<String, Session>>,
+    id: &'a str,
+    written: u64,
+}
+
+impl Drop for Writing<'_> {
+    fn drop(&mut self) {
+        if let Some(session) = self.sessions.lock().unwrap().get_mut(self.id) {
+            session.offset = self.written;
+            session.busy = false;
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct UploadStore {
+    dir: PathBuf,
+    sessions: Arc<Mutex<HashMap<String, Session>>>,
+    blobs: BlobStore,
+    max_bytes: u64,
+    ttl_secs: u64,
+}
+
+impl UploadStore {
+    pub fn new(blobs: BlobStore, max_mb: u64, ttl_secs: u64) -> Self {
+        Self::with_dir(std::env::temp_dir().join("tachyon-uploads"), blobs, max_mb, ttl_secs)
+    }
+
+    /// Replicas may share `dir`, so each keeps its uploads in a subdirectory of its own.
+    /// Partial uploads do not survive a restart (their sessions are gone): what a replica
+    /// left behind is removed once it is older than the TTL.
+    pub fn with_dir(dir: PathBuf, blobs: BlobStore, max_mb: u64, ttl_secs: u64) -> Self {
+        remove_stale(&dir, Duration::from_secs(ttl_secs));
+        let dir = dir.join(format!("instance-{}", uuid::Uuid::new_v4().simple()));
+        std::fs::create_dir_all(&dir).ok();
+        Self { dir, sessions: Arc::new(Mutex::new(HashMap::new())), blobs, max_bytes: max_mb * 1024 * 1024, ttl_secs }
+    }
+
+    /// Opens an upload of `length` bytes, dropping expired ones first.
+    pub fn create(&self, length: u64) -> Result<UploadStatus, (StatusCode, String)> {
+        if length > self.max_bytes {
+            return Err((StatusCode::PAYLOAD_TOO_LARGE, format!("Upload-Length {} exceeds the limit of {} bytes", length, self.max_bytes)));
+        }
+        self.expire();
+        let id = uuid::Uuid::new_v4().simple().to_string();
+        // Recreated in case a replica starting up found it idle for longer than the TTL
+        std::fs::create_dir_all(&self.dir)
+            .and_then(|_| std::fs::File::create(self.dir.join(&id))).map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to create upload: {}", e)))?;
+        let session = Session { length, offset: 0, created_at: unix_now(), busy: false, hash: None };
+        let status = self.status_of(&id, &session);
+        self.sessions.lock().unwrap().insert(id, session);
+        Ok(status)
+    }
+
+    pub fn status(&self, id: &str) -> Option<UploadStatus> {
+        self.sessions.lock().unwrap().get(id).map(|session| self.status_of(id, session))
+    }
+
+    /// Appends `body` at `offset`, which must equal the bytes received so far. Bytes that
+    /// arrive before the body fails are kept, so the client resumes from the new offset.
+    pub async fn append<S, E>(&self, id: &str, offset: u64, mut body: S) -> Result<UploadStatus, (StatusCode, String)>
+    where
+        S: Stream<Item = Result<Bytes, E>> + Unpin,
+        E: std::fmt::Display,
+    {
+        let length = {
+            let mut sessions = self.sessions.lock().unwrap();
+            let session = sessions.get_mut(id).ok_or_else(|| not_found(id))?;
+            if session.busy {
+                return Err((StatusCode::CONFLICT, "Another PATCH for this upload is in progress".to_string()));
+            }
+            if session.offset != offset {
+                return Err((StatusCode::CONFLICT, format!("Upload-Offset {} does not match the {} bytes received", offset, session.offset)));
+            }
+            session.busy = true;
+            session.length
+        };
+
+        let mut writing = Writing { sessions: &self.sessions, id, written: offset };
+        let mut failure = None;
+        let opened = match tokio::fs::OpenOptions::new().append(true).open(self.dir.join(id)).await {
+            // Drops part of a chunk a dropped PATCH was writing, past the recorded offset
+            Ok(file) => file.set_len(offset).await.map(|_| file),
+            Err(e) => Err(e),
+        };
+        match opened {
+            Ok(mut file) => {
+                while let Some(chunk) = body.next().await {
+                    let chunk = match chunk {
+                        Ok(chunk) => chunk,
+                        Err(e) => {
+                            failure = Some((StatusCode::BAD_REQUEST, format!("Upload interrupted: {}", e)));
+                            break;
+                        }
+                    };
+                    if writing.written + chunk.len() as u64 > length {
+                        failure = Some((StatusCode::BAD_REQUEST, format!("Data exceeds Upload-Length {}", length)));
+                        break;
+                    }
+                    if let Err(e) = file.write_all(&chunk).await {
+                        failure = Some((StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to write upload: {}", e)));
+                        break;
+                    }
+                    writing.written += chunk.len() as u64;
+                }
+                if let Err(e) = file.flush().await {
+                    failure.get_or_insert((StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to write upload: {}", e)));
+                }
+            }
+            Err(e) => failure = Some((StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to open upload: {}", e))),
+        }
+
+        if writing.written == length && failure.is_none() {
+            let hash = self.complete(id).await;
+            if let Some(session) = self.sessions.lock().unwrap().get_mut(id) {
+                session.hash = hash;
+            }
+        }
+        drop(writing);
+        match failure {
+            Some(failure) => Err(failure),
+            None => self.status(id).ok_or_else(|| not_found(id)),
+        }
+    }
+
+    /// Moves a finished upload into the blob store, returning its hash. The file is hashed
+    /// and stored in chunks, never held in memory as a whole.
+    async fn complete(&self, id: &str) -> Option<String> {
+        let path = self.dir.join(id);
+        let hash = hash_file(&path).await.ok()?;
+        self.blobs.put_file(hash.clone(), &path).await;
+        tokio::fs::remove_file(&path).await.ok();
+        Some(hash)
+    }
+
+    /// Aborts an upload; `false` if there was none.
+    pub fn remove(&self, id: &str) -> bool {
+        let removed = self.sessions.lock().unwrap().remove(id).is_some();
+        if removed {
+            std::fs::remove_file(self.dir.join(id)).ok();
+        }
+        removed
+    }
+
+    /// Drops uploads older than the TTL, returning how many.
+    pub fn expire(&self) -> usize {
+        let cutoff = unix_now().saturating_sub(self.ttl_secs);
+        let mut sessions = self.sessions.lock().unwrap();
+        let expired: Vec<String> = sessions.iter().filter(|(_, s)| s.created_at < cutoff && !s.busy).map(|(id, _)| id.clone()).collect();
+        for id in &expired {
+            sessions.remove(id);
+            std::fs::remove_file(self.dir.join(id)).ok();
+        }
+        expired.len()
+    }
+
+    fn status_of(&self, id: &str, session: &Session) -> UploadStatus {
+        UploadStatus {
+            id: id.to_string(),
+            offset: session.offset,
+            length: session.length,
+            complete: session.hash.is_some(),
+            hash: session.hash.clone(),
+            expires_at: session.created_at + self.ttl_secs,
+        }
+    }
+}
+
+/// The blob hash of the file at `path` (xxh64, as for inline files), read in chunks.
+async fn hash_file(path: &Path) -> std::io::Result<String> {
+    let mut file = tokio::fs::File::open(path).await?;
+    let mut hasher = Xxh64::new(0);
+    let mut buffer = vec![0u8; 64 * 1024];
+    loop {
+        let read = file.read(&mut buffer).await?;
+        if read == 0 {
+            return Ok(format!("{:x}", hasher.digest()));
+        }
+        hasher.update(&buffer[..read]);
+    }
+}
+
+/// Removes the entries of `dir` last modified more than `max_age` ago.
+fn remove_stale(dir: &Path, max_age: Duration) {
+    let Ok(entries) = std::fs::read_dir(dir) else { return };
+    let now = SystemTime::now();
+    for entry in entries.flatten() {
+        let age = entry.metadata().and_then(|m| m.modified()).ok().and_then(|modified| now.duration_since(modified).ok());
+        if age.is_some_and(|age| age > max_age) {
+            let path = entry.path();
+            if path.is_dir() { std::fs::remove_dir_all(&path).ok(); } else { std::fs::remove_file(&path).ok(); }
+        }
+    }
+}
+
+fn not_found(id: &str) -> (StatusCode, String) {
+    (StatusCode::NOT_FOUND, format!("Upload {} not found or expired", id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunks(parts: Vec<Result<&'static str, &'static str>>) -> impl Stream<Item = Result<Bytes, &'static str>> + Unpin {
+        futures_util::stream::iter(parts.into_iter().map(|p| p.map(|s| Bytes::from_static(s.as_bytes()))))
+    }
+
+    #[tokio::test]
+    async fn test_resumable_upload() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let blobs = BlobStore::new(Arc::new(crate::storage::MemoryStorage::new()));
+        let store = UploadStore::with_dir(dir.path().join("uploads"), blobs.clone(), 1, 3600);
+        let upload = store.create(10).unwrap();
+
+        // The connection drops after the first chunk: what arrived is kept
+        let (status, _) = store.append(&upload.id, 0, chunks(vec![Ok("hello"), Err("reset")])).await.unwrap_err();
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(store.status(&upload.id).unwrap().offset, 5);
+
+        assert_eq!(store.append(&upload.id, 0, chunks(vec![Ok("hello")])).await.unwrap_err().0, StatusCode::CONFLICT);
+        assert_eq!(store.append(&upload.id, 5, chunks(vec![Ok("world!")])).await.unwrap_err().0, StatusCode::BAD_REQUEST);
+
+        let done = store.append(&upload.id, 5, chunks(vec![Ok("wor"), Ok("ld")])).await.unwrap();
+        assert!(done.complete);
+        assert_eq!(blobs.get(done.hash.as_deref().unwrap()).await.unwrap(), "helloworld");
+
+        assert_eq!(store.create(2 * 1024 * 1024).unwrap_err().0, StatusCode::PAYLOAD_TOO_LARGE);
+        assert!(store.remove(&upload.id));
+        assert!(store.status(&upload.id).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_resume_after_dropped_request() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let blobs = BlobStore::new(Arc::new(crate::storage::MemoryStorage::new()));
+        let store = UploadStore::with_dir(dir.path().join("uploads"), blobs.clone(), 1, 3600);
+        let upload = store.create(10).unwrap();
+
+        // The client disconnects mid-stream: axum drops the handler instead of failing the body
+        let stalled = chunks(vec![Ok("hello")]).chain(futures_util::stream::pending());
+        let append = store.append(&upload.id, 0, stalled);
+        assert!(tokio::time::timeout(std::time::Duration::from_millis(50), append).await.is_err());
+        assert_eq!(store.status(&upload.id).unwrap().offset, 5);
+
+        let done = store.append(&upload.id, 5, chunks(vec![Ok("world")])).await.unwrap();
+        assert!(done.complete);
+        assert_eq!(blobs.get(done.hash.as_deref().unwrap()).await.unwrap(), "helloworld");
+    }
+}
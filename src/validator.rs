@@ -0,0 +1,303 @@
+//! Static checks over a project's sources, shared by `POST /validate`, gRPC, MCP and the
+//! CLI. `\input`, `\include` and `\subfile` are followed from the main file so
+//! environments opened in one file and closed in another are matched, while every
+//! message points at the file and line it comes from.
+
+use regex::Regex;
+use std::collections::{HashMap, HashSet};
+
+use crate::bib::{strip_comment, CitationChecker};
+use crate::models::{ValidationMessage, ValidationResult};
+
+/// Two-letter font switches superseded by `\textbf{}`-style commands, with their replacements.
+const DEPRECATED_FONT_COMMANDS: &[(&str, &str)] = &[
+    ("bf", "\\textbf{}"), ("it", "\\textit{}"), ("rm", "\\textrm{}"), ("sf", "\\textsf{}"),
+    ("tt", "\\texttt{}"), ("sc", "\\textsc{}"), ("sl", "\\textsl{}"),
+];
+
+/// Environments whose bodies are not LaTeX and must not be checked.
+pub(crate) const VERBATIM_ENVIRONMENTS: &[&str] = &["verbatim", "verbatim*", "Verbatim", "lstlisting", "minted", "comment", "filecontents", "filecontents*"];
+
+pub struct Validator;
+
+impl Validator {
+    /// Checks `sources` (file name to content). `main` defaults to the first `.tex` file,
+    /// by name, that declares `\documentclass`; without one, every file is checked on its own.
+    pub fn validate(sources: &HashMap<String, String>, main: Option<&str>) -> ValidationResult {
+        let sources: HashMap<String, &str> = sources.iter()
+            .map(|(name, content)| (normalize(name), content.as_str()))
+            .collect();
+        let mut tex_files: Vec<&String> = sources.keys().filter(|n| n.ends_with(".tex")).collect();
+        tex_files.sort();
+
+        let main = main.map(normalize).or_else(|| {
+            tex_files.iter().find(|n| sources[n.as_str()].contains("\\documentclass")).map(|n| n.to_string())
+        });
+
+        let mut walk = Walk::new(&sources);
+        if let Some(main) = &main {
+            match sources.get(main) {
+                Some(content) => {
+                    walk.check_document(main, content);
+                    walk.root(main);
+                }
+                None => walk.error(main, 1, format!("Main file '{}' was not provided", main)),
+            }
+        }
+        for name in tex_files {
+            if !walk.visited.contains(name) {
+                walk.root(name);
+            }
+        }
+
+        let owned: HashMap<String, String> = sources.iter().map(|(n, c)| (n.clone(), c.to_string())).collect();
+        walk.warnings.extend(CitationChecker::check(&owned));
+        ValidationResult {
+            valid: walk.errors.is_empty(),
+            errors: walk.errors,
+            warnings: walk.warnings,
+        }
+    }
+}
+
+/// `.tex` sources using the `subfiles` class, i.e. chapters that build standalone, by name.
+pub fn subfile_targets(sources: &HashMap<String, String>) -> Vec<String> {
+    let class_re = Regex::new(r"\\documentclass\s*(?:\[[^\]]*\])?\s*\{\s*subfiles\s*\}").unwrap();
+    let mut targets: Vec<String> = sources.iter()
+        .filter(|(name, content)| name.ends_with(".tex") && content.lines().any(|l| class_re.is_match(strip_comment(l))))
+        .map(|(name, _)| normalize(name))
+        .collect();
+    targets.sort();
+    targets
+}
+
+/// Project-relative name without a leading `./`.
+fn normalize(name: &str) -> String {
+    name.trim().trim_start_matches("./").to_string()
+}
+
+/// An environment opened at `file:line`.
+struct OpenEnvironment {
+    name: String,
+    file: String,
+    line: u32,
+}
+
+struct Walk<'a> {
+    sources: &'a HashMap<String, &'a str>,
+    command_re: Regex,
+    font_re: Regex,
+    errors: Vec<ValidationMessage>,
+    warnings: Vec<ValidationMessage>,
+    visited: HashSet<String>,
+    /// Files currently being read, outermost first, to detect circular inputs
+    chain: Vec<String>,
+    environments: Vec<OpenEnvironment>,
+}
+
+impl<'a> Walk<'a> {
+    fn new(sources: &'a HashMap<String, &'a str>) -> Self {
+        Self {
+            sources,
+            command_re: Regex::new(r"\\(begin|end|input|include|subfile)\s*\{([^}]*)\}").unwrap(),
+            font_re: Regex::new(r"\\(bf|it|rm|sf|tt|sc|sl)\b").unwrap(),
+            errors: Vec::new(),
+            warnings: Vec::new(),
+            visited: HashSet::new(),
+            chain: Vec::new(),
+            environments: Vec::new(),
+        }
+    }
+
+    fn error(&mut self, file: &str, line: u32, message: String) {
+        self.errors.push(ValidationMessage { file: file.to_string(), line, message });
+    }
+
+    /// Reads `name` and everything it inputs, then reports environments left open.
+    fn root(&mut self, name: &str) {
+        self.file(name);
+        for env in std::mem::take(&mut self.environments) {
+            self.error(&env.file, env.line, format!("\\begin{{{}}} is never closed", env.name));
+        }
+    }
+
+    fn check_document(&mut self, name: &str, content: &str) {
+        let code: Vec<&str> = content.lines().map(strip_comment).collect();
+        if !code.iter().any(|l| l.contains("\\documentclass")) {
+            self.error(name, 1, "Missing \\documentclass".to_string());
+        }
+        if !code.iter().any(|l| l.contains("\\begin{document}")) {
+            self.error(name, 1, "Missing \\begin{document}".to_string());
+        }
+    }
+
+    /// Resolves an input argument the way TeX does: relative to the project root,
+    /// with `.tex` appended when the name has no match as given.
+    fn resolve(&self, target: &str) -> Option<String> {
+        let target = normalize(target);
+        [target.clone(), format!("{}.tex", target)].into_iter().find(|n| self.sources.contains_key(n))
+    }
+
+    fn file(&mut self, name: &str) {
+        let sources = self.sources;
+        let Some(&content) = sources.get(name) else { return };
+        self.visited.insert(name.to_string());
+        self.chain.push(name.to_string());
+
+        let mut braces: Vec<u32> = Vec::new();
+        let mut verbatim: Option<String> = None;
+        for (idx, raw) in content.lines().enumerate() {
+            let line_no = idx as u32 + 1;
+            if let Some(env) = &verbatim {
+                if raw.contains(&format!("\\end{{{}}}", env)) {
+                    self.close(env.clone(), name, line_no);
+                    verbatim = None;
+                }
+                continue;
+            }
+            let line = strip_comment(raw);
+            self.braces(line, name, line_no, &mut braces);
+            self.style(line, name, line_no);
+
+            let commands: Vec<(String, String)> = self.command_re.captures_iter(line)
+                .map(|caps| (caps[1].to_string(), caps[2].trim().to_string()))
+                .collect();
+            for (command, argument) in commands {
+                match command.as_str() {
+                    "begin" => {
+                        self.environments.push(OpenEnvironment { name: argument.clone(), file: name.to_string(), line: line_no });
+                        if VERBATIM_ENVIRONMENTS.contains(&argument.as_str()) {
+                            verbatim = Some(argument);
+                            break;
+                        }
+                    }
+                    "end" => self.close(argument, name, line_no),
+                    _ => match self.resolve(&argument) {
+                        Some(target) if self.chain.contains(&target) => {
+                            self.error(name, line_no, format!("Circular \\{} of '{}'", command, target));
+                        }
+                        Some(target) => self.file(&target),
+                        None => self.error(name, line_no, format!("\\{} refers to '{}', which was not provided", command, argument)),
+                    },
+                }
+            }
+        }
+        for line in braces {
+            self.error(name, line, "Unclosed '{'".to_string());
+        }
+        self.chain.pop();
+    }
+
+    fn close(&mut self, env: String, file: &str, line: u32) {
+        match self.environments.pop() {
+            Some(open) if open.name == env => {}
+            Some(open) => {
+                self.error(file, line, format!("\\end{{{}}} does not match \\begin{{{}}} at {}:{}", env, open.name, open.file, open.line));
+                self.environments.push(open);
+            }
+            None => self.error(file, line, format!("\\end{{{}}} without a matching \\begin", env)),
+        }
+    }
+
+    fn style(&mut self, line: &str, file: &str, line_no: u32) {
+        if line.contains("$$") {
+            self.warnings.push(ValidationMessage { file: file.to_string(), line: line_no, message: "Use \\[ \\] instead of $$ for display math".to_string() });
+        }
+        for caps in self.font_re.captures_iter(line) {
+            let replacement = DEPRECATED_FONT_COMMANDS.iter().find(|(c, _)| *c == &caps[1]).map_or("", |(_, r)| *r);
+            self.warnings.push(ValidationMessage {
+                file: file.to_string(),
+                line: line_no,
+                message: format!("\\{} is deprecated, use {} instead", &caps[1], replacement),
+            });
+        }
+    }
+
+    /// Tracks unescaped braces of one line; `open` holds the lines of unclosed `{`.
+    fn braces(&mut self, line: &str, file: &str, line_no: u32, open: &mut Vec<u32>) {
+        let mut escaped = false;
+        for c in line.chars() {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '{' => open.push(line_no),
+                '}' if open.pop().is_none() => self.error(file, line_no, "Unmatched '}'".to_string()),
+                _ => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn project(files: &[(&str, &str)]) -> HashMap<String, String> {
+        files.iter().map(|(n, c)| (n.to_string(), c.to_string())).collect()
+    }
+
+    #[test]
+    fn test_follows_inputs_with_per_file_lines() {
+        let sources = project(&[
+            ("main.tex", "\\documentclass{article}\n\\begin{document}\n\\input{chapters/one}\n\\end{document}\n"),
+            ("chapters/one.tex", "Intro\n\\begin{itemize}\n\\item {unclosed\n"),
+        ]);
+        let result = Validator::validate(&sources, None);
+        assert!(!result.valid);
+        let found: Vec<(&str, u32)> = result.errors.iter().map(|e| (e.file.as_str(), e.line)).collect();
+        assert!(found.contains(&("chapters/one.tex", 3)), "{:?}", found);
+        // itemize is still open when main.tex reaches \end{document}
+        assert!(found.contains(&("main.tex", 4)), "{:?}", found);
+    }
+
+    #[test]
+    fn test_environment_closed_in_another_file() {
+        let sources = project(&[
+            ("main.tex", "\\documentclass{article}\n\\begin{document}\n\\begin{table}\n\\input{body}\n\\end{document}\n"),
+            ("body.tex", "\\centering\n\\end{table}\n"),
+        ]);
+        let result = Validator::validate(&sources, Some("main.tex"));
+        assert!(result.valid, "{:?}", result.errors.iter().map(|e| &e.message).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_missing_and_circular_inputs() {
+        let sources = project(&[
+            ("main.tex", "\\documentclass{article}\n\\begin{document}\n\\include{a}\n\\input{missing}\n\\end{document}\n"),
+            ("a.tex", "\\input{main}\n"),
+        ]);
+        let result = Validator::validate(&sources, None);
+        let messages: Vec<String> = result.errors.iter().map(|e| format!("{}:{} {}", e.file, e.line, e.message)).collect();
+        assert!(messages.iter().any(|m| m.starts_with("a.tex:1 Circular")), "{:?}", messages);
+        assert!(messages.iter().any(|m| m.starts_with("main.tex:4 \\input refers to 'missing'")), "{:?}", messages);
+    }
+
+    #[test]
+    fn test_ignores_verbatim_and_comments() {
+        let sources = project(&[(
+            "main.tex",
+            "\\documentclass{article}\n\\begin{document}\n% \\begin{itemize} {\n\\begin{verbatim}\n\\end{itemize} }\n\\end{verbatim}\n50\\% \\{\n\\end{document}\n",
+        )]);
+        assert!(Validator::validate(&sources, None).valid);
+    }
+
+    #[test]
+    fn test_subfile_targets() {
+        let sources = project(&[
+            ("main.tex", "\\documentclass{article}\n\\usepackage{subfiles}\n"),
+            ("chapters/two.tex", "\\documentclass[../main.tex]{subfiles}\n"),
+            ("./chapters/one.tex", "\\documentclass[../main.tex]{ subfiles }\n"),
+            ("notes.tex", "% \\documentclass[main.tex]{subfiles}\n"),
+        ]);
+        assert_eq!(subfile_targets(&sources), vec!["chapters/one.tex", "chapters/two.tex"]);
+    }
+
+    #[test]
+    fn test_style_warnings() {
+        let sources = project(&[("main.tex", "\\documentclass{article}\n\\begin{document}\n{\\bf Bold} \\bfseries \\item\n$$x$$\n\\end{document}\n")]);
+        let result = Validator::validate(&sources, None);
+        assert!(result.valid);
+        let messages: Vec<String> = result.warnings.iter().map(|w| format!("{}: {}", w.line, w.message)).collect();
+        assert_eq!(messages, vec!["3: \\bf is deprecated, use \\textbf{} instead", "4: Use \\[ \\] instead of $$ for display math"]);
+    }
+}
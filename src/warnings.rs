@@ -0,0 +1,104 @@
+//! Non-fatal diagnostics of a compile, read from its TeX log.
+
+use regex::Regex;
+
+use crate::healer::SelfHealer;
+use crate::models::CompileWarning;
+use crate::texlog;
+
+/// Extracts non-fatal diagnostics (box warnings, undefined references/citations,
+/// duplicate labels) from the compile logs. Duplicates from rerun passes are dropped.
+pub fn parse_log_warnings(log: &str) -> Vec<CompileWarning> {
+    let box_regex = Regex::new(r"(Overfull|Underfull) \\([hv]box) \(([^)]+)\)").unwrap();
+    let ref_regex = Regex::new(r"(Reference|Citation) [`']([^']+)' on page \S+ undefined").unwrap();
+    let label_regex = Regex::new(r"Label [`']([^']+)' multiply defined").unwrap();
+
+    let mut warnings: Vec<CompileWarning> = Vec::new();
+
+    for entry in SelfHealer::diagnostics(log) {
+        let text = entry.message.as_str();
+        let warning = match entry.category {
+            texlog::Category::BadBox => match box_regex.captures(text) {
+                Some(caps) => CompileWarning {
+                    kind: format!("{}_{}", caps[1].to_lowercase(), &caps[2]),
+                    file: entry.file,
+                    line: entry.line,
+                    measurement: Some(caps[3].to_string()),
+                    message: text.to_string(),
+                },
+                None => continue,
+            },
+            texlog::Category::Warning => {
+                if let Some(caps) = ref_regex.captures(text) {
+                    CompileWarning {
+                        kind: format!("undefined_{}", caps[1].to_lowercase()),
+                        file: entry.file,
+                        line: entry.line,
+                        measurement: None,
+                        message: format!("{} '{}' undefined", &caps[1], &caps[2]),
+                    }
+                } else if let Some(caps) = label_regex.captures(text) {
+                    CompileWarning {
+                        kind: "multiply_defined_label".to_string(),
+                        file: entry.file,
+                        line: entry.line,
+                        measurement: None,
+                        message: format!("Label '{}' multiply defined", &caps[1]),
+                    }
+                } else {
+                    continue;
+                }
+            }
+            texlog::Category::Error => continue,
+        };
+
+        if !warnings.contains(&warning) {
+            warnings.push(warning);
+        }
+    }
+
+    warnings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn of_kind<'a>(warnings: &'a [CompileWarning], kind: &str) -> Vec<&'a CompileWarning> {
+        warnings.iter().filter(|w| w.kind == kind).collect()
+    }
+
+    #[test]
+    fn test_undefined_references() {
+        let warnings = parse_log_warnings(include_str!("../tests/logs/warnings.log"));
+        let references = of_kind(&warnings, "undefined_reference");
+        assert_eq!(references.len(), 1, "{:#?}", warnings);
+        assert_eq!(references[0].message, "Reference 'fig:results' undefined");
+        assert_eq!(references[0].line, Some(48));
+        assert_eq!(of_kind(&warnings, "undefined_citation")[0].message, "Citation 'knuth1984' undefined");
+    }
+
+    #[test]
+    fn test_multiply_defined_label() {
+        let warnings = parse_log_warnings(include_str!("../tests/logs/warnings.log"));
+        let labels = of_kind(&warnings, "multiply_defined_label");
+        assert_eq!(labels.len(), 1, "{:#?}", warnings);
+        assert_eq!(labels[0].message, "Label 'eq:main' multiply defined");
+    }
+
+    #[test]
+    fn test_overfull_box() {
+        let warnings = parse_log_warnings(include_str!("../tests/logs/warnings.log"));
+        let overfull = of_kind(&warnings, "overfull_hbox");
+        assert_eq!(overfull.len(), 2, "{:#?}", warnings);
+        assert_eq!(overfull[0].measurement.as_deref(), Some("15.11617pt too wide"));
+        assert_eq!(overfull[0].line, Some(61));
+        assert_eq!(of_kind(&warnings, "underfull_vbox")[0].measurement.as_deref(), Some("badness 10000"));
+    }
+
+    #[test]
+    fn test_rerun_duplicates_dropped() {
+        let log = include_str!("../tests/logs/warnings.log");
+        assert_eq!(parse_log_warnings(&format!("{}\n{}", log, log)), parse_log_warnings(log));
+    }
+}
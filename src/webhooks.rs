@@ -0,0 +1,459 @@
+//! Webhook subscriptions: event names, target validation, signing and delivery.
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::models::{DeadLetter, WebhookFilter, WebhookPayload, WebhookSubscription};
+use crate::services::AppState;
+use crate::settings::Settings;
+use crate::util::unix_now;
+
+/// Events a subscription can listen to; an empty list on creation means all of them.
+pub const WEBHOOK_EVENTS: &[&str] = &["compile.success", "compile.failure", "regression.failed", "schedule.success", "schedule.failure"];
+
+/// Shortest secret accepted when a client brings its own.
+const MIN_SECRET_LEN: usize = 16;
+
+/// Delay before the first retry; doubled after every failed attempt.
+const RETRY_BASE_DELAY: Duration = Duration::from_secs(2);
+
+/// Per-attempt timeout for a delivery request.
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub struct Webhooks;
+
+impl Webhooks {
+    /// Generates a fresh signing secret (`whsec_` + 64 hex chars).
+    pub fn generate_secret() -> String {
+        format!("whsec_{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple())
+    }
+
+    /// Checks scheme, port and host against the operator policy without touching DNS.
+    pub fn validate_url(url: &str, settings: &Settings) -> Result<reqwest::Url, String> {
+        let parsed = reqwest::Url::parse(url).map_err(|e| format!("Invalid webhook URL '{}': {}", url, e))?;
+        match parsed.scheme() {
+            "https" => {}
+            "http" if !settings.webhook_require_https => {}
+            scheme => return Err(format!("Unsupported webhook URL scheme '{}'", scheme)),
+        }
+        let port = parsed.port_or_known_default().unwrap_or(0);
+        if !settings.webhook_allowed_ports.is_empty() && !settings.webhook_allowed_ports.contains(&port) {
+            return Err(format!("Webhook port {} is not allowed", port));
+        }
+        let host = parsed.host_str().ok_or_else(|| format!("Webhook URL '{}' has no host", url))?;
+        if !settings.webhook_allowed_hosts.is_empty() && !Self::host_allowed(host, &settings.webhook_allowed_hosts) {
+            return Err(format!("Webhook host '{}' is not in the allow-list", host));
+        }
+        Ok(parsed)
+    }
+
+    /// Resolves the URL's host and rejects non-public addresses unless the policy trusts them.
+    /// Deliveries connect only to the returned addresses, so DNS cannot be rebound in between.
+    pub async fn resolve_target(url: &reqwest::Url, settings: &Settings) -> Result<Vec<SocketAddr>, String> {
+        let host = url.host_str().unwrap_or_default();
+        let port = url.port_or_known_default().unwrap_or(0);
+        let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host.trim_matches(['[', ']']), port)).await
+            .map_err(|e| format!("Cannot resolve webhook host '{}': {}", host, e))?
+            .collect();
+        if addrs.is_empty() {
+            return Err(format!("Webhook host '{}' has no addresses", host));
+        }
+        // Hosts the operator allow-listed explicitly may live on the internal network
+        let trusted = settings.webhook_allow_private || !settings.webhook_allowed_hosts.is_empty();
+        if let Some(blocked) = addrs.iter().find(|a| !trusted && !Self::is_public_ip(a.ip())) {
+            return Err(format!("Webhook host '{}' resolves to non-public address {}", host, blocked.ip()));
+        }
+        Ok(addrs)
+    }
+
+    /// `*.example.com` (or `.example.com`) matches any subdomain; other entries match exactly.
+    pub(crate) fn host_allowed(host: &str, allowed: &[String]) -> bool {
+        let host = host.trim_end_matches('.').to_ascii_lowercase();
+        allowed.iter().any(|entry| match entry.strip_prefix('*').unwrap_or(entry).strip_prefix('.') {
+            Some(suffix) => host.ends_with(&format!(".{}", suffix)),
+            None => host == *entry,
+        })
+    }
+
+    pub fn is_public_ip(ip: IpAddr) -> bool {
+        match ip {
+            IpAddr::V4(v4) => Self::is_public_ipv4(v4),
+            IpAddr::V6(v6) => match Self::embedded_ipv4(v6) {
+                Some(v4) => Self::is_public_ipv4(v4),
+                None => Self::is_public_ipv6(v6),
+            },
+        }
+    }
+
+    /// The IPv4 address an IPv4-mapped (`::ffff:0:0/96`) or NAT64 (`64:ff9b::/96`) address
+    /// reaches, which is what the policy must judge.
+    fn embedded_ipv4(ip: Ipv6Addr) -> Option<Ipv4Addr> {
+        let segments = ip.segments();
+        let mapped = segments[..6] == [0, 0, 0, 0, 0, 0xffff];
+        let nat64 = segments[..6] == [0x64, 0xff9b, 0, 0, 0, 0];
+        (mapped || nat64).then(|| Ipv4Addr::from((u32::from(segments[6]) << 16) | u32::from(segments[7])))
+    }
+
+    fn is_public_ipv4(ip: Ipv4Addr) -> bool {
+        let [a, b, ..] = ip.octets();
+        !(ip.is_unspecified()
+            || ip.is_loopback()
+            || ip.is_private()
+            || ip.is_link_local()
+            || ip.is_broadcast()
+            || ip.is_documentation()
+            || ip.is_multicast()
+            || a == 0                               // "this network"
+            || (a == 100 && (64..128).contains(&b)) // carrier-grade NAT
+            || (a == 198 && (b == 18 || b == 19))   // benchmarking
+            || a >= 240)                            // reserved
+    }
+
+    fn is_public_ipv6(ip: Ipv6Addr) -> bool {
+        let segments = ip.segments();
+        let first = segments[0];
+        !(ip.is_unspecified()
+            || ip.is_loopback()
+            || ip.is_multicast()
+            || segments[..6] == [0; 6]              // IPv4-compatible (deprecated)
+            || (first & 0xfe00) == 0xfc00           // unique local
+            || (first & 0xffc0) == 0xfe80           // link-local
+            || first == 0x2001 && segments[1] == 0x0db8 // documentation
+            // Tunnels to IPv4 addresses that cannot be vetted from here
+            || first == 0x2002                      // 6to4
+            || first == 0x2001 && segments[1] == 0  // Teredo
+            || first == 0x64 && segments[1] == 0xff9b) // NAT64 other than 64:ff9b::/96, e.g. local-use 64:ff9b:1::/48
+    }
+
+    pub fn validate_secret(secret: &str) -> Result<(), String> {
+        if secret.len() < MIN_SECRET_LEN {
+            return Err(format!("Webhook secret must be at least {} characters", MIN_SECRET_LEN));
+        }
+        Ok(())
+    }
+
+    /// Checks event names against [`WEBHOOK_EVENTS`], dropping duplicates; empty selects every event.
+    pub fn normalize_events(events: &[String]) -> Result<Vec<String>, String> {
+        if events.is_empty() {
+            return Ok(WEBHOOK_EVENTS.iter().map(|e| e.to_string()).collect());
+        }
+        let mut normalized: Vec<String> = Vec::new();
+        for event in events {
+            if !WEBHOOK_EVENTS.contains(&event.as_str()) {
+                return Err(format!("Unknown webhook event '{}' (expected one of: {})", event, WEBHOOK_EVENTS.join(", ")));
+            }
+            if !normalized.contains(event) {
+                normalized.push(event.clone());
+            }
+        }
+        Ok(normalized)
+    }
+
+    pub fn validate_filter(filter: &WebhookFilter) -> Result<(), String> {
+        if filter.main_file.as_deref().is_some_and(|glob| glob.trim().is_empty()) {
+            return Err("Webhook filter main_file must not be empty".to_string());
+        }
+        Ok(())
+    }
+
+    /// Whether a subscription wants `payload`: it must listen for the event and pass every filter set.
+    pub fn matches(subscription: &WebhookSubscription, payload: &WebhookPayload) -> bool {
+        let filter = &subscription.filter;
+        let field_matches = |wanted: &Option<String>, actual: &Option<String>| {
+            wanted.as_ref().is_none_or(|w| actual.as_ref() == Some(w))
+        };
+        subscription.events.contains(&payload.event)
+            && field_matches(&filter.project_id, &payload.project_id)
+            && field_matches(&filter.tenant, &payload.tenant)
+            && filter.main_file.as_ref().is_none_or(|glob| {
+                payload.main_file.as_deref().is_some_and(|file| glob_match(glob, file))
+            })
+            && filter.min_compile_time_ms.is_none_or(|min| payload.compile_time_ms >= min)
+    }
+
+    /// HMAC-SHA256 over `"{timestamp}.{body}"`, hex encoded, as sent in `X-Tachyon-Signature`.
+    pub fn sign(secret: &str, timestamp: u64, body: &str) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key length");
+        mac.update(timestamp.to_string().as_bytes());
+        mac.update(b".");
+        mac.update(body.as_bytes());
+        hex::encode(mac.finalize().into_bytes())
+    }
+
+    /// Delivers `payload` in the background to every subscription listening for its event.
+    pub fn fire(state: &AppState, payload: WebhookPayload) {
+        let state = state.clone();
+        tokio::spawn(async move {
+            let targets: Vec<WebhookSubscription> = state.webhooks.read().await.iter()
+                .filter(|w| Self::matches(w, &payload))
+                .cloned()
+                .collect();
+            for subscription in targets {
+                tokio::spawn(Self::deliver_with_retries(state.clone(), subscription.id, payload.clone()));
+            }
+        });
+    }
+
+    /// Retries with exponential backoff, re-reading the subscription before every attempt so
+    /// URL or secret changes apply mid-retry; dead-letters the event once attempts run out.
+    async fn deliver_with_retries(state: AppState, webhook_id: String, payload: WebhookPayload) {
+        let delivery_id = Uuid::new_v4().to_string();
+        let first_attempt_at = unix_now();
+        let max_attempts = state.settings.webhook_max_attempts;
+        let mut delay = RETRY_BASE_DELAY;
+        let mut url = String::new();
+        let mut last_error = String::new();
+
+        for attempt in 1..=max_attempts {
+            let Some(subscription) = state.webhooks.read().await.iter().find(|w| w.id == webhook_id).cloned() else {
+                return; // Deleted while we were retrying
+            };
+            url = subscription.url.clone();
+            match Self::deliver(&subscription, &delivery_id, &payload, &state.settings).await {
+                Ok(()) => {
+                    info!("🔔 Delivered {} to webhook {} (attempt {})", payload.event, webhook_id, attempt);
+                    return;
+                }
+                Err(e) => {
+                    warn!("Webhook {} delivery attempt {}/{} failed: {}", webhook_id, attempt, max_attempts, e);
+                    last_error = e;
+                }
+            }
+            if attempt < max_attempts {
+                tokio::time::sleep(delay).await;
+                delay *= 2;
+            }
+        }
+
+        info!("📭 Dead-lettered {} for webhook {} after {} attempts", payload.event, webhook_id, max_attempts);
+        state.dead_letters.push(DeadLetter {
+            id: delivery_id,
+            webhook_id,
+            url,
+            payload,
+            error: last_error,
+            attempts: max_attempts,
+            first_attempt_at,
+            last_attempt_at: unix_now(),
+        }).await;
+    }
+
+    /// Makes a single signed delivery attempt; any non-2xx response is an error.
+    /// The target is re-checked against the SSRF policy on every attempt and redirects are not followed.
+    pub async fn deliver(subscription: &WebhookSubscription, delivery_id: &str, payload: &WebhookPayload, settings: &Settings) -> Result<(), String> {
+        let body = serde_json::to_string(payload).map_err(|e| e.to_string())?;
+        Self::post(&subscription.url, Some(&subscription.secret), &payload.event, delivery_id, body, settings).await
+    }
+
+    /// POSTs a JSON `body` to `url` under the webhook target policy, signed when a secret is given.
+    pub async fn post(url: &str, secret: Option<&str>, event: &str, delivery_id: &str, body: String, settings: &Settings) -> Result<(), String> {
+        let url = Self::validate_url(url, settings)?;
+        let addrs = Self::resolve_target(&url, settings).await?;
+        let client = reqwest::Client::builder()
+            .timeout(DELIVERY_TIMEOUT)
+            .redirect(reqwest::redirect::Policy::none())
+            .resolve_to_addrs(url.host_str().unwrap_or_default(), &addrs)
+            .build()
+            .map_err(|e| e.to_string())?;
+
+        let timestamp = unix_now();
+        let mut request = client
+            .post(url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .header("X-Tachyon-Event", event)
+            .header("X-Tachyon-Delivery", delivery_id)
+            .header("X-Tachyon-Timestamp", timestamp.to_string());
+        if let Some(secret) = secret {
+            request = request.header("X-Tachyon-Signature", format!("sha256={}", Self::sign(secret, timestamp, &body)));
+        }
+        let response = request.body(body).send().await.map_err(|e| e.to_string())?;
+        if response.status().is_success() {
+            Ok(())
+        } else {
+            Err(format!("HTTP {}", response.status()))
+        }
+    }
+}
+
+/// Shell-style glob: `*` matches any run of characters except `/`, `**` also crosses `/`, `?` one character.
+/// Iterative, in O(pattern × text): on a mismatch only the latest `*` takes one more
+/// character, or, once it would have to cross a `/`, the latest `**`.
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let (p, t): (Vec<char>, Vec<char>) = (pattern.chars().collect(), text.chars().collect());
+    let (mut pi, mut ti) = (0, 0);
+    // (pattern index after the wildcard, text index its match ends at)
+    let mut star: Option<(usize, usize)> = None;
+    let mut globstar: Option<(usize, usize)> = None;
+    loop {
+        match p.get(pi) {
+            Some('*') if p.get(pi + 1) == Some(&'*') => {
+                globstar = Some((pi + 2, ti));
+                star = None;
+                pi += 2;
+                continue;
+            }
+            Some('*') => {
+                star = Some((pi + 1, ti));
+                pi += 1;
+                continue;
+            }
+            Some(&c) if t.get(ti).is_some_and(|&x| if c == '?' { x != '/' } else { x == c }) => {
+                pi += 1;
+                ti += 1;
+                continue;
+            }
+            None if ti == t.len() => return true,
+            _ => {}
+        }
+        if let Some((resume, end)) = star.filter(|&(_, end)| t.get(end).is_some_and(|&c| c != '/')) {
+            star = Some((resume, end + 1));
+            (pi, ti) = (resume, end + 1);
+        } else if let Some((resume, end)) = globstar.filter(|&(_, end)| end < t.len()) {
+            globstar = Some((resume, end + 1));
+            star = None;
+            (pi, ti) = (resume, end + 1);
+        } else {
+            return false;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generated_secrets_are_unique_and_valid() {
+        let a = Webhooks::generate_secret();
+        let b = Webhooks::generate_secret();
+        assert_ne!(a, b);
+        assert!(a.starts_with("whsec_"));
+        assert!(Webhooks::validate_secret(&a).is_ok());
+        assert!(Webhooks::validate_secret("short").is_err());
+    }
+
+    #[test]
+    fn test_event_and_url_validation() {
+        assert_eq!(Webhooks::normalize_events(&[]).unwrap().len(), WEBHOOK_EVENTS.len());
+        let events = vec!["compile.failure".to_string(), "compile.failure".to_string()];
+        assert_eq!(Webhooks::normalize_events(&events).unwrap(), vec!["compile.failure"]);
+        assert!(Webhooks::normalize_events(&["compile.started".to_string()]).is_err());
+
+        let settings = policy();
+        assert!(Webhooks::validate_url("ftp://example.com/hook", &settings).is_err());
+        assert!(Webhooks::validate_url("not a url", &settings).is_err());
+    }
+
+    fn policy() -> Settings {
+        Settings {
+            webhook_allowed_hosts: vec![],
+            webhook_allowed_ports: vec![80, 443],
+            webhook_allow_private: false,
+            webhook_require_https: false,
+            ..Settings::from_env()
+        }
+    }
+
+    #[test]
+    fn test_url_policy() {
+        let settings = policy();
+        assert!(Webhooks::validate_url("https://example.com/hook", &settings).is_ok());
+        assert!(Webhooks::validate_url("http://example.com:6379/", &settings).is_err());
+
+        let settings = Settings { webhook_require_https: true, ..policy() };
+        assert!(Webhooks::validate_url("http://example.com/hook", &settings).is_err());
+
+        let settings = Settings { webhook_allowed_hosts: vec!["hooks.example.com".into(), "*.corp.net".into()], ..policy() };
+        assert!(Webhooks::validate_url("https://hooks.example.com/x", &settings).is_ok());
+        assert!(Webhooks::validate_url("https://ci.corp.net/x", &settings).is_ok());
+        assert!(Webhooks::validate_url("https://corp.net/x", &settings).is_err());
+        assert!(Webhooks::validate_url("https://example.com/x", &settings).is_err());
+    }
+
+    #[test]
+    fn test_private_addresses_are_blocked() {
+        for ip in ["127.0.0.1", "10.1.2.3", "172.16.0.1", "192.168.1.1", "169.254.169.254", "100.64.0.1", "0.0.0.0", "::1", "fd00::1", "fe80::1"] {
+            assert!(!Webhooks::is_public_ip(ip.parse().unwrap()), "{} should be blocked", ip);
+        }
+        // IPv6 forms of private IPv4 addresses, tunnels and documentation ranges
+        for ip in ["::ffff:127.0.0.1", "::ffff:10.0.0.1", "::ffff:169.254.169.254", "64:ff9b::10.0.0.1", "64:ff9b::127.0.0.1", "64:ff9b:1::808:808", "::10.0.0.1", "2002:a00:1::1", "2002:5db8:d822::1", "2001:0:4136:e378::1", "2001:db8::1"] {
+            assert!(!Webhooks::is_public_ip(ip.parse().unwrap()), "{} should be blocked", ip);
+        }
+        for ip in ["93.184.216.34", "8.8.8.8", "2606:4700:4700::1111", "::ffff:8.8.8.8", "64:ff9b::808:808"] {
+            assert!(Webhooks::is_public_ip(ip.parse().unwrap()), "{} should be allowed", ip);
+        }
+    }
+
+    #[test]
+    fn test_filters() {
+        let payload = WebhookPayload {
+            event: "compile.success".to_string(),
+            timestamp: 0,
+            project_id: Some("thesis".to_string()),
+            tenant: Some("acme".to_string()),
+            main_file: Some("reports/q3.tex".to_string()),
+            success: true,
+            compile_time_ms: 800,
+            error: None,
+            output_hash: None,
+            test_id: None,
+            schedule_id: None,
+        };
+        let subscription = |filter: WebhookFilter| WebhookSubscription {
+            id: "w".to_string(),
+            url: "https://example.com".to_string(),
+            events: vec!["compile.success".to_string()],
+            secret: String::new(),
+            filter,
+            created_at: 0,
+            updated_at: 0,
+        };
+
+        assert!(Webhooks::matches(&subscription(WebhookFilter::default()), &payload));
+        assert!(Webhooks::matches(&subscription(WebhookFilter {
+            tenant: Some("acme".into()),
+            main_file: Some("reports/*.tex".into()),
+            min_compile_time_ms: Some(500),
+            ..Default::default()
+        }), &payload));
+        assert!(!Webhooks::matches(&subscription(WebhookFilter { project_id: Some("other".into()), ..Default::default() }), &payload));
+        assert!(!Webhooks::matches(&subscription(WebhookFilter { main_file: Some("*.tex".into()), ..Default::default() }), &payload));
+        assert!(Webhooks::matches(&subscription(WebhookFilter { main_file: Some("**.tex".into()), ..Default::default() }), &payload));
+        assert!(!Webhooks::matches(&subscription(WebhookFilter { min_compile_time_ms: Some(1000), ..Default::default() }), &payload));
+    }
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("reports/*.tex", "reports/q3.tex"));
+        assert!(!glob_match("reports/*.tex", "reports/2024/q3.tex"));
+        assert!(glob_match("reports/**.tex", "reports/2024/q3.tex"));
+        assert!(glob_match("**/q?.tex", "a/b/q3.tex"));
+        assert!(!glob_match("q?.tex", "q/.tex"));
+        assert!(glob_match("*a*b", "xaxxab"));
+        assert!(!glob_match("", "a"));
+        // Patterns that took exponential time with backtracking
+        assert!(!glob_match(&format!("{}b", "a*".repeat(20)), &"a".repeat(100)));
+        assert!(!glob_match(&format!("{}b", "a**".repeat(20)), &"a/".repeat(200)));
+    }
+
+    #[tokio::test]
+    async fn test_resolve_target_rejects_loopback() {
+        let url = reqwest::Url::parse("http://127.0.0.1/hook").unwrap();
+        assert!(Webhooks::resolve_target(&url, &policy()).await.is_err());
+        let settings = Settings { webhook_allow_private: true, ..policy() };
+        assert!(Webhooks::resolve_target(&url, &settings).await.is_ok());
+    }
+
+    #[test]
+    fn test_signature_is_stable_and_keyed() {
+        let a = Webhooks::sign("whsec_test_secret", 1700000000, "{}");
+        assert_eq!(a.len(), 64);
+        assert_eq!(a, Webhooks::sign("whsec_test_secret", 1700000000, "{}"));
+        assert_ne!(a, Webhooks::sign("whsec_other_secret", 1700000000, "{}"));
+        assert_ne!(a, Webhooks::sign("whsec_test_secret", 1700000001, "{}"));
+    }
+}
@@ -0,0 +1,308 @@
+//! Controller/worker split: the HTTP frontend ships compile workspaces to remote worker
+//! agents (other tachyon-tex instances started with `WORKER_TOKEN`) so the CPU-heavy
+//! tier scales independently of the API tier.
+
+use std::io::{Read, Write};
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::time::Duration;
+use base64::{engine::general_purpose, Engine as _};
+use tracing::{info, warn};
+
+use crate::compiler::{Compiler, TexOptions};
+use crate::models::WorkerCompileResponse;
+use crate::services::{AppState, CompilePermit, Priority};
+use crate::settings::Settings;
+use crate::tenancy::current_tenant;
+use crate::util::unix_now;
+
+/// Upper bound for one remote compile, including upload and download.
+const WORKER_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// How long a worker that refused a connection is skipped.
+const WORKER_BACKOFF_SECS: u64 = 10;
+
+/// Most entries a workspace archive may hold.
+const MAX_ARCHIVE_ENTRIES: usize = 10_000;
+
+/// Most bytes a workspace archive may unpack to, whatever its entries claim.
+const MAX_UNPACKED_BYTES: u64 = 1024 * 1024 * 1024;
+
+struct Worker {
+    url: String,
+    inflight: AtomicUsize,
+    down_until: AtomicU64,
+}
+
+pub struct WorkerPool {
+    workers: Vec<Worker>,
+    token: Option<String>,
+    client: reqwest::Client,
+    /// Compile locally when no worker is reachable instead of failing
+    pub fallback_local: bool,
+}
+
+impl WorkerPool {
+    pub fn new(urls: &[String], token: Option<String>, fallback_local: bool) -> Self {
+        Self {
+            workers: urls.iter().map(|url| Worker {
+                url: url.trim_end_matches('/').to_string(),
+                inflight: AtomicUsize::new(0),
+                down_until: AtomicU64::new(0),
+            }).collect(),
+            token,
+            client: reqwest::Client::builder().timeout(WORKER_TIMEOUT).build().unwrap_or_default(),
+            fallback_local,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.workers.len()
+    }
+
+    /// Healthy workers, least busy first.
+    fn candidates(&self) -> Vec<&Worker> {
+        let now = unix_now();
+        let mut healthy: Vec<&Worker> = self.workers.iter()
+            .filter(|w| w.down_until.load(Ordering::Relaxed) <= now)
+            .collect();
+        healthy.sort_by_key(|w| w.inflight.load(Ordering::Relaxed));
+        healthy
+    }
+
+    /// Runs the compile on the least busy worker, trying the next one when a worker is
+    /// unreachable. Returns `None` if no worker could take the job. The worker compiles with
+    /// `bundle_url` when given, i.e. with the controller's active bundle.
+    pub async fn compile(&self, workspace: &Path, main_tex_path: &Path, priority: Priority, options: &TexOptions, bundle_url: Option<&str>) -> Option<(Result<Vec<u8>, String>, String)> {
+        let main_file = main_tex_path.strip_prefix(workspace).unwrap_or(main_tex_path).to_string_lossy().to_string();
+        let archive = match pack_workspace(workspace) {
+            Ok(archive) => archive,
+            Err(e) => return Some((Err(format!("Failed to pack workspace: {}", e)), String::new())),
+        };
+
+        let options = (!options.is_default()).then(|| serde_json::to_string(options).unwrap_or_default());
+        for worker in self.candidates() {
+            worker.inflight.fetch_add(1, Ordering::Relaxed);
+            let mut request = self.client.post(format!("{}/internal/worker/compile", worker.url))
+                .header(reqwest::header::CONTENT_TYPE, "application/zip")
+                .header("X-Main-File", &main_file)
+                .header("X-Priority", priority.as_str())
+                .body(archive.clone());
+            if let Some(token) = &self.token {
+                request = request.bearer_auth(token);
+            }
+            if let Some(options) = &options {
+                request = request.header("X-Tex-Options", options);
+            }
+            if let Some(bundle_url) = bundle_url {
+                request = request.header("X-Bundle-Url", bundle_url);
+            }
+            let result = request.send().await;
+            worker.inflight.fetch_sub(1, Ordering::Relaxed);
+
+            let response = match result {
+                Ok(response) if response.status().is_success() => response,
+                Ok(response) => {
+                    warn!("Worker {} rejected compile: {}", worker.url, response.status());
+                    worker.down_until.store(unix_now() + WORKER_BACKOFF_SECS, Ordering::Relaxed);
+                    continue;
+                }
+                Err(e) => {
+                    warn!("Worker {} unreachable: {}", worker.url, e);
+                    worker.down_until.store(unix_now() + WORKER_BACKOFF_SECS, Ordering::Relaxed);
+                    continue;
+                }
+            };
+            let body: WorkerCompileResponse = match response.json().await {
+                Ok(body) => body,
+                Err(e) => return Some((Err(format!("Invalid response from worker {}: {}", worker.url, e)), String::new())),
+            };
+            info!("🛰️ Compiled {} on worker {}", main_file, worker.url);
+            let result = match (body.pdf_base64, body.error) {
+                (Some(pdf), _) => general_purpose::STANDARD.decode(pdf).map_err(|e| format!("Invalid PDF from worker: {}", e)),
+                (None, error) => Err(error.unwrap_or_else(|| "Worker returned no PDF".to_string())),
+            };
+            return Some((result, body.logs));
+        }
+        None
+    }
+}
+
+/// Compiles `main_tex_path` inside `workspace`, with the current tenant's registry
+/// packages, on a remote worker when a pool is configured and locally otherwise. The
+/// scheduler `permit` is released once the compile has actually finished.
+pub async fn compile(state: &AppState, workspace: &Path, main_tex_path: &Path, priority: Priority, permit: CompilePermit) -> (Result<Vec<u8>, String>, String) {
+    compile_with_options(state, workspace, main_tex_path, priority, &TexOptions::default(), permit).await
+}
+
+/// Like [`compile`], with engine settings given by the request.
+pub async fn compile_with_options(state: &AppState, workspace: &Path, main_tex_path: &Path, priority: Priority, options: &TexOptions, permit: CompilePermit) -> (Result<Vec<u8>, String>, String) {
+    state.registry.install(state, workspace).await;
+    // Workers are sent only the rules the tenant's healing policy leaves
+    let options = &with_healer_policy(&state.settings, options);
+    if let Some(pool) = &state.workers {
+        if let Some(outcome) = pool.compile(workspace, main_tex_path, priority, options, state.bundles.active().url.as_deref()).await {
+            return outcome;
+        }
+        if !pool.fallback_local {
+            return (Err("No compile worker available".to_string()), String::new());
+        }
+        warn!("No compile worker reachable, compiling locally");
+    }
+    compile_local(state, workspace, main_tex_path, options, permit).await
+}
+
+/// `options` narrowed to the healing rules the current tenant's policy allows.
+fn with_healer_policy(settings: &Settings, options: &TexOptions) -> TexOptions {
+    let allowed = crate::healer::allowed_rules(settings, current_tenant().as_deref());
+    TexOptions { healer_rules: crate::healer::intersect(options.healer_rules.as_deref(), allowed.as_deref()), ..options.clone() }
+}
+
+/// Sets the flag when dropped, i.e. when the future awaiting a compile goes away.
+struct CancelOnDrop(Arc<AtomicBool>);
+
+impl Drop for CancelOnDrop {
+    fn drop(&mut self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Compiles on this machine with the active bundle.
+pub async fn compile_local(state: &AppState, workspace: &Path, main_tex_path: &Path, options: &TexOptions, permit: CompilePermit) -> (Result<Vec<u8>, String>, String) {
+    compile_with_bundle(state, workspace, main_tex_path, state.bundles.active().url, options, permit).await
+}
+
+/// Runs tectonic with the bundle at `bundle_url` (`None`: the Tectonic config's default) on
+/// the blocking thread pool so multi-second compiles never stall the async workers serving
+/// other requests. If the caller stops waiting (e.g. the client disconnected), the compile
+/// is skipped when it has not started yet and no self-healing retry is attempted. Self-healing
+/// is limited to what the current tenant's policy allows, whoever the caller is. The scheduler
+/// `permit` moves into the blocking task, so a compile that is already running keeps its slot
+/// until tectonic returns even when the caller has gone.
+pub async fn compile_with_bundle(state: &AppState, workspace: &Path, main_tex_path: &Path, bundle_url: Option<String>, options: &TexOptions, permit: CompilePermit) -> (Result<Vec<u8>, String>, String) {
+    let options = with_healer_policy(&state.settings, options);
+    let cancelled = Arc::new(AtomicBool::new(false));
+    let _cancel_on_drop = CancelOnDrop(cancelled.clone());
+    let (workspace, main_tex_path) = (workspace.to_path_buf(), main_tex_path.to_path_buf());
+    let (format_cache_path, config) = (state.format_cache_path.clone(), state.config.clone());
+    let task = tokio::task::spawn_blocking(move || {
+        let _permit = permit;
+        Compiler::compile_file_cancellable(&main_tex_path, &workspace, &format_cache_path, &config, bundle_url.as_deref(), &options, &cancelled)
+    });
+    match task.await {
+        Ok(outcome) => outcome,
+        Err(e) => (Err(format!("Compile task failed: {}", e)), String::new()),
+    }
+}
+
+/// Zips every file under `dir`, keyed by its path relative to `dir`.
+pub fn pack_workspace(dir: &Path) -> Result<Vec<u8>, String> {
+    let mut zip = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(current) = pending.pop() {
+        for entry in std::fs::read_dir(&current).map_err(|e| e.to_string())? {
+            let path = entry.map_err(|e| e.to_string())?.path();
+            if path.is_dir() {
+                pending.push(path);
+                continue;
+            }
+            let name = path.strip_prefix(dir).map_err(|e| e.to_string())?.to_string_lossy().replace('\\', "/");
+            zip.start_file(name, zip::write::FileOptions::default()).map_err(|e| e.to_string())?;
+            zip.write_all(&std::fs::read(&path).map_err(|e| e.to_string())?).map_err(|e| e.to_string())?;
+        }
+    }
+    Ok(zip.finish().map_err(|e| e.to_string())?.into_inner())
+}
+
+/// Extracts a workspace archive into `dir`, rejecting entries that would escape it and
+/// archives with too many entries or that unpack to too many bytes.
+pub fn unpack_workspace(archive: &[u8], dir: &Path) -> Result<(), String> {
+    unpack_workspace_limited(archive, dir, MAX_ARCHIVE_ENTRIES, MAX_UNPACKED_BYTES)
+}
+
+fn unpack_workspace_limited(archive: &[u8], dir: &Path, max_entries: usize, max_bytes: u64) -> Result<(), String> {
+    let mut zip = zip::ZipArchive::new(std::io::Cursor::new(archive)).map_err(|e| format!("Invalid workspace archive: {}", e))?;
+    if zip.len() > max_entries {
+        return Err(format!("Workspace archive has {} entries, more than the limit of {}", zip.len(), max_entries));
+    }
+    let mut remaining = max_bytes;
+    for i in 0..zip.len() {
+        let mut file = zip.by_index(i).map_err(|e| e.to_string())?;
+        let Some(relative) = file.enclosed_name().map(|p| p.to_path_buf()) else {
+            return Err(format!("Unsafe path in workspace archive: {}", file.name()));
+        };
+        let path = dir.join(relative);
+        if file.is_dir() {
+            std::fs::create_dir_all(&path).map_err(|e| e.to_string())?;
+            continue;
+        }
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+        }
+        // Counted as it inflates: the sizes an entry declares can lie
+        let mut data = Vec::new();
+        (&mut file).take(remaining + 1).read_to_end(&mut data).map_err(|e| e.to_string())?;
+        if data.len() as u64 > remaining {
+            return Err(format!("Workspace archive unpacks to more than {} bytes", max_bytes));
+        }
+        remaining -= data.len() as u64;
+        std::fs::write(&path, data).map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_workspace_round_trip() {
+        let source = tempfile::TempDir::new().unwrap();
+        std::fs::write(source.path().join("main.tex"), "\\input{chapters/one}").unwrap();
+        std::fs::create_dir_all(source.path().join("chapters")).unwrap();
+        std::fs::write(source.path().join("chapters/one.tex"), "One").unwrap();
+
+        let archive = pack_workspace(source.path()).unwrap();
+        let target = tempfile::TempDir::new().unwrap();
+        unpack_workspace(&archive, target.path()).unwrap();
+        assert_eq!(std::fs::read_to_string(target.path().join("chapters/one.tex")).unwrap(), "One");
+        assert!(target.path().join("main.tex").exists());
+    }
+
+    #[test]
+    fn test_unpack_rejects_traversal() {
+        let mut zip = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+        zip.start_file("../evil.tex", zip::write::FileOptions::default()).unwrap();
+        zip.write_all(b"x").unwrap();
+        let archive = zip.finish().unwrap().into_inner();
+        let target = tempfile::TempDir::new().unwrap();
+        assert!(unpack_workspace(&archive, target.path()).is_err());
+    }
+
+    #[test]
+    fn test_unpack_caps_entries_and_size() {
+        let source = tempfile::TempDir::new().unwrap();
+        std::fs::write(source.path().join("a.tex"), "x".repeat(600)).unwrap();
+        std::fs::write(source.path().join("b.tex"), "x".repeat(600)).unwrap();
+        let archive = pack_workspace(source.path()).unwrap();
+
+        let target = tempfile::TempDir::new().unwrap();
+        assert!(unpack_workspace_limited(&archive, target.path(), 1, 10_000).unwrap_err().contains("entries"));
+        assert!(unpack_workspace_limited(&archive, target.path(), 10, 1_000).unwrap_err().contains("bytes"));
+        assert!(unpack_workspace_limited(&archive, target.path(), 10, 1_200).is_ok());
+    }
+
+    #[test]
+    fn test_healer_policy_applies_to_every_compile() {
+        let settings = Settings {
+            healer_rules: vec!["missing_end_document".into()],
+            tenant_healer_rules: vec![("ci".into(), "none".into())],
+            ..Settings::from_env()
+        };
+        let defaults = TexOptions::default();
+        assert_eq!(with_healer_policy(&settings, &defaults).healer_rules, Some(vec!["missing_end_document".into()]));
+        let tenant = crate::tenancy::TENANT.sync_scope(Some("ci".to_string()), || with_healer_policy(&settings, &defaults));
+        assert_eq!(tenant.healer_rules, Some(vec![]));
+    }
+}
@@ -0,0 +1,321 @@
+//! Typed async client for a Tachyon-Tex server.
+//!
+//! ```no_run
+//! # async fn run() -> Result<(), tachyon_tex_client::Error> {
+//! use tachyon_tex_client::{Client, CompileOptions, Project};
+//!
+//! let client = Client::new("http://localhost:8080");
+//! let project = Project::new()
+//!     .file("refs.bib", "@book{knuth, title={The TeXbook}}")
+//!     .file("main.tex", "\\documentclass{article}\\begin{document}Hi\\end{document}");
+//! let output = client.compile_project(&project, &CompileOptions::default()).await?;
+//! std::fs::write("main.pdf", &output.pdf)?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::time::Duration;
+use bytes::Bytes;
+use futures_util::{Stream, StreamExt};
+use reqwest::{header::HeaderMap, multipart, RequestBuilder, Response, StatusCode};
+
+mod types;
+mod ws;
+
+pub use types::*;
+pub use ws::LiveSession;
+
+#[derive(Debug)]
+pub enum Error {
+    Http(reqwest::Error),
+    WebSocket(Box<tokio_tungstenite::tungstenite::Error>),
+    Io(std::io::Error),
+    /// The server ran the compile and LaTeX failed
+    Compile { error: String, logs: String },
+    /// Any other non-success response
+    Status { status: u16, body: String },
+    /// The server answered with something this client could not parse
+    Decode(String),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Http(e) => write!(f, "HTTP error: {}", e),
+            Error::WebSocket(e) => write!(f, "WebSocket error: {}", e),
+            Error::Io(e) => write!(f, "I/O error: {}", e),
+            Error::Compile { error, .. } => write!(f, "LaTeX error: {}", error),
+            Error::Status { status, body } => write!(f, "Server returned {}: {}", status, body),
+            Error::Decode(e) => write!(f, "Invalid server response: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<reqwest::Error> for Error {
+    fn from(e: reqwest::Error) -> Self {
+        Error::Http(e)
+    }
+}
+
+impl From<tokio_tungstenite::tungstenite::Error> for Error {
+    fn from(e: tokio_tungstenite::tungstenite::Error) -> Self {
+        Error::WebSocket(Box::new(e))
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+#[derive(Clone)]
+pub struct Client {
+    base_url: String,
+    http: reqwest::Client,
+    max_retries: u32,
+    retry_backoff: Duration,
+}
+
+impl Client {
+    pub fn new(base_url: impl Into<String>) -> Self {
+        Self {
+            base_url: base_url.into().trim_end_matches('/').to_string(),
+            http: reqwest::Client::new(),
+            max_retries: 3,
+            retry_backoff: Duration::from_millis(250),
+        }
+    }
+
+    /// Uses a preconfigured `reqwest` client (timeouts, proxies, default headers).
+    pub fn with_http_client(mut self, http: reqwest::Client) -> Self {
+        self.http = http;
+        self
+    }
+
+    /// Retries connection failures, timeouts and 429/502/503/504 responses up to
+    /// `max_retries` times, doubling `backoff` after each attempt. Compile errors are
+    /// never retried.
+    pub fn with_retries(mut self, max_retries: u32, backoff: Duration) -> Self {
+        self.max_retries = max_retries;
+        self.retry_backoff = backoff;
+        self
+    }
+
+    /// Compiles a single-file document.
+    pub async fn compile(&self, main_tex: &str) -> Result<CompileOutput, Error> {
+        let project = Project::new().file("main.tex", main_tex.to_string());
+        self.compile_project(&project, &CompileOptions::default()).await
+    }
+
+    pub async fn compile_project(&self, project: &Project, options: &CompileOptions) -> Result<CompileOutput, Error> {
+        let (meta, response) = self.send_compile(project, options).await?;
+        Ok(CompileOutput { pdf: response.bytes().await?, meta })
+    }
+
+    /// Like [`Client::compile_project`], but yields the PDF as it arrives instead of
+    /// buffering it.
+    pub async fn compile_project_stream(
+        &self,
+        project: &Project,
+        options: &CompileOptions,
+    ) -> Result<(CompileMeta, impl Stream<Item = Result<Bytes, Error>>), Error> {
+        let (meta, response) = self.send_compile(project, options).await?;
+        Ok((meta, response.bytes_stream().map(|chunk| chunk.map_err(Error::from))))
+    }
+
+    /// Runs the server's static checks (structure across `\input`ed files, citations
+    /// against .bib files).
+    pub async fn validate(&self, project: &Project) -> Result<ValidationResult, Error> {
+        let body = serde_json::json!({
+            "files": project.files().iter().map(|(name, _)| *name).collect::<Vec<_>>(),
+            "sources": project.sources(),
+            "main": project.main_file(),
+        });
+        let response = self.send(|| self.http.post(self.url("/validate")).json(&body)).await?;
+        response.json().await.map_err(|e| Error::Decode(e.to_string()))
+    }
+
+    /// Estimates compile time, PDF size and page count without compiling.
+    pub async fn estimate(&self, project: &Project) -> Result<Estimate, Error> {
+        let response = self.send(|| {
+            let mut form = multipart::Form::new();
+            for (name, content) in project.files() {
+                form = form.part("file", multipart::Part::stream(content.clone()).file_name(name.to_string()));
+            }
+            self.http.post(self.url("/estimate")).multipart(form)
+        }).await?;
+        response.json().await.map_err(|e| Error::Decode(e.to_string()))
+    }
+
+    /// Fetches a previously compiled PDF by its `output_hash`.
+    pub async fn output(&self, output_hash: &str) -> Result<Bytes, Error> {
+        let response = self.send(|| self.http.get(self.url(&format!("/outputs/{}", output_hash)))).await?;
+        Ok(response.bytes().await?)
+    }
+
+    /// Finds documents of `tenant` compiled with `fingerprint` whose text overlaps the
+    /// output `output_hash` by at least `threshold` (0 to 1), most similar first.
+    pub async fn similar(&self, output_hash: &str, tenant: Option<&str>, threshold: f64) -> Result<Vec<SimilarDocument>, Error> {
+        #[derive(serde::Deserialize)]
+        struct Similar {
+            matches: Vec<SimilarDocument>,
+        }
+        let response = self
+            .send(|| {
+                let request = self.http.get(self.url(&format!("/similar/{}", output_hash))).query(&[("threshold", threshold)]);
+                match tenant {
+                    Some(tenant) => request.header("X-Tenant-Id", tenant),
+                    None => request,
+                }
+            })
+            .await?;
+        let similar: Similar = response.json().await.map_err(|e| Error::Decode(e.to_string()))?;
+        Ok(similar.matches)
+    }
+
+    /// Opens a live-preview WebSocket session.
+    pub async fn subscribe_ws(&self) -> Result<LiveSession, Error> {
+        LiveSession::connect(&ws_url(&self.base_url)).await
+    }
+
+    async fn send_compile(&self, project: &Project, options: &CompileOptions) -> Result<(CompileMeta, Response), Error> {
+        let mut query: Vec<(&str, String)> = Vec::new();
+        if options.force {
+            query.push(("force", "true".to_string()));
+        }
+        if options.receipt {
+            query.push(("receipt", "true".to_string()));
+        }
+        if options.sanitize_unicode {
+            query.push(("sanitize_unicode", "true".to_string()));
+        }
+        if options.check_pages {
+            query.push(("check_pages", "true".to_string()));
+        }
+        if options.fingerprint {
+            query.push(("fingerprint", "true".to_string()));
+        }
+        for (name, value) in [("paper", &options.paper), ("font_size", &options.font_size), ("margin", &options.margin), ("language", &options.language)] {
+            if let Some(value) = value {
+                query.push((name, value.clone()));
+            }
+        }
+        if let Some(twoside) = options.twoside {
+            query.push(("twoside", twoside.to_string()));
+        }
+        if !options.defines.is_empty() {
+            query.push(("defines", serde_json::to_string(&options.defines).unwrap_or_default()));
+        }
+        let response = self.send(|| {
+            let mut form = multipart::Form::new();
+            for (name, content) in project.files() {
+                form = form.part("file", multipart::Part::stream(content.clone()).file_name(name.to_string()));
+            }
+            let mut request = self.http.post(self.url("/compile")).query(&query).multipart(form);
+            for (header, value) in [("X-Priority", &options.priority), ("X-Project-Id", &options.project_id), ("X-Tenant-Id", &options.tenant)] {
+                if let Some(value) = value {
+                    request = request.header(header, value);
+                }
+            }
+            request
+        }).await?;
+        Ok((compile_meta(response.headers()), response))
+    }
+
+    /// Sends the request built by `build`, retrying transient failures.
+    async fn send(&self, build: impl Fn() -> RequestBuilder) -> Result<Response, Error> {
+        let mut attempt = 0;
+        loop {
+            match build().send().await {
+                Ok(response) if response.status().is_success() => return Ok(response),
+                Ok(response) if attempt < self.max_retries && is_retryable_status(response.status()) => {}
+                Ok(response) => {
+                    let status = response.status().as_u16();
+                    return Err(error_from_body(status, response.text().await.unwrap_or_default()));
+                }
+                Err(e) if attempt < self.max_retries && (e.is_connect() || e.is_timeout()) => {}
+                Err(e) => return Err(e.into()),
+            }
+            tokio::time::sleep(self.retry_backoff * 2u32.pow(attempt)).await;
+            attempt += 1;
+        }
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}{}", self.base_url, path)
+    }
+}
+
+fn is_retryable_status(status: StatusCode) -> bool {
+    matches!(status.as_u16(), 429 | 502 | 503 | 504)
+}
+
+/// Compile failures come back as `LaTeX Error: <error>\n\nLogs:\n<log>`.
+fn error_from_body(status: u16, body: String) -> Error {
+    match body.strip_prefix("LaTeX Error: ") {
+        Some(rest) => {
+            let (error, logs) = rest.split_once("\n\nLogs:\n").unwrap_or((rest, ""));
+            Error::Compile { error: error.to_string(), logs: logs.to_string() }
+        }
+        None => Error::Status { status, body },
+    }
+}
+
+fn compile_meta(headers: &HeaderMap) -> CompileMeta {
+    let text = |name: &str| headers.get(name).and_then(|v| v.to_str().ok()).map(str::to_string);
+    CompileMeta {
+        compile_time_ms: text("X-Compile-Time-Ms").and_then(|v| v.parse().ok()),
+        cache: text("X-Cache"),
+        output_hash: text("X-Output-Hash"),
+        warnings: text("X-Warnings").and_then(|json| serde_json::from_str(&json).ok()).unwrap_or_default(),
+        receipt: text("X-Compile-Receipt"),
+        fingerprint: text("X-Fingerprint"),
+    }
+}
+
+fn ws_url(base_url: &str) -> String {
+    let base = if let Some(rest) = base_url.strip_prefix("https://") {
+        format!("wss://{}", rest)
+    } else if let Some(rest) = base_url.strip_prefix("http://") {
+        format!("ws://{}", rest)
+    } else {
+        base_url.to_string()
+    };
+    format!("{}/ws", base)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_from_body() {
+        match error_from_body(500, "LaTeX Error: Undefined control sequence\n\nLogs:\n! \\foo".to_string()) {
+            Error::Compile { error, logs } => {
+                assert_eq!(error, "Undefined control sequence");
+                assert_eq!(logs, "! \\foo");
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(matches!(error_from_body(404, "nope".to_string()), Error::Status { status: 404, .. }));
+    }
+
+    #[test]
+    fn test_ws_url() {
+        assert_eq!(ws_url("http://localhost:8080"), "ws://localhost:8080/ws");
+        assert_eq!(ws_url("https://tex.example.com"), "wss://tex.example.com/ws");
+    }
+
+    #[test]
+    fn test_main_file_sent_last() {
+        let project = Project::new()
+            .file("main.tex", "main")
+            .file("chapter.tex", "chapter")
+            .main("main.tex");
+        let names: Vec<&str> = project.files().iter().map(|(n, _)| *n).collect();
+        assert_eq!(names, ["chapter.tex", "main.tex"]);
+    }
+}
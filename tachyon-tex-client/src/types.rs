@@ -0,0 +1,248 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+
+/// A set of files to compile. The main file is sent last so the server picks it as the
+/// entry point.
+#[derive(Clone, Debug, Default)]
+pub struct Project {
+    files: Vec<(String, Bytes)>,
+    main: Option<String>,
+}
+
+impl Project {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds (or replaces) a file; `name` is relative to the project root, e.g. `chapters/one.tex`.
+    pub fn file(mut self, name: impl Into<String>, content: impl Into<Bytes>) -> Self {
+        let name = name.into();
+        self.files.retain(|(n, _)| *n != name);
+        self.files.push((name, content.into()));
+        self
+    }
+
+    /// Sets the entry point; defaults to the last `.tex` file added.
+    pub fn main(mut self, name: impl Into<String>) -> Self {
+        self.main = Some(name.into());
+        self
+    }
+
+    /// Loads every file under `dir`, skipping hidden entries.
+    pub fn from_dir(dir: impl AsRef<Path>) -> std::io::Result<Self> {
+        let dir = dir.as_ref();
+        let mut project = Self::new();
+        let mut pending = vec![dir.to_path_buf()];
+        while let Some(current) = pending.pop() {
+            for entry in std::fs::read_dir(&current)? {
+                let path = entry?.path();
+                if path.file_name().and_then(|n| n.to_str()).is_some_and(|n| n.starts_with('.')) {
+                    continue;
+                }
+                if path.is_dir() {
+                    pending.push(path);
+                    continue;
+                }
+                let name = path.strip_prefix(dir).unwrap_or(&path).to_string_lossy().replace('\\', "/");
+                project = project.file(name, std::fs::read(&path)?);
+            }
+        }
+        if project.files.iter().any(|(n, _)| n == "main.tex") {
+            project = project.main("main.tex");
+        }
+        Ok(project)
+    }
+
+    pub fn main_file(&self) -> Option<&str> {
+        self.main.as_deref()
+            .or_else(|| self.files.iter().rev().find(|(n, _)| n.ends_with(".tex")).map(|(n, _)| n.as_str()))
+    }
+
+    /// Files in upload order, with the main file last.
+    pub fn files(&self) -> Vec<(&str, &Bytes)> {
+        let main = self.main_file();
+        let mut files: Vec<(&str, &Bytes)> = self.files.iter().map(|(n, c)| (n.as_str(), c)).collect();
+        files.sort_by_key(|(n, _)| Some(*n) == main);
+        files
+    }
+
+    /// UTF-8 `.tex` and `.bib` sources, used for server-side validation.
+    pub fn sources(&self) -> HashMap<String, String> {
+        self.files.iter()
+            .filter(|(n, _)| n.ends_with(".tex") || n.ends_with(".bib"))
+            .filter_map(|(n, c)| std::str::from_utf8(c).ok().map(|text| (n.clone(), text.to_string())))
+            .collect()
+    }
+}
+
+/// Options for `POST /compile`.
+#[derive(Clone, Debug, Default)]
+pub struct CompileOptions {
+    /// Bypass the server's PDF and failure caches
+    pub force: bool,
+    /// Ask for a signed provenance receipt
+    pub receipt: bool,
+    /// "interactive" (server default) or "batch"
+    pub priority: Option<String>,
+    /// Reported to webhooks as `project_id`
+    pub project_id: Option<String>,
+    /// Reported to webhooks as `tenant`
+    pub tenant: Option<String>,
+    /// Paper size override: "a4", "letter", ...
+    pub paper: Option<String>,
+    /// Base font size override: "10pt", "11pt" or "12pt"
+    pub font_size: Option<String>,
+    /// Margin override for all four sides, e.g. "2cm"
+    pub margin: Option<String>,
+    /// Force a two-sided or one-sided layout
+    pub twoside: Option<bool>,
+    /// Build flags injected before `\documentclass`: booleans become `\newif` toggles,
+    /// strings and numbers `\def` macros
+    pub defines: BTreeMap<String, serde_json::Value>,
+    /// `auto` or a language code: loads babel for the document language when the
+    /// preamble sets none
+    pub language: Option<String>,
+    /// Replace characters the default fonts lack (smart quotes, emoji, ...) with LaTeX
+    /// equivalents; each substitution comes back as a `unicode` warning
+    pub sanitize_unicode: bool,
+    /// Report blank and float-only pages as `blank_page` and `float_page` warnings
+    pub check_pages: bool,
+    /// Index a fingerprint of the PDF's text under `tenant`, for `Client::similar`
+    pub fingerprint: bool,
+}
+
+/// Response metadata of a successful compile, taken from the `X-*` headers.
+#[derive(Clone, Debug, Default)]
+pub struct CompileMeta {
+    pub compile_time_ms: Option<u64>,
+    /// "HIT", "STALE" or "MISS"
+    pub cache: Option<String>,
+    /// Key for `GET /outputs/:hash`
+    pub output_hash: Option<String>,
+    pub warnings: Vec<CompileWarning>,
+    /// Base64 JSON of the signed receipt, when requested
+    pub receipt: Option<String>,
+    /// Hex SimHash of the text, when a fingerprint was requested and the PDF had enough text
+    pub fingerprint: Option<String>,
+}
+
+#[derive(Clone, Debug)]
+pub struct CompileOutput {
+    pub pdf: Bytes,
+    pub meta: CompileMeta,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct CompileWarning {
+    pub kind: String,
+    pub file: Option<String>,
+    pub line: Option<u32>,
+    pub measurement: Option<String>,
+    pub message: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ValidationResult {
+    pub valid: bool,
+    pub errors: Vec<ValidationMessage>,
+    pub warnings: Vec<ValidationMessage>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ValidationMessage {
+    pub file: String,
+    pub line: u32,
+    pub message: String,
+}
+
+/// Preflight estimate from `Client::estimate`.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Estimate {
+    pub compile_time_ms: u64,
+    pub output_bytes: u64,
+    pub pages: usize,
+    /// "history" when calibrated by earlier compiles of the same preamble, else "heuristic"
+    pub basis: String,
+    pub history_compiles: u64,
+    pub warm_format: bool,
+    pub passes: usize,
+    pub words: usize,
+    pub images: usize,
+    pub image_bytes: u64,
+    pub packages: Vec<String>,
+}
+
+/// A previously compiled document whose text overlaps the queried one.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SimilarDocument {
+    pub output_hash: String,
+    /// Estimated share of overlapping text, 0 to 1
+    pub similarity: f64,
+    pub simhash_distance: u32,
+    pub indexed_at: u64,
+}
+
+/// A message pushed by the server over a live (`/ws`) session.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum LiveEvent {
+    CompileSuccess {
+        compile_time_ms: u64,
+        output_hash: String,
+        /// HIT when the PDF came from the server's compilation cache, MISS otherwise
+        #[serde(default)]
+        cache: Option<String>,
+        /// Base64 PDF; see [`LiveEvent::pdf`]. Absent when the server sent `pdf_delta`
+        #[serde(default)]
+        pdf: Option<String>,
+        /// Base64 binary delta against the PDF named by `delta_base`, sent when the
+        /// session asked for deltas
+        #[serde(default)]
+        pdf_delta: Option<String>,
+        #[serde(default)]
+        delta_base: Option<String>,
+        /// Content hash of every page, in order
+        #[serde(default)]
+        page_hashes: Option<Vec<String>>,
+        /// 1-based pages that are new or differ from the previous compile of this session
+        #[serde(default)]
+        changed_pages: Option<Vec<usize>>,
+        /// Blob store hashes of binary files uploaded in this round, keyed by file name
+        #[serde(default)]
+        blobs: HashMap<String, String>,
+        #[serde(default)]
+        warnings: Vec<CompileWarning>,
+    },
+    CompileError {
+        error: String,
+        logs: String,
+        /// NEGATIVE when the failure came from the server's cache, MISS otherwise
+        #[serde(default)]
+        cache: Option<String>,
+        /// Parsed errors with source snippets and explanations
+        #[serde(default)]
+        details: Vec<serde_json::Value>,
+        #[serde(default)]
+        warnings: Vec<CompileWarning>,
+    },
+    /// Sent before the result when the self-healer rewrote the main file
+    HealingReport {
+        file: String,
+        fixes: Vec<String>,
+        /// Whether the healed source compiled
+        healed: bool,
+    },
+}
+
+impl LiveEvent {
+    /// Decoded PDF of a successful compile sent in full.
+    pub fn pdf(&self) -> Option<Vec<u8>> {
+        use base64::Engine as _;
+        match self {
+            LiveEvent::CompileSuccess { pdf, .. } => pdf.as_ref().and_then(|pdf| base64::engine::general_purpose::STANDARD.decode(pdf).ok()),
+            LiveEvent::CompileError { .. } | LiveEvent::HealingReport { .. } => None,
+        }
+    }
+}
@@ -0,0 +1,90 @@
+use futures_util::{SinkExt, StreamExt};
+use serde_json::{json, Map, Value};
+use tokio::net::TcpStream;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+
+use crate::types::{LiveEvent, Project};
+use crate::Error;
+
+/// A live-preview session on `/ws`. The server keeps a warm workspace per connection, so
+/// repeated compiles of the same project reuse aux files and format state.
+pub struct LiveSession {
+    socket: WebSocketStream<MaybeTlsStream<TcpStream>>,
+}
+
+impl LiveSession {
+    pub(crate) async fn connect(url: &str) -> Result<Self, Error> {
+        let (socket, _) = tokio_tungstenite::connect_async(url).await?;
+        Ok(Self { socket })
+    }
+
+    /// Sends the project; the result arrives through [`LiveSession::next_event`].
+    /// Files missing from `project` are removed from the server workspace.
+    pub async fn send(&mut self, project: &Project) -> Result<(), Error> {
+        self.socket.send(Message::Text(Self::encode(project).to_string())).await?;
+        Ok(())
+    }
+
+    /// Waits for the next server event; `None` once the connection is closed.
+    pub async fn next_event(&mut self) -> Option<Result<LiveEvent, Error>> {
+        while let Some(message) = self.socket.next().await {
+            match message {
+                Ok(Message::Text(text)) => {
+                    return Some(serde_json::from_str(&text).map_err(|e| Error::Decode(e.to_string())));
+                }
+                Ok(Message::Close(_)) => return None,
+                Ok(_) => continue,
+                Err(e) => return Some(Err(e.into())),
+            }
+        }
+        None
+    }
+
+    /// Sends the project and waits for its compile result, skipping healing reports.
+    pub async fn compile(&mut self, project: &Project) -> Result<LiveEvent, Error> {
+        self.send(project).await?;
+        loop {
+            match self.next_event().await {
+                Some(Ok(LiveEvent::HealingReport { .. })) => continue,
+                Some(event) => return event,
+                None => return Err(Error::Decode("connection closed before a result arrived".to_string())),
+            }
+        }
+    }
+
+    pub async fn close(mut self) -> Result<(), Error> {
+        self.socket.close(None).await?;
+        Ok(())
+    }
+
+    /// Text files go as raw strings, everything else as `{"base64": ...}`.
+    fn encode(project: &Project) -> Value {
+        use base64::Engine as _;
+        let mut files = Map::new();
+        for (name, content) in project.files() {
+            let value = match std::str::from_utf8(content) {
+                Ok(text) => Value::String(text.to_string()),
+                Err(_) => json!({ "base64": base64::engine::general_purpose::STANDARD.encode(content) }),
+            };
+            files.insert(name.to_string(), value);
+        }
+        json!({ "main": project.main_file(), "files": files })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_project() {
+        let project = Project::new()
+            .file("main.tex", "\\documentclass{article}")
+            .file("logo.png", vec![0x89, b'P', b'N', b'G', 0xff]);
+        let encoded = LiveSession::encode(&project);
+        assert_eq!(encoded["main"], "main.tex");
+        assert_eq!(encoded["files"]["main.tex"], "\\documentclass{article}");
+        assert!(encoded["files"]["logo.png"]["base64"].is_string());
+    }
+}